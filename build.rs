@@ -0,0 +1,64 @@
+//! Embeds the git commit and the build time into the binary.
+//!
+//! Both fall back to `unknown` if they can not be determined,
+//! for example when building from a crates.io package.
+
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{env, fs};
+
+fn main() {
+    println!(
+        "cargo:rustc-env=AXOCHAT_GIT_COMMIT={}",
+        git_commit().unwrap_or_else(|| "unknown".to_string())
+    );
+    println!(
+        "cargo:rustc-env=AXOCHAT_BUILD_TIMESTAMP={}",
+        build_timestamp().unwrap_or_else(|| "unknown".to_string())
+    );
+
+    // only rebuild if the checked out commit changed
+    let head = Path::new(".git/HEAD");
+    if head.exists() {
+        println!("cargo:rerun-if-changed={}", head.display());
+        if let Some(reference) = fs::read_to_string(head)
+            .ok()
+            .as_deref()
+            .and_then(|head| head.strip_prefix("ref: "))
+        {
+            let reference = Path::new(".git").join(reference.trim());
+            if reference.exists() {
+                println!("cargo:rerun-if-changed={}", reference.display());
+            }
+        }
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
+
+fn git_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let commit = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    if commit.is_empty() {
+        None
+    } else {
+        Some(commit)
+    }
+}
+
+/// The build time in RFC 3339 format.
+/// `SOURCE_DATE_EPOCH` is respected for reproducible builds.
+fn build_timestamp() -> Option<String> {
+    let time = match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => UNIX_EPOCH + Duration::from_secs(epoch.trim().parse().ok()?),
+        Err(_) => SystemTime::now(),
+    };
+    Some(humantime::format_rfc3339_seconds(time).to_string())
+}
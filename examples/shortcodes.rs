@@ -0,0 +1,49 @@
+//! Runs the chat server with a hook which expands emoji shortcodes like `:heart:`.
+
+use axochat::{
+    auth::UserInfo,
+    chat::{ChatHook, ChatServerBuilder, HookDecision},
+    config,
+    error::*,
+};
+
+use actix::*;
+use actix_web::{App, HttpServer};
+
+const SHORTCODES: &[(&str, &str)] = &[
+    (":heart:", "\u{2764}"),
+    (":smile:", "\u{1f604}"),
+    (":thumbsup:", "\u{1f44d}"),
+];
+
+struct ShortcodeHook;
+
+impl ChatHook for ShortcodeHook {
+    fn on_message(&mut self, _author: &UserInfo, content: &str) -> HookDecision {
+        if !content.contains(':') {
+            return HookDecision::Allow;
+        }
+
+        let mut expanded = content.to_string();
+        for (code, emoji) in SHORTCODES {
+            expanded = expanded.replace(code, emoji);
+        }
+        HookDecision::Rewrite(expanded)
+    }
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let config = config::read_config()?;
+
+    let system = System::new("axochat-shortcodes");
+    let chat = ChatServerBuilder::new(config.clone())
+        .hook(ShortcodeHook)
+        .start()?;
+    HttpServer::new(move || App::new().configure(|cfg| chat.configure(cfg)))
+        .bind(config.net.address)?
+        .start();
+
+    system.run()?;
+    Ok(())
+}
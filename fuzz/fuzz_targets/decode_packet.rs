@@ -0,0 +1,20 @@
+//! Feeds arbitrary text frames to the packet decoder.
+//!
+//! Run with `cargo fuzz run decode_packet` from the repository root.
+
+#![no_main]
+
+use axochat::chat::{decode_packet, PacketLimits};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The websocket layer only delivers valid UTF-8 in text frames.
+    if let Ok(text) = std::str::from_utf8(data) {
+        let limits = PacketLimits {
+            max_size: 16 * 1024,
+            max_depth: 8,
+            max_malformed: 10,
+        };
+        let _ = decode_packet(text, &limits);
+    }
+});
@@ -1,25 +1,61 @@
 use crate::error::*;
 use log::*;
 
-use actix_web::{client::Client, http::StatusCode};
+use actix_web::{
+    client::{Client, ConnectError, SendRequestError},
+    http::StatusCode,
+};
 use futures::Future;
 use serde::{de::IgnoredAny, Deserialize, Serialize};
 use url::Url;
 
 use crate::config::AuthConfig;
-use jsonwebtoken::{Header, Validation};
+use jsonwebtoken::{errors::ErrorKind, Header, Validation};
 use std::{
-    fs,
+    fmt, fs,
     time::{Duration, SystemTime},
 };
 use uuid::Uuid;
 
+/// Why an authentication with Mojang failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthFailure {
+    /// Mojang does not know the session or it belongs to another user.
+    Invalid,
+    /// Mojang did not respond in time.
+    Timeout,
+    /// The request failed or Mojang responded with an unexpected status.
+    HttpError,
+}
+
+/// An error of [`authenticate`].
+#[derive(Debug)]
+pub struct AuthError {
+    pub kind: AuthFailure,
+    pub source: Error,
+}
+
+impl AuthError {
+    fn new(kind: AuthFailure, source: Error) -> AuthError {
+        AuthError { kind, source }
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.source.fmt(f)
+    }
+}
+
+/// The Mojang endpoint logins are verified with.
+pub const SESSION_SERVER_URL: &str = "https://sessionserver.mojang.com/session/minecraft/hasJoined";
+
 pub fn authenticate(
     username: &str,
     server_id: &str,
-) -> Result<impl Future<Item = AuthInfo, Error = Error>> {
-    let mut url =
-        Url::parse("https://sessionserver.mojang.com/session/minecraft/hasJoined").unwrap();
+) -> Result<impl Future<Item = AuthInfo, Error = AuthError>> {
+    let mut url = Url::parse(SESSION_SERVER_URL).unwrap();
     url.query_pairs_mut()
         .append_pair("username", username)
         .append_pair("serverId", server_id);
@@ -27,19 +63,34 @@ pub fn authenticate(
     Ok(Client::new()
         .get(url.as_str())
         .send()
-        .map_err(|err| Error::Actix { source: err.into() })
+        .map_err(|err| {
+            let kind = match err {
+                SendRequestError::Timeout | SendRequestError::Connect(ConnectError::Timeout) => {
+                    AuthFailure::Timeout
+                }
+                _ => AuthFailure::HttpError,
+            };
+            AuthError::new(kind, Error::Actix { source: err.into() })
+        })
         .and_then(|response| {
-            if response.status() == StatusCode::OK {
+            let status = response.status();
+            if status == StatusCode::OK {
                 Ok(response)
             } else {
-                debug!("Login status-code is {}", response.status());
-                Err(ClientError::LoginFailed.into())
+                debug!("Login status-code is {}", status);
+                // Mojang answers unknown sessions with `204 No Content`.
+                let kind = if status == StatusCode::NO_CONTENT || status.is_client_error() {
+                    AuthFailure::Invalid
+                } else {
+                    AuthFailure::HttpError
+                };
+                Err(AuthError::new(kind, ClientError::LoginFailed.into()))
             }
         })
         .and_then(|mut response| {
-            response
-                .json()
-                .map_err(|err| Error::Actix { source: err.into() })
+            response.json().map_err(|err| {
+                AuthError::new(AuthFailure::HttpError, Error::Actix { source: err.into() })
+            })
         }))
 }
 
@@ -100,18 +151,48 @@ impl Authenticator {
     }
 
     pub fn auth(&self, token: &str) -> Result<UserInfo> {
-        match jsonwebtoken::decode::<Claims>(token, &self.key, &self.validation) {
-            Ok(data) => Ok(data.claims.user),
-            Err(err) => Err(err.into()),
+        let claims = jsonwebtoken::decode::<Claims>(token, &self.key, &self.validation)?.claims;
+        // `Validation::validate_nbf` would reject tokens without `nbf`, like the ones issued here.
+        if let Some(nbf) = claims.nbf {
+            if nbf > unix_time().as_secs() {
+                return Err(jsonwebtoken::errors::Error::from(ErrorKind::ImmatureSignature).into());
+            }
+        }
+        Ok(claims.user)
+    }
+
+    /// Returns why `token` was rejected with `err` by [`auth`](Authenticator::auth),
+    /// or `None` if the server is at fault, e.g. because its key is invalid.
+    pub fn token_failure(token: &str, err: &Error) -> Option<TokenFailure> {
+        let source = match err {
+            Error::JWT { source } => source,
+            _ => return None,
+        };
+        match source.kind() {
+            ErrorKind::ExpiredSignature => Some(TokenFailure::Expired),
+            ErrorKind::ImmatureSignature => Some(TokenFailure::NotYetValid),
+            ErrorKind::InvalidSignature | ErrorKind::InvalidAlgorithm => {
+                Some(TokenFailure::InvalidSignature)
+            }
+            ErrorKind::InvalidToken | ErrorKind::Base64(_) | ErrorKind::Utf8(_) => {
+                Some(TokenFailure::Malformed)
+            }
+            ErrorKind::InvalidIssuer | ErrorKind::InvalidAudience | ErrorKind::InvalidSubject => {
+                Some(TokenFailure::MissingClaim)
+            }
+            // The header is decoded before the claims, so a valid header means the claims are at fault.
+            ErrorKind::Json(err) if err.is_data() && jsonwebtoken::decode_header(token).is_ok() => {
+                Some(TokenFailure::MissingClaim)
+            }
+            ErrorKind::Json(_) => Some(TokenFailure::Malformed),
+            _ => None,
         }
     }
 
     pub fn new_token(&self, info: UserInfo) -> Result<String> {
-        let unix_time = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .expect("system time is somehow before the unix epoch");
         let claims = Claims {
-            exp: (unix_time + self.valid_time).as_secs(),
+            exp: (unix_time() + self.valid_time).as_secs(),
+            nbf: None,
             user: info,
         };
         jsonwebtoken::encode(&self.header, &claims, &self.key).map_err(|err| err.into())
@@ -121,11 +202,23 @@ impl Authenticator {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     exp: u64,
+    /// The time before which the token is not valid yet, if it is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nbf: Option<u64>,
     user: UserInfo,
 }
 
+fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("system time is somehow before the unix epoch")
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserInfo {
     pub name: String,
     pub uuid: Uuid,
+    /// Set for the bots configured in `bots.uuids`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bot: bool,
 }
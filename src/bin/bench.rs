@@ -0,0 +1,401 @@
+//! Simulates many clients sending messages to a server and measures how fast they are delivered.
+//!
+//! Every client logs in with a JWT, either read from a file or created with the key
+//! from the `auth` section of the configuration, and sends messages at a fixed interval.
+//! Each message contains a unique id, so its latency can be measured whenever a client receives it.
+//! The results are printed as JSON once the benchmark is over.
+
+use axochat::{
+    auth::{Authenticator, UserInfo},
+    chat::{Capabilities, ClientVersion, ServerPacket},
+    config,
+    error::*,
+};
+use structopt::*;
+
+use actix::{Arbiter, System};
+use awc::ws::{Frame, Message};
+use futures::{future, sync::oneshot, Future, Sink, Stream};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tokio_timer::{Delay, Interval};
+use uuid::Uuid;
+
+/// How long the clients keep receiving after the last message was sent.
+const DRAIN_TIME: Duration = Duration::from_secs(2);
+
+#[derive(StructOpt)]
+#[structopt(name = "axochat-bench")]
+struct Opt {
+    /// The websocket endpoint of the server, e.g. `ws://127.0.0.1:8080/ws`.
+    #[structopt(name = "url")]
+    url: String,
+    /// The amount of simulated clients.
+    #[structopt(short = "n", long = "clients", default_value = "100")]
+    clients: usize,
+    /// How many clients connect per second.
+    #[structopt(long = "connect-rate", default_value = "50")]
+    connect_rate: u32,
+    /// How often each client sends a message.
+    #[structopt(
+        long = "message-interval",
+        default_value = "1s",
+        parse(try_from_str = "humantime::parse_duration")
+    )]
+    message_interval: Duration,
+    /// How long the clients send messages after the last one connected.
+    #[structopt(
+        long = "duration",
+        default_value = "30s",
+        parse(try_from_str = "humantime::parse_duration")
+    )]
+    duration: Duration,
+    /// A file containing one JWT per line, which are used instead of creating new ones.
+    /// There must be a token for every client.
+    #[structopt(long = "tokens", parse(from_os_str))]
+    tokens: Option<PathBuf>,
+}
+
+/// A clientbound packet, before its body is decoded.
+#[derive(Deserialize)]
+struct Packet {
+    m: String,
+    #[serde(default)]
+    c: Value,
+}
+
+#[derive(Deserialize)]
+struct MessageBody {
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: ClientError,
+}
+
+#[derive(Default)]
+struct Stats {
+    first_connect: Option<Instant>,
+    last_connect: Option<Instant>,
+    connected: u64,
+    connect_failures: u64,
+    logged_in: u64,
+    /// When each message was sent, by its id.
+    sent: HashMap<String, Instant>,
+    received: u64,
+    latencies: Vec<Duration>,
+    /// The translation key of each received [`ClientError`], or the kind of a connection error.
+    errors: BTreeMap<String, u64>,
+}
+
+impl Stats {
+    fn error(&mut self, code: &str) {
+        *self.errors.entry(code.to_string()).or_insert(0) += 1;
+    }
+
+    fn summary(&mut self, clients: usize) -> Summary {
+        let connect_secs = match (self.first_connect, self.last_connect) {
+            (Some(first), Some(last)) => (last - first).as_secs_f64(),
+            _ => 0.0,
+        };
+        self.latencies.sort();
+        let percentile = |p: f64| -> Option<f64> {
+            if self.latencies.is_empty() {
+                return None;
+            }
+            let index = ((self.latencies.len() - 1) as f64 * p).round() as usize;
+            Some(self.latencies[index].as_secs_f64() * 1000.0)
+        };
+
+        Summary {
+            clients,
+            connected: self.connected,
+            connect_failures: self.connect_failures,
+            logged_in: self.logged_in,
+            connects_per_sec: if connect_secs > 0.0 {
+                self.connected as f64 / connect_secs
+            } else {
+                self.connected as f64
+            },
+            messages_sent: self.sent.len() as u64,
+            messages_received: self.received,
+            latency_ms: Latency {
+                p50: percentile(0.5),
+                p95: percentile(0.95),
+                p99: percentile(0.99),
+                max: percentile(1.0),
+            },
+            errors: self.errors.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Summary {
+    clients: usize,
+    connected: u64,
+    connect_failures: u64,
+    logged_in: u64,
+    connects_per_sec: f64,
+    messages_sent: u64,
+    messages_received: u64,
+    latency_ms: Latency,
+    errors: BTreeMap<String, u64>,
+}
+
+/// Delivery latencies in milliseconds; `null` if no message was delivered.
+#[derive(Serialize)]
+struct Latency {
+    p50: Option<f64>,
+    p95: Option<f64>,
+    p99: Option<f64>,
+    max: Option<f64>,
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let opt = Opt::from_args();
+    let tokens = read_tokens(&opt)?;
+
+    let system = System::new("axochat-bench");
+    let stats = Rc::new(RefCell::new(Stats::default()));
+    let opt = Rc::new(opt);
+
+    let start = Instant::now();
+    let connect_interval = Duration::from_secs(1) / opt.connect_rate.max(1);
+    let end = start + connect_interval * opt.clients as u32 + opt.duration;
+    for (index, token) in tokens.into_iter().enumerate() {
+        let connect_at = start + connect_interval * index as u32;
+        let client = Client {
+            index,
+            opt: opt.clone(),
+            stats: stats.clone(),
+            end,
+        };
+        Arbiter::spawn(
+            Delay::new(connect_at)
+                .map_err(|_| ())
+                .and_then(move |()| client.run(token)),
+        );
+    }
+    Arbiter::spawn(Delay::new(end + DRAIN_TIME).then(|_| {
+        System::current().stop();
+        Ok(())
+    }));
+    system.run()?;
+
+    let summary = stats.borrow_mut().summary(opt.clients);
+    println!("{}", serde_json::to_string_pretty(&summary)?);
+    Ok(())
+}
+
+/// Reads the tokens from `--tokens` or creates one for every client.
+fn read_tokens(opt: &Opt) -> Result<Vec<String>> {
+    if let Some(path) = &opt.tokens {
+        let tokens: Vec<String> = fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .take(opt.clients)
+            .collect();
+        if tokens.len() < opt.clients {
+            eprintln!(
+                "{} contains {} tokens, but {} clients were requested.",
+                path.display(),
+                tokens.len(),
+                opt.clients
+            );
+            return Err(ClientError::LoginFailed.into());
+        }
+        return Ok(tokens);
+    }
+
+    let auth = match config::read_config()?.auth {
+        Some(auth) => Authenticator::new(&auth)?,
+        None => {
+            eprintln!("Please add a `auth` segment to your configuration file or pass `--tokens`.");
+            return Err(ClientError::NotSupported.into());
+        }
+    };
+    (0..opt.clients)
+        .map(|index| {
+            auth.new_token(UserInfo {
+                name: format!("bench{}", index),
+                uuid: Uuid::from_u128(index as u128),
+                bot: false,
+            })
+        })
+        .collect()
+}
+
+/// A simulated client.
+struct Client {
+    index: usize,
+    opt: Rc<Opt>,
+    stats: Rc<RefCell<Stats>>,
+    /// When the client stops sending messages.
+    end: Instant,
+}
+
+impl Client {
+    fn run(self, token: String) -> impl Future<Item = (), Error = ()> {
+        self.stats
+            .borrow_mut()
+            .first_connect
+            .get_or_insert_with(Instant::now);
+
+        let stats = self.stats.clone();
+        awc::Client::new()
+            .ws(self.opt.url.as_str())
+            .connect()
+            .then(move |res| {
+                let mut stats = stats.borrow_mut();
+                stats.last_connect = Some(Instant::now());
+                match res {
+                    Ok((_response, framed)) => {
+                        stats.connected += 1;
+                        Ok(framed)
+                    }
+                    Err(_) => {
+                        stats.connect_failures += 1;
+                        stats.error("connect");
+                        Err(())
+                    }
+                }
+            })
+            .and_then(move |framed| {
+                let (sink, stream) = framed.split();
+                let (logged_in, on_login) = oneshot::channel();
+                Arbiter::spawn(self.receive(stream, logged_in));
+                self.send(sink, token, on_login)
+            })
+    }
+
+    /// Sends `Hello`, logs in, waits for the login to succeed and sends messages until the benchmark is over.
+    fn send<S>(
+        self,
+        sink: S,
+        token: String,
+        on_login: oneshot::Receiver<()>,
+    ) -> impl Future<Item = (), Error = ()>
+    where
+        S: Sink<SinkItem = Message> + 'static,
+    {
+        let hello = ServerPacket::Hello {
+            features: Capabilities::NONE,
+            echo_own_messages: None,
+            client: Some(ClientVersion {
+                brand: String::from("axochat-bench"),
+                version: axochat::version::VERSION.to_string(),
+            }),
+            protocol: Some(axochat::chat::PROTOCOL_VERSION),
+        };
+        let login = ServerPacket::LoginJWT {
+            token,
+            allow_messages: false,
+        };
+        let stats = self.stats.clone();
+        send_packet(sink, &hello)
+            .and_then(move |sink| send_packet(sink, &login))
+            .and_then(|sink| on_login.map(|()| sink).map_err(|_| ()))
+            .and_then(move |sink| {
+                let interval = self.opt.message_interval;
+                let end = self.end;
+                Interval::new(Instant::now() + interval, interval)
+                    .take_while(move |_| Ok(Instant::now() < end))
+                    .map_err(|_| ())
+                    .fold((sink, 0u64), move |(sink, seq), _| {
+                        let id = format!("bench {} {}", self.index, seq);
+                        stats.borrow_mut().sent.insert(id.clone(), Instant::now());
+                        send_packet(
+                            sink,
+                            &ServerPacket::Message {
+                                content: id,
+                                origin: None,
+                                reply_to: None,
+                            },
+                        )
+                        .map(move |sink| (sink, seq + 1))
+                    })
+            })
+            .map(|_| ())
+    }
+
+    /// Handles the packets received by this client.
+    fn receive<S>(
+        &self,
+        stream: S,
+        logged_in: oneshot::Sender<()>,
+    ) -> impl Future<Item = (), Error = ()>
+    where
+        S: Stream<Item = Frame> + 'static,
+    {
+        let stats = self.stats.clone();
+        let mut logged_in = Some(logged_in);
+        stream
+            .map_err({
+                let stats = stats.clone();
+                move |_| stats.borrow_mut().error("websocket")
+            })
+            .for_each(move |frame| {
+                let text = match frame {
+                    Frame::Text(Some(text)) => text,
+                    Frame::Close(_) => {
+                        stats.borrow_mut().error("closed");
+                        return Err(());
+                    }
+                    _ => return Ok(()),
+                };
+                let packet: Packet = match serde_json::from_slice(&text) {
+                    Ok(packet) => packet,
+                    Err(_) => {
+                        stats.borrow_mut().error("invalid_packet");
+                        return Ok(());
+                    }
+                };
+
+                let mut stats = stats.borrow_mut();
+                match packet.m.as_str() {
+                    "Success" => {
+                        if let Some(logged_in) = logged_in.take() {
+                            stats.logged_in += 1;
+                            logged_in.send(()).ok();
+                        }
+                    }
+                    "Message" => {
+                        if let Ok(body) = serde_json::from_value::<MessageBody>(packet.c) {
+                            if let Some(sent) = stats.sent.get(&body.content).copied() {
+                                stats.received += 1;
+                                stats.latencies.push(sent.elapsed());
+                            }
+                        }
+                    }
+                    "Error" => match serde_json::from_value::<ErrorBody>(packet.c) {
+                        Ok(body) => stats.error(body.message.translation_key()),
+                        Err(_) => stats.error("invalid_packet"),
+                    },
+                    _ => {}
+                }
+                Ok(())
+            })
+    }
+}
+
+fn send_packet<S>(sink: S, packet: &ServerPacket) -> impl Future<Item = S, Error = ()>
+where
+    S: Sink<SinkItem = Message>,
+{
+    let text = match serde_json::to_string(packet) {
+        Ok(text) => text,
+        Err(_) => return future::Either::A(future::err(())),
+    };
+    future::Either::B(sink.send(Message::Text(text)).map_err(|_| ()))
+}
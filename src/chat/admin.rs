@@ -0,0 +1,675 @@
+use super::{
+    cluster::ClusterEvent,
+    simulate::{Capture, Collect},
+    AuthorKind, ChatServer, JobStatus, ObserverInfo, ServerPacket, SessionFootprint,
+    SimulatedIdentity, Simulation, UserLookup,
+};
+use crate::error::*;
+use log::*;
+
+use actix::*;
+use futures::Future;
+
+use crate::auth::UserInfo;
+use crate::moderation::{BanPage, ImportMode, ModerationState, Restriction};
+use crate::storage::{AuditAction, AuditPage, AuditQuery, StatsBucket, StatsResolution};
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Allows controlling a running [`ChatServer`] programmatically,
+/// without the permission checks applied to clients.
+#[derive(Clone)]
+pub struct AdminHandle {
+    addr: Addr<ChatServer>,
+}
+
+impl AdminHandle {
+    pub(super) fn new(addr: Addr<ChatServer>) -> AdminHandle {
+        AdminHandle { addr }
+    }
+
+    /// Bans a user. Moderators can not be banned.
+    pub fn ban(&self, user: Uuid) -> impl Future<Item = (), Error = Error> {
+        self.moderate(user, true)
+    }
+
+    /// Unbans a user.
+    pub fn unban(&self, user: Uuid) -> impl Future<Item = (), Error = Error> {
+        self.moderate(user, false)
+    }
+
+    /// Sends a system message to every connected client.
+    ///
+    /// Only the length of the message is checked, against `message.max_system_length`.
+    pub fn broadcast(
+        &self,
+        author_info: UserInfo,
+        content: String,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.send_broadcast(author_info, AuthorKind::System, content)
+    }
+
+    /// Sends a message a bridge relayed from `origin`, e.g. `discord`, to every connected client.
+    ///
+    /// The message is validated like those of players.
+    pub fn relay(
+        &self,
+        author_info: UserInfo,
+        origin: String,
+        content: String,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.send_broadcast(author_info, AuthorKind::Bridge { origin }, content)
+    }
+
+    /// Adds a word to the word filter.
+    pub fn block_word(&self, word: String) -> impl Future<Item = (), Error = Error> {
+        self.edit_blocked_words(word, true)
+    }
+
+    /// Removes a word from the word filter.
+    pub fn unblock_word(&self, word: String) -> impl Future<Item = (), Error = Error> {
+        self.edit_blocked_words(word, false)
+    }
+
+    /// Returns the blocked words containing `filter`.
+    pub fn blocked_words(
+        &self,
+        filter: Option<String>,
+    ) -> impl Future<Item = Vec<String>, Error = Error> {
+        self.addr
+            .send(AdminListBlockedWords { filter })
+            .map_err(Error::from)
+    }
+
+    /// Runs `content` through the validation of messages,
+    /// as if a moderator sent it if `moderator` is set.
+    ///
+    /// All violated rules are reported, regardless of `validation.dry_run`.
+    pub fn validate(
+        &self,
+        content: String,
+        moderator: bool,
+    ) -> impl Future<Item = ValidationReport, Error = Error> {
+        self.addr
+            .send(AdminValidate { content, moderator })
+            .map_err(Error::from)
+    }
+
+    /// Returns up to `limit` banned users whose uuid starts with `prefix`, skipping the first `offset`.
+    pub fn banned(
+        &self,
+        prefix: String,
+        offset: usize,
+        limit: usize,
+    ) -> impl Future<Item = BanPage, Error = Error> {
+        self.addr
+            .send(AdminListBans {
+                prefix,
+                offset,
+                limit,
+            })
+            .map_err(Error::from)
+    }
+
+    /// Returns the entries of the audit log matching `query`, newest first.
+    pub fn audit_log(&self, query: AuditQuery) -> impl Future<Item = AuditPage, Error = Error> {
+        self.addr.send(AdminAuditLog { query }).map_err(Error::from)
+    }
+
+    /// Returns the banned and whitelisted users.
+    pub fn export_moderation(&self) -> impl Future<Item = ModerationState, Error = Error> {
+        self.addr.send(AdminExportModeration).map_err(Error::from)
+    }
+
+    /// Replaces the banned and whitelisted users or merges `state` into them, in one step.
+    ///
+    /// Online users who are banned afterwards are disconnected.
+    pub fn import_moderation(
+        &self,
+        state: ModerationState,
+        mode: ImportMode,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.addr
+            .send(AdminImportModeration { state, mode })
+            .map_err(Error::from)
+            .and_then(|res| res.map_err(Error::from))
+    }
+
+    /// Returns who is connected with `uuid`, or was connected with it recently.
+    pub fn lookup_user(&self, uuid: Uuid) -> impl Future<Item = UserLookup, Error = Error> {
+        self.addr
+            .send(AdminLookupUser { uuid })
+            .map_err(Error::from)
+            .and_then(|res| res.ok_or_else(|| ClientError::UserNotFound.into()))
+    }
+
+    /// Returns the observers connected to the firehose.
+    pub fn observers(&self) -> impl Future<Item = Vec<ObserverInfo>, Error = Error> {
+        self.addr.send(AdminObservers).map_err(Error::from)
+    }
+
+    /// Returns the `limit` connections for which the most memory is kept, heaviest first.
+    pub fn heaviest_sessions(
+        &self,
+        limit: usize,
+    ) -> impl Future<Item = Vec<SessionFootprint>, Error = Error> {
+        self.addr
+            .send(AdminHeaviestSessions { limit })
+            .map_err(Error::from)
+    }
+
+    /// Returns the long-term statistics of the buckets starting in `from..to`,
+    /// in milliseconds since the unix epoch, oldest first and with zeros for buckets without activity.
+    ///
+    /// `to` is now by default, `from` a week before `to`, and at most 2000 buckets are returned.
+    /// Returns `None` if `stats.enabled` is not set.
+    pub fn stats(
+        &self,
+        resolution: StatsResolution,
+        from: Option<u64>,
+        to: Option<u64>,
+    ) -> impl Future<Item = Option<Vec<StatsBucket>>, Error = Error> {
+        self.addr
+            .send(AdminStats {
+                resolution,
+                from,
+                to,
+            })
+            .map_err(Error::from)
+    }
+
+    /// Handles `packet` as if a connection logged in as `identity` sent it,
+    /// and returns the packets it received in the meantime instead of sending them anywhere.
+    ///
+    /// Nothing caused by the packet reaches other clients or instances,
+    /// and the simulation is recorded in the audit log.
+    pub fn simulate(
+        &self,
+        identity: SimulatedIdentity,
+        packet: ServerPacket,
+    ) -> impl Future<Item = Simulation, Error = Error> {
+        self.addr
+            .send(AdminSimulate { identity, packet })
+            .map_err(Error::from)
+            .and_then(|res| res.map_err(Error::from))
+            .and_then(|capture| capture.send(Collect).map_err(Error::from))
+    }
+
+    /// Starts draining the instance, or cancels it if it already drains.
+    ///
+    /// Returns whether the instance drains afterwards.
+    pub fn drain(&self) -> impl Future<Item = bool, Error = Error> {
+        self.set_draining(None)
+    }
+
+    /// Cancels draining the instance.
+    pub fn undrain(&self) -> impl Future<Item = bool, Error = Error> {
+        self.set_draining(Some(false))
+    }
+
+    /// Runs the maintenance jobs now instead of waiting for `maintenance.interval`,
+    /// and returns their status once those running inside of the chat server finished.
+    pub fn run_maintenance(&self) -> impl Future<Item = Vec<JobStatus>, Error = Error> {
+        self.addr.send(AdminRunMaintenance).map_err(Error::from)
+    }
+
+    fn set_draining(&self, draining: Option<bool>) -> impl Future<Item = bool, Error = Error> {
+        self.addr
+            .send(AdminSetDraining { draining })
+            .map_err(Error::from)
+    }
+
+    fn edit_blocked_words(
+        &self,
+        word: String,
+        block: bool,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.addr
+            .send(AdminEditBlockedWords { word, block })
+            .map_err(Error::from)
+            .and_then(|res| res.map_err(Error::from))
+    }
+
+    fn send_broadcast(
+        &self,
+        author_info: UserInfo,
+        author_kind: AuthorKind,
+        content: String,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.addr
+            .send(AdminBroadcast {
+                author_info,
+                author_kind,
+                content,
+            })
+            .map_err(Error::from)
+            .and_then(|res| res.map_err(Error::from))
+    }
+
+    fn moderate(&self, user: Uuid, ban: bool) -> impl Future<Item = (), Error = Error> {
+        self.addr
+            .send(AdminModerate { user, ban })
+            .map_err(Error::from)
+            .and_then(|res| res.map_err(Error::from))
+    }
+}
+
+struct AdminModerate {
+    user: Uuid,
+    ban: bool,
+}
+
+impl Message for AdminModerate {
+    type Result = std::result::Result<(), ClientError>;
+}
+
+impl Handler<AdminModerate> for ChatServer {
+    type Result = std::result::Result<(), ClientError>;
+
+    fn handle(&mut self, msg: AdminModerate, _ctx: &mut Context<Self>) -> Self::Result {
+        let res = if msg.ban {
+            self.moderation.ban(&msg.user, Restriction::default())
+        } else {
+            self.moderation.unban(&msg.user)
+        };
+        match res {
+            Ok(()) => {
+                info!("User `{}` was (un-)banned by an administrator.", msg.user);
+                let action = if msg.ban {
+                    AuditAction::Ban
+                } else {
+                    AuditAction::Unban
+                };
+                self.record_audit(None, action, msg.user, None, None);
+                self.moderation_persisted();
+                if msg.ban {
+                    self.announce_ban(&msg.user);
+                    self.remove_banned(&msg.user);
+                } else {
+                    self.send_moderation_status(&msg.user);
+                }
+                self.publish(ClusterEvent::Moderation {
+                    user: msg.user,
+                    ban: msg.ban,
+                    reason: None,
+                    expires_at: None,
+                });
+                Ok(())
+            }
+            Err(Error::AxoChat { source }) => Err(source),
+            Err(err) => {
+                warn!("Could not (un-)ban user `{}`: {}", msg.user, err);
+                Err(ClientError::Internal)
+            }
+        }
+    }
+}
+
+struct AdminBroadcast {
+    author_info: UserInfo,
+    author_kind: AuthorKind,
+    content: String,
+}
+
+impl Message for AdminBroadcast {
+    type Result = std::result::Result<(), ClientError>;
+}
+
+impl Handler<AdminBroadcast> for ChatServer {
+    type Result = std::result::Result<(), ClientError>;
+
+    fn handle(&mut self, msg: AdminBroadcast, _ctx: &mut Context<Self>) -> Self::Result {
+        let res = match msg.author_kind {
+            AuthorKind::System => self.validator.validate_system(&msg.content),
+            AuthorKind::Player | AuthorKind::Bridge { .. } => self.validator.validate(&msg.content),
+        };
+        let content = match res {
+            Ok(content) => content,
+            Err(Error::AxoChat { source }) => return Err(source),
+            Err(err) => {
+                warn!("Could not validate broadcast: {}", err);
+                return Err(ClientError::Internal);
+            }
+        };
+
+        info!(
+            "Administrator has written `{}` as `{}`.",
+            content, msg.author_info.name
+        );
+        let event = ClusterEvent::Message {
+            author_info: msg.author_info.clone(),
+            author_kind: msg.author_kind.clone(),
+            content: content.to_string(),
+            redacted: content.is_redacted(),
+        };
+        self.deliver_message(None, msg.author_info, msg.author_kind, content, None)?;
+        self.publish(event);
+        Ok(())
+    }
+}
+
+struct AdminEditBlockedWords {
+    word: String,
+    block: bool,
+}
+
+impl Message for AdminEditBlockedWords {
+    type Result = std::result::Result<(), ClientError>;
+}
+
+impl Handler<AdminEditBlockedWords> for ChatServer {
+    type Result = std::result::Result<(), ClientError>;
+
+    fn handle(&mut self, msg: AdminEditBlockedWords, ctx: &mut Context<Self>) -> Self::Result {
+        self.edit_blocked_words(&msg.word, msg.block, ctx)
+    }
+}
+
+struct AdminListBlockedWords {
+    filter: Option<String>,
+}
+
+impl Message for AdminListBlockedWords {
+    type Result = Vec<String>;
+}
+
+impl Handler<AdminListBlockedWords> for ChatServer {
+    type Result = MessageResult<AdminListBlockedWords>;
+
+    fn handle(&mut self, msg: AdminListBlockedWords, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.list_blocked_words(msg.filter.as_deref()))
+    }
+}
+
+struct AdminLookupUser {
+    uuid: Uuid,
+}
+
+impl Message for AdminLookupUser {
+    type Result = Option<UserLookup>;
+}
+
+impl Handler<AdminLookupUser> for ChatServer {
+    type Result = MessageResult<AdminLookupUser>;
+
+    fn handle(&mut self, msg: AdminLookupUser, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.lookup_uuid(&msg.uuid))
+    }
+}
+
+struct AdminObservers;
+
+impl Message for AdminObservers {
+    type Result = Vec<ObserverInfo>;
+}
+
+impl Handler<AdminObservers> for ChatServer {
+    type Result = MessageResult<AdminObservers>;
+
+    fn handle(&mut self, _msg: AdminObservers, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.observers())
+    }
+}
+
+struct AdminHeaviestSessions {
+    limit: usize,
+}
+
+impl Message for AdminHeaviestSessions {
+    type Result = Vec<SessionFootprint>;
+}
+
+impl Handler<AdminHeaviestSessions> for ChatServer {
+    type Result = MessageResult<AdminHeaviestSessions>;
+
+    fn handle(&mut self, msg: AdminHeaviestSessions, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.heaviest_sessions(msg.limit))
+    }
+}
+
+struct AdminStats {
+    resolution: StatsResolution,
+    from: Option<u64>,
+    to: Option<u64>,
+}
+
+impl Message for AdminStats {
+    type Result = Option<Vec<StatsBucket>>;
+}
+
+impl Handler<AdminStats> for ChatServer {
+    type Result = MessageResult<AdminStats>;
+
+    fn handle(&mut self, msg: AdminStats, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.stats_series(msg.resolution, msg.from, msg.to))
+    }
+}
+
+struct AdminSimulate {
+    identity: SimulatedIdentity,
+    packet: ServerPacket,
+}
+
+impl Message for AdminSimulate {
+    type Result = std::result::Result<Addr<Capture>, ClientError>;
+}
+
+impl Handler<AdminSimulate> for ChatServer {
+    type Result = MessageResult<AdminSimulate>;
+
+    fn handle(&mut self, msg: AdminSimulate, ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.simulate(msg.identity, msg.packet, ctx))
+    }
+}
+
+/// The rules a message violates.
+#[derive(Serialize, Debug)]
+pub struct ValidationReport {
+    /// Whether the message would be delivered if `validation.dry_run` is disabled.
+    pub valid: bool,
+    pub violations: Vec<Violation>,
+}
+
+/// A rule violated by a message.
+#[derive(Serialize, Debug)]
+pub struct Violation {
+    /// The translation key of the error a client would receive.
+    pub rule: &'static str,
+    /// The name of the rule of the validator, like `max_length`.
+    pub name: &'static str,
+    pub message: String,
+    /// The blocked word found in the message, if the rule is the word filter.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub word: Option<String>,
+}
+
+struct AdminValidate {
+    content: String,
+    moderator: bool,
+}
+
+impl Message for AdminValidate {
+    type Result = ValidationReport;
+}
+
+impl Handler<AdminValidate> for ChatServer {
+    type Result = MessageResult<AdminValidate>;
+
+    fn handle(&mut self, msg: AdminValidate, _ctx: &mut Context<Self>) -> Self::Result {
+        let violations: Vec<Violation> = self
+            .validator
+            .violations(&msg.content, msg.moderator)
+            .into_iter()
+            .map(|violation| Violation {
+                rule: violation.error.translation_key(),
+                name: violation.rule,
+                message: violation.error.to_string(),
+                word: match violation.error {
+                    ClientError::BlockedContent => self
+                        .validator
+                        .word_filter()
+                        .find(&msg.content)
+                        .map(str::to_string),
+                    _ => None,
+                },
+            })
+            .collect();
+        MessageResult(ValidationReport {
+            valid: violations.is_empty(),
+            violations,
+        })
+    }
+}
+
+struct AdminAuditLog {
+    query: AuditQuery,
+}
+
+impl Message for AdminAuditLog {
+    type Result = AuditPage;
+}
+
+impl Handler<AdminAuditLog> for ChatServer {
+    type Result = MessageResult<AdminAuditLog>;
+
+    fn handle(&mut self, msg: AdminAuditLog, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.storage.audit_log(&msg.query))
+    }
+}
+
+struct AdminListBans {
+    prefix: String,
+    offset: usize,
+    limit: usize,
+}
+
+impl Message for AdminListBans {
+    type Result = BanPage;
+}
+
+impl Handler<AdminListBans> for ChatServer {
+    type Result = MessageResult<AdminListBans>;
+
+    fn handle(&mut self, msg: AdminListBans, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(
+            self.moderation
+                .banned_page(&msg.prefix, msg.offset, msg.limit),
+        )
+    }
+}
+
+struct AdminSetDraining {
+    draining: Option<bool>,
+}
+
+impl Message for AdminSetDraining {
+    type Result = bool;
+}
+
+impl Handler<AdminSetDraining> for ChatServer {
+    type Result = bool;
+
+    fn handle(&mut self, msg: AdminSetDraining, ctx: &mut Context<Self>) -> Self::Result {
+        self.set_draining(msg.draining, ctx)
+    }
+}
+
+struct AdminRunMaintenance;
+
+impl Message for AdminRunMaintenance {
+    type Result = Vec<JobStatus>;
+}
+
+impl Handler<AdminRunMaintenance> for ChatServer {
+    type Result = MessageResult<AdminRunMaintenance>;
+
+    fn handle(&mut self, _msg: AdminRunMaintenance, ctx: &mut Context<Self>) -> Self::Result {
+        info!("Administrator started the maintenance jobs.");
+        self.run_maintenance(ctx);
+        MessageResult(self.maintenance_status())
+    }
+}
+
+struct AdminExportModeration;
+
+impl Message for AdminExportModeration {
+    type Result = ModerationState;
+}
+
+impl Handler<AdminExportModeration> for ChatServer {
+    type Result = MessageResult<AdminExportModeration>;
+
+    fn handle(&mut self, _msg: AdminExportModeration, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.moderation.export(self.system_now()))
+    }
+}
+
+struct AdminImportModeration {
+    state: ModerationState,
+    mode: ImportMode,
+}
+
+impl Message for AdminImportModeration {
+    type Result = std::result::Result<(), ClientError>;
+}
+
+impl Handler<AdminImportModeration> for ChatServer {
+    type Result = std::result::Result<(), ClientError>;
+
+    fn handle(&mut self, msg: AdminImportModeration, _ctx: &mut Context<Self>) -> Self::Result {
+        let (banned, muted, whitelisted) = (
+            msg.state.banned.len(),
+            msg.state.mutes.len(),
+            msg.state.whitelisted.len(),
+        );
+        let now = self.system_now();
+        let was_banned: Vec<Uuid> = self
+            .sessions
+            .online_uuids()
+            .filter(|uuid| self.moderation.is_banned(uuid, now))
+            .copied()
+            .collect();
+        let mutes_before: Vec<(Uuid, Option<Restriction>)> = self
+            .sessions
+            .online_uuids()
+            .map(|uuid| (*uuid, self.moderation.mute_of(uuid, now).cloned()))
+            .collect();
+        match self.moderation.import(msg.state, msg.mode, now) {
+            Ok(()) => {
+                info!(
+                    "Administrator imported {} bans, {} mutes and {} whitelisted users.",
+                    banned, muted, whitelisted
+                );
+                self.moderation_persisted();
+                let online_banned: Vec<Uuid> = self
+                    .sessions
+                    .online_uuids()
+                    .filter(|uuid| self.moderation.is_banned(uuid, now))
+                    .copied()
+                    .collect();
+                for uuid in &online_banned {
+                    self.remove_banned(uuid);
+                }
+                for uuid in &was_banned {
+                    if !self.moderation.is_banned(uuid, now) {
+                        self.send_moderation_status(uuid);
+                    }
+                }
+                for (uuid, mute) in &mutes_before {
+                    if !self.moderation.is_banned(uuid, now)
+                        && !was_banned.contains(uuid)
+                        && self.moderation.mute_of(uuid, now) != mute.as_ref()
+                    {
+                        self.send_moderation_status(uuid);
+                    }
+                }
+                Ok(())
+            }
+            Err(Error::AxoChat { source }) => Err(source),
+            Err(err) => {
+                warn!("Could not import moderation state: {}", err);
+                Err(ClientError::Internal)
+            }
+        }
+    }
+}
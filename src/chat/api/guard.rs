@@ -0,0 +1,277 @@
+//! Rate limiting and request counting for the admin API.
+//!
+//! Requests exceeding a limit are answered with `429 Too Many Requests`
+//! before they reach their handler, so they never cause work on the [`ChatServer`](crate::chat::ChatServer).
+
+use crate::config::ApiConfig;
+use log::*;
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header, HeaderMap},
+    HttpResponse,
+};
+use futures::{
+    future::{self, FutureResult},
+    Future, Poll,
+};
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The amount of tracked clients at which expired entries are removed.
+const MIN_PRUNE_LEN: usize = 1024;
+
+/// Limits the requests per token and per IP address, shared by all workers.
+pub(in crate::chat) struct RateLimits {
+    max_per_token: usize,
+    max_per_ip: usize,
+    duration: Duration,
+    state: Mutex<LimitState>,
+}
+
+#[derive(Default)]
+struct LimitState {
+    per_token: Window<String>,
+    per_ip: Window<IpAddr>,
+}
+
+/// The instants of the recent requests of every client.
+struct Window<K> {
+    requests: HashMap<K, VecDeque<Instant>>,
+    prune_len: usize,
+}
+
+impl<K> Default for Window<K> {
+    fn default() -> Window<K> {
+        Window {
+            requests: HashMap::new(),
+            prune_len: MIN_PRUNE_LEN,
+        }
+    }
+}
+
+impl<K: Hash + Eq> Window<K> {
+    /// Returns how long `key` has to wait if another request would exceed `max`.
+    fn check<Q>(
+        &mut self,
+        key: &Q,
+        max: usize,
+        duration: Duration,
+        now: Instant,
+    ) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let requests = self.requests.get_mut(key)?;
+        while requests
+            .front()
+            .is_some_and(|time| now.duration_since(*time) >= duration)
+        {
+            requests.pop_front();
+        }
+        if requests.len() < max {
+            None
+        } else {
+            requests
+                .front()
+                .map(|oldest| duration - now.duration_since(*oldest))
+        }
+    }
+
+    fn record(&mut self, key: K, duration: Duration, now: Instant) {
+        self.requests.entry(key).or_default().push_back(now);
+
+        if self.requests.len() >= self.prune_len {
+            self.requests.retain(|_, requests| {
+                requests
+                    .back()
+                    .is_some_and(|time| now.duration_since(*time) < duration)
+            });
+            self.prune_len = (self.requests.len() * 2).max(MIN_PRUNE_LEN);
+        }
+    }
+}
+
+impl RateLimits {
+    pub fn new(cfg: &ApiConfig) -> RateLimits {
+        RateLimits {
+            max_per_token: cfg.max_requests_per_token,
+            max_per_ip: cfg.max_requests_per_ip,
+            duration: *cfg.rate_limit_duration,
+            state: Mutex::default(),
+        }
+    }
+
+    /// The stricter limits of `/api/v1/simulate`, which are checked in addition to the usual ones.
+    pub fn simulations(cfg: &ApiConfig) -> RateLimits {
+        RateLimits {
+            max_per_token: cfg.max_simulations,
+            max_per_ip: cfg.max_simulations,
+            duration: *cfg.rate_limit_duration,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Registers a request, unless it exceeds a limit.
+    /// In that case returns how long the client has to wait.
+    pub(super) fn check(&self, ip: Option<IpAddr>, token: Option<&str>) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut state = self
+            .state
+            .lock()
+            .expect("rate limits should not be poisoned");
+
+        let ip_wait =
+            ip.and_then(|ip| state.per_ip.check(&ip, self.max_per_ip, self.duration, now));
+        let token_wait = token.and_then(|token| {
+            state
+                .per_token
+                .check(token, self.max_per_token, self.duration, now)
+        });
+        if let Some(wait) = ip_wait.max(token_wait) {
+            return Err(wait);
+        }
+
+        if let Some(ip) = ip {
+            state.per_ip.record(ip, self.duration, now);
+        }
+        if let Some(token) = token {
+            state
+                .per_token
+                .record(token.to_string(), self.duration, now);
+        }
+        Ok(())
+    }
+}
+
+/// Counts the admin API requests by method, route and status.
+#[derive(Default)]
+pub(in crate::chat) struct RequestCounts {
+    counts: Mutex<BTreeMap<(String, &'static str, u16), u64>>,
+}
+
+impl RequestCounts {
+    fn record(&self, method: String, route: &'static str, status: u16) {
+        *self
+            .counts
+            .lock()
+            .expect("request counts should not be poisoned")
+            .entry((method, route, status))
+            .or_default() += 1;
+    }
+
+    /// Calls `f` with the method, route, status and count of every combination seen so far.
+    pub fn for_each<F: FnMut(&str, &str, u16, u64)>(&self, mut f: F) {
+        let counts = self
+            .counts
+            .lock()
+            .expect("request counts should not be poisoned");
+        for ((method, route, status), count) in counts.iter() {
+            f(method, route, *status, *count);
+        }
+    }
+}
+
+/// Returns the token of an `Authorization: Bearer <token>` header.
+pub(in crate::chat) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Answers a request exceeding a limit, telling the client to retry after `wait`.
+pub(super) fn too_many_requests(wait: Duration) -> HttpResponse {
+    let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    HttpResponse::TooManyRequests()
+        .header(header::RETRY_AFTER, retry_after.to_string())
+        .finish()
+}
+
+/// Middleware applying the [`RateLimits`] and [`RequestCounts`] to a route.
+#[derive(Clone)]
+pub(super) struct Guard {
+    route: &'static str,
+    limits: Arc<RateLimits>,
+    counts: Arc<RequestCounts>,
+}
+
+impl Guard {
+    pub fn new(route: &'static str, limits: Arc<RateLimits>, counts: Arc<RequestCounts>) -> Guard {
+        Guard {
+            route,
+            limits,
+            counts,
+        }
+    }
+}
+
+impl<S> Transform<S> for Guard
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = GuardMiddleware<S>;
+    type Future = FutureResult<GuardMiddleware<S>, ()>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(GuardMiddleware {
+            service,
+            guard: self.clone(),
+        })
+    }
+}
+
+pub(super) struct GuardMiddleware<S> {
+    service: S,
+    guard: Guard,
+}
+
+impl<S> Service for GuardMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type Future = Box<dyn Future<Item = ServiceResponse, Error = actix_web::Error>>;
+
+    fn poll_ready(&mut self) -> Poll<(), actix_web::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let Guard {
+            route,
+            limits,
+            counts,
+        } = self.guard.clone();
+        let method = req.method().to_string();
+
+        let ip = req.peer_addr().map(|addr| addr.ip());
+        if let Err(wait) = limits.check(ip, bearer_token(req.headers())) {
+            info!("Rate limited admin API request to `{} {}`.", method, route);
+            counts.record(method, route, 429);
+            return Box::new(future::ok(req.into_response(too_many_requests(wait))));
+        }
+
+        Box::new(self.service.call(req).then(move |res| {
+            let status = match &res {
+                Ok(res) => res.status(),
+                Err(err) => err.as_response_error().error_response().status(),
+            };
+            counts.record(method, route, status.as_u16());
+            res
+        }))
+    }
+}
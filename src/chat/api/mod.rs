@@ -0,0 +1,669 @@
+//! The admin HTTP API at `/api/v1`.
+//!
+//! Every request has to be authenticated with the configured token
+//! in an `Authorization: Bearer <token>` header.
+//! Requests are rate limited per token and per IP address.
+
+mod guard;
+
+pub(super) use guard::{bearer_token, RateLimits, RequestCounts};
+
+use super::{
+    auth_monitor::{AuthFailureRecord, AuthMonitor},
+    decode_packet, firehose,
+    funnel::Funnel,
+    AdminHandle, ChatServer, PacketLimits, SimulatedIdentity,
+};
+use crate::error::*;
+use crate::moderation::{ImportMode, ModerationState};
+use crate::signing::MessageSigner;
+use crate::storage::{AuditQuery, StatsResolution};
+use log::*;
+
+use actix::Addr;
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures::{future, Future};
+use guard::{too_many_requests, Guard};
+use ring::constant_time;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+type ApiResponse = Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>>;
+
+/// What the routes need from the chat server.
+pub(super) struct ApiState {
+    pub admin: AdminHandle,
+    pub token: String,
+    pub auth_monitor: Arc<AuthMonitor>,
+    pub funnel: Arc<Funnel>,
+    pub signer: Option<Arc<MessageSigner>>,
+    /// The limits of `/simulate`, in addition to those of every route.
+    pub simulations: Arc<RateLimits>,
+    /// The limits simulated packets are decoded with, like those of clients.
+    pub packet_limits: PacketLimits,
+}
+
+/// The maximum size of the JSON body of a request blocking a word.
+const MAX_BLOCKED_WORD_BODY: usize = 1024;
+
+/// The maximum size of an imported moderation state.
+const MAX_IMPORT_BODY: usize = 16 * 1024 * 1024;
+
+/// The maximum number of banned users returned by one request to `/moderation/bans`.
+const MAX_BANS_PER_PAGE: usize = 1000;
+
+/// The maximum number of entries returned by one request to `/moderation/audit`.
+const MAX_AUDIT_ENTRIES_PER_PAGE: usize = 1000;
+
+/// The maximum number of sessions returned by one request to `/debug/sessions`.
+const MAX_DEBUG_SESSIONS: usize = 100;
+
+/// The maximum size of a message validated with `/validate`.
+const MAX_VALIDATE_BODY: usize = 64 * 1024;
+
+/// The maximum size of a packet simulated with `/simulate`, including the identity.
+const MAX_SIMULATE_BODY: usize = 64 * 1024;
+
+/// Registers the API routes.
+pub(super) fn configure(
+    cfg: &mut web::ServiceConfig,
+    state: ApiState,
+    limits: Arc<RateLimits>,
+    counts: Arc<RequestCounts>,
+) {
+    let guard = |route| Guard::new(route, limits.clone(), counts.clone());
+    // The state is application data, since the `JsonConfig` of a resource
+    // would hide data of the scope from its handlers.
+    cfg.data(state);
+    cfg.service(
+        web::scope("/api/v1")
+            .service(
+                web::resource("/blocked-words")
+                    .data(web::JsonConfig::default().limit(MAX_BLOCKED_WORD_BODY))
+                    .route(web::get().to_async(list_blocked_words))
+                    .route(web::post().to_async(add_blocked_word))
+                    .wrap(guard("/api/v1/blocked-words")),
+            )
+            .service(
+                web::resource("/blocked-words/{word}")
+                    .route(web::delete().to_async(remove_blocked_word))
+                    .wrap(guard("/api/v1/blocked-words/{word}")),
+            )
+            .service(
+                web::resource("/moderation/bans")
+                    .route(web::get().to_async(list_bans))
+                    .wrap(guard("/api/v1/moderation/bans")),
+            )
+            .service(
+                web::resource("/moderation/audit")
+                    .route(web::get().to_async(audit_log))
+                    .wrap(guard("/api/v1/moderation/audit")),
+            )
+            .service(
+                web::resource("/moderation/export")
+                    .route(web::get().to_async(export_moderation))
+                    .wrap(guard("/api/v1/moderation/export")),
+            )
+            .service(
+                web::resource("/moderation/import")
+                    .data(web::JsonConfig::default().limit(MAX_IMPORT_BODY))
+                    .route(web::put().to_async(import_moderation))
+                    .wrap(guard("/api/v1/moderation/import")),
+            )
+            .service(
+                web::resource("/users/{uuid}")
+                    .route(web::get().to_async(lookup_user))
+                    .wrap(guard("/api/v1/users/{uuid}")),
+            )
+            .service(
+                web::resource("/validate")
+                    .data(web::JsonConfig::default().limit(MAX_VALIDATE_BODY))
+                    .route(web::post().to_async(validate))
+                    .wrap(guard("/api/v1/validate")),
+            )
+            .service(
+                web::resource("/simulate")
+                    .data(web::JsonConfig::default().limit(MAX_SIMULATE_BODY))
+                    .route(web::post().to_async(simulate))
+                    .wrap(guard("/api/v1/simulate")),
+            )
+            .service(
+                web::resource("/auth_failures")
+                    .route(web::get().to(auth_failures))
+                    .wrap(guard("/api/v1/auth_failures")),
+            )
+            .service(
+                web::resource("/funnel")
+                    .route(web::get().to(funnel_summary))
+                    .wrap(guard("/api/v1/funnel")),
+            )
+            .service(
+                web::resource("/firehose")
+                    .route(web::get().to(open_firehose))
+                    .wrap(guard("/api/v1/firehose")),
+            )
+            .service(
+                web::resource("/observers")
+                    .route(web::get().to_async(list_observers))
+                    .wrap(guard("/api/v1/observers")),
+            )
+            .service(
+                web::resource("/stats")
+                    .route(web::get().to_async(stats))
+                    .wrap(guard("/api/v1/stats")),
+            )
+            .service(
+                web::resource("/debug/sessions")
+                    .route(web::get().to_async(heaviest_sessions))
+                    .wrap(guard("/api/v1/debug/sessions")),
+            )
+            .service(
+                web::resource("/drain")
+                    .route(web::post().to_async(drain))
+                    .wrap(guard("/api/v1/drain")),
+            )
+            .service(
+                web::resource("/undrain")
+                    .route(web::post().to_async(undrain))
+                    .wrap(guard("/api/v1/undrain")),
+            )
+            .service(
+                web::resource("/maintenance")
+                    .route(web::post().to_async(run_maintenance))
+                    .wrap(guard("/api/v1/maintenance")),
+            )
+            .service(
+                web::resource("/signing_key")
+                    .route(web::get().to(signing_key))
+                    .wrap(guard("/api/v1/signing_key")),
+            ),
+    );
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    filter: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BlockedWords {
+    words: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct BlockedWord {
+    word: String,
+}
+
+#[derive(Deserialize)]
+struct ValidateRequest {
+    content: String,
+    #[serde(default)]
+    moderator: bool,
+}
+
+#[derive(Deserialize)]
+struct SimulateRequest {
+    identity: SimulatedIdentity,
+    /// The packet, which is decoded like those of clients.
+    packet: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct BansQuery {
+    #[serde(default)]
+    prefix: String,
+    #[serde(default)]
+    offset: usize,
+    #[serde(default = "default_bans_limit")]
+    limit: usize,
+}
+
+fn default_bans_limit() -> usize {
+    100
+}
+
+#[derive(Deserialize)]
+struct AuditLogQuery {
+    actor: Option<Uuid>,
+    target: Option<Uuid>,
+    since: Option<u64>,
+    #[serde(default)]
+    offset: usize,
+    #[serde(default = "default_bans_limit")]
+    limit: usize,
+}
+
+#[derive(Deserialize)]
+struct ImportQuery {
+    #[serde(default)]
+    mode: ImportMode,
+}
+
+#[derive(Deserialize)]
+struct FunnelQuery {
+    /// The length of the window in seconds.
+    #[serde(default = "default_funnel_window")]
+    window: u64,
+}
+
+fn default_funnel_window() -> u64 {
+    60 * 60
+}
+
+#[derive(Deserialize)]
+struct DebugSessionsQuery {
+    #[serde(default = "default_debug_sessions_limit")]
+    limit: usize,
+}
+
+fn default_debug_sessions_limit() -> usize {
+    10
+}
+
+#[derive(Deserialize)]
+struct StatsQuery {
+    /// In milliseconds since the unix epoch.
+    from: Option<u64>,
+    to: Option<u64>,
+    #[serde(default = "default_stats_resolution")]
+    resolution: StatsResolution,
+}
+
+fn default_stats_resolution() -> StatsResolution {
+    StatsResolution::Hour
+}
+
+#[derive(Serialize)]
+struct Draining {
+    draining: bool,
+}
+
+#[derive(Serialize)]
+struct SigningKey {
+    algorithm: &'static str,
+    public_key: String,
+}
+
+#[derive(Serialize)]
+struct AuthFailures {
+    degraded: bool,
+    failures: Vec<AuthFailureRecord>,
+}
+
+#[derive(Serialize)]
+struct ApiError {
+    error: ClientError,
+}
+
+fn list_blocked_words(
+    req: HttpRequest,
+    state: web::Data<ApiState>,
+    query: web::Query<ListQuery>,
+) -> ApiResponse {
+    if !is_authorized(&req, &state) {
+        return unauthorized();
+    }
+    Box::new(
+        state
+            .admin
+            .blocked_words(query.into_inner().filter)
+            .then(|res| {
+                Ok(match res {
+                    Ok(words) => HttpResponse::Ok().json(BlockedWords { words }),
+                    Err(err) => error_response(err),
+                })
+            }),
+    )
+}
+
+fn add_blocked_word(
+    req: HttpRequest,
+    state: web::Data<ApiState>,
+    body: web::Json<BlockedWord>,
+) -> ApiResponse {
+    if !is_authorized(&req, &state) {
+        return unauthorized();
+    }
+    Box::new(
+        state
+            .admin
+            .block_word(body.into_inner().word)
+            .then(|res| Ok(empty_response(res))),
+    )
+}
+
+fn remove_blocked_word(
+    req: HttpRequest,
+    state: web::Data<ApiState>,
+    word: web::Path<String>,
+) -> ApiResponse {
+    if !is_authorized(&req, &state) {
+        return unauthorized();
+    }
+    Box::new(
+        state
+            .admin
+            .unblock_word(word.into_inner())
+            .then(|res| Ok(empty_response(res))),
+    )
+}
+
+fn list_bans(
+    req: HttpRequest,
+    state: web::Data<ApiState>,
+    query: web::Query<BansQuery>,
+) -> ApiResponse {
+    if !is_authorized(&req, &state) {
+        return unauthorized();
+    }
+    let query = query.into_inner();
+    Box::new(
+        state
+            .admin
+            .banned(
+                query.prefix,
+                query.offset,
+                query.limit.min(MAX_BANS_PER_PAGE),
+            )
+            .then(|res| {
+                Ok(match res {
+                    Ok(page) => HttpResponse::Ok().json(page),
+                    Err(err) => error_response(err),
+                })
+            }),
+    )
+}
+
+fn audit_log(
+    req: HttpRequest,
+    state: web::Data<ApiState>,
+    query: web::Query<AuditLogQuery>,
+) -> ApiResponse {
+    if !is_authorized(&req, &state) {
+        return unauthorized();
+    }
+    let query = query.into_inner();
+    let query = AuditQuery {
+        actor: query.actor,
+        target: query.target,
+        since: query.since,
+        offset: query.offset,
+        limit: query.limit.min(MAX_AUDIT_ENTRIES_PER_PAGE),
+    };
+    Box::new(state.admin.audit_log(query).then(|res| {
+        Ok(match res {
+            Ok(page) => HttpResponse::Ok().json(page),
+            Err(err) => error_response(err),
+        })
+    }))
+}
+
+fn drain(req: HttpRequest, state: web::Data<ApiState>) -> ApiResponse {
+    if !is_authorized(&req, &state) {
+        return unauthorized();
+    }
+    Box::new(state.admin.drain().then(draining_response))
+}
+
+fn undrain(req: HttpRequest, state: web::Data<ApiState>) -> ApiResponse {
+    if !is_authorized(&req, &state) {
+        return unauthorized();
+    }
+    Box::new(state.admin.undrain().then(draining_response))
+}
+
+fn draining_response(res: Result<bool>) -> std::result::Result<HttpResponse, actix_web::Error> {
+    Ok(match res {
+        Ok(draining) => HttpResponse::Ok().json(Draining { draining }),
+        Err(err) => error_response(err),
+    })
+}
+
+fn run_maintenance(req: HttpRequest, state: web::Data<ApiState>) -> ApiResponse {
+    if !is_authorized(&req, &state) {
+        return unauthorized();
+    }
+    Box::new(state.admin.run_maintenance().then(|res| {
+        Ok(match res {
+            Ok(jobs) => HttpResponse::Ok().json(jobs),
+            Err(err) => error_response(err),
+        })
+    }))
+}
+
+fn export_moderation(req: HttpRequest, state: web::Data<ApiState>) -> ApiResponse {
+    if !is_authorized(&req, &state) {
+        return unauthorized();
+    }
+    Box::new(state.admin.export_moderation().then(|res| {
+        Ok(match res {
+            Ok(moderation) => HttpResponse::Ok().json(moderation),
+            Err(err) => error_response(err),
+        })
+    }))
+}
+
+fn import_moderation(
+    req: HttpRequest,
+    state: web::Data<ApiState>,
+    query: web::Query<ImportQuery>,
+    body: web::Json<ModerationState>,
+) -> ApiResponse {
+    if !is_authorized(&req, &state) {
+        return unauthorized();
+    }
+    Box::new(
+        state
+            .admin
+            .import_moderation(body.into_inner(), query.mode)
+            .then(|res| Ok(empty_response(res))),
+    )
+}
+
+fn lookup_user(req: HttpRequest, state: web::Data<ApiState>, uuid: web::Path<Uuid>) -> ApiResponse {
+    if !is_authorized(&req, &state) {
+        return unauthorized();
+    }
+    Box::new(state.admin.lookup_user(uuid.into_inner()).then(|res| {
+        Ok(match res {
+            Ok(lookup) => HttpResponse::Ok().json(lookup),
+            Err(err) => error_response(err),
+        })
+    }))
+}
+
+fn validate(
+    req: HttpRequest,
+    state: web::Data<ApiState>,
+    body: web::Json<ValidateRequest>,
+) -> ApiResponse {
+    if !is_authorized(&req, &state) {
+        return unauthorized();
+    }
+    let body = body.into_inner();
+    Box::new(
+        state
+            .admin
+            .validate(body.content, body.moderator)
+            .then(|res| {
+                Ok(match res {
+                    Ok(report) => HttpResponse::Ok().json(report),
+                    Err(err) => error_response(err),
+                })
+            }),
+    )
+}
+
+fn simulate(
+    req: HttpRequest,
+    state: web::Data<ApiState>,
+    body: web::Json<SimulateRequest>,
+) -> ApiResponse {
+    if !is_authorized(&req, &state) {
+        return unauthorized();
+    }
+    let ip = req.peer_addr().map(|addr| addr.ip());
+    if let Err(wait) = state.simulations.check(ip, bearer_token(req.headers())) {
+        info!("Rate limited simulation of a packet.");
+        return Box::new(future::ok(too_many_requests(wait)));
+    }
+    let body = body.into_inner();
+    let packet = match decode_packet(&body.packet.to_string(), &state.packet_limits) {
+        Ok(packet) => packet,
+        Err(category) => {
+            return Box::new(future::ok(error_response(
+                ClientError::MalformedPacket { category }.into(),
+            )))
+        }
+    };
+    Box::new(state.admin.simulate(body.identity, packet).then(|res| {
+        Ok(match res {
+            Ok(simulation) => HttpResponse::Ok().json(simulation),
+            Err(err) => error_response(err),
+        })
+    }))
+}
+
+fn auth_failures(req: HttpRequest, state: web::Data<ApiState>) -> HttpResponse {
+    if !is_authorized(&req, &state) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    HttpResponse::Ok().json(AuthFailures {
+        degraded: state.auth_monitor.degraded(),
+        failures: state.auth_monitor.recent_failures(),
+    })
+}
+
+fn funnel_summary(
+    req: HttpRequest,
+    state: web::Data<ApiState>,
+    query: web::Query<FunnelQuery>,
+) -> HttpResponse {
+    if !is_authorized(&req, &state) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    HttpResponse::Ok().json(state.funnel.summary(Duration::from_secs(query.window)))
+}
+
+fn signing_key(req: HttpRequest, state: web::Data<ApiState>) -> HttpResponse {
+    if !is_authorized(&req, &state) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    match &state.signer {
+        Some(signer) => HttpResponse::Ok().json(SigningKey {
+            algorithm: "ed25519",
+            public_key: signer.public_key(),
+        }),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Starts a websocket receiving broadcast messages, presence and moderation events.
+///
+/// The connection does not count towards `server.max_connections`.
+fn open_firehose(
+    req: HttpRequest,
+    stream: web::Payload,
+    state: web::Data<ApiState>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> actix_web::Result<HttpResponse> {
+    if !is_authorized(&req, &state) {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+    firehose::start(&srv, &req, stream)
+}
+
+fn list_observers(req: HttpRequest, state: web::Data<ApiState>) -> ApiResponse {
+    if !is_authorized(&req, &state) {
+        return unauthorized();
+    }
+    Box::new(state.admin.observers().then(|res| {
+        Ok(match res {
+            Ok(observers) => HttpResponse::Ok().json(observers),
+            Err(err) => error_response(err),
+        })
+    }))
+}
+
+fn stats(
+    req: HttpRequest,
+    state: web::Data<ApiState>,
+    query: web::Query<StatsQuery>,
+) -> ApiResponse {
+    if !is_authorized(&req, &state) {
+        return unauthorized();
+    }
+    let query = query.into_inner();
+    Box::new(
+        state
+            .admin
+            .stats(query.resolution, query.from, query.to)
+            .then(|res| {
+                Ok(match res {
+                    Ok(Some(buckets)) => HttpResponse::Ok().json(buckets),
+                    Ok(None) => HttpResponse::NotFound().finish(),
+                    Err(err) => error_response(err),
+                })
+            }),
+    )
+}
+
+fn heaviest_sessions(
+    req: HttpRequest,
+    state: web::Data<ApiState>,
+    query: web::Query<DebugSessionsQuery>,
+) -> ApiResponse {
+    if !is_authorized(&req, &state) {
+        return unauthorized();
+    }
+    let limit = query.limit.min(MAX_DEBUG_SESSIONS);
+    Box::new(state.admin.heaviest_sessions(limit).then(|res| {
+        Ok(match res {
+            Ok(sessions) => HttpResponse::Ok().json(sessions),
+            Err(err) => error_response(err),
+        })
+    }))
+}
+
+fn is_authorized(req: &HttpRequest, state: &ApiState) -> bool {
+    bearer_token(req.headers()).is_some_and(|token| {
+        constant_time::verify_slices_are_equal(token.as_bytes(), state.token.as_bytes()).is_ok()
+    })
+}
+
+fn unauthorized() -> ApiResponse {
+    Box::new(future::ok(HttpResponse::Unauthorized().finish()))
+}
+
+fn empty_response(res: Result<()>) -> HttpResponse {
+    match res {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    }
+}
+
+fn error_response(err: Error) -> HttpResponse {
+    match err {
+        Error::AxoChat {
+            source: error @ ClientError::NotBlocked,
+        }
+        | Error::AxoChat {
+            source: error @ ClientError::UserNotFound,
+        } => HttpResponse::NotFound().json(ApiError { error }),
+        Error::AxoChat {
+            source: ClientError::Internal,
+        } => HttpResponse::InternalServerError().json(ApiError {
+            error: ClientError::Internal,
+        }),
+        Error::AxoChat { source: error } => HttpResponse::BadRequest().json(ApiError { error }),
+        err => {
+            warn!("Admin API request failed: {}", err);
+            HttpResponse::InternalServerError().json(ApiError {
+                error: ClientError::Internal,
+            })
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! The audit log of moderation actions, which is kept by the [`Storage`](crate::storage::Storage).
+
+use super::{cluster, firehose::EventKind, ChatServer, ClientPacket, InternalId};
+use crate::error::*;
+use crate::storage::{AuditAction, AuditEntry, AuditQuery};
+use log::*;
+
+use uuid::Uuid;
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+/// The number of entries sent in one `AuditLog` packet if the moderator did not ask for a number.
+pub(super) const DEFAULT_AUDIT_ENTRIES: usize = 20;
+
+impl ChatServer {
+    /// Removes the entries older than `moderation.audit_retention_days`, as a maintenance job.
+    pub(super) fn prune_audit(&mut self) -> Result<()> {
+        let retention = self
+            .config
+            .moderation
+            .audit_retention_days
+            .saturating_mul(DAY_MILLIS);
+        let before = cluster::unix_millis(self.system_now()).saturating_sub(retention);
+        let removed = self.storage.prune_audit(before)?;
+        if removed > 0 {
+            info!("Removed {} old entries from the audit log.", removed);
+        }
+        Ok(())
+    }
+
+    /// Records a moderation action of `actor`, or of the admin API if it is `None`,
+    /// which lasts `duration_secs` if it is a ban or mute which ends.
+    ///
+    /// The action stays in effect if it can not be recorded.
+    pub(in crate::chat) fn record_audit(
+        &mut self,
+        actor: Option<Uuid>,
+        action: AuditAction,
+        target: Uuid,
+        reason: Option<String>,
+        duration_secs: Option<u64>,
+    ) {
+        let entry = AuditEntry {
+            timestamp: cluster::unix_millis(self.system_now()),
+            actor,
+            action,
+            target,
+            reason,
+            duration_secs,
+        };
+        self.publish_firehose(EventKind::Moderation, || {
+            ClientPacket::ModerationAction(entry.clone())
+        });
+        if let Err(err) = self.storage.record_audit(entry) {
+            warn!(
+                "Could not record moderation action in the audit log: {}",
+                err
+            );
+        }
+    }
+
+    /// Sends the moderator `user_id` the newest entries of the audit log matching `query`,
+    /// at most `moderation.audit_log_max_entries` of them.
+    pub(in crate::chat) fn handle_request_audit_log(
+        &mut self,
+        user_id: InternalId,
+        mut query: AuditQuery,
+    ) {
+        let session = self
+            .sessions
+            .get(&user_id)
+            .expect("could not find connection");
+        let packet = match &session.user {
+            Some(info) if self.is_moderator(&info.uuid) => {
+                query.limit = query
+                    .limit
+                    .min(self.config.moderation.audit_log_max_entries);
+                ClientPacket::AuditLog(self.storage.audit_log(&query))
+            }
+            Some(_) => {
+                info!(
+                    "User `{}` tried to read the audit log without permission.",
+                    user_id
+                );
+                ClientPacket::Error {
+                    message: ClientError::NotPermitted,
+                }
+            }
+            None => ClientPacket::Error {
+                message: ClientError::NotLoggedIn,
+            },
+        };
+        self.send_to(user_id, session, packet);
+    }
+}
@@ -0,0 +1,286 @@
+//! Latency and failure tracking of authentications with Mojang,
+//! and the reasons logins with a JWT failed.
+
+use crate::auth::AuthFailure;
+use crate::config::MojangConfig;
+use log::*;
+
+use ring::digest;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The upper bounds of the latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// The minimum number of authentications in the window before it can be considered degraded.
+const MIN_ATTEMPTS: usize = 10;
+
+/// The number of bytes of the SHA-256 hash of a user name included in a failure.
+const NAME_HASH_LEN: usize = 8;
+
+/// Collects the outcomes of authentications with Mojang, shared by the chat server and the routes.
+pub(in crate::chat) struct AuthMonitor {
+    max_failures: usize,
+    window: Duration,
+    threshold: f64,
+    state: Mutex<MonitorState>,
+}
+
+#[derive(Default)]
+struct MonitorState {
+    /// The number of authentications with a latency of at most each bucket.
+    buckets: Vec<u64>,
+    latency_sum: Duration,
+    successes: u64,
+    invalid: u64,
+    timeouts: u64,
+    http_errors: u64,
+    /// The number of logins attempted with a session hash older than `mojang.request_ttl`.
+    expired_requests: u64,
+    /// The number of failed logins with a JWT, by reason.
+    jwt_failures: BTreeMap<&'static str, u64>,
+    /// The most recent failures, oldest first.
+    failures: VecDeque<AuthFailureRecord>,
+    /// When each authentication in the window finished and whether Mojang failed to handle it.
+    attempts: VecDeque<(Instant, bool)>,
+    degraded: bool,
+}
+
+/// A failed authentication, as returned by the admin API.
+#[derive(Serialize, Clone)]
+pub(in crate::chat) struct AuthFailureRecord {
+    /// The time of the failure, in milliseconds since the unix epoch.
+    timestamp: u64,
+    kind: AuthFailure,
+    error: String,
+    /// The start of the hex encoded SHA-256 hash of the user name.
+    user_hash: String,
+}
+
+impl AuthMonitor {
+    pub fn new(config: &MojangConfig) -> AuthMonitor {
+        AuthMonitor {
+            max_failures: config.recent_failures,
+            window: *config.failure_window,
+            threshold: config.degraded_threshold,
+            state: Mutex::new(MonitorState {
+                buckets: vec![0; LATENCY_BUCKETS.len()],
+                ..MonitorState::default()
+            }),
+        }
+    }
+
+    /// Records an authentication which took `latency`.
+    ///
+    /// `failure` is set if it failed, together with the error.
+    pub fn record(
+        &self,
+        username: &str,
+        latency: Duration,
+        failure: Option<(AuthFailure, String)>,
+    ) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        let secs = latency.as_secs_f64();
+        for (bucket, bound) in state.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if secs <= *bound {
+                *bucket += 1;
+            }
+        }
+        state.latency_sum += latency;
+
+        let backend_failed = match &failure {
+            None => {
+                state.successes += 1;
+                false
+            }
+            Some((kind, _)) => {
+                match kind {
+                    AuthFailure::Invalid => state.invalid += 1,
+                    AuthFailure::Timeout => state.timeouts += 1,
+                    AuthFailure::HttpError => state.http_errors += 1,
+                }
+                *kind != AuthFailure::Invalid
+            }
+        };
+
+        if let Some((kind, error)) = failure {
+            if state.failures.len() >= self.max_failures {
+                state.failures.pop_front();
+            }
+            if self.max_failures > 0 {
+                state.failures.push_back(AuthFailureRecord {
+                    timestamp: SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .map(|time| time.as_millis() as u64)
+                        .unwrap_or(0),
+                    kind,
+                    error,
+                    user_hash: hash_name(username),
+                });
+            }
+        }
+
+        state.attempts.push_back((now, backend_failed));
+        while state
+            .attempts
+            .front()
+            .is_some_and(|(time, _)| now.duration_since(*time) > self.window)
+        {
+            state.attempts.pop_front();
+        }
+        let attempts = state.attempts.len();
+        let failed = state.attempts.iter().filter(|(_, failed)| *failed).count();
+        let degraded = attempts >= MIN_ATTEMPTS && failed as f64 / attempts as f64 > self.threshold;
+        if degraded && !state.degraded {
+            warn!(
+                "Authentication with Mojang is degraded: {} of the last {} logins failed within {:?}.",
+                failed, attempts, self.window
+            );
+        } else if !degraded && state.degraded {
+            info!("Authentication with Mojang recovered.");
+        }
+        state.degraded = degraded;
+    }
+
+    /// Records a login attempted with a session hash older than `mojang.request_ttl`.
+    pub fn record_expired_request(&self) {
+        self.state.lock().unwrap().expired_requests += 1;
+    }
+
+    /// Records a failed login with a JWT, with the reason sent to the client or `internal`.
+    pub fn record_jwt_failure(&self, reason: &'static str) {
+        *self
+            .state
+            .lock()
+            .unwrap()
+            .jwt_failures
+            .entry(reason)
+            .or_insert(0) += 1;
+    }
+
+    /// Returns whether too many authentications failed recently.
+    pub fn degraded(&self) -> bool {
+        self.state.lock().unwrap().degraded
+    }
+
+    /// Returns the most recent failures, newest first.
+    pub fn recent_failures(&self) -> Vec<AuthFailureRecord> {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .iter()
+            .rev()
+            .cloned()
+            .collect()
+    }
+
+    /// Writes the metrics in the Prometheus text format.
+    pub fn write_metrics(&self, output: &mut String) {
+        let state = self.state.lock().unwrap();
+        let count = state.successes + state.invalid + state.timeouts + state.http_errors;
+
+        writeln!(
+            output,
+            "# HELP axochat_mojang_auth_duration_seconds How long authentications with Mojang took."
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# TYPE axochat_mojang_auth_duration_seconds histogram"
+        )
+        .unwrap();
+        for (bucket, bound) in state.buckets.iter().zip(LATENCY_BUCKETS) {
+            writeln!(
+                output,
+                "axochat_mojang_auth_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, bucket
+            )
+            .unwrap();
+        }
+        writeln!(
+            output,
+            "axochat_mojang_auth_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            count
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "axochat_mojang_auth_duration_seconds_sum {}",
+            state.latency_sum.as_secs_f64()
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "axochat_mojang_auth_duration_seconds_count {}",
+            count
+        )
+        .unwrap();
+
+        writeln!(
+            output,
+            "# HELP axochat_mojang_auth_total The number of authentications with Mojang by outcome."
+        )
+        .unwrap();
+        writeln!(output, "# TYPE axochat_mojang_auth_total counter").unwrap();
+        for (outcome, value) in &[
+            ("success", state.successes),
+            ("invalid", state.invalid),
+            ("timeout", state.timeouts),
+            ("http_error", state.http_errors),
+        ] {
+            writeln!(
+                output,
+                "axochat_mojang_auth_total{{outcome=\"{}\"}} {}",
+                outcome, value
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            output,
+            "# HELP axochat_mojang_requests_expired_total The number of logins attempted with an expired session hash."
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# TYPE axochat_mojang_requests_expired_total counter"
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "axochat_mojang_requests_expired_total {}",
+            state.expired_requests
+        )
+        .unwrap();
+
+        writeln!(
+            output,
+            "# HELP axochat_jwt_login_failures_total The number of failed logins with a JWT by reason."
+        )
+        .unwrap();
+        writeln!(output, "# TYPE axochat_jwt_login_failures_total counter").unwrap();
+        for (reason, value) in &state.jwt_failures {
+            writeln!(
+                output,
+                "axochat_jwt_login_failures_total{{reason=\"{}\"}} {}",
+                reason, value
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// Hashes a user name, so failures can be correlated without storing the name.
+fn hash_name(name: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, name.to_lowercase().as_bytes());
+    hash.as_ref()[..NAME_HASH_LEN]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
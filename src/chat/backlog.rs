@@ -0,0 +1,116 @@
+use crate::config::ShedPackets;
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Approximately counts the messages waiting in the mailbox of the [`ChatServer`](super::ChatServer).
+///
+/// Every counted message carries a [`Pending`] which is dropped together with the message,
+/// so the count stays correct even if a message is never handled.
+pub struct Backlog {
+    pending: AtomicUsize,
+    threshold: Option<usize>,
+    shed: ShedPackets,
+}
+
+impl Backlog {
+    pub fn new(threshold: Option<usize>, shed: ShedPackets) -> Backlog {
+        Backlog {
+            pending: AtomicUsize::new(0),
+            threshold,
+            shed,
+        }
+    }
+
+    /// The number of messages sent to the chat server which were not handled yet.
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// Returns which packets are rejected, if the backlog is above the threshold.
+    pub fn shedding(&self) -> Option<ShedPackets> {
+        match self.threshold {
+            Some(threshold) if self.pending() > threshold => Some(self.shed),
+            _ => None,
+        }
+    }
+
+    /// Counts a message until the returned value is dropped.
+    pub(super) fn track(self: &Arc<Self>) -> Pending {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        Pending {
+            backlog: self.clone(),
+        }
+    }
+}
+
+/// Keeps a message counted in the [`Backlog`] until it is dropped.
+pub(super) struct Pending {
+    backlog: Arc<Backlog>,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.backlog.pending.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn messages_are_counted_until_dropped() {
+        let backlog = Arc::new(Backlog::new(None, ShedPackets::Messages));
+        let first = backlog.track();
+        let second = backlog.track();
+        assert_eq!(backlog.pending(), 2);
+        drop(first);
+        assert_eq!(backlog.pending(), 1);
+        drop(second);
+        assert_eq!(backlog.pending(), 0);
+    }
+
+    #[test]
+    fn packets_are_shed_above_the_threshold() {
+        let backlog = Arc::new(Backlog::new(Some(1), ShedPackets::All));
+        let first = backlog.track();
+        assert_eq!(backlog.shedding(), None);
+        let second = backlog.track();
+        assert_eq!(backlog.shedding(), Some(ShedPackets::All));
+        drop(second);
+        assert_eq!(backlog.shedding(), None);
+        drop(first);
+
+        let unlimited = Arc::new(Backlog::new(None, ShedPackets::All));
+        let pending: Vec<_> = (0..100).map(|_| unlimited.track()).collect();
+        assert_eq!(unlimited.shedding(), None);
+        drop(pending);
+    }
+
+    #[test]
+    fn the_count_does_not_drift_under_contention() {
+        let backlog = Arc::new(Backlog::new(Some(10), ShedPackets::Messages));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let backlog = backlog.clone();
+                thread::spawn(move || {
+                    let mut pending = Vec::new();
+                    for i in 0..10_000 {
+                        pending.push(backlog.track());
+                        if i % 3 == 0 {
+                            pending.clear();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(backlog.pending(), 0);
+    }
+}
@@ -0,0 +1,516 @@
+#[cfg(feature = "irc")]
+use super::irc::{IrcBridge, IrcStats};
+use super::{
+    api::{self, RateLimits, RequestCounts},
+    auth_monitor::AuthMonitor,
+    chat_route,
+    cluster::Cluster,
+    delivery::DeliveryStats,
+    drain,
+    firehose::FirehoseStats,
+    footprint,
+    funnel::Funnel,
+    history::History,
+    info::info_route,
+    maintenance::{Maintenance, MaintenanceStats},
+    metrics,
+    persistence::StorageHealth,
+    pm_metadata::PmMetadataLog,
+    presence::PresenceBatch,
+    schema,
+    session::HandshakePolicy,
+    sessions::Sessions,
+    stats::Stats,
+    status::{self, StatusAccess},
+    validation_stats::ValidationStats,
+    AdminHandle, Backlog, ChatHook, ChatServer, Clock, ConnectionLimit, MaintenanceJob,
+    PacketLimits, SystemClock,
+};
+use crate::config::Config;
+use crate::error::*;
+use log::*;
+
+use actix::*;
+use actix_web::{web, HttpRequest};
+#[cfg(unix)]
+use futures::{Future, Stream};
+
+use crate::auth::Authenticator;
+use crate::emote::Emotes;
+use crate::filter::WordFilter;
+use crate::message::MessageValidator;
+use crate::moderation::Moderation;
+use crate::signing::MessageSigner;
+use crate::storage::{FileStorage, Storage};
+use crate::version;
+use rand::{rngs::OsRng, SeedableRng};
+use rand_hc::Hc128Rng;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Builds a [`ChatServer`].
+///
+/// Every component which is not set explicitly is created from the configuration.
+pub struct ChatServerBuilder {
+    config: Config,
+    authenticator: Option<Authenticator>,
+    validator: Option<MessageValidator>,
+    moderation: Option<Moderation>,
+    storage: Option<Box<dyn Storage>>,
+    hooks: Vec<Box<dyn ChatHook>>,
+    maintenance_jobs: Vec<Box<dyn MaintenanceJob>>,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl ChatServerBuilder {
+    /// Creates a builder which uses `config` for everything not set explicitly.
+    pub fn new(config: Config) -> ChatServerBuilder {
+        ChatServerBuilder {
+            config,
+            authenticator: None,
+            validator: None,
+            moderation: None,
+            storage: None,
+            hooks: Vec::new(),
+            maintenance_jobs: Vec::new(),
+            clock: None,
+        }
+    }
+
+    /// Uses `authenticator` for JWT logins instead of creating one from `config.auth`.
+    pub fn authenticator(mut self, authenticator: Authenticator) -> ChatServerBuilder {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// Uses `validator` instead of creating one from `config.message` and `config.validation`.
+    pub fn validator(mut self, validator: MessageValidator) -> ChatServerBuilder {
+        self.validator = Some(validator);
+        self
+    }
+
+    /// Uses `moderation` instead of reading the files in `config.moderation`.
+    pub fn moderation(mut self, moderation: Moderation) -> ChatServerBuilder {
+        self.moderation = Some(moderation);
+        self
+    }
+
+    /// Uses `storage` instead of the files in `config.storage`.
+    pub fn storage<S: Storage + 'static>(mut self, storage: S) -> ChatServerBuilder {
+        self.storage = Some(Box::new(storage));
+        self
+    }
+
+    /// Registers a hook; hooks are run in the order they were registered.
+    pub fn hook<H: ChatHook + 'static>(mut self, hook: H) -> ChatServerBuilder {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Registers a maintenance job, which runs after the built-in ones, see [`MaintenanceJob`].
+    pub fn maintenance_job<J: MaintenanceJob + 'static>(mut self, job: J) -> ChatServerBuilder {
+        self.maintenance_jobs.push(Box::new(job));
+        self
+    }
+
+    /// Uses `clock` instead of the system time for the time-dependent rules, see [`Clock`].
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> ChatServerBuilder {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Creates the chat server without starting it.
+    pub fn build(self) -> Result<ChatServer> {
+        version::mark_started();
+        let config = self.config;
+
+        let authenticator = match (self.authenticator, &config.auth) {
+            (Some(authenticator), _) => Some(authenticator),
+            (None, Some(auth)) => Some(Authenticator::new(auth)?),
+            (None, None) => None,
+        };
+        let validator = match self.validator {
+            Some(validator) => validator,
+            None => {
+                let mut validator =
+                    MessageValidator::new(config.message.clone(), config.validation.clone())?;
+                validator.set_word_filter(WordFilter::load(&config.validation.blocked_words)?);
+                validator
+            }
+        };
+        let blocked_words = validator.word_filter().words().clone();
+        let moderation = match self.moderation {
+            Some(moderation) => moderation,
+            None => Moderation::new(config.moderation.clone())?,
+        };
+        let storage = match self.storage {
+            Some(storage) => storage,
+            None => Box::new(FileStorage::new(config.storage.clone())?),
+        };
+
+        let emotes = match &config.message.emotes {
+            Some(path) => Emotes::load(path)?,
+            None => Emotes::default(),
+        };
+
+        let signer = match &config.signing.key_file {
+            Some(path) => Some(Arc::new(MessageSigner::load(path)?)),
+            None => None,
+        };
+
+        let cluster = match &config.cluster {
+            Some(cluster) => Some(Cluster::new(cluster)?),
+            None => None,
+        };
+
+        Ok(ChatServer {
+            sessions: Sessions::default(),
+
+            rng: Hc128Rng::from_rng(OsRng).expect("could not initialize hc128 rng"),
+            authenticator,
+            validator,
+            signer,
+            moderation,
+            storage,
+            hooks: self.hooks,
+            emotes,
+            cluster,
+            history: History::new(config.message.history_size),
+            resume_tokens: HashMap::new(),
+            online_watches: HashMap::new(),
+            last_seen: HashMap::new(),
+            previous_names: HashMap::new(),
+            renamed: HashMap::new(),
+            blocked_words,
+            word_filter_generation: 0,
+            observers: HashMap::new(),
+            presence: PresenceBatch::default(),
+            pm_metadata: PmMetadataLog::new(
+                config.moderation.pm_metadata_retention_minutes,
+                config.moderation.pm_metadata_max_entries,
+            ),
+            stats: Stats::default(),
+            maintenance: Maintenance::new(&config, self.maintenance_jobs),
+            moderator_actions: HashMap::new(),
+            next_private_id: 1,
+            simulating: false,
+            guests: config
+                .auth
+                .as_ref()
+                .map(|auth| auth.guests.clone())
+                .unwrap_or_default(),
+            drain: None,
+            connection_limit: Arc::new(ConnectionLimit::new(
+                config.server.max_connections,
+                config.server.reserved_slots,
+                *config.server.retry_after,
+                *config.drain.retry_after,
+            )),
+            backlog: Arc::new(Backlog::new(
+                config.server.backlog_threshold,
+                config.server.shed_packets,
+            )),
+            auth_monitor: Arc::new(AuthMonitor::new(&config.mojang)),
+            validation_stats: Arc::new(ValidationStats::default()),
+            funnel: Arc::new(Funnel::default()),
+            firehose: Arc::new(FirehoseStats::default()),
+            delivery: Arc::new(DeliveryStats::default()),
+            storage_health: Arc::new(StorageHealth::default()),
+            flush_retry: None,
+            clock: self.clock.unwrap_or_else(|| Arc::new(SystemClock)),
+            config,
+
+            current_internal_user_id: 0,
+        })
+    }
+
+    /// Creates the chat server and starts it in the current actix system.
+    pub fn start(self) -> Result<ChatHandle> {
+        let api = self.config.api.clone();
+        let metrics = self.config.server.metrics;
+        let schema = self.config.server.schema;
+        let status_page = match (
+            self.config.server.status_page,
+            self.config.server.status_page_requires_token,
+        ) {
+            (false, _) => None,
+            (true, false) => Some(StatusAccess { token: None }),
+            (true, true) => match &api.token {
+                Some(token) => Some(StatusAccess {
+                    token: Some(token.clone()),
+                }),
+                None => {
+                    warn!("`server.status_page` requires `api.token`, so `/status` is not served.");
+                    None
+                }
+            },
+        };
+        let handshake = HandshakePolicy {
+            timeout: if self.config.server.allow_legacy_clients {
+                None
+            } else {
+                Some(*self.config.server.handshake_timeout)
+            },
+        };
+        let packet_limits = PacketLimits {
+            max_size: self.config.server.max_packet_size,
+            max_depth: self.config.server.max_packet_depth,
+            max_malformed: self.config.server.max_malformed_packets,
+        };
+        let server = self.build()?;
+        footprint::log_memory_ceiling(&server.config);
+        let connection_limit = server.connection_limit.clone();
+        let backlog = server.backlog.clone();
+        let auth_monitor = server.auth_monitor.clone();
+        let validation_stats = server.validation_stats.clone();
+        let funnel = server.funnel.clone();
+        let firehose = server.firehose.clone();
+        let delivery = server.delivery.clone();
+        let storage_health = server.storage_health.clone();
+        let maintenance = server.maintenance.stats();
+        let signer = server.signer.clone();
+        #[cfg(feature = "irc")]
+        let irc = Arc::new(IrcStats::default());
+        #[cfg(feature = "irc")]
+        let addr = {
+            let irc = irc.clone();
+            ChatServer::create(move |ctx| {
+                let mut server = server;
+                // The bridge needs the address of the chat server to relay incoming messages.
+                if let Some(config) = server.config.irc.clone() {
+                    info!(
+                        "Relaying messages to `{}` on IRC server `{}`.",
+                        config.channel, config.server
+                    );
+                    let admin = AdminHandle::new(ctx.address());
+                    server
+                        .hooks
+                        .push(Box::new(IrcBridge::start(config, admin, irc)));
+                }
+                server
+            })
+        };
+        #[cfg(not(feature = "irc"))]
+        let addr = {
+            if server.config.irc.is_some() {
+                warn!("`irc` is configured, but the server was built without the `irc` feature.");
+            }
+            server.start()
+        };
+        Ok(ChatHandle {
+            addr,
+            connection_limit,
+            backlog,
+            handshake,
+            packet_limits,
+            api_token: api.token.clone(),
+            api_limits: Arc::new(RateLimits::new(&api)),
+            api_simulations: Arc::new(RateLimits::simulations(&api)),
+            api_counts: Arc::new(RequestCounts::default()),
+            auth_monitor,
+            validation_stats,
+            funnel,
+            firehose,
+            delivery,
+            storage_health,
+            maintenance,
+            #[cfg(feature = "irc")]
+            irc,
+            signer,
+            metrics,
+            schema,
+            status_page,
+        })
+    }
+}
+
+/// A handle to a running [`ChatServer`].
+#[derive(Clone)]
+pub struct ChatHandle {
+    addr: Addr<ChatServer>,
+    connection_limit: Arc<ConnectionLimit>,
+    backlog: Arc<Backlog>,
+    handshake: HandshakePolicy,
+    packet_limits: PacketLimits,
+    api_token: Option<String>,
+    api_limits: Arc<RateLimits>,
+    api_simulations: Arc<RateLimits>,
+    api_counts: Arc<RequestCounts>,
+    auth_monitor: Arc<AuthMonitor>,
+    validation_stats: Arc<ValidationStats>,
+    funnel: Arc<Funnel>,
+    firehose: Arc<FirehoseStats>,
+    delivery: Arc<DeliveryStats>,
+    storage_health: Arc<StorageHealth>,
+    maintenance: Arc<MaintenanceStats>,
+    #[cfg(feature = "irc")]
+    irc: Arc<IrcStats>,
+    signer: Option<Arc<MessageSigner>>,
+    metrics: bool,
+    schema: bool,
+    status_page: Option<StatusAccess>,
+}
+
+impl ChatHandle {
+    /// Registers the websocket endpoint at `/ws`, the build information at `/info`,
+    /// whether the instance accepts connections at `/ready`,
+    /// the admin API at `/api/v1` if `api.token` is configured
+    /// the metrics at `/metrics` if `server.metrics` is enabled,
+    /// the status page at `/status` if `server.status_page` is enabled
+    /// and the description of the packets at `/schema` if `server.schema` is enabled.
+    ///
+    /// This can be passed to `App::configure` or `Scope::configure`,
+    /// so the chat can be mounted into any existing actix-web application.
+    pub fn configure(&self, cfg: &mut web::ServiceConfig) {
+        cfg.data(self.addr.clone())
+            .data(self.connection_limit.clone())
+            .data(self.backlog.clone())
+            .data(self.handshake)
+            .data(self.packet_limits)
+            .data(self.api_counts.clone())
+            .data(self.auth_monitor.clone())
+            .data(self.validation_stats.clone())
+            .data(self.funnel.clone())
+            .data(self.firehose.clone())
+            .data(self.delivery.clone())
+            .data(self.storage_health.clone())
+            .data(self.maintenance.clone())
+            .service(web::resource("/ws").to(chat_route))
+            .service(web::resource("/info").route(web::get().to(info_route)))
+            .service(web::resource("/ready").route(web::get().to(drain::ready_route)));
+        #[cfg(feature = "irc")]
+        cfg.data(self.irc.clone());
+        if self.metrics {
+            cfg.service(web::resource("/metrics").to(metrics::metrics_route));
+        }
+        if self.schema {
+            cfg.service(web::resource("/schema").route(web::get().to(schema::schema_route)));
+        }
+        if let Some(access) = &self.status_page {
+            let access = access.clone();
+            cfg.service(web::resource("/status").route(web::get().to_async(
+                move |req: HttpRequest, addr: web::Data<Addr<ChatServer>>| {
+                    status::status_route(&access, req, addr)
+                },
+            )));
+        }
+        if let Some(token) = &self.api_token {
+            let state = api::ApiState {
+                admin: self.admin(),
+                token: token.clone(),
+                auth_monitor: self.auth_monitor.clone(),
+                funnel: self.funnel.clone(),
+                signer: self.signer.clone(),
+                simulations: self.api_simulations.clone(),
+                packet_limits: self.packet_limits,
+            };
+            api::configure(cfg, state, self.api_limits.clone(), self.api_counts.clone());
+        }
+    }
+
+    /// Returns the address of the chat server actor.
+    pub fn addr(&self) -> &Addr<ChatServer> {
+        &self.addr
+    }
+
+    /// Returns a handle for administrative actions.
+    pub fn admin(&self) -> AdminHandle {
+        AdminHandle::new(self.addr.clone())
+    }
+
+    /// Starts or cancels draining the instance whenever the process receives `SIGUSR1`.
+    ///
+    /// This has to be called in a running actix system.
+    #[cfg(unix)]
+    pub fn drain_on_signal(&self) {
+        use tokio_signal::unix::{Signal, SIGUSR1};
+
+        let admin = self.admin();
+        Arbiter::spawn(
+            Signal::new(SIGUSR1)
+                .flatten_stream()
+                .for_each(move |_| {
+                    Arbiter::spawn(admin.drain().then(|res| {
+                        match res {
+                            Ok(true) => info!("Received SIGUSR1, draining."),
+                            Ok(false) => info!("Received SIGUSR1, no longer draining."),
+                            Err(err) => warn!("Could not toggle draining: {}", err),
+                        }
+                        Ok(())
+                    }));
+                    Ok(())
+                })
+                .map_err(|err| warn!("Could not listen for SIGUSR1: {}", err)),
+        );
+    }
+}
+
+#[cfg(test)]
+impl ChatServer {
+    /// Builds a server for unit tests, with its files in a temporary directory
+    /// which is removed once they were loaded.
+    pub(super) fn for_tests(configure: impl FnOnce(&mut Config)) -> ChatServer {
+        use rand::{rngs::OsRng, RngCore};
+        use std::fs;
+
+        let dir = std::env::temp_dir().join(format!("axochat-unit-{:016x}", OsRng.next_u64()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut config = Config::default();
+        config.moderation.moderators = dir.join("moderators.txt");
+        config.moderation.banned = dir.join("banned.txt");
+        config.moderation.whitelisted = dir.join("whitelisted.txt");
+        config.storage.first_seen = dir.join("first_seen.txt");
+        config.storage.audit_log = dir.join("audit_log.jsonl");
+        config.storage.stats = dir.join("stats.jsonl");
+        config.validation.blocked_words = dir.join("blocked_words.txt");
+        fs::write(&config.moderation.moderators, "").unwrap();
+        configure(&mut config);
+        let server = ChatServerBuilder::new(config).build().unwrap();
+        fs::remove_dir_all(&dir).ok();
+        server
+    }
+
+    /// Adds a connection whose packets are recorded by `capture`, logged in as `user` if it is set.
+    pub(super) fn connect_for_tests(
+        &mut self,
+        capture: &Addr<super::simulate::Capture>,
+        user: Option<super::User>,
+    ) -> super::InternalId {
+        use super::{Capabilities, Origin, SessionState, PROTOCOL_VERSION};
+        use crate::message::RateLimiter;
+        use std::cell::Cell;
+        use std::collections::HashSet;
+
+        let id = self.next_internal_id(Origin::Client);
+        self.sessions.insert(
+            id,
+            SessionState {
+                addr: capture.clone().recipient(),
+                close: capture.clone().recipient(),
+                replay: capture.clone().recipient(),
+                traced: capture.clone().recipient(),
+                encoded: capture.clone().recipient(),
+                session_hash: None,
+                user: None,
+                resume_token: None,
+                capabilities: Capabilities::NONE,
+                echo_own_messages: true,
+                moderation_events: None,
+                confirmations: HashMap::new(),
+                watching: HashSet::new(),
+                reserved: false,
+                cooldown_since: None,
+                failed_sends: Cell::new(0),
+                last_diagnostics: None,
+                protocol: PROTOCOL_VERSION,
+                ip: None,
+            },
+        );
+        if let Some(user) = user {
+            let rate_limit = self.rate_limit_config(&user.uuid);
+            self.sessions
+                .insert_login(id, user, || RateLimiter::new(rate_limit));
+        }
+        id
+    }
+}
@@ -0,0 +1,128 @@
+//! Optional features a client can declare support for in its `Hello` packet.
+//!
+//! Packets belonging to an optional feature are only sent to sessions supporting it,
+//! so old clients are not confused by packets they do not know.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// A set of optional features.
+///
+/// It is (de-)serialized as a list of feature names;
+/// unknown names are ignored, so newer clients can talk to older servers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities(u32);
+
+/// The names of all features and their bits.
+const NAMES: &[(&str, Capabilities)] = &[
+    ("resume", Capabilities::RESUME),
+    ("flagged_messages", Capabilities::FLAGGED_MESSAGES),
+    ("system_messages", Capabilities::SYSTEM_MESSAGES),
+    ("delivery_counts", Capabilities::DELIVERY_COUNTS),
+    ("renames", Capabilities::RENAMES),
+    ("private_message_echo", Capabilities::PRIVATE_MESSAGE_ECHO),
+    ("trace", Capabilities::TRACE),
+    ("presence", Capabilities::PRESENCE),
+    ("time_sync", Capabilities::TIME_SYNC),
+    ("session_tag", Capabilities::SESSION_TAG),
+];
+
+impl Capabilities {
+    /// No optional features.
+    pub const NONE: Capabilities = Capabilities(0);
+    /// The client receives `ResumeToken` packets after logging in.
+    pub const RESUME: Capabilities = Capabilities(1);
+    /// Moderators receive `MessageFlagged` packets.
+    pub const FLAGGED_MESSAGES: Capabilities = Capabilities(1 << 1);
+    /// The client receives `SystemMessage` packets.
+    pub const SYSTEM_MESSAGES: Capabilities = Capabilities(1 << 2);
+    /// The client receives the number of recipients in `MessageAck` and `PrivateMessageAck` packets.
+    pub const DELIVERY_COUNTS: Capabilities = Capabilities(1 << 3);
+    /// The client receives `UserRenamed` packets.
+    pub const RENAMES: Capabilities = Capabilities(1 << 4);
+    /// The connections of the sender of a private message receive it too.
+    pub const PRIVATE_MESSAGE_ECHO: Capabilities = Capabilities(1 << 5);
+    /// The client receives the trace id of its packet in direct responses.
+    pub const TRACE: Capabilities = Capabilities(1 << 6);
+    /// The client receives `PresenceDiff` packets.
+    pub const PRESENCE: Capabilities = Capabilities(1 << 7);
+    /// The client receives a `TimeSync` after `Hello`.
+    pub const TIME_SYNC: Capabilities = Capabilities(1 << 8);
+    /// The client receives a `SessionTag` after `Hello`.
+    pub const SESSION_TAG: Capabilities = Capabilities(1 << 9);
+
+    /// All features supported by this server.
+    pub const ALL: Capabilities = Capabilities(
+        Capabilities::RESUME.0
+            | Capabilities::FLAGGED_MESSAGES.0
+            | Capabilities::SYSTEM_MESSAGES.0
+            | Capabilities::DELIVERY_COUNTS.0
+            | Capabilities::RENAMES.0
+            | Capabilities::PRIVATE_MESSAGE_ECHO.0
+            | Capabilities::TRACE.0
+            | Capabilities::PRESENCE.0
+            | Capabilities::TIME_SYNC.0
+            | Capabilities::SESSION_TAG.0,
+    );
+
+    /// Returns whether all features of `other` are in `self`.
+    pub fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Adds all features of `other` to `self`.
+    pub fn insert(&mut self, other: Capabilities) {
+        self.0 |= other.0;
+    }
+
+    /// Looks up a feature by its name.
+    pub fn from_name(name: &str) -> Option<Capabilities> {
+        NAMES
+            .iter()
+            .find(|(feature, _)| *feature == name)
+            .map(|(_, capability)| *capability)
+    }
+
+    /// Returns the names of all features in `self`.
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        NAMES
+            .iter()
+            .filter(move |(_, capability)| self.contains(*capability))
+            .map(|(name, _)| *name)
+    }
+}
+
+impl Serialize for Capabilities {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.names())
+    }
+}
+
+impl<'de> Deserialize<'de> for Capabilities {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Capabilities, D::Error> {
+        struct CapabilitiesVisitor;
+
+        impl<'de> de::Visitor<'de> for CapabilitiesVisitor {
+            type Value = Capabilities;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a list of feature names")
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Capabilities, A::Error> {
+                let mut capabilities = Capabilities::NONE;
+                while let Some(name) = seq.next_element::<String>()? {
+                    if let Some(capability) = Capabilities::from_name(&name) {
+                        capabilities.insert(capability);
+                    }
+                }
+                Ok(capabilities)
+            }
+        }
+
+        deserializer.deserialize_seq(CapabilitiesVisitor)
+    }
+}
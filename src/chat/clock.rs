@@ -0,0 +1,87 @@
+//! The time seen by the chat server.
+//!
+//! Rate limits, cooldowns, probation, resume tokens and the other time-dependent rules
+//! of the chat server ask its [`Clock`] instead of the system,
+//! so tests can fast-forward them with a [`ManualClock`] instead of sleeping.
+//! Timers of the actor system, like handshake timeouts and the intervals of jobs, still use the real time.
+
+use super::ChatServer;
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A source of the current time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    fn system_now(&self) -> SystemTime;
+}
+
+/// The real time, which is used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock which starts at the time it was created and only moves when it is advanced.
+#[derive(Debug)]
+pub struct ManualClock {
+    start: Instant,
+    system_start: SystemTime,
+    offset: Mutex<Duration>,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock {
+            start: Instant::now(),
+            system_start: SystemTime::now(),
+            offset: Mutex::new(Duration::from_secs(0)),
+        }
+    }
+
+    /// Moves the clock `duration` forward.
+    pub fn advance(&self, duration: Duration) {
+        *self.offset.lock().unwrap() += duration;
+    }
+
+    fn offset(&self) -> Duration {
+        *self.offset.lock().unwrap()
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> ManualClock {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.offset()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.system_start + self.offset()
+    }
+}
+
+impl ChatServer {
+    /// The current time of the [`Clock`] of the chat server.
+    pub(in crate::chat) fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// The current system time of the [`Clock`] of the chat server.
+    pub(in crate::chat) fn system_now(&self) -> SystemTime {
+        self.clock.system_now()
+    }
+}
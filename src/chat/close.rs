@@ -0,0 +1,230 @@
+//! The websocket close codes the server uses when it closes a connection.
+//!
+//! Codes below 4000 are defined by RFC 6455, the others are specific to AxoChat.
+
+use crate::error::keys;
+use actix::*;
+use actix_web_actors::ws::{CloseCode, CloseReason};
+use std::fmt;
+
+/// The client closed the connection.
+pub const NORMAL: u16 = 1000;
+/// The client sent a frame which violates the websocket protocol.
+pub const PROTOCOL_ERROR: u16 = 1002;
+/// The client sent text which is not valid UTF-8.
+pub const INVALID_PAYLOAD: u16 = 1007;
+/// The user was banned.
+pub const POLICY_VIOLATION: u16 = 1008;
+/// The client sent a frame larger than the server accepts.
+pub const MESSAGE_TOO_BIG: u16 = 1009;
+/// The server could not handle the connection.
+pub const INTERNAL_ERROR: u16 = 1011;
+/// The server is full and the remaining connections are reserved for moderators.
+pub const SERVER_FULL: u16 = 4001;
+/// The client did not send `Hello` in time.
+pub const HANDSHAKE_TIMEOUT: u16 = 4002;
+/// The user logged in with too many other sessions.
+pub const SESSION_LIMIT: u16 = 4003;
+/// The client sent too many packets which could not be decoded.
+pub const MALFORMED_PACKETS: u16 = 4004;
+/// The client is older than the server accepts.
+pub const CLIENT_OUTDATED: u16 = 4005;
+/// The client or a firehose observer did not keep up with the packets sent to it.
+pub const SLOW_CONSUMER: u16 = 4006;
+/// Too many connections from the same IP address did not log in.
+pub const GUEST_LIMIT: u16 = 4007;
+/// The client did not log in in time, while guests are not allowed.
+pub const LOGIN_TIMEOUT: u16 = 4008;
+/// The instance is draining and the client should reconnect, likely to another instance.
+pub const MIGRATE: u16 = 4009;
+/// A moderator kicked the user.
+pub const KICKED: u16 = 4010;
+
+/// Why the server closes a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum DisconnectReason {
+    ClientClosed,
+    ProtocolError,
+    InvalidPayload,
+    Banned,
+    FrameTooLarge,
+    Internal,
+    ServerFull,
+    HandshakeTimeout,
+    SessionLimit,
+    MalformedPackets,
+    ClientOutdated,
+    SlowConsumer,
+    GuestLimit,
+    LoginTimeout,
+    Migrate,
+    Kicked,
+}
+
+impl DisconnectReason {
+    /// The close code sent to the client.
+    pub fn code(self) -> u16 {
+        match self {
+            DisconnectReason::ClientClosed => NORMAL,
+            DisconnectReason::ProtocolError => PROTOCOL_ERROR,
+            DisconnectReason::InvalidPayload => INVALID_PAYLOAD,
+            DisconnectReason::Banned => POLICY_VIOLATION,
+            DisconnectReason::FrameTooLarge => MESSAGE_TOO_BIG,
+            DisconnectReason::Internal => INTERNAL_ERROR,
+            DisconnectReason::ServerFull => SERVER_FULL,
+            DisconnectReason::HandshakeTimeout => HANDSHAKE_TIMEOUT,
+            DisconnectReason::SessionLimit => SESSION_LIMIT,
+            DisconnectReason::MalformedPackets => MALFORMED_PACKETS,
+            DisconnectReason::ClientOutdated => CLIENT_OUTDATED,
+            DisconnectReason::SlowConsumer => SLOW_CONSUMER,
+            DisconnectReason::GuestLimit => GUEST_LIMIT,
+            DisconnectReason::LoginTimeout => LOGIN_TIMEOUT,
+            DisconnectReason::Migrate => MIGRATE,
+            DisconnectReason::Kicked => KICKED,
+        }
+    }
+
+    /// The name of the reason in metrics.
+    pub fn label(self) -> &'static str {
+        match self {
+            DisconnectReason::ClientClosed => "client_closed",
+            DisconnectReason::ProtocolError => "protocol_error",
+            DisconnectReason::InvalidPayload => "invalid_payload",
+            DisconnectReason::Banned => "banned",
+            DisconnectReason::FrameTooLarge => "frame_too_large",
+            DisconnectReason::Internal => "internal",
+            DisconnectReason::ServerFull => "server_full",
+            DisconnectReason::HandshakeTimeout => "handshake_timeout",
+            DisconnectReason::SessionLimit => "session_limit",
+            DisconnectReason::MalformedPackets => "malformed_packets",
+            DisconnectReason::ClientOutdated => "client_outdated",
+            DisconnectReason::SlowConsumer => "slow_consumer",
+            DisconnectReason::GuestLimit => "guest_limit",
+            DisconnectReason::LoginTimeout => "login_timeout",
+            DisconnectReason::Migrate => "migrate",
+            DisconnectReason::Kicked => "kicked",
+        }
+    }
+
+    /// The key of the translation of this reason.
+    pub fn translation_key(self) -> &'static str {
+        match self {
+            DisconnectReason::ClientClosed => keys::DISCONNECT_CLIENT_CLOSED,
+            DisconnectReason::ProtocolError => keys::DISCONNECT_PROTOCOL_ERROR,
+            DisconnectReason::InvalidPayload => keys::DISCONNECT_INVALID_PAYLOAD,
+            DisconnectReason::Banned => keys::DISCONNECT_BANNED,
+            DisconnectReason::FrameTooLarge => keys::DISCONNECT_FRAME_TOO_LARGE,
+            DisconnectReason::Internal => keys::DISCONNECT_INTERNAL,
+            DisconnectReason::ServerFull => keys::DISCONNECT_SERVER_FULL,
+            DisconnectReason::HandshakeTimeout => keys::DISCONNECT_HANDSHAKE_TIMEOUT,
+            DisconnectReason::SessionLimit => keys::DISCONNECT_SESSION_LIMIT,
+            DisconnectReason::MalformedPackets => keys::DISCONNECT_MALFORMED_PACKETS,
+            DisconnectReason::ClientOutdated => keys::DISCONNECT_CLIENT_OUTDATED,
+            DisconnectReason::SlowConsumer => keys::DISCONNECT_SLOW_CONSUMER,
+            DisconnectReason::GuestLimit => keys::DISCONNECT_GUEST_LIMIT,
+            DisconnectReason::LoginTimeout => keys::DISCONNECT_LOGIN_TIMEOUT,
+            DisconnectReason::Migrate => keys::DISCONNECT_MIGRATE,
+            DisconnectReason::Kicked => keys::DISCONNECT_KICKED,
+        }
+    }
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DisconnectReason::ClientClosed => write!(f, "closed by client"),
+            DisconnectReason::ProtocolError => write!(f, "protocol error"),
+            DisconnectReason::InvalidPayload => write!(f, "invalid payload"),
+            DisconnectReason::Banned => write!(f, "banned"),
+            DisconnectReason::FrameTooLarge => write!(f, "frame too large"),
+            DisconnectReason::Internal => write!(f, "internal error"),
+            DisconnectReason::ServerFull => write!(f, "server full"),
+            DisconnectReason::HandshakeTimeout => write!(f, "handshake timed out"),
+            DisconnectReason::SessionLimit => write!(f, "too many sessions"),
+            DisconnectReason::MalformedPackets => write!(f, "too many malformed packets"),
+            DisconnectReason::ClientOutdated => write!(f, "client outdated"),
+            DisconnectReason::SlowConsumer => write!(f, "too slow"),
+            DisconnectReason::GuestLimit => write!(f, "too many guests"),
+            DisconnectReason::LoginTimeout => write!(f, "login timed out"),
+            DisconnectReason::Migrate => write!(f, "server is draining"),
+            DisconnectReason::Kicked => write!(f, "kicked"),
+        }
+    }
+}
+
+impl From<DisconnectReason> for CloseReason {
+    fn from(reason: DisconnectReason) -> CloseReason {
+        CloseReason {
+            code: CloseCode::from(reason.code()),
+            description: Some(reason.to_string()),
+        }
+    }
+}
+
+/// Tells a session to close its connection.
+#[derive(Message)]
+pub(super) struct Close(pub DisconnectReason);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const ALL: &[DisconnectReason] = &[
+        DisconnectReason::ClientClosed,
+        DisconnectReason::ProtocolError,
+        DisconnectReason::InvalidPayload,
+        DisconnectReason::Banned,
+        DisconnectReason::FrameTooLarge,
+        DisconnectReason::Internal,
+        DisconnectReason::ServerFull,
+        DisconnectReason::HandshakeTimeout,
+        DisconnectReason::SessionLimit,
+        DisconnectReason::MalformedPackets,
+        DisconnectReason::ClientOutdated,
+        DisconnectReason::SlowConsumer,
+        DisconnectReason::GuestLimit,
+        DisconnectReason::LoginTimeout,
+        DisconnectReason::Migrate,
+        DisconnectReason::Kicked,
+    ];
+
+    /// Fails to compile if [`ALL`] misses a variant added to [`DisconnectReason`].
+    #[allow(dead_code)]
+    fn is_listed(reason: DisconnectReason) {
+        match reason {
+            DisconnectReason::ClientClosed
+            | DisconnectReason::ProtocolError
+            | DisconnectReason::InvalidPayload
+            | DisconnectReason::Banned
+            | DisconnectReason::FrameTooLarge
+            | DisconnectReason::Internal
+            | DisconnectReason::ServerFull
+            | DisconnectReason::HandshakeTimeout
+            | DisconnectReason::SessionLimit
+            | DisconnectReason::MalformedPackets
+            | DisconnectReason::ClientOutdated
+            | DisconnectReason::SlowConsumer
+            | DisconnectReason::GuestLimit
+            | DisconnectReason::LoginTimeout
+            | DisconnectReason::Migrate
+            | DisconnectReason::Kicked => {}
+        }
+    }
+
+    #[test]
+    fn keys_are_named_like_the_labels() {
+        for reason in ALL {
+            assert_eq!(
+                reason.translation_key(),
+                format!("disconnect.{}", reason.label())
+            );
+        }
+    }
+
+    #[test]
+    fn codes_are_unique() {
+        let codes: HashSet<_> = ALL.iter().map(|reason| reason.code()).collect();
+        assert_eq!(codes.len(), ALL.len());
+    }
+}
@@ -0,0 +1,1148 @@
+//! Connects multiple instances through Redis, so they act as one chat.
+//!
+//! Every instance publishes its events to `<prefix>:events` and applies the
+//! events of the other instances locally.
+//! Private messages are only sent to the instances hosting the receiver,
+//! using `<prefix>:instance:<id>`.
+//! Which instances host a user is stored in the sorted set `<prefix>:user:<name>`,
+//! scored by the time until which the entry is valid, where `<name>` is the [`CanonicalId`] of the user.
+//!
+//! The last (un-)ban and (un-)mute of every user is also stored in the hashes `<prefix>:bans`
+//! and `<prefix>:mutes`, keyed by uuid.
+//! An instance applies what it missed from them whenever it (re)connects or resubscribes,
+//! and keeps its own bans and mutes until it can send them while Redis is unreachable.
+
+use super::{handler::PrivateBody, AuthorKind, CanonicalId, ChatServer, ClientPacket, InternalId};
+use crate::config::ClusterConfig;
+use crate::error::*;
+use log::*;
+
+use actix::*;
+use futures::{stream, Future, Sink, Stream};
+use serde::{Deserialize, Serialize};
+use tokio_codec::Framed;
+use tokio_tcp::TcpStream;
+
+use crate::auth::UserInfo;
+use crate::message::ValidatedContent;
+use crate::moderation::Restriction;
+use crate::redis::{Command, FireCommand, RedisConnection, RespCodec, Value};
+use std::collections::{HashMap, VecDeque};
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    time::{Duration, Instant, SystemTime},
+};
+use uuid::Uuid;
+
+/// An event exchanged between instances.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "e", content = "c")]
+pub(super) enum ClusterEvent {
+    Message {
+        author_info: UserInfo,
+        #[serde(default)]
+        author_kind: AuthorKind,
+        content: String,
+        /// Whether blocked words of `content` were redacted.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        redacted: bool,
+    },
+    PrivateMessage {
+        receiver: CanonicalId,
+        author_info: UserInfo,
+        #[serde(default)]
+        id: u64,
+        #[serde(default)]
+        timestamp: u64,
+        /// The payload, if the message is encrypted.
+        content: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        encrypted: bool,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        redacted: bool,
+    },
+    Moderation {
+        user: Uuid,
+        ban: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+        /// When the ban ends, in seconds since the unix epoch; `None` if it is permanent.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_at: Option<u64>,
+    },
+    /// `user` was muted until `expires_at`, or unmuted if it is `None`.
+    Mute {
+        user: Uuid,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+        expires_at: Option<u64>,
+    },
+    Kick {
+        user: Uuid,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    Presence {
+        connections: u32,
+        logged_in: u32,
+    },
+}
+
+/// The most commands kept for Redis while it is unreachable; the oldest are dropped first.
+const MAX_PENDING: usize = 1024;
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    origin: String,
+    event: ClusterEvent,
+}
+
+/// What became of a private message to a user who is not connected to this instance.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Routing {
+    /// There is no cluster, so the receiver is not online anywhere.
+    NoCluster,
+    /// The connection to Redis is lost, so the instances hosting the receiver are unknown.
+    Disconnected,
+    /// The sender disconnected, so the message is dropped.
+    Dropped,
+    /// The message is sent to the instances hosting the receiver once they are looked up.
+    Routed,
+}
+
+/// The presence last announced by another instance.
+struct RemotePresence {
+    received: Instant,
+    connections: u32,
+    logged_in: u32,
+}
+
+/// The state of this instance in the cluster.
+pub(super) struct Cluster {
+    addr: SocketAddr,
+    password: Option<String>,
+    prefix: String,
+    instance_id: String,
+    registry_ttl: Duration,
+
+    connection: Option<Addr<RedisConnection>>,
+    connecting: bool,
+    remote_presence: HashMap<String, RemotePresence>,
+    /// The commands storing and publishing bans and mutes which could not be sent yet.
+    pending: VecDeque<Value>,
+}
+
+impl Cluster {
+    pub(super) fn new(config: &ClusterConfig) -> Result<Cluster> {
+        let url = url::Url::parse(&config.redis_url).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid redis url: {}", err),
+            )
+        })?;
+        let host = match url.host_str() {
+            Some(host) if url.scheme() == "redis" => host,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid redis url `{}`", config.redis_url),
+                )
+                .into());
+            }
+        };
+        let addr = (host, url.port().unwrap_or(6379))
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("could not resolve `{}`", host),
+                )
+            })?;
+        let instance_id = match &config.instance_id {
+            Some(instance_id) => instance_id.clone(),
+            None => format!("{:016x}", rand::random::<u64>()),
+        };
+
+        Ok(Cluster {
+            addr,
+            password: url.password().map(str::to_string),
+            prefix: config.prefix.clone(),
+            instance_id,
+            registry_ttl: *config.registry_ttl,
+
+            connection: None,
+            connecting: false,
+            remote_presence: HashMap::new(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    fn events_channel(&self) -> String {
+        format!("{}:events", self.prefix)
+    }
+
+    fn instance_channel(&self) -> String {
+        instance_channel(&self.prefix, &self.instance_id)
+    }
+
+    fn user_key(&self, name: &CanonicalId) -> String {
+        format!("{}:user:{}", self.prefix, name)
+    }
+
+    /// The hash storing the last (un-)ban of every user.
+    fn bans_key(&self) -> String {
+        format!("{}:bans", self.prefix)
+    }
+
+    /// The hash storing the last (un-)mute of every user.
+    fn mutes_key(&self) -> String {
+        format!("{}:mutes", self.prefix)
+    }
+
+    fn connected(&self) -> Option<&Addr<RedisConnection>> {
+        self.connection
+            .as_ref()
+            .filter(|connection| connection.connected())
+    }
+
+    fn send(&self, command: Value) {
+        match self.connected() {
+            Some(connection) => connection.do_send(FireCommand(command)),
+            None => debug!("Dropping redis command, not connected."),
+        }
+    }
+
+    /// Sends `command`, or keeps it until Redis is connected again.
+    fn send_or_keep(&mut self, command: Value) {
+        match self.connected() {
+            Some(connection) => connection.do_send(FireCommand(command)),
+            None => {
+                if self.pending.len() == MAX_PENDING {
+                    warn!("Dropping the oldest redis command kept while not connected.");
+                    self.pending.pop_front();
+                }
+                self.pending.push_back(command);
+            }
+        }
+    }
+
+    /// Sends the commands kept while Redis was unreachable, in the order they were kept.
+    fn send_pending(&mut self) {
+        if let Some(connection) = self.connected().cloned() {
+            if !self.pending.is_empty() {
+                info!(
+                    "Sending {} redis commands kept while not connected.",
+                    self.pending.len()
+                );
+            }
+            for command in self.pending.drain(..) {
+                connection.do_send(FireCommand(command));
+            }
+        }
+    }
+
+    /// Publishes `event` on `channel`.
+    ///
+    /// Bans and mutes are also stored under the affected user,
+    /// and kept until Redis is connected again if it is unreachable.
+    fn publish(&mut self, channel: String, event: ClusterEvent) {
+        let state = match &event {
+            ClusterEvent::Moderation { user, .. } => Some((self.bans_key(), *user)),
+            ClusterEvent::Mute { user, .. } => Some((self.mutes_key(), *user)),
+            _ => None,
+        };
+        let envelope = Envelope {
+            origin: self.instance_id.clone(),
+            event,
+        };
+        let payload = match serde_json::to_vec(&envelope) {
+            Ok(payload) => payload,
+            Err(err) => {
+                warn!("Could not serialize cluster event: {}", err);
+                return;
+            }
+        };
+        match state {
+            Some((key, user)) => {
+                self.send_or_keep(Value::command(vec![
+                    b"HSET".to_vec(),
+                    key.into_bytes(),
+                    user.to_hyphenated().to_string().into_bytes(),
+                    payload.clone(),
+                ]));
+                self.send_or_keep(Value::command(vec![
+                    b"PUBLISH".to_vec(),
+                    channel.into_bytes(),
+                    payload,
+                ]));
+            }
+            None => self.send(Value::command(vec![
+                b"PUBLISH".to_vec(),
+                channel.into_bytes(),
+                payload,
+            ])),
+        }
+    }
+
+    /// Marks this instance as hosting `name` until the registry entry expires, counting from `now`.
+    fn register(&self, name: &CanonicalId, now: SystemTime) {
+        let key = self.user_key(name);
+        let expires = unix_millis(now + self.registry_ttl);
+        self.send(Value::command(vec![
+            "ZADD".to_string(),
+            key.clone(),
+            expires.to_string(),
+            self.instance_id.clone(),
+        ]));
+        self.send(Value::command(vec![
+            "PEXPIRE".to_string(),
+            key,
+            (self.registry_ttl.as_millis() as u64).to_string(),
+        ]));
+    }
+
+    fn unregister(&self, name: &CanonicalId) {
+        self.send(Value::command(vec![
+            "ZREM".to_string(),
+            self.user_key(name),
+            self.instance_id.clone(),
+        ]));
+    }
+
+    /// The number of connections and logged in users of all other instances.
+    pub(super) fn remote_user_count(&self) -> (u32, u32) {
+        self.remote_presence
+            .values()
+            .fold((0, 0), |(connections, logged_in), presence| {
+                (
+                    connections + presence.connections,
+                    logged_in + presence.logged_in,
+                )
+            })
+    }
+}
+
+impl ChatServer {
+    /// Connects to the cluster, if one is configured.
+    pub(super) fn start_cluster(&mut self, ctx: &mut Context<Self>) {
+        let interval = match &self.cluster {
+            Some(cluster) => cluster.registry_ttl / 3,
+            None => return,
+        };
+
+        self.connect_cluster(ctx);
+        self.subscribe_cluster(ctx);
+        ctx.run_interval(interval, |actor, ctx| actor.refresh_cluster(ctx));
+    }
+
+    fn connect_cluster(&mut self, ctx: &mut Context<Self>) {
+        let cluster = match &mut self.cluster {
+            Some(cluster) if !cluster.connecting => cluster,
+            _ => return,
+        };
+        cluster.connecting = true;
+
+        RedisConnection::connect(cluster.addr, cluster.password.clone())
+            .into_actor(self)
+            .then(|res, actor, ctx| {
+                let cluster = actor.cluster.as_mut().expect("cluster should still exist");
+                cluster.connecting = false;
+                match res {
+                    Ok(connection) => {
+                        info!("Connected to redis at {}.", cluster.addr);
+                        cluster.connection = Some(connection);
+                        cluster.send_pending();
+                        actor.register_local_users();
+                        actor.resync_moderation(ctx);
+                    }
+                    Err(err) => warn!("Could not connect to redis: {}", err),
+                }
+                fut::ok(())
+            })
+            .spawn(ctx);
+    }
+
+    fn subscribe_cluster(&mut self, ctx: &mut Context<Self>) {
+        let cluster = match &self.cluster {
+            Some(cluster) => cluster,
+            None => return,
+        };
+
+        let mut commands = Vec::new();
+        if let Some(password) = &cluster.password {
+            commands.push(Value::command(vec!["AUTH".to_string(), password.clone()]));
+        }
+        commands.push(Value::command(vec![
+            "SUBSCRIBE".to_string(),
+            cluster.events_channel(),
+            cluster.instance_channel(),
+        ]));
+
+        TcpStream::connect(&cluster.addr)
+            .and_then(|stream| {
+                Framed::new(stream, RespCodec::new())
+                    .send_all(stream::iter_ok::<_, io::Error>(commands))
+            })
+            .into_actor(self)
+            .map(|(framed, _), actor, ctx| {
+                ctx.add_stream(framed.filter_map(Published::from_value));
+                // Events published while the subscription was closed are missed.
+                actor.resync_moderation(ctx);
+            })
+            .map_err(|err, actor, ctx| {
+                warn!("Could not subscribe to redis: {}", err);
+                actor.resubscribe_later(ctx);
+            })
+            .spawn(ctx);
+    }
+
+    /// Applies the bans and mutes stored in Redis which this instance has missed.
+    fn resync_moderation(&mut self, ctx: &mut Context<Self>) {
+        let (connection, keys) = match &self.cluster {
+            Some(cluster) => match cluster.connected() {
+                Some(connection) => (
+                    connection.clone(),
+                    vec![cluster.bans_key(), cluster.mutes_key()],
+                ),
+                None => return,
+            },
+            None => return,
+        };
+
+        for key in keys {
+            connection
+                .send(Command(Value::command(vec![
+                    "HGETALL".to_string(),
+                    key.clone(),
+                ])))
+                .map_err(Error::from)
+                .and_then(|res| res.map_err(Error::from))
+                .into_actor(self)
+                .then(move |res, actor, _ctx| {
+                    match res {
+                        Ok(Value::Array(Some(values))) => actor.resync_entries(values),
+                        Ok(value) => warn!("Unexpected reply to `HGETALL {}`: {:?}", key, value),
+                        Err(err) => warn!("Could not read `{}` from redis: {}", key, err),
+                    }
+                    fut::ok(())
+                })
+                .spawn(ctx);
+        }
+    }
+
+    /// Applies the events of a `HGETALL` reply, alternating between field and value,
+    /// which change the moderation state of this instance.
+    fn resync_entries(&mut self, values: Vec<Value>) {
+        for value in values.into_iter().skip(1).step_by(2) {
+            let envelope: Envelope = match value {
+                Value::Bulk(Some(payload)) => match serde_json::from_slice(&payload) {
+                    Ok(envelope) => envelope,
+                    Err(err) => {
+                        warn!("Stored cluster event is invalid: {}", err);
+                        continue;
+                    }
+                },
+                value => {
+                    warn!("Unexpected stored cluster event: {:?}", value);
+                    continue;
+                }
+            };
+            if !self.is_applied(&envelope.event) {
+                self.apply_cluster_event(envelope.origin, envelope.event);
+            }
+        }
+    }
+
+    /// Returns whether applying the stored ban or mute `event` would change nothing,
+    /// because this instance already applied it or it has ended.
+    fn is_applied(&self, event: &ClusterEvent) -> bool {
+        let now = self.system_now();
+        match event {
+            ClusterEvent::Moderation {
+                user,
+                ban: true,
+                reason,
+                expires_at,
+            } => {
+                let ban = Restriction {
+                    reason: reason.clone(),
+                    expires_at: *expires_at,
+                };
+                ban.is_expired(now) || self.moderation.ban_of(user, now) == Some(&ban)
+            }
+            ClusterEvent::Moderation {
+                user, ban: false, ..
+            } => !self.moderation.is_banned(user, now),
+            ClusterEvent::Mute {
+                user,
+                reason,
+                expires_at: Some(expires_at),
+            } => {
+                let mute = Restriction {
+                    reason: reason.clone(),
+                    expires_at: Some(*expires_at),
+                };
+                mute.is_expired(now) || self.moderation.mute_of(user, now) == Some(&mute)
+            }
+            ClusterEvent::Mute {
+                user,
+                expires_at: None,
+                ..
+            } => self.moderation.mute_of(user, now).is_none(),
+            _ => false,
+        }
+    }
+
+    fn resubscribe_later(&mut self, ctx: &mut Context<Self>) {
+        if let Some(cluster) = &self.cluster {
+            ctx.run_later(cluster.registry_ttl / 3, |actor, ctx| {
+                actor.subscribe_cluster(ctx)
+            });
+        }
+    }
+
+    /// Refreshes the registry and presence of this instance and reconnects if necessary.
+    fn refresh_cluster(&mut self, ctx: &mut Context<Self>) {
+        let now = self.now();
+        let connected = match &mut self.cluster {
+            Some(cluster) => {
+                let registry_ttl = cluster.registry_ttl;
+                cluster
+                    .remote_presence
+                    .retain(|_, presence| now.duration_since(presence.received) < registry_ttl);
+                cluster.connected().is_some()
+            }
+            None => return,
+        };
+
+        if connected {
+            self.register_local_users();
+        } else {
+            self.connect_cluster(ctx);
+        }
+    }
+
+    fn register_local_users(&mut self) {
+        if let Some(cluster) = &self.cluster {
+            let now = self.system_now();
+            for name in self.sessions.user_names() {
+                cluster.register(name, now);
+            }
+            self.publish(ClusterEvent::Presence {
+                connections: self.sessions.len() as u32,
+                logged_in: self.sessions.user_count() as u32,
+            });
+        }
+    }
+
+    /// Publishes `event` to all other instances.
+    pub(super) fn publish(&mut self, event: ClusterEvent) {
+        if let Some(cluster) = &mut self.cluster {
+            cluster.publish(cluster.events_channel(), event);
+        }
+    }
+
+    /// Registers a user who logged in on this instance.
+    pub(super) fn cluster_login(&self, name: &CanonicalId) {
+        if let Some(cluster) = &self.cluster {
+            cluster.register(name, self.system_now());
+        }
+    }
+
+    /// Unregisters a user whose last connection to this instance closed.
+    pub(super) fn cluster_logout(&self, name: &CanonicalId) {
+        if let Some(cluster) = &self.cluster {
+            cluster.unregister(name);
+        }
+    }
+
+    /// Sends a private message to the instances hosting `receiver`.
+    ///
+    /// If the lookup of these instances fails, the sender receives `DeliveryFailed`,
+    /// and `UserNotFound` if no instance hosts `receiver`.
+    pub(super) fn route_private_message(
+        &self,
+        user_id: InternalId,
+        receiver: CanonicalId,
+        author_info: UserInfo,
+        id: u64,
+        timestamp: u64,
+        body: PrivateBody,
+    ) -> Routing {
+        let cluster = match &self.cluster {
+            Some(cluster) => cluster,
+            None => return Routing::NoCluster,
+        };
+        let sender = match self.sessions.get(&user_id) {
+            Some(session) => session.addr.clone(),
+            None => {
+                debug!(
+                    "User `{}` disconnected before its private message was routed.",
+                    user_id
+                );
+                return Routing::Dropped;
+            }
+        };
+        let connection = match cluster.connected() {
+            Some(connection) => connection.clone(),
+            None => return Routing::Disconnected,
+        };
+
+        let lookup = Value::command(vec![
+            "ZRANGEBYSCORE".to_string(),
+            cluster.user_key(&receiver),
+            unix_millis(self.system_now()).to_string(),
+            "+inf".to_string(),
+        ]);
+        let instance_id = cluster.instance_id.clone();
+        let prefix = cluster.prefix.clone();
+        let publish_connection = connection.clone();
+        Arbiter::spawn(
+            connection
+                .send(Command(lookup))
+                .map_err(Error::from)
+                .and_then(|res| res.map_err(Error::from))
+                .then(move |res| {
+                    let send_error = |message| sender.do_send(ClientPacket::Error { message }).ok();
+                    let instances: Vec<String> = match res {
+                        Ok(Value::Array(Some(values))) => values
+                            .into_iter()
+                            .filter_map(|value| match value {
+                                Value::Bulk(Some(data)) => String::from_utf8(data).ok(),
+                                _ => None,
+                            })
+                            .filter(|instance| *instance != instance_id)
+                            .collect(),
+                        Ok(value) => {
+                            warn!("Unexpected reply to user lookup: {:?}", value);
+                            send_error(ClientError::DeliveryFailed);
+                            return Ok(());
+                        }
+                        Err(err) => {
+                            warn!("Could not look up instances of `{}`: {}", receiver, err);
+                            send_error(ClientError::DeliveryFailed);
+                            return Ok(());
+                        }
+                    };
+
+                    if instances.is_empty() {
+                        debug!(
+                            "User `{}` tried to write to non-existing user `{}`.",
+                            user_id, receiver
+                        );
+                        send_error(ClientError::UserNotFound);
+                        return Ok(());
+                    }
+
+                    let (content, encrypted, redacted) = match body {
+                        PrivateBody::Plain(content) => {
+                            let redacted = content.is_redacted();
+                            (content.into_string(), false, redacted)
+                        }
+                        PrivateBody::Encrypted(payload) => (payload, true, false),
+                    };
+                    let envelope = Envelope {
+                        origin: instance_id,
+                        event: ClusterEvent::PrivateMessage {
+                            receiver,
+                            author_info,
+                            id,
+                            timestamp,
+                            content,
+                            encrypted,
+                            redacted,
+                        },
+                    };
+                    let payload = serde_json::to_vec(&envelope).expect("could not serialize event");
+                    for instance in instances {
+                        publish_connection.do_send(FireCommand(Value::command(vec![
+                            b"PUBLISH".to_vec(),
+                            instance_channel(&prefix, &instance).into_bytes(),
+                            payload.clone(),
+                        ])));
+                    }
+                    Ok(())
+                }),
+        );
+        Routing::Routed
+    }
+
+    fn apply_cluster_event(&mut self, origin: String, event: ClusterEvent) {
+        match event {
+            ClusterEvent::Message {
+                author_info,
+                author_kind,
+                content,
+                redacted,
+            } => {
+                debug!("Instance `{}` has sent a message.", origin);
+                // The instance of the author validated the message.
+                let content = ValidatedContent::trusted(content, redacted);
+                if let Err(err) =
+                    self.deliver_message(None, author_info, author_kind, content, None)
+                {
+                    warn!(
+                        "Could not deliver message of instance `{}`: {}",
+                        origin, err
+                    );
+                }
+            }
+            ClusterEvent::PrivateMessage {
+                receiver,
+                author_info,
+                id,
+                timestamp,
+                content,
+                encrypted,
+                redacted,
+            } => {
+                let body = if encrypted {
+                    PrivateBody::Encrypted(content)
+                } else {
+                    PrivateBody::Plain(ValidatedContent::trusted(content, redacted))
+                };
+                if let Err(err) =
+                    self.deliver_private_message(&receiver, &author_info, id, timestamp, &body)
+                {
+                    debug!(
+                        "Could not deliver private message from instance `{}` to `{}`: {}",
+                        origin, receiver, err
+                    );
+                }
+            }
+            ClusterEvent::Moderation {
+                user,
+                ban,
+                reason,
+                expires_at,
+            } => {
+                let res = if ban {
+                    let ban = Restriction { reason, expires_at };
+                    self.moderation.ban(&user, ban)
+                } else {
+                    self.moderation.unban(&user)
+                };
+                match res {
+                    Ok(()) => {
+                        info!("User `{}` was (un-)banned by instance `{}`.", user, origin);
+                        self.moderation_persisted();
+                        if ban {
+                            self.announce_ban(&user);
+                            self.remove_banned(&user);
+                        } else {
+                            self.send_moderation_status(&user);
+                        }
+                    }
+                    Err(err) => debug!("Could not apply (un-)ban of `{}`: {}", user, err),
+                }
+            }
+            ClusterEvent::Mute {
+                user,
+                reason,
+                expires_at,
+            } => {
+                let res = match expires_at {
+                    Some(expires_at) => self.moderation.mute(&user, reason, expires_at),
+                    None => self.moderation.unmute(&user),
+                };
+                match res {
+                    Ok(()) => {
+                        info!("User `{}` was (un-)muted by instance `{}`.", user, origin);
+                        self.send_moderation_status(&user);
+                    }
+                    Err(err) => debug!("Could not apply (un-)mute of `{}`: {}", user, err),
+                }
+            }
+            ClusterEvent::Kick { user, reason } => {
+                info!("User `{}` was kicked by instance `{}`.", user, origin);
+                self.remove_kicked(&user, reason.as_deref());
+            }
+            ClusterEvent::Presence {
+                connections,
+                logged_in,
+            } => {
+                let received = self.now();
+                if let Some(cluster) = &mut self.cluster {
+                    cluster.remote_presence.insert(
+                        origin,
+                        RemotePresence {
+                            received,
+                            connections,
+                            logged_in,
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// A message received on a subscribed channel.
+struct Published {
+    payload: Vec<u8>,
+}
+
+impl Published {
+    fn from_value(value: Value) -> Option<Published> {
+        match value {
+            Value::Array(Some(values)) => {
+                let mut values = values.into_iter();
+                match (values.next(), values.next(), values.next()) {
+                    (Some(Value::Bulk(Some(kind))), Some(_), Some(Value::Bulk(Some(payload))))
+                        if kind == b"message" =>
+                    {
+                        Some(Published { payload })
+                    }
+                    _ => None,
+                }
+            }
+            Value::Error(message) => {
+                warn!("Redis subscription failed: {}", message);
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+impl StreamHandler<Published, io::Error> for ChatServer {
+    fn handle(&mut self, msg: Published, _ctx: &mut Context<Self>) {
+        let envelope: Envelope = match serde_json::from_slice(&msg.payload) {
+            Ok(envelope) => envelope,
+            Err(err) => {
+                warn!("Received invalid cluster event: {}", err);
+                return;
+            }
+        };
+        let is_own = match &self.cluster {
+            Some(cluster) => envelope.origin == cluster.instance_id,
+            None => true,
+        };
+        if !is_own {
+            self.apply_cluster_event(envelope.origin, envelope.event);
+        }
+    }
+
+    fn error(&mut self, err: io::Error, _ctx: &mut Context<Self>) -> Running {
+        warn!("Redis subscription failed: {}", err);
+        Running::Stop
+    }
+
+    fn finished(&mut self, ctx: &mut Context<Self>) {
+        warn!("Redis subscription closed, resubscribing.");
+        self.resubscribe_later(ctx);
+    }
+}
+
+fn instance_channel(prefix: &str, instance_id: &str) -> String {
+    format!("{}:instance:{}", prefix, instance_id)
+}
+
+/// The milliseconds between the unix epoch and `time`.
+pub(super) fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .expect("system time is somehow before the unix epoch")
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{
+        simulate::{Capture, Collect},
+        Capabilities, DisplayName, User,
+    };
+    use serde_json::json;
+
+    fn notch() -> Uuid {
+        Uuid::from_u128(0x069a79f4_44e9_4726_a5be_fca90e38aaf5)
+    }
+
+    fn jeb() -> Uuid {
+        Uuid::from_u128(0x853c80ef_3c37_49fd_aa49_938b674adae6)
+    }
+
+    /// A server in a cluster whose Redis is never connected, announcing bans.
+    fn server() -> ChatServer {
+        ChatServer::for_tests(|config| {
+            config.moderation.announce_actions = true;
+            config.cluster = Some(ClusterConfig {
+                redis_url: "redis://127.0.0.1:6379".to_string(),
+                prefix: "axochat".to_string(),
+                instance_id: None,
+                registry_ttl: Duration::from_secs(30).into(),
+            });
+        })
+    }
+
+    /// Connects `name` with a connection receiving system messages, recording its packets.
+    fn connect(server: &mut ChatServer, name: &str, uuid: Uuid) -> Addr<Capture> {
+        let capture = Capture::default().start();
+        let user = User {
+            name: DisplayName::new(name.to_string()),
+            uuid,
+            allow_messages: true,
+        };
+        let id = server.connect_for_tests(&capture, Some(user));
+        server.sessions.get_mut(&id).unwrap().capabilities = Capabilities::SYSTEM_MESSAGES;
+        capture
+    }
+
+    /// The packets `capture` received.
+    fn packets(system: &mut SystemRunner, capture: &Addr<Capture>) -> Vec<serde_json::Value> {
+        system.block_on(capture.send(Collect)).unwrap().packets
+    }
+
+    fn names(packets: &[serde_json::Value]) -> Vec<&str> {
+        packets
+            .iter()
+            .map(|packet| packet["m"].as_str().unwrap())
+            .collect()
+    }
+
+    /// A `HGETALL` reply storing `events` as published by another instance.
+    fn stored(events: Vec<(Uuid, ClusterEvent)>) -> Vec<Value> {
+        events
+            .into_iter()
+            .flat_map(|(user, event)| {
+                let envelope = Envelope {
+                    origin: "other".to_string(),
+                    event,
+                };
+                vec![
+                    Value::Bulk(Some(user.to_hyphenated().to_string().into_bytes())),
+                    Value::Bulk(Some(serde_json::to_vec(&envelope).unwrap())),
+                ]
+            })
+            .collect()
+    }
+
+    fn in_an_hour(server: &ChatServer) -> u64 {
+        unix_millis(server.system_now()) / 1000 + 3600
+    }
+
+    #[test]
+    fn remote_bans_are_announced_and_disconnect_the_user() {
+        let mut system = System::new("test");
+        let mut server = server();
+        let notch_capture = connect(&mut server, "Notch", notch());
+        let jeb_capture = connect(&mut server, "jeb_", jeb());
+
+        server.apply_cluster_event(
+            "other".to_string(),
+            ClusterEvent::Moderation {
+                user: notch(),
+                ban: true,
+                reason: Some("spam".to_string()),
+                expires_at: None,
+            },
+        );
+        assert!(server.moderation.is_banned(&notch(), server.system_now()));
+
+        let notch_packets = packets(&mut system, &notch_capture);
+        let disconnected = notch_packets
+            .iter()
+            .find(|packet| packet["m"] == "Disconnected")
+            .expect("the banned user should be disconnected");
+        assert_eq!(disconnected["c"]["reason"], json!("spam"));
+        assert_eq!(
+            names(&packets(&mut system, &jeb_capture)),
+            ["SystemMessage"]
+        );
+    }
+
+    #[test]
+    fn remote_unbans_and_mutes_update_the_moderation_status() {
+        let mut system = System::new("test");
+        let mut server = server();
+        server
+            .moderation
+            .ban(&notch(), Restriction::default())
+            .unwrap();
+        let capture = connect(&mut server, "Notch", notch());
+
+        let origin = || "other".to_string();
+        server.apply_cluster_event(
+            origin(),
+            ClusterEvent::Moderation {
+                user: notch(),
+                ban: false,
+                reason: None,
+                expires_at: None,
+            },
+        );
+        assert!(!server.moderation.is_banned(&notch(), server.system_now()));
+        let expires_at = in_an_hour(&server);
+        server.apply_cluster_event(
+            origin(),
+            ClusterEvent::Mute {
+                user: notch(),
+                reason: None,
+                expires_at: Some(expires_at),
+            },
+        );
+        assert!(server.mute_error(&notch()).is_some());
+        server.apply_cluster_event(
+            origin(),
+            ClusterEvent::Mute {
+                user: notch(),
+                reason: None,
+                expires_at: None,
+            },
+        );
+        assert!(server.mute_error(&notch()).is_none());
+
+        assert_eq!(
+            names(&packets(&mut system, &capture)),
+            ["ModerationStatus"; 3]
+        );
+    }
+
+    #[test]
+    fn remote_kicks_disconnect_the_user() {
+        let mut system = System::new("test");
+        let mut server = server();
+        let capture = connect(&mut server, "Notch", notch());
+
+        server.apply_cluster_event(
+            "other".to_string(),
+            ClusterEvent::Kick {
+                user: notch(),
+                reason: Some("calm down".to_string()),
+            },
+        );
+
+        let packets = packets(&mut system, &capture);
+        assert_eq!(names(&packets), ["Disconnected"]);
+        assert_eq!(packets[0]["c"]["reason"], json!("calm down"));
+    }
+
+    #[test]
+    fn remote_presence_is_counted_per_instance() {
+        let _system = System::new("test");
+        let mut server = server();
+        for (origin, connections, logged_in) in
+            &[("first", 2, 1), ("second", 3, 2), ("first", 4, 3)]
+        {
+            server.apply_cluster_event(
+                origin.to_string(),
+                ClusterEvent::Presence {
+                    connections: *connections,
+                    logged_in: *logged_in,
+                },
+            );
+        }
+        assert_eq!(server.cluster.as_ref().unwrap().remote_user_count(), (7, 5));
+    }
+
+    /// Redis is not connected yet right after the cluster is configured.
+    #[test]
+    fn only_bans_and_mutes_are_kept_until_redis_is_connected() {
+        let _system = System::new("test");
+        let mut server = server();
+        let pending = |server: &ChatServer| server.cluster.as_ref().unwrap().pending.len();
+
+        server.publish(ClusterEvent::Presence {
+            connections: 1,
+            logged_in: 1,
+        });
+        server.publish(ClusterEvent::Kick {
+            user: notch(),
+            reason: None,
+        });
+        assert_eq!(pending(&server), 0);
+
+        // Each is stored and published.
+        server.publish(ClusterEvent::Moderation {
+            user: notch(),
+            ban: true,
+            reason: None,
+            expires_at: None,
+        });
+        server.publish(ClusterEvent::Mute {
+            user: jeb(),
+            reason: None,
+            expires_at: None,
+        });
+        assert_eq!(pending(&server), 4);
+
+        for _ in 0..MAX_PENDING {
+            server.publish(ClusterEvent::Mute {
+                user: jeb(),
+                reason: None,
+                expires_at: None,
+            });
+        }
+        assert_eq!(pending(&server), MAX_PENDING);
+    }
+
+    #[test]
+    fn resyncing_applies_only_what_was_missed() {
+        let mut system = System::new("test");
+        let mut server = server();
+        let ban = Restriction {
+            reason: Some("spam".to_string()),
+            expires_at: None,
+        };
+        server.moderation.ban(&notch(), ban.clone()).unwrap();
+        let notch_capture = connect(&mut server, "Notch", notch());
+        let jeb_capture = connect(&mut server, "jeb_", jeb());
+        let dinnerbone = Uuid::from_u128(0x61699b2e_d327_4a01_9f1e_0ea8c3f06bc6);
+
+        let expires_at = in_an_hour(&server);
+        server.resync_entries(stored(vec![
+            // Already applied.
+            (
+                notch(),
+                ClusterEvent::Moderation {
+                    user: notch(),
+                    ban: true,
+                    reason: ban.reason,
+                    expires_at: ban.expires_at,
+                },
+            ),
+            // Ended.
+            (
+                jeb(),
+                ClusterEvent::Moderation {
+                    user: jeb(),
+                    ban: true,
+                    reason: None,
+                    expires_at: Some(1),
+                },
+            ),
+            // Not banned here.
+            (
+                dinnerbone,
+                ClusterEvent::Moderation {
+                    user: dinnerbone,
+                    ban: false,
+                    reason: None,
+                    expires_at: None,
+                },
+            ),
+            // Missed.
+            (
+                jeb(),
+                ClusterEvent::Mute {
+                    user: jeb(),
+                    reason: None,
+                    expires_at: Some(expires_at),
+                },
+            ),
+        ]));
+
+        assert!(!server.moderation.is_banned(&jeb(), server.system_now()));
+        assert!(server.mute_error(&jeb()).is_some());
+        assert_eq!(
+            packets(&mut system, &notch_capture),
+            Vec::<serde_json::Value>::new()
+        );
+        assert_eq!(
+            names(&packets(&mut system, &jeb_capture)),
+            ["ModerationStatus"]
+        );
+    }
+}
@@ -0,0 +1,326 @@
+//! Serving clients which speak an older version of the protocol, so they keep working
+//! while they are upgraded.
+//!
+//! Packets are adapted when the session writes them: packets which existed in the old version
+//! are sent in their old shape, without the fields added later, and all other packets unchanged.
+
+use super::{
+    close::{Close, DisconnectReason},
+    handler::PrivateBody,
+    ChatServer, ClientPacket, InternalId, SuccessReason, TraceId,
+};
+use crate::auth::UserInfo;
+use crate::error::*;
+use log::*;
+
+use actix::Message;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+/// The version of the protocol described in `PROTOCOL.md`.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The version spoken by clients which do not send `Hello`.
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+
+/// Whether the server speaks a version of the protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Support {
+    Current,
+    /// A version in `server.legacy_protocols`, which is rejected after `sunset`.
+    Legacy {
+        sunset: Option<SystemTime>,
+    },
+    Unsupported,
+}
+
+impl ChatServer {
+    pub(super) fn protocol_support(&self, protocol: u32) -> Support {
+        if protocol >= PROTOCOL_VERSION {
+            return Support::Current;
+        }
+        let legacy = self
+            .config
+            .server
+            .legacy_protocols
+            .iter()
+            .find(|legacy| legacy.version == protocol);
+        match legacy.map(|legacy| legacy.sunset.map(|sunset| *sunset)) {
+            Some(Some(sunset)) if sunset <= self.system_now() => Support::Unsupported,
+            Some(sunset) => Support::Legacy { sunset },
+            None => Support::Unsupported,
+        }
+    }
+
+    /// Closes the connection `user_id` if its version of the protocol is not spoken anymore.
+    ///
+    /// Returns whether it was closed.
+    pub(super) fn reject_unsupported_protocol(&self, user_id: InternalId) -> bool {
+        let session = &self.sessions[&user_id];
+        if self.protocol_support(session.protocol) != Support::Unsupported {
+            return false;
+        }
+        info!(
+            "User `{}` uses the unsupported protocol version {}.",
+            user_id, session.protocol
+        );
+        self.send_error(
+            user_id,
+            ClientError::ClientOutdated {
+                upgrade_url: self.config.server.client_upgrade_url.clone(),
+            },
+        );
+        session
+            .close
+            .do_send(Close(DisconnectReason::ClientOutdated))
+            .ok();
+        true
+    }
+
+    /// Tells the user logged in with `user_id` when its version of the protocol stops being spoken.
+    pub(super) fn warn_protocol_sunset(&self, user_id: InternalId) {
+        let protocol = self.sessions[&user_id].protocol;
+        if let Support::Legacy {
+            sunset: Some(sunset),
+        } = self.protocol_support(protocol)
+        {
+            let packet = ClientPacket::ProtocolDeprecated {
+                protocol,
+                sunset: super::cluster::unix_millis(sunset),
+            };
+            self.reply(user_id, packet);
+        }
+    }
+}
+
+/// Encodes `packet` for a session speaking the version `protocol`,
+/// with the `trace` of the packet it responds to.
+///
+/// Returns `None` if the packet can not be expressed in that version.
+/// Version 1 has no traces, so they are left out.
+pub(super) fn encode(
+    packet: &ClientPacket,
+    protocol: u32,
+    trace: Option<TraceId>,
+) -> Option<String> {
+    let encoded = if protocol >= PROTOCOL_VERSION {
+        match trace {
+            Some(trace) => serde_json::to_string(&Traced { packet, trace }),
+            None => serde_json::to_string(packet),
+        }
+    } else {
+        match to_v1(packet) {
+            Some(Some(legacy)) => serde_json::to_string(&legacy),
+            Some(None) => serde_json::to_string(packet),
+            None => return None,
+        }
+    };
+    Some(encoded.expect("could not encode message"))
+}
+
+/// A packet sent to many connections, encoded once for all of them which speak the current version.
+#[derive(Clone)]
+pub(super) struct EncodedPacket {
+    pub packet: ClientPacket,
+    encoded: Arc<str>,
+}
+
+impl EncodedPacket {
+    pub fn new(packet: ClientPacket) -> EncodedPacket {
+        let encoded = serde_json::to_string(&packet).expect("could not encode message");
+        EncodedPacket {
+            packet,
+            encoded: encoded.into(),
+        }
+    }
+
+    /// The size of the packet in the current version, in bytes.
+    pub fn size(&self) -> usize {
+        self.encoded.len()
+    }
+
+    /// Encodes the packet like [`encode`] without a trace, reusing the encoding if it can.
+    pub fn encode(&self, protocol: u32) -> Option<String> {
+        if protocol >= PROTOCOL_VERSION {
+            Some(self.encoded.to_string())
+        } else {
+            encode(&self.packet, protocol, None)
+        }
+    }
+}
+
+impl Message for EncodedPacket {
+    type Result = ();
+}
+
+/// A packet with the `trace` field next to `m` and `c`.
+#[derive(Serialize)]
+struct Traced<'a> {
+    #[serde(flatten)]
+    packet: &'a ClientPacket,
+    trace: TraceId,
+}
+
+/// The packets of version 1, in their original shape.
+#[derive(Serialize)]
+#[serde(tag = "m", content = "c")]
+enum V1Packet<'a> {
+    MojangInfo {
+        session_hash: &'a str,
+    },
+    NewJWT {
+        token: &'a str,
+    },
+    Message {
+        author_info: V1User,
+        content: &'a str,
+    },
+    PrivateMessage {
+        author_info: V1User,
+        content: &'a str,
+    },
+    UserCount {
+        connections: u32,
+        logged_in: u32,
+    },
+    Success {
+        reason: SuccessReason,
+    },
+    /// `message` is the name of the error, without any details.
+    Error {
+        message: Value,
+    },
+}
+
+#[derive(Serialize)]
+struct V1User {
+    name: String,
+    uuid: Uuid,
+}
+
+impl From<&UserInfo> for V1User {
+    fn from(info: &UserInfo) -> V1User {
+        V1User {
+            name: info.name.clone(),
+            uuid: info.uuid,
+        }
+    }
+}
+
+/// Converts `packet` to version 1.
+///
+/// Returns `Some(None)` for packets which did not exist in version 1 and are sent unchanged,
+/// and `None` for encrypted private messages, which version 1 can not show.
+fn to_v1(packet: &ClientPacket) -> Option<Option<V1Packet<'_>>> {
+    let legacy = match packet {
+        ClientPacket::MojangInfo { session_hash } => V1Packet::MojangInfo { session_hash },
+        ClientPacket::NewJWT { token } => V1Packet::NewJWT { token },
+        ClientPacket::Message {
+            author_info,
+            content,
+            ..
+        } => V1Packet::Message {
+            author_info: author_info.into(),
+            content: content.as_str(),
+        },
+        ClientPacket::PrivateMessage {
+            author_info, body, ..
+        } => match body {
+            PrivateBody::Plain(content) => V1Packet::PrivateMessage {
+                author_info: author_info.into(),
+                content: content.as_str(),
+            },
+            PrivateBody::Encrypted(_) => return None,
+        },
+        ClientPacket::UserCount {
+            connections,
+            logged_in,
+        } => V1Packet::UserCount {
+            connections: *connections,
+            logged_in: *logged_in,
+        },
+        ClientPacket::Success { reason } => V1Packet::Success { reason: *reason },
+        ClientPacket::Error { message }
+        | ClientPacket::RepeatedError { message, .. }
+        | ClientPacket::Rejected { message, .. } => V1Packet::Error {
+            message: v1_error(message),
+        },
+        _ => return Some(None),
+    };
+    Some(Some(legacy))
+}
+
+/// Encodes `message` like version 1 did: as the name of the error,
+/// except for `InvalidCharacter`, which carried the character.
+fn v1_error(message: &ClientError) -> Value {
+    if let ClientError::InvalidCharacter { character, .. } = message {
+        let mut object = serde_json::Map::new();
+        object.insert(
+            "InvalidCharacter".to_string(),
+            Value::String(character.to_string()),
+        );
+        return Value::Object(object);
+    }
+    match serde_json::to_value(message) {
+        Ok(Value::Object(object)) => object
+            .into_iter()
+            .next()
+            .map(|(name, _)| Value::String(name))
+            .unwrap_or(Value::Null),
+        Ok(name) => name,
+        Err(err) => {
+            warn!("Could not encode error for version 1: {}", err);
+            Value::String("Internal".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{samples, Origin};
+
+    use std::path::PathBuf;
+
+    /// Compares the packets sent to version 1 sessions byte for byte with those of
+    /// `tests/snapshots/compat_v1.jsonl`, one per line, or `null` if one is not sent at all.
+    ///
+    /// After reviewing a change, the snapshot is updated by running the tests with `UPDATE_SNAPSHOTS=1`.
+    #[test]
+    fn version_1_packets_match_the_snapshot() {
+        // Version 1 has no traces, so they must not show up either.
+        let trace = TraceId::new(InternalId::new(1, Origin::Client, 0), 1);
+        let encoded: String = samples::client_packets()
+            .iter()
+            .map(|packet| {
+                let line = encode(packet, LEGACY_PROTOCOL_VERSION, Some(trace))
+                    .unwrap_or_else(|| "null".to_string());
+                line + "\n"
+            })
+            .collect();
+
+        let path =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots/compat_v1.jsonl");
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::write(&path, &encoded).unwrap();
+            return;
+        }
+        let snapshot = std::fs::read_to_string(&path).unwrap();
+        for (i, (actual, expected)) in encoded.lines().zip(snapshot.lines()).enumerate() {
+            assert_eq!(
+                actual,
+                expected,
+                "packet {} differs from the snapshot",
+                i + 1
+            );
+        }
+        assert_eq!(
+            encoded.lines().count(),
+            snapshot.lines().count(),
+            "the number of packets differs from the snapshot"
+        );
+    }
+}
@@ -1,35 +1,116 @@
 use log::*;
 
-use super::{ChatServer, ClientPacket, InternalId, SessionState};
+use super::{
+    backlog::Pending,
+    close::{Close, DisconnectReason},
+    compat::EncodedPacket,
+    funnel::Stage,
+    handler::ReplayChunk,
+    trace::TracedPacket,
+    Capabilities, ChatServer, ClientPacket, InternalId, Origin, SessionState,
+    LEGACY_PROTOCOL_VERSION,
+};
 use actix::*;
+use rand::RngCore;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 
+/// Adds a new connection, whose id is returned,
+/// or tells the session why it has to close its connection right away.
 #[derive(Message)]
-#[rtype(InternalId)]
+#[rtype(result = "Result<InternalId, DisconnectReason>")]
 pub(super) struct Connect {
     addr: Recipient<ClientPacket>,
+    close: Recipient<Close>,
+    replay: Recipient<ReplayChunk>,
+    traced: Recipient<TracedPacket>,
+    encoded: Recipient<EncodedPacket>,
+    reserved: bool,
+    ip: Option<IpAddr>,
+    /// The version of the protocol negotiated in the handshake, if any.
+    subprotocol: Option<u32>,
+    _pending: Pending,
 }
 
 impl Connect {
-    pub fn new(addr: Recipient<ClientPacket>) -> Connect {
-        Connect { addr }
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        addr: Recipient<ClientPacket>,
+        close: Recipient<Close>,
+        replay: Recipient<ReplayChunk>,
+        traced: Recipient<TracedPacket>,
+        encoded: Recipient<EncodedPacket>,
+        reserved: bool,
+        ip: Option<IpAddr>,
+        subprotocol: Option<u32>,
+        pending: Pending,
+    ) -> Connect {
+        Connect {
+            addr,
+            close,
+            replay,
+            traced,
+            encoded,
+            reserved,
+            ip,
+            subprotocol,
+            _pending: pending,
+        }
     }
 }
 
 impl Handler<Connect> for ChatServer {
-    type Result = InternalId;
+    type Result = Result<InternalId, DisconnectReason>;
 
-    fn handle(&mut self, msg: Connect, _ctx: &mut Context<Self>) -> InternalId {
-        self.current_internal_user_id += 1;
-        let id = InternalId::new(self.current_internal_user_id);
-        self.connections.insert(
+    fn handle(&mut self, msg: Connect, ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(ip) = msg.ip {
+            if self.has_too_many_guests(ip) {
+                info!("Rejecting connection, `{}` has too many guests.", ip);
+                return Err(DisconnectReason::GuestLimit);
+            }
+        }
+        let id = self.next_internal_id(Origin::Client);
+        self.sessions.insert(
             id,
             SessionState {
-                addr: msg.addr.clone(),
+                addr: msg.addr,
+                close: msg.close,
+                replay: msg.replay,
+                traced: msg.traced,
+                encoded: msg.encoded,
                 session_hash: None,
                 user: None,
+                resume_token: None,
+                capabilities: Capabilities::NONE,
+                echo_own_messages: true,
+                moderation_events: None,
+                confirmations: HashMap::new(),
+                watching: HashSet::new(),
+                reserved: msg.reserved,
+                cooldown_since: None,
+                failed_sends: Cell::new(0),
+                last_diagnostics: None,
+                protocol: msg.subprotocol.unwrap_or(LEGACY_PROTOCOL_VERSION),
+                ip: msg.ip,
             },
         );
+        self.funnel.record_stage(Stage::Connected);
+        self.record_stats_connections();
         debug!("User `{}` joined the chat.", id);
-        id
+        self.update_guest_count();
+        self.start_login_timeout(id, ctx);
+        if msg.subprotocol.is_some() {
+            self.reject_unsupported_protocol(id);
+        }
+        Ok(id)
+    }
+}
+
+impl ChatServer {
+    /// Numbers a new connection from `origin` and gives it a random tag.
+    pub(super) fn next_internal_id(&mut self, origin: Origin) -> InternalId {
+        self.current_internal_user_id += 1;
+        InternalId::new(self.current_internal_user_id, origin, self.rng.next_u32())
     }
 }
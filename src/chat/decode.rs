@@ -0,0 +1,103 @@
+//! Decoding of the packets sent by clients.
+//!
+//! Every frame is checked against the [`PacketLimits`] before it is parsed,
+//! and every failure is mapped to a [`MalformedCategory`], so clients always learn why a packet was rejected.
+//! The name and the required fields of a packet are checked against the [schema](super::schema)
+//! before it is decoded, so the category does not depend on the messages of the parser.
+
+use super::{
+    schema::{self, Required},
+    ServerPacket,
+};
+use crate::error::MalformedCategory;
+
+use serde_json::{error::Category, Value};
+
+/// Bounds on the packets a session accepts.
+#[derive(Debug, Clone, Copy)]
+pub struct PacketLimits {
+    /// The maximum size of a packet in bytes.
+    pub max_size: usize,
+    /// The maximum nesting of arrays and objects in a packet.
+    pub max_depth: usize,
+    /// The number of malformed packets after which a session is closed; unlimited if `0`.
+    pub max_malformed: u32,
+}
+
+/// Decodes a packet received in a text frame.
+pub fn decode_packet(
+    text: &str,
+    limits: &PacketLimits,
+) -> std::result::Result<ServerPacket, MalformedCategory> {
+    if text.len() > limits.max_size {
+        return Err(MalformedCategory::TooLarge);
+    }
+    if nesting_depth(text.as_bytes()) > limits.max_depth {
+        return Err(MalformedCategory::TooDeep);
+    }
+
+    let value: Value = serde_json::from_str(text).map_err(|_| MalformedCategory::Syntax)?;
+    check_shape(&value)?;
+    serde_json::from_value(value).map_err(|err| match err.classify() {
+        // The name and the required fields were checked, so a value has the wrong type.
+        Category::Data => MalformedCategory::TypeMismatch,
+        Category::Syntax | Category::Eof | Category::Io => MalformedCategory::Syntax,
+    })
+}
+
+/// Checks that `packet` names a known packet and contains the fields it requires,
+/// according to the [schema](super::schema).
+fn check_shape(packet: &Value) -> std::result::Result<(), MalformedCategory> {
+    let packet = packet.as_object().ok_or(MalformedCategory::TypeMismatch)?;
+    let name = match packet.get("m") {
+        Some(Value::String(name)) => name,
+        Some(_) => return Err(MalformedCategory::TypeMismatch),
+        None => return Err(MalformedCategory::MissingField),
+    };
+    let fields = match schema::required_fields(name) {
+        Some(Required::Content(fields)) => fields,
+        Some(Required::Nothing) => return Ok(()),
+        None => return Err(MalformedCategory::UnknownPacket),
+    };
+    let content = match packet.get("c") {
+        Some(Value::Object(content)) => content,
+        Some(_) => return Err(MalformedCategory::TypeMismatch),
+        None => return Err(MalformedCategory::MissingField),
+    };
+    if fields.iter().all(|field| content.contains_key(*field)) {
+        Ok(())
+    } else {
+        Err(MalformedCategory::MissingField)
+    }
+}
+
+/// Returns the deepest nesting of arrays and objects in `json`, ignoring brackets in strings.
+///
+/// The input does not have to be valid JSON; it is checked before the parser recurses into it.
+fn nesting_depth(json: &[u8]) -> usize {
+    let mut depth = 0usize;
+    let mut max_depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for byte in json {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                max_depth = max_depth.max(depth);
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    max_depth
+}
@@ -0,0 +1,461 @@
+//! Sending packets to sessions which may already be gone.
+//!
+//! A connection which stopped accepting packets fails every broadcast,
+//! so only the first of its consecutive failures is logged and the rest are summarized once it closes.
+//!
+//! Which connections receive a broadcast message is decided by the [`DeliveryFilter`]s.
+
+use super::{
+    compat::EncodedPacket,
+    trace::{self, TracedPacket},
+    Capabilities, ChatServer, ClientPacket, InternalId, SessionState,
+};
+use crate::config::GuestEvent;
+use crate::error::ClientError;
+use crate::rules::Rejection;
+use log::*;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// The upper bounds of the frame size histogram buckets, in bytes.
+const FRAME_SIZE_BUCKETS: [usize; 8] = [256, 512, 1024, 2048, 4096, 8192, 16384, 65536];
+
+/// The number of packets which could not be sent to sessions, of the errors sent
+/// and the sizes of broadcast messages, shared by the chat server and the routes.
+#[derive(Default)]
+pub(super) struct DeliveryStats {
+    failed: AtomicU64,
+    /// The errors sent to sessions, by translation key.
+    errors: Mutex<BTreeMap<&'static str, u64>>,
+    frames: Mutex<FrameSizes>,
+}
+
+/// The sizes of the `Message` packets of broadcast messages.
+#[derive(Default)]
+struct FrameSizes {
+    /// The number of frames with a size of at most each bucket.
+    buckets: [u64; FRAME_SIZE_BUCKETS.len()],
+    sum: u64,
+    count: u64,
+    /// The number of frames sent without their optional parts to stay below `server.max_outgoing_frame_bytes`.
+    degraded: u64,
+    /// The number of messages rejected since their frame was too large even without them.
+    rejected: u64,
+}
+
+/// What happened to a broadcast message after its frame was measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum FrameOutcome {
+    Sent,
+    Degraded,
+    Rejected,
+}
+
+impl DeliveryStats {
+    /// Records the size in bytes of the frame of a broadcast message.
+    pub fn record_frame(&self, bytes: usize, outcome: FrameOutcome) {
+        let mut frames = self.frames.lock().unwrap();
+        for (bucket, bound) in frames.buckets.iter_mut().zip(&FRAME_SIZE_BUCKETS) {
+            if bytes <= *bound {
+                *bucket += 1;
+            }
+        }
+        frames.sum += bytes as u64;
+        frames.count += 1;
+        match outcome {
+            FrameOutcome::Sent => {}
+            FrameOutcome::Degraded => frames.degraded += 1,
+            FrameOutcome::Rejected => frames.rejected += 1,
+        }
+    }
+
+    /// Appends the counters and the histogram in the Prometheus text format.
+    pub fn write_metrics(&self, output: &mut String) {
+        writeln!(
+            output,
+            "# HELP axochat_failed_sends_total The number of packets which could not be sent to a session."
+        )
+        .unwrap();
+        writeln!(output, "# TYPE axochat_failed_sends_total counter").unwrap();
+        writeln!(
+            output,
+            "axochat_failed_sends_total {}",
+            self.failed.load(Ordering::Relaxed)
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# HELP axochat_errors_sent_total The number of errors sent to sessions by the chat server."
+        )
+        .unwrap();
+        writeln!(output, "# TYPE axochat_errors_sent_total counter").unwrap();
+        for (error, count) in self.errors.lock().unwrap().iter() {
+            writeln!(
+                output,
+                "axochat_errors_sent_total{{error=\"{}\"}} {}",
+                error, count
+            )
+            .unwrap();
+        }
+
+        let frames = self.frames.lock().unwrap();
+        writeln!(
+            output,
+            "# HELP axochat_broadcast_frame_bytes The size of the Message packets of broadcast messages."
+        )
+        .unwrap();
+        writeln!(output, "# TYPE axochat_broadcast_frame_bytes histogram").unwrap();
+        for (bucket, bound) in frames.buckets.iter().zip(&FRAME_SIZE_BUCKETS) {
+            writeln!(
+                output,
+                "axochat_broadcast_frame_bytes_bucket{{le=\"{}\"}} {}",
+                bound, bucket
+            )
+            .unwrap();
+        }
+        writeln!(
+            output,
+            "axochat_broadcast_frame_bytes_bucket{{le=\"+Inf\"}} {}",
+            frames.count
+        )
+        .unwrap();
+        writeln!(output, "axochat_broadcast_frame_bytes_sum {}", frames.sum).unwrap();
+        writeln!(
+            output,
+            "axochat_broadcast_frame_bytes_count {}",
+            frames.count
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# HELP axochat_oversized_broadcasts_total The number of broadcast messages above server.max_outgoing_frame_bytes by outcome."
+        )
+        .unwrap();
+        writeln!(output, "# TYPE axochat_oversized_broadcasts_total counter").unwrap();
+        writeln!(
+            output,
+            "axochat_oversized_broadcasts_total{{outcome=\"degraded\"}} {}",
+            frames.degraded
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "axochat_oversized_broadcasts_total{{outcome=\"rejected\"}} {}",
+            frames.rejected
+        )
+        .unwrap();
+    }
+}
+
+impl ChatServer {
+    /// Sends `packet` to the connection `id` and returns whether it was sent.
+    ///
+    /// Errors and failures are counted, but only the first of consecutive failures is logged.
+    /// Every packet the chat server sends to a single session should go through this.
+    ///
+    /// A direct response to the packet being handled carries its trace,
+    /// if the connection sent that packet and supports traces.
+    pub(in crate::chat) fn send_to(
+        &self,
+        id: InternalId,
+        session: &SessionState,
+        packet: ClientPacket,
+    ) -> bool {
+        if let ClientPacket::Error { message } | ClientPacket::Rejected { message, .. } = &packet {
+            *self
+                .delivery
+                .errors
+                .lock()
+                .unwrap()
+                .entry(message.translation_key())
+                .or_insert(0) += 1;
+        }
+        let trace = trace::current_trace().filter(|trace| {
+            trace.session() == id
+                && session.capabilities.contains(Capabilities::TRACE)
+                && packet.is_response()
+        });
+        let sent = match trace {
+            Some(trace) => session
+                .traced
+                .do_send(TracedPacket { packet, trace })
+                .map_err(|err| err.to_string()),
+            None => session.addr.do_send(packet).map_err(|err| err.to_string()),
+        };
+        self.record_send(id, session, sent)
+    }
+
+    /// Sends the broadcast `packet`, which was encoded once for every connection, to `id`
+    /// and returns whether it was sent. Failures are counted like those of [`Self::send_to`].
+    pub(in crate::chat) fn send_encoded_to(
+        &self,
+        id: InternalId,
+        session: &SessionState,
+        packet: &EncodedPacket,
+    ) -> bool {
+        let sent = session
+            .encoded
+            .do_send(packet.clone())
+            .map_err(|err| err.to_string());
+        self.record_send(id, session, sent)
+    }
+
+    fn record_send(
+        &self,
+        id: InternalId,
+        session: &SessionState,
+        sent: std::result::Result<(), String>,
+    ) -> bool {
+        match sent {
+            Ok(()) => {
+                let failed = session.failed_sends.replace(0);
+                if failed > 0 {
+                    info!("Sending to `{}` works again after {} failures.", id, failed);
+                }
+                true
+            }
+            Err(err) => {
+                self.delivery.failed.fetch_add(1, Ordering::Relaxed);
+                let failed = session.failed_sends.get() + 1;
+                session.failed_sends.set(failed);
+                if failed == 1 {
+                    warn!("Could not send packet to `{}`: {}", id, err);
+                }
+                false
+            }
+        }
+    }
+}
+
+impl ChatServer {
+    /// Sends `packet` to the connection `id`, unless it closed in the meantime.
+    pub(in crate::chat) fn reply(&self, id: InternalId, packet: ClientPacket) -> bool {
+        match self.sessions.get(&id) {
+            Some(session) => self.send_to(id, session, packet),
+            None => {
+                debug!("Connection `{}` closed before it could be answered.", id);
+                false
+            }
+        }
+    }
+
+    /// Tells the connection `id` why its packet was rejected, unless it closed in the meantime.
+    pub(in crate::chat) fn send_error(&self, id: InternalId, message: ClientError) -> bool {
+        self.reply(id, ClientPacket::Error { message })
+    }
+
+    /// Tells the connection `id` which rule of the validator rejected its message.
+    pub(in crate::chat) fn send_rejection(&self, id: InternalId, rejection: Rejection) -> bool {
+        self.reply(
+            id,
+            ClientPacket::Rejected {
+                message: rejection.error,
+                rule: rejection.rule,
+            },
+        )
+    }
+}
+
+/// What the delivery filters know about a broadcast message.
+pub(super) struct BroadcastContext {
+    /// The connection which sent the message, if it was sent on this instance.
+    pub author: Option<InternalId>,
+}
+
+/// Decides per recipient whether a broadcast message is delivered.
+///
+/// Filters are combined statically, so the broadcast loop is not slower than checks written inline.
+pub(super) trait DeliveryFilter<'a> {
+    /// Prepares the filter for one message.
+    ///
+    /// Since [`delivers`](DeliveryFilter::delivers) is called for every connection,
+    /// anything which does not depend on the recipient, like a set of recipients,
+    /// should be computed here.
+    fn prepare(server: &'a ChatServer, context: &'a BroadcastContext) -> Self;
+
+    /// Returns whether the connection `id` receives the message.
+    fn delivers(&self, id: InternalId, session: &SessionState) -> bool;
+}
+
+/// Delivers a message to the connections both filters deliver it to.
+impl<'a, A: DeliveryFilter<'a>, B: DeliveryFilter<'a>> DeliveryFilter<'a> for (A, B) {
+    fn prepare(server: &'a ChatServer, context: &'a BroadcastContext) -> Self {
+        (A::prepare(server, context), B::prepare(server, context))
+    }
+
+    fn delivers(&self, id: InternalId, session: &SessionState) -> bool {
+        self.0.delivers(id, session) && self.1.delivers(id, session)
+    }
+}
+
+/// Skips the connection of the author, unless it wants its own messages echoed.
+struct OwnEcho {
+    author: Option<InternalId>,
+}
+
+impl<'a> DeliveryFilter<'a> for OwnEcho {
+    fn prepare(_server: &'a ChatServer, context: &'a BroadcastContext) -> Self {
+        OwnEcho {
+            author: context.author,
+        }
+    }
+
+    fn delivers(&self, id: InternalId, session: &SessionState) -> bool {
+        Some(id) != self.author || session.echo_own_messages
+    }
+}
+
+/// Skips the connections which did not log in, unless guests receive messages.
+struct Guests {
+    guests_receive: bool,
+}
+
+impl<'a> DeliveryFilter<'a> for Guests {
+    fn prepare(server: &'a ChatServer, _context: &'a BroadcastContext) -> Self {
+        Guests {
+            guests_receive: server.guests.allow_guests
+                && server.guests.guest_events.contains(&GuestEvent::Messages),
+        }
+    }
+
+    fn delivers(&self, _id: InternalId, session: &SessionState) -> bool {
+        self.guests_receive || session.is_logged_in()
+    }
+}
+
+/// The filters applied to every broadcast message, in order; more are added by nesting pairs.
+type DeliveryFilters = (OwnEcho, Guests);
+
+impl ChatServer {
+    /// Combines the [`DeliveryFilters`] for a message.
+    pub(in crate::chat) fn delivery_filter<'a>(
+        &'a self,
+        context: &'a BroadcastContext,
+    ) -> impl Fn(InternalId, &SessionState) -> bool + 'a {
+        let filters = DeliveryFilters::prepare(self, context);
+        move |id, session| filters.delivers(id, session)
+    }
+}
+
+impl SessionState {
+    /// Logs the consecutive failures which were not logged, once the connection closed.
+    pub(super) fn log_failed_sends(&self, id: InternalId) {
+        let failed = self.failed_sends.get();
+        if failed > 1 {
+            warn!(
+                "Could not send {} consecutive packets to `{}` before it closed.",
+                failed, id
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{simulate::Capture, DisplayName, User};
+
+    use actix::*;
+    use std::hint::black_box;
+    use std::time::{Duration, Instant};
+    use uuid::Uuid;
+
+    const CONNECTIONS: u128 = 2000;
+    const MESSAGES: u32 = 2000;
+
+    /// A chat server with `CONNECTIONS` connections, every tenth of which did not log in.
+    fn server() -> ChatServer {
+        let mut server = ChatServer::for_tests(|_| {});
+        let capture = Capture::default().start();
+        for i in 0..CONNECTIONS {
+            let user = Some(User {
+                name: DisplayName::new(format!("user{}", i)),
+                uuid: Uuid::from_u128(i),
+                allow_messages: true,
+            })
+            .filter(|_| i % 10 != 0);
+            let id = server.connect_for_tests(&capture, user);
+            server.sessions.get_mut(&id).unwrap().echo_own_messages = i % 2 == 0;
+        }
+        server
+    }
+
+    /// Runs `deliveries` once per message, with every connection of `server` in turn as the author.
+    fn time<F: FnMut(Option<InternalId>) -> usize>(
+        server: &ChatServer,
+        mut deliveries: F,
+    ) -> Duration {
+        let authors: Vec<_> = server.sessions.iter().map(|(id, _)| *id).collect();
+        let start = Instant::now();
+        for i in 0..MESSAGES as usize {
+            black_box(deliveries(Some(authors[i % authors.len()])));
+        }
+        start.elapsed()
+    }
+
+    /// Compares the combined delivery filters with the checks they replaced, written inline in the loop.
+    ///
+    /// Run it with `cargo test --release composed_filters -- --ignored`;
+    /// the timings are logged and part of the failure.
+    #[test]
+    #[ignore]
+    fn composed_filters_are_not_slower_than_inline_checks() {
+        let _system = System::new("delivery-benchmark");
+        let server = server();
+
+        let inline = time(&server, |author| {
+            server
+                .sessions
+                .iter()
+                .filter(|(id, session)| {
+                    (Some(**id) != author || session.echo_own_messages)
+                        && server.receives(session, GuestEvent::Messages)
+                })
+                .count()
+        });
+        let composed = time(&server, |author| {
+            let context = BroadcastContext { author };
+            let delivers = server.delivery_filter(&context);
+            server
+                .sessions
+                .iter()
+                .filter(|(id, session)| delivers(**id, session))
+                .count()
+        });
+
+        let per_message = |elapsed: Duration| elapsed / MESSAGES;
+        info!(
+            "{} connections: inline {:?}, composed {:?} per message",
+            CONNECTIONS,
+            per_message(inline),
+            per_message(composed)
+        );
+        // Leaves room for noise, while catching a filter which does per-recipient work it could prepare.
+        assert!(
+            composed < inline * 3 / 2,
+            "composed filters took {:?}, inline checks {:?}",
+            composed,
+            inline
+        );
+    }
+
+    #[test]
+    fn composed_filters_decide_like_inline_checks() {
+        let _system = System::new("delivery-test");
+        let server = server();
+        for (author, _) in server.sessions.iter().take(20) {
+            let context = BroadcastContext {
+                author: Some(*author),
+            };
+            let delivers = server.delivery_filter(&context);
+            for (id, session) in server.sessions.iter() {
+                let expected = (id != author || session.echo_own_messages)
+                    && server.receives(session, GuestEvent::Messages);
+                assert_eq!(delivers(*id, session), expected);
+            }
+        }
+    }
+}
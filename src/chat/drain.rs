@@ -0,0 +1,116 @@
+//! Taking an instance out of rotation for a deploy, without disconnecting all of its clients at once.
+//!
+//! While an instance drains, `/ready` answers `503 Service Unavailable`, so load balancers stop sending
+//! new connections to it, and new websocket connections are rejected with a `Retry-After` header.
+//! The open connections receive a `Disconnected` packet and are closed with the code `4009`,
+//! `drain.step_percent` of those open when draining started every `drain.step_interval`,
+//! so their clients reconnect to the other instances over time instead of all at once.
+//!
+//! Draining is started with `/api/v1/drain` or `SIGUSR1`, and it is cancelled by doing so again
+//! or with `/api/v1/undrain`. Connections closed until then stay closed.
+
+use super::{
+    close::{Close, DisconnectReason},
+    ChatServer, ClientPacket, ConnectionLimit, InternalId,
+};
+use log::*;
+
+use actix::*;
+use actix_web::{http::header, web, HttpResponse};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// The progress of the current drain.
+pub(super) struct Drain {
+    handle: SpawnHandle,
+    /// The number of connections closed in every step.
+    step: usize,
+    /// The connections which were told to reconnect, but may not be removed yet.
+    closed: HashSet<InternalId>,
+}
+
+impl ChatServer {
+    /// Starts or cancels draining, or toggles it if `draining` is `None`.
+    ///
+    /// Returns whether the instance drains afterwards.
+    pub(super) fn set_draining(&mut self, draining: Option<bool>, ctx: &mut Context<Self>) -> bool {
+        let draining = draining.unwrap_or_else(|| self.drain.is_none());
+        match (draining, self.drain.take()) {
+            (true, Some(drain)) => self.drain = Some(drain),
+            (true, None) => {
+                let connections = self.sessions.len();
+                let percent = self.config.drain.step_percent.clamp(1, 100) as usize;
+                let step = (connections * percent).div_ceil(100).max(1);
+                info!(
+                    "Draining {} connections, {} every {}.",
+                    connections,
+                    step,
+                    humantime::format_duration(*self.config.drain.step_interval)
+                );
+                let handle = ctx.run_interval(*self.config.drain.step_interval, |actor, ctx| {
+                    actor.drain_step(ctx)
+                });
+                self.drain = Some(Drain {
+                    handle,
+                    step,
+                    closed: HashSet::new(),
+                });
+            }
+            (false, Some(drain)) => {
+                info!("Stopped draining.");
+                ctx.cancel_future(drain.handle);
+            }
+            (false, None) => {}
+        }
+        self.connection_limit.set_draining(draining);
+        draining
+    }
+
+    /// Tells the next connections to reconnect and closes them.
+    fn drain_step(&mut self, ctx: &mut Context<Self>) {
+        let drain = match &mut self.drain {
+            Some(drain) => drain,
+            None => return,
+        };
+        let ids: Vec<InternalId> = self
+            .sessions
+            .iter()
+            .map(|(id, _)| *id)
+            .filter(|id| !drain.closed.contains(id))
+            .take(drain.step)
+            .collect();
+        if ids.is_empty() {
+            info!("Every connection was drained.");
+            ctx.cancel_future(drain.handle);
+            return;
+        }
+        debug!("Draining {} connections.", ids.len());
+        drain.closed.extend(&ids);
+
+        let packet = ClientPacket::Disconnected {
+            reason_code: DisconnectReason::Migrate.label(),
+            translation_key: DisconnectReason::Migrate.translation_key(),
+            retry_after_secs: Some(self.config.drain.retry_after.as_secs()),
+            reason: None,
+        };
+        for id in ids {
+            let session = &self.sessions[&id];
+            self.send_to(id, session, packet.clone());
+            session.close.do_send(Close(DisconnectReason::Migrate)).ok();
+        }
+    }
+}
+
+/// Answers `200 OK` while the instance accepts connections and `503 Service Unavailable` while it drains.
+pub(super) fn ready_route(limit: web::Data<Arc<ConnectionLimit>>) -> HttpResponse {
+    if limit.draining() {
+        HttpResponse::ServiceUnavailable()
+            .header(
+                header::RETRY_AFTER,
+                limit.retry_after().as_secs().to_string(),
+            )
+            .finish()
+    } else {
+        HttpResponse::Ok().finish()
+    }
+}
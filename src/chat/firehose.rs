@@ -0,0 +1,343 @@
+//! The read-only stream of broadcast messages, presence and moderation events
+//! served to trusted observers at `/api/v1/firehose`.
+//!
+//! Observers are kept apart from the sessions: they are not users,
+//! do not count towards `server.max_connections` and never receive private messages.
+//! Events are sent without waiting, and an observer which does not keep up is disconnected,
+//! so a slow observer can never delay the chat server or the users.
+
+use super::{
+    close::{Close, DisconnectReason},
+    ChatServer, ClientPacket, InternalId, Origin,
+};
+use log::*;
+
+use actix::{dev::SendError, *};
+use actix_web_actors::ws;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// The number of events queued for an observer before it is disconnected.
+const MAX_QUEUED_EVENTS: usize = 1024;
+
+/// The maximum size of a packet sent by an observer.
+const MAX_REQUEST_SIZE: usize = 1024;
+
+/// The kinds of events an observer can subscribe to.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EventKind {
+    /// Broadcast messages, as `Message`.
+    Message,
+    /// Users logging in with their first session and closing their last one,
+    /// as `UserJoined` and `UserLeft`.
+    Presence,
+    /// Rejected messages as `ModerationEvent` and bans as `ModerationAction`.
+    Moderation,
+}
+
+const ALL_KINDS: [EventKind; 3] = [
+    EventKind::Message,
+    EventKind::Presence,
+    EventKind::Moderation,
+];
+
+/// The number of observers and of those which were too slow, shared by the chat server and the routes.
+#[derive(Default)]
+pub(super) struct FirehoseStats {
+    observers: AtomicUsize,
+    slow_disconnects: AtomicU64,
+}
+
+impl FirehoseStats {
+    /// Appends the gauge and counter in the Prometheus text format.
+    pub fn write_metrics(&self, output: &mut String) {
+        writeln!(
+            output,
+            "# HELP axochat_firehose_observers The number of connected firehose observers."
+        )
+        .unwrap();
+        writeln!(output, "# TYPE axochat_firehose_observers gauge").unwrap();
+        writeln!(
+            output,
+            "axochat_firehose_observers {}",
+            self.observers.load(Ordering::Relaxed)
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# HELP axochat_firehose_slow_disconnects_total The number of firehose observers disconnected for not keeping up."
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# TYPE axochat_firehose_slow_disconnects_total counter"
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "axochat_firehose_slow_disconnects_total {}",
+            self.slow_disconnects.load(Ordering::Relaxed)
+        )
+        .unwrap();
+    }
+}
+
+pub(super) struct ObserverState {
+    addr: Recipient<FirehoseEvent>,
+    close: Recipient<Close>,
+    kinds: BTreeSet<EventKind>,
+    connected_at: Instant,
+    /// Set once the observer was told to close, so it is not told again.
+    closing: Cell<bool>,
+}
+
+/// An observer, as listed by `/api/v1/observers`.
+#[derive(Serialize)]
+pub struct ObserverInfo {
+    pub id: InternalId,
+    pub kinds: BTreeSet<EventKind>,
+    pub connected_secs: u64,
+}
+
+/// An event sent to an observer.
+pub(super) struct FirehoseEvent(ClientPacket);
+
+impl Message for FirehoseEvent {
+    type Result = ();
+}
+
+impl ChatServer {
+    /// Sends the packet built by `packet` to every observer subscribed to `kind`.
+    ///
+    /// The packet is only built if there is such an observer.
+    pub(in crate::chat) fn publish_firehose(
+        &self,
+        kind: EventKind,
+        packet: impl FnOnce() -> ClientPacket,
+    ) {
+        let mut observers = self
+            .observers
+            .iter()
+            .filter(|(_, observer)| observer.kinds.contains(&kind) && !observer.closing.get())
+            .peekable();
+        if observers.peek().is_none() {
+            return;
+        }
+        let packet = packet();
+        for (id, observer) in observers {
+            match observer.addr.try_send(FirehoseEvent(packet.clone())) {
+                Ok(()) => {}
+                Err(SendError::Full(_)) => {
+                    info!("Observer `{}` does not keep up with the firehose.", id);
+                    observer.closing.set(true);
+                    self.firehose
+                        .slow_disconnects
+                        .fetch_add(1, Ordering::Relaxed);
+                    observer
+                        .close
+                        .do_send(Close(DisconnectReason::SlowConsumer))
+                        .ok();
+                }
+                // It is removed once its `ObserverDisconnect` arrives.
+                Err(SendError::Closed(_)) => {}
+            }
+        }
+    }
+
+    pub(in crate::chat) fn observers(&self) -> Vec<ObserverInfo> {
+        let now = Instant::now();
+        let mut observers: Vec<_> = self
+            .observers
+            .iter()
+            .map(|(id, observer)| ObserverInfo {
+                id: *id,
+                kinds: observer.kinds.clone(),
+                connected_secs: now.duration_since(observer.connected_at).as_secs(),
+            })
+            .collect();
+        observers.sort_by_key(|observer| observer.id);
+        observers
+    }
+}
+
+#[derive(Message)]
+#[rtype(InternalId)]
+struct ObserverConnect {
+    addr: Recipient<FirehoseEvent>,
+    close: Recipient<Close>,
+}
+
+impl Handler<ObserverConnect> for ChatServer {
+    type Result = InternalId;
+
+    fn handle(&mut self, msg: ObserverConnect, _ctx: &mut Context<Self>) -> InternalId {
+        let id = self.next_internal_id(Origin::Observer);
+        self.observers.insert(
+            id,
+            ObserverState {
+                addr: msg.addr,
+                close: msg.close,
+                kinds: ALL_KINDS.iter().copied().collect(),
+                connected_at: Instant::now(),
+                closing: Cell::new(false),
+            },
+        );
+        self.firehose
+            .observers
+            .store(self.observers.len(), Ordering::Relaxed);
+        info!("Observer `{}` connected to the firehose.", id);
+        id
+    }
+}
+
+#[derive(Message)]
+struct ObserverDisconnect {
+    id: InternalId,
+}
+
+impl Handler<ObserverDisconnect> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ObserverDisconnect, _ctx: &mut Context<Self>) {
+        if self.observers.remove(&msg.id).is_some() {
+            self.firehose
+                .observers
+                .store(self.observers.len(), Ordering::Relaxed);
+            info!("Observer `{}` disconnected from the firehose.", msg.id);
+        }
+    }
+}
+
+#[derive(Message)]
+struct ObserverSubscribe {
+    id: InternalId,
+    kinds: BTreeSet<EventKind>,
+}
+
+impl Handler<ObserverSubscribe> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ObserverSubscribe, _ctx: &mut Context<Self>) {
+        if let Some(observer) = self.observers.get_mut(&msg.id) {
+            debug!("Observer `{}` subscribed to {:?}.", msg.id, msg.kinds);
+            observer.kinds = msg.kinds;
+        }
+    }
+}
+
+/// The only packet an observer may send.
+#[derive(Deserialize)]
+#[serde(tag = "m", content = "c")]
+enum ObserverPacket {
+    /// Replaces the kinds of events the observer receives; initially it receives all of them.
+    Subscribe { kinds: BTreeSet<EventKind> },
+}
+
+/// The websocket connection of an observer.
+pub(super) struct Observer {
+    id: InternalId,
+    addr: Addr<ChatServer>,
+}
+
+impl Observer {
+    pub fn new(addr: Addr<ChatServer>) -> Observer {
+        Observer {
+            id: InternalId::new(0, Origin::Observer, 0),
+            addr,
+        }
+    }
+
+    fn close(&mut self, reason: DisconnectReason, ctx: &mut ws::WebsocketContext<Self>) {
+        info!("Closing observer `{}`: {}", self.id, reason);
+        ctx.close(Some(reason.into()));
+        ctx.stop();
+    }
+}
+
+impl Actor for Observer {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.set_mailbox_capacity(MAX_QUEUED_EVENTS);
+        self.addr
+            .send(ObserverConnect {
+                addr: ctx.address().recipient(),
+                close: ctx.address().recipient(),
+            })
+            .into_actor(self)
+            .then(|res, actor, ctx| {
+                match res {
+                    Ok(id) => actor.id = id,
+                    Err(err) => {
+                        warn!("Could not accept observer: {}", err);
+                        actor.close(DisconnectReason::Internal, ctx);
+                    }
+                }
+                fut::ok(())
+            })
+            .wait(ctx);
+    }
+
+    fn stopping(&mut self, _ctx: &mut Self::Context) -> Running {
+        self.addr.do_send(ObserverDisconnect { id: self.id });
+        Running::Stop
+    }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for Observer {
+    fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
+        match msg {
+            ws::Message::Ping(msg) => ctx.pong(&msg),
+            ws::Message::Pong(_) | ws::Message::Nop => {}
+            ws::Message::Text(ref msg) if msg.len() > MAX_REQUEST_SIZE => {
+                self.close(DisconnectReason::FrameTooLarge, ctx)
+            }
+            ws::Message::Text(msg) => match serde_json::from_str(&msg) {
+                Ok(ObserverPacket::Subscribe { kinds }) => {
+                    self.addr.do_send(ObserverSubscribe { id: self.id, kinds })
+                }
+                Err(err) => {
+                    debug!("Observer `{}` sent an invalid packet: {}", self.id, err);
+                    self.close(DisconnectReason::ProtocolError, ctx);
+                }
+            },
+            ws::Message::Binary(_) => self.close(DisconnectReason::ProtocolError, ctx),
+            ws::Message::Close(_) => self.close(DisconnectReason::ClientClosed, ctx),
+        }
+    }
+
+    fn error(&mut self, err: ws::ProtocolError, _ctx: &mut Self::Context) -> Running {
+        warn!("Websocket error on observer `{}`: {}", self.id, err);
+        Running::Stop
+    }
+}
+
+impl Handler<FirehoseEvent> for Observer {
+    type Result = ();
+
+    fn handle(&mut self, msg: FirehoseEvent, ctx: &mut Self::Context) {
+        let msg = serde_json::to_string(&msg.0).expect("could not encode message");
+        ctx.text(msg);
+    }
+}
+
+impl Handler<Close> for Observer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Close, ctx: &mut Self::Context) {
+        self.close(msg.0, ctx);
+    }
+}
+
+/// Starts the firehose websocket of an authorized observer.
+pub(super) fn start(
+    srv: &Addr<ChatServer>,
+    req: &actix_web::HttpRequest,
+    stream: actix_web::web::Payload,
+) -> actix_web::Result<actix_web::HttpResponse> {
+    ws::start(Observer::new(srv.clone()), req, stream)
+}
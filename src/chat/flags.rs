@@ -0,0 +1,126 @@
+//! Flags of `Message` and `PrivateMessage` packets, which tell clients how to show a message.
+//!
+//! Flags are sent as a list of names in `flags`, which is left out if none is set.
+//! Clients ignore the names they do not know, so flags can be added without a new version of the protocol.
+//! Every flag is listed in [`NAMES`] and in the protocol documentation.
+
+use super::{handler::PrivateBody, history::Reply, AuthorKind, ChatServer};
+use crate::auth::UserInfo;
+use crate::message::ValidatedContent;
+
+use serde::{ser::SerializeSeq, Serialize, Serializer};
+
+/// A set of flags of a message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessageFlags(u32);
+
+/// The names of all flags and their bits.
+pub(super) const NAMES: &[(&str, MessageFlags)] = &[
+    ("own", MessageFlags::OWN),
+    ("moderator", MessageFlags::MODERATOR),
+    ("bridged", MessageFlags::BRIDGED),
+    ("redacted", MessageFlags::REDACTED),
+    ("reply_missing", MessageFlags::REPLY_MISSING),
+    ("encrypted", MessageFlags::ENCRYPTED),
+];
+
+impl MessageFlags {
+    pub const NONE: MessageFlags = MessageFlags(0);
+    /// The message was sent by the user receiving it, from this or another connection.
+    pub const OWN: MessageFlags = MessageFlags(1);
+    /// The author is a moderator.
+    pub const MODERATOR: MessageFlags = MessageFlags(1 << 1);
+    /// A bot relayed the message from another chat, see `author_kind`.
+    pub const BRIDGED: MessageFlags = MessageFlags(1 << 2);
+    /// Blocked words of the message were replaced with asterisks.
+    pub const REDACTED: MessageFlags = MessageFlags(1 << 3);
+    /// The message replies to one the server did not know anymore, or `reply_excerpt` was left out
+    /// to keep the packet below `server.max_outgoing_frame_bytes`.
+    pub const REPLY_MISSING: MessageFlags = MessageFlags(1 << 4);
+    /// The private message is end-to-end encrypted and carries `payload` instead of `content`.
+    pub const ENCRYPTED: MessageFlags = MessageFlags(1 << 5);
+
+    /// Returns whether all flags of `other` are in `self`.
+    pub fn contains(self, other: MessageFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Adds all flags of `other` to `self`.
+    pub fn insert(&mut self, other: MessageFlags) {
+        self.0 |= other.0;
+    }
+
+    /// Returns `self` with all flags of `other` added.
+    pub fn with(mut self, other: MessageFlags) -> MessageFlags {
+        self.insert(other);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the names of the set flags.
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        NAMES
+            .iter()
+            .filter(move |(_, flag)| self.contains(*flag))
+            .map(|(name, _)| *name)
+    }
+}
+
+impl Serialize for MessageFlags {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        for name in self.names() {
+            seq.serialize_element(name)?;
+        }
+        seq.end()
+    }
+}
+
+impl ChatServer {
+    /// The flags of a broadcast message, which are the same for every receiver except [`MessageFlags::OWN`].
+    pub(super) fn message_flags(
+        &self,
+        author_info: &UserInfo,
+        author_kind: &AuthorKind,
+        content: &ValidatedContent,
+        reply: Option<&Reply>,
+    ) -> MessageFlags {
+        let mut flags = self.author_flags(author_info, content.is_redacted());
+        if let AuthorKind::Bridge { .. } = author_kind {
+            flags.insert(MessageFlags::BRIDGED);
+        }
+        if reply.is_some_and(|reply| reply.reply_excerpt.is_none()) {
+            flags.insert(MessageFlags::REPLY_MISSING);
+        }
+        flags
+    }
+
+    /// The flags of a private message, which are the same for the receiver and the echoes to the author
+    /// except [`MessageFlags::OWN`].
+    pub(super) fn private_message_flags(
+        &self,
+        author_info: &UserInfo,
+        body: &PrivateBody,
+    ) -> MessageFlags {
+        match body {
+            PrivateBody::Plain(content) => self.author_flags(author_info, content.is_redacted()),
+            PrivateBody::Encrypted(_) => self
+                .author_flags(author_info, false)
+                .with(MessageFlags::ENCRYPTED),
+        }
+    }
+
+    fn author_flags(&self, author_info: &UserInfo, redacted: bool) -> MessageFlags {
+        let mut flags = MessageFlags::NONE;
+        if self.is_moderator(&author_info.uuid) {
+            flags.insert(MessageFlags::MODERATOR);
+        }
+        if redacted {
+            flags.insert(MessageFlags::REDACTED);
+        }
+        flags
+    }
+}
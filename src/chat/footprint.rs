@@ -0,0 +1,160 @@
+//! An approximation of the memory kept for each session, to plan `server.max_connections` with.
+//!
+//! Every structure growing with the packets of a client is capped:
+//! - the users a connection waits for by `MAX_WATCHES`, and their names by `MAX_NAME_BYTES`,
+//! - the recent messages of the rate limiter, which also detects duplicates,
+//!   by `message.max_messages` and `bots.max_messages`,
+//! - the recent reactions by `reactions.max_reactions`,
+//! - the connections of a user by `server.max_sessions_per_user`,
+//! - and the packets queued by the connection by `MAX_QUEUED_PACKETS`.
+//!
+//! The sizes are estimates: allocator overhead and the buckets of hash sets are not counted exactly.
+
+use super::{
+    handler::MAX_WATCHES, id::MAX_NAME_BYTES, outgoing::MAX_QUEUED_PACKETS, CanonicalId,
+    ChatServer, ClientPacket, InternalId, SessionState, UserSession,
+};
+use crate::config::Config;
+use log::*;
+
+use serde::Serialize;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::mem;
+use std::time::Instant;
+
+/// The length of the hex encoded SHA-1 of a session hash.
+const SESSION_HASH_LENGTH: usize = 40;
+
+/// The length of a hex encoded resume token.
+const RESUME_TOKEN_LENGTH: usize = 64;
+
+/// The memory kept for a connection, as listed by `/api/v1/debug/sessions`.
+#[derive(Serialize)]
+pub struct SessionFootprint {
+    pub id: InternalId,
+    /// The random tag of the connection, which is logged together with `id`.
+    pub session_tag: String,
+    /// The name the connection is logged in with.
+    pub user: Option<String>,
+    /// The bytes kept for the connection itself.
+    pub session_bytes: usize,
+    /// The bytes kept for its user, which are shared by all of their connections.
+    pub user_bytes: usize,
+}
+
+impl SessionState {
+    /// The approximate number of bytes the chat server keeps for this connection,
+    /// without its [`UserSession`] and the packets queued by the connection.
+    pub fn memory_footprint(&self) -> usize {
+        mem::size_of::<SessionState>()
+            + self
+                .session_hash
+                .as_ref()
+                .map_or(0, |(hash, _)| hash.capacity())
+            + self
+                .user
+                .as_ref()
+                .map_or(0, |user| user.name.as_str().len())
+            + self.resume_token.as_ref().map_or(0, String::capacity)
+            + set_footprint(&self.watching)
+            + self
+                .watching
+                .iter()
+                .map(|id| id.as_str().len())
+                .sum::<usize>()
+    }
+}
+
+impl UserSession {
+    /// The approximate number of bytes the chat server keeps for a logged in user.
+    pub fn memory_footprint(&self) -> usize {
+        mem::size_of::<UserSession>()
+            + self.rate_limiter.memory_footprint()
+            + self.reaction_times.capacity() * mem::size_of::<Instant>()
+            + set_footprint(&self.connections)
+    }
+}
+
+fn set_footprint<T: Eq + Hash>(set: &HashSet<T>) -> usize {
+    // Every bucket has a control byte besides the value.
+    set.capacity() * (mem::size_of::<T>() + 1)
+}
+
+/// The most memory a single session can take with `config`, including a user of its own
+/// and a full queue of outgoing packets.
+pub(super) fn max_session_footprint(config: &Config) -> usize {
+    let max_content = if config.validation.dry_run {
+        // Messages are delivered even if they are too long.
+        config.server.max_packet_size
+    } else {
+        config
+            .validation
+            .max_bytes
+            .max(config.message.max_encrypted_size)
+    };
+
+    let session = mem::size_of::<SessionState>()
+        + SESSION_HASH_LENGTH
+        + MAX_NAME_BYTES
+        + RESUME_TOKEN_LENGTH
+        + MAX_WATCHES * (mem::size_of::<CanonicalId>() + 1 + MAX_NAME_BYTES);
+
+    let max_messages = config.message.max_messages.max(config.bots.max_messages);
+    let user = mem::size_of::<UserSession>()
+        + max_messages * (mem::size_of::<(Instant, String)>() + max_content)
+        + config.reactions.max_reactions * mem::size_of::<Instant>()
+        + config.server.max_sessions_per_user * (mem::size_of::<InternalId>() + 1);
+
+    // A packet carries at most a message, its author and an excerpt of the message it replies to.
+    let max_content = max_content.max(config.message.max_system_length * 4);
+    let queue =
+        MAX_QUEUED_PACKETS * (mem::size_of::<ClientPacket>() + max_content + 2 * MAX_NAME_BYTES);
+
+    session + user + queue
+}
+
+/// Logs how much memory the sessions can take at most, so `server.max_connections` can be planned.
+pub(super) fn log_memory_ceiling(config: &Config) {
+    let footprint = max_session_footprint(config);
+    match config.server.max_connections {
+        Some(max_connections) => info!(
+            "A session takes at most about {} KiB, so {} connections take at most about {} MiB.",
+            footprint / 1024,
+            max_connections,
+            footprint.saturating_mul(max_connections) / (1024 * 1024)
+        ),
+        None => warn!(
+            "A session takes at most about {} KiB, and `server.max_connections` is not set, \
+             so the memory taken by sessions is not bounded.",
+            footprint / 1024
+        ),
+    }
+}
+
+impl ChatServer {
+    /// The `limit` connections for which the most memory is kept, heaviest first.
+    pub(super) fn heaviest_sessions(&self, limit: usize) -> Vec<SessionFootprint> {
+        let mut sessions: Vec<_> = self
+            .sessions
+            .iter()
+            .map(|(id, session)| {
+                let user = session.user.as_ref();
+                SessionFootprint {
+                    id: *id,
+                    session_tag: id.session_tag(),
+                    user: user.map(|user| user.name.to_string()),
+                    session_bytes: session.memory_footprint(),
+                    user_bytes: user
+                        .and_then(|user| self.sessions.user(&user.name.canonical()))
+                        .map_or(0, UserSession::memory_footprint),
+                }
+            })
+            .collect();
+        sessions.sort_by_key(|session| {
+            std::cmp::Reverse((session.session_bytes + session.user_bytes, session.id))
+        });
+        sessions.truncate(limit);
+        sessions
+    }
+}
@@ -0,0 +1,185 @@
+//! Counting how far connections get on their way to logging in, and why they close.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The maximum number of recent transitions kept for [`Funnel::summary`].
+const MAX_RECENT_TRANSITIONS: usize = 100_000;
+
+/// How long transitions are kept for [`Funnel::summary`].
+const MAX_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A stage a connection reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Stage {
+    /// The websocket connection was accepted.
+    Connected,
+    /// The client sent an accepted `Hello`.
+    Hello,
+    /// The client requested the information for a login with Mojang.
+    MojangInfo,
+    /// The client logged in or resumed a session.
+    LoggedIn,
+    /// The connection closed before it logged in.
+    DisconnectedBeforeLogin,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [
+        Stage::Connected,
+        Stage::Hello,
+        Stage::MojangInfo,
+        Stage::LoggedIn,
+        Stage::DisconnectedBeforeLogin,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Stage::Connected => "connected",
+            Stage::Hello => "hello",
+            Stage::MojangInfo => "mojang_info",
+            Stage::LoggedIn => "logged_in",
+            Stage::DisconnectedBeforeLogin => "disconnected_before_login",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Transition {
+    Stage(Stage),
+    Disconnect(&'static str),
+}
+
+/// The transitions of all connections, shared by the chat server and the routes.
+#[derive(Default)]
+pub(super) struct Funnel {
+    state: Mutex<FunnelState>,
+}
+
+#[derive(Default)]
+struct FunnelState {
+    /// The number of connections which reached each stage, in the order of [`Stage::ALL`].
+    stages: [u64; 5],
+    /// The number of closed connections by reason.
+    disconnects: BTreeMap<&'static str, u64>,
+    /// The most recent transitions and when they happened, oldest first.
+    recent: VecDeque<(Instant, Transition)>,
+}
+
+/// The transitions within a window, as returned by the admin API.
+#[derive(Serialize)]
+pub(super) struct FunnelSummary {
+    /// The length of the window in seconds.
+    window: u64,
+    /// Whether transitions in the window were dropped because too many happened.
+    truncated: bool,
+    stages: BTreeMap<&'static str, u64>,
+    disconnects: BTreeMap<&'static str, u64>,
+}
+
+impl Funnel {
+    pub fn record_stage(&self, stage: Stage) {
+        let mut state = self.state.lock().unwrap();
+        state.stages[stage as usize] += 1;
+        state.push(Transition::Stage(stage));
+    }
+
+    /// Records a closed connection; `reason` is the label of its [`DisconnectReason`](super::close::DisconnectReason).
+    pub fn record_disconnect(&self, reason: &'static str) {
+        let mut state = self.state.lock().unwrap();
+        *state.disconnects.entry(reason).or_insert(0) += 1;
+        state.push(Transition::Disconnect(reason));
+    }
+
+    /// Aggregates the transitions of the last `window`, which is at most [`MAX_WINDOW`].
+    pub fn summary(&self, window: Duration) -> FunnelSummary {
+        let window = window.min(MAX_WINDOW);
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.expire(now);
+
+        let mut summary = FunnelSummary {
+            window: window.as_secs(),
+            truncated: state.recent.len() >= MAX_RECENT_TRANSITIONS
+                && state
+                    .recent
+                    .front()
+                    .is_some_and(|(time, _)| now.duration_since(*time) < window),
+            stages: Stage::ALL.iter().map(|stage| (stage.as_str(), 0)).collect(),
+            disconnects: BTreeMap::new(),
+        };
+        for (_, transition) in state
+            .recent
+            .iter()
+            .rev()
+            .take_while(|(time, _)| now.duration_since(*time) <= window)
+        {
+            let count = match transition {
+                Transition::Stage(stage) => summary.stages.entry(stage.as_str()),
+                Transition::Disconnect(reason) => summary.disconnects.entry(reason),
+            };
+            *count.or_insert(0) += 1;
+        }
+        summary
+    }
+
+    /// Appends the counters in the Prometheus text format.
+    pub fn write_metrics(&self, output: &mut String) {
+        let state = self.state.lock().unwrap();
+        writeln!(
+            output,
+            "# HELP axochat_connection_stage_total The number of connections which reached each stage of the login."
+        )
+        .unwrap();
+        writeln!(output, "# TYPE axochat_connection_stage_total counter").unwrap();
+        for (stage, count) in Stage::ALL.iter().zip(&state.stages) {
+            writeln!(
+                output,
+                "axochat_connection_stage_total{{stage=\"{}\"}} {}",
+                stage.as_str(),
+                count
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            output,
+            "# HELP axochat_disconnects_total The number of closed connections by reason."
+        )
+        .unwrap();
+        writeln!(output, "# TYPE axochat_disconnects_total counter").unwrap();
+        for (reason, count) in &state.disconnects {
+            writeln!(
+                output,
+                "axochat_disconnects_total{{reason=\"{}\"}} {}",
+                reason, count
+            )
+            .unwrap();
+        }
+    }
+}
+
+impl FunnelState {
+    fn push(&mut self, transition: Transition) {
+        let now = Instant::now();
+        self.expire(now);
+        if self.recent.len() >= MAX_RECENT_TRANSITIONS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((now, transition));
+    }
+
+    /// Drops the transitions older than [`MAX_WINDOW`].
+    fn expire(&mut self, now: Instant) {
+        while self
+            .recent
+            .front()
+            .is_some_and(|(time, _)| now.duration_since(*time) > MAX_WINDOW)
+        {
+            self.recent.pop_front();
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Connections which watch the chat without logging in, like web widgets.
+//!
+//! Guests receive the broadcasts in `auth.guest_events`, but are rejected with `NotLoggedIn`
+//! whenever they send something, until they log in on the same connection.
+//! If guests are not allowed, connections receive no broadcasts until they logged in,
+//! and are closed if they did not log in within `auth.login_timeout`.
+//! Either way, if `auth.max_guests_per_ip` is set, new connections are rejected while that many connections
+//! of their address did not log in.
+
+use super::{
+    close::{Close, DisconnectReason},
+    ChatServer, InternalId, SessionState,
+};
+use crate::config::GuestEvent;
+use log::*;
+
+use actix::*;
+use std::net::IpAddr;
+
+impl ChatServer {
+    /// Whether `session` receives the broadcasts of `event`.
+    pub(in crate::chat) fn receives(&self, session: &SessionState, event: GuestEvent) -> bool {
+        session.is_logged_in()
+            || (self.guests.allow_guests && self.guests.guest_events.contains(&event))
+    }
+
+    /// Whether a new connection from `ip` would exceed `auth.max_guests_per_ip`.
+    pub(super) fn has_too_many_guests(&self, ip: IpAddr) -> bool {
+        let max = self.guests.max_guests_per_ip;
+        max != 0
+            && self
+                .sessions
+                .values()
+                .filter(|session| session.ip == Some(ip) && !session.is_logged_in())
+                .count()
+                >= max
+    }
+
+    /// Closes the new connection `id` after `auth.login_timeout` if it did not log in,
+    /// unless guests are allowed.
+    pub(super) fn start_login_timeout(&self, id: InternalId, ctx: &mut Context<Self>) {
+        if self.guests.allow_guests {
+            return;
+        }
+        ctx.run_later(*self.guests.login_timeout, move |actor, _ctx| {
+            if let Some(session) = actor.sessions.get(&id) {
+                if !session.is_logged_in() {
+                    info!("`{}` did not log in in time.", id);
+                    session
+                        .close
+                        .do_send(Close(DisconnectReason::LoginTimeout))
+                        .ok();
+                }
+            }
+        });
+    }
+
+    /// Publishes the number of connections which did not log in for the metrics.
+    pub(in crate::chat) fn update_guest_count(&self) {
+        self.connection_limit
+            .set_guests(self.sessions.guest_count());
+    }
+}
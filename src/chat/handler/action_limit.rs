@@ -0,0 +1,43 @@
+use super::ChatServer;
+
+use uuid::Uuid;
+
+/// The moderation actions which are limited separately, see `moderation.max_ban_actions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(in crate::chat) enum ActionClass {
+    /// Banning, unbanning, muting, unmuting or kicking a user.
+    Ban,
+    /// Blocking or unblocking a word.
+    BlockedWord,
+}
+
+impl ChatServer {
+    /// Returns whether the moderator `actor` took too many actions of `class`
+    /// within `moderation.action_count_duration`. If not, the action is counted.
+    ///
+    /// The actions are counted per moderator, so opening more sessions does not help.
+    pub(super) fn check_action_rate(&mut self, actor: Uuid, class: ActionClass) -> bool {
+        let now = self.now();
+        let cfg = &self.config.moderation;
+        let max = match class {
+            ActionClass::Ban => cfg.max_ban_actions,
+            ActionClass::BlockedWord => cfg.max_word_actions,
+        };
+        if max == 0 {
+            return false;
+        }
+        let times = self.moderator_actions.entry((actor, class)).or_default();
+        while times
+            .front()
+            .is_some_and(|time| now.duration_since(*time) >= *cfg.action_count_duration)
+        {
+            times.pop_front();
+        }
+        if times.len() >= max {
+            true
+        } else {
+            times.push_back(now);
+            false
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use log::*;
+
+use super::{ChatServer, ClientPacket};
+use crate::chat::Capabilities;
+use crate::config::{AnnouncementSchedule, GuestEvent};
+use serde::Serialize;
+use uuid::Uuid;
+
+use actix::*;
+use std::time::UNIX_EPOCH;
+
+/// What a system message is about.
+#[derive(Serialize, Clone, Copy, Debug)]
+pub(in crate::chat) enum SystemMessageKind {
+    Ban,
+    /// One of the configured `announcements`.
+    Announcement,
+}
+
+impl ChatServer {
+    /// Schedules the configured `announcements`.
+    pub(in crate::chat) fn start_announcements(&mut self, ctx: &mut Context<Self>) {
+        for index in 0..self.config.announcements.len() {
+            match self.config.announcements[index].schedule {
+                AnnouncementSchedule::Interval(interval) => {
+                    ctx.run_interval(interval, move |actor, _ctx| actor.announce(index));
+                }
+                AnnouncementSchedule::Daily { .. } => self.schedule_announcement(index, ctx),
+            }
+        }
+    }
+
+    /// Waits for the next time of a daily announcement.
+    fn schedule_announcement(&mut self, index: usize, ctx: &mut Context<Self>) {
+        let now = self
+            .system_now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let wait = self.config.announcements[index].schedule.next_after(now);
+        ctx.run_later(wait, move |actor, ctx| {
+            actor.announce(index);
+            actor.schedule_announcement(index, ctx);
+        });
+    }
+
+    /// Sends an announcement, unless fewer than its `min_users` are logged in.
+    fn announce(&self, index: usize) {
+        let announcement = &self.config.announcements[index];
+        if (self.sessions.user_count() as u32) < announcement.min_users {
+            debug!(
+                "Skipping announcement {}, only {} users are logged in.",
+                index,
+                self.sessions.user_count()
+            );
+            return;
+        }
+        self.broadcast_system_message(
+            SystemMessageKind::Announcement,
+            announcement.content.clone(),
+        );
+    }
+
+    /// Sends a message from the server itself to every client supporting system messages.
+    pub(in crate::chat) fn broadcast_system_message(
+        &self,
+        kind: SystemMessageKind,
+        content: String,
+    ) {
+        let packet = ClientPacket::SystemMessage { content, kind };
+        for session in self.sessions_with(Capabilities::SYSTEM_MESSAGES) {
+            if !self.receives(session, GuestEvent::Announcements) {
+                continue;
+            }
+            if let Err(err) = session.addr.do_send(packet.clone()) {
+                warn!("Could not send system message to client: {}", err);
+            }
+        }
+    }
+
+    /// Announces that `uuid` was banned, if `moderation.announce_actions` is enabled.
+    ///
+    /// This has to be called before the connections of the user are closed,
+    /// since the name of the user is looked up in them.
+    pub(in crate::chat) fn announce_ban(&self, uuid: &Uuid) {
+        if !self.config.moderation.announce_actions {
+            return;
+        }
+
+        let name = self
+            .sessions
+            .values()
+            .filter_map(|session| session.user.as_ref())
+            .find(|info| info.uuid == *uuid)
+            .map(|info| info.name.to_string())
+            .unwrap_or_else(|| uuid.to_hyphenated().to_string());
+        let content = self
+            .config
+            .moderation
+            .ban_announcement
+            .replace("{name}", &name);
+        self.broadcast_system_message(SystemMessageKind::Ban, content);
+    }
+}
@@ -1,78 +1,306 @@
-use super::{ChatServer, ClientPacket};
-use crate::chat::{InternalId, SuccessReason};
+use super::{ActionClass, ChatServer, ClientPacket};
+use crate::chat::{
+    close::{Close, DisconnectReason},
+    cluster::ClusterEvent,
+    InternalId, SuccessReason,
+};
 
 use crate::error::*;
+use crate::moderation::Restriction;
+use crate::storage::AuditAction;
 use log::*;
+use std::time::Duration;
 use uuid::Uuid;
 
 impl ChatServer {
+    /// Bans from `BanUser` are permanent, so they have to be confirmed first.
     pub(super) fn ban_user(&mut self, user_id: InternalId, to_ban: &Uuid) {
-        self.handle_user(user_id, to_ban, true);
+        let summary = format!("ban `{}` permanently", to_ban);
+        if let Err(message) = self.request_ban_confirmation(user_id, to_ban, None, summary) {
+            self.send_error(user_id, message);
+        }
     }
 
     pub(super) fn unban_user(&mut self, user_id: InternalId, to_unban: &Uuid) {
-        self.handle_user(user_id, to_unban, false);
+        let packet = match self.moderate_user(user_id, to_unban, false, None, None) {
+            Ok(reason) => ClientPacket::Success { reason },
+            Err(message) => ClientPacket::Error { message },
+        };
+
+        self.reply(user_id, packet);
     }
 
-    fn handle_user(&mut self, user_id: InternalId, receiver: &Uuid, ban: bool) {
-        let session = self
-            .connections
-            .get(&user_id)
-            .expect("could not find connection");
-        if let Some(info) = &session.user {
-            if !self.moderation.is_moderator(&info.uuid) {
-                info!("`{}` tried to (un-)ban user without permission", user_id);
-                session
-                    .addr
-                    .do_send(ClientPacket::Error {
-                        message: ClientError::NotPermitted,
-                    })
-                    .ok();
-                return;
+    /// Returns the uuid of `user_id` if it is a logged in moderator
+    /// who did not take too many moderation actions recently.
+    pub(super) fn check_moderation_action(
+        &mut self,
+        user_id: InternalId,
+    ) -> std::result::Result<Uuid, ClientError> {
+        let actor = match self.sessions.get(&user_id).and_then(|s| s.user.as_ref()) {
+            Some(info) => info.uuid,
+            None => {
+                info!("`{}` is not logged in.", user_id);
+                return Err(ClientError::NotLoggedIn);
             }
+        };
+        if !self.is_moderator(&actor) {
+            info!("`{}` tried to moderate a user without permission", user_id);
+            return Err(ClientError::NotPermitted);
+        }
+        if self.check_action_rate(actor, ActionClass::Ban) {
+            info!("`{}` moderated too many users recently", user_id);
+            return Err(ClientError::RateLimited);
+        }
+        Ok(actor)
+    }
 
-            let res = if ban {
-                self.moderation.ban(receiver)
-            } else {
-                self.moderation.unban(receiver)
-            };
-            match res {
-                Ok(()) => {
-                    let reason = if ban {
-                        info!("User `{}` banned.", receiver);
-                        SuccessReason::Ban
-                    } else {
-                        info!("User `{}` unbanned.", receiver);
-                        SuccessReason::Unban
-                    };
-                    let _ = session.addr.do_send(ClientPacket::Success { reason });
-                }
-                Err(Error::AxoChat { source }) => {
-                    info!("Could not (un-)ban user `{}`: {}", receiver, source);
-                    session
-                        .addr
-                        .do_send(ClientPacket::Error { message: source })
-                        .ok();
+    /// (Un-)bans `receiver` if `user_id` is a logged in moderator
+    /// and records it with `reason` in the audit log.
+    /// A ban with a `duration` ends after it, otherwise it is permanent.
+    ///
+    /// If the ban could not be saved, `user_id` is also sent a `PersistenceDegraded` error.
+    pub(super) fn moderate_user(
+        &mut self,
+        user_id: InternalId,
+        receiver: &Uuid,
+        ban: bool,
+        reason: Option<String>,
+        duration: Option<Duration>,
+    ) -> std::result::Result<SuccessReason, ClientError> {
+        let actor = self.check_moderation_action(user_id)?;
+        self.apply_moderation(user_id, actor, receiver, ban, reason, duration)
+    }
+
+    /// (Un-)bans `receiver` for the moderator `actor`, whose permission was already checked.
+    pub(super) fn apply_moderation(
+        &mut self,
+        user_id: InternalId,
+        actor: Uuid,
+        receiver: &Uuid,
+        ban: bool,
+        reason: Option<String>,
+        duration: Option<Duration>,
+    ) -> std::result::Result<SuccessReason, ClientError> {
+        let restriction = Restriction::new(reason.clone(), duration, self.system_now());
+        let res = if ban {
+            self.moderation.ban(receiver, restriction.clone())
+        } else {
+            self.moderation.unban(receiver)
+        };
+        match res {
+            Ok(()) => {
+                let action = if ban {
+                    AuditAction::Ban
+                } else {
+                    AuditAction::Unban
+                };
+                let duration_secs = duration.map(|duration| duration.as_secs());
+                self.record_audit(Some(actor), action, *receiver, reason, duration_secs);
+                if !self.moderation_persisted() {
+                    self.send_error(user_id, ClientError::PersistenceDegraded);
                 }
-                Err(err) => {
-                    info!("Could not (un-)ban user `{}`: {}", receiver, err);
-                    session
-                        .addr
-                        .do_send(ClientPacket::Error {
-                            message: ClientError::Internal,
-                        })
-                        .ok();
+                self.publish(ClusterEvent::Moderation {
+                    user: *receiver,
+                    ban,
+                    reason: restriction.reason,
+                    expires_at: restriction.expires_at,
+                });
+                if ban {
+                    info!("User `{}` banned.", receiver);
+                    self.announce_ban(receiver);
+                    self.remove_banned(receiver);
+                    Ok(SuccessReason::Ban)
+                } else {
+                    info!("User `{}` unbanned.", receiver);
+                    self.send_moderation_status(receiver);
+                    Ok(SuccessReason::Unban)
                 }
             }
-        } else {
-            info!("`{}` is not logged in.", user_id);
-            session
-                .addr
-                .do_send(ClientPacket::Error {
-                    message: ClientError::NotLoggedIn,
-                })
-                .ok();
+            Err(Error::AxoChat { source }) => {
+                info!("Could not (un-)ban user `{}`: {}", receiver, source);
+                Err(source)
+            }
+            Err(err) => {
+                info!("Could not (un-)ban user `{}`: {}", receiver, err);
+                Err(ClientError::Internal)
+            }
+        }
+    }
+
+    /// Mutes `receiver` for `duration`, or unmutes them if it is `None`,
+    /// if `user_id` is a logged in moderator, and records it with `reason` in the audit log.
+    pub(super) fn mute_user(
+        &mut self,
+        user_id: InternalId,
+        receiver: &Uuid,
+        duration: Option<Duration>,
+        reason: Option<String>,
+    ) -> std::result::Result<(), ClientError> {
+        let actor = self.check_moderation_action(user_id)?;
+
+        let expires_at = duration
+            .map(|duration| Restriction::new(None, Some(duration), self.system_now()))
+            .and_then(|mute| mute.expires_at);
+        let res = match expires_at {
+            Some(expires_at) => self.moderation.mute(receiver, reason.clone(), expires_at),
+            None => self.moderation.unmute(receiver),
+        };
+        match res {
+            Ok(()) => {
+                let action = match duration {
+                    Some(_) => AuditAction::Mute,
+                    None => AuditAction::Unmute,
+                };
+                let duration_secs = duration.map(|duration| duration.as_secs());
+                self.record_audit(
+                    Some(actor),
+                    action,
+                    *receiver,
+                    reason.clone(),
+                    duration_secs,
+                );
+                self.publish(ClusterEvent::Mute {
+                    user: *receiver,
+                    reason,
+                    expires_at,
+                });
+                info!("User `{}` (un-)muted.", receiver);
+                self.send_moderation_status(receiver);
+                Ok(())
+            }
+            Err(Error::AxoChat { source }) => {
+                info!("Could not (un-)mute user `{}`: {}", receiver, source);
+                Err(source)
+            }
+            Err(err) => {
+                info!("Could not (un-)mute user `{}`: {}", receiver, err);
+                Err(ClientError::Internal)
+            }
+        }
+    }
+
+    /// Closes the connections of `receiver` on every instance
+    /// if `user_id` is a logged in moderator, and records it with `reason` in the audit log.
+    ///
+    /// Fails with `UserNotFound` if `receiver` is not connected to this instance
+    /// and there are no other instances.
+    pub(super) fn kick_user(
+        &mut self,
+        user_id: InternalId,
+        receiver: &Uuid,
+        reason: Option<String>,
+    ) -> std::result::Result<(), ClientError> {
+        let actor = self.check_moderation_action(user_id)?;
+        if self.is_moderator(receiver) {
+            return Err(ClientError::NotPermitted);
+        }
+        let online = self.sessions.sessions_for_uuid(receiver).next().is_some();
+        if !online && self.cluster.is_none() {
+            return Err(ClientError::UserNotFound);
+        }
+
+        self.record_audit(
+            Some(actor),
+            AuditAction::Kick,
+            *receiver,
+            reason.clone(),
+            None,
+        );
+        self.publish(ClusterEvent::Kick {
+            user: *receiver,
+            reason: reason.clone(),
+        });
+        info!("User `{}` kicked.", receiver);
+        self.remove_kicked(receiver, reason.as_deref());
+        Ok(())
+    }
+
+    /// Closes all connections of a banned user and invalidates their resume tokens.
+    pub(in crate::chat) fn remove_banned(&mut self, uuid: &Uuid) {
+        let reason = self
+            .moderation
+            .ban_of(uuid, self.system_now())
+            .and_then(|ban| ban.reason.clone());
+        self.revoke_resume_tokens(uuid);
+        self.disconnect_user(uuid, DisconnectReason::Banned, reason.as_deref());
+    }
+
+    /// Closes all connections of a kicked user and invalidates their resume tokens,
+    /// so they have to log in again.
+    pub(in crate::chat) fn remove_kicked(&mut self, uuid: &Uuid, reason: Option<&str>) {
+        self.revoke_resume_tokens(uuid);
+        self.disconnect_user(uuid, DisconnectReason::Kicked, reason);
+    }
+
+    /// Removes the bans and mutes which ended, as a maintenance job,
+    /// and tells the connections of the users they restricted.
+    pub(in crate::chat) fn prune_restrictions(&mut self) {
+        let lapsed = self.moderation.prune_expired(self.system_now());
+        if lapsed.is_empty() {
             return;
         }
+        info!("Removed {} bans and mutes which ended.", lapsed.len());
+        self.moderation_persisted();
+        for uuid in &lapsed {
+            self.send_moderation_status(uuid);
+        }
+    }
+
+    /// Returns the `Muted` error if `uuid` is muted.
+    pub(in crate::chat) fn mute_error(&self, uuid: &Uuid) -> Option<ClientError> {
+        let now = self.system_now();
+        let remaining = self.moderation.mute_of(uuid, now)?.remaining(now)?;
+        Some(ClientError::Muted {
+            remaining_secs: remaining.as_secs(),
+        })
+    }
+
+    /// Returns whether `uuid` is banned or muted, with the end and reason of the restriction.
+    ///
+    /// A ban takes precedence over a mute.
+    pub(in crate::chat) fn moderation_status(&self, uuid: &Uuid) -> ClientPacket {
+        let now = self.system_now();
+        let ban = self.moderation.ban_of(uuid, now);
+        let mute = self.moderation.mute_of(uuid, now);
+        let restriction = ban.or(mute);
+        ClientPacket::ModerationStatus {
+            banned: ban.is_some(),
+            muted: mute.is_some(),
+            expires_at: restriction.and_then(|restriction| restriction.expires_at),
+            reason: restriction.and_then(|restriction| restriction.reason.clone()),
+        }
+    }
+
+    /// Tells the connections of `uuid` whether the user is banned or muted,
+    /// after the status changed while they are connected.
+    pub(in crate::chat) fn send_moderation_status(&self, uuid: &Uuid) {
+        let packet = self.moderation_status(uuid);
+        for id in self.sessions.sessions_for_uuid(uuid) {
+            if let Some(session) = self.sessions.get(&id) {
+                session.addr.do_send(packet.clone()).ok();
+            }
+        }
+    }
+
+    /// Sends all connections of the user `uuid` a `Disconnected` packet
+    /// with the `message` of the moderator, and closes them.
+    fn disconnect_user(&self, uuid: &Uuid, reason: DisconnectReason, message: Option<&str>) {
+        let packet = ClientPacket::Disconnected {
+            reason_code: reason.label(),
+            translation_key: reason.translation_key(),
+            retry_after_secs: None,
+            reason: message.map(str::to_string),
+        };
+        for (id, session) in self.sessions.iter() {
+            match &session.user {
+                Some(info) if info.uuid == *uuid => {
+                    self.send_to(*id, session, packet.clone());
+                    if let Err(err) = session.close.do_send(Close(reason)) {
+                        warn!("Could not close connection of `{}`: {}", uuid, err);
+                    }
+                }
+                _ => {}
+            }
+        }
     }
 }
@@ -0,0 +1,43 @@
+use super::ChatServer;
+use crate::config::MsgConfig;
+use uuid::Uuid;
+
+impl ChatServer {
+    /// Returns whether `uuid` is listed in `bots.uuids`.
+    pub(in crate::chat) fn is_bot(&self, uuid: &Uuid) -> bool {
+        self.config.bots.uuids.contains(uuid)
+    }
+
+    /// Returns whether `uuid` may moderate.
+    ///
+    /// Bots never may, even if they are also listed in `moderation.moderators`.
+    pub(in crate::chat) fn is_moderator(&self, uuid: &Uuid) -> bool {
+        !self.is_bot(uuid) && self.moderation.is_moderator(uuid)
+    }
+
+    /// The maximum amount of messages `uuid` may send within the window of its rate limiter.
+    ///
+    /// Bots use `bots.max_messages`; users in probation may send half as many messages
+    /// if `moderation.probation_halve_rate_limit` is enabled.
+    pub(in crate::chat) fn max_messages(&self, uuid: &Uuid) -> usize {
+        if self.is_bot(uuid) {
+            return self.config.bots.max_messages;
+        }
+        let mut max_messages = self.config.message.max_messages;
+        if self.config.moderation.probation_halve_rate_limit
+            && self.probation_remaining(uuid).is_some()
+        {
+            max_messages -= max_messages / 2;
+        }
+        max_messages
+    }
+
+    /// The configuration of the rate limiter of a user logging in as `uuid`.
+    pub(in crate::chat) fn rate_limit_config(&self, uuid: &Uuid) -> MsgConfig {
+        let mut config = self.config.message.clone();
+        if self.is_bot(uuid) {
+            config.count_duration = self.config.bots.count_duration;
+        }
+        config
+    }
+}
@@ -0,0 +1,572 @@
+use super::{ChatServer, ClientPacket};
+use crate::chat::{CanonicalId, InternalId};
+
+use crate::error::*;
+use log::*;
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Clone, Copy, PartialEq)]
+enum CommandKind {
+    Ban,
+    Unban,
+    Mute,
+    Unmute,
+    Kick,
+    Msg,
+    Motd,
+    Help,
+}
+
+struct Command {
+    kind: CommandKind,
+    /// The first name is the primary one, all others are aliases.
+    names: &'static [&'static str],
+    usage: &'static str,
+    description: &'static str,
+    /// Whether only moderators can use this command.
+    /// This is only used to decide which commands to list;
+    /// permissions are checked by the action itself.
+    moderator: bool,
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        kind: CommandKind::Ban,
+        names: &["ban"],
+        usage: "/ban <user> [duration] [reason]",
+        description: "bans a user, permanently unless a duration like `1h` is given",
+        moderator: true,
+    },
+    Command {
+        kind: CommandKind::Unban,
+        names: &["unban"],
+        usage: "/unban <user>",
+        description: "unbans a user",
+        moderator: true,
+    },
+    Command {
+        kind: CommandKind::Mute,
+        names: &["mute"],
+        usage: "/mute <user> <duration> [reason]",
+        description: "prevents a user from sending messages for a duration like `10m`",
+        moderator: true,
+    },
+    Command {
+        kind: CommandKind::Unmute,
+        names: &["unmute"],
+        usage: "/unmute <user>",
+        description: "unmutes a user",
+        moderator: true,
+    },
+    Command {
+        kind: CommandKind::Kick,
+        names: &["kick"],
+        usage: "/kick <user> [reason]",
+        description: "disconnects a user",
+        moderator: true,
+    },
+    Command {
+        kind: CommandKind::Msg,
+        names: &["msg", "w"],
+        usage: "/msg <name> <message>",
+        description: "sends a private message",
+        moderator: false,
+    },
+    Command {
+        kind: CommandKind::Motd,
+        names: &["motd"],
+        usage: "/motd",
+        description: "shows the message of the day",
+        moderator: false,
+    },
+    Command {
+        kind: CommandKind::Help,
+        names: &["help"],
+        usage: "/help",
+        description: "lists all available commands",
+        moderator: false,
+    },
+];
+
+impl ChatServer {
+    /// Handles a message starting with `/`; `input` is the message without the slash.
+    ///
+    /// Commands count towards the rate limit of messages,
+    /// except for `/msg`, which is limited like any other private message.
+    pub(super) fn handle_command(&mut self, user_id: InternalId, input: &str) {
+        let session = match self.sessions.get(&user_id) {
+            Some(session) => session,
+            None => return,
+        };
+        let is_moderator = match &session.user {
+            Some(info) => self.is_moderator(&info.uuid),
+            None => {
+                info!("`{}` is not logged in.", user_id);
+                self.send_error(user_id, ClientError::NotLoggedIn);
+                return;
+            }
+        };
+
+        let mut args = Arguments::new(input);
+        let name = match args.next() {
+            Some(Ok(name)) => name,
+            Some(Err(err)) => {
+                self.send_command_result(user_id, false, argument_error(err));
+                return;
+            }
+            None => String::new(),
+        };
+        let command = COMMANDS
+            .iter()
+            .find(|cmd| cmd.names.contains(&name.as_str()));
+        let is_msg = command.is_some_and(|cmd| cmd.kind == CommandKind::Msg);
+        if !is_msg && self.check_ratelimit(user_id, &format!("/{}", input)) {
+            return;
+        }
+        let command = match command {
+            Some(command) => command,
+            None => {
+                let names: Vec<_> = available_commands(is_moderator)
+                    .map(|cmd| format!("/{}", cmd.names[0]))
+                    .collect();
+                let names = names.join(", ");
+                let reply = Reply::new(
+                    keys::COMMAND_UNKNOWN,
+                    format!("unknown command `/{}`; available commands: {}", name, names),
+                )
+                .param("command", name)
+                .param("available", names);
+                self.send_command_result(user_id, false, reply);
+                return;
+            }
+        };
+        info!("User `{}` used command `/{}`.", user_id, command.names[0]);
+
+        if command.kind == CommandKind::Msg {
+            match args.next() {
+                Some(Ok(receiver)) if !args.rest().is_empty() => {
+                    let content = args.rest().to_string();
+                    self.handle_private_message(user_id, receiver, content);
+                }
+                Some(Err(err)) => self.send_command_result(user_id, false, argument_error(err)),
+                _ => self.send_command_result(user_id, false, usage(command)),
+            }
+            return;
+        }
+
+        let args: std::result::Result<Vec<String>, _> = args.collect();
+        let (success, reply) = match args {
+            Ok(args) => self.run_command(user_id, command, is_moderator, &args),
+            Err(err) => (false, argument_error(err)),
+        };
+        self.send_command_result(user_id, success, reply);
+    }
+
+    fn run_command(
+        &mut self,
+        user_id: InternalId,
+        command: &Command,
+        is_moderator: bool,
+        args: &[String],
+    ) -> (bool, Reply) {
+        match command.kind {
+            CommandKind::Ban
+            | CommandKind::Unban
+            | CommandKind::Mute
+            | CommandKind::Unmute
+            | CommandKind::Kick => {
+                let target = match args.first() {
+                    Some(target) => target,
+                    None => return (false, usage(command)),
+                };
+                let uuid = match self.resolve_uuid(target) {
+                    Some(uuid) => uuid,
+                    None => {
+                        let reply = Reply::new(
+                            keys::COMMAND_UNKNOWN_USER,
+                            format!("unknown user `{}`", target),
+                        )
+                        .param("user", target);
+                        return (false, reply);
+                    }
+                };
+                match self.run_moderation(user_id, command, target, &uuid, &args[1..]) {
+                    Ok(reply) => (true, reply),
+                    Err(reply) => (false, reply),
+                }
+            }
+            CommandKind::Motd => match &self.config.welcome.motd {
+                Some(motd) => (
+                    true,
+                    Reply::new(keys::COMMAND_MOTD, motd.clone()).param("motd", motd),
+                ),
+                None => (
+                    false,
+                    Reply::new(
+                        keys::COMMAND_NO_MOTD,
+                        "there is no message of the day".to_string(),
+                    ),
+                ),
+            },
+            CommandKind::Help => {
+                let lines: Vec<_> = available_commands(is_moderator)
+                    .map(|cmd| format!("{} - {}", cmd.usage, cmd.description))
+                    .collect();
+                let commands = lines.join("\n");
+                (
+                    true,
+                    Reply::new(keys::COMMAND_HELP, commands.clone()).param("commands", commands),
+                )
+            }
+            CommandKind::Msg => unreachable!("private messages are handled separately"),
+        }
+    }
+
+    /// Runs a moderation command against `target`, who resolved to `uuid`.
+    ///
+    /// `args` are the arguments after the target.
+    fn run_moderation(
+        &mut self,
+        user_id: InternalId,
+        command: &Command,
+        target: &str,
+        uuid: &Uuid,
+        args: &[String],
+    ) -> std::result::Result<Reply, Reply> {
+        let reason = |args: &[String]| {
+            if args.is_empty() {
+                None
+            } else {
+                Some(args.join(" "))
+            }
+        };
+        match command.kind {
+            CommandKind::Ban => {
+                // The duration is optional, so an argument which is none starts the reason.
+                let (duration, args) = match args.split_first() {
+                    Some((first, rest)) => match parse_duration(first) {
+                        Some(duration) => (duration, rest),
+                        None => (None, args),
+                    },
+                    None => (None, args),
+                };
+                let reason = reason(args);
+                info!(
+                    "User `{}` is banning `{}` for {}: {}",
+                    user_id,
+                    uuid,
+                    describe_duration(duration),
+                    reason.as_deref().unwrap_or("no reason")
+                );
+                if duration.is_none() {
+                    let summary = format!("ban `{}` permanently", target);
+                    self.request_ban_confirmation(user_id, uuid, reason, summary)
+                        .map_err(|err| Reply::from_error(&err))?;
+                    return Ok(Reply::new(
+                        keys::COMMAND_CONFIRM,
+                        format!("confirm to ban `{}` permanently", target),
+                    )
+                    .param("user", target));
+                }
+                self.moderate_user(user_id, uuid, true, reason, duration)
+                    .map_err(|err| Reply::from_error(&err))?;
+                let duration = describe_duration(duration);
+                Ok(Reply::new(
+                    keys::COMMAND_BANNED,
+                    format!("banned `{}` for {}", target, duration),
+                )
+                .param("user", target)
+                .param("duration", duration))
+            }
+            CommandKind::Mute => {
+                let (duration, args) = match args.split_first() {
+                    Some((first, rest)) => match parse_duration(first) {
+                        Some(Some(duration)) => (duration, rest),
+                        _ => {
+                            let reply = Reply::new(
+                                keys::COMMAND_INVALID_DURATION,
+                                format!("invalid duration `{}`", first),
+                            )
+                            .param("duration", first);
+                            return Err(reply);
+                        }
+                    },
+                    None => return Err(usage(command)),
+                };
+                self.mute_user(user_id, uuid, Some(duration), reason(args))
+                    .map_err(|err| Reply::from_error(&err))?;
+                let duration = describe_duration(Some(duration));
+                Ok(Reply::new(
+                    keys::COMMAND_MUTED,
+                    format!("muted `{}` for {}", target, duration),
+                )
+                .param("user", target)
+                .param("duration", duration))
+            }
+            CommandKind::Kick => {
+                self.kick_user(user_id, uuid, reason(args))
+                    .map_err(|err| Reply::from_error(&err))?;
+                Ok(
+                    Reply::new(keys::COMMAND_KICKED, format!("kicked `{}`", target))
+                        .param("user", target),
+                )
+            }
+            CommandKind::Unban | CommandKind::Unmute if !args.is_empty() => Err(usage(command)),
+            CommandKind::Unban => {
+                self.moderate_user(user_id, uuid, false, None, None)
+                    .map_err(|err| Reply::from_error(&err))?;
+                Ok(
+                    Reply::new(keys::COMMAND_UNBANNED, format!("unbanned `{}`", target))
+                        .param("user", target),
+                )
+            }
+            CommandKind::Unmute => {
+                self.mute_user(user_id, uuid, None, None)
+                    .map_err(|err| Reply::from_error(&err))?;
+                Ok(
+                    Reply::new(keys::COMMAND_UNMUTED, format!("unmuted `{}`", target))
+                        .param("user", target),
+                )
+            }
+            _ => unreachable!("only moderation commands are run here"),
+        }
+    }
+
+    /// Resolves either a uuid or the name of a user who is currently online.
+    fn resolve_uuid(&self, target: &str) -> Option<Uuid> {
+        if let Ok(uuid) = target.parse() {
+            return Some(uuid);
+        }
+
+        self.sessions
+            .sessions_of(&CanonicalId::parse_client_input(target))
+            .map(|(_, _, info)| info.uuid)
+            .next()
+    }
+
+    fn send_command_result(&self, user_id: InternalId, success: bool, reply: Reply) {
+        let packet = ClientPacket::CommandResult {
+            success,
+            message: reply.message,
+            translation_key: reply.key,
+            params: reply.params,
+        };
+        if !self.reply(user_id, packet) {
+            warn!("Could not send command result to `{}`.", user_id);
+        }
+    }
+}
+
+fn available_commands(is_moderator: bool) -> impl Iterator<Item = &'static Command> {
+    COMMANDS
+        .iter()
+        .filter(move |cmd| !cmd.moderator || is_moderator)
+}
+
+/// The result of a command, with its translation.
+struct Reply {
+    key: &'static str,
+    params: TranslationParams,
+    /// The message in English.
+    message: String,
+}
+
+impl Reply {
+    fn new(key: &'static str, message: String) -> Reply {
+        Reply {
+            key,
+            params: TranslationParams::new(),
+            message,
+        }
+    }
+
+    fn param<V: ToString>(mut self, name: &'static str, value: V) -> Reply {
+        self.params.insert(name, value.to_string());
+        self
+    }
+
+    fn from_error(err: &ClientError) -> Reply {
+        Reply {
+            key: err.translation_key(),
+            params: err.translation_params(),
+            message: err.to_string(),
+        }
+    }
+}
+
+fn usage(command: &Command) -> Reply {
+    Reply::new(keys::COMMAND_USAGE, format!("usage: {}", command.usage))
+        .param("usage", command.usage)
+}
+
+/// Parses the duration of a ban or mute, like `30m` or `1h 30m`.
+///
+/// Returns `Some(None)` for `permanent`, and `None` if `arg` is no duration.
+fn parse_duration(arg: &str) -> Option<Option<Duration>> {
+    if arg.eq_ignore_ascii_case("permanent") {
+        return Some(None);
+    }
+    match humantime::parse_duration(arg) {
+        Ok(duration) if duration.as_secs() > 0 => Some(Some(duration)),
+        _ => None,
+    }
+}
+
+fn describe_duration(duration: Option<Duration>) -> String {
+    match duration {
+        Some(duration) => humantime::format_duration(duration).to_string(),
+        None => "permanent".to_string(),
+    }
+}
+
+fn argument_error(err: &'static str) -> Reply {
+    Reply::new(keys::COMMAND_UNTERMINATED_QUOTE, err.to_string())
+}
+
+/// Splits the input of a command into whitespace separated arguments.
+///
+/// An argument can be surrounded by `"` to include whitespace.
+/// Inside of quotes, `\` escapes the following character.
+struct Arguments<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Arguments<'a> {
+    fn new(input: &'a str) -> Arguments<'a> {
+        Arguments { rest: input }
+    }
+
+    /// The remaining input which has not been split yet.
+    fn rest(&self) -> &'a str {
+        self.rest.trim()
+    }
+}
+
+impl<'a> Iterator for Arguments<'a> {
+    type Item = std::result::Result<String, &'static str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let input = self.rest.trim_start();
+        if input.is_empty() {
+            self.rest = input;
+            return None;
+        }
+
+        let mut arg = String::new();
+        let mut end = input.len();
+        let mut quoted = false;
+        let mut chars = input.char_indices();
+        while let Some((i, ch)) = chars.next() {
+            match ch {
+                '"' => quoted = !quoted,
+                '\\' if quoted => match chars.next() {
+                    Some((_, escaped)) => arg.push(escaped),
+                    None => break,
+                },
+                ch if ch.is_whitespace() && !quoted => {
+                    end = i;
+                    break;
+                }
+                ch => arg.push(ch),
+            }
+        }
+
+        if quoted {
+            self.rest = "";
+            Some(Err("unterminated quote"))
+        } else {
+            self.rest = &input[end..];
+            Some(Ok(arg))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(input: &str) -> Vec<std::result::Result<String, &'static str>> {
+        Arguments::new(input).collect()
+    }
+
+    #[test]
+    fn arguments_are_split_by_whitespace() {
+        assert_eq!(
+            split("  ban   Notch\tspam "),
+            vec![
+                Ok("ban".to_string()),
+                Ok("Notch".to_string()),
+                Ok("spam".to_string())
+            ]
+        );
+        assert!(split("   ").is_empty());
+    }
+
+    #[test]
+    fn quoted_arguments_keep_whitespace_and_escapes() {
+        assert_eq!(
+            split(r#"ban "two words" "a \"quote\"""#),
+            vec![
+                Ok("ban".to_string()),
+                Ok("two words".to_string()),
+                Ok(r#"a "quote""#.to_string()),
+            ]
+        );
+        assert_eq!(
+            split(r#"ban "open"#),
+            vec![Ok("ban".to_string()), Err("unterminated quote")]
+        );
+    }
+
+    #[test]
+    fn rest_is_the_unsplit_input() {
+        let mut args = Arguments::new("msg Notch  hello  there ");
+        args.next();
+        args.next();
+        assert_eq!(args.rest(), "hello  there");
+    }
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(parse_duration("1h"), Some(Some(Duration::from_secs(3600))));
+        assert_eq!(
+            parse_duration("1h30m"),
+            Some(Some(Duration::from_secs(5400)))
+        );
+        assert_eq!(
+            parse_duration("7days"),
+            Some(Some(Duration::from_secs(7 * 86400)))
+        );
+        assert_eq!(parse_duration("permanent"), Some(None));
+        assert_eq!(parse_duration("Permanent"), Some(None));
+    }
+
+    #[test]
+    fn reasons_are_no_durations() {
+        assert_eq!(parse_duration("spam"), None);
+        assert_eq!(parse_duration("0s"), None);
+        assert_eq!(parse_duration("500ms"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+
+    #[test]
+    fn durations_are_described() {
+        assert_eq!(describe_duration(Some(Duration::from_secs(5400))), "1h 30m");
+        assert_eq!(describe_duration(None), "permanent");
+    }
+
+    #[test]
+    fn moderation_commands_are_only_listed_for_moderators() {
+        let names = |is_moderator| -> Vec<_> {
+            available_commands(is_moderator)
+                .map(|cmd| cmd.names[0])
+                .collect()
+        };
+        assert_eq!(names(false), ["msg", "motd", "help"]);
+        assert_eq!(
+            names(true),
+            ["ban", "unban", "mute", "unmute", "kick", "msg", "motd", "help"]
+        );
+    }
+}
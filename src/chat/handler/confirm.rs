@@ -0,0 +1,133 @@
+use super::{ChatServer, ClientPacket};
+use crate::chat::InternalId;
+
+use crate::error::*;
+use crate::storage::AuditAction;
+use log::*;
+use rand::RngCore;
+use std::time::Instant;
+use uuid::Uuid;
+
+/// An action which only runs once the moderator confirmed it with `ConfirmAction`.
+pub(in crate::chat) struct PendingConfirmation {
+    action: ConfirmableAction,
+    requested: Instant,
+}
+
+enum ConfirmableAction {
+    /// A permanent ban.
+    Ban { user: Uuid, reason: Option<String> },
+}
+
+impl ChatServer {
+    /// Asks `user_id` to confirm banning `receiver` permanently with the `summary`,
+    /// if it is a logged in moderator who may ban them.
+    ///
+    /// The request counts towards the rate limit of the moderator and is recorded in the audit log.
+    pub(super) fn request_ban_confirmation(
+        &mut self,
+        user_id: InternalId,
+        receiver: &Uuid,
+        reason: Option<String>,
+        summary: String,
+    ) -> std::result::Result<(), ClientError> {
+        let actor = self.check_moderation_action(user_id)?;
+        if self.is_moderator(receiver) {
+            info!("`{}` tried to ban moderator `{}`", user_id, receiver);
+            return Err(ClientError::NotPermitted);
+        }
+
+        let mut bytes = [0; 16];
+        self.rng.fill_bytes(&mut bytes);
+        let nonce: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let now = self.now();
+        let ttl = *self.config.moderation.confirmation_ttl;
+        let session = match self.sessions.get_mut(&user_id) {
+            Some(session) => session,
+            None => {
+                debug!(
+                    "User `{}` disconnected before its message was handled.",
+                    user_id
+                );
+                return Ok(());
+            }
+        };
+        session
+            .confirmations
+            .retain(|_, pending| now.duration_since(pending.requested) < ttl);
+        session.confirmations.insert(
+            nonce.clone(),
+            PendingConfirmation {
+                action: ConfirmableAction::Ban {
+                    user: *receiver,
+                    reason: reason.clone(),
+                },
+                requested: now,
+            },
+        );
+        if let Err(err) = session
+            .addr
+            .do_send(ClientPacket::ConfirmAction { nonce, summary })
+        {
+            info!("Could not send confirmation to `{}`: {}", user_id, err);
+        }
+        self.record_audit(
+            Some(actor),
+            AuditAction::BanRequested,
+            *receiver,
+            reason,
+            None,
+        );
+        Ok(())
+    }
+
+    /// Runs the action `user_id` was sent the `nonce` for, if it did not expire.
+    /// The nonce can only be used once.
+    pub(super) fn confirm_action(&mut self, user_id: InternalId, nonce: &str) {
+        let now = self.now();
+        let ttl = *self.config.moderation.confirmation_ttl;
+        let session = match self.sessions.get_mut(&user_id) {
+            Some(session) => session,
+            None => {
+                debug!(
+                    "User `{}` disconnected before its message was handled.",
+                    user_id
+                );
+                return;
+            }
+        };
+        let pending = session.confirmations.remove(nonce);
+        let actor = session.user.as_ref().map(|user| user.uuid);
+        let (action, actor) = match (pending, actor) {
+            (Some(pending), Some(actor)) if now.duration_since(pending.requested) < ttl => {
+                (pending.action, actor)
+            }
+            (Some(_), _) => {
+                info!("`{}` confirmed an action too late", user_id);
+                self.send_error(user_id, ClientError::ConfirmationFailed);
+                return;
+            }
+            (None, _) => {
+                info!("`{}` confirmed an unknown action", user_id);
+                self.send_error(user_id, ClientError::ConfirmationFailed);
+                return;
+            }
+        };
+        // The moderator may have been removed in the meantime.
+        if !self.is_moderator(&actor) {
+            info!("`{}` confirmed an action without permission", user_id);
+            self.send_error(user_id, ClientError::NotPermitted);
+            return;
+        }
+
+        let packet = match action {
+            ConfirmableAction::Ban { user, reason } => {
+                match self.apply_moderation(user_id, actor, &user, true, reason, None) {
+                    Ok(reason) => ClientPacket::Success { reason },
+                    Err(message) => ClientPacket::Error { message },
+                }
+            }
+        };
+        self.reply(user_id, packet);
+    }
+}
@@ -5,33 +5,32 @@ use log::*;
 impl ChatServer {
     pub(super) fn send_user_count(&mut self, user_id: InternalId) {
         let session = self
-            .connections
+            .sessions
             .get(&user_id)
             .expect("could not find connection");
 
         if let Some(info) = &session.user {
-            if !self.moderation.is_moderator(&info.uuid) {
+            if !self.is_moderator(&info.uuid) {
                 info!(
                     "`{}` tried to get the user count without permission",
                     user_id
                 );
-                let _ = session.addr.do_send(ClientPacket::Error {
-                    message: ClientError::NotPermitted,
-                });
+                self.send_error(user_id, ClientError::NotPermitted);
                 return;
             }
 
-            if let Err(err) = session.addr.do_send(ClientPacket::UserCount {
-                connections: self.connections.len() as u32,
-                logged_in: self.users.len() as u32,
-            }) {
-                warn!("Could not send user count to user `{}`: {}", user_id, err);
-            }
+            let (remote_connections, remote_logged_in) = self
+                .cluster
+                .as_ref()
+                .map_or((0, 0), |cluster| cluster.remote_user_count());
+            let packet = ClientPacket::UserCount {
+                connections: self.sessions.len() as u32 + remote_connections,
+                logged_in: self.sessions.user_count() as u32 + remote_logged_in,
+            };
+            self.send_to(user_id, session, packet);
         } else {
             info!("`{}` is not logged in.", user_id);
-            let _ = session.addr.do_send(ClientPacket::Error {
-                message: ClientError::NotLoggedIn,
-            });
+            self.send_error(user_id, ClientError::NotLoggedIn);
         }
     }
 }
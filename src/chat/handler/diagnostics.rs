@@ -0,0 +1,129 @@
+use log::*;
+
+use super::{ChatServer, ClientPacket};
+use crate::chat::{cluster, Capabilities, InternalId, UserStatus};
+use crate::error::*;
+use serde::Serialize;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How often a connection can request its diagnostics.
+const DIAGNOSTICS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The state of a connection, for support to find out why messages of a user do not show up.
+///
+/// It only describes the connection which requested it.
+#[derive(Serialize, Clone)]
+pub struct Diagnostics {
+    pub id: InternalId,
+    /// The random tag of the connection, which is logged together with `id`.
+    pub session_tag: String,
+    pub name: String,
+    pub uuid: Uuid,
+    pub features: Capabilities,
+    pub echo_own_messages: bool,
+    pub status: UserStatus,
+    pub moderator: bool,
+    pub banned: bool,
+    /// The seconds until the probation of a new user ends, if it did not yet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probation_secs: Option<u64>,
+    /// The seconds until the user can send messages after logging in, if they have to wait.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub join_cooldown_secs: Option<u64>,
+    /// How long the server keeps who sent private messages to whom, if it does.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pm_metadata_retention_minutes: Option<u64>,
+    pub rate_limit: RateLimitDiagnostics,
+    /// The packets waiting to be written to the connection, filled in by the session.
+    pub queued_packets: usize,
+    /// The packets of the connection which could not be decoded, filled in by the session.
+    pub malformed_packets: u32,
+    /// The time of the server, in milliseconds since the unix epoch.
+    pub server_time: u64,
+}
+
+/// How close a user is to being rate limited.
+#[derive(Serialize, Clone)]
+pub struct RateLimitDiagnostics {
+    /// The messages counted in the current window.
+    pub messages: usize,
+    pub max_messages: usize,
+    pub window_secs: u64,
+}
+
+impl ChatServer {
+    /// Sends the connection `user_id` its diagnostics,
+    /// at most once per [`DIAGNOSTICS_INTERVAL`].
+    pub(super) fn handle_request_diagnostics(&mut self, user_id: InternalId) {
+        let now = self.now();
+        let session = self
+            .sessions
+            .get_mut(&user_id)
+            .expect("could not find connection");
+        let packet = if session.user.is_none() {
+            ClientPacket::Error {
+                message: ClientError::NotLoggedIn,
+            }
+        } else if session
+            .last_diagnostics
+            .is_some_and(|last| now.duration_since(last) < DIAGNOSTICS_INTERVAL)
+        {
+            debug!("User `{}` requested diagnostics too often.", user_id);
+            ClientPacket::Error {
+                message: ClientError::RateLimited,
+            }
+        } else {
+            session.last_diagnostics = Some(now);
+            ClientPacket::Diagnostics(self.diagnostics(user_id))
+        };
+        self.reply(user_id, packet);
+    }
+
+    fn diagnostics(&self, user_id: InternalId) -> Diagnostics {
+        let session = &self.sessions[&user_id];
+        let user = session
+            .user
+            .as_ref()
+            .expect("diagnostics are only sent to logged in connections");
+        let user_session = self
+            .sessions
+            .user(&user.name.canonical())
+            .expect("logged in connections have a user session");
+
+        let probation = self.probation_remaining(&user.uuid);
+        let cooldown = Duration::from_secs(self.config.moderation.join_cooldown_secs);
+        let join_cooldown = session
+            .cooldown_since
+            .and_then(|since| cooldown.checked_sub(self.now().duration_since(since)))
+            .filter(|remaining| *remaining > Duration::from_secs(0));
+
+        Diagnostics {
+            id: user_id,
+            session_tag: user_id.session_tag(),
+            name: user.name.to_string(),
+            uuid: user.uuid,
+            features: session.capabilities,
+            echo_own_messages: session.echo_own_messages,
+            status: user_session.status,
+            moderator: self.is_moderator(&user.uuid),
+            banned: self.moderation.is_banned(&user.uuid, self.system_now()),
+            probation_secs: probation.map(ceil_secs),
+            join_cooldown_secs: join_cooldown.map(ceil_secs),
+            pm_metadata_retention_minutes: self.pm_metadata_retention(),
+            rate_limit: RateLimitDiagnostics {
+                messages: user_session.rate_limiter.recent_messages(self.now()),
+                max_messages: self.max_messages(&user.uuid),
+                window_secs: user_session.rate_limiter.window().as_secs(),
+            },
+            queued_packets: 0,
+            malformed_packets: 0,
+            server_time: cluster::unix_millis(self.system_now()),
+        }
+    }
+}
+
+/// Rounds `duration` up to whole seconds.
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
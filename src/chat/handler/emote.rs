@@ -0,0 +1,17 @@
+use super::{ChatServer, ClientPacket};
+use crate::chat::InternalId;
+
+impl ChatServer {
+    pub(super) fn handle_request_emotes(&self, user_id: InternalId) {
+        let session = self
+            .sessions
+            .get(&user_id)
+            .expect("could not find connection");
+        session
+            .addr
+            .do_send(ClientPacket::Emotes {
+                emotes: self.emotes.table().clone(),
+            })
+            .ok();
+    }
+}
@@ -0,0 +1,162 @@
+use log::*;
+
+use super::{ChatServer, ClientPacket};
+use crate::auth::UserInfo;
+use crate::chat::{firehose::EventKind, InternalId};
+use crate::error::*;
+use serde::Serialize;
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/// The maximum amount of chars of a message included in a moderation event.
+const MAX_EXCERPT_LENGTH: usize = 64;
+
+/// The maximum amount of moderation events a subscriber receives in `EVENT_WINDOW`.
+const MAX_EVENTS: u32 = 20;
+const EVENT_WINDOW: Duration = Duration::from_secs(10);
+
+/// The amount of consecutive rate limited messages after which moderators are notified.
+pub(super) const REPEATED_RATE_LIMIT: u32 = 3;
+
+/// Why a message of a user was rejected.
+#[derive(Serialize, Clone, Copy, Debug)]
+pub(in crate::chat) enum ModerationEventKind {
+    BlockedWord,
+    /// The message was delivered with its blocked words redacted, see `validation.report_redactions`.
+    Redacted,
+    Probation,
+    RateLimit,
+    ReviewDenied,
+    /// The message was delivered anyway because of `validation.dry_run`.
+    DryRun,
+}
+
+/// The state of a moderator subscribed to moderation events.
+///
+/// Events exceeding the budget of the current window are dropped,
+/// so a flood of rejected messages can not flood the moderators too.
+pub(in crate::chat) struct ModerationSubscription {
+    window_start: Cell<Instant>,
+    sent: Cell<u32>,
+    dropped: Cell<u32>,
+}
+
+impl ModerationSubscription {
+    /// A subscription whose first window starts at `now`.
+    fn new(now: Instant) -> ModerationSubscription {
+        ModerationSubscription {
+            window_start: Cell::new(now),
+            sent: Cell::new(0),
+            dropped: Cell::new(0),
+        }
+    }
+
+    /// Returns whether another event fits into the budget and counts it if so.
+    fn take(&self, now: Instant) -> bool {
+        if now.duration_since(self.window_start.get()) >= EVENT_WINDOW {
+            if self.dropped.get() > 0 {
+                debug!(
+                    "Dropped {} moderation events for a subscriber.",
+                    self.dropped.get()
+                );
+            }
+            self.window_start.set(now);
+            self.sent.set(0);
+            self.dropped.set(0);
+        }
+        if self.sent.get() < MAX_EVENTS {
+            self.sent.set(self.sent.get() + 1);
+            true
+        } else {
+            self.dropped.set(self.dropped.get() + 1);
+            false
+        }
+    }
+}
+
+impl ChatServer {
+    pub(super) fn handle_subscribe_moderation_events(
+        &mut self,
+        user_id: InternalId,
+        enabled: bool,
+    ) {
+        let is_moderator = match &self.sessions[&user_id].user {
+            Some(info) => self.is_moderator(&info.uuid),
+            None => false,
+        };
+        let now = self.now();
+        let session = self
+            .sessions
+            .get_mut(&user_id)
+            .expect("could not find connection");
+        if !is_moderator {
+            info!(
+                "User `{}` tried to subscribe to moderation events without permission.",
+                user_id
+            );
+            self.send_error(user_id, ClientError::NotPermitted);
+            return;
+        }
+
+        debug!(
+            "User `{}` sets subscription to moderation events to {}.",
+            user_id, enabled
+        );
+        session.moderation_events = if enabled {
+            Some(ModerationSubscription::new(now))
+        } else {
+            None
+        };
+    }
+
+    /// Tells the subscribed moderators that a message of `user_id` was rejected.
+    pub(in crate::chat) fn notify_moderators(
+        &self,
+        user_id: InternalId,
+        kind: ModerationEventKind,
+        content: &str,
+        rule: &str,
+    ) {
+        let user = match self
+            .sessions
+            .get(&user_id)
+            .and_then(|session| session.user.as_ref())
+        {
+            Some(info) => UserInfo {
+                name: info.name.to_string(),
+                uuid: info.uuid,
+                bot: self.is_bot(&info.uuid),
+            },
+            None => return,
+        };
+
+        let mut content_excerpt: String = content.chars().take(MAX_EXCERPT_LENGTH).collect();
+        if content_excerpt.len() < content.len() {
+            content_excerpt.push('…');
+        }
+        let event = ClientPacket::ModerationEvent {
+            kind,
+            user,
+            content_excerpt,
+            rule: rule.to_string(),
+        };
+        self.publish_firehose(EventKind::Moderation, || event.clone());
+
+        let now = self.now();
+        for session in self.sessions.values() {
+            let subscription = match &session.moderation_events {
+                Some(subscription) => subscription,
+                None => continue,
+            };
+            match &session.user {
+                Some(info) if self.is_moderator(&info.uuid) => {}
+                _ => continue,
+            }
+            if subscription.take(now) {
+                if let Err(err) = session.addr.do_send(event.clone()) {
+                    warn!("Could not send moderation event to moderator: {}", err);
+                }
+            }
+        }
+    }
+}
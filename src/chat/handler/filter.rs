@@ -0,0 +1,154 @@
+use log::*;
+
+use super::{ActionClass, ChatServer, ClientPacket};
+use crate::chat::{InternalId, SuccessReason};
+use crate::error::*;
+use crate::filter::{normalize_word, write_words, WordFilter};
+
+use actix::*;
+use actix_web::web;
+use uuid::Uuid;
+
+impl ChatServer {
+    pub(super) fn handle_edit_blocked_word(
+        &mut self,
+        user_id: InternalId,
+        word: &str,
+        block: bool,
+        ctx: &mut Context<Self>,
+    ) {
+        let packet = match self
+            .check_moderator(user_id)
+            .and_then(|actor| {
+                if self.check_action_rate(actor, ActionClass::BlockedWord) {
+                    info!("`{}` edited too many blocked words recently", user_id);
+                    Err(ClientError::RateLimited)
+                } else {
+                    Ok(())
+                }
+            })
+            .and_then(|()| self.edit_blocked_words(word, block, ctx))
+        {
+            Ok(()) if block => ClientPacket::Success {
+                reason: SuccessReason::BlockWord,
+            },
+            Ok(()) => ClientPacket::Success {
+                reason: SuccessReason::UnblockWord,
+            },
+            Err(message) => ClientPacket::Error { message },
+        };
+
+        self.reply(user_id, packet);
+    }
+
+    pub(super) fn handle_list_blocked_words(&self, user_id: InternalId, filter: Option<String>) {
+        let packet = match self.check_moderator(user_id) {
+            Ok(_) => ClientPacket::BlockedWords {
+                words: self.list_blocked_words(filter.as_deref()),
+            },
+            Err(message) => ClientPacket::Error { message },
+        };
+
+        self.reply(user_id, packet);
+    }
+
+    /// Returns the uuid of the user of `user_id` if they are a moderator.
+    fn check_moderator(&self, user_id: InternalId) -> std::result::Result<Uuid, ClientError> {
+        let session = self
+            .sessions
+            .get(&user_id)
+            .expect("could not find connection");
+        match &session.user {
+            Some(info) if self.is_moderator(&info.uuid) => Ok(info.uuid),
+            Some(_) => {
+                info!(
+                    "`{}` tried to edit blocked words without permission",
+                    user_id
+                );
+                Err(ClientError::NotPermitted)
+            }
+            None => {
+                info!("`{}` is not logged in.", user_id);
+                Err(ClientError::NotLoggedIn)
+            }
+        }
+    }
+
+    /// Returns the blocked words containing `filter`.
+    pub(in crate::chat) fn list_blocked_words(&self, filter: Option<&str>) -> Vec<String> {
+        let filter = filter.map(normalize_word);
+        self.blocked_words
+            .iter()
+            .filter(|word| match &filter {
+                Some(filter) => word.contains(filter.as_str()),
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Blocks or unblocks `word` and stores the new list.
+    ///
+    /// The filter used by the validator is rebuilt in the background and
+    /// replaced once it is complete, so messages are checked against either
+    /// the old or the new list until then.
+    pub(in crate::chat) fn edit_blocked_words(
+        &mut self,
+        word: &str,
+        block: bool,
+        ctx: &mut Context<Self>,
+    ) -> std::result::Result<(), ClientError> {
+        let word = normalize_word(word);
+        if word.is_empty() {
+            return Err(ClientError::EmptyWord);
+        }
+
+        let changed = if block {
+            self.blocked_words.insert(word.clone())
+        } else {
+            self.blocked_words.remove(&word)
+        };
+        if !changed {
+            return if block {
+                Ok(())
+            } else {
+                Err(ClientError::NotBlocked)
+            };
+        }
+
+        if let Err(err) = write_words(&self.config.validation.blocked_words, &self.blocked_words) {
+            warn!("Could not store blocked words: {}", err);
+            if block {
+                self.blocked_words.remove(&word);
+            } else {
+                self.blocked_words.insert(word);
+            }
+            return Err(ClientError::Internal);
+        }
+        info!("Word `{}` was (un-)blocked.", word);
+
+        self.rebuild_word_filter(ctx);
+        Ok(())
+    }
+
+    fn rebuild_word_filter(&mut self, ctx: &mut Context<Self>) {
+        self.word_filter_generation += 1;
+        let generation = self.word_filter_generation;
+        let words = self.blocked_words.clone();
+
+        web::block(move || Ok::<_, ()>(WordFilter::new(words)))
+            .into_actor(self)
+            .then(move |res, actor, _ctx| {
+                match res {
+                    // a newer list might have been built already
+                    Ok(filter) if generation == actor.word_filter_generation => {
+                        actor.validator.set_word_filter(filter);
+                    }
+                    Ok(_) => debug!("Discarding outdated word filter."),
+                    Err(err) => warn!("Could not build word filter: {}", err),
+                }
+                fut::ok(())
+            })
+            .spawn(ctx);
+    }
+}
@@ -0,0 +1,242 @@
+use log::*;
+
+use super::{ChatServer, ClientPacket};
+use crate::chat::{
+    close::{Close, DisconnectReason},
+    cluster,
+    funnel::Stage,
+    Capabilities, ClientVersion, InternalId, SessionState, PROTOCOL_VERSION,
+};
+use crate::config::UnknownClients;
+use crate::error::*;
+use semver::{Version, VersionReq};
+
+impl ChatServer {
+    pub(super) fn handle_hello(
+        &mut self,
+        user_id: InternalId,
+        features: Capabilities,
+        echo_own_messages: Option<bool>,
+        client: Option<ClientVersion>,
+        protocol: Option<u32>,
+    ) {
+        self.sessions
+            .get_mut(&user_id)
+            .expect("could not find connection")
+            .protocol = protocol.unwrap_or(PROTOCOL_VERSION);
+        if self.reject_unsupported_protocol(user_id) {
+            return;
+        }
+        if !self.is_client_accepted(user_id, client.as_ref()) {
+            self.send_error(
+                user_id,
+                ClientError::ClientOutdated {
+                    upgrade_url: self.config.server.client_upgrade_url.clone(),
+                },
+            );
+            self.sessions[&user_id]
+                .close
+                .do_send(Close(DisconnectReason::ClientOutdated))
+                .ok();
+            return;
+        }
+
+        debug!(
+            "User `{}` supports {:?}.",
+            user_id,
+            features.names().collect::<Vec<_>>()
+        );
+        let session = self
+            .sessions
+            .get_mut(&user_id)
+            .expect("could not find connection");
+        session.capabilities = features;
+        if let Some(enabled) = echo_own_messages {
+            session.echo_own_messages = enabled;
+        }
+        self.funnel.record_stage(Stage::Hello);
+        if features.contains(Capabilities::TIME_SYNC) {
+            let packet = ClientPacket::TimeSync {
+                client_time_ms: None,
+                server_time_ms: cluster::unix_millis(self.system_now()),
+            };
+            self.reply(user_id, packet);
+        }
+        if features.contains(Capabilities::SESSION_TAG) {
+            let packet = ClientPacket::SessionTag {
+                session_tag: user_id.session_tag(),
+            };
+            self.reply(user_id, packet);
+        }
+    }
+
+    pub(super) fn set_echo_own_messages(&mut self, user_id: InternalId, enabled: bool) {
+        let session = self
+            .sessions
+            .get_mut(&user_id)
+            .expect("could not find connection");
+
+        debug!(
+            "User `{}` sets echo of own messages to {}.",
+            user_id, enabled
+        );
+        session.echo_own_messages = enabled;
+    }
+
+    /// Checks `client` against `server.min_client_versions` and logs the decision.
+    fn is_client_accepted(&self, user_id: InternalId, client: Option<&ClientVersion>) -> bool {
+        let cfg = &self.config.server;
+        if cfg.min_client_versions.is_empty() && cfg.unknown_clients == UnknownClients::Allow {
+            return true;
+        }
+
+        let (brand, version) = match client {
+            Some(client) => (client.brand.as_str(), client.version.as_str()),
+            None => ("", ""),
+        };
+        let (accepted, requirement) = match cfg.min_client_versions.get(brand) {
+            Some(requirement) => (
+                meets_requirement(version, requirement),
+                requirement.as_str(),
+            ),
+            None => (
+                cfg.unknown_clients == UnknownClients::Allow,
+                "unknown brand",
+            ),
+        };
+        info!(
+            "Client of `{}` is `{}` version `{}` ({}); {}.",
+            user_id,
+            brand,
+            version,
+            requirement,
+            if accepted { "accepted" } else { "rejected" }
+        );
+        accepted
+    }
+
+    /// Returns all sessions which support `capabilities`.
+    pub(super) fn sessions_with(
+        &self,
+        capabilities: Capabilities,
+    ) -> impl Iterator<Item = &SessionState> {
+        self.sessions
+            .values()
+            .filter(move |session| session.capabilities.contains(capabilities))
+    }
+}
+
+/// Returns whether `version` meets the semver `requirement`.
+///
+/// If either can not be parsed, `version` is compared as a string
+/// with the version in `requirement`, which may be prefixed by an operator like `>=`.
+fn meets_requirement(version: &str, requirement: &str) -> bool {
+    if let (Ok(version), Ok(requirement)) =
+        (Version::parse(version), VersionReq::parse(requirement))
+    {
+        return requirement.matches(&version);
+    }
+    let min = requirement.trim_start_matches(|c: char| "<>=^~ ".contains(c));
+    version >= min
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{
+        simulate::{Capture, Collect},
+        trace::{self, TraceId},
+        DisplayName, User,
+    };
+    use actix::*;
+    use std::sync::{Mutex, Once};
+    use std::thread::{self, ThreadId};
+    use uuid::Uuid;
+
+    /// Keeps the log messages with the thread which logged them,
+    /// so tests running at the same time do not see each other's.
+    struct Recorder(Mutex<Vec<(ThreadId, String)>>);
+
+    impl Log for Recorder {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            let message = match trace::current_trace() {
+                Some(trace) => format!("trace={} {}", trace, record.args()),
+                None => record.args().to_string(),
+            };
+            self.0
+                .lock()
+                .unwrap()
+                .push((thread::current().id(), message));
+        }
+
+        fn flush(&self) {}
+    }
+
+    static RECORDER: Recorder = Recorder(Mutex::new(Vec::new()));
+
+    fn record_logs() {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&RECORDER).unwrap();
+            log::set_max_level(LevelFilter::Debug);
+        });
+    }
+
+    /// The messages this thread logged.
+    fn logs() -> Vec<String> {
+        let current = thread::current().id();
+        RECORDER
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(thread, _)| *thread == current)
+            .map(|(_, message)| message.clone())
+            .collect()
+    }
+
+    #[test]
+    fn session_tags_are_told_to_clients_and_logged() {
+        record_logs();
+        let mut system = System::new("test");
+        let mut server = ChatServer::for_tests(|_| ());
+        let capture = Capture::default().start();
+        let user = User {
+            name: DisplayName::new("Notch".to_string()),
+            uuid: Uuid::from_u128(0x069a79f4_44e9_4726_a5be_fca90e38aaf5),
+            allow_messages: true,
+        };
+        let id = server.connect_for_tests(&capture, Some(user));
+
+        {
+            let _entered = trace::enter(TraceId::new(id, 1));
+            server.handle_hello(id, Capabilities::SESSION_TAG, None, None, None);
+        }
+        server.handle_request_diagnostics(id);
+        let footprint = server.heaviest_sessions(1).remove(0);
+        let packets = system.block_on(capture.send(Collect)).unwrap().packets;
+
+        assert_eq!(packets[0]["m"], "SessionTag");
+        let tag = packets[0]["c"]["session_tag"].as_str().unwrap();
+        assert_eq!(tag.len(), 8);
+        assert!(tag.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(packets[1]["m"], "Diagnostics");
+        assert_eq!(packets[1]["c"]["session_tag"], tag);
+        assert_eq!(footprint.session_tag, tag);
+
+        // Support can find the log events of the connection by its tag.
+        let logged = format!("{}", id);
+        assert!(logged.starts_with('c') && logged.ends_with(&format!("/{}", tag)));
+        let logs = logs();
+        let hello = logs
+            .iter()
+            .find(|message| message.contains("supports"))
+            .expect("`Hello` was not logged");
+        assert!(hello.starts_with(&format!("trace={}-1 ", logged)));
+        assert!(hello.contains(&format!("User `{}` supports", logged)));
+    }
+}
@@ -0,0 +1,42 @@
+use log::*;
+
+use super::{ChatServer, ClientPacket};
+use crate::chat::{Capabilities, InternalId, PROTOCOL_VERSION};
+use crate::version;
+
+impl ChatServer {
+    pub(super) fn handle_request_server_info(&mut self, user_id: InternalId) {
+        let session = self
+            .sessions
+            .get(&user_id)
+            .expect("could not find connection");
+
+        if let Err(err) = session.addr.do_send(self.server_info()) {
+            warn!("Could not send server info to user `{}`: {}", user_id, err);
+        }
+    }
+
+    pub(super) fn server_info(&self) -> ClientPacket {
+        let validation = &self.config.validation;
+        ClientPacket::ServerInfo {
+            version: version::VERSION.to_string(),
+            protocol: PROTOCOL_VERSION,
+            max_message_length: self.config.message.max_length as u32,
+            max_lines: if validation.allow_newlines {
+                validation.max_lines as u32
+            } else {
+                1
+            },
+            max_line_length: Some(validation.max_line_length as u32).filter(|max| *max != 0),
+            commands_enabled: self.config.commands.enabled,
+            commit: version::GIT_COMMIT.to_string(),
+            build_timestamp: version::BUILD_TIMESTAMP.to_string(),
+            uptime_secs: version::uptime().as_secs(),
+            features: Capabilities::ALL,
+            connections: self.connection_limit.current() as u32,
+            max_connections: self.connection_limit.max().map(|max| max as u32),
+            online_users: self.sessions.user_count() as u32,
+            guest_viewers: self.sessions.guest_count() as u32,
+        }
+    }
+}
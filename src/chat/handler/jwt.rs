@@ -2,56 +2,38 @@ use crate::error::*;
 use log::*;
 
 use super::{ChatServer, ClientPacket};
-use crate::auth::UserInfo;
-use crate::chat::{InternalId, SuccessReason, User, UserSession};
-use crate::message::RateLimiter;
-use std::collections::HashSet;
+use crate::auth::{Authenticator, UserInfo};
+use crate::chat::{InternalId, SuccessReason, User};
 
 impl ChatServer {
     pub(super) fn handle_request_jwt(&mut self, user_id: InternalId) {
         let session = self
-            .connections
+            .sessions
             .get(&user_id)
             .expect("could not find connection");
         if let Some(auth) = &self.authenticator {
             if let Some(user) = &session.user {
                 let token = match auth.new_token(UserInfo {
-                    name: user.name.clone(),
+                    name: user.name.to_string(),
                     uuid: user.uuid,
+                    bot: false,
                 }) {
                     Ok(token) => token,
                     Err(err) => {
                         warn!("Could not create new token for user `{}`: {}", user_id, err);
-                        session
-                            .addr
-                            .do_send(ClientPacket::Error {
-                                message: ClientError::Internal,
-                            })
-                            .ok();
+                        self.send_error(user_id, ClientError::Internal);
                         return;
                     }
                 };
 
-                if let Err(err) = session.addr.do_send(ClientPacket::NewJWT { token }) {
-                    warn!("Could not send mojang info to user `{}`: {}", user_id, err);
-                }
+                self.send_to(user_id, session, ClientPacket::NewJWT { token });
             } else {
                 info!("User `{}` tried to get JWT but is not logged in.", user_id);
-                session
-                    .addr
-                    .do_send(ClientPacket::Error {
-                        message: ClientError::NotLoggedIn,
-                    })
-                    .ok();
+                self.send_error(user_id, ClientError::NotLoggedIn);
             }
         } else {
             info!("User `{}` tried to request not supported JWT", user_id);
-            session
-                .addr
-                .do_send(ClientPacket::Error {
-                    message: ClientError::NotSupported,
-                })
-                .ok();
+            self.send_error(user_id, ClientError::NotSupported);
         }
     }
 
@@ -61,51 +43,35 @@ impl ChatServer {
         jwt: &str,
         allow_messages: bool,
     ) {
-        let session = self
-            .connections
-            .get_mut(&user_id)
-            .expect("could not find connection");
         if let Some(auth) = &self.authenticator {
             match auth.auth(jwt) {
                 Ok(info) => {
-                    self.users
-                        .entry(info.name.clone())
-                        .or_insert(UserSession {
-                            rate_limiter: RateLimiter::new(self.config.message.clone()),
-                            connections: HashSet::new(),
-                        })
-                        .connections
-                        .insert(user_id);
-
-                    session.user = Some(User {
-                        name: info.name,
-                        uuid: info.uuid,
-                        allow_messages,
-                    });
-                    if let Err(err) = session.addr.do_send(ClientPacket::Success {
-                        reason: SuccessReason::Login,
-                    }) {
-                        info!("Could not send login success to `{}`: {}", user_id, err);
-                    }
-                }
-                Err(err) => {
-                    info!("Login of user `{}` using JWT failed: {}", user_id, err);
-                    session
-                        .addr
-                        .do_send(ClientPacket::Error {
-                            message: ClientError::LoginFailed,
-                        })
-                        .ok();
+                    self.complete_login(
+                        user_id,
+                        User {
+                            name: info.name.into(),
+                            uuid: info.uuid,
+                            allow_messages,
+                        },
+                        SuccessReason::Login,
+                    );
                 }
+                Err(err) => match Authenticator::token_failure(jwt, &err) {
+                    Some(reason) => {
+                        info!("Login of user `{}` using JWT failed: {}", user_id, err);
+                        self.auth_monitor.record_jwt_failure(reason.as_str());
+                        self.send_error(user_id, ClientError::InvalidToken { reason });
+                    }
+                    None => {
+                        warn!("Could not verify JWT of user `{}`: {}", user_id, err);
+                        self.auth_monitor.record_jwt_failure("internal");
+                        self.send_error(user_id, ClientError::Internal);
+                    }
+                },
             };
         } else {
             info!("User `{}` tried to request not supported JWT", user_id);
-            session
-                .addr
-                .do_send(ClientPacket::Error {
-                    message: ClientError::NotSupported,
-                })
-                .ok();
+            self.send_error(user_id, ClientError::NotSupported);
         }
     }
 }
@@ -0,0 +1,184 @@
+use log::*;
+
+use super::{ChatServer, ClientPacket};
+use crate::auth::UserInfo;
+use crate::chat::{
+    close::{Close, DisconnectReason},
+    firehose::EventKind,
+    funnel::Stage,
+    InternalId, SuccessReason, User,
+};
+use crate::config::{BannedLogin, SessionLimit};
+use crate::error::*;
+use crate::message::RateLimiter;
+use std::time::Duration;
+
+impl ChatServer {
+    /// Marks the connection `user_id` as logged in as `user` after a successful authentication.
+    ///
+    /// `reason` is either [`SuccessReason::Login`] or [`SuccessReason::Resume`];
+    /// resumed sessions do not receive the welcome sequence again.
+    pub(super) fn complete_login(
+        &mut self,
+        user_id: InternalId,
+        user: User,
+        reason: SuccessReason,
+    ) {
+        let session = match self.sessions.get(&user_id) {
+            Some(session) => session,
+            None => {
+                info!("User `{}` disconnected while logging in.", user_id);
+                return;
+            }
+        };
+        if session.is_logged_in() {
+            info!("User `{}` tried to log in multiple times.", user_id);
+            self.send_error(user_id, ClientError::AlreadyLoggedIn);
+            return;
+        }
+        if session.reserved && !self.is_moderator(&user.uuid) {
+            info!(
+                "User `{}` used a reserved slot without being a moderator.",
+                user_id
+            );
+            session
+                .close
+                .do_send(Close(DisconnectReason::ServerFull))
+                .ok();
+            return;
+        }
+        let now = self.system_now();
+        let ban = self.moderation.ban_of(&user.uuid, now);
+        if let (Some(ban), BannedLogin::Reject) = (ban, self.config.moderation.banned_login) {
+            info!("User `{}` tried to log in while banned.", user_id);
+            let reason = DisconnectReason::Banned;
+            let packet = ClientPacket::Disconnected {
+                reason_code: reason.label(),
+                translation_key: reason.translation_key(),
+                retry_after_secs: ban.remaining(now).map(|remaining| remaining.as_secs()),
+                reason: ban.reason.clone(),
+            };
+            self.send_to(user_id, session, packet);
+            session.close.do_send(Close(reason)).ok();
+            return;
+        }
+        let restricted = ban.is_some() || self.moderation.mute_of(&user.uuid, now).is_some();
+        if self.reject_unsupported_protocol(user_id) || self.enforce_session_limit(user_id, &user) {
+            return;
+        }
+
+        let id = user.name.canonical();
+        let first_session = !self.sessions.is_online(&id);
+        let cooldown = self.has_join_cooldown(&user, first_session, reason);
+
+        if let Err(err) = self.storage.register_seen(&user.uuid, self.system_now()) {
+            warn!("Could not store first login of `{}`: {}", user_id, err);
+        }
+
+        let info = UserInfo {
+            name: user.name.to_string(),
+            uuid: user.uuid,
+            bot: self.is_bot(&user.uuid),
+        };
+        self.detect_rename(user_id, user.uuid, &user.name);
+        let rate_limit = self.rate_limit_config(&user.uuid);
+        let now = self.now();
+        let session = self
+            .sessions
+            .insert_login(user_id, user, || RateLimiter::new(rate_limit))
+            .expect("could not find connection");
+        if cooldown {
+            session.cooldown_since = Some(now);
+        }
+        self.funnel.record_stage(Stage::LoggedIn);
+        self.update_guest_count();
+        self.reply(user_id, ClientPacket::Success { reason });
+        if restricted {
+            self.reply(user_id, self.moderation_status(&info.uuid));
+        }
+
+        self.cluster_login(&id);
+        if first_session {
+            self.notify_watchers(&id, &info.name);
+            self.record_presence_join(&info);
+            let user = info.clone();
+            self.publish_firehose(EventKind::Presence, || ClientPacket::UserJoined { user });
+        }
+        self.notify_hooks(|hook| hook.on_login(&info));
+
+        self.issue_resume_token(user_id);
+        self.warn_protocol_sunset(user_id);
+        if let SuccessReason::Login = reason {
+            self.send_welcome(user_id);
+        }
+    }
+
+    /// Returns whether `user` has to wait `moderation.join_cooldown_secs` before sending messages.
+    ///
+    /// Moderators and users who are already online, resume a session
+    /// or were online within `moderation.join_cooldown_grace_secs` are exempt.
+    fn has_join_cooldown(&self, user: &User, first_session: bool, reason: SuccessReason) -> bool {
+        let cfg = &self.config.moderation;
+        if cfg.join_cooldown_secs == 0
+            || !first_session
+            || matches!(reason, SuccessReason::Resume)
+            || self.is_bot(&user.uuid)
+            || self.is_moderator(&user.uuid)
+        {
+            return false;
+        }
+
+        let grace = Duration::from_secs(cfg.join_cooldown_grace_secs);
+        self.last_seen
+            .get(&user.uuid)
+            .is_none_or(|seen| self.now().duration_since(seen.time) > grace)
+    }
+
+    /// Enforces `server.max_sessions_per_user` before `user_id` logs in as `user`.
+    ///
+    /// Depending on `server.session_limit`, either the new login is rejected
+    /// or the oldest sessions of the user are closed.
+    /// Returns whether the login is rejected.
+    fn enforce_session_limit(&self, user_id: InternalId, user: &User) -> bool {
+        let max = self.config.server.max_sessions_per_user;
+        if max == 0 {
+            return false;
+        }
+        let mut sessions: Vec<InternalId> = self
+            .sessions
+            .sessions_of(&user.name.canonical())
+            .filter(|(id, _, info)| *id != user_id && info.uuid == user.uuid)
+            .map(|(id, _, _)| id)
+            .collect();
+        if sessions.len() < max {
+            return false;
+        }
+
+        match self.config.server.session_limit {
+            SessionLimit::Reject => {
+                info!(
+                    "User `{}` already has {} sessions as `{}`.",
+                    user_id,
+                    sessions.len(),
+                    user.name
+                );
+                self.send_error(user_id, ClientError::TooManySessions { max });
+                true
+            }
+            SessionLimit::KickOldest => {
+                sessions.sort();
+                for id in &sessions[..=sessions.len() - max] {
+                    info!(
+                        "Closing session `{}` of `{}`, which has too many sessions.",
+                        id, user.name
+                    );
+                    self.sessions[id]
+                        .close
+                        .do_send(Close(DisconnectReason::SessionLimit))
+                        .ok();
+                }
+                false
+            }
+        }
+    }
+}
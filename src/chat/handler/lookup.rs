@@ -0,0 +1,146 @@
+use log::*;
+
+use super::{ChatServer, ClientPacket};
+use crate::chat::{cluster, CanonicalId, DisplayName, InternalId, UserStatus};
+use crate::error::*;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// When a session of a user closed for the last time.
+pub(in crate::chat) struct LastSeen {
+    name: DisplayName,
+    pub(in crate::chat) time: Instant,
+}
+
+/// Who is currently connected with a uuid, or was connected with it recently.
+#[derive(Serialize, Clone)]
+pub struct UserLookup {
+    pub uuid: Uuid,
+    /// The name of the user, which is used to address them.
+    pub id: String,
+    pub online: bool,
+    /// The number of connections logged in as the user on this instance.
+    pub sessions: u32,
+    /// When the last session of an offline user closed, in milliseconds since the unix epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<u64>,
+    /// The names the user used before, newest first.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub previous_names: Vec<String>,
+    /// The status of an online user.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<UserStatus>,
+}
+
+impl ChatServer {
+    /// Sends the moderator `user_id` who is connected with `uuid`.
+    pub(super) fn handle_lookup_uuid(&mut self, user_id: InternalId, uuid: Uuid) {
+        let session = self
+            .sessions
+            .get(&user_id)
+            .expect("could not find connection");
+        let packet = match &session.user {
+            Some(info) if self.is_moderator(&info.uuid) => match self.lookup_uuid(&uuid) {
+                Some(lookup) => ClientPacket::UserLookup(lookup),
+                None => ClientPacket::Error {
+                    message: ClientError::UserNotFound,
+                },
+            },
+            Some(_) => {
+                info!(
+                    "User `{}` tried to look up a uuid without permission.",
+                    user_id
+                );
+                ClientPacket::Error {
+                    message: ClientError::NotPermitted,
+                }
+            }
+            None => ClientPacket::Error {
+                message: ClientError::NotLoggedIn,
+            },
+        };
+        self.send_to(user_id, session, packet);
+    }
+
+    /// Looks up `uuid` in the index of logged in connections,
+    /// or in the users seen within `server.last_seen_duration`.
+    pub(in crate::chat) fn lookup_uuid(&self, uuid: &Uuid) -> Option<UserLookup> {
+        let sessions: Vec<InternalId> = self.sessions.sessions_for_uuid(uuid).collect();
+        if !sessions.is_empty() {
+            // The newest session has the current name of the user.
+            let id = sessions
+                .iter()
+                .max()
+                .and_then(|id| self.sessions.get(id))
+                .and_then(|session| session.user.as_ref())
+                .map(|user| user.name.to_string())
+                .expect("the uuid index should only contain logged in connections");
+            let status = self
+                .sessions
+                .user(&CanonicalId::from_display_name(&id))
+                .map(|user| user.status);
+            return Some(UserLookup {
+                uuid: *uuid,
+                id,
+                online: true,
+                sessions: sessions.len() as u32,
+                last_seen: None,
+                previous_names: self.previous_names_of(uuid),
+                status,
+            });
+        }
+
+        let last_seen = self.last_seen.get(uuid)?;
+        let elapsed = self.now().duration_since(last_seen.time);
+        if elapsed > *self.config.server.last_seen_duration {
+            return None;
+        }
+        Some(UserLookup {
+            uuid: *uuid,
+            id: last_seen.name.to_string(),
+            online: false,
+            sessions: 0,
+            last_seen: Some(cluster::unix_millis(self.system_now() - elapsed)),
+            previous_names: self.previous_names_of(uuid),
+            status: None,
+        })
+    }
+
+    fn previous_names_of(&self, uuid: &Uuid) -> Vec<String> {
+        self.previous_names
+            .get(uuid)
+            .map_or_else(Vec::new, |names| {
+                names
+                    .iter()
+                    .rev()
+                    .map(|previous| previous.name.to_string())
+                    .collect()
+            })
+    }
+
+    /// Remembers when a session of `name` closed.
+    ///
+    /// Entries older than `server.last_seen_duration` and the grace period of the join cooldown are dropped.
+    pub(in crate::chat) fn remember_last_seen(&mut self, name: &DisplayName, uuid: Uuid) {
+        self.prune_last_seen();
+        self.last_seen.insert(
+            uuid,
+            LastSeen {
+                name: name.clone(),
+                time: self.now(),
+            },
+        );
+    }
+
+    /// Drops the entries older than `server.last_seen_duration` and the grace period of the join cooldown,
+    /// which is also a maintenance job, since they are otherwise only dropped when a session closes.
+    pub(in crate::chat) fn prune_last_seen(&mut self) {
+        let retention = (*self.config.server.last_seen_duration).max(Duration::from_secs(
+            self.config.moderation.join_cooldown_grace_secs,
+        ));
+        let now = self.now();
+        self.last_seen
+            .retain(|_, last_seen| now.duration_since(last_seen.time) <= retention);
+    }
+}
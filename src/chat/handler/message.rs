@@ -1,171 +1,1114 @@
+use super::events::{ModerationEventKind, REPEATED_RATE_LIMIT};
 use super::{ChatServer, ClientPacket};
 use crate::auth::UserInfo;
-use crate::chat::{InternalId, SessionState};
+use crate::chat::{
+    cluster::{self, ClusterEvent, Routing},
+    compat::EncodedPacket,
+    delivery::{BroadcastContext, FrameOutcome},
+    firehose::EventKind,
+    history::Reply,
+    AuthorKind, CanonicalId, Capabilities, InternalId, MessageFlags, SessionState, UserStatus,
+};
+use crate::config::{BlockedWordsAction, LengthUnit};
+use crate::message::{find_url, ValidatedContent};
+use crate::rules::Rejection;
+use crate::transform::transform;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use uuid::Uuid;
 
 use crate::error::*;
 use log::*;
 
+use actix::*;
+
 impl ChatServer {
-    pub(super) fn handle_message(&mut self, user_id: InternalId, content: String) {
-        if self.check_ratelimit(user_id, content.clone()) {
+    /// Handles a broadcast message of `user_id`.
+    ///
+    /// Bots may send it on behalf of a user of another chat with its `origin`,
+    /// like `discord`; for everyone else, setting it is not permitted.
+    /// `reply_to` is the sequence number of the message it replies to,
+    /// which does not have to be in the history anymore.
+    pub(super) fn handle_message(
+        &mut self,
+        user_id: InternalId,
+        content: String,
+        origin: Option<String>,
+        reply_to: Option<u64>,
+        ctx: &mut Context<Self>,
+    ) {
+        if self.config.commands.enabled && content.starts_with('/') {
+            self.handle_command(user_id, &content[1..]);
             return;
         }
 
-        if self.basic_check(user_id, &content).is_some() {
-            let session = self
-                .connections
-                .get_mut(&user_id)
-                .expect("could not find connection");
+        let content = match self.transform_content(user_id, content) {
+            Some(content) => content,
+            None => return,
+        };
 
+        if self.check_ratelimit(user_id, &content) {
+            return;
+        }
+        if self.check_probation(user_id, &content, false) {
+            return;
+        }
+        if self.check_join_cooldown(user_id, false) {
+            return;
+        }
+
+        if let Some((session, content)) = self.basic_check(user_id, &content) {
             let info = session.user.as_ref().unwrap();
+            let author_kind = match origin {
+                None => AuthorKind::Player,
+                Some(origin) if self.is_bot(&info.uuid) => AuthorKind::Bridge { origin },
+                Some(_) => {
+                    info!(
+                        "User `{}` tried to send a bridged message without being a bot.",
+                        user_id
+                    );
+                    self.send_error(user_id, ClientError::NotPermitted);
+                    return;
+                }
+            };
+            let author_info = UserInfo {
+                name: info.name.to_string(),
+                uuid: info.uuid,
+                bot: self.is_bot(&info.uuid),
+            };
 
-            info!("User `{}` has written `{}`.", user_id, content);
-            let client_packet = ClientPacket::Message {
-                author_info: UserInfo {
-                    name: info.name.clone(),
-                    uuid: info.uuid,
-                },
-                content,
+            let content = match self.apply_hooks(user_id, content, |hook, content| {
+                hook.on_message(&author_info, content)
+            }) {
+                Some(content) => content,
+                None => return,
             };
-            for session in self.connections.values() {
-                if let Err(err) = session.addr.do_send(client_packet.clone()) {
-                    warn!("Could not send message to client: {}", err);
-                }
+
+            // The review completes after a simulation was torn down.
+            if self.config.moderation.review_url.is_some() && !self.simulating {
+                self.review_message(user_id, author_info, author_kind, content, reply_to, ctx);
+            } else {
+                self.broadcast_message(user_id, author_info, author_kind, content, reply_to);
+            }
+        }
+    }
+
+    /// Sends a message of `user_id` to every connected client.
+    ///
+    /// Other instances of a cluster do not share sequence numbers, so they receive it without `reply_to`.
+    pub(in crate::chat) fn broadcast_message(
+        &mut self,
+        user_id: InternalId,
+        author_info: UserInfo,
+        author_kind: AuthorKind,
+        content: ValidatedContent,
+        reply_to: Option<u64>,
+    ) {
+        info!("User `{}` has written `{}`.", user_id, content);
+        let event = ClusterEvent::Message {
+            author_info: author_info.clone(),
+            author_kind: author_kind.clone(),
+            content: content.to_string(),
+            redacted: content.is_redacted(),
+        };
+        let uuid = author_info.uuid;
+        // Other instances only receive messages which fit into a frame.
+        match self.deliver_message(Some(user_id), author_info, author_kind, content, reply_to) {
+            Ok(_) => {
+                self.record_stats_message(uuid);
+                self.publish(event);
+            }
+            Err(err) => {
+                self.send_error(user_id, err);
             }
         }
     }
 
+    /// Sends a message to every client connected to this instance.
+    ///
+    /// The message is numbered and stored in the history before it is sent.
+    /// If the connection of the `author` does not want its own messages echoed,
+    /// it only receives a [`ClientPacket::MessageAck`];
+    /// other connections of the same user still receive the message.
+    /// Authors supporting delivery counts always receive the acknowledgement,
+    /// after their own message if it is echoed.
+    ///
+    /// If the `Message` packet is larger than `server.max_outgoing_frame_bytes`,
+    /// it is sent without the reply excerpt; if it is still too large,
+    /// the message is neither stored nor sent and `MessageTooLong` is returned.
+    ///
+    /// Returns to how many connections other than the author's the message was sent.
+    pub(in crate::chat) fn deliver_message(
+        &mut self,
+        author: Option<InternalId>,
+        author_info: UserInfo,
+        author_kind: AuthorKind,
+        content: ValidatedContent,
+        reply_to: Option<u64>,
+    ) -> std::result::Result<u32, ClientError> {
+        // The excerpt is taken before the message is stored, which may evict the original.
+        let mut reply = reply_to.map(|seq| self.history.reply_to(seq));
+        let seq = self.history.next_seq();
+        // The message is signed once, before it is sent to every connection.
+        let signature = self.signer.as_ref().map(|signer| {
+            signer.sign(
+                seq,
+                cluster::unix_millis(self.system_now()),
+                &author_info.uuid,
+                content.as_str(),
+            )
+        });
+        let message = |reply: Option<Reply>, flags| ClientPacket::Message {
+            seq,
+            author_info: author_info.clone(),
+            author_kind: author_kind.clone(),
+            content: content.clone(),
+            signature: signature.clone(),
+            reactions: BTreeMap::new(),
+            reply,
+            flags,
+        };
+        // The packet of the author is the largest one, since it is flagged as their own.
+        // It is encoded once, to measure it and to send it.
+        let own_message = |reply: &Option<Reply>| {
+            let flags = self.message_flags(&author_info, &author_kind, &content, reply.as_ref());
+            EncodedPacket::new(message(reply.clone(), flags.with(MessageFlags::OWN)))
+        };
+
+        let max_bytes = self.config.server.max_outgoing_frame_bytes;
+        let mut own_packet = own_message(&reply);
+        let mut bytes = own_packet.size();
+        let mut outcome = FrameOutcome::Sent;
+        if max_bytes != 0 && bytes > max_bytes {
+            // Mentions would be dropped after the excerpt, once messages have them.
+            let excerpt = reply.as_mut().and_then(|reply| reply.reply_excerpt.take());
+            if excerpt.is_some() {
+                own_packet = own_message(&reply);
+                warn!(
+                    "Sending message `{}` without its reply excerpt, since its frame of {} bytes is larger than {} bytes.",
+                    seq, bytes, max_bytes
+                );
+                bytes = own_packet.size();
+                outcome = FrameOutcome::Degraded;
+            }
+            if bytes > max_bytes {
+                outcome = FrameOutcome::Rejected;
+            }
+        }
+        // Simulated messages were never sent.
+        if !self.simulating {
+            self.delivery.record_frame(bytes, outcome);
+        }
+        if outcome == FrameOutcome::Rejected {
+            warn!(
+                "Rejected message of `{}`, since its frame of {} bytes is larger than {} bytes.",
+                author_info.name, bytes, max_bytes
+            );
+            return Err(ClientError::MessageTooLong {
+                length: bytes,
+                max_length: max_bytes,
+                unit: LengthUnit::Bytes,
+            });
+        }
+
+        let flags = self.message_flags(&author_info, &author_kind, &content, reply.as_ref());
+        self.history.push(
+            author_info.clone(),
+            author_kind.clone(),
+            content.clone(),
+            signature.clone(),
+            reply.clone(),
+            flags,
+        );
+        self.notify_hooks(|hook| hook.on_broadcast(&author_info, &author_kind, content.as_str()));
+        // The connections of the author receive their message flagged as their own.
+        let client_packet = EncodedPacket::new(message(reply, flags));
+        self.publish_firehose(EventKind::Message, || client_packet.packet.clone());
+        let context = BroadcastContext { author };
+        let delivers = self.delivery_filter(&context);
+        let mut delivery_count = 0;
+        for (id, session) in self.sessions.iter() {
+            if !delivers(*id, session) {
+                continue;
+            }
+            let packet = match &session.user {
+                Some(info) if info.uuid == author_info.uuid => &own_packet,
+                _ => &client_packet,
+            };
+            if self.send_encoded_to(*id, session, packet) && Some(*id) != author {
+                delivery_count += 1;
+            }
+        }
+
+        if let Some((id, session)) = author.and_then(|id| Some((id, self.sessions.get(&id)?))) {
+            let report = session.capabilities.contains(Capabilities::DELIVERY_COUNTS);
+            if report || !session.echo_own_messages {
+                let ack = ClientPacket::MessageAck {
+                    seq,
+                    timestamp: cluster::unix_millis(self.system_now()),
+                    delivery_count: if report { Some(delivery_count) } else { None },
+                };
+                self.send_to(id, session, ack);
+            }
+        }
+        Ok(delivery_count)
+    }
+
+    /// Rejects a private message of `user_id` replying to another message,
+    /// since only broadcast messages can be replied to.
+    pub(super) fn reject_private_reply(&self, user_id: InternalId) {
+        info!("User `{}` tried to reply with a private message.", user_id);
+        self.send_error(user_id, ClientError::NotSupported);
+    }
+
+    /// Handles a private message of `user_id` to `receiver`.
+    ///
+    /// The sender is only looked up once, when the message is validated;
+    /// the error or acknowledgement at the end is not sent if it disconnected in between.
     pub(super) fn handle_private_message(
         &mut self,
         user_id: InternalId,
         receiver: String,
         content: String,
     ) {
-        if self.check_ratelimit(user_id, content.clone()) {
+        let content = match self.transform_content(user_id, content) {
+            Some(content) => content,
+            None => return,
+        };
+
+        if self.check_ratelimit(user_id, &content) {
+            return;
+        }
+        if self.check_probation(user_id, &content, true) {
+            return;
+        }
+        if self.check_join_cooldown(user_id, true) {
             return;
         }
 
-        if let Some(sender_session) = self.basic_check(user_id, &content) {
-            let sender_info = sender_session.user.as_ref().unwrap();
+        if let Some((session, content)) = self.basic_check(user_id, &content) {
+            let info = session.user.as_ref().unwrap();
+            let author_info = UserInfo {
+                name: info.name.to_string(),
+                uuid: info.uuid,
+                bot: self.is_bot(&info.uuid),
+            };
+            self.send_private_message(user_id, author_info, receiver, PrivateBody::Plain(content));
+        }
+    }
 
-            let receiver_user = match self.users.get(&receiver) {
-                Some(user) => user,
-                None => {
+    /// Handles an end-to-end encrypted private message of `user_id` to `receiver`.
+    ///
+    /// The server can not read the payload, so it is neither validated nor passed to the hooks;
+    /// only its size, the rate limit and the restrictions of the sender are checked.
+    pub(super) fn handle_encrypted_private_message(
+        &mut self,
+        user_id: InternalId,
+        receiver: String,
+        payload: Option<String>,
+    ) {
+        let max_size = self.config.message.max_encrypted_size;
+        let res = match payload {
+            _ if !self.config.message.allow_encrypted_private => Err(ClientError::NotSupported),
+            None => Err(ClientError::InvalidPayload),
+            Some(payload) if payload.is_empty() => Err(ClientError::EmptyMessage),
+            Some(payload) if payload.len() > max_size => Err(ClientError::MessageTooLong {
+                length: payload.len(),
+                max_length: max_size,
+                unit: LengthUnit::Bytes,
+            }),
+            Some(payload) => match base64::decode(&payload) {
+                Ok(_) => Ok(payload),
+                Err(_) => Err(ClientError::InvalidPayload),
+            },
+        };
+        let payload = match res {
+            Ok(payload) => payload,
+            Err(message) => {
+                info!(
+                    "User `{}` tried to send an invalid encrypted message: {}",
+                    user_id, message
+                );
+                self.send_error(user_id, message);
+                return;
+            }
+        };
+
+        if self.check_ratelimit(user_id, &payload) {
+            return;
+        }
+        if self.check_probation(user_id, "", true) {
+            return;
+        }
+        if self.check_join_cooldown(user_id, true) {
+            return;
+        }
+
+        let session = match self.sessions.get(&user_id) {
+            Some(session) => session,
+            None => {
+                debug!(
+                    "User `{}` disconnected before its message was handled.",
+                    user_id
+                );
+                return;
+            }
+        };
+        let author_info = match &session.user {
+            Some(info) if self.moderation.is_banned(&info.uuid, self.system_now()) => {
+                info!("User `{}` tried to send message while banned", user_id);
+                self.send_error(user_id, ClientError::Banned);
+                return;
+            }
+            Some(info) => UserInfo {
+                name: info.name.to_string(),
+                uuid: info.uuid,
+                bot: self.is_bot(&info.uuid),
+            },
+            None => {
+                self.send_error(user_id, ClientError::NotLoggedIn);
+                return;
+            }
+        };
+        if let Some(err) = self.mute_error(&author_info.uuid) {
+            info!("User `{}` tried to send message while muted", user_id);
+            self.send_error(user_id, err);
+            return;
+        }
+        let body = PrivateBody::Encrypted(payload);
+        self.send_private_message(user_id, author_info, receiver, body);
+    }
+
+    /// Sends the validated or encrypted private message of `user_id` to `receiver`,
+    /// and tells the sender if it could not be delivered.
+    fn send_private_message(
+        &mut self,
+        user_id: InternalId,
+        author_info: UserInfo,
+        mut receiver: String,
+        body: PrivateBody,
+    ) {
+        let mut receiver_id = CanonicalId::parse_client_input(&receiver);
+        if !self.sessions.is_online(&receiver_id) {
+            match self.resolve_renamed(&receiver_id) {
+                Some(Ok(current)) => {
+                    debug!("Resolved renamed user `{}` to `{}`.", receiver, current);
+                    receiver_id = CanonicalId::from_display_name(&current);
+                    receiver = current;
+                }
+                Some(Err(message)) => {
+                    self.send_error(user_id, message);
+                    return;
+                }
+                None => {}
+            }
+        }
+
+        let body = match body {
+            PrivateBody::Plain(content) => {
+                match self.apply_hooks(user_id, content, |hook, content| {
+                    hook.on_private_message(&author_info, &receiver, content)
+                }) {
+                    Some(content) => PrivateBody::Plain(content),
+                    None => return,
+                }
+            }
+            // The hooks can not read the payload, so they do not decide about it.
+            body @ PrivateBody::Encrypted(_) => body,
+        };
+
+        let id = self.next_private_id;
+        self.next_private_id += 1;
+        let timestamp = cluster::unix_millis(self.system_now());
+
+        if !self.sessions.is_online(&receiver_id) {
+            let author = author_info.uuid;
+            // The instance hosting the receiver does not report back, so there is no count.
+            match self.route_private_message(user_id, receiver_id, author_info, id, timestamp, body)
+            {
+                Routing::Routed => self.record_stats_message(author),
+                Routing::NoCluster => {
                     debug!(
                         "User `{}` tried to write to non-existing user `{}`.",
                         user_id, receiver
                     );
+                    self.send_error(user_id, ClientError::UserNotFound);
+                }
+                Routing::Disconnected => {
+                    debug!(
+                        "Could not route private message from `{}` to `{}`: not connected to Redis",
+                        user_id, receiver
+                    );
+                    self.send_error(user_id, ClientError::DeliveryFailed);
+                }
+                Routing::Dropped => {}
+            }
+            return;
+        }
+
+        let delivery_count =
+            match self.deliver_private_message(&receiver_id, &author_info, id, timestamp, &body) {
+                Ok(delivery_count) => delivery_count,
+                Err(err) => {
+                    debug!(
+                        "Could not deliver private message from `{}` to `{}`: {}",
+                        user_id, receiver, err
+                    );
+                    self.send_error(user_id, err);
                     return;
                 }
             };
+        info!(
+            "User `{}` has written to `{}` privately.",
+            user_id, receiver
+        );
+        self.record_stats_message(author_info.uuid);
+        self.echo_private_message(user_id, receiver_id, &author_info, id, timestamp, body);
+        if self
+            .sessions
+            .get(&user_id)
+            .is_some_and(|session| session.capabilities.contains(Capabilities::DELIVERY_COUNTS))
+        {
+            self.reply(
+                user_id,
+                ClientPacket::PrivateMessageAck {
+                    receiver,
+                    delivery_count,
+                },
+            );
+        }
+    }
 
-            for receiver_session in receiver_user
-                .connections
-                .iter()
-                .filter_map(|id| self.connections.get(id))
-            {
-                match &receiver_session.user {
-                    Some(info) if info.allow_messages => {
-                        let client_packet = ClientPacket::PrivateMessage {
-                            author_info: UserInfo {
-                                name: sender_info.name.clone(),
-                                uuid: sender_info.uuid,
-                            },
-                            content: content.clone(),
-                        };
-                        info!(
-                            "User `{}` has written to `{}` privately.",
-                            user_id, receiver
-                        );
-                        if let Err(err) = receiver_session.addr.do_send(client_packet) {
-                            warn!("Could not send private message to client: {}", err);
-                        } else {
-                            return;
-                        }
+    /// Sends a private message to the connections of `receiver` on this instance
+    /// which accept private messages, and returns to how many connections it was sent.
+    ///
+    /// Connections which can not receive it anymore are removed.
+    /// Fails with `UserNotFound` if the receiver has no live connection,
+    /// `DoNotDisturb` if they do not want to be disturbed, `PrivateMessageNotAccepted`
+    /// if none of their connections accepts private messages, and `DeliveryFailed`
+    /// if all the connections which do are dead.
+    pub(in crate::chat) fn deliver_private_message(
+        &mut self,
+        receiver: &CanonicalId,
+        author_info: &UserInfo,
+        id: u64,
+        timestamp: u64,
+        body: &PrivateBody,
+    ) -> std::result::Result<u32, ClientError> {
+        let receiver_user = self
+            .sessions
+            .user(receiver)
+            .ok_or(ClientError::UserNotFound)?;
+        if receiver_user.status == UserStatus::Dnd {
+            return Err(ClientError::DoNotDisturb);
+        }
+
+        let mut live = false;
+        let mut delivery_count = 0;
+        let mut dead = Vec::new();
+        let mut receiver_uuid = None;
+        let flags = self.private_message_flags(author_info, body);
+        for (receiver_id, receiver_session) in receiver_user
+            .connections
+            .iter()
+            .filter_map(|id| Some((*id, self.sessions.get(id)?)))
+        {
+            live = true;
+            match &receiver_session.user {
+                Some(info) if info.allow_messages => {
+                    let client_packet = ClientPacket::PrivateMessage {
+                        author_info: author_info.clone(),
+                        conversation: CanonicalId::from_display_name(&author_info.name),
+                        id,
+                        timestamp,
+                        body: body.clone(),
+                        flags,
+                    };
+                    if self.send_to(receiver_id, receiver_session, client_packet) {
+                        delivery_count += 1;
+                        receiver_uuid = Some(info.uuid);
+                    } else {
+                        dead.push(receiver_id);
                     }
-                    _ => {}
                 }
+                _ => {}
             }
         }
 
-        let _ = self
-            .connections
-            .get_mut(&user_id)
-            .expect("could not find connection")
-            .addr
-            .do_send(ClientPacket::Error {
-                message: ClientError::PrivateMessageNotAccepted,
-            });
+        let failed = !dead.is_empty();
+        for receiver_id in dead {
+            info!(
+                "Removing connection `{}`, which could not receive a private message.",
+                receiver_id
+            );
+            self.remove_session(receiver_id, false, None);
+        }
+
+        if let Some(receiver_uuid) = receiver_uuid {
+            self.record_pm_metadata(author_info.uuid, receiver_uuid, body.len());
+        }
+
+        if !live {
+            Err(ClientError::UserNotFound)
+        } else if delivery_count > 0 {
+            Ok(delivery_count)
+        } else if failed {
+            Err(ClientError::DeliveryFailed)
+        } else {
+            Err(ClientError::PrivateMessageNotAccepted)
+        }
     }
 
-    fn basic_check(&self, user_id: InternalId, content: &str) -> Option<&SessionState> {
-        let session = self
+    /// Sends a delivered private message of `user_id` to `receiver`
+    /// to the connections of the author supporting private message echoes.
+    ///
+    /// The connection which sent it only receives it if it wants its own messages echoed.
+    fn echo_private_message(
+        &self,
+        user_id: InternalId,
+        receiver: CanonicalId,
+        author_info: &UserInfo,
+        id: u64,
+        timestamp: u64,
+        body: PrivateBody,
+    ) {
+        let author = match self
+            .sessions
+            .user(&CanonicalId::from_display_name(&author_info.name))
+        {
+            Some(author) => author,
+            None => return,
+        };
+        let flags = self.private_message_flags(author_info, &body);
+        let packet = ClientPacket::PrivateMessage {
+            author_info: author_info.clone(),
+            conversation: receiver,
+            id,
+            timestamp,
+            body,
+            flags: flags.with(MessageFlags::OWN),
+        };
+        for (connection, session) in author
             .connections
-            .get(&user_id)
-            .expect("could not find connection");
+            .iter()
+            .filter_map(|connection| Some((connection, self.sessions.get(connection)?)))
+        {
+            if !session
+                .capabilities
+                .contains(Capabilities::PRIVATE_MESSAGE_ECHO)
+                || (*connection == user_id && !session.echo_own_messages)
+            {
+                continue;
+            }
+            self.send_to(*connection, session, packet.clone());
+        }
+    }
 
-        if let Some(info) = &session.user {
-            if let Err(err) = self.validator.validate(content) {
+    /// Logs, counts and reports the `violations` of a message of `user_id`
+    /// which is delivered anyway because of `validation.dry_run`.
+    pub(in crate::chat) fn record_dry_run(
+        &self,
+        user_id: InternalId,
+        content: &str,
+        violations: &[Rejection],
+    ) {
+        let rules: Vec<&'static str> = violations
+            .iter()
+            .map(|violation| violation.error.translation_key())
+            .collect();
+        let names: Vec<&'static str> = violations.iter().map(|violation| violation.rule).collect();
+        info!(
+            "Message of user `{}` would have been rejected by {}.",
+            user_id,
+            names.join(", ")
+        );
+        for rule in &rules {
+            self.validation_stats.record_dry_run(rule);
+        }
+        let rule = match self.validator.word_filter().find(content) {
+            Some(word) => word.to_string(),
+            None => rules.join(", "),
+        };
+        self.notify_moderators(user_id, ModerationEventKind::DryRun, content, &rule);
+    }
+
+    /// Applies `validation.transforms` to a message of `user_id`.
+    /// Returns `None` and tells the client if the transformed message exceeds `validation.max_bytes`.
+    fn transform_content(&self, user_id: InternalId, content: String) -> Option<String> {
+        let cfg = &self.config.validation;
+        match transform(&cfg.transforms, &content, &self.emotes, cfg.max_bytes) {
+            Ok(transformed) => Some(transformed),
+            Err(err) => {
                 info!("User `{}` tried to send invalid message: {}", user_id, err);
                 if let Error::AxoChat { source } = err {
-                    session
-                        .addr
-                        .do_send(ClientPacket::Error { message: source })
-                        .ok();
+                    self.send_error(user_id, source);
                 }
+                None
+            }
+        }
+    }
 
+    /// Validates the message `content` of `user_id`, who has to be logged in and not banned.
+    ///
+    /// If the message may not be sent, the user is told why and `None` is returned.
+    fn basic_check(
+        &self,
+        user_id: InternalId,
+        content: &str,
+    ) -> Option<(&SessionState, ValidatedContent)> {
+        let session = match self.sessions.get(&user_id) {
+            Some(session) => session,
+            None => {
+                debug!(
+                    "User `{}` disconnected before its message was handled.",
+                    user_id
+                );
                 return None;
             }
-            if self.moderation.is_banned(&info.uuid) {
+        };
+
+        if let Some(info) = &session.user {
+            let is_moderator = self.is_moderator(&info.uuid);
+            let res = self.validator.check(content).and_then(|validated| {
+                self.validator
+                    .check_links(content, is_moderator)
+                    .map(|()| validated)
+            });
+            let validated = match res {
+                Ok(validated) => {
+                    self.report_redaction(user_id, content);
+                    validated
+                }
+                Err(_) if self.config.validation.dry_run => {
+                    let violations = self.validator.violations(content, is_moderator);
+                    self.record_dry_run(user_id, content, &violations);
+                    self.validator.unchecked(content)
+                }
+                Err(rejection) => {
+                    info!(
+                        "User `{}` tried to send invalid message rejected by `{}`: {}",
+                        user_id, rejection.rule, rejection.error
+                    );
+                    if let ClientError::BlockedContent = rejection.error {
+                        if let Some(word) = self.validator.word_filter().find(content) {
+                            self.notify_moderators(
+                                user_id,
+                                ModerationEventKind::BlockedWord,
+                                content,
+                                word,
+                            );
+                        }
+                    }
+                    self.record_rejection(&rejection);
+                    self.send_rejection(user_id, rejection);
+
+                    return None;
+                }
+            };
+            if self.moderation.is_banned(&info.uuid, self.system_now()) {
                 info!("User `{}` tried to send message while banned", user_id);
-                session
-                    .addr
-                    .do_send(ClientPacket::Error {
-                        message: ClientError::Banned,
-                    })
-                    .ok();
+                self.send_error(user_id, ClientError::Banned);
+
+                return None;
+            }
+            if let Some(err) = self.mute_error(&info.uuid) {
+                info!("User `{}` tried to send message while muted", user_id);
+                self.send_error(user_id, err);
 
                 return None;
             }
 
-            Some(session)
+            Some((session, validated))
         } else {
             info!("`{}` is not logged in.", user_id);
-            session
-                .addr
-                .do_send(ClientPacket::Error {
-                    message: ClientError::NotLoggedIn,
-                })
-                .ok();
+            self.send_error(user_id, ClientError::NotLoggedIn);
             None
         }
     }
 
-    fn check_ratelimit(&mut self, user_id: InternalId, message: String) -> bool {
-        let session = self
-            .connections
-            .get(&user_id)
-            .expect("could not find connection");
+    /// Tells the subscribed moderators that the blocked words in the message `content` of `user_id`
+    /// were redacted, if `validation.report_redactions` is enabled.
+    fn report_redaction(&self, user_id: InternalId, content: &str) {
+        let cfg = &self.config.validation;
+        if !cfg.report_redactions || cfg.blocked_words_action != BlockedWordsAction::Redact {
+            return;
+        }
+        if let Some(word) = self.validator.word_filter().find(content) {
+            self.notify_moderators(user_id, ModerationEventKind::Redacted, content, word);
+        }
+    }
+
+    pub(super) fn check_ratelimit(&mut self, user_id: InternalId, message: &str) -> bool {
+        let session = match self.sessions.get(&user_id) {
+            Some(session) => session,
+            None => {
+                debug!(
+                    "User `{}` disconnected before its message was handled.",
+                    user_id
+                );
+                return true;
+            }
+        };
 
-        if let Some(user) = &session.user {
-            let user = self.users.get_mut(&user.name).unwrap();
-            if user.rate_limiter.check_new_message(message) {
+        if let Some(uuid) = session.user.as_ref().map(|user| user.uuid) {
+            let max_messages = self.max_messages(&uuid);
+            let now = self.now();
+
+            let (_, user) = self.sessions.user_of_mut(&user_id).unwrap();
+            if user
+                .rate_limiter
+                .check_new_message(message.to_string(), max_messages, now)
+            {
                 info!(
                     "User `{}` tried to send message, but was rate limited.",
                     user_id
                 );
-                session
-                    .addr
-                    .do_send(ClientPacket::Error {
-                        message: ClientError::RateLimited,
-                    })
-                    .ok();
+                user.rate_limit_violations += 1;
+                let repeated = user.rate_limit_violations == REPEATED_RATE_LIMIT;
+                self.send_error(user_id, ClientError::RateLimited);
+                if repeated {
+                    self.notify_moderators(
+                        user_id,
+                        ModerationEventKind::RateLimit,
+                        message,
+                        "rate limit",
+                    );
+                }
                 true
             } else {
+                user.rate_limit_violations = 0;
                 false
             }
         } else {
             false
         }
     }
+
+    /// Returns if the user in probation is not allowed to send this message.
+    fn check_probation(&self, user_id: InternalId, content: &str, private: bool) -> bool {
+        let session = match self.sessions.get(&user_id) {
+            Some(session) => session,
+            None => {
+                debug!(
+                    "User `{}` disconnected before its message was handled.",
+                    user_id
+                );
+                return true;
+            }
+        };
+
+        let remaining = match &session.user {
+            Some(info) => match self.probation_remaining(&info.uuid) {
+                Some(remaining) => remaining,
+                None => return false,
+            },
+            None => return false,
+        };
+
+        let cfg = &self.config.moderation;
+        let blocked = if private {
+            cfg.probation_block_private
+        } else {
+            cfg.probation_block_links && find_url(content).is_some()
+        };
+        if blocked {
+            info!(
+                "User `{}` tried to send message, but is in probation.",
+                user_id
+            );
+            self.notify_moderators(
+                user_id,
+                ModerationEventKind::Probation,
+                content,
+                if private {
+                    "probation: private messages"
+                } else {
+                    "probation: links"
+                },
+            );
+            let remaining_secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+            self.send_error(user_id, ClientError::Probation { remaining_secs });
+        }
+        blocked
+    }
+
+    /// Returns if the user logged in too recently to send this message.
+    fn check_join_cooldown(&self, user_id: InternalId, private: bool) -> bool {
+        let session = match self.sessions.get(&user_id) {
+            Some(session) => session,
+            None => {
+                debug!(
+                    "User `{}` disconnected before its message was handled.",
+                    user_id
+                );
+                return true;
+            }
+        };
+        if private && !self.config.moderation.join_cooldown_block_private {
+            return false;
+        }
+
+        let cooldown = Duration::from_secs(self.config.moderation.join_cooldown_secs);
+        let remaining = match session
+            .cooldown_since
+            .and_then(|since| cooldown.checked_sub(self.now().duration_since(since)))
+            .filter(|remaining| *remaining > Duration::from_secs(0))
+        {
+            Some(remaining) => remaining,
+            None => return false,
+        };
+
+        info!(
+            "User `{}` tried to send message, but joined too recently.",
+            user_id
+        );
+        let remaining_secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+        self.send_error(user_id, ClientError::JoinCooldown { remaining_secs });
+        true
+    }
+
+    /// Returns how long the user stays in probation, if they are in probation.
+    pub(super) fn probation_remaining(&self, uuid: &Uuid) -> Option<Duration> {
+        let probation = Duration::from_secs(self.config.moderation.probation_secs);
+        if probation == Duration::from_secs(0)
+            || self.is_bot(uuid)
+            || self.is_moderator(uuid)
+            || self.moderation.is_whitelisted(uuid)
+        {
+            return None;
+        }
+
+        let first_seen = self.storage.first_seen(uuid)?;
+        let elapsed = self
+            .system_now()
+            .duration_since(first_seen)
+            .unwrap_or_default();
+        probation
+            .checked_sub(elapsed)
+            .filter(|remaining| *remaining > Duration::from_secs(0))
+    }
+}
+
+/// What a private message carries.
+#[derive(Debug, Clone)]
+pub(in crate::chat) enum PrivateBody {
+    /// Validated text, sent as `content`.
+    Plain(ValidatedContent),
+    /// The base64 payload of an end-to-end encrypted message, which is relayed untouched
+    /// and sent as `payload` next to `"encrypted": true`.
+    Encrypted(String),
+}
+
+impl PrivateBody {
+    /// The length of the content or payload in bytes.
+    pub(in crate::chat) fn len(&self) -> usize {
+        match self {
+            PrivateBody::Plain(content) => content.as_str().len(),
+            PrivateBody::Encrypted(payload) => payload.len(),
+        }
+    }
+}
+
+impl Serialize for PrivateBody {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            PrivateBody::Plain(content) => {
+                let mut state = serializer.serialize_struct("PrivateBody", 1)?;
+                state.serialize_field("content", content)?;
+                state.end()
+            }
+            PrivateBody::Encrypted(payload) => {
+                let mut state = serializer.serialize_struct("PrivateBody", 1)?;
+                state.serialize_field("payload", payload)?;
+                state.end()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{
+        simulate::{Capture, Collect},
+        DisplayName, User,
+    };
+    use crate::config::ClusterConfig;
+    use serde_json::json;
+
+    /// Packets may still be handled after the connection which sent them was removed,
+    /// e.g. when the message to remove it overtook them.
+    #[test]
+    fn packets_of_removed_connections_are_dropped() {
+        let _system = System::new("test");
+        let mut server = ChatServer::for_tests(|config| {
+            config.message.allow_encrypted_private = true;
+            config.cluster = Some(ClusterConfig {
+                redis_url: "redis://127.0.0.1:6379".to_string(),
+                prefix: "axochat".to_string(),
+                instance_id: None,
+                registry_ttl: Duration::from_secs(30).into(),
+            });
+        });
+        let capture = Capture::default().start();
+        let user = User {
+            name: DisplayName::new("Notch".to_string()),
+            uuid: Uuid::from_u128(0x069a79f4_44e9_4726_a5be_fca90e38aaf5),
+            allow_messages: true,
+        };
+        let author_info = UserInfo {
+            name: user.name.to_string(),
+            uuid: user.uuid,
+            bot: false,
+        };
+        let id = server.connect_for_tests(&capture, Some(user));
+        server.sessions.remove(id);
+
+        server.handle_private_message(id, "jeb_".to_string(), "psst".to_string());
+        server.handle_encrypted_private_message(
+            id,
+            "jeb_".to_string(),
+            Some("cHNzdA==".to_string()),
+        );
+        assert!(server.basic_check(id, "hello").is_none());
+        assert!(server.check_ratelimit(id, "hello"));
+        assert!(server.check_probation(id, "hello", false));
+        assert!(server.check_join_cooldown(id, false));
+        assert_eq!(
+            server.route_private_message(
+                id,
+                CanonicalId::from_display_name("jeb_"),
+                author_info,
+                1,
+                0,
+                PrivateBody::Plain(ValidatedContent::trusted("psst".to_string(), false)),
+            ),
+            Routing::Dropped
+        );
+    }
+
+    fn private_message_errors(cluster: Option<ClusterConfig>) -> Vec<serde_json::Value> {
+        let mut system = System::new("test");
+        let mut server = ChatServer::for_tests(|config| config.cluster = cluster);
+        let capture = Capture::default().start();
+        let user = User {
+            name: DisplayName::new("Notch".to_string()),
+            uuid: Uuid::from_u128(0x069a79f4_44e9_4726_a5be_fca90e38aaf5),
+            allow_messages: true,
+        };
+        let id = server.connect_for_tests(&capture, Some(user));
+
+        server.handle_private_message(id, "jeb_".to_string(), "psst".to_string());
+        let simulation = system.block_on(capture.send(Collect)).unwrap();
+        simulation
+            .packets
+            .into_iter()
+            .filter(|packet| packet["m"] == "Error")
+            .map(|packet| packet["c"]["message"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn private_messages_to_unknown_users_are_not_found() {
+        assert_eq!(private_message_errors(None), [json!("UserNotFound")]);
+    }
+
+    /// Redis is not connected yet right after the cluster is configured.
+    #[test]
+    fn private_messages_fail_while_redis_is_disconnected() {
+        let cluster = ClusterConfig {
+            redis_url: "redis://127.0.0.1:6379".to_string(),
+            prefix: "axochat".to_string(),
+            instance_id: None,
+            registry_ttl: Duration::from_secs(30).into(),
+        };
+        assert_eq!(
+            private_message_errors(Some(cluster)),
+            [json!("DeliveryFailed")]
+        );
+    }
+
+    /// Delivers a reply to the message `1` as the connection of Notch with `max_bytes` as
+    /// `server.max_outgoing_frame_bytes`, and returns the result with the packet it received.
+    fn deliver_reply(
+        system: &mut SystemRunner,
+        server: &mut ChatServer,
+        max_bytes: usize,
+    ) -> (
+        std::result::Result<u32, ClientError>,
+        Option<serde_json::Value>,
+    ) {
+        server.config.server.max_outgoing_frame_bytes = max_bytes;
+        let capture = Capture::default().start();
+        let user = User {
+            name: DisplayName::new("Notch".to_string()),
+            uuid: Uuid::from_u128(0x069a79f4_44e9_4726_a5be_fca90e38aaf5),
+            allow_messages: true,
+        };
+        let author_info = UserInfo {
+            name: user.name.to_string(),
+            uuid: user.uuid,
+            bot: false,
+        };
+        let id = server.connect_for_tests(&capture, Some(user));
+        let content = ValidatedContent::trusted("a reply".to_string(), false);
+        let res =
+            server.deliver_message(Some(id), author_info, AuthorKind::Player, content, Some(1));
+        server.sessions.remove(id);
+
+        let mut packets = system.block_on(capture.send(Collect)).unwrap().packets;
+        assert!(packets.len() <= 1, "{:?}", packets);
+        (res, packets.pop())
+    }
+
+    fn frame_size(packet: &serde_json::Value) -> usize {
+        serde_json::to_string(packet).unwrap().len()
+    }
+
+    #[test]
+    fn oversized_messages_lose_their_reply_excerpt_before_they_are_rejected() {
+        let mut system = System::new("test");
+        let mut server = ChatServer::for_tests(|_| {});
+        let original = UserInfo {
+            name: "jeb_".to_string(),
+            uuid: Uuid::from_u128(0x853c80ef_3c37_49fd_aa49_938b674adae6),
+            bot: false,
+        };
+        let content = ValidatedContent::trusted("a".repeat(200), false);
+        server
+            .deliver_message(None, original, AuthorKind::Player, content, None)
+            .unwrap();
+
+        let (res, packet) = deliver_reply(&mut system, &mut server, 0);
+        res.unwrap();
+        let packet = packet.unwrap();
+        assert!(packet["c"].get("reply_excerpt").is_some());
+        let full = frame_size(&packet);
+
+        let (res, packet) = deliver_reply(&mut system, &mut server, full);
+        res.unwrap();
+        assert!(packet.unwrap()["c"].get("reply_excerpt").is_some());
+
+        let (res, packet) = deliver_reply(&mut system, &mut server, full - 1);
+        res.unwrap();
+        let packet = packet.unwrap();
+        assert!(packet["c"].get("reply_excerpt").is_none());
+        assert_eq!(packet["c"]["reply_to"], 1);
+        let degraded = frame_size(&packet);
+        assert!(degraded < full);
+
+        let (res, packet) = deliver_reply(&mut system, &mut server, degraded);
+        res.unwrap();
+        assert!(packet.unwrap()["c"].get("reply_excerpt").is_none());
+
+        let (res, packet) = deliver_reply(&mut system, &mut server, degraded - 1);
+        match res {
+            Err(ClientError::MessageTooLong {
+                length, max_length, ..
+            }) => assert_eq!((length, max_length), (degraded, degraded - 1)),
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(packet.is_none());
+    }
 }
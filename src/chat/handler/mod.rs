@@ -1,10 +1,48 @@
+mod action_limit;
+mod announce;
 mod ban;
+mod bots;
+mod command;
+mod confirm;
 mod count;
+mod diagnostics;
+mod emote;
+mod events;
+mod filter;
+mod hello;
+mod info;
 mod jwt;
+mod login;
+mod lookup;
 mod message;
 mod mojang;
+mod reaction;
+mod rename;
+mod resume;
+mod resync;
+mod review;
+mod status;
+mod watch;
+mod welcome;
 
-use super::{ChatServer, ClientPacket, ServerPacket, ServerPacketId};
+pub(super) use action_limit::ActionClass;
+pub(super) use announce::SystemMessageKind;
+pub(super) use confirm::PendingConfirmation;
+pub use diagnostics::{Diagnostics, RateLimitDiagnostics};
+pub(super) use events::{ModerationEventKind, ModerationSubscription};
+pub(super) use lookup::LastSeen;
+pub use lookup::UserLookup;
+pub(super) use message::PrivateBody;
+pub(super) use rename::PreviousName;
+pub(super) use resume::ResumeState;
+pub(super) use resync::ReplayChunk;
+pub use status::UserStatus;
+pub(super) use watch::MAX_WATCHES;
+
+use super::{
+    audit, cluster, trace, ChatServer, ClientPacket, InternalId, ServerPacket, ServerPacketId,
+};
+use crate::storage::AuditQuery;
 
 use actix::*;
 
@@ -13,10 +51,42 @@ impl Handler<ServerPacketId> for ChatServer {
 
     fn handle(
         &mut self,
-        ServerPacketId { user_id, packet }: ServerPacketId,
+        ServerPacketId {
+            user_id,
+            packet,
+            trace,
+            ..
+        }: ServerPacketId,
+        ctx: &mut Context<Self>,
+    ) {
+        let _entered = trace::enter(trace);
+        self.handle_packet(user_id, packet, ctx);
+    }
+}
+
+impl ChatServer {
+    /// Handles a packet the connection `user_id` sent.
+    pub(super) fn handle_packet(
+        &mut self,
+        user_id: InternalId,
+        packet: ServerPacket,
         ctx: &mut Context<Self>,
     ) {
         match packet {
+            ServerPacket::Hello {
+                features,
+                echo_own_messages,
+                client,
+                protocol,
+            } => {
+                self.handle_hello(user_id, features, echo_own_messages, client, protocol);
+            }
+            ServerPacket::SetEchoOwnMessages { enabled } => {
+                self.set_echo_own_messages(user_id, enabled);
+            }
+            ServerPacket::SetStatus { status } => {
+                self.handle_set_status(user_id, status);
+            }
             ServerPacket::RequestMojangInfo => {
                 self.handle_request_mojang_info(user_id);
             }
@@ -32,9 +102,30 @@ impl Handler<ServerPacketId> for ChatServer {
             } => {
                 self.handle_login_jwt(user_id, &token, allow_messages);
             }
-            ServerPacket::Message { content } => self.handle_message(user_id, content),
-            ServerPacket::PrivateMessage { receiver, content } => {
-                self.handle_private_message(user_id, receiver, content);
+            ServerPacket::Message {
+                content,
+                origin,
+                reply_to,
+            } => {
+                self.handle_message(user_id, content, origin, reply_to, ctx);
+            }
+            ServerPacket::PrivateMessage {
+                receiver,
+                content,
+                encrypted,
+                payload,
+                reply_to,
+            } => {
+                if reply_to.is_some() {
+                    self.reject_private_reply(user_id);
+                } else if encrypted {
+                    self.handle_encrypted_private_message(user_id, receiver, payload);
+                } else {
+                    self.handle_private_message(user_id, receiver, content);
+                }
+            }
+            ServerPacket::NotifyWhenOnline { id } => {
+                self.handle_notify_when_online(user_id, id);
             }
             ServerPacket::BanUser { user } => {
                 self.ban_user(user_id, &user);
@@ -42,9 +133,74 @@ impl Handler<ServerPacketId> for ChatServer {
             ServerPacket::UnbanUser { user } => {
                 self.unban_user(user_id, &user);
             }
+            ServerPacket::ConfirmAction { nonce } => {
+                self.confirm_action(user_id, &nonce);
+            }
+            ServerPacket::AddBlockedWord { word } => {
+                self.handle_edit_blocked_word(user_id, &word, true, ctx);
+            }
+            ServerPacket::RemoveBlockedWord { word } => {
+                self.handle_edit_blocked_word(user_id, &word, false, ctx);
+            }
+            ServerPacket::ListBlockedWords { filter } => {
+                self.handle_list_blocked_words(user_id, filter);
+            }
+            ServerPacket::SubscribeModerationEvents { enabled } => {
+                self.handle_subscribe_moderation_events(user_id, enabled);
+            }
             ServerPacket::RequestUserCount => {
                 self.send_user_count(user_id);
             }
+            ServerPacket::LookupUuid { uuid } => {
+                self.handle_lookup_uuid(user_id, uuid);
+            }
+            ServerPacket::RequestServerInfo => {
+                self.handle_request_server_info(user_id);
+            }
+            ServerPacket::Resume { token } => {
+                self.handle_resume(user_id, &token, ctx);
+            }
+            ServerPacket::ResyncFrom { seq } => {
+                self.handle_resync_from(user_id, seq, ctx);
+            }
+            ServerPacket::RequestEmotes => {
+                self.handle_request_emotes(user_id);
+            }
+            ServerPacket::RequestDiagnostics => {
+                self.handle_request_diagnostics(user_id);
+            }
+            // Sessions answer it themselves, so it only arrives here when it is simulated.
+            ServerPacket::TimeSync { client_time_ms } => {
+                let packet = ClientPacket::TimeSync {
+                    client_time_ms: Some(client_time_ms),
+                    server_time_ms: cluster::unix_millis(self.system_now()),
+                };
+                self.reply(user_id, packet);
+            }
+            ServerPacket::RequestAuditLog {
+                actor,
+                target,
+                since,
+                limit,
+            } => {
+                let query = AuditQuery {
+                    actor,
+                    target,
+                    since,
+                    offset: 0,
+                    limit: limit.unwrap_or(audit::DEFAULT_AUDIT_ENTRIES),
+                };
+                self.handle_request_audit_log(user_id, query);
+            }
+            ServerPacket::RequestPmMetadata { uuid, limit } => {
+                self.handle_request_pm_metadata(user_id, uuid, limit);
+            }
+            ServerPacket::React { message_id, emoji } => {
+                self.handle_reaction(user_id, message_id, emoji, true);
+            }
+            ServerPacket::RemoveReaction { message_id, emoji } => {
+                self.handle_reaction(user_id, message_id, emoji, false);
+            }
         }
     }
 }
@@ -1,20 +1,21 @@
 use crate::error::*;
 use log::*;
 
-use crate::chat::{ChatServer, ClientPacket, InternalId, SuccessReason, User, UserSession};
-use crate::message::RateLimiter;
-use std::collections::HashSet;
+use crate::chat::{funnel::Stage, ChatServer, ClientPacket, InternalId, SuccessReason, User};
 
-use crate::auth::authenticate;
+use crate::auth::{authenticate, AuthFailure};
 use actix::*;
 use rand::RngCore;
 use std::str::FromStr;
 use uuid::Uuid;
 
 impl ChatServer {
+    /// Sends a new session hash to `user_id`, which replaces the previous one.
     pub(super) fn handle_request_mojang_info(&mut self, user_id: InternalId) {
+        self.funnel.record_stage(Stage::MojangInfo);
+        let now = self.now();
         let session = self
-            .connections
+            .sessions
             .get_mut(&user_id)
             .expect("could not find connection");
 
@@ -24,7 +25,7 @@ impl ChatServer {
         bytes[0] &= 0b0111_1111;
 
         let session_hash = crate::auth::encode_sha1_bytes(&bytes);
-        session.session_hash = Some(session_hash.clone());
+        session.session_hash = Some((session_hash.clone(), now));
 
         if let Err(err) = session
             .addr
@@ -40,41 +41,44 @@ impl ChatServer {
         info: User,
         ctx: &mut Context<Self>,
     ) {
-        fn send_login_failed(
-            user_id: InternalId,
-            err: Error,
-            session: &Recipient<ClientPacket>,
-            _ctx: &mut Context<ChatServer>,
-        ) {
+        fn send_login_failed(server: &ChatServer, user_id: InternalId, err: Error) {
             warn!("Could not authenticate user `{}`: {}", user_id, err);
-            session
-                .do_send(ClientPacket::Error {
-                    message: ClientError::LoginFailed,
-                })
-                .ok();
+            server.send_error(user_id, ClientError::LoginFailed);
         }
 
+        let ttl = *self.config.mojang.request_ttl;
+        let now = self.now();
         let session = self
-            .connections
-            .get(&user_id)
+            .sessions
+            .get_mut(&user_id)
             .expect("could not find connection");
+        if session
+            .session_hash
+            .as_ref()
+            .is_some_and(|(_, requested)| now.duration_since(*requested) >= ttl)
+        {
+            info!(
+                "User `{}` tried to log in with an expired session hash.",
+                user_id
+            );
+            session.session_hash = None;
+            self.auth_monitor.record_expired_request();
+        }
+        let session = &self.sessions[&user_id];
 
         if session.is_logged_in() {
             info!("User `{}` tried to log in multiple times.", user_id);
-            session
-                .addr
-                .do_send(ClientPacket::Error {
-                    message: ClientError::AlreadyLoggedIn,
-                })
-                .ok();
+            self.send_error(user_id, ClientError::AlreadyLoggedIn);
             return;
         }
 
-        if let Some(session_hash) = &session.session_hash {
-            match authenticate(&info.name, session_hash) {
+        if let Some((session_hash, _)) = &session.session_hash {
+            let started = self.now();
+            match authenticate(info.name.as_str(), session_hash) {
                 Ok(fut) => {
                     fut.into_actor(self)
-                        .then(move |res, actor, ctx| {
+                        .then(move |res, actor, _ctx| {
+                            let latency = actor.now().duration_since(started);
                             match res {
                                 Ok(ref mojang_info)
                                     if Uuid::from_str(&mojang_info.id)
@@ -85,65 +89,40 @@ impl ChatServer {
                                         "User `{}` has uuid `{}` and username `{}`",
                                         user_id, mojang_info.id, mojang_info.name
                                     );
+                                    actor.auth_monitor.record(info.name.as_str(), latency, None);
 
-                                    if let Some(session) = actor.connections.get_mut(&user_id) {
-                                        actor
-                                            .users
-                                            .entry(info.name.clone())
-                                            .or_insert(UserSession {
-                                                rate_limiter: RateLimiter::new(
-                                                    actor.config.message.clone(),
-                                                ),
-                                                connections: HashSet::new(),
-                                            })
-                                            .connections
-                                            .insert(user_id);
-
-                                        session.user = Some(info);
-
-                                        if let Err(err) =
-                                            session.addr.do_send(ClientPacket::Success {
-                                                reason: SuccessReason::Login,
-                                            })
-                                        {
-                                            info!(
-                                                "Could not send login success to `{}`: {}",
-                                                user_id, err
-                                            );
-                                        }
-                                    }
+                                    actor.complete_login(user_id, info, SuccessReason::Login);
                                 }
                                 Ok(_) => {
-                                    let session = actor.connections.get(&user_id).unwrap();
-                                    send_login_failed(
-                                        user_id,
-                                        ClientError::InvalidId.into(),
-                                        &session.addr,
-                                        ctx,
-                                    )
+                                    let err: Error = ClientError::InvalidId.into();
+                                    actor.auth_monitor.record(
+                                        info.name.as_str(),
+                                        latency,
+                                        Some((AuthFailure::Invalid, err.to_string())),
+                                    );
+                                    send_login_failed(actor, user_id, err)
                                 }
                                 Err(err) => {
-                                    let session = actor.connections.get(&user_id).unwrap();
-                                    send_login_failed(user_id, err, &session.addr, ctx)
+                                    actor.auth_monitor.record(
+                                        info.name.as_str(),
+                                        latency,
+                                        Some((err.kind, err.to_string())),
+                                    );
+                                    send_login_failed(actor, user_id, err.source)
                                 }
                             }
                             fut::ok(())
                         })
                         .spawn(ctx);
                 }
-                Err(err) => send_login_failed(user_id, err, &session.addr, ctx),
+                Err(err) => send_login_failed(self, user_id, err),
             }
         } else {
             info!(
                 "User `{}` did not request mojang info, but tried to log in.",
                 user_id
             );
-            session
-                .addr
-                .do_send(ClientPacket::Error {
-                    message: ClientError::MojangRequestMissing,
-                })
-                .ok();
+            self.send_error(user_id, ClientError::MojangRequestMissing);
         }
     }
 }
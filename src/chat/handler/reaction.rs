@@ -0,0 +1,152 @@
+use log::*;
+
+use super::{ChatServer, ClientPacket};
+use crate::chat::InternalId;
+use crate::config::GuestEvent;
+use crate::error::*;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+impl ChatServer {
+    /// Adds or removes the reaction `emoji` of the user `user_id` on the broadcast message `message_id`
+    /// and sends a [`ClientPacket::ReactionUpdate`] to every connection.
+    ///
+    /// Only messages which are still in the history can be reacted to.
+    /// Adding a reaction twice has no effect.
+    pub(super) fn handle_reaction(
+        &mut self,
+        user_id: InternalId,
+        message_id: u64,
+        emoji: String,
+        added: bool,
+    ) {
+        let session = self
+            .sessions
+            .get(&user_id)
+            .expect("could not find connection");
+        let user = match &session.user {
+            Some(user) => user,
+            None => {
+                self.send_error(user_id, ClientError::NotLoggedIn);
+                return;
+            }
+        };
+        let (name, uuid) = (user.name.to_string(), user.uuid);
+
+        let result = if self.moderation.is_banned(&uuid, self.system_now()) {
+            Err(ClientError::Banned)
+        } else if let Some(err) = self.mute_error(&uuid) {
+            Err(err)
+        } else if !self.is_allowed_reaction(&emoji) {
+            Err(ClientError::InvalidReaction)
+        } else if self.check_reaction_rate(user_id) {
+            Err(ClientError::RateLimited)
+        } else {
+            let max = self.config.reactions.max_per_message;
+            match self.history.get_mut(message_id) {
+                None => Err(ClientError::MessageNotFound),
+                Some(entry) if added => {
+                    if entry
+                        .reactions
+                        .get(&emoji)
+                        .is_some_and(|users| users.contains(&uuid))
+                    {
+                        return;
+                    }
+                    let own = entry
+                        .reactions
+                        .values()
+                        .filter(|users| users.contains(&uuid))
+                        .count();
+                    if own >= max {
+                        Err(ClientError::TooManyReactions { max })
+                    } else {
+                        entry
+                            .reactions
+                            .entry(emoji.clone())
+                            .or_default()
+                            .insert(uuid);
+                        Ok(())
+                    }
+                }
+                Some(entry) => {
+                    let removed = match entry.reactions.get_mut(&emoji) {
+                        Some(users) => users.remove(&uuid),
+                        None => false,
+                    };
+                    if !removed {
+                        Err(ClientError::NotReacted)
+                    } else {
+                        if entry.reactions[&emoji].is_empty() {
+                            entry.reactions.remove(&emoji);
+                        }
+                        Ok(())
+                    }
+                }
+            }
+        };
+
+        if let Err(err) = result {
+            info!(
+                "User `{}` could not change reaction on `{}`: {}",
+                user_id, message_id, err
+            );
+            self.send_error(user_id, err);
+            return;
+        }
+
+        debug!(
+            "User `{}` {} reaction `{}` on `{}`.",
+            user_id,
+            if added { "added" } else { "removed" },
+            emoji,
+            message_id
+        );
+        let packet = ClientPacket::ReactionUpdate {
+            message_id,
+            emoji,
+            user_id: name,
+            added,
+        };
+        for (id, session) in self.sessions.iter() {
+            if self.receives(session, GuestEvent::Messages) {
+                self.send_to(*id, session, packet.clone());
+            }
+        }
+    }
+
+    /// Returns whether `emoji` is listed in `reactions.allowed`,
+    /// or if that is empty, whether it is a single grapheme without whitespace or control characters.
+    fn is_allowed_reaction(&self, emoji: &str) -> bool {
+        let allowed = &self.config.reactions.allowed;
+        if !allowed.is_empty() {
+            return allowed.iter().any(|allowed| allowed == emoji);
+        }
+        emoji.graphemes(true).count() == 1
+            && !emoji.chars().any(|c| c.is_whitespace() || c.is_control())
+    }
+
+    /// Returns whether the user of `user_id` changed too many reactions within `reactions.count_duration`.
+    /// If not, the change is counted.
+    fn check_reaction_rate(&mut self, user_id: InternalId) -> bool {
+        let now = self.now();
+        let cfg = &self.config.reactions;
+        let (_, user) = self
+            .sessions
+            .user_of_mut(&user_id)
+            .expect("logged in connections have a user session");
+        while user
+            .reaction_times
+            .front()
+            .is_some_and(|time| now.duration_since(*time) >= *cfg.count_duration)
+        {
+            user.reaction_times.pop_front();
+        }
+        if user.reaction_times.len() >= cfg.max_reactions {
+            true
+        } else {
+            user.reaction_times.push_back(now);
+            false
+        }
+    }
+}
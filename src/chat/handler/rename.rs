@@ -0,0 +1,122 @@
+use log::*;
+
+use super::{ChatServer, ClientPacket};
+use crate::chat::{CanonicalId, Capabilities, DisplayName, InternalId};
+use crate::config::GuestEvent;
+use crate::error::*;
+use std::time::Instant;
+use uuid::Uuid;
+
+/// The maximum amount of previous names remembered per user.
+const MAX_PREVIOUS_NAMES: usize = 5;
+
+/// A name a user does not use anymore.
+pub(in crate::chat) struct PreviousName {
+    pub(in crate::chat) name: DisplayName,
+    /// When the user logged in with a new name.
+    renamed: Instant,
+}
+
+impl ChatServer {
+    /// Checks whether `uuid` was online or seen recently with a name other than `name`,
+    /// before the connection `user_id` logs in.
+    ///
+    /// If so, the old name is remembered, users waiting for it to log in wait for the new one
+    /// and clients supporting renames are told about the change.
+    pub(super) fn detect_rename(&mut self, user_id: InternalId, uuid: Uuid, name: &DisplayName) {
+        let old_name = match self.lookup_uuid(&uuid) {
+            Some(lookup) if lookup.id != name.as_str() => DisplayName::new(lookup.id),
+            _ => return,
+        };
+        let old_id = old_name.canonical();
+        let new_id = name.canonical();
+        info!(
+            "User `{}` logged in as `{}`, which was known as `{}`.",
+            user_id, name, old_name
+        );
+
+        let now = self.now();
+        let previous_names = self.previous_names.entry(uuid).or_default();
+        previous_names.retain(|previous| {
+            let id = previous.name.canonical();
+            id != old_id && id != new_id
+        });
+        previous_names.push_back(PreviousName {
+            name: old_name.clone(),
+            renamed: now,
+        });
+        while previous_names.len() > MAX_PREVIOUS_NAMES {
+            if let Some(forgotten) = previous_names.pop_front() {
+                let forgotten = forgotten.name.canonical();
+                if self.renamed.get(&forgotten) == Some(&uuid) {
+                    self.renamed.remove(&forgotten);
+                }
+            }
+        }
+        self.renamed.insert(old_id.clone(), uuid);
+        if self.renamed.get(&new_id) == Some(&uuid) {
+            self.renamed.remove(&new_id);
+        }
+
+        self.migrate_watches(&old_id, &new_id);
+
+        let packet = ClientPacket::UserRenamed {
+            old_id: old_name.into(),
+            new_id: name.to_string(),
+        };
+        for session in self.sessions.values() {
+            if session.capabilities.contains(Capabilities::RENAMES)
+                && self.receives(session, GuestEvent::Presence)
+            {
+                session.addr.do_send(packet.clone()).ok();
+            }
+        }
+    }
+
+    /// Moves the connections waiting for `old_name` to log in to `new_name`.
+    fn migrate_watches(&mut self, old_name: &CanonicalId, new_name: &CanonicalId) {
+        let watchers = match self.online_watches.remove(old_name) {
+            Some(watchers) => watchers,
+            None => return,
+        };
+        for (watcher, expires) in watchers {
+            if let Some(session) = self.sessions.get_mut(&watcher) {
+                session.watching.remove(old_name);
+                session.watching.insert(new_name.clone());
+            }
+            self.online_watches
+                .entry(new_name.clone())
+                .or_default()
+                .insert(watcher, expires);
+        }
+    }
+
+    /// Resolves `name` if a user used it before logging in with another name.
+    ///
+    /// Returns `None` if nobody was renamed from `name`,
+    /// the current name of the user if they were renamed within `message.rename_grace`,
+    /// and [`ClientError::UserNotFound`] otherwise.
+    pub(super) fn resolve_renamed(
+        &self,
+        name: &CanonicalId,
+    ) -> Option<std::result::Result<String, ClientError>> {
+        let uuid = self.renamed.get(name)?;
+        let renamed = self
+            .previous_names
+            .get(uuid)
+            .and_then(|names| {
+                names
+                    .iter()
+                    .find(|previous| previous.name.canonical() == *name)
+            })
+            .map(|previous| previous.renamed)?;
+        if self.now().duration_since(renamed) > *self.config.message.rename_grace {
+            return Some(Err(ClientError::UserNotFound));
+        }
+        Some(
+            self.lookup_uuid(uuid)
+                .map(|lookup| lookup.id)
+                .ok_or(ClientError::UserNotFound),
+        )
+    }
+}
@@ -0,0 +1,134 @@
+use log::*;
+
+use super::{ChatServer, ClientPacket};
+use crate::chat::{Capabilities, InternalId, SuccessReason, User};
+use crate::error::*;
+use rand::RngCore;
+use std::time::Instant;
+use uuid::Uuid;
+
+use actix::*;
+
+/// What is restored when a session is resumed.
+pub(in crate::chat) struct ResumeState {
+    user: User,
+    /// The sequence number of the last broadcast message the session received.
+    last_seq: u64,
+    /// When the token expires; `None` while the session is still connected.
+    expires: Option<Instant>,
+}
+
+impl ResumeState {
+    fn is_valid(&self, now: Instant) -> bool {
+        match self.expires {
+            Some(expires) => expires > now,
+            None => true,
+        }
+    }
+}
+
+impl ChatServer {
+    /// Drops the expired resume tokens, which is also a maintenance job,
+    /// since they are otherwise only dropped when a token is issued.
+    pub(in crate::chat) fn prune_resume_tokens(&mut self) {
+        let now = self.now();
+        self.resume_tokens.retain(|_, state| state.is_valid(now));
+    }
+
+    /// Issues a new resume token for `user_id` if resuming is enabled.
+    pub(super) fn issue_resume_token(&mut self, user_id: InternalId) {
+        if !self.config.resume.enabled {
+            return;
+        }
+        self.prune_resume_tokens();
+
+        let session = match self.sessions.get_mut(&user_id) {
+            Some(session) => session,
+            None => return,
+        };
+        let user = match &session.user {
+            Some(user) if session.capabilities.contains(Capabilities::RESUME) => user.clone(),
+            _ => return,
+        };
+
+        let mut bytes = [0; 32];
+        self.rng.fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        self.resume_tokens.insert(
+            token.clone(),
+            ResumeState {
+                user,
+                last_seq: 0,
+                expires: None,
+            },
+        );
+        session.resume_token = Some(token.clone());
+
+        if let Err(err) = session.addr.do_send(ClientPacket::ResumeToken { token }) {
+            info!("Could not send resume token to `{}`: {}", user_id, err);
+        }
+    }
+
+    /// Starts the expiry of the resume token of a disconnected session.
+    ///
+    /// If the client logged out explicitly, the token is invalidated instead.
+    pub(in crate::chat) fn detach_resume_token(&mut self, token: &str, logout: bool) {
+        if logout {
+            self.resume_tokens.remove(token);
+        } else if let Some(state) = self.resume_tokens.get_mut(token) {
+            state.last_seq = self.history.last_seq();
+            state.expires = Some(self.clock.now() + *self.config.resume.ttl);
+        }
+    }
+
+    /// Invalidates all resume tokens of the user `uuid`.
+    pub(in crate::chat) fn revoke_resume_tokens(&mut self, uuid: &Uuid) {
+        self.resume_tokens
+            .retain(|_, state| state.user.uuid != *uuid);
+    }
+
+    pub(super) fn handle_resume(
+        &mut self,
+        user_id: InternalId,
+        token: &str,
+        ctx: &mut Context<Self>,
+    ) {
+        let session = self
+            .sessions
+            .get(&user_id)
+            .expect("could not find connection");
+        if session.is_logged_in() {
+            info!("User `{}` tried to resume while logged in.", user_id);
+            self.send_error(user_id, ClientError::AlreadyLoggedIn);
+            return;
+        }
+
+        let last_seq = self.history.last_seq();
+        let mut state = match self.resume_tokens.remove(token) {
+            Some(state) if state.is_valid(self.now()) => state,
+            _ => {
+                info!("User `{}` tried to resume with an invalid token.", user_id);
+                self.send_error(user_id, ClientError::ResumeFailed);
+                return;
+            }
+        };
+        if self
+            .moderation
+            .is_banned(&state.user.uuid, self.system_now())
+        {
+            info!("User `{}` tried to resume while banned.", user_id);
+            self.send_error(user_id, ClientError::Banned);
+            return;
+        }
+
+        if state.expires.is_none() {
+            // the old connection is still open, so it received everything until now
+            state.last_seq = last_seq;
+        }
+
+        info!("User `{}` resumed as `{}`.", user_id, state.user.name);
+        self.complete_login(user_id, state.user, SuccessReason::Resume);
+        self.handle_resync_from(user_id, state.last_seq, ctx);
+    }
+}
@@ -0,0 +1,113 @@
+use log::*;
+
+use super::{ChatServer, ClientPacket};
+use crate::chat::{InternalId, MessageFlags};
+
+use actix::*;
+
+/// A batch of replayed messages sent to a session.
+///
+/// The session responds once the messages were written to its connection,
+/// so waiting for the response keeps a long replay from piling up in its queue.
+pub(in crate::chat) struct ReplayChunk(pub Vec<ClientPacket>);
+
+impl Message for ReplayChunk {
+    type Result = Result<(), ()>;
+}
+
+impl ChatServer {
+    /// Sends all broadcast messages newer than `seq` again.
+    ///
+    /// The messages are sent in chunks of `message.replay_chunk_size`,
+    /// each after the session wrote the previous one to its connection,
+    /// followed by [`ClientPacket::ReplayComplete`].
+    /// Messages broadcast during the replay are not part of it, since they are delivered anyway.
+    pub(super) fn handle_resync_from(
+        &mut self,
+        user_id: InternalId,
+        seq: u64,
+        ctx: &mut Context<Self>,
+    ) {
+        debug!("User `{}` resynchronizes from `{}`.", user_id, seq);
+        let end = self.history.last_seq();
+        self.replay_chunk(user_id, seq, end, 0, ctx);
+    }
+
+    /// Sends the next chunk of messages newer than `seq` up to `end`,
+    /// after `count` were already replayed.
+    fn replay_chunk(
+        &mut self,
+        user_id: InternalId,
+        seq: u64,
+        end: u64,
+        count: u32,
+        ctx: &mut Context<Self>,
+    ) {
+        let session = match self.sessions.get(&user_id) {
+            Some(session) => session,
+            None => {
+                debug!("User `{}` disconnected during a replay.", user_id);
+                return;
+            }
+        };
+
+        let own = session.user.as_ref().map(|info| info.uuid);
+        let chunk: Vec<ClientPacket> = match self.history.since(seq) {
+            Ok(messages) => messages
+                .take_while(|entry| entry.seq <= end)
+                .take(self.config.message.replay_chunk_size.max(1))
+                .map(|entry| ClientPacket::Message {
+                    seq: entry.seq,
+                    author_info: entry.author_info.clone(),
+                    author_kind: entry.author_kind.clone(),
+                    content: entry.content.clone(),
+                    signature: entry.signature.clone(),
+                    reactions: entry.reaction_counts(),
+                    reply: entry.reply.clone(),
+                    flags: if own == Some(entry.author_info.uuid) {
+                        entry.flags.with(MessageFlags::OWN)
+                    } else {
+                        entry.flags
+                    },
+                })
+                .collect(),
+            Err(oldest_available) => {
+                debug!(
+                    "User `{}` tried to resynchronize from `{}`, which is too old.",
+                    user_id, seq
+                );
+                session
+                    .addr
+                    .do_send(ClientPacket::ResyncTooOld { oldest_available })
+                    .ok();
+                return;
+            }
+        };
+        if chunk.is_empty() {
+            session
+                .addr
+                .do_send(ClientPacket::ReplayComplete { count })
+                .ok();
+            return;
+        }
+
+        let last_seq = match chunk.last() {
+            Some(ClientPacket::Message { seq, .. }) => *seq,
+            _ => unreachable!("replay chunks only contain messages"),
+        };
+        let count = count + chunk.len() as u32;
+        session
+            .replay
+            .send(ReplayChunk(chunk))
+            .into_actor(self)
+            .then(move |res, actor, ctx| {
+                match res {
+                    Ok(Ok(())) => actor.replay_chunk(user_id, last_seq, end, count, ctx),
+                    Ok(Err(())) => debug!("Aborting replay to `{}`, it closed.", user_id),
+                    Err(err) => debug!("Aborting replay to `{}`: {}", user_id, err),
+                }
+                fut::ok(())
+            })
+            .spawn(ctx);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::error::*;
+use log::*;
+
+use super::{events::ModerationEventKind, ChatServer, ClientPacket};
+use crate::auth::UserInfo;
+use crate::chat::{AuthorKind, Capabilities, InternalId};
+use crate::config::ReviewVerdict;
+use crate::message::ValidatedContent;
+
+use actix::*;
+use actix_web::{client::Client, http::StatusCode};
+use futures::Future;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize)]
+struct ReviewRequest<'a> {
+    author: &'a UserInfo,
+    content: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ReviewResponse {
+    verdict: ReviewVerdict,
+}
+
+impl ChatServer {
+    /// Asks the external reviewer at `moderation.review_url` about a message
+    /// and delivers it depending on the verdict.
+    ///
+    /// The message is held back until the reviewer responded or the timeout elapsed.
+    /// This is only called if `moderation.review_url` is set.
+    pub(super) fn review_message(
+        &mut self,
+        user_id: InternalId,
+        author_info: UserInfo,
+        author_kind: AuthorKind,
+        content: ValidatedContent,
+        reply_to: Option<u64>,
+        ctx: &mut Context<Self>,
+    ) {
+        let review_url = self
+            .config
+            .moderation
+            .review_url
+            .as_deref()
+            .expect("no review url configured");
+        let timeout = *self.config.moderation.review_timeout;
+        let request = Client::new()
+            .post(review_url)
+            .timeout(timeout)
+            .send_json(&ReviewRequest {
+                author: &author_info,
+                content: content.as_str(),
+            })
+            .map_err(|err| Some(Error::Actix { source: err.into() }))
+            .and_then(|response| {
+                if response.status() == StatusCode::OK {
+                    Ok(response)
+                } else {
+                    debug!("Review status-code is {}", response.status());
+                    Err(Some(ClientError::Internal.into()))
+                }
+            })
+            .and_then(|mut response| {
+                response
+                    .json::<ReviewResponse>()
+                    .map_err(|err| Some(Error::Actix { source: err.into() }))
+            });
+
+        request
+            .into_actor(self)
+            .timeout(timeout, None)
+            .then(move |res, actor, _ctx| {
+                let verdict = match res {
+                    Ok(review) => review.verdict,
+                    Err(err) => {
+                        match err {
+                            Some(err) => {
+                                warn!("Could not review message of `{}`: {}", user_id, err)
+                            }
+                            None => warn!("Review of message of `{}` timed out.", user_id),
+                        }
+                        actor.config.moderation.review_fallback
+                    }
+                };
+                actor.apply_verdict(
+                    user_id,
+                    author_info,
+                    author_kind,
+                    content,
+                    reply_to,
+                    verdict,
+                );
+                fut::ok(())
+            })
+            .spawn(ctx);
+    }
+
+    fn apply_verdict(
+        &mut self,
+        user_id: InternalId,
+        author_info: UserInfo,
+        author_kind: AuthorKind,
+        content: ValidatedContent,
+        reply_to: Option<u64>,
+        verdict: ReviewVerdict,
+    ) {
+        if verdict != ReviewVerdict::Deny && !self.may_still_send(user_id, &author_info) {
+            return;
+        }
+        match verdict {
+            ReviewVerdict::Allow => {
+                self.broadcast_message(user_id, author_info, author_kind, content, reply_to);
+            }
+            ReviewVerdict::Deny => {
+                info!("Message of user `{}` was denied by review.", user_id);
+                self.notify_moderators(
+                    user_id,
+                    ModerationEventKind::ReviewDenied,
+                    content.as_str(),
+                    "review",
+                );
+                self.send_error(user_id, ClientError::BlockedContent);
+            }
+            ReviewVerdict::Flag => {
+                info!("Message of user `{}` was flagged by review.", user_id);
+                let flagged = ClientPacket::MessageFlagged {
+                    author_info: author_info.clone(),
+                    content: content.clone(),
+                };
+                for session in self.sessions_with(Capabilities::FLAGGED_MESSAGES) {
+                    match &session.user {
+                        Some(info) if self.is_moderator(&info.uuid) => {
+                            if let Err(err) = session.addr.do_send(flagged.clone()) {
+                                warn!("Could not send flagged message to moderator: {}", err);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+
+                self.broadcast_message(user_id, author_info, author_kind, content, reply_to);
+            }
+        }
+    }
+
+    /// Returns whether the author of a reviewed message may still send it,
+    /// since they may have disconnected, or been banned or muted, while it was held back.
+    fn may_still_send(&self, user_id: InternalId, author_info: &UserInfo) -> bool {
+        if self.sessions.get(&user_id).is_none() {
+            debug!(
+                "User `{}` disconnected before its message was reviewed.",
+                user_id
+            );
+            return false;
+        }
+        if self
+            .moderation
+            .is_banned(&author_info.uuid, self.system_now())
+        {
+            info!(
+                "User `{}` was banned while its message was reviewed.",
+                user_id
+            );
+            self.send_error(user_id, ClientError::Banned);
+            return false;
+        }
+        if let Some(err) = self.mute_error(&author_info.uuid) {
+            info!(
+                "User `{}` was muted while its message was reviewed.",
+                user_id
+            );
+            self.send_error(user_id, err);
+            return false;
+        }
+        true
+    }
+}
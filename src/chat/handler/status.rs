@@ -0,0 +1,42 @@
+use log::*;
+
+use super::{ChatServer, ClientPacket};
+use crate::chat::{InternalId, SuccessReason};
+use crate::error::*;
+use serde::{Deserialize, Serialize};
+
+/// Whether a user wants to be disturbed.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UserStatus {
+    #[default]
+    Online,
+    /// Private messages to the user are rejected with [`ClientError::DoNotDisturb`].
+    Dnd,
+}
+
+impl ChatServer {
+    /// Sets the status of the user logged in with `user_id`, for all of their sessions.
+    ///
+    /// The status is reset once the last session of the user closes.
+    pub(super) fn handle_set_status(&mut self, user_id: InternalId, status: UserStatus) {
+        let packet = match self.sessions.user_of_mut(&user_id) {
+            Some((session, user_session)) => {
+                if let Some(info) = &session.user {
+                    info!(
+                        "User `{}` sets the status of `{}` to {:?}.",
+                        user_id, info.name, status
+                    );
+                }
+                user_session.status = status;
+                ClientPacket::Success {
+                    reason: SuccessReason::SetStatus,
+                }
+            }
+            None => ClientPacket::Error {
+                message: ClientError::NotLoggedIn,
+            },
+        };
+        self.reply(user_id, packet);
+    }
+}
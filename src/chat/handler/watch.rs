@@ -0,0 +1,106 @@
+use log::*;
+
+use super::{ChatServer, ClientPacket};
+use crate::chat::{id::MAX_NAME_BYTES, CanonicalId, InternalId};
+use crate::error::*;
+use std::collections::HashSet;
+
+/// The maximum amount of users a connection can wait for at once.
+pub(in crate::chat) const MAX_WATCHES: usize = 10;
+
+impl ChatServer {
+    /// Tells `user_id` once the user named `name` logs in.
+    ///
+    /// If the user is already online, the notification is sent immediately.
+    /// Either way, it contains the name as the user spells it.
+    pub(super) fn handle_notify_when_online(&mut self, user_id: InternalId, name: String) {
+        let now = self.now();
+        let id = CanonicalId::parse_client_input(&name);
+        if id.as_str().len() > MAX_NAME_BYTES {
+            debug!("User `{}` waits for a name which is too long.", user_id);
+            self.send_error(user_id, ClientError::UserNotFound);
+            return;
+        }
+        let online = self
+            .sessions
+            .sessions_of(&id)
+            .map(|(_, _, user)| user.name.to_string())
+            .next();
+        let session = self
+            .sessions
+            .get_mut(&user_id)
+            .expect("could not find connection");
+        if !session.is_logged_in() {
+            self.send_error(user_id, ClientError::NotLoggedIn);
+            return;
+        }
+
+        if let Some(name) = online {
+            session
+                .addr
+                .do_send(ClientPacket::UserOnline { id: name })
+                .ok();
+            return;
+        }
+
+        let online_watches = &self.online_watches;
+        session.watching.retain(|watched| {
+            online_watches
+                .get(watched)
+                .and_then(|watchers| watchers.get(&user_id))
+                .is_some_and(|expires| *expires > now)
+        });
+        if session.watching.len() >= MAX_WATCHES && !session.watching.contains(&id) {
+            info!("User `{}` is waiting for too many users.", user_id);
+            self.send_error(user_id, ClientError::TooManyWatches { max: MAX_WATCHES });
+            return;
+        }
+
+        debug!("User `{}` waits for `{}` to log in.", user_id, name);
+        session.watching.insert(id.clone());
+        self.online_watches
+            .entry(id)
+            .or_default()
+            .insert(user_id, now + *self.config.message.online_watch_duration);
+    }
+
+    /// Notifies the connections waiting for `id`, who has just logged in as `name`.
+    pub(super) fn notify_watchers(&mut self, id: &CanonicalId, name: &str) {
+        let watchers = match self.online_watches.remove(id) {
+            Some(watchers) => watchers,
+            None => return,
+        };
+        let now = self.now();
+        for (watcher, expires) in watchers {
+            let session = match self.sessions.get_mut(&watcher) {
+                Some(session) => session,
+                None => continue,
+            };
+            session.watching.remove(id);
+            if expires > now {
+                session
+                    .addr
+                    .do_send(ClientPacket::UserOnline {
+                        id: name.to_string(),
+                    })
+                    .ok();
+            }
+        }
+    }
+
+    /// Drops the watches of a connection which has closed.
+    pub(in crate::chat) fn drop_watches(
+        &mut self,
+        user_id: InternalId,
+        watching: &HashSet<CanonicalId>,
+    ) {
+        for name in watching {
+            if let Some(watchers) = self.online_watches.get_mut(name) {
+                watchers.remove(&user_id);
+                if watchers.is_empty() {
+                    self.online_watches.remove(name);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use crate::error::*;
+use log::*;
+
+use super::{ChatServer, ClientPacket};
+use crate::chat::InternalId;
+use crate::config::WelcomeItem;
+
+impl ChatServer {
+    /// Sends the configured welcome sequence to a user who just logged in.
+    /// If a single item fails, the remaining items are still sent.
+    pub(super) fn send_welcome(&self, user_id: InternalId) {
+        let session = match self.sessions.get(&user_id) {
+            Some(session) => session,
+            None => return,
+        };
+
+        for item in &self.config.welcome.items {
+            match self.welcome_packet(*item) {
+                Ok(None) => {}
+                Ok(Some(packet)) => {
+                    if let Err(err) = session.addr.do_send(packet) {
+                        warn!("Could not send welcome to user `{}`: {}", user_id, err);
+                        return;
+                    }
+                }
+                Err(err) => {
+                    warn!(
+                        "Could not create welcome item {:?} for user `{}`: {}",
+                        item, user_id, err
+                    );
+                }
+            }
+        }
+    }
+
+    /// Creates the packet for a welcome item, if there is anything to send.
+    fn welcome_packet(&self, item: WelcomeItem) -> Result<Option<ClientPacket>> {
+        match item {
+            WelcomeItem::Motd => Ok(self
+                .config
+                .welcome
+                .motd
+                .clone()
+                .map(|content| ClientPacket::Motd { content })),
+            WelcomeItem::ServerInfo => Ok(Some(self.server_info())),
+            WelcomeItem::UserCount => Ok(Some(ClientPacket::UserCount {
+                connections: self.sessions.len() as u32,
+                logged_in: self.sessions.user_count() as u32,
+            })),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use super::{flags::MessageFlags, AuthorKind};
+use crate::auth::UserInfo;
+use crate::message::ValidatedContent;
+use crate::signing::MessageSignature;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use unicode_segmentation::UnicodeSegmentation;
+use uuid::Uuid;
+
+/// The number of graphemes of the original message included in a reply.
+const REPLY_EXCERPT_LENGTH: usize = 80;
+
+/// The message a broadcast message replies to.
+#[derive(Debug, Clone, Serialize)]
+pub struct Reply {
+    /// The sequence number of the original message.
+    pub reply_to: u64,
+    /// Only set if the original message was still in the history,
+    /// otherwise the message is flagged with [`MessageFlags::REPLY_MISSING`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_excerpt: Option<ReplyExcerpt>,
+}
+
+/// The author and the beginning of the message replied to.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplyExcerpt {
+    pub author_info: UserInfo,
+    /// At most 80 graphemes, followed by `…` if the message was longer.
+    pub content: String,
+}
+
+/// A broadcast message kept in the [`History`].
+pub(super) struct HistoryEntry {
+    pub seq: u64,
+    pub author_info: UserInfo,
+    pub author_kind: AuthorKind,
+    pub content: ValidatedContent,
+    pub signature: Option<MessageSignature>,
+    pub reply: Option<Reply>,
+    /// The flags of the message, without [`MessageFlags::OWN`].
+    pub flags: MessageFlags,
+    /// The users who reacted to the message, by reaction.
+    pub reactions: BTreeMap<String, HashSet<Uuid>>,
+}
+
+impl HistoryEntry {
+    /// The number of users who reacted to the message, by reaction.
+    pub fn reaction_counts(&self) -> BTreeMap<String, u32> {
+        self.reactions
+            .iter()
+            .map(|(emoji, users)| (emoji.clone(), users.len() as u32))
+            .collect()
+    }
+}
+
+/// The most recent broadcast messages,
+/// so clients which missed some of them can catch up.
+///
+/// Every message is numbered with a sequence number, starting at `1`.
+pub(super) struct History {
+    capacity: usize,
+    next_seq: u64,
+    messages: VecDeque<HistoryEntry>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> History {
+        History {
+            capacity,
+            next_seq: 1,
+            messages: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Stores a message and returns its sequence number.
+    pub fn push(
+        &mut self,
+        author_info: UserInfo,
+        author_kind: AuthorKind,
+        content: ValidatedContent,
+        signature: Option<MessageSignature>,
+        reply: Option<Reply>,
+        flags: MessageFlags,
+    ) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        if self.capacity > 0 {
+            if self.messages.len() == self.capacity {
+                self.messages.pop_front();
+            }
+            self.messages.push_back(HistoryEntry {
+                seq,
+                author_info,
+                author_kind,
+                content,
+                signature,
+                reply,
+                flags,
+                reactions: BTreeMap::new(),
+            });
+        }
+        seq
+    }
+
+    /// The sequence number the next message will have.
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// The sequence number of the latest message, or `0` if there was none.
+    pub fn last_seq(&self) -> u64 {
+        self.next_seq - 1
+    }
+
+    /// Returns the message with the sequence number `seq`, if it is still stored.
+    pub fn get(&self, seq: u64) -> Option<&HistoryEntry> {
+        let oldest = self.messages.front()?.seq;
+        let index = seq.checked_sub(oldest)?;
+        self.messages.get(index as usize)
+    }
+
+    /// Returns the message with the sequence number `seq`, if it is still stored.
+    pub fn get_mut(&mut self, seq: u64) -> Option<&mut HistoryEntry> {
+        let oldest = self.messages.front()?.seq;
+        let index = seq.checked_sub(oldest)?;
+        self.messages.get_mut(index as usize)
+    }
+
+    /// Describes a reply to the message `seq`, including an excerpt of it if it is still stored.
+    pub fn reply_to(&self, seq: u64) -> Reply {
+        let reply_excerpt = self.get(seq).map(|entry| {
+            let mut graphemes = entry.content.as_str().graphemes(true);
+            let mut content: String = graphemes.by_ref().take(REPLY_EXCERPT_LENGTH).collect();
+            if graphemes.next().is_some() {
+                content.push('…');
+            }
+            ReplyExcerpt {
+                author_info: entry.author_info.clone(),
+                content,
+            }
+        });
+        Reply {
+            reply_to: seq,
+            reply_excerpt,
+        }
+    }
+
+    /// Returns all messages newer than `seq`.
+    ///
+    /// If some of them are not stored anymore,
+    /// the sequence number of the oldest message available is returned instead.
+    pub fn since(&self, seq: u64) -> Result<impl Iterator<Item = &HistoryEntry>, u64> {
+        let oldest_available = self
+            .messages
+            .front()
+            .map_or(self.next_seq, |entry| entry.seq);
+        if seq + 1 < oldest_available {
+            Err(oldest_available)
+        } else {
+            Ok(self.messages.iter().filter(move |entry| entry.seq > seq))
+        }
+    }
+}
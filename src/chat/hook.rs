@@ -0,0 +1,162 @@
+use super::{AuthorKind, ChatServer, InternalId};
+use crate::auth::UserInfo;
+use crate::error::*;
+use crate::message::ValidatedContent;
+use log::*;
+
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    time::{Duration, Instant},
+};
+
+/// The time a single hook call should take at most.
+///
+/// Hooks run synchronously inside of the chat server actor,
+/// so every hook call delays all other users.
+/// Exceeding this budget is logged, but not prevented.
+pub const HOOK_TIME_BUDGET: Duration = Duration::from_millis(1);
+
+/// Custom rules which are run by the [`ChatServer`] for messages and lifecycle events.
+///
+/// Hooks are registered using [`ChatServerBuilder::hook`](super::ChatServerBuilder::hook)
+/// and run in registration order.
+/// Every method has a default implementation which allows everything.
+///
+/// A panicking hook is logged and otherwise treated as if it allowed the message.
+pub trait ChatHook {
+    /// Decides about a message sent to all users.
+    fn on_message(&mut self, _author: &UserInfo, _content: &str) -> HookDecision {
+        HookDecision::Allow
+    }
+
+    /// Decides about a private message sent to `receiver`.
+    fn on_private_message(
+        &mut self,
+        _author: &UserInfo,
+        _receiver: &str,
+        _content: &str,
+    ) -> HookDecision {
+        HookDecision::Allow
+    }
+
+    /// Called after a message was sent to all users of this instance.
+    fn on_broadcast(&mut self, _author: &UserInfo, _kind: &AuthorKind, _content: &str) {}
+
+    /// Called after a user logged in.
+    fn on_login(&mut self, _user: &UserInfo) {}
+
+    /// Called after a connection was closed.
+    /// `user` is `None` if the connection was not logged in.
+    fn on_disconnect(&mut self, _user: Option<&UserInfo>) {}
+}
+
+/// The decision of a [`ChatHook`] about a message.
+pub enum HookDecision {
+    /// The message is passed on unchanged.
+    Allow,
+    /// The message is rejected and the author receives the error.
+    Reject(ClientError),
+    /// The content of the message is replaced.
+    /// The new content is validated again after all hooks ran.
+    Rewrite(String),
+}
+
+impl ChatServer {
+    /// Lets every hook decide about the content of a message.
+    ///
+    /// Returns the possibly rewritten content,
+    /// or `None` if the message was rejected, in which case the author was already notified.
+    pub(super) fn apply_hooks<F>(
+        &mut self,
+        user_id: InternalId,
+        content: ValidatedContent,
+        mut decide: F,
+    ) -> Option<ValidatedContent>
+    where
+        F: FnMut(&mut dyn ChatHook, &str) -> HookDecision,
+    {
+        let mut rewritten: Option<String> = None;
+        for hook in &mut self.hooks {
+            let current = rewritten.as_deref().unwrap_or(content.as_str());
+            match call_hook(|| decide(hook.as_mut(), current)) {
+                Some(HookDecision::Allow) | None => {}
+                Some(HookDecision::Reject(err)) => {
+                    info!(
+                        "Message of user `{}` was rejected by hook: {}",
+                        user_id, err
+                    );
+                    self.send_error(user_id, err);
+                    return None;
+                }
+                Some(HookDecision::Rewrite(new_content)) => rewritten = Some(new_content),
+            }
+        }
+
+        let rewritten = match rewritten {
+            Some(rewritten) => rewritten,
+            None => return Some(content),
+        };
+        match self.validator.check(&rewritten) {
+            Ok(content) => Some(content),
+            Err(rejection) if self.config.validation.dry_run => {
+                self.record_dry_run(user_id, &rewritten, &[rejection]);
+                Some(self.validator.unchecked(&rewritten))
+            }
+            Err(rejection) => {
+                info!(
+                    "Message of user `{}` is rejected by `{}` after being rewritten by hook: {}",
+                    user_id, rejection.rule, rejection.error
+                );
+                self.record_rejection(&rejection);
+                self.send_rejection(user_id, rejection);
+                None
+            }
+        }
+    }
+
+    /// Calls `f` for every hook.
+    pub(super) fn notify_hooks<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut dyn ChatHook),
+    {
+        // Hooks act on what they are told, so they must not learn about simulated packets.
+        if self.simulating {
+            return;
+        }
+        for hook in &mut self.hooks {
+            call_hook(|| f(hook.as_mut()));
+        }
+    }
+}
+
+/// Calls a hook, catching panics and checking the time budget.
+fn call_hook<T>(f: impl FnOnce() -> T) -> Option<T> {
+    let start = Instant::now();
+    let res = panic::catch_unwind(AssertUnwindSafe(f));
+
+    let elapsed = start.elapsed();
+    if elapsed > HOOK_TIME_BUDGET {
+        warn!(
+            "A chat hook took {:?}, exceeding its budget of {:?}.",
+            elapsed, HOOK_TIME_BUDGET
+        );
+    }
+
+    match res {
+        Ok(value) => Some(value),
+        Err(payload) => {
+            error!("A chat hook panicked: {}", panic_message(&*payload));
+            None
+        }
+    }
+}
+
+/// The message a panic was started with.
+pub(super) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause")
+}
@@ -2,22 +2,64 @@ use actix::{
     dev::{MessageResponse, ResponseChannel},
     *,
 };
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use std::fmt;
+use unicode_normalization::UnicodeNormalization;
 
-#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Hash)]
-#[serde(transparent)]
-pub struct InternalId(u64);
+/// Where a connection came from, which is the first character of its [`InternalId`] in logs.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Origin {
+    /// A websocket connection of a client, `c`.
+    Client,
+    /// A websocket connection to the firehose, `o`.
+    Observer,
+    /// A connection made up by `/api/v1/simulate`, `s`.
+    Simulated,
+}
+
+impl Origin {
+    fn prefix(self) -> char {
+        match self {
+            Origin::Client => 'c',
+            Origin::Observer => 'o',
+            Origin::Simulated => 's',
+        }
+    }
+}
+
+/// Identifies a connection, e.g. `c17/ab3f09c2` for the 17th connection since the server started.
+///
+/// The number restarts with the server, so every connection also gets a random tag,
+/// which the client is told and can show to its user; support can then find the connection in the logs.
+/// Tags are not unique, but two connections have the same one only rarely.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct InternalId {
+    id: u64,
+    origin: Origin,
+    tag: u32,
+}
 
 impl InternalId {
-    pub fn new(id: u64) -> InternalId {
-        InternalId(id)
+    pub fn new(id: u64, origin: Origin, tag: u32) -> InternalId {
+        InternalId { id, origin, tag }
+    }
+
+    /// The random tag of the connection, as 8 hex digits.
+    pub fn session_tag(self) -> String {
+        format!("{:08x}", self.tag)
     }
 }
 
 impl fmt::Display for InternalId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:08x}", self.0)
+        write!(f, "{}{}/{:08x}", self.origin.prefix(), self.id, self.tag)
+    }
+}
+
+/// Only the number of the connection is serialized; the tag is sent separately as `session_tag`.
+impl Serialize for InternalId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.id)
     }
 }
 
@@ -32,3 +74,94 @@ where
         }
     }
 }
+
+/// The longest name in bytes a client can refer to a user by, after normalization.
+///
+/// Minecraft names have at most 16 characters, so this leaves room for any name a user can log in with.
+pub(super) const MAX_NAME_BYTES: usize = 64;
+
+/// The name of a user as they spell it, which is shown to other users.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
+#[serde(transparent)]
+pub struct DisplayName(String);
+
+impl DisplayName {
+    pub fn new(name: String) -> DisplayName {
+        DisplayName(name)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the id used to look up this user.
+    pub fn canonical(&self) -> CanonicalId {
+        CanonicalId::from_display_name(&self.0)
+    }
+}
+
+impl From<String> for DisplayName {
+    fn from(name: String) -> DisplayName {
+        DisplayName(name)
+    }
+}
+
+impl From<DisplayName> for String {
+    fn from(name: DisplayName) -> String {
+        name.0
+    }
+}
+
+impl fmt::Display for DisplayName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The name of a user normalized for lookups.
+///
+/// Two names are the same user if they are equal after NFKC normalization and lowercasing,
+/// so `Notch`, `notch` and `ｎｏｔｃｈ` are the same user, while names which still differ after that never are.
+/// Ids can only be created by [`CanonicalId::from_display_name`] and [`CanonicalId::parse_client_input`],
+/// which both normalize the name that way; deserializing an id normalizes it too.
+#[derive(Serialize, Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[serde(transparent)]
+pub struct CanonicalId(String);
+
+impl CanonicalId {
+    /// The id of a user who logged in as `name`.
+    pub fn from_display_name(name: &str) -> CanonicalId {
+        CanonicalId::canonicalize(name)
+    }
+
+    /// The id of a user named by a client, like the receiver of a private message.
+    pub fn parse_client_input(name: &str) -> CanonicalId {
+        CanonicalId::canonicalize(name.trim())
+    }
+
+    fn canonicalize(name: &str) -> CanonicalId {
+        CanonicalId(name.nfkc().collect::<String>().to_lowercase())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<'de> Deserialize<'de> for CanonicalId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|name| CanonicalId::parse_client_input(&name))
+    }
+}
+
+impl From<CanonicalId> for String {
+    fn from(id: CanonicalId) -> String {
+        id.0
+    }
+}
+
+impl fmt::Display for CanonicalId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
@@ -0,0 +1,38 @@
+use super::{auth_monitor::AuthMonitor, persistence::StorageHealth, ConnectionLimit};
+use crate::version;
+
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+use std::sync::Arc;
+
+#[derive(Serialize)]
+struct Info {
+    version: &'static str,
+    commit: &'static str,
+    build_timestamp: &'static str,
+    uptime_secs: u64,
+    connections: usize,
+    max_connections: Option<usize>,
+    /// Whether many authentications with Mojang failed recently.
+    mojang_degraded: bool,
+    /// Whether the moderation state could be saved.
+    storage_healthy: bool,
+}
+
+/// Serves the build, uptime and health of the server as JSON.
+pub(super) fn info_route(
+    limit: web::Data<Arc<ConnectionLimit>>,
+    auth_monitor: web::Data<Arc<AuthMonitor>>,
+    storage_health: web::Data<Arc<StorageHealth>>,
+) -> HttpResponse {
+    HttpResponse::Ok().json(Info {
+        version: version::VERSION,
+        commit: version::GIT_COMMIT,
+        build_timestamp: version::BUILD_TIMESTAMP,
+        uptime_secs: version::uptime().as_secs(),
+        connections: limit.current(),
+        max_connections: limit.max(),
+        mojang_degraded: auth_monitor.degraded(),
+        storage_healthy: storage_health.healthy(),
+    })
+}
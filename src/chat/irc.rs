@@ -0,0 +1,412 @@
+//! A bridge relaying broadcast messages to an IRC channel
+//! and, if `irc.relay_incoming` is enabled, the messages of the channel back to the chat.
+//!
+//! The bridge runs in its own arbiter, so a slow or unreachable IRC server never delays the chat server.
+//! Messages are queued and sent at most once per `irc.send_interval`;
+//! while the queue is full, new messages are dropped and counted instead.
+
+use super::{AdminHandle, AuthorKind, ChatHook};
+use crate::auth::UserInfo;
+use crate::config::IrcConfig;
+use log::*;
+
+use actix::io::{FramedWrite, WriteHandler};
+use actix::*;
+use bytes::BytesMut;
+use futures::{future, Future};
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_codec::{Decoder, Encoder, FramedRead, LinesCodec};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_rustls::{rustls::ClientConfig, TlsConnector};
+use tokio_tcp::TcpStream;
+use uuid::Uuid;
+
+/// The delay before the first reconnect, which doubles with every failed attempt.
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// The maximum length of a line received from IRC; the protocol allows 512 bytes.
+const MAX_LINE_LENGTH: usize = 4096;
+
+/// The maximum length of the text of a relayed message, so the whole line stays within 512 bytes.
+const MAX_TEXT_LENGTH: usize = 400;
+
+/// The messages dropped because IRC was slower than the chat, shared with the metrics route.
+#[derive(Default)]
+pub(super) struct IrcStats {
+    dropped: AtomicUsize,
+}
+
+impl IrcStats {
+    fn record_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Appends the counter in the Prometheus text format.
+    pub fn write_metrics(&self, output: &mut String) {
+        writeln!(
+            output,
+            "# HELP axochat_irc_dropped_total Messages not relayed to IRC because its queue was full."
+        )
+        .unwrap();
+        writeln!(output, "# TYPE axochat_irc_dropped_total counter").unwrap();
+        writeln!(
+            output,
+            "axochat_irc_dropped_total {}",
+            self.dropped.load(Ordering::Relaxed)
+        )
+        .unwrap();
+    }
+}
+
+/// Passes broadcast messages to the [`IrcBridge`] without waiting for it.
+pub(super) struct IrcHook {
+    bridge: Recipient<Relay>,
+    stats: Arc<IrcStats>,
+}
+
+impl ChatHook for IrcHook {
+    fn on_broadcast(&mut self, author: &UserInfo, kind: &AuthorKind, content: &str) {
+        if let AuthorKind::Bridge { origin } = kind {
+            if origin == "irc" {
+                return;
+            }
+        }
+        let line = format!("<{}> {}", author.name, content);
+        if self.bridge.try_send(Relay(line)).is_err() {
+            self.stats.record_dropped();
+        }
+    }
+}
+
+/// A broadcast message to relay to IRC.
+struct Relay(String);
+
+impl Message for Relay {
+    type Result = ();
+}
+
+type Reader = Box<dyn AsyncRead>;
+type Writer = Box<dyn AsyncWrite>;
+
+/// The connection to the IRC server.
+pub(super) struct IrcBridge {
+    config: IrcConfig,
+    admin: AdminHandle,
+    stats: Arc<IrcStats>,
+    writer: Option<FramedWrite<Writer, IrcCodec>>,
+    reader: Option<SpawnHandle>,
+    /// The nick accepted by the server, which may differ from `irc.nick` if that was in use.
+    nick: String,
+    /// Whether the channel was joined, so messages can be sent.
+    joined: bool,
+    queue: VecDeque<String>,
+    /// The failed connection attempts since the last successful one.
+    failures: u32,
+}
+
+impl IrcBridge {
+    /// Starts the bridge in its own arbiter and returns the hook feeding it.
+    pub(super) fn start(config: IrcConfig, admin: AdminHandle, stats: Arc<IrcStats>) -> IrcHook {
+        let bridge_stats = stats.clone();
+        let addr = IrcBridge::start_in_arbiter(&Arbiter::new(), move |_| IrcBridge {
+            nick: config.nick.clone(),
+            config,
+            admin,
+            stats: bridge_stats,
+            writer: None,
+            reader: None,
+            joined: false,
+            queue: VecDeque::new(),
+            failures: 0,
+        });
+        IrcHook {
+            bridge: addr.recipient(),
+            stats,
+        }
+    }
+
+    fn connect(&mut self, ctx: &mut Context<Self>) {
+        info!("Connecting to IRC server `{}`.", self.config.server);
+        connect(&self.config.server, self.config.tls)
+            .into_actor(self)
+            .then(|res, actor, ctx| {
+                match res {
+                    Ok((reader, writer)) => actor.connected(reader, writer, ctx),
+                    Err(err) => {
+                        warn!(
+                            "Could not connect to IRC server `{}`: {}",
+                            actor.config.server, err
+                        );
+                        actor.reconnect_later(ctx);
+                    }
+                }
+                fut::ok(())
+            })
+            .spawn(ctx);
+    }
+
+    fn connected(&mut self, reader: Reader, writer: Writer, ctx: &mut Context<Self>) {
+        self.failures = 0;
+        self.nick = self.config.nick.clone();
+        self.reader = Some(ctx.add_stream(FramedRead::new(reader, IrcCodec::new())));
+        let mut writer = FramedWrite::new(writer, IrcCodec::new(), ctx);
+        writer.write(format!("NICK {}", self.nick));
+        writer.write(format!("USER {} 0 * :AxoChat bridge", self.config.nick));
+        self.writer = Some(writer);
+    }
+
+    /// Forgets the current connection and connects again after a delay.
+    fn disconnected(&mut self, ctx: &mut Context<Self>) {
+        if let Some(reader) = self.reader.take() {
+            ctx.cancel_future(reader);
+        }
+        if self.writer.take().is_none() {
+            return;
+        }
+        self.joined = false;
+        warn!("Lost connection to IRC server `{}`.", self.config.server);
+        self.reconnect_later(ctx);
+    }
+
+    fn reconnect_later(&mut self, ctx: &mut Context<Self>) {
+        let max = *self.config.reconnect_max;
+        let delay = MIN_RECONNECT_DELAY
+            .checked_mul(1 << self.failures.min(16))
+            .map_or(max, |delay| delay.min(max));
+        self.failures += 1;
+        info!("Reconnecting to IRC in {:?}.", delay);
+        ctx.run_later(delay, |actor, ctx| actor.connect(ctx));
+    }
+
+    /// Sends the oldest queued message, if the channel was joined.
+    fn send_next(&mut self) {
+        if !self.joined {
+            return;
+        }
+        if let (Some(writer), Some(text)) = (&mut self.writer, self.queue.pop_front()) {
+            writer.write(format!("PRIVMSG {} :{}", self.config.channel, text));
+        }
+    }
+
+    fn handle_line(&mut self, line: &str) {
+        let (prefix, command, params) = match parse_line(line) {
+            Some(parsed) => parsed,
+            None => return,
+        };
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return,
+        };
+        match command {
+            "PING" => writer.write(format!("PONG :{}", params.join(" "))),
+            // The server accepted the registration.
+            "001" => {
+                info!("Joining IRC channel `{}`.", self.config.channel);
+                writer.write(format!("JOIN {}", self.config.channel));
+            }
+            // The nick is in use.
+            "433" => {
+                self.nick.push('_');
+                writer.write(format!("NICK {}", self.nick));
+            }
+            "JOIN" if nick_of(prefix) == Some(&self.nick) => self.joined = true,
+            "PRIVMSG" if self.config.relay_incoming => {
+                let (target, text) = match params.as_slice() {
+                    [target, text] => (target, text),
+                    _ => return,
+                };
+                let sender = match nick_of(prefix) {
+                    Some(sender) => sender,
+                    None => return,
+                };
+                if !target.eq_ignore_ascii_case(&self.config.channel) || sender == self.nick {
+                    return;
+                }
+                let author_info = UserInfo {
+                    name: sender.to_string(),
+                    uuid: Uuid::nil(),
+                    bot: false,
+                };
+                Arbiter::spawn(
+                    self.admin
+                        .relay(author_info, "irc".to_string(), text.to_string())
+                        .map_err(|err| debug!("Could not relay IRC message: {}", err)),
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Actor for IrcBridge {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        self.connect(ctx);
+        ctx.run_interval(*self.config.send_interval, |actor, _ctx| actor.send_next());
+    }
+}
+
+impl Handler<Relay> for IrcBridge {
+    type Result = ();
+
+    fn handle(&mut self, msg: Relay, _ctx: &mut Context<Self>) {
+        if self.queue.len() >= self.config.max_queue {
+            self.stats.record_dropped();
+            return;
+        }
+        self.queue.push_back(sanitize(&msg.0));
+    }
+}
+
+impl WriteHandler<io::Error> for IrcBridge {
+    fn error(&mut self, err: io::Error, ctx: &mut Self::Context) -> Running {
+        warn!("Could not write to IRC: {}", err);
+        self.disconnected(ctx);
+        Running::Continue
+    }
+}
+
+impl StreamHandler<String, io::Error> for IrcBridge {
+    fn handle(&mut self, line: String, _ctx: &mut Context<Self>) {
+        self.handle_line(&line);
+    }
+
+    fn error(&mut self, err: io::Error, _ctx: &mut Context<Self>) -> Running {
+        warn!("Could not read from IRC: {}", err);
+        Running::Stop
+    }
+
+    fn finished(&mut self, ctx: &mut Context<Self>) {
+        self.reader = None;
+        self.disconnected(ctx);
+    }
+}
+
+/// Connects to `server`, a `host:port`, using TLS if `tls` is set.
+fn connect(server: &str, tls: bool) -> Box<dyn Future<Item = (Reader, Writer), Error = io::Error>> {
+    // The bridge has its own arbiter, so resolving the address may block.
+    let addr: SocketAddr = match server.to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(addr)) => addr,
+        Ok(None) => {
+            return Box::new(future::err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "server has no address",
+            )))
+        }
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let stream = TcpStream::connect(&addr);
+    if !tls {
+        return Box::new(stream.map(|stream| {
+            let (reader, writer) = stream.split();
+            (Box::new(reader) as Reader, Box::new(writer) as Writer)
+        }));
+    }
+
+    let host = server.rsplit_once(':').map_or(server, |(host, _)| host);
+    let domain = match webpki::DNSNameRef::try_from_ascii_str(host) {
+        Ok(domain) => domain.to_owned(),
+        Err(()) => {
+            return Box::new(future::err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "server is not a valid DNS name",
+            )))
+        }
+    };
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    let connector = TlsConnector::from(Arc::new(config));
+    Box::new(
+        stream
+            .and_then(move |stream| connector.connect(domain.as_ref(), stream))
+            .map(|stream| {
+                let (reader, writer) = stream.split();
+                (Box::new(reader) as Reader, Box::new(writer) as Writer)
+            }),
+    )
+}
+
+/// Splits an IRC line into its prefix, command and parameters.
+fn parse_line(line: &str) -> Option<(Option<&str>, &str, Vec<&str>)> {
+    let (prefix, rest) = match line.strip_prefix(':') {
+        Some(rest) => {
+            let (prefix, rest) = rest.split_once(' ')?;
+            (Some(prefix), rest)
+        }
+        None => (None, line),
+    };
+    let (middle, trailing) = match rest.split_once(" :") {
+        Some((middle, trailing)) => (middle, Some(trailing)),
+        None => (rest, None),
+    };
+    let mut words = middle.split(' ').filter(|word| !word.is_empty());
+    let command = words.next()?;
+    let mut params: Vec<&str> = words.collect();
+    params.extend(trailing);
+    Some((prefix, command, params))
+}
+
+/// Returns the nick of a `nick!user@host` prefix.
+fn nick_of(prefix: Option<&str>) -> Option<&str> {
+    prefix.map(|prefix| prefix.split('!').next().unwrap_or(prefix))
+}
+
+/// Removes line breaks and shortens `text` to [`MAX_TEXT_LENGTH`] bytes.
+fn sanitize(text: &str) -> String {
+    let mut sanitized = String::new();
+    for c in text.chars() {
+        let c = if c == '\r' || c == '\n' { ' ' } else { c };
+        if sanitized.len() + c.len_utf8() > MAX_TEXT_LENGTH {
+            break;
+        }
+        sanitized.push(c);
+    }
+    sanitized
+}
+
+/// Decodes lines ending with `\n` or `\r\n` and encodes lines ending with `\r\n`.
+struct IrcCodec {
+    lines: LinesCodec,
+}
+
+impl IrcCodec {
+    fn new() -> IrcCodec {
+        IrcCodec {
+            lines: LinesCodec::new_with_max_length(MAX_LINE_LENGTH),
+        }
+    }
+}
+
+impl Decoder for IrcCodec {
+    type Item = String;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<String>> {
+        self.lines.decode(src)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> io::Result<Option<String>> {
+        self.lines.decode_eof(src)
+    }
+}
+
+impl Encoder for IrcCodec {
+    type Item = String;
+    type Error = io::Error;
+
+    fn encode(&mut self, line: String, dst: &mut BytesMut) -> io::Result<()> {
+        dst.reserve(line.len() + 2);
+        dst.extend_from_slice(line.as_bytes());
+        dst.extend_from_slice(b"\r\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+/// Counts the open connections and limits them to a maximum.
+///
+/// The last `reserved` connections below the maximum are reserved for moderators.
+pub struct ConnectionLimit {
+    current: AtomicUsize,
+    /// The open connections which did not log in, as last counted by the chat server.
+    guests: AtomicUsize,
+    max: Option<usize>,
+    reserved: usize,
+    retry_after: Duration,
+    /// Set while the instance drains, as decided by the chat server.
+    draining: AtomicBool,
+    drain_retry_after: Duration,
+}
+
+impl ConnectionLimit {
+    pub fn new(
+        max: Option<usize>,
+        reserved: usize,
+        retry_after: Duration,
+        drain_retry_after: Duration,
+    ) -> ConnectionLimit {
+        ConnectionLimit {
+            current: AtomicUsize::new(0),
+            guests: AtomicUsize::new(0),
+            max,
+            reserved,
+            retry_after,
+            draining: AtomicBool::new(false),
+            drain_retry_after,
+        }
+    }
+
+    /// The time rejected clients are told to wait before retrying.
+    pub fn retry_after(&self) -> Duration {
+        if self.draining() {
+            self.drain_retry_after
+        } else {
+            self.retry_after
+        }
+    }
+
+    /// Whether the instance drains, so no new connections are accepted.
+    pub fn draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    pub(super) fn set_draining(&self, draining: bool) {
+        self.draining.store(draining, Ordering::SeqCst);
+    }
+
+    /// The number of open connections.
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::SeqCst)
+    }
+
+    /// The number of open connections which did not log in.
+    pub fn guests(&self) -> usize {
+        self.guests.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn set_guests(&self, guests: usize) {
+        self.guests.store(guests, Ordering::Relaxed);
+    }
+
+    /// The maximum number of connections, if there is one.
+    pub fn max(&self) -> Option<usize> {
+        self.max
+    }
+
+    /// Counts a new connection, unless the maximum is reached or the instance drains.
+    ///
+    /// The connection is counted until the returned guard is dropped.
+    pub(super) fn try_acquire(self: &Arc<Self>) -> Option<ConnectionGuard> {
+        if self.draining() {
+            return None;
+        }
+        let previous = self.current.fetch_add(1, Ordering::SeqCst);
+        let reserved = match self.max {
+            Some(max) if previous >= max => {
+                self.current.fetch_sub(1, Ordering::SeqCst);
+                return None;
+            }
+            Some(max) => previous >= max.saturating_sub(self.reserved),
+            None => false,
+        };
+        Some(ConnectionGuard {
+            limit: self.clone(),
+            reserved,
+        })
+    }
+}
+
+/// Keeps a connection counted until it is dropped.
+pub(super) struct ConnectionGuard {
+    limit: Arc<ConnectionLimit>,
+    /// Whether the connection uses a slot reserved for moderators.
+    pub reserved: bool,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.limit.current.fetch_sub(1, Ordering::SeqCst);
+    }
+}
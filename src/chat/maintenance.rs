@@ -0,0 +1,524 @@
+//! Periodic cleanups of state which accumulates, run by a single scheduler.
+//!
+//! Every `maintenance.interval` of the [`Clock`](super::Clock) of the chat server,
+//! the jobs of [`BUILTIN_JOBS`] which are enabled by the configuration run once, in order,
+//! followed by the jobs registered with [`ChatServerBuilder::maintenance_job`](super::ChatServerBuilder::maintenance_job).
+//! A run can also be started with `/api/v1/maintenance`.
+//!
+//! Jobs run inside of the chat server actor, so they should be quick;
+//! exceeding `maintenance.job_budget` there is logged and counted, but not prevented.
+//! Jobs which block, like on I/O, hand their work to the thread pool with [`JobRun::Blocking`]
+//! and time out once it takes longer than the budget; they are not started again until it finished.
+//! A failing or panicking job is logged and does not stop the others.
+
+use super::{cluster, hook::panic_message, ChatServer};
+use crate::config::Config;
+use crate::error::*;
+use log::*;
+
+use actix::*;
+use actix_web::{error::BlockingError, web};
+use futures::Future;
+use serde::Serialize;
+use std::cell::Cell;
+use std::fmt::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often it is checked whether the next run is due.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The upper bounds of the duration histogram buckets, in seconds.
+const DURATION_BUCKETS: &[f64] = &[0.001, 0.01, 0.1, 0.5, 1.0, 5.0, 30.0];
+
+/// A custom cleanup which is run with the maintenance jobs of the chat server.
+///
+/// Jobs are registered using [`ChatServerBuilder::maintenance_job`](super::ChatServerBuilder::maintenance_job)
+/// and run after the built-in ones, in registration order.
+pub trait MaintenanceJob {
+    /// The name of the job, which labels its metrics and is shown on the status page.
+    fn name(&self) -> &'static str;
+
+    /// Runs the job once.
+    fn run(&mut self) -> JobRun;
+}
+
+/// What a maintenance job did when it ran.
+pub enum JobRun {
+    /// The job finished inside of the chat server.
+    Done(Result<()>),
+    /// The job continues on the thread pool, which reports back once it finished.
+    Blocking(Box<dyn FnOnce() -> std::result::Result<(), String> + Send>),
+}
+
+/// A job which comes with the chat server.
+struct BuiltinJob {
+    name: &'static str,
+    /// Whether the configuration enables the job.
+    enabled: fn(&Config) -> bool,
+    run: fn(&mut ChatServer) -> JobRun,
+}
+
+/// The built-in jobs, in the order they run.
+const BUILTIN_JOBS: &[BuiltinJob] = &[
+    BuiltinJob {
+        name: "audit_log",
+        enabled: |config| config.moderation.audit_retention_days != 0,
+        run: |server| JobRun::Done(server.prune_audit()),
+    },
+    BuiltinJob {
+        name: "pm_metadata",
+        enabled: |config| {
+            config.moderation.pm_metadata_retention_minutes != 0
+                && config.moderation.pm_metadata_max_entries != 0
+        },
+        run: |server| {
+            server.prune_pm_metadata();
+            JobRun::Done(Ok(()))
+        },
+    },
+    BuiltinJob {
+        name: "last_seen",
+        enabled: |_config| true,
+        run: |server| {
+            server.prune_last_seen();
+            JobRun::Done(Ok(()))
+        },
+    },
+    BuiltinJob {
+        name: "restrictions",
+        enabled: |_config| true,
+        run: |server| {
+            server.prune_restrictions();
+            JobRun::Done(Ok(()))
+        },
+    },
+    BuiltinJob {
+        name: "resume_tokens",
+        enabled: |config| config.resume.enabled,
+        run: |server| {
+            server.prune_resume_tokens();
+            JobRun::Done(Ok(()))
+        },
+    },
+];
+
+enum Job {
+    Builtin(&'static BuiltinJob),
+    Custom(Box<dyn MaintenanceJob>),
+}
+
+impl Job {
+    fn name(&self) -> &'static str {
+        match self {
+            Job::Builtin(job) => job.name,
+            Job::Custom(job) => job.name(),
+        }
+    }
+}
+
+/// How the last run of a job ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobOutcome {
+    Succeeded,
+    Failed,
+    Panicked,
+    /// The work on the thread pool took longer than `maintenance.job_budget`.
+    TimedOut,
+}
+
+impl JobOutcome {
+    fn label(self) -> &'static str {
+        match self {
+            JobOutcome::Succeeded => "succeeded",
+            JobOutcome::Failed => "failed",
+            JobOutcome::Panicked => "panicked",
+            JobOutcome::TimedOut => "timed_out",
+        }
+    }
+}
+
+/// The last run of a maintenance job, as shown on the status page and returned by the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub name: &'static str,
+    /// When the last run finished, in milliseconds since the unix epoch.
+    pub last_run: Option<u64>,
+    pub last_duration_ms: Option<u64>,
+    pub last_outcome: Option<JobOutcome>,
+    /// Whether the job is waiting for its work on the thread pool.
+    pub running: bool,
+}
+
+/// The scheduler and the registered jobs.
+pub(super) struct Maintenance {
+    jobs: Vec<Job>,
+    /// Whether each job is waiting for its work on the thread pool.
+    running: Vec<bool>,
+    /// When the next run is due, or `None` before the chat server started.
+    next_run: Option<Instant>,
+    stats: Arc<MaintenanceStats>,
+}
+
+impl Maintenance {
+    /// Registers the built-in jobs enabled by `config`, followed by `custom`.
+    pub fn new(config: &Config, custom: Vec<Box<dyn MaintenanceJob>>) -> Maintenance {
+        let jobs: Vec<Job> = BUILTIN_JOBS
+            .iter()
+            .filter(|job| (job.enabled)(config))
+            .map(Job::Builtin)
+            .chain(custom.into_iter().map(Job::Custom))
+            .collect();
+        let stats = MaintenanceStats {
+            jobs: Mutex::new(jobs.iter().map(|job| JobStats::new(job.name())).collect()),
+        };
+        Maintenance {
+            running: vec![false; jobs.len()],
+            jobs,
+            next_run: None,
+            stats: Arc::new(stats),
+        }
+    }
+
+    pub fn stats(&self) -> Arc<MaintenanceStats> {
+        self.stats.clone()
+    }
+}
+
+/// The durations and outcomes of the maintenance jobs, shared by the chat server and the routes.
+pub(super) struct MaintenanceStats {
+    /// By job, in the order they run.
+    jobs: Mutex<Vec<JobStats>>,
+}
+
+struct JobStats {
+    name: &'static str,
+    /// The number of runs which took at most each bucket.
+    buckets: Vec<u64>,
+    duration_sum: Duration,
+    runs: u64,
+    /// The number of runs which did not succeed, by the outcomes of [`FAILURES`].
+    failures: [u64; 3],
+    /// The number of runs inside of the chat server which exceeded `maintenance.job_budget`.
+    over_budget: u64,
+    last_run: Option<u64>,
+    last_duration: Option<Duration>,
+    last_outcome: Option<JobOutcome>,
+}
+
+impl JobStats {
+    fn new(name: &'static str) -> JobStats {
+        JobStats {
+            name,
+            buckets: vec![0; DURATION_BUCKETS.len()],
+            duration_sum: Duration::from_secs(0),
+            runs: 0,
+            failures: [0; 3],
+            over_budget: 0,
+            last_run: None,
+            last_duration: None,
+            last_outcome: None,
+        }
+    }
+}
+
+/// The outcomes which count as failures, in the order of [`JobStats::failures`].
+const FAILURES: [JobOutcome; 3] = [
+    JobOutcome::Failed,
+    JobOutcome::Panicked,
+    JobOutcome::TimedOut,
+];
+
+impl MaintenanceStats {
+    fn record(&self, index: usize, finished: u64, duration: Duration, outcome: JobOutcome) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = &mut jobs[index];
+        let secs = duration.as_secs_f64();
+        for (bucket, bound) in job.buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if secs <= *bound {
+                *bucket += 1;
+            }
+        }
+        job.duration_sum += duration;
+        job.runs += 1;
+        if let Some(failure) = FAILURES.iter().position(|failure| *failure == outcome) {
+            job.failures[failure] += 1;
+        }
+        job.last_run = Some(finished);
+        job.last_duration = Some(duration);
+        job.last_outcome = Some(outcome);
+    }
+
+    fn record_over_budget(&self, index: usize) {
+        self.jobs.lock().unwrap()[index].over_budget += 1;
+    }
+
+    /// Appends the histogram and the counters in the Prometheus text format.
+    pub fn write_metrics(&self, output: &mut String) {
+        let jobs = self.jobs.lock().unwrap();
+        writeln!(
+            output,
+            "# HELP axochat_maintenance_job_duration_seconds How long the runs of each maintenance job took."
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# TYPE axochat_maintenance_job_duration_seconds histogram"
+        )
+        .unwrap();
+        for job in jobs.iter() {
+            for (bucket, bound) in job.buckets.iter().zip(DURATION_BUCKETS) {
+                writeln!(
+                    output,
+                    "axochat_maintenance_job_duration_seconds_bucket{{job=\"{}\",le=\"{}\"}} {}",
+                    job.name, bound, bucket
+                )
+                .unwrap();
+            }
+            writeln!(
+                output,
+                "axochat_maintenance_job_duration_seconds_bucket{{job=\"{}\",le=\"+Inf\"}} {}",
+                job.name, job.runs
+            )
+            .unwrap();
+            writeln!(
+                output,
+                "axochat_maintenance_job_duration_seconds_sum{{job=\"{}\"}} {}",
+                job.name,
+                job.duration_sum.as_secs_f64()
+            )
+            .unwrap();
+            writeln!(
+                output,
+                "axochat_maintenance_job_duration_seconds_count{{job=\"{}\"}} {}",
+                job.name, job.runs
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            output,
+            "# HELP axochat_maintenance_job_failures_total The number of runs of each maintenance job which did not succeed."
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# TYPE axochat_maintenance_job_failures_total counter"
+        )
+        .unwrap();
+        for job in jobs.iter() {
+            for (outcome, count) in FAILURES.iter().zip(&job.failures) {
+                writeln!(
+                    output,
+                    "axochat_maintenance_job_failures_total{{job=\"{}\",outcome=\"{}\"}} {}",
+                    job.name,
+                    outcome.label(),
+                    count
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(
+            output,
+            "# HELP axochat_maintenance_job_over_budget_total The number of runs of each maintenance job on the chat server which took longer than maintenance.job_budget."
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# TYPE axochat_maintenance_job_over_budget_total counter"
+        )
+        .unwrap();
+        for job in jobs.iter() {
+            writeln!(
+                output,
+                "axochat_maintenance_job_over_budget_total{{job=\"{}\"}} {}",
+                job.name, job.over_budget
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            output,
+            "# HELP axochat_maintenance_job_last_run_timestamp_seconds When each maintenance job last finished, in seconds since the unix epoch."
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# TYPE axochat_maintenance_job_last_run_timestamp_seconds gauge"
+        )
+        .unwrap();
+        for job in jobs.iter() {
+            if let Some(last_run) = job.last_run {
+                writeln!(
+                    output,
+                    "axochat_maintenance_job_last_run_timestamp_seconds{{job=\"{}\"}} {}",
+                    job.name,
+                    last_run as f64 / 1000.0
+                )
+                .unwrap();
+            }
+        }
+    }
+}
+
+impl ChatServer {
+    /// Checks every [`CHECK_INTERVAL`] whether the next run is due, which the first one is immediately.
+    pub(super) fn start_maintenance(&mut self, ctx: &mut Context<Self>) {
+        self.maintenance.next_run = Some(self.now());
+        ctx.run_interval(CHECK_INTERVAL, |actor, ctx| {
+            if actor
+                .maintenance
+                .next_run
+                .is_some_and(|next_run| actor.now() >= next_run)
+            {
+                actor.run_maintenance(ctx);
+            }
+        });
+    }
+
+    /// Runs every job once and schedules the next run after `maintenance.interval`.
+    ///
+    /// Jobs still waiting for their work on the thread pool are skipped.
+    pub(in crate::chat) fn run_maintenance(&mut self, ctx: &mut Context<Self>) {
+        self.maintenance.next_run = Some(self.now() + *self.config.maintenance.interval);
+        for index in 0..self.maintenance.jobs.len() {
+            let name = self.maintenance.jobs[index].name();
+            if self.maintenance.running[index] {
+                debug!(
+                    "Skipping maintenance job `{}`, which is still running.",
+                    name
+                );
+                continue;
+            }
+
+            let start = Instant::now();
+            let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                match &mut self.maintenance.jobs[index] {
+                    Job::Builtin(job) => {
+                        let run = job.run;
+                        run(self)
+                    }
+                    Job::Custom(job) => job.run(),
+                }
+            }));
+            let elapsed = start.elapsed();
+            let budget = *self.config.maintenance.job_budget;
+            if elapsed > budget {
+                warn!(
+                    "Maintenance job `{}` took {:?} on the chat server, exceeding its budget of {:?}.",
+                    name, elapsed, budget
+                );
+                self.maintenance.stats.record_over_budget(index);
+            }
+
+            let outcome = match res {
+                Ok(JobRun::Done(Ok(()))) => JobOutcome::Succeeded,
+                Ok(JobRun::Done(Err(err))) => {
+                    warn!("Maintenance job `{}` failed: {}", name, err);
+                    JobOutcome::Failed
+                }
+                Ok(JobRun::Blocking(work)) => {
+                    self.spawn_blocking_job(index, start, work, ctx);
+                    continue;
+                }
+                Err(payload) => {
+                    error!(
+                        "Maintenance job `{}` panicked: {}",
+                        name,
+                        panic_message(&*payload)
+                    );
+                    JobOutcome::Panicked
+                }
+            };
+            self.finish_job(index, start, outcome);
+        }
+    }
+
+    /// Runs the work of the job `index` on the thread pool and records how it ended,
+    /// or that it timed out once it takes longer than `maintenance.job_budget`.
+    ///
+    /// The job is not started again until its work finished, even once it timed out,
+    /// since the thread pool can not interrupt it.
+    fn spawn_blocking_job(
+        &mut self,
+        index: usize,
+        start: Instant,
+        work: Box<dyn FnOnce() -> std::result::Result<(), String> + Send>,
+        ctx: &mut Context<Self>,
+    ) {
+        self.maintenance.running[index] = true;
+        let name = self.maintenance.jobs[index].name();
+        // Whether the outcome of this run was recorded, by the timeout or once the work finished.
+        let recorded = Rc::new(Cell::new(false));
+
+        let timed_out = recorded.clone();
+        let budget = *self.config.maintenance.job_budget;
+        ctx.run_later(budget, move |actor, _ctx| {
+            if !timed_out.replace(true) {
+                warn!(
+                    "Maintenance job `{}` took longer than {:?}, its work keeps running on the thread pool.",
+                    name, budget
+                );
+                actor.finish_job(index, start, JobOutcome::TimedOut);
+            }
+        });
+
+        web::block(work)
+            .then(move |res| {
+                Ok::<_, ()>(match res {
+                    Ok(()) => JobOutcome::Succeeded,
+                    Err(BlockingError::Error(err)) => {
+                        warn!("Maintenance job `{}` failed: {}", name, err);
+                        JobOutcome::Failed
+                    }
+                    // The work panicked, which drops the sender of its result.
+                    Err(BlockingError::Canceled) => {
+                        error!("Maintenance job `{}` panicked.", name);
+                        JobOutcome::Panicked
+                    }
+                })
+            })
+            .into_actor(self)
+            .map(move |outcome, actor, _ctx| {
+                actor.maintenance.running[index] = false;
+                if recorded.replace(true) {
+                    info!(
+                        "Maintenance job `{}` finished after {:?}, having timed out.",
+                        name,
+                        start.elapsed()
+                    );
+                } else {
+                    actor.finish_job(index, start, outcome);
+                }
+            })
+            .spawn(ctx);
+    }
+
+    fn finish_job(&self, index: usize, start: Instant, outcome: JobOutcome) {
+        let finished = cluster::unix_millis(self.system_now());
+        self.maintenance
+            .stats
+            .record(index, finished, start.elapsed(), outcome);
+    }
+
+    /// The last runs of the jobs, in the order they run.
+    pub(in crate::chat) fn maintenance_status(&self) -> Vec<JobStatus> {
+        let jobs = self.maintenance.stats.jobs.lock().unwrap();
+        jobs.iter()
+            .zip(&self.maintenance.running)
+            .map(|(job, running)| JobStatus {
+                name: job.name,
+                last_run: job.last_run,
+                last_duration_ms: job
+                    .last_duration
+                    .map(|duration| duration.as_millis() as u64),
+                last_outcome: job.last_outcome,
+                running: *running,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,126 @@
+#[cfg(feature = "irc")]
+use super::irc::IrcStats;
+use super::{
+    api::RequestCounts, auth_monitor::AuthMonitor, delivery::DeliveryStats,
+    firehose::FirehoseStats, funnel::Funnel, maintenance::MaintenanceStats,
+    persistence::StorageHealth, validation_stats::ValidationStats, Backlog, ConnectionLimit,
+};
+use crate::version;
+
+use actix_web::{web, HttpResponse};
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// Serves metrics in the Prometheus text format.
+// Every argument extracts one of the shared statistics;
+// those of the storage and the maintenance jobs share one, since handlers take at most 10.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn metrics_route(
+    limit: web::Data<Arc<ConnectionLimit>>,
+    api_counts: web::Data<Arc<RequestCounts>>,
+    backlog: web::Data<Arc<Backlog>>,
+    auth_monitor: web::Data<Arc<AuthMonitor>>,
+    validation_stats: web::Data<Arc<ValidationStats>>,
+    funnel: web::Data<Arc<Funnel>>,
+    firehose: web::Data<Arc<FirehoseStats>>,
+    delivery: web::Data<Arc<DeliveryStats>>,
+    (storage_health, maintenance): (
+        web::Data<Arc<StorageHealth>>,
+        web::Data<Arc<MaintenanceStats>>,
+    ),
+    #[cfg(feature = "irc")] irc: web::Data<Arc<IrcStats>>,
+) -> HttpResponse {
+    let mut output = String::new();
+    writeln!(
+        output,
+        "# HELP axochat_build_info The build of the server; the value is always 1."
+    )
+    .unwrap();
+    writeln!(output, "# TYPE axochat_build_info gauge").unwrap();
+    writeln!(
+        output,
+        "axochat_build_info{{version=\"{}\",commit=\"{}\",build_timestamp=\"{}\"}} 1",
+        version::VERSION,
+        version::GIT_COMMIT,
+        version::BUILD_TIMESTAMP,
+    )
+    .unwrap();
+    gauge(
+        &mut output,
+        "axochat_uptime_seconds",
+        "How long the server has been running.",
+        version::uptime().as_secs() as usize,
+    );
+    gauge(
+        &mut output,
+        "axochat_connections",
+        "The number of open websocket connections.",
+        limit.current(),
+    );
+    gauge(
+        &mut output,
+        "axochat_guest_viewers",
+        "The number of open websocket connections which did not log in.",
+        limit.guests(),
+    );
+    gauge(
+        &mut output,
+        "axochat_chat_server_backlog",
+        "The approximate number of messages waiting for the chat server.",
+        backlog.pending(),
+    );
+    if let Some(max) = limit.max() {
+        gauge(
+            &mut output,
+            "axochat_max_connections",
+            "The maximum number of websocket connections.",
+            max,
+        );
+    }
+    gauge(
+        &mut output,
+        "axochat_draining",
+        "Whether the instance drains; 1 while it does, 0 otherwise.",
+        usize::from(limit.draining()),
+    );
+    gauge(
+        &mut output,
+        "axochat_drain_remaining_connections",
+        "The number of websocket connections left to close while the instance drains; 0 otherwise.",
+        if limit.draining() { limit.current() } else { 0 },
+    );
+
+    writeln!(
+        output,
+        "# HELP axochat_api_requests_total The number of admin API requests."
+    )
+    .unwrap();
+    writeln!(output, "# TYPE axochat_api_requests_total counter").unwrap();
+    api_counts.for_each(|method, route, status, count| {
+        writeln!(
+            output,
+            "axochat_api_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+            method, route, status, count
+        )
+        .unwrap();
+    });
+    auth_monitor.write_metrics(&mut output);
+    validation_stats.write_metrics(&mut output);
+    funnel.write_metrics(&mut output);
+    firehose.write_metrics(&mut output);
+    delivery.write_metrics(&mut output);
+    storage_health.write_metrics(&mut output);
+    maintenance.write_metrics(&mut output);
+    #[cfg(feature = "irc")]
+    irc.write_metrics(&mut output);
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(output)
+}
+
+fn gauge(output: &mut String, name: &str, help: &str, value: usize) {
+    writeln!(output, "# HELP {} {}", name, help).unwrap();
+    writeln!(output, "# TYPE {} gauge", name).unwrap();
+    writeln!(output, "{} {}", name, value).unwrap();
+}
@@ -1,101 +1,322 @@
+mod admin;
+mod api;
+mod audit;
+mod auth_monitor;
+mod backlog;
+mod builder;
+mod capabilities;
+mod clock;
+pub mod close;
+mod cluster;
+mod compat;
 mod connect;
+mod decode;
+mod delivery;
+mod drain;
+mod firehose;
+mod flags;
+mod footprint;
+mod funnel;
+mod guest;
 mod handler;
+mod history;
+mod hook;
 mod id;
+mod info;
+#[cfg(feature = "irc")]
+mod irc;
+mod limit;
+mod maintenance;
+mod metrics;
+mod outgoing;
+mod persistence;
+mod pm_metadata;
+mod presence;
+mod protocol;
+#[cfg(test)]
+mod samples;
+mod schema;
 mod session;
+mod sessions;
+mod simulate;
+mod stats;
+mod status;
+mod subprotocol;
+mod trace;
+mod validation_stats;
 
+pub use admin::{AdminHandle, ValidationReport, Violation};
+pub use backlog::Backlog;
+pub use builder::{ChatHandle, ChatServerBuilder};
+pub use capabilities::Capabilities;
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use compat::{LEGACY_PROTOCOL_VERSION, PROTOCOL_VERSION};
+pub use decode::{decode_packet, PacketLimits};
+pub use firehose::{EventKind, ObserverInfo};
+pub use flags::MessageFlags;
+pub use footprint::SessionFootprint;
+pub use handler::{Diagnostics, RateLimitDiagnostics, UserLookup, UserStatus};
+pub use hook::{ChatHook, HookDecision, HOOK_TIME_BUDGET};
 pub use id::*;
+pub use limit::ConnectionLimit;
+pub use maintenance::{JobOutcome, JobRun, JobStatus, MaintenanceJob};
+pub use schema::{protocol_schema, Schema};
+pub use simulate::{SimulatedIdentity, Simulation};
+pub use trace::{current_trace, TraceId};
 
-use crate::config::Config;
+use crate::config::{Config, GuestConfig};
 use crate::error::*;
+use close::DisconnectReason;
+use funnel::Stage;
 use log::*;
 
 use actix::*;
-use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 
 use crate::auth::{Authenticator, UserInfo};
-use crate::message::{MessageValidator, RateLimiter};
+use crate::emote::Emotes;
+use crate::message::{MessageValidator, RateLimiter, ValidatedContent};
 use crate::moderation::Moderation;
-use rand::{rngs::OsRng, SeedableRng};
-use rand_hc::Hc128Rng;
-use std::collections::{HashMap, HashSet};
+use crate::signing::{MessageSignature, MessageSigner};
+use crate::storage::{AuditEntry, AuditPage, Storage};
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
 use uuid::Uuid;
 
+/// The websocket endpoint clients connect to.
+///
+/// If the maximum number of connections is reached or the instance drains,
+/// the handshake is rejected with `503 Service Unavailable`.
+/// Clients offering only unknown subprotocols are rejected with `400 Bad Request`.
 pub fn chat_route(
     req: HttpRequest,
     stream: web::Payload,
     srv: web::Data<Addr<ChatServer>>,
+    limit: web::Data<Arc<ConnectionLimit>>,
+    backlog: web::Data<Arc<Backlog>>,
+    handshake: web::Data<session::HandshakePolicy>,
+    packet_limits: web::Data<PacketLimits>,
 ) -> actix_web::Result<HttpResponse> {
-    ws::start(
-        session::Session::new(InternalId::new(0), srv.get_ref().clone()),
-        &req,
-        stream,
-    )
+    let negotiation = subprotocol::negotiate(req.headers());
+    if negotiation == subprotocol::Negotiation::Unknown {
+        info!("Rejecting connection, it offered only unknown subprotocols.");
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+    let guard = match limit.try_acquire() {
+        Some(guard) => guard,
+        None if limit.draining() => {
+            info!("Rejecting connection, the server is draining.");
+            return Ok(HttpResponse::ServiceUnavailable()
+                .header(
+                    header::RETRY_AFTER,
+                    limit.retry_after().as_secs().to_string(),
+                )
+                .finish());
+        }
+        None => {
+            info!("Rejecting connection, the server is full.");
+            return Ok(HttpResponse::ServiceUnavailable()
+                .header(
+                    header::RETRY_AFTER,
+                    limit.retry_after().as_secs().to_string(),
+                )
+                .finish());
+        }
+    };
+
+    let mut res = ws::handshake(&req)?;
+    let protocol = match negotiation {
+        subprotocol::Negotiation::Selected { name, protocol } => {
+            res.header(header::SEC_WEBSOCKET_PROTOCOL, name);
+            Some(protocol)
+        }
+        _ => None,
+    };
+    let session = session::Session::new(
+        InternalId::new(0, Origin::Client, 0),
+        srv.get_ref().clone(),
+        guard,
+        req.peer_addr().map(|addr| addr.ip()),
+        backlog.get_ref().clone(),
+        *handshake.get_ref(),
+        *packet_limits.get_ref(),
+        protocol,
+    );
+    Ok(res.streaming(ws::WebsocketContext::create(session, stream)))
 }
 
+/// The actor managing all connections and users.
+///
+/// It is created by a [`ChatServerBuilder`].
 pub struct ChatServer {
-    connections: HashMap<InternalId, SessionState>,
-    users: HashMap<String, UserSession>,
+    sessions: sessions::Sessions,
 
     rng: rand_hc::Hc128Rng,
     authenticator: Option<Authenticator>,
     validator: MessageValidator,
+    signer: Option<Arc<MessageSigner>>,
     moderation: Moderation,
+    storage: Box<dyn Storage>,
+    hooks: Vec<Box<dyn ChatHook>>,
+    emotes: Emotes,
+    cluster: Option<cluster::Cluster>,
+    history: history::History,
+    resume_tokens: HashMap<String, handler::ResumeState>,
+    /// The connections waiting for a user to log in and when they stop waiting, by the name of the user.
+    online_watches: HashMap<CanonicalId, HashMap<InternalId, Instant>>,
+    /// When a session of each user closed for the last time.
+    last_seen: HashMap<Uuid, handler::LastSeen>,
+    /// The names each user used before, oldest first.
+    previous_names: HashMap<Uuid, VecDeque<handler::PreviousName>>,
+    /// The users who were renamed, by their previous name.
+    renamed: HashMap<CanonicalId, Uuid>,
+    /// The blocked words, including changes whose filter is still being built.
+    blocked_words: BTreeSet<String>,
+    word_filter_generation: u64,
+    /// The connections of the firehose, which are not sessions.
+    observers: HashMap<InternalId, firehose::ObserverState>,
+    /// The logins and logouts for the next `PresenceDiff`.
+    presence: presence::PresenceBatch,
+    /// Who sent private messages to whom recently, if enabled.
+    pm_metadata: pm_metadata::PmMetadataLog,
+    /// The statistics which were not written to the storage yet.
+    stats: stats::Stats,
+    /// The periodic cleanups and when they run next.
+    maintenance: maintenance::Maintenance,
+    /// When each moderator took their recent actions, by the class of the action.
+    moderator_actions: HashMap<(Uuid, handler::ActionClass), VecDeque<Instant>>,
+    /// The id of the next private message sent from this instance.
+    next_private_id: u64,
+    /// Set while a packet is simulated through the admin API, see [`ChatServer::simulate`].
+    simulating: bool,
+    /// How connections which did not log in are treated.
+    guests: GuestConfig,
+    /// Set while the instance drains.
+    drain: Option<drain::Drain>,
+    connection_limit: Arc<ConnectionLimit>,
+    backlog: Arc<Backlog>,
+    auth_monitor: Arc<auth_monitor::AuthMonitor>,
+    validation_stats: Arc<validation_stats::ValidationStats>,
+    funnel: Arc<funnel::Funnel>,
+    firehose: Arc<firehose::FirehoseStats>,
+    delivery: Arc<delivery::DeliveryStats>,
+    storage_health: Arc<persistence::StorageHealth>,
+    clock: Arc<dyn Clock>,
+    /// Set while the moderation state could not be saved.
+    flush_retry: Option<persistence::FlushRetry>,
     config: Config,
 
     current_internal_user_id: u64,
 }
 
-impl ChatServer {
-    pub fn new(config: Config) -> ChatServer {
-        ChatServer {
-            connections: HashMap::new(),
-            users: HashMap::new(),
-
-            rng: Hc128Rng::from_rng(OsRng).expect("could not initialize hc128 rng"),
-            authenticator: config
-                .auth
-                .as_ref()
-                .map(|auth| Authenticator::new(&auth).expect("could not initialize authenticator")),
-            validator: MessageValidator::new(config.message.clone()),
-            moderation: Moderation::new(config.moderation.clone())
-                .expect("could not start moderation"),
-            config,
-
-            current_internal_user_id: 0,
-        }
-    }
-}
-
 impl Actor for ChatServer {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        self.start_cluster(ctx);
+        self.start_announcements(ctx);
+        self.start_flush_retries(ctx);
+        self.start_maintenance(ctx);
+        self.start_presence(ctx);
+        self.start_stats(ctx);
+    }
 }
 
 impl Handler<Disconnect> for ChatServer {
     type Result = ();
 
     fn handle(&mut self, msg: Disconnect, _ctx: &mut Context<Self>) {
-        info!("User `{}` disconnected.", msg.id);
-        if let Some(session) = self.connections.remove(&msg.id) {
-            if let Some(info) = session.user {
-                let user_session = self
-                    .users
-                    .get_mut(&info.name)
-                    .expect("the ids should still exist here");
-                user_session.connections.remove(&msg.id);
-                if user_session.connections.is_empty() {
-                    self.users.remove(&info.name);
+        self.remove_session(msg.id, msg.logout, msg.reason);
+    }
+}
+
+impl ChatServer {
+    /// Forgets the connection `id`, which closed or can not receive packets anymore.
+    ///
+    /// Connections which were already removed are ignored.
+    fn remove_session(&mut self, id: InternalId, logout: bool, reason: Option<DisconnectReason>) {
+        if let Some(sessions::Removed {
+            session,
+            last_session,
+        }) = self.sessions.remove(id)
+        {
+            info!("User `{}` disconnected.", id);
+            self.funnel
+                .record_disconnect(reason.map_or("connection_lost", DisconnectReason::label));
+            session.log_failed_sends(id);
+            if session.user.is_none() {
+                self.funnel.record_stage(Stage::DisconnectedBeforeLogin);
+            }
+            if let Some(info) = &session.user {
+                if last_session {
+                    self.cluster_logout(&info.name.canonical());
+                    let user = UserInfo {
+                        name: info.name.to_string(),
+                        uuid: info.uuid,
+                        bot: self.is_bot(&info.uuid),
+                    };
+                    self.record_presence_leave(&user);
+                    self.publish_firehose(firehose::EventKind::Presence, || {
+                        ClientPacket::UserLeft { user }
+                    });
                 }
+                self.remember_last_seen(&info.name, info.uuid);
             }
+
+            self.drop_watches(id, &session.watching);
+            if let Some(token) = &session.resume_token {
+                self.detach_resume_token(token, logout);
+            }
+
+            let info = session.user.map(|user| UserInfo {
+                name: user.name.into(),
+                uuid: user.uuid,
+                bot: self.is_bot(&user.uuid),
+            });
+            self.notify_hooks(|hook| hook.on_disconnect(info.as_ref()));
+            self.update_guest_count();
         }
     }
 }
 
 pub(self) struct SessionState {
     addr: Recipient<ClientPacket>,
-    session_hash: Option<String>,
+    close: Recipient<close::Close>,
+    replay: Recipient<handler::ReplayChunk>,
+    traced: Recipient<trace::TracedPacket>,
+    /// Receives broadcasts which were encoded once for every connection.
+    encoded: Recipient<compat::EncodedPacket>,
+    /// The session hash of the last `RequestMojangInfo` and when it was requested.
+    session_hash: Option<(String, Instant)>,
     user: Option<User>,
+    resume_token: Option<String>,
+    capabilities: Capabilities,
+    /// Whether the client receives its own broadcast messages,
+    /// instead of just a [`ClientPacket::MessageAck`].
+    echo_own_messages: bool,
+    /// Set if a moderator subscribed to moderation events.
+    moderation_events: Option<handler::ModerationSubscription>,
+    /// The actions waiting for the moderator to confirm them, by their nonce.
+    confirmations: HashMap<String, handler::PendingConfirmation>,
+    /// The users this connection waits for to log in.
+    watching: HashSet<CanonicalId>,
+    /// Whether the connection uses a slot reserved for moderators.
+    reserved: bool,
+    /// When the connection logged in, if it is subject to the join cooldown.
+    cooldown_since: Option<Instant>,
+    /// The number of packets which could not be sent since the last one which was.
+    failed_sends: Cell<u32>,
+    /// When the connection last requested its diagnostics.
+    last_diagnostics: Option<Instant>,
+    /// The version of the protocol the client speaks.
+    protocol: u32,
+    /// The address the client connected from, if it is known.
+    ip: Option<IpAddr>,
 }
 
 impl SessionState {
@@ -106,12 +327,22 @@ impl SessionState {
 
 struct UserSession {
     rate_limiter: RateLimiter,
+    /// The amount of consecutive messages which were rate limited.
+    rate_limit_violations: u32,
+    /// When the user added or removed their most recent reactions.
+    reaction_times: VecDeque<Instant>,
     connections: HashSet<InternalId>,
+    status: UserStatus,
 }
 
 #[derive(Message)]
 struct Disconnect {
     id: InternalId,
+    /// Whether the client closed the connection itself.
+    logout: bool,
+    /// Why the connection was closed, or `None` if it was lost.
+    reason: Option<DisconnectReason>,
+    _pending: backlog::Pending,
 }
 
 /// A clientbound packet
@@ -125,12 +356,83 @@ enum ClientPacket {
         token: String,
     },
     Message {
+        seq: u64,
         author_info: UserInfo,
-        content: String,
+        /// Omitted for players.
+        #[serde(skip_serializing_if = "AuthorKind::is_player")]
+        author_kind: AuthorKind,
+        content: ValidatedContent,
+        /// Set if `signing.key_file` is configured.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        signature: Option<MessageSignature>,
+        /// The number of users who reacted to a replayed message, by reaction.
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        reactions: BTreeMap<String, u32>,
+        /// Set if the message replies to another one.
+        #[serde(flatten)]
+        reply: Option<history::Reply>,
+        #[serde(skip_serializing_if = "MessageFlags::is_empty")]
+        flags: MessageFlags,
     },
     PrivateMessage {
         author_info: UserInfo,
-        content: String,
+        /// The other participant from the perspective of the receiving connection.
+        conversation: CanonicalId,
+        id: u64,
+        timestamp: u64,
+        /// Either `content`, or `payload` if the message is flagged as encrypted.
+        #[serde(flatten)]
+        body: handler::PrivateBody,
+        #[serde(skip_serializing_if = "MessageFlags::is_empty")]
+        flags: MessageFlags,
+    },
+    MessageAck {
+        seq: u64,
+        timestamp: u64,
+        /// Only set for clients supporting delivery counts.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        delivery_count: Option<u32>,
+    },
+    PrivateMessageAck {
+        receiver: String,
+        delivery_count: u32,
+    },
+    ResumeToken {
+        token: String,
+    },
+    BlockedWords {
+        words: Vec<String>,
+    },
+    ResyncTooOld {
+        oldest_available: u64,
+    },
+    ReplayComplete {
+        count: u32,
+    },
+    UserOnline {
+        id: String,
+    },
+    UserRenamed {
+        old_id: String,
+        new_id: String,
+    },
+    /// `user_id` is the name of the user who added or removed the reaction.
+    ReactionUpdate {
+        message_id: u64,
+        emoji: String,
+        user_id: String,
+        added: bool,
+    },
+    Emotes {
+        emotes: BTreeMap<String, String>,
+    },
+    UserLookup(handler::UserLookup),
+    Diagnostics(handler::Diagnostics),
+    AuditLog(AuditPage),
+    /// The recent private messages sent or received by `uuid`, newest first, without their content.
+    PmMetadata {
+        uuid: Uuid,
+        entries: Vec<pm_metadata::PmMetadataEntry>,
     },
     UserCount {
         connections: u32,
@@ -139,35 +441,339 @@ enum ClientPacket {
     Success {
         reason: SuccessReason,
     },
+    CommandResult {
+        success: bool,
+        message: String,
+        translation_key: &'static str,
+        params: TranslationParams,
+    },
+    /// Asks a moderator to confirm an action with the `nonce`; `summary` describes the action.
+    ConfirmAction {
+        nonce: String,
+        summary: String,
+    },
+    MessageFlagged {
+        author_info: UserInfo,
+        content: ValidatedContent,
+    },
+    ModerationStatus {
+        banned: bool,
+        muted: bool,
+        /// When the ban, or if the user is only muted, the mute ends, in seconds since the unix epoch.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        expires_at: Option<u64>,
+        /// The reason the moderator gave for the ban or mute.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    ModerationEvent {
+        kind: handler::ModerationEventKind,
+        user: UserInfo,
+        content_excerpt: String,
+        rule: String,
+    },
+    /// Only sent to firehose observers.
+    UserJoined {
+        user: UserInfo,
+    },
+    /// Only sent to firehose observers.
+    UserLeft {
+        user: UserInfo,
+    },
+    /// The users who logged in with their first session and closed their last one
+    /// since the previous diff.
+    PresenceDiff {
+        joined: Vec<UserInfo>,
+        left: Vec<String>,
+    },
+    /// Only sent to firehose observers.
+    ModerationAction(AuditEntry),
+    /// Sent after logging in with a version of the protocol which will stop being spoken at `sunset`.
+    ProtocolDeprecated {
+        protocol: u32,
+        sunset: u64,
+    },
+    Motd {
+        content: String,
+    },
+    /// Sent before the connection is closed because the instance drains,
+    /// or because a moderator banned or kicked the user.
+    /// `reason_code` is the label of the [`DisconnectReason`].
+    Disconnected {
+        reason_code: &'static str,
+        translation_key: &'static str,
+        /// Only set when draining, or when a temporary ban rejects a login.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        retry_after_secs: Option<u64>,
+        /// The reason the moderator gave.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// Sent after `Hello` to clients supporting `session_tag`, so their users can tell support which connection is theirs.
+    SessionTag {
+        session_tag: String,
+    },
+    /// Answers `TimeSync`, or is sent after `Hello` to clients supporting `time_sync` without `client_time_ms`.
+    /// The times are in milliseconds since the unix epoch.
+    TimeSync {
+        client_time_ms: Option<u64>,
+        server_time_ms: u64,
+    },
+    SystemMessage {
+        content: String,
+        kind: handler::SystemMessageKind,
+    },
+    ServerInfo {
+        version: String,
+        protocol: u32,
+        max_message_length: u32,
+        /// `1` if messages may not contain line breaks.
+        max_lines: u32,
+        max_line_length: Option<u32>,
+        commands_enabled: bool,
+        commit: String,
+        build_timestamp: String,
+        uptime_secs: u64,
+        features: Capabilities,
+        connections: u32,
+        max_connections: Option<u32>,
+        /// The users logged in on this instance.
+        online_users: u32,
+        /// The connections on this instance which did not log in.
+        guest_viewers: u32,
+    },
+    #[serde(serialize_with = "serialize_error")]
     Error {
         message: ClientError,
     },
+    /// Sent as `Error` by a session after it suppressed `repeated` identical errors.
+    #[serde(rename = "Error", serialize_with = "serialize_repeated_error")]
+    RepeatedError {
+        message: ClientError,
+        repeated: u32,
+    },
+    /// Sent as `Error` if a message was rejected by the rule `rule` of the validator.
+    #[serde(rename = "Error", serialize_with = "serialize_rejection")]
+    Rejected {
+        message: ClientError,
+        rule: &'static str,
+    },
+}
+
+/// Serializes an error together with its translation.
+fn serialize_error<S: Serializer>(
+    message: &ClientError,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serialize_error_details(message, 0, None, serializer)
+}
+
+fn serialize_repeated_error<S: Serializer>(
+    message: &ClientError,
+    repeated: &u32,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serialize_error_details(message, *repeated, None, serializer)
+}
+
+fn serialize_rejection<S: Serializer>(
+    message: &ClientError,
+    rule: &&'static str,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serialize_error_details(message, 0, Some(*rule), serializer)
+}
+
+fn serialize_error_details<S: Serializer>(
+    message: &ClientError,
+    repeated: u32,
+    rule: Option<&'static str>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct Error<'a> {
+        message: &'a ClientError,
+        translation_key: &'static str,
+        params: TranslationParams,
+        #[serde(skip_serializing_if = "is_zero")]
+        repeated: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rule: Option<&'static str>,
+    }
+
+    fn is_zero(repeated: &u32) -> bool {
+        *repeated == 0
+    }
+
+    Error {
+        message,
+        translation_key: message.translation_key(),
+        params: message.translation_params(),
+        repeated,
+        rule,
+    }
+    .serialize(serializer)
 }
 
 /// A serverbound packet
-#[derive(Message, Deserialize)]
+///
+/// It is public so clients written in Rust, like `axochat-bench`, can use the same definitions.
+#[derive(Message, Serialize, Deserialize)]
 #[serde(tag = "m", content = "c")]
-enum ServerPacket {
+pub enum ServerPacket {
+    Hello {
+        features: Capabilities,
+        #[serde(default)]
+        echo_own_messages: Option<bool>,
+        /// The client software, which has to be recent enough if the server requires a minimum version.
+        #[serde(default)]
+        client: Option<ClientVersion>,
+        /// The version of the protocol the client speaks; the current one if it is not set.
+        #[serde(default)]
+        protocol: Option<u32>,
+    },
+    SetEchoOwnMessages {
+        enabled: bool,
+    },
+    SetStatus {
+        status: UserStatus,
+    },
     RequestMojangInfo,
     LoginMojang(User),
-    LoginJWT { token: String, allow_messages: bool },
+    LoginJWT {
+        token: String,
+        allow_messages: bool,
+    },
     RequestJWT,
-    Message { content: String },
-    PrivateMessage { receiver: String, content: String },
-    BanUser { user: Uuid },
-    UnbanUser { user: Uuid },
+    Message {
+        content: String,
+        /// The chat a bot relays the message from, like `discord`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        origin: Option<String>,
+        /// The sequence number of the message this one replies to.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reply_to: Option<u64>,
+    },
+    /// If `encrypted` is set, the opaque `payload` is sent instead of `content`.
+    PrivateMessage {
+        receiver: String,
+        #[serde(default)]
+        content: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        encrypted: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload: Option<String>,
+        /// Replies to private messages are not supported yet.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reply_to: Option<u64>,
+    },
+    /// `id` is the name of the user, like the receiver of a private message.
+    NotifyWhenOnline {
+        id: String,
+    },
+    BanUser {
+        user: Uuid,
+    },
+    UnbanUser {
+        user: Uuid,
+    },
+    /// Confirms the action the server sent the `nonce` for with a `ConfirmAction`.
+    ConfirmAction {
+        nonce: String,
+    },
+    AddBlockedWord {
+        word: String,
+    },
+    RemoveBlockedWord {
+        word: String,
+    },
+    ListBlockedWords {
+        filter: Option<String>,
+    },
+    SubscribeModerationEvents {
+        enabled: bool,
+    },
     RequestUserCount,
+    /// Only available to moderators.
+    LookupUuid {
+        uuid: Uuid,
+    },
+    RequestServerInfo,
+    Resume {
+        token: String,
+    },
+    ResyncFrom {
+        seq: u64,
+    },
+    RequestEmotes,
+    /// Adds the reaction `emoji` to the broadcast message with the sequence number `message_id`.
+    React {
+        message_id: u64,
+        emoji: String,
+    },
+    RemoveReaction {
+        message_id: u64,
+        emoji: String,
+    },
+    RequestDiagnostics,
+    /// Answered by the session itself, with its own rate limit.
+    /// `client_time_ms` is the time of the client in milliseconds since the unix epoch.
+    TimeSync {
+        client_time_ms: u64,
+    },
+    /// Only available to moderators.
+    /// `since` is in milliseconds since the unix epoch.
+    RequestAuditLog {
+        actor: Option<Uuid>,
+        target: Option<Uuid>,
+        since: Option<u64>,
+        limit: Option<usize>,
+    },
+    /// Only available to moderators, if `moderation.pm_metadata_retention_minutes` is set.
+    RequestPmMetadata {
+        uuid: Uuid,
+        limit: Option<usize>,
+    },
 }
 
 #[derive(Message)]
 struct ServerPacketId {
     user_id: InternalId,
     packet: ServerPacket,
+    trace: TraceId,
+    _pending: backlog::Pending,
+}
+
+/// Who wrote a broadcast message.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(tag = "kind")]
+pub enum AuthorKind {
+    /// A user connected to the chat.
+    #[default]
+    Player,
+    /// The operator of the server, e.g. through the [`AdminHandle`].
+    System,
+    /// A user of another chat, relayed by a bridge, e.g. with the `origin` `discord` or `irc`.
+    Bridge { origin: String },
+}
+
+impl AuthorKind {
+    pub fn is_player(&self) -> bool {
+        *self == AuthorKind::Player
+    }
+}
+
+/// The brand and version of a client, e.g. `LiquidBounce` and `1.4.2`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClientVersion {
+    pub brand: String,
+    pub version: String,
 }
 
+/// A user logging in with Mojang.
 #[derive(Serialize, Deserialize, Clone)]
-struct User {
-    pub name: String,
+pub struct User {
+    pub name: DisplayName,
     pub uuid: Uuid,
     /// Should this user allow private messages?
     pub allow_messages: bool,
@@ -176,6 +782,10 @@ struct User {
 #[derive(Serialize, Deserialize, Copy, Clone)]
 enum SuccessReason {
     Login,
+    Resume,
     Ban,
     Unban,
+    BlockWord,
+    UnblockWord,
+    SetStatus,
 }
@@ -0,0 +1,247 @@
+//! The packets a session has yet to write to its connection.
+//!
+//! Packets are queued by priority, so errors are not stuck behind a backlog of broadcasts.
+
+use super::{compat::EncodedPacket, ClientPacket, TraceId};
+use log::*;
+
+use std::collections::VecDeque;
+
+/// The maximum amount of queued packets before bulk and then interactive packets are dropped.
+///
+/// A client which has this many control packets waiting is too slow and is disconnected.
+pub(super) const MAX_QUEUED_PACKETS: usize = 1024;
+
+/// How urgently a packet has to reach the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) enum Priority {
+    /// Errors, time synchronizations and the notice of draining, which are never dropped.
+    Control = 0,
+    /// Direct responses to packets of the client and private messages.
+    Interactive = 1,
+    /// Broadcasts and everything belonging to them.
+    Bulk = 2,
+}
+
+impl ClientPacket {
+    pub(super) fn priority(&self) -> Priority {
+        match self {
+            ClientPacket::Error { .. }
+            | ClientPacket::RepeatedError { .. }
+            | ClientPacket::Rejected { .. }
+            | ClientPacket::TimeSync { .. }
+            | ClientPacket::SessionTag { .. }
+            | ClientPacket::Disconnected { .. } => Priority::Control,
+            ClientPacket::MojangInfo { .. }
+            | ClientPacket::NewJWT { .. }
+            | ClientPacket::PrivateMessage { .. }
+            | ClientPacket::PrivateMessageAck { .. }
+            | ClientPacket::ResumeToken { .. }
+            | ClientPacket::BlockedWords { .. }
+            | ClientPacket::ResyncTooOld { .. }
+            | ClientPacket::UserLookup(_)
+            | ClientPacket::Diagnostics(_)
+            | ClientPacket::AuditLog(_)
+            | ClientPacket::PmMetadata { .. }
+            | ClientPacket::ModerationStatus { .. }
+            | ClientPacket::UserOnline { .. }
+            | ClientPacket::Emotes { .. }
+            | ClientPacket::UserCount { .. }
+            | ClientPacket::Success { .. }
+            | ClientPacket::CommandResult { .. }
+            | ClientPacket::ConfirmAction { .. }
+            | ClientPacket::Motd { .. }
+            | ClientPacket::ProtocolDeprecated { .. }
+            | ClientPacket::ServerInfo { .. } => Priority::Interactive,
+            // An acknowledgement follows the echo of the message and a replay ends after its messages.
+            ClientPacket::Message { .. }
+            | ClientPacket::MessageAck { .. }
+            | ClientPacket::ReplayComplete { .. }
+            | ClientPacket::ReactionUpdate { .. }
+            | ClientPacket::UserRenamed { .. }
+            | ClientPacket::MessageFlagged { .. }
+            | ClientPacket::ModerationEvent { .. }
+            | ClientPacket::UserJoined { .. }
+            | ClientPacket::UserLeft { .. }
+            | ClientPacket::PresenceDiff { .. }
+            | ClientPacket::ModerationAction(_)
+            | ClientPacket::SystemMessage { .. } => Priority::Bulk,
+        }
+    }
+}
+
+/// A packet waiting to be written to the connection.
+pub(super) enum Queued {
+    /// A packet with the trace it is sent with.
+    Packet(ClientPacket, Option<TraceId>),
+    /// A broadcast which was encoded before.
+    Encoded(EncodedPacket),
+}
+
+impl Queued {
+    pub fn packet(&self) -> &ClientPacket {
+        match self {
+            Queued::Packet(packet, _) => packet,
+            Queued::Encoded(encoded) => &encoded.packet,
+        }
+    }
+
+    /// Encodes the packet for a connection speaking the version `protocol`.
+    ///
+    /// Returns `None` if the packet can not be expressed in that version.
+    pub fn encode(&self, protocol: u32) -> Option<String> {
+        match self {
+            Queued::Packet(packet, trace) => super::compat::encode(packet, protocol, *trace),
+            Queued::Encoded(encoded) => encoded.encode(protocol),
+        }
+    }
+}
+
+/// Every queued packet is a control packet, so none can be dropped to make room.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct QueueFull;
+
+/// Queued packets with the trace they are sent with, in order within each [`Priority`].
+#[derive(Default)]
+pub(super) struct OutgoingQueue {
+    queues: [VecDeque<Queued>; 3],
+}
+
+impl OutgoingQueue {
+    /// Queues `packet`.
+    ///
+    /// If too many packets are queued, the oldest bulk packet is dropped,
+    /// or if there is none, the oldest interactive one.
+    /// If only control packets are queued, `packet` is not queued and [`QueueFull`] is returned.
+    pub fn push(&mut self, queued: Queued) -> std::result::Result<(), QueueFull> {
+        if self.len() >= MAX_QUEUED_PACKETS {
+            let dropped = [Priority::Bulk, Priority::Interactive]
+                .iter()
+                .find_map(|priority| self.queues[*priority as usize].pop_front());
+            if dropped.is_none() {
+                return Err(QueueFull);
+            }
+            debug!("Dropped a packet, the outgoing queue is full.");
+        }
+        self.queues[queued.packet().priority() as usize].push_back(queued);
+        Ok(())
+    }
+
+    /// Removes the oldest packet of the highest priority.
+    pub fn pop(&mut self) -> Option<Queued> {
+        self.queues.iter_mut().find_map(VecDeque::pop_front)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    /// Returns whether no bulk packets are queued.
+    pub fn is_bulk_empty(&self) -> bool {
+        self.queues[Priority::Bulk as usize].is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::handler::SystemMessageKind;
+    use crate::error::ClientError;
+
+    fn broadcast(i: usize) -> Queued {
+        let packet = ClientPacket::SystemMessage {
+            content: i.to_string(),
+            kind: SystemMessageKind::Announcement,
+        };
+        Queued::Packet(packet, None)
+    }
+
+    fn response() -> Queued {
+        let packet = ClientPacket::UserCount {
+            connections: 1,
+            logged_in: 1,
+        };
+        Queued::Packet(packet, None)
+    }
+
+    fn error() -> Queued {
+        let packet = ClientPacket::Error {
+            message: ClientError::RateLimited,
+        };
+        Queued::Packet(packet, None)
+    }
+
+    fn priorities(queue: &mut OutgoingQueue) -> Vec<Priority> {
+        std::iter::from_fn(|| queue.pop())
+            .map(|queued| queued.packet().priority())
+            .collect()
+    }
+
+    #[test]
+    fn an_error_overtakes_a_backlog_of_broadcasts() {
+        let mut queue = OutgoingQueue::default();
+        for i in 0..500 {
+            queue.push(broadcast(i)).unwrap();
+        }
+        queue.push(response()).unwrap();
+        queue.push(error()).unwrap();
+
+        let priorities = priorities(&mut queue);
+        assert_eq!(priorities[..2], [Priority::Control, Priority::Interactive]);
+        assert_eq!(priorities.len(), 502);
+    }
+
+    #[test]
+    fn keeps_the_order_within_a_priority() {
+        let mut queue = OutgoingQueue::default();
+        for i in 0..10 {
+            queue.push(broadcast(i)).unwrap();
+            queue.push(error()).unwrap();
+        }
+        let contents: Vec<_> = std::iter::from_fn(|| queue.pop())
+            .filter_map(|queued| match queued.packet() {
+                ClientPacket::SystemMessage { content, .. } => Some(content.clone()),
+                _ => None,
+            })
+            .collect();
+        let expected: Vec<_> = (0..10).map(|i: usize| i.to_string()).collect();
+        assert_eq!(contents, expected);
+    }
+
+    #[test]
+    fn sheds_bulk_then_interactive_packets() {
+        let mut queue = OutgoingQueue::default();
+        queue.push(error()).unwrap();
+        queue.push(response()).unwrap();
+        for i in 0..MAX_QUEUED_PACKETS - 2 {
+            queue.push(broadcast(i)).unwrap();
+        }
+        // Replaces the first broadcast.
+        queue.push(response()).unwrap();
+        assert_eq!(queue.len(), MAX_QUEUED_PACKETS);
+        match queue.queues[Priority::Bulk as usize][0].packet() {
+            ClientPacket::SystemMessage { content, .. } => assert_eq!(content, "1"),
+            _ => unreachable!(),
+        }
+
+        // Replaces every other packet.
+        for _ in 1..MAX_QUEUED_PACKETS {
+            queue.push(error()).unwrap();
+        }
+        assert_eq!(queue.len(), MAX_QUEUED_PACKETS);
+        assert!(priorities(&mut queue)
+            .iter()
+            .all(|priority| *priority == Priority::Control));
+    }
+
+    #[test]
+    fn is_full_with_only_control_packets() {
+        let mut queue = OutgoingQueue::default();
+        for _ in 0..MAX_QUEUED_PACKETS {
+            queue.push(error()).unwrap();
+        }
+        assert_eq!(queue.push(error()), Err(QueueFull));
+        assert_eq!(queue.push(broadcast(0)), Err(QueueFull));
+        assert_eq!(queue.len(), MAX_QUEUED_PACKETS);
+    }
+}
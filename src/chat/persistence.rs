@@ -0,0 +1,110 @@
+//! Retrying to write the banned users after a write failed.
+//!
+//! Moderation actions are applied in memory even if their file can not be written,
+//! so the chat keeps working while the disk is full or not writable.
+
+use super::ChatServer;
+use log::*;
+
+use actix::*;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// How often it is checked whether a write has to be retried.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The delay before the first retry, which doubles with every failed one.
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Whether the moderation state on disk is up to date, shared by the chat server and the routes.
+pub(super) struct StorageHealth {
+    healthy: AtomicBool,
+}
+
+impl Default for StorageHealth {
+    fn default() -> StorageHealth {
+        StorageHealth {
+            healthy: AtomicBool::new(true),
+        }
+    }
+}
+
+impl StorageHealth {
+    pub fn healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::Relaxed);
+    }
+
+    /// Appends the gauge in the Prometheus text format.
+    pub fn write_metrics(&self, output: &mut String) {
+        writeln!(
+            output,
+            "# HELP axochat_storage_healthy Whether the moderation state could be saved; 0 while writes are retried."
+        )
+        .unwrap();
+        writeln!(output, "# TYPE axochat_storage_healthy gauge").unwrap();
+        writeln!(output, "axochat_storage_healthy {}", self.healthy() as u8).unwrap();
+    }
+}
+
+/// The failed writes since the moderation state could last be saved.
+pub(super) struct FlushRetry {
+    attempts: u32,
+    next: Instant,
+}
+
+impl ChatServer {
+    pub(super) fn start_flush_retries(&mut self, ctx: &mut Context<Self>) {
+        ctx.run_interval(CHECK_INTERVAL, |actor, _ctx| actor.retry_flush());
+    }
+
+    /// Updates the storage health after the moderation state changed
+    /// and returns whether the change was saved.
+    ///
+    /// If it was not, writing it is retried in the background.
+    pub(in crate::chat) fn moderation_persisted(&mut self) -> bool {
+        let persisted = !self.moderation.is_dirty();
+        if persisted {
+            if let Some(retry) = self.flush_retry.take() {
+                info!(
+                    "Saved the moderation state again after {} failed retries.",
+                    retry.attempts
+                );
+            }
+        } else if self.flush_retry.is_none() {
+            self.flush_retry = Some(FlushRetry {
+                attempts: 0,
+                next: Instant::now() + MIN_RETRY_DELAY,
+            });
+        }
+        self.storage_health.set(persisted);
+        persisted
+    }
+
+    fn retry_flush(&mut self) {
+        let now = Instant::now();
+        let retry = match &mut self.flush_retry {
+            Some(retry) if retry.next <= now => retry,
+            _ => return,
+        };
+        if let Err(err) = self.moderation.flush() {
+            retry.attempts += 1;
+            let delay = MIN_RETRY_DELAY
+                .checked_mul(1 << retry.attempts.min(16))
+                .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY));
+            retry.next = now + delay;
+            warn!(
+                "Could not save the moderation state, retrying in {:?}: {}",
+                delay, err
+            );
+            return;
+        }
+        self.moderation_persisted();
+    }
+}
@@ -0,0 +1,177 @@
+//! Who sent private messages to whom, for moderators investigating reports of harassment.
+//!
+//! Only the sender, the receiver, the time and the length of a message are kept, never its content,
+//! and only if `moderation.pm_metadata_retention_minutes` is set.
+//! Entries are kept in memory for at most that long and are never persisted;
+//! at most `moderation.pm_metadata_max_entries` are kept, dropping the oldest.
+//! Messages are recorded by the instance which delivered them to the receiver.
+
+use super::{cluster, ChatServer, ClientPacket, InternalId};
+use crate::error::*;
+use log::*;
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// The maximum number of entries sent in one `PmMetadata` packet.
+const MAX_PM_METADATA_ENTRIES: usize = 100;
+
+/// A private message, without its content.
+#[derive(Debug, Clone, Serialize)]
+pub(super) struct PmMetadataEntry {
+    pub sender: Uuid,
+    pub receiver: Uuid,
+    /// When the message was delivered, in milliseconds since the unix epoch.
+    pub timestamp: u64,
+    /// The length of the content in bytes, or of the payload if it is encrypted.
+    pub length: usize,
+}
+
+/// The metadata of the recent private messages, oldest first.
+pub(super) struct PmMetadataLog {
+    entries: VecDeque<(Instant, PmMetadataEntry)>,
+    retention: Duration,
+    max_entries: usize,
+}
+
+impl PmMetadataLog {
+    /// Creates a log keeping entries for `retention_minutes`, which is disabled if it is `0`.
+    pub fn new(retention_minutes: u64, max_entries: usize) -> PmMetadataLog {
+        PmMetadataLog {
+            entries: VecDeque::new(),
+            retention: Duration::from_secs(retention_minutes.saturating_mul(60)),
+            max_entries,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.retention > Duration::from_secs(0) && self.max_entries > 0
+    }
+
+    fn is_expired(&self, recorded: Instant, now: Instant) -> bool {
+        now.saturating_duration_since(recorded) >= self.retention
+    }
+
+    fn record(&mut self, entry: PmMetadataEntry, now: Instant) {
+        if !self.enabled() {
+            return;
+        }
+        self.prune(now);
+        if self.entries.len() >= self.max_entries {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((now, entry));
+    }
+
+    /// Removes the entries older than the retention.
+    fn prune(&mut self, now: Instant) {
+        while let Some((recorded, _)) = self.entries.front() {
+            if !self.is_expired(*recorded, now) {
+                break;
+            }
+            self.entries.pop_front();
+        }
+    }
+
+    /// Returns the newest `limit` entries sent or received by `uuid`, newest first.
+    ///
+    /// Expired entries are left out, even if they were not removed yet.
+    fn query(&self, uuid: &Uuid, limit: usize, now: Instant) -> Vec<PmMetadataEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .take_while(|(recorded, _)| !self.is_expired(*recorded, now))
+            .map(|(_, entry)| entry)
+            .filter(|entry| entry.sender == *uuid || entry.receiver == *uuid)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+impl ChatServer {
+    /// Removes the expired entries even if no private messages are sent, as a maintenance job.
+    pub(super) fn prune_pm_metadata(&mut self) {
+        let now = self.now();
+        self.pm_metadata.prune(now);
+    }
+
+    /// Records that `sender` sent `receiver` a private message of `length` bytes, if enabled.
+    pub(in crate::chat) fn record_pm_metadata(
+        &mut self,
+        sender: Uuid,
+        receiver: Uuid,
+        length: usize,
+    ) {
+        // Simulated messages were never sent.
+        if self.simulating {
+            return;
+        }
+        let entry = PmMetadataEntry {
+            sender,
+            receiver,
+            timestamp: cluster::unix_millis(self.system_now()),
+            length,
+        };
+        let now = self.now();
+        self.pm_metadata.record(entry, now);
+    }
+
+    /// Whether the metadata of private messages is kept, for `Diagnostics`.
+    pub(in crate::chat) fn pm_metadata_retention(&self) -> Option<u64> {
+        if self.pm_metadata.enabled() {
+            Some(self.config.moderation.pm_metadata_retention_minutes)
+        } else {
+            None
+        }
+    }
+
+    /// Sends the moderator `user_id` the newest private messages sent or received by `uuid`.
+    pub(in crate::chat) fn handle_request_pm_metadata(
+        &mut self,
+        user_id: InternalId,
+        uuid: Uuid,
+        limit: Option<usize>,
+    ) {
+        let session = self
+            .sessions
+            .get(&user_id)
+            .expect("could not find connection");
+        let packet = match &session.user {
+            Some(info) if self.is_moderator(&info.uuid) => {
+                if self.pm_metadata.enabled() {
+                    info!(
+                        "Moderator `{}` looked up the private messages of `{}`.",
+                        user_id, uuid
+                    );
+                    let limit = limit
+                        .unwrap_or(MAX_PM_METADATA_ENTRIES)
+                        .min(MAX_PM_METADATA_ENTRIES);
+                    ClientPacket::PmMetadata {
+                        uuid,
+                        entries: self.pm_metadata.query(&uuid, limit, self.now()),
+                    }
+                } else {
+                    ClientPacket::Error {
+                        message: ClientError::NotSupported,
+                    }
+                }
+            }
+            Some(_) => {
+                info!(
+                    "User `{}` tried to look up private messages without permission.",
+                    user_id
+                );
+                ClientPacket::Error {
+                    message: ClientError::NotPermitted,
+                }
+            }
+            None => ClientPacket::Error {
+                message: ClientError::NotLoggedIn,
+            },
+        };
+        self.send_to(user_id, session, packet);
+    }
+}
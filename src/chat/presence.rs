@@ -0,0 +1,261 @@
+//! Telling clients which users logged in and out, without a broadcast for every single one.
+//!
+//! Logins and logouts are collected for `presence.batch_window` and sent as one `PresenceDiff`
+//! to the logged in clients supporting the `presence` feature.
+//! A user who logs in and out again within the window does not appear at all,
+//! and a batch is sent early once it holds `presence.max_batch` users,
+//! so a reconnect storm after a restart costs one packet per client and window instead of one per login.
+//! Clients waiting for a user with `NotifyWhenOnline` are still told immediately.
+
+use super::{CanonicalId, Capabilities, ChatServer, ClientPacket};
+use crate::auth::UserInfo;
+use crate::config::GuestEvent;
+use log::*;
+
+use actix::*;
+use std::collections::BTreeMap;
+
+/// The logins and logouts since the last `PresenceDiff`.
+#[derive(Default)]
+pub(super) struct PresenceBatch {
+    joined: BTreeMap<CanonicalId, UserInfo>,
+    left: BTreeMap<CanonicalId, String>,
+}
+
+impl PresenceBatch {
+    /// Records that `user` logged in with their first session.
+    ///
+    /// Returns the number of users in the batch.
+    fn join(&mut self, id: CanonicalId, user: UserInfo) -> usize {
+        // The user was online before the batch started, so nothing changed.
+        if self.left.remove(&id).is_none() {
+            self.joined.insert(id, user);
+        }
+        self.len()
+    }
+
+    /// Records that the user `name` closed their last session.
+    ///
+    /// Returns the number of users in the batch.
+    fn leave(&mut self, id: CanonicalId, name: String) -> usize {
+        // The user was not online before the batch started, so nothing changed.
+        if self.joined.remove(&id).is_none() {
+            self.left.insert(id, name);
+        }
+        self.len()
+    }
+
+    fn len(&self) -> usize {
+        self.joined.len() + self.left.len()
+    }
+
+    /// Takes the collected changes, or returns `None` if nothing changed.
+    fn take(&mut self) -> Option<ClientPacket> {
+        if self.len() == 0 {
+            return None;
+        }
+        let joined = std::mem::take(&mut self.joined).into_values().collect();
+        let left = std::mem::take(&mut self.left).into_values().collect();
+        Some(ClientPacket::PresenceDiff { joined, left })
+    }
+}
+
+impl ChatServer {
+    /// Sends the collected changes every `presence.batch_window`, if enabled.
+    pub(super) fn start_presence(&mut self, ctx: &mut Context<Self>) {
+        if !self.config.presence.enabled {
+            return;
+        }
+        ctx.run_interval(*self.config.presence.batch_window, |actor, _ctx| {
+            actor.flush_presence()
+        });
+    }
+
+    /// Adds the login of `user` with their first session to the next `PresenceDiff`.
+    pub(in crate::chat) fn record_presence_join(&mut self, user: &UserInfo) {
+        if !self.config.presence.enabled {
+            return;
+        }
+        let id = CanonicalId::from_display_name(&user.name);
+        if self.presence.join(id, user.clone()) >= self.config.presence.max_batch {
+            self.flush_presence();
+        }
+    }
+
+    /// Adds the logout of `user` with their last session to the next `PresenceDiff`.
+    pub(in crate::chat) fn record_presence_leave(&mut self, user: &UserInfo) {
+        if !self.config.presence.enabled {
+            return;
+        }
+        let id = CanonicalId::from_display_name(&user.name);
+        if self.presence.leave(id, user.name.clone()) >= self.config.presence.max_batch {
+            self.flush_presence();
+        }
+    }
+
+    /// Sends the collected changes to every logged in client supporting presence.
+    fn flush_presence(&mut self) {
+        let packet = match self.presence.take() {
+            Some(packet) => packet,
+            None => return,
+        };
+        debug!("Sending a presence update.");
+        for (id, session) in self.sessions.iter() {
+            if self.receives(session, GuestEvent::Presence)
+                && session.capabilities.contains(Capabilities::PRESENCE)
+            {
+                self.send_to(*id, session, packet.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{
+        simulate::{Capture, Collect},
+        DisplayName, User,
+    };
+    use serde_json::json;
+    use uuid::Uuid;
+
+    fn user(name: &str, uuid: u128) -> UserInfo {
+        UserInfo {
+            name: name.to_string(),
+            uuid: Uuid::from_u128(uuid),
+            bot: false,
+        }
+    }
+
+    fn notch() -> UserInfo {
+        user("Notch", 0x069a79f4_44e9_4726_a5be_fca90e38aaf5)
+    }
+
+    fn jeb() -> UserInfo {
+        user("jeb_", 0x853c80ef_3c37_49fd_aa49_938b674adae6)
+    }
+
+    fn join(batch: &mut PresenceBatch, user: UserInfo) -> usize {
+        batch.join(CanonicalId::from_display_name(&user.name), user)
+    }
+
+    fn leave(batch: &mut PresenceBatch, user: UserInfo) -> usize {
+        batch.leave(CanonicalId::from_display_name(&user.name), user.name)
+    }
+
+    /// The names in the `PresenceDiff` taken from `batch`.
+    fn diff(batch: &mut PresenceBatch) -> Option<(Vec<String>, Vec<String>)> {
+        match batch.take()? {
+            ClientPacket::PresenceDiff { joined, left } => {
+                Some((joined.into_iter().map(|user| user.name).collect(), left))
+            }
+            _ => panic!("expected a `PresenceDiff`"),
+        }
+    }
+
+    #[test]
+    fn changes_are_collected_into_one_diff() {
+        let mut batch = PresenceBatch::default();
+        assert_eq!(join(&mut batch, notch()), 1);
+        assert_eq!(leave(&mut batch, jeb()), 2);
+        // Another session of a user who already joined does not count twice.
+        assert_eq!(join(&mut batch, notch()), 2);
+
+        assert_eq!(
+            diff(&mut batch),
+            Some((vec!["Notch".to_string()], vec!["jeb_".to_string()]))
+        );
+        assert_eq!(diff(&mut batch), None);
+    }
+
+    #[test]
+    fn changes_within_a_window_cancel_out() {
+        let mut batch = PresenceBatch::default();
+        join(&mut batch, notch());
+        assert_eq!(leave(&mut batch, notch()), 0);
+        leave(&mut batch, jeb());
+        assert_eq!(join(&mut batch, jeb()), 0);
+        assert_eq!(diff(&mut batch), None);
+
+        // Cancelled changes can happen again in the same window.
+        join(&mut batch, notch());
+        leave(&mut batch, notch());
+        join(&mut batch, notch());
+        assert_eq!(diff(&mut batch), Some((vec!["Notch".to_string()], vec![])));
+    }
+
+    /// A server batching presence changes, with a client which supports presence
+    /// and one which does not.
+    fn server(max_batch: usize) -> (ChatServer, Addr<Capture>, Addr<Capture>) {
+        let mut server = ChatServer::for_tests(|config| {
+            config.presence.enabled = true;
+            config.presence.max_batch = max_batch;
+        });
+        let mut connect = |name: &str, capabilities| {
+            let capture = Capture::default().start();
+            let user = User {
+                name: DisplayName::new(name.to_string()),
+                uuid: Uuid::nil(),
+                allow_messages: true,
+            };
+            let id = server.connect_for_tests(&capture, Some(user));
+            server.sessions.get_mut(&id).unwrap().capabilities = capabilities;
+            capture
+        };
+        let watcher = connect("Watcher", Capabilities::PRESENCE);
+        let legacy = connect("Legacy", Capabilities::NONE);
+        (server, watcher, legacy)
+    }
+
+    /// The packets `capture` received, after which it stops.
+    fn packets(system: &mut SystemRunner, capture: &Addr<Capture>) -> Vec<serde_json::Value> {
+        system.block_on(capture.send(Collect)).unwrap().packets
+    }
+
+    #[test]
+    fn batches_are_sent_early_once_full() {
+        let mut system = System::new("test");
+        let (mut server, watcher, legacy) = server(3);
+
+        server.record_presence_join(&notch());
+        server.record_presence_leave(&jeb());
+        assert_eq!(server.presence.len(), 2);
+        server.record_presence_join(&user("Dinnerbone", 0x61699b2e_d327_4a01_9f1e_0ea8c3f06bc6));
+        assert_eq!(server.presence.len(), 0);
+        // The window closing afterwards has nothing left to send.
+        server.flush_presence();
+
+        let packets_of_watcher = packets(&mut system, &watcher);
+        assert_eq!(packets_of_watcher.len(), 1);
+        assert_eq!(packets_of_watcher[0]["m"], json!("PresenceDiff"));
+        let content = &packets_of_watcher[0]["c"];
+        assert_eq!(content["left"], json!(["jeb_"]));
+        let joined: Vec<_> = content["joined"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|user| user["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(joined, ["Dinnerbone", "Notch"]);
+        assert!(packets(&mut system, &legacy).is_empty());
+    }
+
+    #[test]
+    fn cancelled_changes_are_not_sent() {
+        let mut system = System::new("test");
+        let (mut server, watcher, _legacy) = server(2);
+
+        // Cancelling shrinks the batch again, so it is not sent early.
+        server.record_presence_join(&notch());
+        server.record_presence_leave(&notch());
+        server.record_presence_join(&jeb());
+        assert_eq!(server.presence.len(), 1);
+
+        server.flush_presence();
+        let packets = packets(&mut system, &watcher);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0]["c"]["joined"][0]["name"], json!("jeb_"));
+        assert_eq!(packets[0]["c"]["left"], json!([]));
+    }
+}
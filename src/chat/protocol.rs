@@ -0,0 +1,677 @@
+//! The protocol state of a websocket session, separate from the actor driving it.
+//!
+//! [`SessionProtocol`] decides what happens to every frame of the client, without actix,
+//! and the [`Session`](super::session::Session) carries out the [`Output`]s:
+//! packets are forwarded to the chat server, answered, or the connection is closed.
+//!
+//! A session goes through these states, and every packet has a defined outcome in each of them:
+//!
+//! | Packet                              | `Connected`¹        | `HelloReceived`, `AwaitingMojang` | `LoggedIn`        | `Closing` |
+//! |-------------------------------------|---------------------|-----------------------------------|-------------------|-----------|
+//! | `Hello`                             | process             | process²                          | process²          | ignore    |
+//! | `RequestServerInfo`                 | process             | process                           | process           | ignore    |
+//! | `RequestMojangInfo`                 | `HandshakeRequired` | process                           | `AlreadyLoggedIn` | ignore    |
+//! | `LoginMojang`, `LoginJWT`, `Resume` | `HandshakeRequired` | process                           | `AlreadyLoggedIn` | ignore    |
+//! | `TimeSync`                          | `HandshakeRequired` | answer                            | answer            | ignore    |
+//! | every other packet                  | `HandshakeRequired` | process                           | process           | ignore    |
+//!
+//! ¹ If `Hello` is not required, a session in `Connected` handles packets like one in `HelloReceived`.
+//! ² Only the first `Hello` of a connection is processed, later ones are ignored.
+//!
+//! If a version of the protocol was negotiated with the `Sec-WebSocket-Protocol` header,
+//! packets are encoded in it from the start, and the `protocol` of `Hello` is replaced with it.
+//!
+//! Processed packets are still rejected with `RateLimited` while the chat server sheds them.
+//! Whether a user may send a packet, e.g. a message before logging in, is checked by the chat server.
+
+use super::{
+    close::DisconnectReason,
+    cluster,
+    decode::{decode_packet, PacketLimits},
+    Capabilities, ClientPacket, ServerPacket, SuccessReason, LEGACY_PROTOCOL_VERSION,
+    PROTOCOL_VERSION,
+};
+use crate::config::ShedPackets;
+use crate::error::*;
+use log::*;
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The time in which a client can not send more than [`MAX_TIME_SYNCS`] `TimeSync` packets.
+const TIME_SYNC_WINDOW: Duration = Duration::from_secs(60);
+/// The number of `TimeSync` packets answered in a window.
+const MAX_TIME_SYNCS: usize = 6;
+
+/// How far a session got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ProtocolState {
+    /// The client did not send anything which changes the state yet.
+    Connected,
+    /// The client sent `Hello`.
+    HelloReceived,
+    /// The client requested a session hash to log in with Mojang.
+    AwaitingMojang,
+    /// The chat server confirmed a login or resumed session.
+    LoggedIn,
+    /// The connection is being closed; nothing is handled anymore.
+    Closing,
+}
+
+/// Something which happened to a session.
+pub(super) enum Input<'a> {
+    /// A text frame of the client.
+    Text(&'a str),
+    /// A binary frame of the client, which never holds a packet.
+    Binary,
+    /// A packet which is sent to the client.
+    Outgoing(&'a ClientPacket),
+    /// The time to send `Hello` ran out.
+    HandshakeTimeout,
+    /// The connection has to be closed, by the client or the server.
+    Close(DisconnectReason),
+}
+
+/// What the session has to do.
+pub(super) enum Output {
+    /// Hand the packet to the chat server.
+    Forward(ServerPacket),
+    /// Send the packet to the client, as a response to the frame which is being handled.
+    Reply(ClientPacket),
+    /// Send a close frame and stop the session.
+    Close(DisconnectReason),
+}
+
+/// The conditions a frame is handled under, which the session knows and a test can make up.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Conditions {
+    pub now: Instant,
+    pub system_now: SystemTime,
+    /// Which packets the chat server currently rejects because it is overloaded.
+    pub shedding: Option<ShedPackets>,
+}
+
+/// What is done with a decoded packet.
+enum Outcome {
+    /// Forward it to the chat server.
+    Process,
+    /// Answer it in the session.
+    Answer,
+    /// Answer it with an error.
+    Reject(ClientError),
+    /// Drop it, for the reason in the log.
+    Ignore(&'static str),
+}
+
+/// The packets which are treated alike in every state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PacketKind {
+    Hello,
+    ServerInfo,
+    MojangInfo,
+    Login,
+    TimeSync,
+    Session,
+}
+
+impl PacketKind {
+    fn of(packet: &ServerPacket) -> PacketKind {
+        // Every packet is listed, so a new one needs a decision here.
+        match packet {
+            ServerPacket::Hello { .. } => PacketKind::Hello,
+            ServerPacket::RequestServerInfo => PacketKind::ServerInfo,
+            ServerPacket::RequestMojangInfo => PacketKind::MojangInfo,
+            ServerPacket::LoginMojang(_)
+            | ServerPacket::LoginJWT { .. }
+            | ServerPacket::Resume { .. } => PacketKind::Login,
+            ServerPacket::TimeSync { .. } => PacketKind::TimeSync,
+            ServerPacket::SetEchoOwnMessages { .. }
+            | ServerPacket::SetStatus { .. }
+            | ServerPacket::RequestJWT
+            | ServerPacket::Message { .. }
+            | ServerPacket::PrivateMessage { .. }
+            | ServerPacket::NotifyWhenOnline { .. }
+            | ServerPacket::BanUser { .. }
+            | ServerPacket::UnbanUser { .. }
+            | ServerPacket::ConfirmAction { .. }
+            | ServerPacket::AddBlockedWord { .. }
+            | ServerPacket::RemoveBlockedWord { .. }
+            | ServerPacket::ListBlockedWords { .. }
+            | ServerPacket::SubscribeModerationEvents { .. }
+            | ServerPacket::RequestUserCount
+            | ServerPacket::LookupUuid { .. }
+            | ServerPacket::ResyncFrom { .. }
+            | ServerPacket::RequestEmotes
+            | ServerPacket::React { .. }
+            | ServerPacket::RemoveReaction { .. }
+            | ServerPacket::RequestDiagnostics
+            | ServerPacket::RequestAuditLog { .. }
+            | ServerPacket::RequestPmMetadata { .. } => PacketKind::Session,
+        }
+    }
+}
+
+/// The protocol state of one websocket session.
+pub(super) struct SessionProtocol {
+    state: ProtocolState,
+    /// Whether packets other than `Hello` are rejected in `Connected`.
+    requires_hello: bool,
+    /// Whether `Hello` was handled, which happens only once.
+    hello: bool,
+    packet_limits: PacketLimits,
+    /// The number of packets received from the client which could not be decoded.
+    malformed_packets: u32,
+    /// The version of the protocol packets are encoded in.
+    protocol: u32,
+    /// The version negotiated with the `Sec-WebSocket-Protocol` header, which `Hello` can not change.
+    subprotocol: Option<u32>,
+    /// Whether the client declared the `trace` feature.
+    traces: bool,
+    /// When the `TimeSync` packets of the current window were answered.
+    time_syncs: VecDeque<Instant>,
+}
+
+impl SessionProtocol {
+    pub fn new(
+        requires_hello: bool,
+        packet_limits: PacketLimits,
+        subprotocol: Option<u32>,
+    ) -> SessionProtocol {
+        SessionProtocol {
+            state: ProtocolState::Connected,
+            requires_hello,
+            hello: false,
+            packet_limits,
+            malformed_packets: 0,
+            protocol: subprotocol.unwrap_or(LEGACY_PROTOCOL_VERSION),
+            subprotocol,
+            traces: false,
+            time_syncs: VecDeque::new(),
+        }
+    }
+
+    /// The version of the protocol the client speaks.
+    pub fn protocol(&self) -> u32 {
+        self.protocol
+    }
+
+    /// The version of the protocol negotiated in the handshake, if any.
+    pub fn subprotocol(&self) -> Option<u32> {
+        self.subprotocol
+    }
+
+    /// Whether the client receives the traces of its packets.
+    pub fn traces(&self) -> bool {
+        self.traces
+    }
+
+    pub fn malformed_packets(&self) -> u32 {
+        self.malformed_packets
+    }
+
+    /// Advances the state with `input` and returns what the session has to do, in order.
+    pub fn handle(&mut self, input: Input<'_>, conditions: Conditions) -> Vec<Output> {
+        if self.state == ProtocolState::Closing {
+            if let Input::Text(_) | Input::Binary = input {
+                debug!("Ignoring a frame of a closing connection.");
+            }
+            return Vec::new();
+        }
+        match input {
+            Input::Text(text) => match decode_packet(text, &self.packet_limits) {
+                Ok(packet) => self.handle_packet(packet, conditions),
+                Err(category) => self.reject_malformed(category),
+            },
+            Input::Binary => self.reject_malformed(MalformedCategory::Binary),
+            Input::Outgoing(packet) => {
+                if let ClientPacket::Success {
+                    reason: SuccessReason::Login,
+                }
+                | ClientPacket::Success {
+                    reason: SuccessReason::Resume,
+                } = packet
+                {
+                    self.state = ProtocolState::LoggedIn;
+                }
+                Vec::new()
+            }
+            Input::HandshakeTimeout => {
+                if self.requires_hello && !self.hello {
+                    self.close(DisconnectReason::HandshakeTimeout)
+                } else {
+                    Vec::new()
+                }
+            }
+            Input::Close(reason) => self.close(reason),
+        }
+    }
+
+    fn handle_packet(&mut self, mut packet: ServerPacket, conditions: Conditions) -> Vec<Output> {
+        let kind = PacketKind::of(&packet);
+        match self.outcome(kind) {
+            Outcome::Process if is_shed(&packet, conditions.shedding) => {
+                debug!("Rejecting packet, the chat server is overloaded.");
+                vec![Output::Reply(ClientPacket::Error {
+                    message: ClientError::RateLimited,
+                })]
+            }
+            Outcome::Process => {
+                match &mut packet {
+                    ServerPacket::Hello {
+                        protocol, features, ..
+                    } => {
+                        // The chat server is told the negotiated version, whatever `Hello` declared.
+                        if let Some(subprotocol) = self.subprotocol {
+                            match *protocol {
+                                Some(declared) if declared != subprotocol => debug!(
+                                    "Hello declared protocol {}, but {} was negotiated.",
+                                    declared, subprotocol
+                                ),
+                                _ => {}
+                            }
+                            *protocol = Some(subprotocol);
+                        }
+                        self.hello = true;
+                        self.protocol = protocol.unwrap_or(PROTOCOL_VERSION);
+                        self.traces = features.contains(Capabilities::TRACE);
+                        if self.state == ProtocolState::Connected {
+                            self.state = ProtocolState::HelloReceived;
+                        }
+                    }
+                    ServerPacket::RequestMojangInfo => self.state = ProtocolState::AwaitingMojang,
+                    _ => {}
+                }
+                vec![Output::Forward(packet)]
+            }
+            Outcome::Answer => match packet {
+                ServerPacket::TimeSync { client_time_ms } => {
+                    vec![Output::Reply(self.time_sync(client_time_ms, conditions))]
+                }
+                _ => unreachable!("only `TimeSync` is answered by the session"),
+            },
+            Outcome::Reject(message) => {
+                debug!("Rejecting packet in state {:?}: {}", self.state, message);
+                vec![Output::Reply(ClientPacket::Error { message })]
+            }
+            Outcome::Ignore(reason) => {
+                debug!("Ignoring packet in state {:?}: {}", self.state, reason);
+                Vec::new()
+            }
+        }
+    }
+
+    /// The outcome of a packet of `kind` in the current state.
+    fn outcome(&self, kind: PacketKind) -> Outcome {
+        use PacketKind::*;
+        use ProtocolState::*;
+
+        let awaiting_hello = self.state == Connected && self.requires_hello;
+        match (self.state, kind) {
+            (Closing, _) => Outcome::Ignore("the connection is closing"),
+            (_, Hello) if self.hello => Outcome::Ignore("`Hello` was sent before"),
+            (_, Hello) | (_, ServerInfo) => Outcome::Process,
+            (Connected, _) if awaiting_hello => Outcome::Reject(ClientError::HandshakeRequired),
+            (LoggedIn, MojangInfo) | (LoggedIn, Login) => {
+                Outcome::Reject(ClientError::AlreadyLoggedIn)
+            }
+            (Connected, MojangInfo)
+            | (Connected, Login)
+            | (HelloReceived, MojangInfo)
+            | (HelloReceived, Login)
+            | (AwaitingMojang, MojangInfo)
+            | (AwaitingMojang, Login) => Outcome::Process,
+            (_, TimeSync) => Outcome::Answer,
+            (_, Session) => Outcome::Process,
+        }
+    }
+
+    /// Answers a `TimeSync` right away, without going through the chat server,
+    /// so the round trip the client measures does not include its backlog.
+    fn time_sync(&mut self, client_time_ms: u64, conditions: Conditions) -> ClientPacket {
+        let now = conditions.now;
+        while self
+            .time_syncs
+            .front()
+            .is_some_and(|time| now.duration_since(*time) >= TIME_SYNC_WINDOW)
+        {
+            self.time_syncs.pop_front();
+        }
+        if self.time_syncs.len() >= MAX_TIME_SYNCS {
+            debug!("Connection synchronizes its time too often.");
+            return ClientPacket::Error {
+                message: ClientError::RateLimited,
+            };
+        }
+        self.time_syncs.push_back(now);
+        ClientPacket::TimeSync {
+            client_time_ms: Some(client_time_ms),
+            server_time_ms: cluster::unix_millis(conditions.system_now),
+        }
+    }
+
+    /// Tells the client that its packet could not be decoded,
+    /// and closes the connection once it sent too many malformed packets.
+    fn reject_malformed(&mut self, category: MalformedCategory) -> Vec<Output> {
+        debug!("Connection sent a malformed packet: {}", category.as_str());
+        self.malformed_packets += 1;
+        let max = self.packet_limits.max_malformed;
+        if max != 0 && self.malformed_packets >= max {
+            return self.close(DisconnectReason::MalformedPackets);
+        }
+        vec![Output::Reply(ClientPacket::Error {
+            message: ClientError::MalformedPacket { category },
+        })]
+    }
+
+    fn close(&mut self, reason: DisconnectReason) -> Vec<Output> {
+        self.state = ProtocolState::Closing;
+        vec![Output::Close(reason)]
+    }
+}
+
+/// Returns whether `packet` is rejected because the chat server sheds `shedding`.
+fn is_shed(packet: &ServerPacket, shedding: Option<ShedPackets>) -> bool {
+    match shedding {
+        Some(ShedPackets::Messages) => matches!(
+            packet,
+            ServerPacket::Message { .. }
+                | ServerPacket::PrivateMessage { .. }
+                | ServerPacket::React { .. }
+                | ServerPacket::RemoveReaction { .. }
+        ),
+        Some(ShedPackets::All) => !matches!(
+            packet,
+            ServerPacket::Hello { .. }
+                | ServerPacket::RequestMojangInfo
+                | ServerPacket::LoginMojang(_)
+                | ServerPacket::LoginJWT { .. }
+                | ServerPacket::Resume { .. }
+        ),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{samples, User};
+
+    /// The states a session can be in, including those the table distinguishes by `Hello`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum State {
+        /// `Connected`, with `Hello` required.
+        AwaitingHello,
+        /// `Connected`, with `Hello` not required.
+        Connected,
+        HelloReceived,
+        AwaitingMojang,
+        LoggedIn,
+        /// `LoggedIn` by a client which never sent `Hello`.
+        LoggedInWithoutHello,
+        Closing,
+    }
+
+    const STATES: &[State] = &[
+        State::AwaitingHello,
+        State::Connected,
+        State::HelloReceived,
+        State::AwaitingMojang,
+        State::LoggedIn,
+        State::LoggedInWithoutHello,
+        State::Closing,
+    ];
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Expected {
+        Forward,
+        Answer,
+        Reject(&'static str),
+        Ignore,
+    }
+
+    fn conditions() -> Conditions {
+        Conditions {
+            now: Instant::now(),
+            system_now: SystemTime::now(),
+            shedding: None,
+        }
+    }
+
+    fn send(protocol: &mut SessionProtocol, packet: &ServerPacket) -> Vec<Output> {
+        let encoded = serde_json::to_string(packet).unwrap();
+        protocol.handle(Input::Text(&encoded), conditions())
+    }
+
+    fn hello() -> ServerPacket {
+        ServerPacket::Hello {
+            features: Capabilities::NONE,
+            echo_own_messages: None,
+            client: None,
+            protocol: Some(PROTOCOL_VERSION),
+        }
+    }
+
+    /// Drives a new session into `state` with the inputs a real one receives.
+    fn session_in(state: State) -> SessionProtocol {
+        let limits = PacketLimits {
+            max_size: usize::MAX,
+            max_depth: usize::MAX,
+            max_malformed: 0,
+        };
+        let mut protocol = SessionProtocol::new(state == State::AwaitingHello, limits, None);
+        let login = ClientPacket::Success {
+            reason: SuccessReason::Login,
+        };
+        match state {
+            State::AwaitingHello | State::Connected => {}
+            State::HelloReceived => {
+                send(&mut protocol, &hello());
+            }
+            State::AwaitingMojang => {
+                send(&mut protocol, &hello());
+                send(&mut protocol, &ServerPacket::RequestMojangInfo);
+            }
+            State::LoggedIn => {
+                send(&mut protocol, &hello());
+                protocol.handle(Input::Outgoing(&login), conditions());
+            }
+            State::LoggedInWithoutHello => {
+                protocol.handle(Input::Outgoing(&login), conditions());
+            }
+            State::Closing => {
+                protocol.handle(Input::Close(DisconnectReason::ClientClosed), conditions());
+            }
+        }
+        assert_eq!(protocol.state, protocol_state(state));
+        protocol
+    }
+
+    fn protocol_state(state: State) -> ProtocolState {
+        match state {
+            State::AwaitingHello | State::Connected => ProtocolState::Connected,
+            State::HelloReceived => ProtocolState::HelloReceived,
+            State::AwaitingMojang => ProtocolState::AwaitingMojang,
+            State::LoggedIn | State::LoggedInWithoutHello => ProtocolState::LoggedIn,
+            State::Closing => ProtocolState::Closing,
+        }
+    }
+
+    /// The outcome of `packet` in `state`, as the table of the module documentation defines it.
+    ///
+    /// The match lists every packet, so a new one does not compile until its row is decided.
+    fn expected(state: State, packet: &ServerPacket) -> Expected {
+        use ServerPacket::*;
+        use State::*;
+
+        const HANDSHAKE_REQUIRED: &str = keys::HANDSHAKE_REQUIRED;
+        const ALREADY_LOGGED_IN: &str = keys::ALREADY_LOGGED_IN;
+
+        if state == Closing {
+            return Expected::Ignore;
+        }
+        match packet {
+            Hello { .. } => match state {
+                AwaitingHello | Connected | LoggedInWithoutHello => Expected::Forward,
+                _ => Expected::Ignore,
+            },
+            RequestServerInfo => Expected::Forward,
+            RequestMojangInfo | LoginMojang(_) | LoginJWT { .. } | Resume { .. } => match state {
+                AwaitingHello => Expected::Reject(HANDSHAKE_REQUIRED),
+                LoggedIn | LoggedInWithoutHello => Expected::Reject(ALREADY_LOGGED_IN),
+                _ => Expected::Forward,
+            },
+            TimeSync { .. } => match state {
+                AwaitingHello => Expected::Reject(HANDSHAKE_REQUIRED),
+                _ => Expected::Answer,
+            },
+            SetEchoOwnMessages { .. }
+            | SetStatus { .. }
+            | RequestJWT
+            | Message { .. }
+            | PrivateMessage { .. }
+            | NotifyWhenOnline { .. }
+            | BanUser { .. }
+            | UnbanUser { .. }
+            | ConfirmAction { .. }
+            | AddBlockedWord { .. }
+            | RemoveBlockedWord { .. }
+            | ListBlockedWords { .. }
+            | SubscribeModerationEvents { .. }
+            | RequestUserCount
+            | LookupUuid { .. }
+            | ResyncFrom { .. }
+            | RequestEmotes
+            | React { .. }
+            | RemoveReaction { .. }
+            | RequestDiagnostics
+            | RequestAuditLog { .. }
+            | RequestPmMetadata { .. } => match state {
+                AwaitingHello => Expected::Reject(HANDSHAKE_REQUIRED),
+                _ => Expected::Forward,
+            },
+        }
+    }
+
+    /// The state after `packet` was forwarded in `state`.
+    fn next_state(state: State, packet: &ServerPacket) -> ProtocolState {
+        match (state, packet) {
+            (State::AwaitingHello, ServerPacket::Hello { .. })
+            | (State::Connected, ServerPacket::Hello { .. }) => ProtocolState::HelloReceived,
+            (_, ServerPacket::RequestMojangInfo) => ProtocolState::AwaitingMojang,
+            _ => protocol_state(state),
+        }
+    }
+
+    fn outcome(outputs: &[Output]) -> Expected {
+        match outputs {
+            [] => Expected::Ignore,
+            [Output::Forward(_)] => Expected::Forward,
+            [Output::Reply(ClientPacket::TimeSync { .. })] => Expected::Answer,
+            [Output::Reply(ClientPacket::Error { message })] => {
+                Expected::Reject(message.translation_key())
+            }
+            _ => panic!("unexpected outputs"),
+        }
+    }
+
+    #[test]
+    fn every_packet_has_the_documented_outcome_in_every_state() {
+        for state in STATES {
+            for packet in samples::server_packets() {
+                let mut protocol = session_in(*state);
+                let outputs = send(&mut protocol, &packet);
+                let name = serde_json::to_value(&packet).unwrap()["m"].clone();
+                let expected = expected(*state, &packet);
+                assert_eq!(outcome(&outputs), expected, "{} in {:?}", name, state);
+
+                let next = match expected {
+                    Expected::Forward => next_state(*state, &packet),
+                    _ => protocol_state(*state),
+                };
+                assert_eq!(protocol.state, next, "{} in {:?}", name, state);
+            }
+        }
+    }
+
+    #[test]
+    fn forwarded_logins_are_not_logged_in_until_the_chat_server_confirms_them() {
+        let mut protocol = session_in(State::HelloReceived);
+        let login = ServerPacket::LoginMojang(User {
+            name: crate::chat::DisplayName::new("Notch".to_string()),
+            uuid: uuid::Uuid::nil(),
+            allow_messages: true,
+        });
+        assert_eq!(outcome(&send(&mut protocol, &login)), Expected::Forward);
+        assert_eq!(outcome(&send(&mut protocol, &login)), Expected::Forward);
+        assert_eq!(protocol.state, ProtocolState::HelloReceived);
+
+        let success = ClientPacket::Success {
+            reason: SuccessReason::Resume,
+        };
+        protocol.handle(Input::Outgoing(&success), conditions());
+        assert_eq!(
+            outcome(&send(&mut protocol, &login)),
+            Expected::Reject(keys::ALREADY_LOGGED_IN)
+        );
+    }
+
+    /// Sends a `TimeSync` with `client_time_ms` at `now`, returning the answer.
+    fn time_sync(
+        protocol: &mut SessionProtocol,
+        client_time_ms: u64,
+        now: Instant,
+        system_now: SystemTime,
+    ) -> ClientPacket {
+        let encoded = serde_json::to_string(&ServerPacket::TimeSync { client_time_ms }).unwrap();
+        let conditions = Conditions {
+            now,
+            system_now,
+            shedding: None,
+        };
+        match protocol.handle(Input::Text(&encoded), conditions).pop() {
+            Some(Output::Reply(packet)) => packet,
+            _ => panic!("`TimeSync` was not answered"),
+        }
+    }
+
+    #[test]
+    fn time_syncs_echo_the_client_time() {
+        let mut protocol = session_in(State::HelloReceived);
+        let system_now = SystemTime::UNIX_EPOCH + Duration::from_millis(1_600_000_000_123);
+        match time_sync(&mut protocol, 42, Instant::now(), system_now) {
+            ClientPacket::TimeSync {
+                client_time_ms,
+                server_time_ms,
+            } => {
+                assert_eq!(client_time_ms, Some(42));
+                assert_eq!(server_time_ms, 1_600_000_000_123);
+            }
+            _ => panic!("expected a `TimeSync`"),
+        }
+    }
+
+    #[test]
+    fn time_syncs_are_rate_limited_per_window() {
+        let mut protocol = session_in(State::LoggedIn);
+        let start = Instant::now();
+        let answer = |protocol: &mut SessionProtocol, client_time_ms, after| {
+            let packet = time_sync(protocol, client_time_ms, start + after, SystemTime::now());
+            outcome(&[Output::Reply(packet)])
+        };
+
+        for i in 0..MAX_TIME_SYNCS as u64 {
+            let after = Duration::from_secs(i);
+            assert_eq!(answer(&mut protocol, i, after), Expected::Answer);
+        }
+        let rate_limited = Expected::Reject(keys::RATE_LIMITED);
+        let late = Duration::from_secs(TIME_SYNC_WINDOW.as_secs() - 1);
+        assert_eq!(answer(&mut protocol, 6, late), rate_limited);
+
+        // The oldest answer leaves the window, which makes room for one more.
+        assert_eq!(answer(&mut protocol, 7, TIME_SYNC_WINDOW), Expected::Answer);
+        assert_eq!(answer(&mut protocol, 8, TIME_SYNC_WINDOW), rate_limited);
+        // Rejected packets do not count towards the limit.
+        let later = TIME_SYNC_WINDOW + Duration::from_secs(1);
+        assert_eq!(answer(&mut protocol, 9, later), Expected::Answer);
+    }
+}
@@ -0,0 +1,567 @@
+//! Sample instances of every packet, for the tests of their encodings.
+//!
+//! Packets with optional parts are sampled with and without them.
+
+use super::{
+    handler::{
+        self, ModerationEventKind, PrivateBody, RateLimitDiagnostics, SystemMessageKind, UserStatus,
+    },
+    history::{Reply, ReplyExcerpt},
+    pm_metadata::PmMetadataEntry,
+    AuthorKind, CanonicalId, Capabilities, ClientPacket, ClientVersion, DisplayName, InternalId,
+    MessageFlags, Origin, ServerPacket, SuccessReason, User,
+};
+use crate::auth::UserInfo;
+use crate::config::LengthUnit;
+use crate::error::{ClientError, TranslationParams};
+use crate::message::ValidatedContent;
+use crate::signing::MessageSignature;
+use crate::storage::{AuditAction, AuditEntry, AuditPage};
+
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+fn notch() -> Uuid {
+    Uuid::from_u128(0x069a79f4_44e9_4726_a5be_fca90e38aaf5)
+}
+
+fn jeb() -> Uuid {
+    Uuid::from_u128(0x853c80ef_3c37_49fd_aa49_938b674adae6)
+}
+
+fn user_info() -> UserInfo {
+    UserInfo {
+        name: "Notch".to_string(),
+        uuid: notch(),
+        bot: false,
+    }
+}
+
+fn bot_info() -> UserInfo {
+    UserInfo {
+        name: "Relay".to_string(),
+        uuid: jeb(),
+        bot: true,
+    }
+}
+
+fn audit_entry() -> AuditEntry {
+    AuditEntry {
+        timestamp: 1_700_000_000_000,
+        actor: Some(jeb()),
+        action: AuditAction::Ban,
+        target: notch(),
+        reason: Some("spam".to_string()),
+        duration_secs: Some(3600),
+    }
+}
+
+fn params() -> TranslationParams {
+    let mut params = TranslationParams::new();
+    params.insert("name", "Notch".to_string());
+    params
+}
+
+/// One or more packets of every variant of [`ClientPacket`].
+pub(super) fn client_packets() -> Vec<ClientPacket> {
+    use ClientPacket::*;
+
+    let mut reactions = BTreeMap::new();
+    reactions.insert("👍".to_string(), 2);
+    let mut emotes = BTreeMap::new();
+    emotes.insert("wave".to_string(), "👋".to_string());
+
+    vec![
+        MojangInfo {
+            session_hash: "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48".to_string(),
+        },
+        NewJWT {
+            token: "eyJhbGciOiJIUzI1NiJ9.e30.c2lnbmF0dXJl".to_string(),
+        },
+        Message {
+            seq: 1,
+            author_info: user_info(),
+            author_kind: AuthorKind::Player,
+            content: ValidatedContent::trusted("hello".to_string(), false),
+            signature: None,
+            reactions: BTreeMap::new(),
+            reply: None,
+            flags: MessageFlags::NONE,
+        },
+        Message {
+            seq: 2,
+            author_info: bot_info(),
+            author_kind: AuthorKind::Bridge {
+                origin: "discord".to_string(),
+            },
+            content: ValidatedContent::trusted("hi ***".to_string(), true),
+            signature: Some(MessageSignature {
+                timestamp: 1_700_000_000_000,
+                signature: "c2lnbmF0dXJl".to_string(),
+            }),
+            reactions,
+            reply: Some(Reply {
+                reply_to: 1,
+                reply_excerpt: Some(ReplyExcerpt {
+                    author_info: user_info(),
+                    content: "hello".to_string(),
+                }),
+            }),
+            flags: MessageFlags::BRIDGED.with(MessageFlags::REDACTED),
+        },
+        Message {
+            seq: 3,
+            author_info: user_info(),
+            author_kind: AuthorKind::System,
+            content: ValidatedContent::trusted("restarting".to_string(), false),
+            signature: None,
+            reactions: BTreeMap::new(),
+            reply: Some(Reply {
+                reply_to: 1,
+                reply_excerpt: None,
+            }),
+            flags: MessageFlags::OWN.with(MessageFlags::REPLY_MISSING),
+        },
+        PrivateMessage {
+            author_info: user_info(),
+            conversation: CanonicalId::from_display_name("jeb_"),
+            id: 1,
+            timestamp: 1_700_000_000_000,
+            body: PrivateBody::Plain(ValidatedContent::trusted("psst".to_string(), false)),
+            flags: MessageFlags::NONE,
+        },
+        PrivateMessage {
+            author_info: user_info(),
+            conversation: CanonicalId::from_display_name("jeb_"),
+            id: 2,
+            timestamp: 1_700_000_000_000,
+            body: PrivateBody::Encrypted("ZW5jcnlwdGVk".to_string()),
+            flags: MessageFlags::ENCRYPTED,
+        },
+        MessageAck {
+            seq: 1,
+            timestamp: 1_700_000_000_000,
+            delivery_count: None,
+        },
+        MessageAck {
+            seq: 1,
+            timestamp: 1_700_000_000_000,
+            delivery_count: Some(3),
+        },
+        PrivateMessageAck {
+            receiver: "jeb_".to_string(),
+            delivery_count: 1,
+        },
+        ResumeToken {
+            token: "cmVzdW1l".to_string(),
+        },
+        BlockedWords {
+            words: vec!["blocked".to_string()],
+        },
+        ResyncTooOld {
+            oldest_available: 10,
+        },
+        ReplayComplete { count: 5 },
+        UserOnline {
+            id: "jeb_".to_string(),
+        },
+        UserRenamed {
+            old_id: "notch".to_string(),
+            new_id: "notch2".to_string(),
+        },
+        ReactionUpdate {
+            message_id: 1,
+            emoji: "👍".to_string(),
+            user_id: "jeb_".to_string(),
+            added: true,
+        },
+        Emotes { emotes },
+        ClientPacket::UserLookup(handler::UserLookup {
+            uuid: notch(),
+            id: "notch".to_string(),
+            online: false,
+            sessions: 0,
+            last_seen: Some(1_700_000_000_000),
+            previous_names: vec!["Notch_".to_string()],
+            status: None,
+        }),
+        ClientPacket::UserLookup(handler::UserLookup {
+            uuid: notch(),
+            id: "notch".to_string(),
+            online: true,
+            sessions: 2,
+            last_seen: None,
+            previous_names: Vec::new(),
+            status: Some(UserStatus::Dnd),
+        }),
+        ClientPacket::Diagnostics(handler::Diagnostics {
+            id: InternalId::new(1, Origin::Client, 0x1234_5678),
+            session_tag: "c1/12345678".to_string(),
+            name: "Notch".to_string(),
+            uuid: notch(),
+            features: Capabilities::ALL,
+            echo_own_messages: true,
+            status: UserStatus::Online,
+            moderator: false,
+            banned: false,
+            probation_secs: Some(30),
+            join_cooldown_secs: Some(10),
+            pm_metadata_retention_minutes: Some(60),
+            rate_limit: RateLimitDiagnostics {
+                messages: 1,
+                max_messages: 10,
+                window_secs: 5,
+            },
+            queued_packets: 0,
+            malformed_packets: 0,
+            server_time: 1_700_000_000_000,
+        }),
+        AuditLog(AuditPage {
+            total: 2,
+            entries: vec![
+                audit_entry(),
+                AuditEntry {
+                    actor: None,
+                    action: AuditAction::Unban,
+                    reason: None,
+                    duration_secs: None,
+                    ..audit_entry()
+                },
+            ],
+        }),
+        PmMetadata {
+            uuid: notch(),
+            entries: vec![PmMetadataEntry {
+                sender: notch(),
+                receiver: jeb(),
+                timestamp: 1_700_000_000_000,
+                length: 4,
+            }],
+        },
+        UserCount {
+            connections: 3,
+            logged_in: 2,
+        },
+        Success {
+            reason: SuccessReason::Login,
+        },
+        CommandResult {
+            success: true,
+            message: "banned `Notch`".to_string(),
+            translation_key: "command.banned",
+            params: params(),
+        },
+        ConfirmAction {
+            nonce: "5f2b8c1e9a7d4e3f".to_string(),
+            summary: "ban `Notch` permanently".to_string(),
+        },
+        MessageFlagged {
+            author_info: user_info(),
+            content: ValidatedContent::trusted("suspicious".to_string(), false),
+        },
+        ModerationStatus {
+            banned: false,
+            muted: false,
+            expires_at: None,
+            reason: None,
+        },
+        ModerationStatus {
+            banned: false,
+            muted: true,
+            expires_at: Some(1_700_000_600),
+            reason: Some("caps".to_string()),
+        },
+        ModerationEvent {
+            kind: ModerationEventKind::BlockedWord,
+            user: user_info(),
+            content_excerpt: "a blocked word".to_string(),
+            rule: "blocked_words".to_string(),
+        },
+        UserJoined { user: user_info() },
+        UserLeft { user: bot_info() },
+        PresenceDiff {
+            joined: vec![user_info()],
+            left: vec!["jeb_".to_string()],
+        },
+        ModerationAction(audit_entry()),
+        ProtocolDeprecated {
+            protocol: 1,
+            sunset: 1_800_000_000,
+        },
+        Motd {
+            content: "Welcome!".to_string(),
+        },
+        Disconnected {
+            reason_code: "migrate",
+            translation_key: "disconnect.migrate",
+            retry_after_secs: Some(5),
+            reason: None,
+        },
+        Disconnected {
+            reason_code: "banned",
+            translation_key: "disconnect.banned",
+            retry_after_secs: None,
+            reason: Some("spam".to_string()),
+        },
+        SessionTag {
+            session_tag: "c1/12345678".to_string(),
+        },
+        TimeSync {
+            client_time_ms: Some(1_700_000_000_000),
+            server_time_ms: 1_700_000_000_005,
+        },
+        TimeSync {
+            client_time_ms: None,
+            server_time_ms: 1_700_000_000_005,
+        },
+        SystemMessage {
+            content: "Notch was banned.".to_string(),
+            kind: SystemMessageKind::Ban,
+        },
+        ServerInfo {
+            version: "0.10.0".to_string(),
+            protocol: 2,
+            max_message_length: 100,
+            max_lines: 1,
+            max_line_length: None,
+            commands_enabled: true,
+            commit: "3d8548e".to_string(),
+            build_timestamp: "2026-10-14T00:00:00Z".to_string(),
+            uptime_secs: 60,
+            features: Capabilities::ALL,
+            connections: 3,
+            max_connections: Some(100),
+            online_users: 2,
+            guest_viewers: 1,
+        },
+        Error {
+            message: ClientError::NotLoggedIn,
+        },
+        Error {
+            message: ClientError::MessageTooLong {
+                length: 120,
+                max_length: 100,
+                unit: LengthUnit::Graphemes,
+            },
+        },
+        // Version 1 sent the character of this error.
+        Error {
+            message: ClientError::InvalidCharacter {
+                character: '\u{7}',
+                char_index: 2,
+                byte_offset: 2,
+            },
+        },
+        RepeatedError {
+            message: ClientError::RateLimited,
+            repeated: 4,
+        },
+        Rejected {
+            message: ClientError::BlockedContent,
+            rule: "blocked_words",
+        },
+    ]
+}
+
+/// Fails to compile if [`client_packets`] misses a variant added to [`ClientPacket`].
+#[allow(dead_code)]
+fn is_client_packet_listed(packet: &ClientPacket) {
+    use ClientPacket::*;
+
+    match packet {
+        MojangInfo { .. }
+        | NewJWT { .. }
+        | Message { .. }
+        | PrivateMessage { .. }
+        | MessageAck { .. }
+        | PrivateMessageAck { .. }
+        | ResumeToken { .. }
+        | BlockedWords { .. }
+        | ResyncTooOld { .. }
+        | ReplayComplete { .. }
+        | UserOnline { .. }
+        | UserRenamed { .. }
+        | ReactionUpdate { .. }
+        | Emotes { .. }
+        | ClientPacket::UserLookup(_)
+        | ClientPacket::Diagnostics(_)
+        | AuditLog(_)
+        | PmMetadata { .. }
+        | UserCount { .. }
+        | Success { .. }
+        | CommandResult { .. }
+        | ConfirmAction { .. }
+        | MessageFlagged { .. }
+        | ModerationStatus { .. }
+        | ModerationEvent { .. }
+        | UserJoined { .. }
+        | UserLeft { .. }
+        | PresenceDiff { .. }
+        | ModerationAction(_)
+        | ProtocolDeprecated { .. }
+        | Motd { .. }
+        | Disconnected { .. }
+        | SessionTag { .. }
+        | TimeSync { .. }
+        | SystemMessage { .. }
+        | ServerInfo { .. }
+        | Error { .. }
+        | RepeatedError { .. }
+        | Rejected { .. } => {}
+    }
+}
+
+/// One or more packets of every variant of [`ServerPacket`].
+pub(super) fn server_packets() -> Vec<ServerPacket> {
+    use ServerPacket::*;
+
+    vec![
+        Hello {
+            features: Capabilities::NONE,
+            echo_own_messages: None,
+            client: None,
+            protocol: None,
+        },
+        Hello {
+            features: Capabilities::ALL,
+            echo_own_messages: Some(false),
+            client: Some(ClientVersion {
+                brand: "LiquidBounce".to_string(),
+                version: "1.0.0".to_string(),
+            }),
+            protocol: Some(2),
+        },
+        SetEchoOwnMessages { enabled: false },
+        SetStatus {
+            status: UserStatus::Dnd,
+        },
+        RequestMojangInfo,
+        LoginMojang(User {
+            name: DisplayName::new("Notch".to_string()),
+            uuid: notch(),
+            allow_messages: true,
+        }),
+        LoginJWT {
+            token: "eyJhbGciOiJIUzI1NiJ9.e30.c2lnbmF0dXJl".to_string(),
+            allow_messages: false,
+        },
+        RequestJWT,
+        Message {
+            content: "hello".to_string(),
+            origin: None,
+            reply_to: None,
+        },
+        Message {
+            content: "hello".to_string(),
+            origin: Some("discord".to_string()),
+            reply_to: Some(1),
+        },
+        PrivateMessage {
+            receiver: "jeb_".to_string(),
+            content: "psst".to_string(),
+            encrypted: false,
+            payload: None,
+            reply_to: None,
+        },
+        PrivateMessage {
+            receiver: "jeb_".to_string(),
+            content: String::new(),
+            encrypted: true,
+            payload: Some("ZW5jcnlwdGVk".to_string()),
+            reply_to: Some(1),
+        },
+        NotifyWhenOnline {
+            id: "jeb_".to_string(),
+        },
+        BanUser { user: notch() },
+        UnbanUser { user: notch() },
+        ConfirmAction {
+            nonce: "5f2b8c1e9a7d4e3f".to_string(),
+        },
+        AddBlockedWord {
+            word: "blocked".to_string(),
+        },
+        RemoveBlockedWord {
+            word: "blocked".to_string(),
+        },
+        ListBlockedWords { filter: None },
+        ListBlockedWords {
+            filter: Some("bl".to_string()),
+        },
+        SubscribeModerationEvents { enabled: true },
+        RequestUserCount,
+        LookupUuid { uuid: notch() },
+        RequestServerInfo,
+        Resume {
+            token: "cmVzdW1l".to_string(),
+        },
+        ResyncFrom { seq: 10 },
+        RequestEmotes,
+        React {
+            message_id: 1,
+            emoji: "👍".to_string(),
+        },
+        RemoveReaction {
+            message_id: 1,
+            emoji: "👍".to_string(),
+        },
+        RequestDiagnostics,
+        TimeSync {
+            client_time_ms: 1_700_000_000_000,
+        },
+        RequestAuditLog {
+            actor: None,
+            target: None,
+            since: None,
+            limit: None,
+        },
+        RequestAuditLog {
+            actor: Some(jeb()),
+            target: Some(notch()),
+            since: Some(1_700_000_000_000),
+            limit: Some(10),
+        },
+        RequestPmMetadata {
+            uuid: notch(),
+            limit: None,
+        },
+    ]
+}
+
+/// Fails to compile if [`server_packets`] misses a variant added to [`ServerPacket`].
+#[allow(dead_code)]
+fn is_server_packet_listed(packet: &ServerPacket) {
+    use ServerPacket::*;
+
+    match packet {
+        Hello { .. }
+        | SetEchoOwnMessages { .. }
+        | SetStatus { .. }
+        | RequestMojangInfo
+        | LoginMojang(_)
+        | LoginJWT { .. }
+        | RequestJWT
+        | Message { .. }
+        | PrivateMessage { .. }
+        | NotifyWhenOnline { .. }
+        | BanUser { .. }
+        | UnbanUser { .. }
+        | ConfirmAction { .. }
+        | AddBlockedWord { .. }
+        | RemoveBlockedWord { .. }
+        | ListBlockedWords { .. }
+        | SubscribeModerationEvents { .. }
+        | RequestUserCount
+        | LookupUuid { .. }
+        | RequestServerInfo
+        | Resume { .. }
+        | ResyncFrom { .. }
+        | RequestEmotes
+        | React { .. }
+        | RemoveReaction { .. }
+        | RequestDiagnostics
+        | TimeSync { .. }
+        | RequestAuditLog { .. }
+        | RequestPmMetadata { .. } => {}
+    }
+}
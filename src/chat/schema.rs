@@ -0,0 +1,805 @@
+//! A machine-readable description of the packets, for authors of clients.
+//!
+//! Packets are JSON objects with the name of the packet in `m` and its content in `c`,
+//! which is omitted for packets without content.
+//! The description is maintained by hand next to [`ServerPacket`](super::ServerPacket)
+//! and [`ClientPacket`](super::ClientPacket), so it has to be updated together with them.
+
+use super::{flags, Capabilities};
+use crate::error::keys;
+use crate::version;
+
+use actix_web::HttpResponse;
+use serde::Serialize;
+
+/// The description of all packets, as served at `/schema`.
+#[derive(Serialize)]
+pub struct Schema {
+    /// The version of the server the description belongs to.
+    version: &'static str,
+    /// The version of the protocol it describes.
+    protocol: u32,
+    /// The packets sent by clients.
+    serverbound: &'static [Packet],
+    /// The packets sent by the server.
+    clientbound: &'static [Packet],
+    /// The structures used in packets.
+    types: &'static [Type],
+    /// The strings used in packets, by the name of their type.
+    enums: Vec<Enum>,
+    /// The translation keys of the errors the server sends.
+    errors: &'static [&'static str],
+}
+
+#[derive(Serialize)]
+struct Packet {
+    name: &'static str,
+    /// The fields of the content, if it is an object of its own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<&'static [Field]>,
+    /// The type of the content, if it is one of the [`Type`]s.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+struct Type {
+    name: &'static str,
+    fields: &'static [Field],
+}
+
+#[derive(Serialize)]
+struct Enum {
+    name: &'static str,
+    values: Vec<&'static str>,
+}
+
+/// A field of an object.
+///
+/// The type is `string`, `integer`, `boolean`, `uuid`, `object` for any object,
+/// `T[]` for arrays, `map<string, T>` for objects with arbitrary keys,
+/// `T | null` if the field may be `null`, or the name of a type or enum.
+#[derive(Serialize)]
+struct Field {
+    name: &'static str,
+    #[serde(rename = "type")]
+    ty: &'static str,
+    /// Whether the field may be omitted.
+    optional: bool,
+}
+
+const fn field(name: &'static str, ty: &'static str) -> Field {
+    Field {
+        name,
+        ty,
+        optional: false,
+    }
+}
+
+const fn optional(name: &'static str, ty: &'static str) -> Field {
+    Field {
+        name,
+        ty,
+        optional: true,
+    }
+}
+
+const fn unit(name: &'static str) -> Packet {
+    Packet {
+        name,
+        fields: None,
+        content: None,
+    }
+}
+
+const fn object(name: &'static str, fields: &'static [Field]) -> Packet {
+    Packet {
+        name,
+        fields: Some(fields),
+        content: None,
+    }
+}
+
+const fn newtype(name: &'static str, content: &'static str) -> Packet {
+    Packet {
+        name,
+        fields: None,
+        content: Some(content),
+    }
+}
+
+const SERVERBOUND: &[Packet] = &[
+    object(
+        "Hello",
+        &[
+            field("features", "Feature[]"),
+            optional("echo_own_messages", "boolean | null"),
+            optional("client", "ClientVersion | null"),
+            optional("protocol", "integer | null"),
+        ],
+    ),
+    object("SetEchoOwnMessages", &[field("enabled", "boolean")]),
+    object("SetStatus", &[field("status", "UserStatus")]),
+    unit("RequestMojangInfo"),
+    newtype("LoginMojang", "User"),
+    object(
+        "LoginJWT",
+        &[field("token", "string"), field("allow_messages", "boolean")],
+    ),
+    unit("RequestJWT"),
+    object(
+        "Message",
+        &[
+            field("content", "string"),
+            optional("origin", "string | null"),
+            optional("reply_to", "integer | null"),
+        ],
+    ),
+    object(
+        "PrivateMessage",
+        &[
+            field("receiver", "string"),
+            optional("content", "string"),
+            optional("encrypted", "boolean"),
+            optional("payload", "string"),
+            optional("reply_to", "integer | null"),
+        ],
+    ),
+    object("NotifyWhenOnline", &[field("id", "string")]),
+    object("BanUser", &[field("user", "uuid")]),
+    object("UnbanUser", &[field("user", "uuid")]),
+    object("ConfirmAction", &[field("nonce", "string")]),
+    object("AddBlockedWord", &[field("word", "string")]),
+    object("RemoveBlockedWord", &[field("word", "string")]),
+    object("ListBlockedWords", &[optional("filter", "string | null")]),
+    object("SubscribeModerationEvents", &[field("enabled", "boolean")]),
+    unit("RequestUserCount"),
+    object("LookupUuid", &[field("uuid", "uuid")]),
+    unit("RequestServerInfo"),
+    object("Resume", &[field("token", "string")]),
+    object("ResyncFrom", &[field("seq", "integer")]),
+    unit("RequestEmotes"),
+    unit("RequestDiagnostics"),
+    object("TimeSync", &[field("client_time_ms", "integer")]),
+    object(
+        "RequestAuditLog",
+        &[
+            optional("actor", "uuid | null"),
+            optional("target", "uuid | null"),
+            optional("since", "integer | null"),
+            optional("limit", "integer | null"),
+        ],
+    ),
+    object(
+        "RequestPmMetadata",
+        &[field("uuid", "uuid"), optional("limit", "integer | null")],
+    ),
+    object(
+        "React",
+        &[field("message_id", "integer"), field("emoji", "string")],
+    ),
+    object(
+        "RemoveReaction",
+        &[field("message_id", "integer"), field("emoji", "string")],
+    ),
+];
+
+const CLIENTBOUND: &[Packet] = &[
+    object("MojangInfo", &[field("session_hash", "string")]),
+    object("NewJWT", &[field("token", "string")]),
+    object(
+        "Message",
+        &[
+            field("seq", "integer"),
+            field("author_info", "UserInfo"),
+            optional("author_kind", "AuthorKind"),
+            field("content", "string"),
+            optional("signature", "MessageSignature"),
+            optional("reactions", "map<string, integer>"),
+            optional("reply_to", "integer"),
+            optional("reply_excerpt", "ReplyExcerpt"),
+            optional("flags", "MessageFlag[]"),
+        ],
+    ),
+    object(
+        "PrivateMessage",
+        &[
+            field("author_info", "UserInfo"),
+            field("conversation", "string"),
+            field("id", "integer"),
+            field("timestamp", "integer"),
+            optional("content", "string"),
+            optional("payload", "string"),
+            optional("flags", "MessageFlag[]"),
+        ],
+    ),
+    object(
+        "MessageAck",
+        &[
+            field("seq", "integer"),
+            field("timestamp", "integer"),
+            optional("delivery_count", "integer"),
+        ],
+    ),
+    object(
+        "PrivateMessageAck",
+        &[
+            field("receiver", "string"),
+            field("delivery_count", "integer"),
+        ],
+    ),
+    object("ResumeToken", &[field("token", "string")]),
+    object("BlockedWords", &[field("words", "string[]")]),
+    object("ResyncTooOld", &[field("oldest_available", "integer")]),
+    object("ReplayComplete", &[field("count", "integer")]),
+    object("UserOnline", &[field("id", "string")]),
+    object(
+        "UserRenamed",
+        &[field("old_id", "string"), field("new_id", "string")],
+    ),
+    object(
+        "ReactionUpdate",
+        &[
+            field("message_id", "integer"),
+            field("emoji", "string"),
+            field("user_id", "string"),
+            field("added", "boolean"),
+        ],
+    ),
+    object("Emotes", &[field("emotes", "map<string, string>")]),
+    newtype("UserLookup", "UserLookup"),
+    newtype("Diagnostics", "Diagnostics"),
+    newtype("AuditLog", "AuditPage"),
+    object(
+        "PmMetadata",
+        &[field("uuid", "uuid"), field("entries", "PmMetadataEntry[]")],
+    ),
+    object(
+        "UserCount",
+        &[
+            field("connections", "integer"),
+            field("logged_in", "integer"),
+        ],
+    ),
+    object("Success", &[field("reason", "SuccessReason")]),
+    object(
+        "CommandResult",
+        &[
+            field("success", "boolean"),
+            field("message", "string"),
+            field("translation_key", "string"),
+            field("params", "map<string, string>"),
+        ],
+    ),
+    object(
+        "ConfirmAction",
+        &[field("nonce", "string"), field("summary", "string")],
+    ),
+    object(
+        "MessageFlagged",
+        &[field("author_info", "UserInfo"), field("content", "string")],
+    ),
+    object(
+        "ModerationStatus",
+        &[
+            field("banned", "boolean"),
+            field("muted", "boolean"),
+            optional("expires_at", "integer"),
+            optional("reason", "string"),
+        ],
+    ),
+    object(
+        "ModerationEvent",
+        &[
+            field("kind", "ModerationEventKind"),
+            field("user", "UserInfo"),
+            field("content_excerpt", "string"),
+            field("rule", "string"),
+        ],
+    ),
+    object("UserJoined", &[field("user", "UserInfo")]),
+    object("UserLeft", &[field("user", "UserInfo")]),
+    object(
+        "PresenceDiff",
+        &[field("joined", "UserInfo[]"), field("left", "string[]")],
+    ),
+    newtype("ModerationAction", "AuditEntry"),
+    object(
+        "ProtocolDeprecated",
+        &[field("protocol", "integer"), field("sunset", "integer")],
+    ),
+    object("Motd", &[field("content", "string")]),
+    object(
+        "Disconnected",
+        &[
+            field("reason_code", "string"),
+            field("translation_key", "string"),
+            optional("retry_after_secs", "integer"),
+            optional("reason", "string"),
+        ],
+    ),
+    object("SessionTag", &[field("session_tag", "string")]),
+    object(
+        "TimeSync",
+        &[
+            field("client_time_ms", "integer | null"),
+            field("server_time_ms", "integer"),
+        ],
+    ),
+    object(
+        "SystemMessage",
+        &[
+            field("content", "string"),
+            field("kind", "SystemMessageKind"),
+        ],
+    ),
+    object(
+        "ServerInfo",
+        &[
+            field("version", "string"),
+            field("protocol", "integer"),
+            field("max_message_length", "integer"),
+            field("max_lines", "integer"),
+            field("max_line_length", "integer | null"),
+            field("commands_enabled", "boolean"),
+            field("commit", "string"),
+            field("build_timestamp", "string"),
+            field("uptime_secs", "integer"),
+            field("features", "Feature[]"),
+            field("connections", "integer"),
+            field("max_connections", "integer | null"),
+            field("online_users", "integer"),
+            field("guest_viewers", "integer"),
+        ],
+    ),
+    object(
+        "Error",
+        &[
+            // Errors with details are an object with the name of the error as the only key.
+            field("message", "string | map<string, object>"),
+            field("translation_key", "string"),
+            field("params", "map<string, string>"),
+            optional("repeated", "integer"),
+            optional("rule", "string"),
+        ],
+    ),
+];
+
+const TYPES: &[Type] = &[
+    Type {
+        name: "AuditEntry",
+        fields: &[
+            field("timestamp", "integer"),
+            field("actor", "uuid | null"),
+            field("action", "AuditAction"),
+            field("target", "uuid"),
+            optional("reason", "string"),
+            optional("duration_secs", "integer"),
+        ],
+    },
+    Type {
+        name: "AuditPage",
+        fields: &[field("total", "integer"), field("entries", "AuditEntry[]")],
+    },
+    Type {
+        name: "AuthorKind",
+        fields: &[
+            field("kind", "AuthorKindName"),
+            optional("origin", "string"),
+        ],
+    },
+    Type {
+        name: "ClientVersion",
+        fields: &[field("brand", "string"), field("version", "string")],
+    },
+    Type {
+        name: "Diagnostics",
+        fields: &[
+            field("id", "integer"),
+            field("session_tag", "string"),
+            field("name", "string"),
+            field("uuid", "uuid"),
+            field("features", "Feature[]"),
+            field("echo_own_messages", "boolean"),
+            field("status", "UserStatus"),
+            field("moderator", "boolean"),
+            field("banned", "boolean"),
+            optional("probation_secs", "integer"),
+            optional("join_cooldown_secs", "integer"),
+            optional("pm_metadata_retention_minutes", "integer"),
+            field("rate_limit", "RateLimitDiagnostics"),
+            field("queued_packets", "integer"),
+            field("malformed_packets", "integer"),
+            field("server_time", "integer"),
+        ],
+    },
+    Type {
+        name: "MessageSignature",
+        fields: &[field("timestamp", "integer"), field("signature", "string")],
+    },
+    Type {
+        name: "PmMetadataEntry",
+        fields: &[
+            field("sender", "uuid"),
+            field("receiver", "uuid"),
+            field("timestamp", "integer"),
+            field("length", "integer"),
+        ],
+    },
+    Type {
+        name: "RateLimitDiagnostics",
+        fields: &[
+            field("messages", "integer"),
+            field("max_messages", "integer"),
+            field("window_secs", "integer"),
+        ],
+    },
+    Type {
+        name: "ReplyExcerpt",
+        fields: &[field("author_info", "UserInfo"), field("content", "string")],
+    },
+    Type {
+        name: "User",
+        fields: &[
+            field("name", "string"),
+            field("uuid", "uuid"),
+            field("allow_messages", "boolean"),
+        ],
+    },
+    Type {
+        name: "UserInfo",
+        fields: &[
+            field("name", "string"),
+            field("uuid", "uuid"),
+            optional("bot", "boolean"),
+        ],
+    },
+    Type {
+        name: "UserLookup",
+        fields: &[
+            field("uuid", "uuid"),
+            field("id", "string"),
+            field("online", "boolean"),
+            field("sessions", "integer"),
+            optional("last_seen", "integer"),
+            optional("previous_names", "string[]"),
+            optional("status", "UserStatus"),
+        ],
+    },
+];
+
+const ERRORS: &[&str] = &[
+    keys::NOT_SUPPORTED,
+    keys::LOGIN_FAILED,
+    keys::NOT_LOGGED_IN,
+    keys::ALREADY_LOGGED_IN,
+    keys::TOO_MANY_SESSIONS,
+    keys::HANDSHAKE_REQUIRED,
+    keys::CLIENT_OUTDATED,
+    keys::MOJANG_REQUEST_MISSING,
+    keys::NOT_PERMITTED,
+    keys::NOT_BANNED,
+    keys::NOT_MUTED,
+    keys::NOT_BLOCKED,
+    keys::USER_NOT_FOUND,
+    keys::EMPTY_WORD,
+    keys::BANNED,
+    keys::MUTED,
+    keys::RATE_LIMITED,
+    keys::PROBATION,
+    keys::JOIN_COOLDOWN,
+    keys::PRIVATE_MESSAGE_NOT_ACCEPTED,
+    keys::DELIVERY_FAILED,
+    keys::DO_NOT_DISTURB,
+    keys::TOO_MANY_WATCHES,
+    keys::EMPTY_MESSAGE,
+    keys::MESSAGE_TOO_LONG,
+    keys::INVALID_CHARACTER,
+    keys::TOO_MANY_LINES,
+    keys::LINE_TOO_LONG,
+    keys::CONSECUTIVE_BLANK_LINES,
+    keys::EXCESSIVE_REPETITION,
+    keys::INSUFFICIENT_CONTENT,
+    keys::LINKS_NOT_ALLOWED,
+    keys::BLOCKED_CONTENT,
+    keys::RESUME_FAILED,
+    keys::INVALID_ID,
+    keys::INVALID_REACTION,
+    keys::INVALID_PAYLOAD,
+    keys::MESSAGE_NOT_FOUND,
+    keys::TOO_MANY_REACTIONS,
+    keys::NOT_REACTED,
+    keys::PERSISTENCE_DEGRADED,
+    keys::CONFIRMATION_FAILED,
+    keys::INVALID_IMPORT,
+    keys::MALFORMED_PACKET,
+    keys::INVALID_TOKEN,
+    keys::INTERNAL,
+];
+
+/// Describes the packets of this version of the server.
+pub fn protocol_schema() -> Schema {
+    let enums = vec![
+        Enum {
+            name: "AuditAction",
+            values: vec![
+                "Ban",
+                "BanRequested",
+                "Unban",
+                "Mute",
+                "Unmute",
+                "Kick",
+                "Simulate",
+            ],
+        },
+        Enum {
+            name: "AuthorKindName",
+            values: vec!["Player", "System", "Bridge"],
+        },
+        Enum {
+            name: "Feature",
+            values: Capabilities::ALL.names().collect(),
+        },
+        Enum {
+            name: "MessageFlag",
+            values: flags::NAMES.iter().map(|(name, _)| *name).collect(),
+        },
+        Enum {
+            name: "ModerationEventKind",
+            values: vec![
+                "BlockedWord",
+                "Redacted",
+                "Probation",
+                "RateLimit",
+                "ReviewDenied",
+                "DryRun",
+            ],
+        },
+        Enum {
+            name: "SuccessReason",
+            values: vec![
+                "Login",
+                "Resume",
+                "Ban",
+                "Unban",
+                "BlockWord",
+                "UnblockWord",
+                "SetStatus",
+            ],
+        },
+        Enum {
+            name: "SystemMessageKind",
+            values: vec!["Ban", "Announcement"],
+        },
+        Enum {
+            name: "UserStatus",
+            values: vec!["online", "dnd"],
+        },
+    ];
+
+    Schema {
+        version: version::VERSION,
+        protocol: super::PROTOCOL_VERSION,
+        serverbound: SERVERBOUND,
+        clientbound: CLIENTBOUND,
+        types: TYPES,
+        enums,
+        errors: ERRORS,
+    }
+}
+
+/// The fields of the content of the serverbound packet `name` which can not be omitted,
+/// or `None` if there is no such packet.
+///
+/// Packets without content have no fields; the content of other packets is required itself.
+pub(super) fn required_fields(name: &str) -> Option<Required> {
+    let packet = SERVERBOUND.iter().find(|packet| packet.name == name)?;
+    let fields = match (packet.fields, packet.content) {
+        (Some(fields), _) => fields,
+        (None, Some(content)) => {
+            TYPES
+                .iter()
+                .find(|ty| ty.name == content)
+                .expect("content of a packet is not a type")
+                .fields
+        }
+        (None, None) => return Some(Required::Nothing),
+    };
+    Some(Required::Content(
+        fields
+            .iter()
+            .filter(|field| !field.optional)
+            .map(|field| field.name)
+            .collect(),
+    ))
+}
+
+/// What the content of a serverbound packet has to contain.
+pub(super) enum Required {
+    /// The packet has no content.
+    Nothing,
+    /// The packet has content, with these fields.
+    Content(Vec<&'static str>),
+}
+
+/// Serves the description of the packets as JSON.
+pub(super) fn schema_route() -> HttpResponse {
+    HttpResponse::Ok().json(protocol_schema())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{
+        compat, decode_packet, samples, PacketLimits, ServerPacket, PROTOCOL_VERSION,
+    };
+
+    use serde_json::Value;
+    use std::collections::BTreeSet;
+
+    /// Checks `value` against the type `ty` of a [`Field`].
+    fn check_type(ty: &str, value: &Value) -> std::result::Result<(), String> {
+        if ty.contains(" | ") {
+            return if ty.split(" | ").any(|ty| check_type(ty, value).is_ok()) {
+                Ok(())
+            } else {
+                Err(format!("{} is not {}", value, ty))
+            };
+        }
+        if let Some(item) = ty.strip_suffix("[]") {
+            let items = value
+                .as_array()
+                .ok_or_else(|| format!("{} is not {}", value, ty))?;
+            return items.iter().try_for_each(|value| check_type(item, value));
+        }
+        if let Some(item) = ty
+            .strip_prefix("map<string, ")
+            .and_then(|ty| ty.strip_suffix('>'))
+        {
+            let map = value
+                .as_object()
+                .ok_or_else(|| format!("{} is not {}", value, ty))?;
+            return map.values().try_for_each(|value| check_type(item, value));
+        }
+        let matches = match ty {
+            "null" => value.is_null(),
+            "string" => value.is_string(),
+            "integer" => value.is_u64() || value.is_i64(),
+            "boolean" => value.is_boolean(),
+            "object" => value.is_object(),
+            "uuid" => value
+                .as_str()
+                .is_some_and(|uuid| uuid::Uuid::parse_str(uuid).is_ok()),
+            _ => {
+                if let Some(ty) = TYPES.iter().find(|t| t.name == ty) {
+                    return check_fields(ty.fields, value);
+                }
+                let schema = protocol_schema();
+                let values = &schema
+                    .enums
+                    .iter()
+                    .find(|e| e.name == ty)
+                    .ok_or_else(|| format!("unknown type {}", ty))?
+                    .values;
+                value.as_str().is_some_and(|value| values.contains(&value))
+            }
+        };
+        if matches {
+            Ok(())
+        } else {
+            Err(format!("{} is not {}", value, ty))
+        }
+    }
+
+    /// Checks that `value` has all required of `fields`, and no others.
+    fn check_fields(fields: &[Field], value: &Value) -> std::result::Result<(), String> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| format!("{} is not an object", value))?;
+        for key in object.keys() {
+            if !fields.iter().any(|field| field.name == key) {
+                return Err(format!("{} has the undocumented field {}", value, key));
+            }
+        }
+        for field in fields {
+            match object.get(field.name) {
+                Some(value) => {
+                    check_type(field.ty, value).map_err(|err| format!("{}: {}", field.name, err))?
+                }
+                None if field.optional => {}
+                None => return Err(format!("{} misses the field {}", value, field.name)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks an encoded packet against its description in `packets`.
+    fn check_packet(packets: &[Packet], packet: &Value) -> std::result::Result<(), String> {
+        let name = packet["m"].as_str().ok_or("packet without name")?;
+        let description = packets
+            .iter()
+            .find(|packet| packet.name == name)
+            .ok_or_else(|| format!("undocumented packet {}", name))?;
+        let keys = packet.as_object().unwrap().len();
+        match (description.fields, description.content) {
+            (Some(fields), _) => check_fields(fields, &packet["c"]),
+            (None, Some(content)) => check_type(content, &packet["c"]),
+            (None, None) if keys == 1 => Ok(()),
+            (None, None) => Err(format!("{} has content", name)),
+        }
+        .map_err(|err| format!("{}: {}", name, err))
+    }
+
+    fn names(packets: &[Packet]) -> BTreeSet<&'static str> {
+        packets.iter().map(|packet| packet.name).collect()
+    }
+
+    #[test]
+    fn every_clientbound_packet_is_described() {
+        let mut sampled = BTreeSet::new();
+        for packet in samples::client_packets() {
+            let encoded = compat::encode(&packet, PROTOCOL_VERSION, None).unwrap();
+            let value: Value = serde_json::from_str(&encoded).unwrap();
+            check_packet(CLIENTBOUND, &value).unwrap();
+            sampled.insert(value["m"].as_str().unwrap().to_string());
+        }
+        let described: BTreeSet<_> = names(CLIENTBOUND).into_iter().map(str::to_string).collect();
+        assert_eq!(sampled, described);
+    }
+
+    #[test]
+    fn every_serverbound_packet_is_described_and_decoded() {
+        let limits = PacketLimits {
+            max_size: usize::MAX,
+            max_depth: usize::MAX,
+            max_malformed: 0,
+        };
+        let mut sampled = BTreeSet::new();
+        for packet in samples::server_packets() {
+            let encoded = serde_json::to_string(&packet).unwrap();
+            let value: Value = serde_json::from_str(&encoded).unwrap();
+            check_packet(SERVERBOUND, &value).unwrap();
+
+            let decoded: ServerPacket = decode_packet(&encoded, &limits).unwrap();
+            assert_eq!(serde_json::to_string(&decoded).unwrap(), encoded);
+            sampled.insert(value["m"].as_str().unwrap().to_string());
+        }
+        let described: BTreeSet<_> = names(SERVERBOUND).into_iter().map(str::to_string).collect();
+        assert_eq!(sampled, described);
+    }
+
+    #[test]
+    fn every_type_is_defined() {
+        let mut types = Vec::new();
+        for packet in SERVERBOUND.iter().chain(CLIENTBOUND) {
+            types.extend(packet.fields.unwrap_or(&[]).iter().map(|field| field.ty));
+            types.extend(packet.content);
+        }
+        types.extend(
+            TYPES
+                .iter()
+                .flat_map(|ty| ty.fields.iter().map(|field| field.ty)),
+        );
+        for ty in types {
+            assert!(is_known(ty), "unknown type {}", ty);
+        }
+    }
+
+    fn is_known(ty: &str) -> bool {
+        if ty.contains(" | ") {
+            return ty.split(" | ").all(is_known);
+        }
+        if let Some(item) = ty.strip_suffix("[]") {
+            return is_known(item);
+        }
+        if let Some(item) = ty
+            .strip_prefix("map<string, ")
+            .and_then(|ty| ty.strip_suffix('>'))
+        {
+            return is_known(item);
+        }
+        ["null", "string", "integer", "boolean", "object", "uuid"].contains(&ty)
+            || TYPES.iter().any(|t| t.name == ty)
+            || protocol_schema().enums.iter().any(|e| e.name == ty)
+    }
+}
@@ -1,21 +1,296 @@
 use super::{
-    connect::Connect, ChatServer, ClientPacket, Disconnect, InternalId, ServerPacket,
-    ServerPacketId,
+    backlog::Backlog,
+    close::{Close, DisconnectReason},
+    compat::EncodedPacket,
+    connect::Connect,
+    decode::PacketLimits,
+    handler::ReplayChunk,
+    limit::ConnectionGuard,
+    outgoing::{OutgoingQueue, Queued},
+    protocol::{Conditions, Input, Output, SessionProtocol},
+    trace::{self, TracedPacket},
+    ChatServer, ClientPacket, Disconnect, InternalId, ServerPacketId, TraceId,
 };
 
+use crate::error::*;
 use log::*;
 
 use actix::*;
 use actix_web_actors::ws;
+use futures::{sync::oneshot, Future};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
+/// The time in which identical errors are coalesced.
+const ERROR_WINDOW: Duration = Duration::from_secs(2);
+/// The number of identical errors sent in a window before further ones are suppressed.
+const MAX_REPEATED_ERRORS: u32 = 3;
+/// The number of queued packets written at once, before newer packets are queued.
+const DRAIN_BATCH: usize = 64;
+
+/// Whether clients have to send `Hello` before any other packet.
+#[derive(Debug, Clone, Copy)]
+pub struct HandshakePolicy {
+    /// Set if `Hello` is required; connections which did not send it are closed after `timeout`.
+    pub timeout: Option<Duration>,
+}
+
+/// The errors recently sent to a client.
+///
+/// Errors of the same kind as the last one are suppressed after [`MAX_REPEATED_ERRORS`],
+/// until its window closes and a single summary is sent.
+#[derive(Default)]
+struct RecentErrors {
+    /// The last error and when its window started.
+    last: Option<(ClientError, Instant)>,
+    sent: u32,
+    suppressed: u32,
+    /// Identifies the current window, so flushes scheduled for earlier ones are ignored.
+    window: u64,
+}
+
+/// Drives the [`SessionProtocol`] of a websocket connection: it hands the frames of the client to it,
+/// carries out what it decides and writes the packets for the client.
 pub struct Session {
     id: InternalId,
     addr: Addr<ChatServer>,
+    /// Keeps this connection counted while the session exists.
+    guard: ConnectionGuard,
+    /// The address the client connected from, if it is known.
+    ip: Option<IpAddr>,
+    backlog: Arc<Backlog>,
+    handshake_policy: HandshakePolicy,
+    protocol: SessionProtocol,
+    errors: RecentErrors,
+    outgoing: OutgoingQueue,
+    /// Whether a [`Drain`] is scheduled.
+    draining: bool,
+    /// Replays waiting for the queued bulk packets to be written.
+    replay_waiters: Vec<oneshot::Sender<()>>,
+    /// Whether the client closed the connection itself.
+    logout: bool,
+    /// Why the server closed the connection, if it did.
+    close_reason: Option<DisconnectReason>,
+    /// The number of packets received from the client, which numbers their traces.
+    received_packets: u32,
+    /// The trace of the packet which is being handled.
+    trace: Option<TraceId>,
 }
 
 impl Session {
-    pub fn new(id: InternalId, addr: Addr<ChatServer>) -> Session {
-        Session { id, addr }
+    /// `subprotocol` is the version of the protocol negotiated in the handshake, if any.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        id: InternalId,
+        addr: Addr<ChatServer>,
+        guard: ConnectionGuard,
+        ip: Option<IpAddr>,
+        backlog: Arc<Backlog>,
+        handshake_policy: HandshakePolicy,
+        packet_limits: PacketLimits,
+        subprotocol: Option<u32>,
+    ) -> Session {
+        Session {
+            id,
+            addr,
+            guard,
+            ip,
+            backlog,
+            handshake_policy,
+            protocol: SessionProtocol::new(
+                handshake_policy.timeout.is_some(),
+                packet_limits,
+                subprotocol,
+            ),
+            errors: RecentErrors::default(),
+            outgoing: OutgoingQueue::default(),
+            draining: false,
+            replay_waiters: Vec::new(),
+            logout: false,
+            close_reason: None,
+            received_packets: 0,
+            trace: None,
+        }
+    }
+
+    /// Queues `packet` for the client, with the trace of the packet being handled if it responds to it.
+    fn send(&mut self, packet: ClientPacket, ctx: &mut ws::WebsocketContext<Self>) {
+        let trace = self
+            .trace
+            .filter(|_| self.protocol.traces() && packet.is_response());
+        self.send_traced(packet, trace, ctx);
+    }
+
+    /// Queues `packet` with `trace`, unless it is a repeated error which is suppressed.
+    ///
+    /// Acknowledgements of successful packets end the suppression of errors.
+    fn send_traced(
+        &mut self,
+        packet: ClientPacket,
+        trace: Option<TraceId>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        self.run(Input::Outgoing(&packet), ctx);
+        match &packet {
+            ClientPacket::Error { message } | ClientPacket::Rejected { message, .. } => {
+                let now = Instant::now();
+                let window_start = match &self.errors.last {
+                    Some((last, start))
+                        if last.translation_key() == message.translation_key()
+                            && now.duration_since(*start) < ERROR_WINDOW =>
+                    {
+                        Some(*start)
+                    }
+                    _ => None,
+                };
+                match window_start {
+                    Some(_) if self.errors.sent < MAX_REPEATED_ERRORS => {
+                        self.errors.sent += 1;
+                    }
+                    Some(start) => {
+                        if self.errors.suppressed == 0 {
+                            let window = self.errors.window;
+                            ctx.run_later(start + ERROR_WINDOW - now, move |actor, ctx| {
+                                if actor.errors.window == window {
+                                    actor.flush_errors(ctx);
+                                }
+                            });
+                        }
+                        self.errors.suppressed += 1;
+                        self.errors.last = Some((message.clone(), start));
+                        return;
+                    }
+                    None => {
+                        self.flush_errors(ctx);
+                        self.errors.window += 1;
+                        self.errors.last = Some((message.clone(), now));
+                        self.errors.sent = 1;
+                    }
+                }
+            }
+            ClientPacket::Success { .. }
+            | ClientPacket::MessageAck { .. }
+            | ClientPacket::PrivateMessageAck { .. } => {
+                self.flush_errors(ctx);
+                self.errors.last = None;
+            }
+            _ => {}
+        }
+        self.enqueue(Queued::Packet(packet, trace), ctx);
+    }
+
+    /// Queues a packet and schedules writing the queue to the connection.
+    ///
+    /// The connection is closed if the client does not keep up with the queue.
+    fn enqueue(&mut self, queued: Queued, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.outgoing.push(queued).is_err() {
+            // Packets queued until the close is handled are dropped as well.
+            debug!("Outgoing queue of connection `{}` is full.", self.id);
+            ctx.notify(Close(DisconnectReason::SlowConsumer));
+            return;
+        }
+        if !self.draining {
+            self.draining = true;
+            ctx.notify(Drain);
+        }
+    }
+
+    /// Writes at most `max` queued packets to the connection, highest priority first.
+    fn drain(&mut self, max: usize, ctx: &mut ws::WebsocketContext<Self>) {
+        for _ in 0..max {
+            match self.outgoing.pop() {
+                Some(queued) => match queued.encode(self.protocol.protocol()) {
+                    Some(msg) => ctx.text(msg),
+                    None => debug!(
+                        "Dropped packet for `{}`, which version {} can not express.",
+                        self.id,
+                        self.protocol.protocol()
+                    ),
+                },
+                None => break,
+            }
+        }
+        if self.outgoing.is_bulk_empty() {
+            for waiter in self.replay_waiters.drain(..) {
+                waiter.send(()).ok();
+            }
+        }
+    }
+
+    /// Sends the summary of the errors suppressed in the current window.
+    fn flush_errors(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.errors.suppressed == 0 {
+            return;
+        }
+        if let Some((message, _)) = &self.errors.last {
+            debug!(
+                "Suppressed {} errors for connection `{}`.",
+                self.errors.suppressed, self.id
+            );
+            let packet = ClientPacket::RepeatedError {
+                message: message.clone(),
+                repeated: self.errors.suppressed,
+            };
+            self.enqueue(Queued::Packet(packet, None), ctx);
+        }
+        self.errors.suppressed = 0;
+    }
+
+    /// Hands `input` to the protocol state and carries out what it decides.
+    fn run(&mut self, input: Input<'_>, ctx: &mut ws::WebsocketContext<Self>) {
+        let conditions = Conditions {
+            now: Instant::now(),
+            system_now: SystemTime::now(),
+            shedding: self.backlog.shedding(),
+        };
+        for output in self.protocol.handle(input, conditions) {
+            match output {
+                Output::Forward(packet) => {
+                    let trace = self
+                        .trace
+                        .expect("only packets of the client are forwarded");
+                    self.addr
+                        .send(ServerPacketId {
+                            user_id: self.id,
+                            packet,
+                            trace,
+                            _pending: self.backlog.track(),
+                        })
+                        .into_actor(self)
+                        .map_err(|err, _actor, _ctx| {
+                            warn!("Could not decode packet: {}", err);
+                        })
+                        .spawn(ctx);
+                }
+                Output::Reply(packet) => self.send(packet, ctx),
+                Output::Close(reason) => self.close(reason, ctx),
+            }
+        }
+    }
+
+    /// Hands a packet of the client to the protocol state,
+    /// with a new trace which is current while it is handled.
+    fn handle_text(&mut self, msg: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        self.received_packets += 1;
+        let trace = TraceId::new(self.id, self.received_packets);
+        let _entered = trace::enter(trace);
+        self.trace = Some(trace);
+        debug!("Received text message of {} bytes", msg.len());
+        self.run(Input::Text(msg), ctx);
+        self.trace = None;
+    }
+
+    /// Sends a close frame for `reason` and stops the session.
+    ///
+    /// Every closed connection goes through this, once the protocol state decided to close it.
+    fn close(&mut self, reason: DisconnectReason, ctx: &mut ws::WebsocketContext<Self>) {
+        info!("Closing connection `{}`: {}", self.id, reason);
+        self.close_reason = Some(reason);
+        let queued = self.outgoing.len();
+        self.drain(queued, ctx);
+        ctx.close(Some(reason.into()));
+        ctx.stop();
     }
 }
 
@@ -24,72 +299,166 @@ impl Actor for Session {
 
     fn started(&mut self, ctx: &mut Self::Context) {
         self.addr
-            .send(Connect::new(ctx.address().recipient()))
+            .send(Connect::new(
+                ctx.address().recipient(),
+                ctx.address().recipient(),
+                ctx.address().recipient(),
+                ctx.address().recipient(),
+                ctx.address().recipient(),
+                self.guard.reserved,
+                self.ip,
+                self.protocol.subprotocol(),
+                self.backlog.track(),
+            ))
             .into_actor(self)
-            .then(|res, actor, _ctx| {
+            .then(|res, actor, ctx| {
                 match res {
-                    Ok(id) => {
+                    Ok(Ok(id)) => {
                         actor.id = id;
                     }
+                    Ok(Err(reason)) => actor.run(Input::Close(reason), ctx),
                     Err(err) => {
                         warn!("Could not accept connection: {}", err);
+                        actor.run(Input::Close(DisconnectReason::Internal), ctx);
                     }
                 }
                 fut::ok(())
             })
-            .spawn(ctx)
+            // Frames are only handled after the id is known, so they are not sent as `c0`.
+            .wait(ctx);
+
+        if let Some(timeout) = self.handshake_policy.timeout {
+            ctx.run_later(timeout, |actor, ctx| {
+                actor.run(Input::HandshakeTimeout, ctx)
+            });
+        }
     }
 
     fn stopping(&mut self, _ctx: &mut Self::Context) -> Running {
-        self.addr.do_send(Disconnect { id: self.id });
+        self.addr.do_send(Disconnect {
+            id: self.id,
+            logout: self.logout,
+            reason: self.close_reason,
+            _pending: self.backlog.track(),
+        });
         Running::Stop
     }
 }
 
 impl StreamHandler<ws::Message, ws::ProtocolError> for Session {
     fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
-        debug!("Received message {:?}", msg);
+        // Packets are not logged, since they may contain encrypted private messages.
+        if !matches!(msg, ws::Message::Text(_)) {
+            debug!("Received message {:?}", msg);
+        }
         match msg {
             ws::Message::Ping(msg) => ctx.pong(&msg),
             ws::Message::Pong(_msg) => {}
-            ws::Message::Text(msg) => match serde_json::from_slice::<ServerPacket>(msg.as_ref()) {
-                Ok(packet) => self
-                    .addr
-                    .send(ServerPacketId {
-                        user_id: self.id,
-                        packet,
-                    })
-                    .into_actor(self)
-                    .map_err(|err, _actor, _ctx| {
-                        warn!("Could not decode packet: {}", err);
-                    })
-                    .spawn(ctx),
-                Err(err) => {
-                    warn!("Could not decode packet: {}", err);
-                }
-            },
-            ws::Message::Binary(_msg) => {
-                warn!("Can't decode binary messages.");
-            }
+            ws::Message::Text(msg) => self.handle_text(&msg, ctx),
+            ws::Message::Binary(_msg) => self.run(Input::Binary, ctx),
             ws::Message::Nop => {}
             ws::Message::Close(Some(reason)) => {
                 info!(
                     "Connection `{}` closed; code: {:?}, reason: {:?}",
                     self.id, reason.code, reason.description
                 );
+                self.logout = true;
+                self.run(Input::Close(DisconnectReason::ClientClosed), ctx);
             }
             ws::Message::Close(None) => {
                 info!("Connection `{}` closed.", self.id);
+                self.logout = true;
+                self.run(Input::Close(DisconnectReason::ClientClosed), ctx);
             }
         }
     }
+
+    fn error(&mut self, err: ws::ProtocolError, ctx: &mut Self::Context) -> Running {
+        warn!("Websocket error on connection `{}`: {}", self.id, err);
+        let reason = match err {
+            ws::ProtocolError::Overflow => DisconnectReason::FrameTooLarge,
+            ws::ProtocolError::BadEncoding => DisconnectReason::InvalidPayload,
+            // The websocket codec does not reassemble fragmented messages.
+            ws::ProtocolError::NoContinuation => {
+                debug!("Connection `{}` sent a fragmented message.", self.id);
+                DisconnectReason::ProtocolError
+            }
+            // The connection itself is broken, so no close frame can be sent.
+            ws::ProtocolError::Io(_) => return Running::Stop,
+            _ => DisconnectReason::ProtocolError,
+        };
+        self.run(Input::Close(reason), ctx);
+        Running::Stop
+    }
+}
+
+impl Handler<Close> for Session {
+    type Result = ();
+
+    fn handle(&mut self, msg: Close, ctx: &mut Self::Context) {
+        self.run(Input::Close(msg.0), ctx);
+    }
+}
+
+/// Writes the next packets of the outgoing queue.
+struct Drain;
+
+impl Message for Drain {
+    type Result = ();
+}
+
+impl Handler<Drain> for Session {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Drain, ctx: &mut Self::Context) {
+        self.drain(DRAIN_BATCH, ctx);
+        if self.outgoing.len() > 0 {
+            ctx.notify(Drain);
+        } else {
+            self.draining = false;
+        }
+    }
+}
+
+impl Handler<ReplayChunk> for Session {
+    type Result = Response<(), ()>;
+
+    /// Queues the messages and responds once they and every bulk packet before them were written.
+    fn handle(&mut self, msg: ReplayChunk, ctx: &mut Self::Context) -> Self::Result {
+        for packet in msg.0 {
+            self.send(packet, ctx);
+        }
+        let (tx, rx) = oneshot::channel();
+        self.replay_waiters.push(tx);
+        Response::fut(rx.map_err(|_| ()))
+    }
+}
+
+impl Handler<TracedPacket> for Session {
+    type Result = ();
+
+    fn handle(&mut self, msg: TracedPacket, ctx: &mut Self::Context) {
+        self.send_traced(msg.packet, Some(msg.trace), ctx);
+    }
+}
+
+impl Handler<EncodedPacket> for Session {
+    type Result = ();
+
+    fn handle(&mut self, msg: EncodedPacket, ctx: &mut Self::Context) {
+        self.run(Input::Outgoing(&msg.packet), ctx);
+        self.enqueue(Queued::Encoded(msg), ctx);
+    }
 }
 
 impl Handler<ClientPacket> for Session {
     type Result = ();
 
-    fn handle(&mut self, msg: ClientPacket, ctx: &mut Self::Context) {
-        let msg = serde_json::to_string(&msg).expect("could not encode message");
-        ctx.text(msg);
+    fn handle(&mut self, mut msg: ClientPacket, ctx: &mut Self::Context) {
+        if let ClientPacket::Diagnostics(diagnostics) = &mut msg {
+            diagnostics.queued_packets = self.outgoing.len();
+            diagnostics.malformed_packets = self.protocol.malformed_packets();
+        }
+        self.send(msg, ctx);
     }
 }
@@ -0,0 +1,181 @@
+//! The connections of this instance and the indexes of the logged in ones.
+//!
+//! The indexes by name and by uuid are only changed together with the connections,
+//! so they never contain connections which closed or did not log in.
+
+use super::{CanonicalId, InternalId, SessionState, User, UserSession, UserStatus};
+use crate::message::RateLimiter;
+
+use std::collections::{hash_map, HashMap, HashSet, VecDeque};
+use std::ops::Index;
+use uuid::Uuid;
+
+#[derive(Default)]
+pub(super) struct Sessions {
+    connections: HashMap<InternalId, SessionState>,
+    users: HashMap<CanonicalId, UserSession>,
+    /// The logged in connections of each user, by uuid.
+    by_uuid: HashMap<Uuid, HashSet<InternalId>>,
+    /// The number of logged in connections.
+    logged_in: usize,
+}
+
+/// A connection removed by [`Sessions::remove`].
+pub(super) struct Removed {
+    pub session: SessionState,
+    /// Whether it was the last session of its user on this instance.
+    pub last_session: bool,
+}
+
+impl Sessions {
+    /// Adds a new connection, which is not logged in yet.
+    pub fn insert(&mut self, id: InternalId, session: SessionState) {
+        debug_assert!(session.user.is_none(), "new connections are not logged in");
+        self.connections.insert(id, session);
+    }
+
+    /// Marks the connection `id` as logged in as `user` and adds it to the indexes.
+    ///
+    /// `rate_limiter` is only called if it is the first session of the user.
+    /// Returns `None` if the connection does not exist.
+    pub fn insert_login(
+        &mut self,
+        id: InternalId,
+        user: User,
+        rate_limiter: impl FnOnce() -> RateLimiter,
+    ) -> Option<&mut SessionState> {
+        let session = self.connections.get_mut(&id)?;
+        debug_assert!(session.user.is_none(), "connections only log in once");
+        self.users
+            .entry(user.name.canonical())
+            .or_insert_with(|| UserSession {
+                rate_limiter: rate_limiter(),
+                rate_limit_violations: 0,
+                reaction_times: VecDeque::new(),
+                connections: HashSet::new(),
+                status: UserStatus::Online,
+            })
+            .connections
+            .insert(id);
+        self.by_uuid.entry(user.uuid).or_default().insert(id);
+        self.logged_in += 1;
+        session.user = Some(user);
+        Some(session)
+    }
+
+    /// Removes the closed connection `id` and, if it was logged in, its entries in the indexes.
+    ///
+    /// The state of the user is dropped with their last session.
+    pub fn remove(&mut self, id: InternalId) -> Option<Removed> {
+        let session = self.connections.remove(&id)?;
+        let mut last_session = false;
+        if let Some(user) = &session.user {
+            self.logged_in -= 1;
+            let name = user.name.canonical();
+            if let hash_map::Entry::Occupied(mut entry) = self.users.entry(name) {
+                entry.get_mut().connections.remove(&id);
+                if entry.get().connections.is_empty() {
+                    entry.remove();
+                    last_session = true;
+                }
+            }
+            if let hash_map::Entry::Occupied(mut entry) = self.by_uuid.entry(user.uuid) {
+                entry.get_mut().remove(&id);
+                if entry.get().is_empty() {
+                    entry.remove();
+                }
+            }
+        }
+        Some(Removed {
+            session,
+            last_session,
+        })
+    }
+
+    pub fn get(&self, id: &InternalId) -> Option<&SessionState> {
+        self.connections.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &InternalId) -> Option<&mut SessionState> {
+        self.connections.get_mut(id)
+    }
+
+    pub fn iter(&self) -> hash_map::Iter<'_, InternalId, SessionState> {
+        self.connections.iter()
+    }
+
+    pub fn values(&self) -> hash_map::Values<'_, InternalId, SessionState> {
+        self.connections.values()
+    }
+
+    /// The number of connections, including those which did not log in.
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// The number of connections which did not log in.
+    pub fn guest_count(&self) -> usize {
+        self.connections.len() - self.logged_in
+    }
+
+    pub fn user(&self, name: &CanonicalId) -> Option<&UserSession> {
+        self.users.get(name)
+    }
+
+    /// The logged in connection `id` and the state of its user.
+    pub fn user_of_mut(&mut self, id: &InternalId) -> Option<(&SessionState, &mut UserSession)> {
+        let session = self.connections.get(id)?;
+        let user = self
+            .users
+            .get_mut(&session.user.as_ref()?.name.canonical())?;
+        Some((session, user))
+    }
+
+    /// Returns whether a connection is logged in as `name`.
+    pub fn is_online(&self, name: &CanonicalId) -> bool {
+        self.users.contains_key(name)
+    }
+
+    /// The names of the users with at least one logged in connection.
+    pub fn user_names(&self) -> hash_map::Keys<'_, CanonicalId, UserSession> {
+        self.users.keys()
+    }
+
+    /// The number of users with at least one logged in connection.
+    pub fn user_count(&self) -> usize {
+        self.users.len()
+    }
+
+    /// The logged in connections of `name` and the users they are logged in as.
+    pub fn sessions_of<'a>(
+        &'a self,
+        name: &CanonicalId,
+    ) -> impl Iterator<Item = (InternalId, &'a SessionState, &'a User)> + 'a {
+        self.users
+            .get(name)
+            .into_iter()
+            .flat_map(|user| &user.connections)
+            .filter_map(move |id| {
+                let session = self.connections.get(id)?;
+                Some((*id, session, session.user.as_ref()?))
+            })
+    }
+
+    /// The ids of the connections logged in as `uuid`.
+    pub fn sessions_for_uuid<'a>(&'a self, uuid: &Uuid) -> impl Iterator<Item = InternalId> + 'a {
+        self.by_uuid.get(uuid).into_iter().flatten().copied()
+    }
+
+    /// The uuids with at least one logged in connection.
+    pub fn online_uuids(&self) -> hash_map::Keys<'_, Uuid, HashSet<InternalId>> {
+        self.by_uuid.keys()
+    }
+}
+
+impl Index<&InternalId> for Sessions {
+    type Output = SessionState;
+
+    fn index(&self, id: &InternalId) -> &SessionState {
+        &self.connections[id]
+    }
+}
@@ -0,0 +1,259 @@
+//! Running a packet through the handlers for a made up connection,
+//! to reproduce the problems users report without building a client.
+//!
+//! The simulated connection only exists while its packet is handled.
+//! Meanwhile the connections, the history, the cluster and the firehose observers of the server
+//! are put aside, so no other client receives anything the packet caused,
+//! and the packets sent to the connection are captured instead of being written anywhere.
+//! Hooks still filter messages, but are not notified of them,
+//! and messages are not sent for review.
+
+use super::{
+    close::Close,
+    cluster::Cluster,
+    compat::{self, EncodedPacket},
+    firehose::ObserverState,
+    handler::ReplayChunk,
+    history::History,
+    sessions::Sessions,
+    trace::{self, TracedPacket},
+    Capabilities, ChatServer, ClientPacket, DisplayName, InternalId, Origin, ServerPacket,
+    SessionState, TraceId, User, PROTOCOL_VERSION,
+};
+use crate::error::*;
+use crate::message::RateLimiter;
+use crate::storage::AuditAction;
+use log::*;
+
+use actix::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use uuid::Uuid;
+
+/// Who a simulated packet is sent by. The connection is logged in as this user.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SimulatedIdentity {
+    pub name: String,
+    pub uuid: Uuid,
+    /// The features the connection supports, like those sent with `Hello`.
+    #[serde(default)]
+    pub capabilities: Capabilities,
+    #[serde(default = "default_allow_messages")]
+    pub allow_messages: bool,
+}
+
+fn default_allow_messages() -> bool {
+    true
+}
+
+/// What a simulated connection received while its packet was handled.
+#[derive(Debug, Default, Serialize)]
+pub struct Simulation {
+    /// The packets, as they would have been written to the connection.
+    pub packets: Vec<Value>,
+    /// Why the server would have closed the connection, if it would have.
+    pub closed: Option<&'static str>,
+}
+
+/// Stands in for the session of a simulated connection and keeps what it receives.
+#[derive(Default)]
+pub(super) struct Capture(Simulation);
+
+impl Capture {
+    fn record(&mut self, packet: &ClientPacket, trace: Option<TraceId>) {
+        let encoded = compat::encode(packet, PROTOCOL_VERSION, trace)
+            .expect("current protocol can encode every packet");
+        let value = serde_json::from_str(&encoded).expect("could not decode encoded packet");
+        self.0.packets.push(value);
+    }
+}
+
+impl Actor for Capture {
+    type Context = Context<Self>;
+}
+
+impl Handler<ClientPacket> for Capture {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClientPacket, _ctx: &mut Context<Self>) {
+        self.record(&msg, None);
+    }
+}
+
+impl Handler<TracedPacket> for Capture {
+    type Result = ();
+
+    fn handle(&mut self, msg: TracedPacket, _ctx: &mut Context<Self>) {
+        self.record(&msg.packet, Some(msg.trace));
+    }
+}
+
+impl Handler<EncodedPacket> for Capture {
+    type Result = ();
+
+    fn handle(&mut self, msg: EncodedPacket, _ctx: &mut Context<Self>) {
+        self.record(&msg.packet, None);
+    }
+}
+
+impl Handler<ReplayChunk> for Capture {
+    type Result = std::result::Result<(), ()>;
+
+    fn handle(&mut self, msg: ReplayChunk, _ctx: &mut Context<Self>) -> Self::Result {
+        for packet in &msg.0 {
+            self.record(packet, None);
+        }
+        Ok(())
+    }
+}
+
+impl Handler<Close> for Capture {
+    type Result = ();
+
+    fn handle(&mut self, msg: Close, _ctx: &mut Context<Self>) {
+        self.0.closed = Some(msg.0.label());
+    }
+}
+
+/// Takes what was captured. It is handled after every packet sent before it.
+pub(super) struct Collect;
+
+impl Message for Collect {
+    type Result = Simulation;
+}
+
+impl Handler<Collect> for Capture {
+    type Result = MessageResult<Collect>;
+
+    fn handle(&mut self, _msg: Collect, ctx: &mut Context<Self>) -> Self::Result {
+        ctx.stop();
+        MessageResult(mem::take(&mut self.0))
+    }
+}
+
+/// The state of the server which is put aside during a simulation.
+struct PutAside {
+    sessions: Sessions,
+    history: History,
+    cluster: Option<Cluster>,
+    observers: HashMap<InternalId, ObserverState>,
+}
+
+impl ChatServer {
+    /// Handles `packet` as if a connection logged in as `identity` sent it,
+    /// and returns the actor which captured the packets sent to that connection.
+    ///
+    /// Packets which log in or complete later, like `LoginJWT` or `ResyncFrom`,
+    /// can not be simulated, and neither can packets of moderators,
+    /// so a simulation never changes the moderation state.
+    pub(super) fn simulate(
+        &mut self,
+        identity: SimulatedIdentity,
+        packet: ServerPacket,
+        ctx: &mut Context<Self>,
+    ) -> std::result::Result<Addr<Capture>, ClientError> {
+        match packet {
+            ServerPacket::LoginMojang(_)
+            | ServerPacket::LoginJWT { .. }
+            | ServerPacket::Resume { .. }
+            | ServerPacket::ResyncFrom { .. } => return Err(ClientError::NotSupported),
+            _ => {}
+        }
+        if self.is_moderator(&identity.uuid) {
+            return Err(ClientError::NotPermitted);
+        }
+
+        let name = packet_name(&packet);
+        let id = self.next_internal_id(Origin::Simulated);
+        info!(
+            "Simulating `{}` of `{}` as connection `{}`.",
+            name, identity.name, id
+        );
+
+        let capture = Capture::default().start();
+        let aside = self.put_aside();
+        self.sessions.insert(
+            id,
+            SessionState {
+                addr: capture.clone().recipient(),
+                close: capture.clone().recipient(),
+                replay: capture.clone().recipient(),
+                traced: capture.clone().recipient(),
+                encoded: capture.clone().recipient(),
+                session_hash: None,
+                user: None,
+                resume_token: None,
+                capabilities: identity.capabilities,
+                echo_own_messages: true,
+                moderation_events: None,
+                confirmations: HashMap::new(),
+                watching: HashSet::new(),
+                reserved: false,
+                cooldown_since: None,
+                failed_sends: Cell::new(0),
+                last_diagnostics: None,
+                protocol: PROTOCOL_VERSION,
+                ip: None,
+            },
+        );
+        let rate_limit = self.rate_limit_config(&identity.uuid);
+        let user = User {
+            name: DisplayName::new(identity.name),
+            uuid: identity.uuid,
+            allow_messages: identity.allow_messages,
+        };
+        self.sessions
+            .insert_login(id, user, || RateLimiter::new(rate_limit))
+            .expect("could not find connection");
+
+        {
+            let _entered = trace::enter(TraceId::new(id, 1));
+            self.handle_packet(id, packet, ctx);
+        }
+
+        let watching = self
+            .sessions
+            .remove(id)
+            .map(|removed| removed.session.watching)
+            .unwrap_or_default();
+        self.restore(aside);
+        self.drop_watches(id, &watching);
+        self.record_audit(None, AuditAction::Simulate, identity.uuid, Some(name), None);
+        Ok(capture)
+    }
+
+    fn put_aside(&mut self) -> PutAside {
+        self.simulating = true;
+        PutAside {
+            sessions: mem::take(&mut self.sessions),
+            history: mem::replace(
+                &mut self.history,
+                History::new(self.config.message.history_size),
+            ),
+            cluster: self.cluster.take(),
+            observers: mem::take(&mut self.observers),
+        }
+    }
+
+    fn restore(&mut self, aside: PutAside) {
+        self.sessions = aside.sessions;
+        self.history = aside.history;
+        self.cluster = aside.cluster;
+        self.observers = aside.observers;
+        self.simulating = false;
+    }
+}
+
+/// The name of `packet`, like `Message`.
+fn packet_name(packet: &ServerPacket) -> String {
+    match serde_json::to_value(packet) {
+        Ok(Value::Object(mut object)) => match object.remove("m") {
+            Some(Value::String(name)) => name,
+            _ => String::new(),
+        },
+        _ => String::new(),
+    }
+}
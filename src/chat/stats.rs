@@ -0,0 +1,400 @@
+//! Long-term statistics of the message volume, kept in the [`Storage`](crate::storage::Storage)
+//! so operators can graph months of activity without running Prometheus.
+//!
+//! Messages and the peak of open connections are counted with atomics,
+//! which the statistics task takes every `stats.flush_interval` and adds to the buckets
+//! of the current hour and day. Both buckets are written to the storage on every flush,
+//! and a bucket is closed by the first flush after it ended, which adds the counts since the
+//! previous flush to it. Active users are told apart by their uuid, up to [`MAX_ACTIVE_USERS`] per bucket.
+//!
+//! The messages of the last hour are also counted per minute, even if the statistics are disabled,
+//! for the status page.
+
+use super::{cluster, ChatServer};
+use crate::storage::{StatsBucket, StatsResolution};
+use log::*;
+
+use actix::*;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use uuid::Uuid;
+
+/// The number of users told apart in one bucket.
+/// Buckets with more active users only report that there were at least this many.
+const MAX_ACTIVE_USERS: usize = 10_000;
+
+/// The maximum number of buckets returned by one request to `/api/v1/stats`.
+const MAX_STATS_BUCKETS: usize = 2000;
+
+/// The time covered by a request to `/api/v1/stats` without `from`.
+const DEFAULT_STATS_SPAN: u64 = 7 * 24 * 60 * 60 * 1000;
+
+/// The length of a slot of the recent messages, in milliseconds.
+const MINUTE: u64 = 60 * 1000;
+
+/// The number of slots of the recent messages, which cover an hour.
+const RECENT_MINUTES: u64 = 60;
+
+const RESOLUTIONS: [StatsResolution; 2] = [StatsResolution::Hour, StatsResolution::Day];
+
+/// The counters of the current flush interval.
+#[derive(Default)]
+struct Counters {
+    messages: AtomicU64,
+    peak_connections: AtomicU64,
+}
+
+/// The bucket of the current hour or day.
+struct Period {
+    resolution: StatsResolution,
+    bucket: StatsBucket,
+    users: HashSet<Uuid>,
+}
+
+impl Period {
+    fn new(resolution: StatsResolution, now: u64, connections: u64) -> Period {
+        Period {
+            resolution,
+            bucket: StatsBucket {
+                start: resolution.bucket_start(now),
+                peak_connections: connections,
+                ..StatsBucket::default()
+            },
+            users: HashSet::new(),
+        }
+    }
+
+    fn add_user(&mut self, uuid: Uuid) {
+        if self.users.len() < MAX_ACTIVE_USERS {
+            self.users.insert(uuid);
+            self.bucket.active_users = self.users.len() as u64;
+        } else if !self.users.contains(&uuid) {
+            self.bucket.active_users_at_least = true;
+        }
+    }
+
+    /// The bucket including the counts which were not flushed yet.
+    fn current(&self, counters: &Counters) -> StatsBucket {
+        let mut bucket = self.bucket;
+        bucket.messages += counters.messages.load(Ordering::Relaxed);
+        bucket.peak_connections = bucket
+            .peak_connections
+            .max(counters.peak_connections.load(Ordering::Relaxed));
+        bucket
+    }
+}
+
+/// The statistics which were not written to the storage yet.
+#[derive(Default)]
+pub(super) struct Stats {
+    counters: Counters,
+    /// The current hour and day, once the statistics task started.
+    periods: Vec<Period>,
+    /// The number of messages per minute since the unix epoch, oldest first.
+    recent: VecDeque<(u64, u64)>,
+}
+
+impl ChatServer {
+    /// Writes the statistics to the storage every `stats.flush_interval`, if enabled.
+    pub(super) fn start_stats(&mut self, ctx: &mut Context<Self>) {
+        if !self.config.stats.enabled {
+            return;
+        }
+        let now = cluster::unix_millis(self.system_now());
+        let connections = self.sessions.len() as u64;
+        self.stats.periods = RESOLUTIONS
+            .iter()
+            .map(|resolution| Period::new(*resolution, now, connections))
+            .collect();
+        ctx.run_interval(*self.config.stats.flush_interval, |actor, _ctx| {
+            actor.flush_stats()
+        });
+    }
+
+    /// Counts a broadcast or private message of the user `uuid`.
+    pub(in crate::chat) fn record_stats_message(&mut self, uuid: Uuid) {
+        if self.simulating {
+            return;
+        }
+        self.stats.counters.messages.fetch_add(1, Ordering::Relaxed);
+        for period in &mut self.stats.periods {
+            period.add_user(uuid);
+        }
+
+        let minute = cluster::unix_millis(self.system_now()) / MINUTE;
+        let recent = &mut self.stats.recent;
+        match recent.back_mut() {
+            Some((last, count)) if *last == minute => *count += 1,
+            _ => recent.push_back((minute, 1)),
+        }
+        while recent
+            .front()
+            .is_some_and(|(first, _)| first + RECENT_MINUTES <= minute)
+        {
+            recent.pop_front();
+        }
+    }
+
+    /// The number of broadcast and private messages in the last hour, counted per minute.
+    pub(super) fn messages_last_hour(&self) -> u64 {
+        let minute = cluster::unix_millis(self.system_now()) / MINUTE;
+        self.stats
+            .recent
+            .iter()
+            .filter(|(start, _)| start + RECENT_MINUTES > minute)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// Updates the peak of open connections.
+    pub(in crate::chat) fn record_stats_connections(&self) {
+        self.stats
+            .counters
+            .peak_connections
+            .fetch_max(self.sessions.len() as u64, Ordering::Relaxed);
+    }
+
+    /// Adds the counts since the last flush to the current buckets and writes them to the storage.
+    ///
+    /// Buckets which ended since the last flush are written a last time and replaced.
+    fn flush_stats(&mut self) {
+        let now = cluster::unix_millis(self.system_now());
+        let connections = self.sessions.len() as u64;
+        let counters = &self.stats.counters;
+        let messages = counters.messages.swap(0, Ordering::Relaxed);
+        let peak = counters
+            .peak_connections
+            .swap(connections, Ordering::Relaxed);
+
+        for period in &mut self.stats.periods {
+            period.bucket.messages += messages;
+            period.bucket.peak_connections = period.bucket.peak_connections.max(peak);
+            if let Err(err) = self.storage.record_stats(period.resolution, period.bucket) {
+                warn!("Could not store statistics: {}", err);
+            }
+            if period.resolution.bucket_start(now) != period.bucket.start {
+                debug!(
+                    "Statistics of the {:?} starting at {} are complete.",
+                    period.resolution, period.bucket.start
+                );
+                *period = Period::new(period.resolution, now, connections);
+            }
+        }
+    }
+
+    /// The buckets starting in `from..to`, in milliseconds since the unix epoch, oldest first.
+    /// `to` is now by default, and `from` a week before `to`.
+    ///
+    /// Buckets without any activity are included with zeros,
+    /// and at most [`MAX_STATS_BUCKETS`] are returned.
+    /// Returns `None` if the statistics are disabled.
+    pub(super) fn stats_series(
+        &self,
+        resolution: StatsResolution,
+        from: Option<u64>,
+        to: Option<u64>,
+    ) -> Option<Vec<StatsBucket>> {
+        if !self.config.stats.enabled {
+            return None;
+        }
+        let to = to.unwrap_or_else(|| cluster::unix_millis(self.system_now()));
+        let from = from.unwrap_or_else(|| to.saturating_sub(DEFAULT_STATS_SPAN));
+        let length = resolution.millis();
+        let from = resolution.bucket_start(from);
+        let to = to.min(from.saturating_add(length * MAX_STATS_BUCKETS as u64));
+
+        let mut stored: BTreeMap<u64, StatsBucket> = self
+            .storage
+            .stats(resolution, from, to)
+            .into_iter()
+            .map(|bucket| (bucket.start, bucket))
+            .collect();
+        // The current bucket is newer than its stored version.
+        for period in &self.stats.periods {
+            if period.resolution == resolution && (from..to).contains(&period.bucket.start) {
+                stored.insert(period.bucket.start, period.current(&self.stats.counters));
+            }
+        }
+
+        Some(
+            (from..to)
+                .step_by(length as usize)
+                .map(|start| {
+                    stored.remove(&start).unwrap_or(StatsBucket {
+                        start,
+                        ..StatsBucket::default()
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{Clock, ManualClock};
+    use crate::error::Result;
+    use crate::storage::Storage;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    const HOUR: u64 = 60 * 60 * 1000;
+
+    /// Keeps the statistics in memory.
+    #[derive(Default)]
+    struct Memory(BTreeMap<(StatsResolution, u64), StatsBucket>);
+
+    impl Storage for Memory {
+        fn first_seen(&self, _user: &Uuid) -> Option<SystemTime> {
+            None
+        }
+
+        fn register_seen(&mut self, _user: &Uuid, time: SystemTime) -> Result<SystemTime> {
+            Ok(time)
+        }
+
+        fn record_stats(&mut self, resolution: StatsResolution, bucket: StatsBucket) -> Result<()> {
+            self.0.insert((resolution, bucket.start), bucket);
+            Ok(())
+        }
+
+        fn stats(&self, resolution: StatsResolution, from: u64, to: u64) -> Vec<StatsBucket> {
+            self.0
+                .range((resolution, from)..(resolution, to))
+                .map(|(_, bucket)| *bucket)
+                .collect()
+        }
+    }
+
+    fn notch() -> Uuid {
+        Uuid::from_u128(0x069a79f4_44e9_4726_a5be_fca90e38aaf5)
+    }
+
+    fn jeb() -> Uuid {
+        Uuid::from_u128(0x853c80ef_3c37_49fd_aa49_938b674adae6)
+    }
+
+    /// A server with statistics enabled whose clock is a second before the end of an hour,
+    /// returning the start of that hour.
+    fn server() -> (ChatServer, Arc<ManualClock>, u64) {
+        let mut server = ChatServer::for_tests(|config| config.stats.enabled = true);
+        let clock = Arc::new(ManualClock::new());
+        let now = cluster::unix_millis(clock.system_now());
+        clock.advance(Duration::from_millis(2 * HOUR - now % HOUR - 1000));
+        server.clock = clock.clone();
+        server.storage = Box::new(Memory::default());
+
+        // This is what the statistics task does when it starts.
+        let now = cluster::unix_millis(server.system_now());
+        server.stats.periods = RESOLUTIONS
+            .iter()
+            .map(|resolution| Period::new(*resolution, now, 0))
+            .collect();
+        (server, clock, StatsResolution::Hour.bucket_start(now))
+    }
+
+    fn messages(buckets: &[StatsBucket]) -> Vec<(u64, u64)> {
+        buckets
+            .iter()
+            .map(|bucket| (bucket.start, bucket.messages))
+            .collect()
+    }
+
+    #[test]
+    fn buckets_roll_over_at_their_end() {
+        let (mut server, clock, hour) = server();
+        server.record_stats_message(notch());
+        server.record_stats_message(jeb());
+        server.flush_stats();
+        // Counted before the hour ended, but only flushed afterwards.
+        server.record_stats_message(notch());
+        clock.advance(Duration::from_secs(2));
+        server.flush_stats();
+        server.record_stats_message(notch());
+        server.flush_stats();
+
+        let series = server
+            .stats_series(StatsResolution::Hour, Some(hour), Some(hour + 2 * HOUR))
+            .unwrap();
+        assert_eq!(messages(&series), [(hour, 3), (hour + HOUR, 1)]);
+        assert_eq!(series[0].active_users, 2);
+        assert_eq!(series[1].active_users, 1);
+        assert!(!series[0].active_users_at_least);
+
+        // The hour may also have ended a day.
+        let days = server
+            .stats_series(StatsResolution::Day, Some(hour), Some(hour + 2 * HOUR))
+            .unwrap();
+        assert_eq!(days.iter().map(|day| day.messages).sum::<u64>(), 4);
+    }
+
+    #[test]
+    fn unflushed_messages_are_included() {
+        let (mut server, _clock, hour) = server();
+        server.record_stats_message(notch());
+        server.flush_stats();
+        server.record_stats_message(notch());
+
+        let series = server
+            .stats_series(StatsResolution::Hour, Some(hour), Some(hour + HOUR))
+            .unwrap();
+        assert_eq!(messages(&series), [(hour, 2)]);
+    }
+
+    #[test]
+    fn missing_buckets_are_zeros() {
+        let (mut server, _clock, hour) = server();
+        let bucket = |start, messages| StatsBucket {
+            start,
+            messages,
+            ..StatsBucket::default()
+        };
+        server
+            .storage
+            .record_stats(StatsResolution::Hour, bucket(hour - 3 * HOUR, 5))
+            .unwrap();
+        server
+            .storage
+            .record_stats(StatsResolution::Hour, bucket(hour - HOUR, 7))
+            .unwrap();
+
+        // `from` is rounded down to the start of its bucket.
+        let series = server
+            .stats_series(
+                StatsResolution::Hour,
+                Some(hour - 4 * HOUR + 1),
+                Some(hour + 2 * HOUR),
+            )
+            .unwrap();
+        assert_eq!(
+            messages(&series),
+            [
+                (hour - 4 * HOUR, 0),
+                (hour - 3 * HOUR, 5),
+                (hour - 2 * HOUR, 0),
+                (hour - HOUR, 7),
+                (hour, 0),
+                (hour + HOUR, 0),
+            ]
+        );
+
+        // Nothing was ever stored that long ago.
+        let series = server
+            .stats_series(StatsResolution::Hour, Some(0), Some(3 * HOUR))
+            .unwrap();
+        assert_eq!(messages(&series), [(0, 0), (HOUR, 0), (2 * HOUR, 0)]);
+        assert!(server
+            .stats_series(StatsResolution::Hour, Some(hour), Some(hour))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn series_are_unavailable_if_disabled() {
+        let server = ChatServer::for_tests(|_| ());
+        assert!(server
+            .stats_series(StatsResolution::Day, None, None)
+            .is_none());
+    }
+}
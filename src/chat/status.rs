@@ -0,0 +1,246 @@
+//! A status page for operators at `/status`, readable in any browser without a dashboard.
+//!
+//! The page is plain HTML rendered by the server, without scripts or external resources,
+//! and reloads itself every [`REFRESH_SECONDS`].
+
+use super::{api::bearer_token, ChatServer, JobStatus};
+use crate::storage::{AuditEntry, AuditQuery};
+use crate::version;
+
+use actix::*;
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use futures::{future, Future};
+use ring::constant_time;
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How often the page reloads itself.
+const REFRESH_SECONDS: u64 = 30;
+
+/// The number of entries of the audit log shown on the page.
+const AUDIT_ENTRIES: usize = 20;
+
+/// Who may read the status page.
+#[derive(Clone)]
+pub(super) struct StatusAccess {
+    /// The token required to read the page, or `None` if it is public.
+    pub token: Option<String>,
+}
+
+/// What the page shows about the chat server.
+struct StatusSnapshot {
+    now: SystemTime,
+    connections: usize,
+    guests: usize,
+    max_connections: Option<usize>,
+    messages_last_hour: u64,
+    /// The latest entries of the audit log, newest first.
+    audit: Vec<AuditEntry>,
+    maintenance: Vec<JobStatus>,
+}
+
+struct StatusQuery;
+
+impl Message for StatusQuery {
+    type Result = StatusSnapshot;
+}
+
+impl Handler<StatusQuery> for ChatServer {
+    type Result = MessageResult<StatusQuery>;
+
+    fn handle(&mut self, _msg: StatusQuery, _ctx: &mut Context<Self>) -> Self::Result {
+        let audit = self.storage.audit_log(&AuditQuery {
+            limit: AUDIT_ENTRIES,
+            ..AuditQuery::default()
+        });
+        MessageResult(StatusSnapshot {
+            now: self.system_now(),
+            connections: self.sessions.len(),
+            guests: self.sessions.guest_count(),
+            max_connections: self.config.server.max_connections,
+            messages_last_hour: self.messages_last_hour(),
+            audit: audit.entries,
+            maintenance: self.maintenance_status(),
+        })
+    }
+}
+
+/// Serves the status page, if the request carries the token required by `access`.
+pub(super) fn status_route(
+    access: &StatusAccess,
+    req: HttpRequest,
+    addr: web::Data<Addr<ChatServer>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    if let Some(token) = &access.token {
+        if !is_authorized(&req, token) {
+            return Box::new(future::ok(
+                HttpResponse::Unauthorized()
+                    .header(header::WWW_AUTHENTICATE, "Bearer")
+                    .finish(),
+            ));
+        }
+    }
+    Box::new(addr.send(StatusQuery).then(|res| {
+        Ok(match res {
+            Ok(status) => HttpResponse::Ok()
+                .content_type("text/html; charset=utf-8")
+                .header(header::CACHE_CONTROL, "no-store")
+                .body(render(&status)),
+            Err(_) => HttpResponse::ServiceUnavailable().finish(),
+        })
+    }))
+}
+
+/// Whether the request carries `token` as a bearer token or in the query string.
+fn is_authorized(req: &HttpRequest, token: &str) -> bool {
+    let query = url::form_urlencoded::parse(req.query_string().as_bytes())
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned());
+    bearer_token(req.headers())
+        .map(str::to_string)
+        .or(query)
+        .is_some_and(|sent| {
+            constant_time::verify_slices_are_equal(sent.as_bytes(), token.as_bytes()).is_ok()
+        })
+}
+
+fn render(status: &StatusSnapshot) -> String {
+    let logged_in = status.connections.saturating_sub(status.guests);
+    let connections = match status.max_connections {
+        Some(max) => format!("{} of {}", status.connections, max),
+        None => status.connections.to_string(),
+    };
+    let uptime = Duration::from_secs(version::uptime().as_secs());
+
+    let mut html = String::new();
+    writeln!(html, "<!DOCTYPE html>").unwrap();
+    writeln!(html, "<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">").unwrap();
+    writeln!(
+        html,
+        "<meta http-equiv=\"refresh\" content=\"{}\">",
+        REFRESH_SECONDS
+    )
+    .unwrap();
+    writeln!(html, "<title>AxoChat status</title>").unwrap();
+    writeln!(
+        html,
+        "<style>body{{font-family:sans-serif;margin:2em}}\
+         table{{border-collapse:collapse}}\
+         th,td{{text-align:left;padding:.2em 1em .2em 0}}</style>"
+    )
+    .unwrap();
+    writeln!(html, "</head>\n<body>\n<h1>AxoChat status</h1>\n<table>").unwrap();
+    row(&mut html, "Version", version::VERSION);
+    row(
+        &mut html,
+        "Uptime",
+        &humantime::format_duration(uptime).to_string(),
+    );
+    row(&mut html, "Connections", &connections);
+    row(&mut html, "Logged in", &logged_in.to_string());
+    row(&mut html, "Guests", &status.guests.to_string());
+    row(
+        &mut html,
+        "Messages in the last hour",
+        &status.messages_last_hour.to_string(),
+    );
+    writeln!(html, "</table>\n<h2>Recent moderation</h2>").unwrap();
+
+    if status.audit.is_empty() {
+        writeln!(html, "<p>No moderation actions were recorded.</p>").unwrap();
+    } else {
+        writeln!(
+            html,
+            "<table>\n<tr><th>Time</th><th>Moderator</th><th>Action</th><th>Target</th><th>Reason</th></tr>"
+        )
+        .unwrap();
+        for entry in &status.audit {
+            let time = UNIX_EPOCH + Duration::from_millis(entry.timestamp);
+            let actor = match entry.actor {
+                Some(actor) => actor.to_string(),
+                None => "admin API".to_string(),
+            };
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{:?}</td><td>{}</td><td>{}</td></tr>",
+                humantime::format_rfc3339_seconds(time),
+                actor,
+                entry.action,
+                entry.target,
+                escape_html(entry.reason.as_deref().unwrap_or_default()),
+            )
+            .unwrap();
+        }
+        writeln!(html, "</table>").unwrap();
+    }
+
+    writeln!(html, "<h2>Maintenance</h2>").unwrap();
+    if status.maintenance.is_empty() {
+        writeln!(html, "<p>No maintenance jobs are enabled.</p>").unwrap();
+    } else {
+        writeln!(
+            html,
+            "<table>\n<tr><th>Job</th><th>Last run</th><th>Duration</th><th>Outcome</th></tr>"
+        )
+        .unwrap();
+        for job in &status.maintenance {
+            let last_run = match job.last_run {
+                Some(last_run) => {
+                    humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_millis(last_run))
+                        .to_string()
+                }
+                None => "never".to_string(),
+            };
+            let duration = job
+                .last_duration_ms
+                .map_or_else(String::new, |duration| format!("{} ms", duration));
+            let outcome = match (job.running, job.last_outcome) {
+                (true, _) => "running".to_string(),
+                (false, Some(outcome)) => format!("{:?}", outcome),
+                (false, None) => String::new(),
+            };
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                job.name, last_run, duration, outcome
+            )
+            .unwrap();
+        }
+        writeln!(html, "</table>").unwrap();
+    }
+
+    writeln!(
+        html,
+        "<p>Rendered at {}, reloading every {} seconds.</p>\n</body>\n</html>",
+        humantime::format_rfc3339_seconds(status.now),
+        REFRESH_SECONDS
+    )
+    .unwrap();
+    html
+}
+
+fn row(html: &mut String, name: &str, value: &str) {
+    writeln!(
+        html,
+        "<tr><th>{}</th><td>{}</td></tr>",
+        name,
+        escape_html(value)
+    )
+    .unwrap();
+}
+
+/// Escapes the characters with a meaning in HTML, so `text` is shown as it is.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,107 @@
+//! Negotiating the version of the protocol with the `Sec-WebSocket-Protocol` header,
+//! for clients like browsers which can not send `Hello` before the handshake completes.
+//!
+//! Of the subprotocols the client offers, the server selects the newest one it knows of and echoes it.
+//! The version of the selected subprotocol takes precedence over the `protocol` of `Hello`.
+
+use actix_web::http::{header, HeaderMap};
+
+/// The subprotocols the server knows of, oldest first, with the version of the protocol they stand for.
+///
+/// There is no `axochat.v2+msgpack`, since packets are only encoded as JSON.
+const SUBPROTOCOLS: &[(&str, u32)] = &[("axochat.v1", 1), ("axochat.v2", 2)];
+
+/// The outcome of the negotiation of a handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Negotiation {
+    /// The client did not offer any subprotocol.
+    None,
+    Selected {
+        /// The name which is echoed to the client.
+        name: &'static str,
+        /// The version of the protocol the client speaks.
+        protocol: u32,
+    },
+    /// The client offered only subprotocols the server does not know of.
+    Unknown,
+}
+
+/// Selects the newest subprotocol of those offered in `headers`.
+pub(super) fn negotiate(headers: &HeaderMap) -> Negotiation {
+    let offers: Vec<&str> = headers
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|offer| !offer.is_empty())
+        .collect();
+    if offers.is_empty() {
+        return Negotiation::None;
+    }
+    SUBPROTOCOLS
+        .iter()
+        .rev()
+        .find(|(name, _)| offers.contains(name))
+        .map_or(Negotiation::Unknown, |&(name, protocol)| {
+            Negotiation::Selected { name, protocol }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::HeaderValue;
+
+    fn offering(offers: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for offer in offers {
+            headers.append(
+                header::SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_static(offer),
+            );
+        }
+        headers
+    }
+
+    fn selected(name: &'static str, protocol: u32) -> Negotiation {
+        Negotiation::Selected { name, protocol }
+    }
+
+    #[test]
+    fn the_newest_known_offer_is_selected() {
+        assert_eq!(
+            negotiate(&offering(&["axochat.v1"])),
+            selected("axochat.v1", 1)
+        );
+        assert_eq!(
+            negotiate(&offering(&["axochat.v1, axochat.v2"])),
+            selected("axochat.v2", 2)
+        );
+        assert_eq!(
+            negotiate(&offering(&["axochat.v2,axochat.v1"])),
+            selected("axochat.v2", 2)
+        );
+        assert_eq!(
+            negotiate(&offering(&["chat.example", " axochat.v1 ,"])),
+            selected("axochat.v1", 1)
+        );
+    }
+
+    #[test]
+    fn offers_are_matched_exactly() {
+        for offer in &["AXOCHAT.V2", "axochat.v2+msgpack", "axochat", "axochat.v3"] {
+            assert_eq!(
+                negotiate(&offering(&[offer])),
+                Negotiation::Unknown,
+                "{}",
+                offer
+            );
+        }
+    }
+
+    #[test]
+    fn missing_or_empty_offers_negotiate_nothing() {
+        assert_eq!(negotiate(&HeaderMap::new()), Negotiation::None);
+        assert_eq!(negotiate(&offering(&["", " , "])), Negotiation::None);
+    }
+}
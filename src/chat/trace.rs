@@ -0,0 +1,94 @@
+//! Trace ids correlating the packets of a client with the log events and responses they caused.
+//!
+//! Every packet a session receives gets an id, which is the current trace of the thread
+//! while the session and the chat server handle it.
+//! The log format of `axochat start` appends it to every event, see [`current_trace`].
+//! Clients supporting the `trace` feature receive it in the direct responses to their packets,
+//! while broadcasts caused by the packet only carry it in the logs.
+
+use super::{ClientPacket, InternalId};
+
+use actix::Message;
+use serde::{Serialize, Serializer};
+use std::cell::Cell;
+use std::fmt;
+
+/// Identifies a packet received by a session, e.g. `c12/ab3f09c2-3` for the third packet of connection `c12/ab3f09c2`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct TraceId {
+    session: InternalId,
+    packet: u32,
+}
+
+impl TraceId {
+    pub(super) fn new(session: InternalId, packet: u32) -> TraceId {
+        TraceId { session, packet }
+    }
+
+    /// The connection which sent the packet.
+    pub fn session(self) -> InternalId {
+        self.session
+    }
+}
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.session, self.packet)
+    }
+}
+
+impl Serialize for TraceId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+thread_local! {
+    static CURRENT: Cell<Option<TraceId>> = const { Cell::new(None) };
+}
+
+/// The trace of the packet handled on this thread, if any.
+pub fn current_trace() -> Option<TraceId> {
+    CURRENT.with(Cell::get)
+}
+
+/// Makes `trace` the current trace of this thread until the returned guard is dropped.
+pub(super) fn enter(trace: TraceId) -> Entered {
+    Entered(CURRENT.with(|current| current.replace(Some(trace))))
+}
+
+/// Restores the previous trace when dropped.
+pub(super) struct Entered(Option<TraceId>);
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.0));
+    }
+}
+
+/// A direct response to a packet, sent with the trace of that packet.
+pub(super) struct TracedPacket {
+    pub packet: ClientPacket,
+    pub trace: TraceId,
+}
+
+impl Message for TracedPacket {
+    type Result = ();
+}
+
+impl ClientPacket {
+    /// Whether the packet answers a packet of the client directly,
+    /// so the client receives it with the trace of that packet.
+    pub(super) fn is_response(&self) -> bool {
+        matches!(
+            self,
+            ClientPacket::Error { .. }
+                | ClientPacket::RepeatedError { .. }
+                | ClientPacket::Rejected { .. }
+                | ClientPacket::Success { .. }
+                | ClientPacket::MessageAck { .. }
+                | ClientPacket::PrivateMessageAck { .. }
+                | ClientPacket::TimeSync { .. }
+        )
+    }
+}
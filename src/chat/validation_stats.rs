@@ -0,0 +1,69 @@
+//! Counting the messages which were rejected by a rule of the validator,
+//! or only delivered because of `validation.dry_run`.
+
+use super::ChatServer;
+use crate::rules::Rejection;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+#[derive(Default)]
+pub(super) struct ValidationStats {
+    /// The number of messages rejected, by the name of the rule which rejected them.
+    rejections: Mutex<BTreeMap<&'static str, u64>>,
+    /// The number of violations let through, by the translation key of the rule.
+    dry_run: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl ValidationStats {
+    pub fn record_rejection(&self, rule: &'static str) {
+        *self.rejections.lock().unwrap().entry(rule).or_insert(0) += 1;
+    }
+
+    pub fn record_dry_run(&self, rule: &'static str) {
+        *self.dry_run.lock().unwrap().entry(rule).or_insert(0) += 1;
+    }
+
+    /// Appends the counters in the Prometheus text format.
+    pub fn write_metrics(&self, output: &mut String) {
+        writeln!(
+            output,
+            "# HELP axochat_validation_rejections_total The number of messages which were rejected, by the rule which rejected them."
+        )
+        .unwrap();
+        writeln!(output, "# TYPE axochat_validation_rejections_total counter").unwrap();
+        for (rule, count) in self.rejections.lock().unwrap().iter() {
+            writeln!(
+                output,
+                "axochat_validation_rejections_total{{rule=\"{}\"}} {}",
+                rule, count
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            output,
+            "# HELP axochat_dry_run_violations_total The number of rule violations which were delivered because of the dry run."
+        )
+        .unwrap();
+        writeln!(output, "# TYPE axochat_dry_run_violations_total counter").unwrap();
+        for (rule, count) in self.dry_run.lock().unwrap().iter() {
+            writeln!(
+                output,
+                "axochat_dry_run_violations_total{{rule=\"{}\"}} {}",
+                rule, count
+            )
+            .unwrap();
+        }
+    }
+}
+
+impl ChatServer {
+    /// Counts a message which was rejected, unless it was only simulated.
+    pub(in crate::chat) fn record_rejection(&self, rejection: &Rejection) {
+        if !self.simulating {
+            self.validation_stats.record_rejection(rejection.rule);
+        }
+    }
+}
@@ -6,27 +6,84 @@ use serde::{
     Deserialize, Serialize,
 };
 use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
     env, fmt,
     fs::{self, File},
     io::{self, Read},
     net::SocketAddr,
     ops::Deref,
     path::PathBuf,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
+use uuid::Uuid;
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct Config {
     #[serde(default)]
     pub net: NetConfig,
 
+    #[serde(default)]
+    pub server: ServerConfig,
+
     #[serde(default)]
     pub message: MsgConfig,
 
+    #[serde(default)]
+    pub validation: ValidationConfig,
+
     #[serde(default)]
     pub moderation: ModConfig,
 
+    #[serde(default)]
+    pub commands: CommandConfig,
+
+    #[serde(default)]
+    pub storage: StorageConfig,
+
+    #[serde(default)]
+    pub welcome: WelcomeConfig,
+
+    #[serde(default)]
+    pub resume: ResumeConfig,
+
+    #[serde(default)]
+    pub api: ApiConfig,
+
+    #[serde(default)]
+    pub mojang: MojangConfig,
+
+    #[serde(default)]
+    pub signing: SigningConfig,
+
+    #[serde(default)]
+    pub bots: BotConfig,
+
+    #[serde(default)]
+    pub reactions: ReactionConfig,
+
+    #[serde(default)]
+    pub presence: PresenceConfig,
+
+    #[serde(default)]
+    pub stats: StatsConfig,
+
+    #[serde(default)]
+    pub drain: DrainConfig,
+
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
+
     pub auth: Option<AuthConfig>,
+
+    pub cluster: Option<ClusterConfig>,
+
+    /// The IRC channel broadcast messages are relayed to; requires the `irc` feature.
+    pub irc: Option<IrcConfig>,
+
+    /// The messages broadcast regularly.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub announcements: Vec<Announcement>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -51,28 +108,391 @@ impl Default for NetConfig {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// The maximum number of websocket connections; unlimited if not set.
+    pub max_connections: Option<usize>,
+
+    /// The number of connections below `max_connections` reserved for moderators.
+    /// Other users logging in on one of these are disconnected.
+    pub reserved_slots: usize,
+
+    /// The time clients are told to wait before retrying if the server is full.
+    pub retry_after: WDuration,
+
+    /// Whether metrics are served at `/metrics`.
+    pub metrics: bool,
+
+    /// Whether the description of the packets is served at `/schema`.
+    pub schema: bool,
+
+    /// Whether a status page for operators is served as HTML at `/status`.
+    pub status_page: bool,
+
+    /// Whether `/status` requires `api.token`, sent as a bearer token or as `?token=`.
+    /// The page is not served if this is enabled without a token.
+    pub status_page_requires_token: bool,
+
+    /// The number of messages waiting for the chat server above which packets are rejected.
+    /// Packets are never rejected if this is not set.
+    pub backlog_threshold: Option<usize>,
+
+    /// Which packets are rejected while the backlog is above `backlog_threshold`.
+    pub shed_packets: ShedPackets,
+
+    /// Whether clients may skip the `Hello` packet.
+    /// If disabled, other packets are rejected until a client sent `Hello`.
+    pub allow_legacy_clients: bool,
+
+    /// The time after which connections which did not send `Hello` are closed,
+    /// unless `allow_legacy_clients` is enabled.
+    pub handshake_timeout: WDuration,
+
+    /// The maximum number of sessions a user can be logged in with at once.
+    /// A value of `0` disables the limit.
+    pub max_sessions_per_user: usize,
+
+    /// What happens if a user exceeds `max_sessions_per_user`.
+    pub session_limit: SessionLimit,
+
+    /// How long users can still be looked up by their uuid after their last session closed.
+    pub last_seen_duration: WDuration,
+
+    /// The maximum size of a packet in bytes.
+    pub max_packet_size: usize,
+
+    /// The maximum nesting of arrays and objects in a packet.
+    pub max_packet_depth: usize,
+
+    /// The maximum size in bytes of the `Message` packet of a broadcast message.
+    /// Larger packets are sent without their reply excerpt; messages which are still too large are rejected.
+    /// A value of `0` disables the limit.
+    pub max_outgoing_frame_bytes: usize,
+
+    /// The number of malformed packets after which a connection is closed.
+    /// A value of `0` disables the limit.
+    pub max_malformed_packets: u32,
+
+    /// Whether clients of brands not in `min_client_versions`, or without a brand, are accepted.
+    pub unknown_clients: UnknownClients,
+
+    /// Where rejected clients can download a newer version.
+    pub client_upgrade_url: Option<String>,
+
+    /// The versions clients of each brand need, as semver requirements like `>=1.4.0`, by brand.
+    /// Versions which are not valid semver are compared as strings with the requirement.
+    // Tables have to follow the plain values when the configuration is written as TOML.
+    pub min_client_versions: BTreeMap<String, String>,
+
+    /// The versions of the protocol older than the current one which are still spoken.
+    /// Clients which do not send `Hello` use version `1`.
+    pub legacy_protocols: Vec<LegacyProtocol>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownClients {
+    Allow,
+    Reject,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            max_connections: None,
+            reserved_slots: 0,
+            retry_after: Duration::from_secs(30).into(),
+            metrics: false,
+            schema: false,
+            status_page: false,
+            status_page_requires_token: true,
+            backlog_threshold: None,
+            shed_packets: ShedPackets::Messages,
+            allow_legacy_clients: true,
+            handshake_timeout: Duration::from_secs(10).into(),
+            max_sessions_per_user: 3,
+            session_limit: SessionLimit::Reject,
+            last_seen_duration: Duration::from_secs(60 * 60).into(),
+            max_packet_size: 16 * 1024,
+            max_packet_depth: 8,
+            max_outgoing_frame_bytes: 64 * 1024,
+            max_malformed_packets: 10,
+            min_client_versions: BTreeMap::new(),
+            unknown_clients: UnknownClients::Allow,
+            client_upgrade_url: None,
+            legacy_protocols: vec![LegacyProtocol {
+                version: 1,
+                sunset: None,
+            }],
+        }
+    }
+}
+
+/// An old version of the protocol, which the server adapts its packets to.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+pub struct LegacyProtocol {
+    pub version: u32,
+    /// The time after which clients using the version are rejected, like `2027-01-01T00:00:00Z`.
+    /// Until then, they are warned when they log in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sunset: Option<WTimestamp>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionLimit {
+    /// The new login is rejected.
+    Reject,
+    /// The oldest session of the user is closed.
+    KickOldest,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShedPackets {
+    /// Only messages, private messages and reactions are rejected.
+    Messages,
+    /// Every packet except logins is rejected.
+    All,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MsgConfig {
-    /// The maximum message length in chars.
+    /// The maximum message length in `validation.length_unit`.
     pub max_length: usize,
 
+    /// The maximum length of messages broadcast by the operator of the server.
+    #[serde(default = "default_max_system_length")]
+    pub max_system_length: usize,
+
     /// The maximum amount of messages in `count_duration`.
     pub max_messages: usize,
 
     /// The duration in which the amount of messages cannot be greater.
     pub count_duration: WDuration,
+
+    /// The amount of broadcast messages kept for clients which resynchronize.
+    #[serde(default = "default_history_size")]
+    pub history_size: usize,
+
+    /// The amount of messages sent to a resynchronizing client at once.
+    #[serde(default = "default_replay_chunk_size")]
+    pub replay_chunk_size: usize,
+
+    /// A TOML or JSON file mapping emote shortcodes, without colons, to their replacements.
+    #[serde(default)]
+    pub emotes: Option<PathBuf>,
+
+    /// How long a client waits for a user to log in after sending `NotifyWhenOnline`.
+    #[serde(default = "default_online_watch_duration")]
+    pub online_watch_duration: WDuration,
+
+    /// How long private messages to the previous name of a renamed user are delivered to them.
+    /// Afterwards, they fail with `UserNotFound`.
+    #[serde(default = "default_rename_grace")]
+    pub rename_grace: WDuration,
+
+    /// Whether clients may send end-to-end encrypted private messages,
+    /// which are relayed without being validated, filtered or passed to the hooks.
+    #[serde(default = "default_allow_encrypted_private")]
+    pub allow_encrypted_private: bool,
+
+    /// The maximum size of the base64 payload of an encrypted private message, in bytes.
+    #[serde(default = "default_max_encrypted_size")]
+    pub max_encrypted_size: usize,
+}
+
+fn default_max_system_length() -> usize {
+    1000
+}
+
+fn default_replay_chunk_size() -> usize {
+    50
+}
+
+fn default_history_size() -> usize {
+    100
+}
+
+fn default_online_watch_duration() -> WDuration {
+    Duration::from_secs(60 * 60).into()
+}
+
+fn default_rename_grace() -> WDuration {
+    Duration::from_secs(0).into()
+}
+
+fn default_allow_encrypted_private() -> bool {
+    true
+}
+
+fn default_max_encrypted_size() -> usize {
+    4096
 }
 
 impl Default for MsgConfig {
     fn default() -> MsgConfig {
         MsgConfig {
             max_length: 100,
+            max_system_length: default_max_system_length(),
             max_messages: 40,
             count_duration: Duration::from_secs(60).into(),
+            history_size: default_history_size(),
+            replay_chunk_size: default_replay_chunk_size(),
+            emotes: None,
+            online_watch_duration: default_online_watch_duration(),
+            rename_grace: default_rename_grace(),
+            allow_encrypted_private: default_allow_encrypted_private(),
+            max_encrypted_size: default_max_encrypted_size(),
         }
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ValidationConfig {
+    /// How URLs in messages are handled.
+    pub links: LinkPolicy,
+
+    /// The domains URLs may point to if `links` is `whitelist`.
+    /// Subdomains of these domains are allowed too.
+    pub link_whitelist: Vec<String>,
+
+    /// The file containing the blocked words, one per line.
+    /// Messages containing any of them are handled according to `blocked_words_action`.
+    pub blocked_words: PathBuf,
+
+    /// Whether messages containing blocked words are rejected or delivered with the words redacted.
+    pub blocked_words_action: BlockedWordsAction,
+
+    /// The minimum number of letters and digits a message has to keep after its blocked words were redacted.
+    /// Messages with fewer are rejected as if `blocked_words_action` was `reject`.
+    pub min_redacted_content: usize,
+
+    /// Whether subscribed moderators are told about redacted messages, with an excerpt of the original.
+    pub report_redactions: bool,
+
+    /// Whether moderators are exempt from the link policy.
+    pub moderators_bypass_links: bool,
+
+    /// How the length of messages is counted.
+    pub length_unit: LengthUnit,
+
+    /// The maximum message length in bytes, regardless of `length_unit`.
+    pub max_bytes: usize,
+
+    /// Whether messages may contain line breaks.
+    /// `\r\n` and `\r` are normalized to `\n` before the lines are counted.
+    pub allow_newlines: bool,
+
+    /// The maximum number of lines of a message if `allow_newlines` is enabled.
+    pub max_lines: usize,
+
+    /// The maximum length of each line in `length_unit`; unlimited if `0`.
+    pub max_line_length: usize,
+
+    /// How consecutive blank lines are handled if `allow_newlines` is enabled.
+    pub blank_lines: BlankLines,
+
+    /// Whether messages consisting only of whitespace are rejected as empty.
+    pub reject_blank: bool,
+
+    /// The maximum number of times the same character may be repeated in a row; unlimited if `0`.
+    pub max_char_run: usize,
+
+    /// The minimum fraction of the characters other than whitespace which have to be letters or digits;
+    /// disabled if `0`.
+    pub min_content_ratio: f64,
+
+    /// The transformations applied to messages before they are validated, in this order.
+    /// The transformed message is what is length-checked and delivered.
+    pub transforms: Vec<Transform>,
+
+    /// Whether messages violating the rules above are still delivered.
+    /// The violations are logged, counted in the metrics and sent to subscribed moderators.
+    /// Rate limits and bans are enforced regardless.
+    pub dry_run: bool,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> ValidationConfig {
+        ValidationConfig {
+            links: LinkPolicy::Allow,
+            link_whitelist: Vec::new(),
+            blocked_words: PathBuf::from("./blocked_words.txt"),
+            blocked_words_action: BlockedWordsAction::Reject,
+            min_redacted_content: 3,
+            report_redactions: false,
+            moderators_bypass_links: true,
+            length_unit: LengthUnit::Chars,
+            max_bytes: 4096,
+            allow_newlines: false,
+            max_lines: 5,
+            max_line_length: 0,
+            blank_lines: BlankLines::Collapse,
+            reject_blank: false,
+            max_char_run: 0,
+            min_content_ratio: 0.0,
+            transforms: vec![Transform::ExpandEmotes],
+            dry_run: false,
+        }
+    }
+}
+
+/// A transformation of messages, see [`crate::transform`].
+/// Unknown names are rejected when the configuration is loaded.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Transform {
+    /// Removes characters without a visible glyph, like zero-width spaces.
+    StripInvisible,
+    /// Replaces runs of whitespace other than line breaks with a single space.
+    CollapseWhitespace,
+    /// Replaces shortcodes like `:heart:` with their emote.
+    ExpandEmotes,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkPolicy {
+    /// All URLs are allowed.
+    Allow,
+    /// No URLs are allowed.
+    Block,
+    /// Only URLs to domains in `link_whitelist` are allowed.
+    Whitelist,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockedWordsAction {
+    /// Messages containing blocked words are rejected.
+    Reject,
+    /// The characters of blocked words are replaced with `*` before the message is delivered.
+    Redact,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BlankLines {
+    /// Consecutive blank lines are replaced by a single one.
+    Collapse,
+    /// Messages with consecutive blank lines are rejected.
+    Reject,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LengthUnit {
+    /// Unicode scalar values.
+    Chars,
+    /// Extended grapheme clusters, roughly what users perceive as characters.
+    Graphemes,
+    /// Bytes of the UTF-8 encoding.
+    Bytes,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AuthConfig {
     /// The file containing the key of the JWT
@@ -86,15 +506,150 @@ pub struct AuthConfig {
 
     /// Whether users can be anonymous
     pub allow_anonymous: bool,
+
+    /// How connections which did not log in are treated.
+    #[serde(flatten)]
+    pub guests: GuestConfig,
 }
 
+/// Connections which watch the chat without logging in.
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct GuestConfig {
+    /// Whether connections which did not log in stay connected and receive the `guest_events`.
+    /// If disabled, they receive no broadcasts and are closed after `login_timeout`.
+    pub allow_guests: bool,
+
+    /// The time in which connections have to log in if `allow_guests` is disabled.
+    pub login_timeout: WDuration,
+
+    /// The broadcasts guests receive.
+    pub guest_events: Vec<GuestEvent>,
+
+    /// The maximum number of connections from one IP address which did not log in; unlimited if `0`,
+    /// the default.
+    ///
+    /// This also limits connections which log in later, since they are guests until they do.
+    pub max_guests_per_ip: usize,
+}
+
+impl Default for GuestConfig {
+    fn default() -> GuestConfig {
+        GuestConfig {
+            allow_guests: true,
+            login_timeout: Duration::from_secs(30).into(),
+            guest_events: vec![
+                GuestEvent::Messages,
+                GuestEvent::Announcements,
+                GuestEvent::Presence,
+            ],
+            max_guests_per_ip: 0,
+        }
+    }
+}
+
+/// A kind of broadcast guests may receive.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GuestEvent {
+    /// Broadcast messages and the changes of their reactions.
+    Messages,
+    /// `SystemMessage` packets, like announcements and bans.
+    Announcements,
+    /// `PresenceDiff` and `UserRenamed` packets.
+    Presence,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct ModConfig {
     /// The file containing the moderators (line separated).
     pub moderators: PathBuf,
 
     /// The file containing the banned users (line separated).
+    /// The end and the reason of a ban follow its uuid, separated by tabs.
     pub banned: PathBuf,
+
+    /// The file containing the whitelisted users (line separated).
+    /// Whitelisted users are exempt from the probation of new users.
+    pub whitelisted: PathBuf,
+
+    /// The time in seconds for which new users are restricted after their first login.
+    /// A value of `0` disables the probation.
+    pub probation_secs: u64,
+
+    /// Whether users in probation are not allowed to send private messages.
+    pub probation_block_private: bool,
+
+    /// Whether users in probation are not allowed to send messages containing URLs.
+    pub probation_block_links: bool,
+
+    /// Whether users in probation can only send half of `max_messages`.
+    pub probation_halve_rate_limit: bool,
+
+    /// The time in seconds after a login in which a user can not send messages.
+    /// A value of `0` disables the cooldown.
+    /// Moderators and users returning within `join_cooldown_grace_secs` are exempt.
+    pub join_cooldown_secs: u64,
+
+    /// Whether private messages are also rejected during the join cooldown.
+    pub join_cooldown_block_private: bool,
+
+    /// The time in seconds after the last session of a user closed
+    /// in which a new login is not subject to the join cooldown.
+    pub join_cooldown_grace_secs: u64,
+
+    /// The URL messages are reviewed at before they are broadcast.
+    ///
+    /// A review is a POST request with a JSON body like
+    /// `{"author": {"name": "...", "uuid": "..."}, "content": "..."}`,
+    /// which has to be answered with `{"verdict": "allow" | "deny" | "flag"}`.
+    /// Messages whose author disconnected, or was banned or muted, during the review are dropped.
+    pub review_url: Option<String>,
+
+    /// The maximum time a review may take.
+    pub review_timeout: WDuration,
+
+    /// The verdict used if a review failed or timed out.
+    pub review_fallback: ReviewVerdict,
+
+    /// Whether bans are announced to all clients with a `SystemMessage`.
+    pub announce_actions: bool,
+
+    /// The announcement of a ban; `{name}` is replaced with the name of the user.
+    pub ban_announcement: String,
+
+    /// Whether banned users can log in, which tells them about the ban right away.
+    pub banned_login: BannedLogin,
+
+    /// The number of days after which entries of the audit log are removed.
+    /// A value of `0` keeps them forever.
+    pub audit_retention_days: u64,
+
+    /// The maximum number of entries of the audit log a moderator receives for one `RequestAuditLog`.
+    pub audit_log_max_entries: usize,
+
+    /// The number of minutes for which moderators can look up who sent private messages to whom,
+    /// when and how long they were, but never their content.
+    /// A value of `0` disables it.
+    pub pm_metadata_retention_minutes: u64,
+
+    /// The maximum number of private messages whose metadata is kept; the oldest are dropped first.
+    pub pm_metadata_max_entries: usize,
+
+    /// The maximum amount of users a moderator may (un-)ban, (un-)mute or kick in `action_count_duration`.
+    /// A value of `0` disables the limit.
+    pub max_ban_actions: usize,
+
+    /// The maximum amount of words a moderator may (un-)block in `action_count_duration`.
+    /// A value of `0` disables the limit.
+    pub max_word_actions: usize,
+
+    /// The duration in which the amount of moderation actions cannot be greater.
+    pub action_count_duration: WDuration,
+
+    /// How long a moderator has to confirm a permanent ban with `ConfirmAction`.
+    pub confirmation_ttl: WDuration,
 }
 
 impl Default for ModConfig {
@@ -102,6 +657,527 @@ impl Default for ModConfig {
         ModConfig {
             moderators: PathBuf::from("./moderators.txt"),
             banned: PathBuf::from("./banned.txt"),
+            whitelisted: PathBuf::from("./whitelisted.txt"),
+            probation_secs: 0,
+            probation_block_private: true,
+            probation_block_links: true,
+            probation_halve_rate_limit: true,
+            join_cooldown_secs: 0,
+            join_cooldown_block_private: false,
+            join_cooldown_grace_secs: 300,
+            review_url: None,
+            review_timeout: Duration::from_millis(150).into(),
+            review_fallback: ReviewVerdict::Allow,
+            announce_actions: false,
+            ban_announcement: String::from("{name} was banned."),
+            banned_login: BannedLogin::Allow,
+            audit_retention_days: 0,
+            audit_log_max_entries: 100,
+            pm_metadata_retention_minutes: 0,
+            pm_metadata_max_entries: 10_000,
+            max_ban_actions: 10,
+            max_word_actions: 20,
+            action_count_duration: Duration::from_secs(60).into(),
+            confirmation_ttl: Duration::from_secs(30).into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BannedLogin {
+    /// The login completes and the client receives a `ModerationStatus`.
+    Allow,
+    /// The client receives a `Disconnected` with the reason and end of the ban,
+    /// and the connection is closed with the `banned` close code.
+    Reject,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReviewVerdict {
+    /// The message is broadcast.
+    Allow,
+    /// The message is rejected.
+    Deny,
+    /// The message is broadcast, but moderators are notified about it.
+    Flag,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct MojangConfig {
+    /// The number of recent failed authentications kept for the admin API.
+    pub recent_failures: usize,
+
+    /// The duration over which the failure rate of authentications is calculated.
+    pub failure_window: WDuration,
+
+    /// The fraction of authentications in `failure_window` which have to time out
+    /// or fail with an HTTP error for Mojang to be considered degraded.
+    pub degraded_threshold: f64,
+
+    /// How long the session hash sent in reply to `RequestMojangInfo` can be used to log in.
+    pub request_ttl: WDuration,
+}
+
+impl Default for MojangConfig {
+    fn default() -> MojangConfig {
+        MojangConfig {
+            recent_failures: 100,
+            failure_window: Duration::from_secs(5 * 60).into(),
+            degraded_threshold: 0.5,
+            request_ttl: Duration::from_secs(2 * 60).into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct SigningConfig {
+    /// The Ed25519 private key in the PKCS#8 DER format broadcast messages are signed with.
+    /// Messages are not signed if this is not set.
+    pub key_file: Option<PathBuf>,
+}
+
+/// Accounts of bots, like bridges, which need to send more messages than people.
+///
+/// Bots are exempt from the probation and the join cooldown and may send messages for a bridge,
+/// but never moderate, even if they are also listed in `moderation.moderators`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct BotConfig {
+    /// The uuids of the bots.
+    pub uuids: Vec<Uuid>,
+
+    /// The maximum amount of messages of a bot in `count_duration`.
+    pub max_messages: usize,
+
+    /// The duration in which the amount of messages of a bot cannot be greater.
+    pub count_duration: WDuration,
+}
+
+impl Default for BotConfig {
+    fn default() -> BotConfig {
+        BotConfig {
+            uuids: Vec::new(),
+            max_messages: 200,
+            count_duration: Duration::from_secs(60).into(),
+        }
+    }
+}
+
+/// Reactions to broadcast messages, which are kept as long as the messages are in the history.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ReactionConfig {
+    /// The reactions users may add.
+    /// If this is empty, any single grapheme without whitespace or control characters is allowed.
+    pub allowed: Vec<String>,
+
+    /// The maximum amount of different reactions of a user on one message.
+    pub max_per_message: usize,
+
+    /// The maximum amount of reactions a user may add or remove in `count_duration`.
+    pub max_reactions: usize,
+
+    /// The duration in which the amount of reactions cannot be greater.
+    pub count_duration: WDuration,
+}
+
+impl Default for ReactionConfig {
+    fn default() -> ReactionConfig {
+        ReactionConfig {
+            allowed: Vec::new(),
+            max_per_message: 3,
+            max_reactions: 30,
+            count_duration: Duration::from_secs(60).into(),
+        }
+    }
+}
+
+/// Telling clients which users logged in and out, in batches.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PresenceConfig {
+    /// Whether clients supporting the `presence` feature receive `PresenceDiff` packets.
+    pub enabled: bool,
+
+    /// The time in which logins and logouts are collected into one `PresenceDiff`.
+    pub batch_window: WDuration,
+
+    /// The number of collected logins and logouts after which the batch is sent early.
+    pub max_batch: usize,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> PresenceConfig {
+        PresenceConfig {
+            enabled: false,
+            batch_window: Duration::from_secs(2).into(),
+            max_batch: 100,
+        }
+    }
+}
+
+/// Taking an instance out of rotation with `/api/v1/drain` or `SIGUSR1`,
+/// without disconnecting all of its clients at once.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DrainConfig {
+    /// The percentage of the connections open when draining started which is closed in every step,
+    /// between 1 and 100.
+    pub step_percent: u32,
+
+    /// The time between two steps.
+    pub step_interval: WDuration,
+
+    /// The time clients are told to wait before reconnecting,
+    /// both when they are disconnected and when their connection is rejected.
+    pub retry_after: WDuration,
+}
+
+impl Default for DrainConfig {
+    fn default() -> DrainConfig {
+        DrainConfig {
+            step_percent: 5,
+            step_interval: Duration::from_secs(10).into(),
+            retry_after: Duration::from_secs(5).into(),
+        }
+    }
+}
+
+/// Removing expired state, like old entries of the audit log, in one periodic run.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct MaintenanceConfig {
+    /// The time between two runs of the maintenance jobs.
+    pub interval: WDuration,
+
+    /// The time a single job may take.
+    /// Jobs on the thread pool time out after it, but are not started again until they finished;
+    /// exceeding it on the chat server is logged.
+    pub job_budget: WDuration,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> MaintenanceConfig {
+        MaintenanceConfig {
+            interval: Duration::from_secs(60 * 60).into(),
+            job_budget: Duration::from_secs(5).into(),
+        }
+    }
+}
+
+/// Keeping the message volume in the storage, for reports over months.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct StatsConfig {
+    /// Whether hourly and daily statistics are stored and served at `/api/v1/stats`.
+    pub enabled: bool,
+
+    /// How often the statistics of the current hour and day are written to the storage.
+    pub flush_interval: WDuration,
+}
+
+impl Default for StatsConfig {
+    fn default() -> StatsConfig {
+        StatsConfig {
+            enabled: false,
+            flush_interval: Duration::from_secs(5 * 60).into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ApiConfig {
+    /// The bearer token required for the admin API at `/api/v1`.
+    /// The API is disabled if this is not set.
+    pub token: Option<String>,
+
+    /// The maximum amount of requests with the same token in `rate_limit_duration`.
+    pub max_requests_per_token: usize,
+
+    /// The maximum amount of requests from the same IP address in `rate_limit_duration`.
+    pub max_requests_per_ip: usize,
+
+    /// The duration in which the amount of requests cannot be greater.
+    pub rate_limit_duration: WDuration,
+
+    /// The maximum amount of packets simulated with `/api/v1/simulate`
+    /// with the same token or from the same IP address in `rate_limit_duration`.
+    pub max_simulations: usize,
+}
+
+impl Default for ApiConfig {
+    fn default() -> ApiConfig {
+        ApiConfig {
+            token: None,
+            max_requests_per_token: 120,
+            max_requests_per_ip: 60,
+            rate_limit_duration: Duration::from_secs(60).into(),
+            max_simulations: 10,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ResumeConfig {
+    /// Whether clients receive a token after logging in,
+    /// which lets them resume their session after reconnecting.
+    pub enabled: bool,
+
+    /// The time for which a session can be resumed after its connection was lost.
+    pub ttl: WDuration,
+}
+
+impl Default for ResumeConfig {
+    fn default() -> ResumeConfig {
+        ResumeConfig {
+            enabled: false,
+            ttl: Duration::from_secs(120).into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClusterConfig {
+    /// The Redis server instances exchange events through, e.g. `redis://127.0.0.1:6379`.
+    pub redis_url: String,
+
+    /// The prefix of all Redis keys and channels used by the cluster.
+    #[serde(default = "default_cluster_prefix")]
+    pub prefix: String,
+
+    /// The unique id of this instance; a random one is chosen if it is not set.
+    pub instance_id: Option<String>,
+
+    /// The time after which an instance which stopped refreshing its users is
+    /// no longer considered to host them.
+    #[serde(default = "default_registry_ttl")]
+    pub registry_ttl: WDuration,
+}
+
+fn default_cluster_prefix() -> String {
+    "axochat".to_string()
+}
+
+fn default_registry_ttl() -> WDuration {
+    Duration::from_secs(30).into()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IrcConfig {
+    /// The IRC server as `host:port`, e.g. `irc.libera.chat:6697`.
+    pub server: String,
+
+    /// Whether to connect using TLS.
+    #[serde(default)]
+    pub tls: bool,
+
+    pub nick: String,
+
+    /// The channel to join, e.g. `#axochat`.
+    pub channel: String,
+
+    /// Whether messages of the channel are sent to the chat, like those sent through the admin API.
+    #[serde(default)]
+    pub relay_incoming: bool,
+
+    /// The minimum time between two messages sent to IRC, so the server does not throttle the bridge.
+    #[serde(default = "default_irc_send_interval")]
+    pub send_interval: WDuration,
+
+    /// The maximum number of messages waiting to be sent; further messages are dropped.
+    #[serde(default = "default_irc_max_queue")]
+    pub max_queue: usize,
+
+    /// The maximum delay between two connection attempts.
+    #[serde(default = "default_irc_reconnect_max")]
+    pub reconnect_max: WDuration,
+}
+
+fn default_irc_send_interval() -> WDuration {
+    Duration::from_secs(1).into()
+}
+
+fn default_irc_max_queue() -> usize {
+    50
+}
+
+fn default_irc_reconnect_max() -> WDuration {
+    Duration::from_secs(5 * 60).into()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// The file containing the time each user was first seen at.
+    pub first_seen: PathBuf,
+
+    /// The file containing the audit log of moderation actions, one JSON object per line.
+    pub audit_log: PathBuf,
+
+    /// The file containing the long-term statistics, one JSON object per line.
+    pub stats: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> StorageConfig {
+        StorageConfig {
+            first_seen: PathBuf::from("./first_seen.txt"),
+            audit_log: PathBuf::from("./audit_log.jsonl"),
+            stats: PathBuf::from("./stats.jsonl"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct CommandConfig {
+    /// Whether messages starting with `/` are handled as commands instead of being broadcast.
+    pub enabled: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct WelcomeConfig {
+    /// The packets sent to users after they logged in, in this order.
+    pub items: Vec<WelcomeItem>,
+
+    /// The message of the day.
+    pub motd: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WelcomeItem {
+    /// Sends `motd`.
+    Motd,
+    /// Sends information about the server, like the maximum message length.
+    ServerInfo,
+    /// Sends the amount of connected and logged in users.
+    UserCount,
+}
+
+/// The shortest interval announcements can be sent at.
+pub const MIN_ANNOUNCEMENT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A message the server broadcasts regularly as a `SystemMessage`.
+///
+/// In the configuration file, either `interval_secs` or `schedule`, a time of day like `04:00` in UTC, is set.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(try_from = "RawAnnouncement", into = "RawAnnouncement")]
+pub struct Announcement {
+    pub content: String,
+    pub schedule: AnnouncementSchedule,
+    /// The announcement is skipped while fewer users are logged in on this instance.
+    pub min_users: u32,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AnnouncementSchedule {
+    /// Every interval, starting one interval after the server started.
+    Interval(Duration),
+    /// Every day, at this many minutes after midnight UTC.
+    Daily { minute: u32 },
+}
+
+impl AnnouncementSchedule {
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    /// Returns the time from `now`, measured since the unix epoch, until the next announcement.
+    pub fn next_after(&self, now: Duration) -> Duration {
+        match *self {
+            AnnouncementSchedule::Interval(interval) => interval,
+            AnnouncementSchedule::Daily { minute } => {
+                // Announcing slightly early must not cause a second announcement, so look one second ahead.
+                let ahead = now + Duration::from_secs(1);
+                let day = AnnouncementSchedule::DAY.as_millis();
+                let target = u128::from(minute) * 60 * 1000;
+                let wait = (target + day - ahead.as_millis() % day) % day;
+                Duration::from_millis(wait as u64) + Duration::from_secs(1)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawAnnouncement {
+    content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    interval_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schedule: Option<String>,
+    #[serde(default = "default_announcement_min_users")]
+    min_users: u32,
+}
+
+fn default_announcement_min_users() -> u32 {
+    1
+}
+
+impl TryFrom<RawAnnouncement> for Announcement {
+    type Error = String;
+
+    fn try_from(raw: RawAnnouncement) -> std::result::Result<Announcement, String> {
+        let schedule = match (raw.interval_secs, raw.schedule) {
+            (Some(secs), None) => {
+                let interval = Duration::from_secs(secs);
+                if interval < MIN_ANNOUNCEMENT_INTERVAL {
+                    return Err(format!(
+                        "announcement interval of {}s is shorter than {}s",
+                        secs,
+                        MIN_ANNOUNCEMENT_INTERVAL.as_secs()
+                    ));
+                }
+                AnnouncementSchedule::Interval(interval)
+            }
+            (None, Some(schedule)) => {
+                let minute = schedule
+                    .split_once(':')
+                    .and_then(|(hours, minutes)| {
+                        let hours: u32 = hours.parse().ok()?;
+                        let minutes: u32 = minutes.parse().ok()?;
+                        if hours < 24 && minutes < 60 {
+                            Some(hours * 60 + minutes)
+                        } else {
+                            None
+                        }
+                    })
+                    .ok_or_else(|| {
+                        format!(
+                            "invalid announcement schedule `{}`, expected HH:MM",
+                            schedule
+                        )
+                    })?;
+                AnnouncementSchedule::Daily { minute }
+            }
+            _ => return Err("announcements need either `interval_secs` or `schedule`".to_string()),
+        };
+        Ok(Announcement {
+            content: raw.content,
+            schedule,
+            min_users: raw.min_users,
+        })
+    }
+}
+
+impl From<Announcement> for RawAnnouncement {
+    fn from(announcement: Announcement) -> RawAnnouncement {
+        let (interval_secs, schedule) = match announcement.schedule {
+            AnnouncementSchedule::Interval(interval) => (Some(interval.as_secs()), None),
+            AnnouncementSchedule::Daily { minute } => {
+                (None, Some(format!("{:02}:{:02}", minute / 60, minute % 60)))
+            }
+        };
+        RawAnnouncement {
+            content: announcement.content,
+            interval_secs,
+            schedule,
+            min_users: announcement.min_users,
         }
     }
 }
@@ -182,3 +1258,60 @@ impl Serialize for WDuration {
         serializer.serialize_str(&duration.to_string())
     }
 }
+
+/// A point in time, written in RFC 3339 format.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct WTimestamp(SystemTime);
+
+impl From<SystemTime> for WTimestamp {
+    fn from(time: SystemTime) -> WTimestamp {
+        WTimestamp(time)
+    }
+}
+
+impl Deref for WTimestamp {
+    type Target = SystemTime;
+
+    fn deref(&self) -> &SystemTime {
+        &self.0
+    }
+}
+
+impl<'de> Deserialize<'de> for WTimestamp {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<WTimestamp, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TimestampVisitor;
+
+        impl<'de> Visitor<'de> for TimestampVisitor {
+            type Value = WTimestamp;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an RFC 3339 timestamp")
+            }
+
+            fn visit_str<E>(self, value: &str) -> std::result::Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                match humantime::parse_rfc3339_weak(value) {
+                    Ok(time) => Ok(WTimestamp(time)),
+                    Err(err) => Err(E::custom(err)),
+                }
+            }
+        }
+
+        deserializer.deserialize_str(TimestampVisitor)
+    }
+}
+
+impl Serialize for WTimestamp {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let time = humantime::format_rfc3339_seconds(self.0);
+        serializer.serialize_str(&time.to_string())
+    }
+}
@@ -0,0 +1,90 @@
+use crate::config::LengthUnit;
+use crate::error::*;
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::{fs, path::Path};
+
+/// The longest shortcode which is looked up, in bytes.
+const MAX_CODE_LENGTH: usize = 32;
+
+/// A table of shortcodes like `:heart:` and their replacements.
+#[derive(Default)]
+pub struct Emotes {
+    /// The codes without colons.
+    table: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct EmoteFile(BTreeMap<String, String>);
+
+impl Emotes {
+    pub fn new(table: BTreeMap<String, String>) -> Emotes {
+        Emotes { table }
+    }
+
+    /// Reads a table from a file mapping codes without colons to their replacements.
+    /// Files ending with `.json` are read as JSON, all others as TOML.
+    pub fn load(path: &Path) -> Result<Emotes> {
+        let input = fs::read_to_string(path)?;
+        let EmoteFile(table) = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&input)?
+        } else {
+            toml::from_str(&input)?
+        };
+        Ok(Emotes::new(table))
+    }
+
+    /// The codes without colons and their replacements.
+    pub fn table(&self) -> &BTreeMap<String, String> {
+        &self.table
+    }
+
+    /// Replaces all known shortcodes in `msg`; unknown ones are left untouched.
+    ///
+    /// Fails with [`ClientError::MessageTooLong`] if the expanded message
+    /// would be longer than `max_bytes`.
+    /// Once that limit is exceeded, the remaining message is only counted.
+    pub fn expand(&self, msg: &str, max_bytes: usize) -> Result<String> {
+        let mut expanded = String::with_capacity(msg.len());
+        let mut length = 0;
+        let mut push = |expanded: &mut String, text: &str| {
+            length += text.len();
+            if length <= max_bytes {
+                expanded.push_str(text);
+            }
+        };
+
+        let mut rest = msg;
+        while let Some(start) = rest.find(':') {
+            push(&mut expanded, &rest[..start]);
+            rest = &rest[start + 1..];
+
+            let code = rest
+                .find(':')
+                .filter(|end| *end <= MAX_CODE_LENGTH)
+                .map(|end| &rest[..end]);
+            match code.and_then(|code| self.table.get(code).map(|emote| (code, emote))) {
+                Some((code, emote)) => {
+                    push(&mut expanded, emote);
+                    rest = &rest[code.len() + 1..];
+                }
+                // the closing colon might start another code
+                None => push(&mut expanded, ":"),
+            }
+        }
+        push(&mut expanded, rest);
+
+        if length > max_bytes {
+            Err(ClientError::MessageTooLong {
+                length,
+                max_length: max_bytes,
+                unit: LengthUnit::Bytes,
+            }
+            .into())
+        } else {
+            Ok(expanded)
+        }
+    }
+}
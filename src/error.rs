@@ -1,6 +1,8 @@
+use crate::config::LengthUnit;
 use derive_more::From;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use snafu::Snafu;
+use std::collections::BTreeMap;
 use std::{error, fmt, io};
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -15,6 +17,8 @@ pub enum Error {
     TOML { source: toml::de::Error },
     #[snafu(display("actix-web: {}", source))]
     Actix { source: actix_web::Error },
+    #[snafu(display("actix mailbox: {}", source))]
+    Mailbox { source: actix::MailboxError },
     #[cfg(feature = "ssl")]
     #[snafu(display("OpenSSL: {}", source))]
     OpenSSL { source: openssl::error::ErrorStack },
@@ -33,27 +37,409 @@ pub enum Error {
 }
 
 /// A client-facing error.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ClientError {
     NotSupported,
     LoginFailed,
     NotLoggedIn,
     AlreadyLoggedIn,
+    /// The user is already logged in with `max` other sessions.
+    TooManySessions {
+        max: usize,
+    },
+    HandshakeRequired,
+    /// The client is too old or not accepted by the server; `upgrade_url` is where a newer one can be found.
+    ClientOutdated {
+        upgrade_url: Option<String>,
+    },
     MojangRequestMissing,
     NotPermitted,
     NotBanned,
+    /// The user to unmute is not muted.
+    NotMuted,
+    NotBlocked,
+    /// No user is or was recently connected with the uuid.
+    UserNotFound,
+    EmptyWord,
     Banned,
+    /// A moderator muted the user, who can not send messages for another `remaining_secs`.
+    Muted {
+        remaining_secs: u64,
+    },
     RateLimited,
+    Probation {
+        remaining_secs: u64,
+    },
+    /// The user logged in recently and has to wait before sending messages.
+    JoinCooldown {
+        remaining_secs: u64,
+    },
     PrivateMessageNotAccepted,
+    /// The receiver of a private message is online,
+    /// but none of their connections which accept private messages could receive it.
+    DeliveryFailed,
+    /// The receiver of a private message does not want to be disturbed.
+    DoNotDisturb,
+    /// The client already waits for `max` users to log in.
+    TooManyWatches {
+        max: usize,
+    },
     EmptyMessage,
-    MessageTooLong,
-    InvalidCharacter(char),
+    /// `length` and `max_length` are counted in `unit`.
+    MessageTooLong {
+        length: usize,
+        max_length: usize,
+        unit: LengthUnit,
+    },
+    /// The first invalid character of a message,
+    /// at `char_index` chars and `byte_offset` bytes from the start of the message.
+    InvalidCharacter {
+        character: char,
+        char_index: usize,
+        byte_offset: usize,
+    },
+    TooManyLines {
+        lines: usize,
+        max_lines: usize,
+    },
+    /// The first line longer than `validation.max_line_length`, counting from 1.
+    LineTooLong {
+        line: usize,
+        length: usize,
+        max_length: usize,
+        unit: LengthUnit,
+    },
+    ConsecutiveBlankLines,
+    /// The longest `run` of `character` in a message, which is longer than `validation.max_char_run`.
+    ExcessiveRepetition {
+        character: char,
+        run: usize,
+        max_run: usize,
+    },
+    /// Too few characters of a message are letters or digits for `validation.min_content_ratio`.
+    InsufficientContent,
+    LinksNotAllowed {
+        url: String,
+    },
+    BlockedContent,
+    ResumeFailed,
     InvalidId,
+    /// The reaction is not allowed by `reactions.allowed` or is not a single grapheme.
+    InvalidReaction,
+    /// The payload of an encrypted private message is missing or not valid base64.
+    InvalidPayload,
+    /// The message is not in the history anymore, or never existed.
+    MessageNotFound,
+    /// The user already added `max` reactions to the message.
+    TooManyReactions {
+        max: usize,
+    },
+    /// The user did not add the reaction which should be removed.
+    NotReacted,
+    /// The moderation action was applied, but could not be saved and is retried in the background.
+    PersistenceDegraded,
+    /// The nonce of a `ConfirmAction` is unknown, was already used or expired.
+    ConfirmationFailed,
+    /// An imported moderation state has details of a user it does not ban,
+    /// a mute without an end or a ban or mute which already ended.
+    InvalidImport,
+    /// The client sent a packet which could not be decoded.
+    MalformedPacket {
+        category: MalformedCategory,
+    },
+    /// The token sent with `LoginJWT` was rejected.
+    InvalidToken {
+        reason: TokenFailure,
+    },
     Internal,
 }
 
 impl error::Error for ClientError {}
 
+/// Why a packet could not be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MalformedCategory {
+    /// The packet is larger than the server accepts.
+    TooLarge,
+    /// Arrays or objects are nested too deeply.
+    TooDeep,
+    /// The packet is not valid JSON.
+    Syntax,
+    /// There is no packet with the given name.
+    UnknownPacket,
+    /// A field of the packet is missing.
+    MissingField,
+    /// A field of the packet has the wrong type or an invalid value.
+    TypeMismatch,
+    /// The packet was sent in a binary frame.
+    Binary,
+}
+
+impl MalformedCategory {
+    /// The name of the category, as it is serialized.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MalformedCategory::TooLarge => "too_large",
+            MalformedCategory::TooDeep => "too_deep",
+            MalformedCategory::Syntax => "syntax",
+            MalformedCategory::UnknownPacket => "unknown_packet",
+            MalformedCategory::MissingField => "missing_field",
+            MalformedCategory::TypeMismatch => "type_mismatch",
+            MalformedCategory::Binary => "binary",
+        }
+    }
+}
+
+/// Why a login token was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenFailure {
+    /// The token expired; the client has to request a new one.
+    Expired,
+    /// The token is not valid yet.
+    NotYetValid,
+    /// The token was not signed with the key or algorithm of the server.
+    InvalidSignature,
+    /// The token is not a valid JWT.
+    Malformed,
+    /// A claim of the token is missing or has an invalid value.
+    MissingClaim,
+}
+
+impl TokenFailure {
+    /// The name of the reason, as it is serialized.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TokenFailure::Expired => "expired",
+            TokenFailure::NotYetValid => "not_yet_valid",
+            TokenFailure::InvalidSignature => "invalid_signature",
+            TokenFailure::Malformed => "malformed",
+            TokenFailure::MissingClaim => "missing_claim",
+        }
+    }
+}
+
+/// The values interpolated into a translated message, by name.
+pub type TranslationParams = BTreeMap<&'static str, String>;
+
+/// The keys clients use to look up translations of messages sent by the server.
+///
+/// Keys are stable, so they can be used in translation files.
+/// They consist of a category and the snake case name of the message, separated by a dot:
+/// `error.*` for [`ClientError`]s, `command.*` for command results
+/// and `disconnect.*` for the reasons a connection is closed.
+pub mod keys {
+    pub const NOT_SUPPORTED: &str = "error.not_supported";
+    pub const LOGIN_FAILED: &str = "error.login_failed";
+    pub const NOT_LOGGED_IN: &str = "error.not_logged_in";
+    pub const ALREADY_LOGGED_IN: &str = "error.already_logged_in";
+    pub const TOO_MANY_SESSIONS: &str = "error.too_many_sessions";
+    pub const HANDSHAKE_REQUIRED: &str = "error.handshake_required";
+    pub const CLIENT_OUTDATED: &str = "error.client_outdated";
+    pub const MOJANG_REQUEST_MISSING: &str = "error.mojang_request_missing";
+    pub const NOT_PERMITTED: &str = "error.not_permitted";
+    pub const NOT_BANNED: &str = "error.not_banned";
+    pub const NOT_MUTED: &str = "error.not_muted";
+    pub const NOT_BLOCKED: &str = "error.not_blocked";
+    pub const USER_NOT_FOUND: &str = "error.user_not_found";
+    pub const EMPTY_WORD: &str = "error.empty_word";
+    pub const BANNED: &str = "error.banned";
+    pub const MUTED: &str = "error.muted";
+    pub const RATE_LIMITED: &str = "error.rate_limited";
+    pub const PROBATION: &str = "error.probation";
+    pub const JOIN_COOLDOWN: &str = "error.join_cooldown";
+    pub const PRIVATE_MESSAGE_NOT_ACCEPTED: &str = "error.private_message_not_accepted";
+    pub const DELIVERY_FAILED: &str = "error.delivery_failed";
+    pub const DO_NOT_DISTURB: &str = "error.do_not_disturb";
+    pub const TOO_MANY_WATCHES: &str = "error.too_many_watches";
+    pub const EMPTY_MESSAGE: &str = "error.empty_message";
+    pub const MESSAGE_TOO_LONG: &str = "error.message_too_long";
+    pub const INVALID_CHARACTER: &str = "error.invalid_character";
+    pub const TOO_MANY_LINES: &str = "error.too_many_lines";
+    pub const LINE_TOO_LONG: &str = "error.line_too_long";
+    pub const CONSECUTIVE_BLANK_LINES: &str = "error.consecutive_blank_lines";
+    pub const EXCESSIVE_REPETITION: &str = "error.excessive_repetition";
+    pub const INSUFFICIENT_CONTENT: &str = "error.insufficient_content";
+    pub const LINKS_NOT_ALLOWED: &str = "error.links_not_allowed";
+    pub const BLOCKED_CONTENT: &str = "error.blocked_content";
+    pub const RESUME_FAILED: &str = "error.resume_failed";
+    pub const INVALID_ID: &str = "error.invalid_id";
+    pub const INVALID_REACTION: &str = "error.invalid_reaction";
+    pub const INVALID_PAYLOAD: &str = "error.invalid_payload";
+    pub const MESSAGE_NOT_FOUND: &str = "error.message_not_found";
+    pub const TOO_MANY_REACTIONS: &str = "error.too_many_reactions";
+    pub const NOT_REACTED: &str = "error.not_reacted";
+    pub const PERSISTENCE_DEGRADED: &str = "error.persistence_degraded";
+    pub const CONFIRMATION_FAILED: &str = "error.confirmation_failed";
+    pub const INVALID_IMPORT: &str = "error.invalid_import";
+    pub const MALFORMED_PACKET: &str = "error.malformed_packet";
+    pub const INVALID_TOKEN: &str = "error.invalid_token";
+    pub const INTERNAL: &str = "error.internal";
+
+    pub const COMMAND_UNKNOWN: &str = "command.unknown";
+    pub const COMMAND_USAGE: &str = "command.usage";
+    pub const COMMAND_UNTERMINATED_QUOTE: &str = "command.unterminated_quote";
+    pub const COMMAND_UNKNOWN_USER: &str = "command.unknown_user";
+    pub const COMMAND_BANNED: &str = "command.banned";
+    pub const COMMAND_CONFIRM: &str = "command.confirm";
+    pub const COMMAND_UNBANNED: &str = "command.unbanned";
+    pub const COMMAND_MUTED: &str = "command.muted";
+    pub const COMMAND_UNMUTED: &str = "command.unmuted";
+    pub const COMMAND_KICKED: &str = "command.kicked";
+    pub const COMMAND_INVALID_DURATION: &str = "command.invalid_duration";
+    pub const COMMAND_MOTD: &str = "command.motd";
+    pub const COMMAND_NO_MOTD: &str = "command.no_motd";
+    pub const COMMAND_HELP: &str = "command.help";
+
+    pub const DISCONNECT_CLIENT_CLOSED: &str = "disconnect.client_closed";
+    pub const DISCONNECT_PROTOCOL_ERROR: &str = "disconnect.protocol_error";
+    pub const DISCONNECT_INVALID_PAYLOAD: &str = "disconnect.invalid_payload";
+    pub const DISCONNECT_BANNED: &str = "disconnect.banned";
+    pub const DISCONNECT_FRAME_TOO_LARGE: &str = "disconnect.frame_too_large";
+    pub const DISCONNECT_INTERNAL: &str = "disconnect.internal";
+    pub const DISCONNECT_SERVER_FULL: &str = "disconnect.server_full";
+    pub const DISCONNECT_HANDSHAKE_TIMEOUT: &str = "disconnect.handshake_timeout";
+    pub const DISCONNECT_SESSION_LIMIT: &str = "disconnect.session_limit";
+    pub const DISCONNECT_MALFORMED_PACKETS: &str = "disconnect.malformed_packets";
+    pub const DISCONNECT_CLIENT_OUTDATED: &str = "disconnect.client_outdated";
+    pub const DISCONNECT_SLOW_CONSUMER: &str = "disconnect.slow_consumer";
+    pub const DISCONNECT_GUEST_LIMIT: &str = "disconnect.guest_limit";
+    pub const DISCONNECT_LOGIN_TIMEOUT: &str = "disconnect.login_timeout";
+    pub const DISCONNECT_MIGRATE: &str = "disconnect.migrate";
+    pub const DISCONNECT_KICKED: &str = "disconnect.kicked";
+}
+
+impl ClientError {
+    /// The key of the translation of this error.
+    pub fn translation_key(&self) -> &'static str {
+        use self::ClientError::*;
+
+        match self {
+            NotSupported => keys::NOT_SUPPORTED,
+            LoginFailed => keys::LOGIN_FAILED,
+            NotLoggedIn => keys::NOT_LOGGED_IN,
+            AlreadyLoggedIn => keys::ALREADY_LOGGED_IN,
+            TooManySessions { .. } => keys::TOO_MANY_SESSIONS,
+            HandshakeRequired => keys::HANDSHAKE_REQUIRED,
+            ClientOutdated { .. } => keys::CLIENT_OUTDATED,
+            MojangRequestMissing => keys::MOJANG_REQUEST_MISSING,
+            NotPermitted => keys::NOT_PERMITTED,
+            NotBanned => keys::NOT_BANNED,
+            NotMuted => keys::NOT_MUTED,
+            NotBlocked => keys::NOT_BLOCKED,
+            UserNotFound => keys::USER_NOT_FOUND,
+            EmptyWord => keys::EMPTY_WORD,
+            Banned => keys::BANNED,
+            Muted { .. } => keys::MUTED,
+            RateLimited => keys::RATE_LIMITED,
+            Probation { .. } => keys::PROBATION,
+            JoinCooldown { .. } => keys::JOIN_COOLDOWN,
+            PrivateMessageNotAccepted => keys::PRIVATE_MESSAGE_NOT_ACCEPTED,
+            DeliveryFailed => keys::DELIVERY_FAILED,
+            DoNotDisturb => keys::DO_NOT_DISTURB,
+            TooManyWatches { .. } => keys::TOO_MANY_WATCHES,
+            EmptyMessage => keys::EMPTY_MESSAGE,
+            MessageTooLong { .. } => keys::MESSAGE_TOO_LONG,
+            InvalidCharacter { .. } => keys::INVALID_CHARACTER,
+            TooManyLines { .. } => keys::TOO_MANY_LINES,
+            LineTooLong { .. } => keys::LINE_TOO_LONG,
+            ConsecutiveBlankLines => keys::CONSECUTIVE_BLANK_LINES,
+            ExcessiveRepetition { .. } => keys::EXCESSIVE_REPETITION,
+            InsufficientContent => keys::INSUFFICIENT_CONTENT,
+            LinksNotAllowed { .. } => keys::LINKS_NOT_ALLOWED,
+            BlockedContent => keys::BLOCKED_CONTENT,
+            ResumeFailed => keys::RESUME_FAILED,
+            InvalidId => keys::INVALID_ID,
+            InvalidReaction => keys::INVALID_REACTION,
+            InvalidPayload => keys::INVALID_PAYLOAD,
+            MessageNotFound => keys::MESSAGE_NOT_FOUND,
+            TooManyReactions { .. } => keys::TOO_MANY_REACTIONS,
+            NotReacted => keys::NOT_REACTED,
+            PersistenceDegraded => keys::PERSISTENCE_DEGRADED,
+            ConfirmationFailed => keys::CONFIRMATION_FAILED,
+            InvalidImport => keys::INVALID_IMPORT,
+            MalformedPacket { .. } => keys::MALFORMED_PACKET,
+            InvalidToken { .. } => keys::INVALID_TOKEN,
+            Internal => keys::INTERNAL,
+        }
+    }
+
+    /// The values interpolated into the translation of this error.
+    pub fn translation_params(&self) -> TranslationParams {
+        use self::ClientError::*;
+
+        let mut params = TranslationParams::new();
+        match self {
+            Probation { remaining_secs }
+            | JoinCooldown { remaining_secs }
+            | Muted { remaining_secs } => {
+                params.insert("remaining_secs", remaining_secs.to_string());
+            }
+            TooManySessions { max } | TooManyWatches { max } | TooManyReactions { max } => {
+                params.insert("max", max.to_string());
+            }
+            MessageTooLong {
+                length,
+                max_length,
+                unit,
+            } => {
+                params.insert("length", length.to_string());
+                params.insert("max_length", max_length.to_string());
+                params.insert("unit", unit_name(*unit).to_string());
+            }
+            TooManyLines { lines, max_lines } => {
+                params.insert("lines", lines.to_string());
+                params.insert("max_lines", max_lines.to_string());
+            }
+            LineTooLong {
+                line,
+                length,
+                max_length,
+                unit,
+            } => {
+                params.insert("line", line.to_string());
+                params.insert("length", length.to_string());
+                params.insert("max_length", max_length.to_string());
+                params.insert("unit", unit_name(*unit).to_string());
+            }
+            InvalidCharacter {
+                character,
+                char_index,
+                ..
+            } => {
+                params.insert("character", character.to_string());
+                params.insert("char_index", char_index.to_string());
+            }
+            ExcessiveRepetition {
+                character,
+                run,
+                max_run,
+            } => {
+                params.insert("character", character.to_string());
+                params.insert("run", run.to_string());
+                params.insert("max_run", max_run.to_string());
+            }
+            LinksNotAllowed { url } => {
+                params.insert("url", url.clone());
+            }
+            ClientOutdated {
+                upgrade_url: Some(url),
+            } => {
+                params.insert("upgrade_url", url.clone());
+            }
+            MalformedPacket { category } => {
+                params.insert("category", category.as_str().to_string());
+            }
+            InvalidToken { reason } => {
+                params.insert("reason", reason.as_str().to_string());
+            }
+            _ => {}
+        }
+        params
+    }
+}
+
 impl fmt::Display for ClientError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::ClientError::*;
@@ -63,21 +449,123 @@ impl fmt::Display for ClientError {
             LoginFailed => write!(f, "login failed"),
             NotLoggedIn => write!(f, "not logged in"),
             AlreadyLoggedIn => write!(f, "already logged in"),
+            TooManySessions { max } => write!(f, "already logged in with {} sessions", max),
+            HandshakeRequired => write!(f, "hello required"),
+            ClientOutdated { upgrade_url } => match upgrade_url {
+                Some(url) => write!(f, "client is outdated, please upgrade: {}", url),
+                None => write!(f, "client is outdated, please upgrade"),
+            },
             MojangRequestMissing => write!(f, "mojang request missing"),
             NotPermitted => write!(f, "not permitted"),
             NotBanned => write!(f, "not banned"),
+            NotMuted => write!(f, "not muted"),
+            NotBlocked => write!(f, "word is not blocked"),
+            UserNotFound => write!(f, "user not found"),
+            EmptyWord => write!(f, "word is empty"),
             Banned => write!(f, "banned"),
+            Muted { remaining_secs } => {
+                write!(f, "you are muted for another {} seconds", remaining_secs)
+            }
             RateLimited => write!(f, "rate limited"),
+            Probation { remaining_secs } => write!(
+                f,
+                "new users can not do this for another {} seconds",
+                remaining_secs
+            ),
+            JoinCooldown { remaining_secs } => write!(
+                f,
+                "you can not send messages for another {} seconds after joining",
+                remaining_secs
+            ),
             PrivateMessageNotAccepted => write!(f, "private message not accepted"),
+            DeliveryFailed => write!(f, "private message could not be delivered"),
+            DoNotDisturb => write!(f, "the receiver does not want to be disturbed"),
+            TooManyWatches { max } => write!(f, "already waiting for {} users", max),
             EmptyMessage => write!(f, "empty message"),
-            MessageTooLong => write!(f, "message was too long"),
-            InvalidCharacter(ch) => write!(
+            MessageTooLong {
+                length,
+                max_length,
+                unit,
+            } => write!(
+                f,
+                "message was too long: {} of at most {} {}",
+                length,
+                max_length,
+                unit_description(*unit)
+            ),
+            TooManyLines { lines, max_lines } => write!(
                 f,
-                "message contained invalid character: `{}`",
-                ch.escape_default()
+                "message had too many lines: {} of at most {}",
+                lines, max_lines
             ),
+            LineTooLong {
+                line,
+                length,
+                max_length,
+                unit,
+            } => write!(
+                f,
+                "line {} was too long: {} of at most {} {}",
+                line,
+                length,
+                max_length,
+                unit_description(*unit)
+            ),
+            InvalidCharacter {
+                character,
+                char_index,
+                ..
+            } => write!(
+                f,
+                "message contained invalid character at {}: `{}`",
+                char_index,
+                character.escape_default()
+            ),
+            ConsecutiveBlankLines => write!(f, "message contained consecutive blank lines"),
+            ExcessiveRepetition {
+                character,
+                run,
+                max_run,
+            } => write!(
+                f,
+                "message repeated `{}` {} times, at most {} are allowed",
+                character.escape_default(),
+                run,
+                max_run
+            ),
+            InsufficientContent => write!(f, "message contained too few letters and digits"),
+            LinksNotAllowed { url } => write!(f, "links are not allowed: `{}`", url),
+            BlockedContent => write!(f, "message was blocked"),
+            ResumeFailed => write!(f, "session can not be resumed"),
             InvalidId => write!(f, "invalid id"),
+            InvalidReaction => write!(f, "invalid reaction"),
+            InvalidPayload => write!(f, "invalid encrypted payload"),
+            MessageNotFound => write!(f, "message not found"),
+            TooManyReactions { max } => write!(f, "already added {} reactions", max),
+            NotReacted => write!(f, "reaction was not added"),
+            PersistenceDegraded => write!(f, "applied, but could not be saved yet"),
+            ConfirmationFailed => write!(f, "action can not be confirmed"),
+            InvalidImport => write!(f, "invalid moderation state"),
+            MalformedPacket { category } => write!(f, "malformed packet: {}", category.as_str()),
+            InvalidToken { reason } => write!(f, "invalid login token: {}", reason.as_str()),
             Internal => write!(f, "internal error"),
         }
     }
 }
+
+/// The name of `unit` in translation parameters.
+fn unit_name(unit: LengthUnit) -> &'static str {
+    match unit {
+        LengthUnit::Chars => "chars",
+        LengthUnit::Graphemes => "graphemes",
+        LengthUnit::Bytes => "bytes",
+    }
+}
+
+fn unit_description(unit: LengthUnit) -> &'static str {
+    match unit {
+        LengthUnit::Chars => "characters",
+        LengthUnit::Graphemes => "graphemes",
+        LengthUnit::Bytes => "bytes",
+    }
+}
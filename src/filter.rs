@@ -0,0 +1,129 @@
+use crate::error::*;
+
+use aho_corasick::AhoCorasick;
+use std::collections::BTreeSet;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+/// Blocks messages containing any of a set of words, or redacts the words in them.
+///
+/// Words are matched case-insensitively anywhere in a message.
+/// A filter is immutable; changing the words means building a new one,
+/// which can be done off the actor thread since building the matcher can be slow.
+pub struct WordFilter {
+    words: BTreeSet<String>,
+    matcher: Option<AhoCorasick>,
+}
+
+impl WordFilter {
+    pub fn new(words: BTreeSet<String>) -> WordFilter {
+        let matcher = if words.is_empty() {
+            None
+        } else {
+            Some(AhoCorasick::new(&words))
+        };
+        WordFilter { words, matcher }
+    }
+
+    /// Reads the words from a line separated file.
+    /// A missing file is treated like an empty one.
+    pub fn load(path: &Path) -> Result<WordFilter> {
+        Ok(WordFilter::new(read_words(path)?))
+    }
+
+    /// The blocked words, in lowercase.
+    pub fn words(&self) -> &BTreeSet<String> {
+        &self.words
+    }
+
+    /// Returns the first blocked word found in `msg`.
+    pub fn find(&self, msg: &str) -> Option<&str> {
+        let matcher = self.matcher.as_ref()?;
+        let msg = msg.to_lowercase();
+        matcher.find(&msg).map(|found| {
+            self.words
+                .iter()
+                .nth(found.pattern())
+                .expect("pattern should exist")
+                .as_str()
+        })
+    }
+
+    /// Replaces every character of the blocked words in `msg` with `*`,
+    /// or returns `None` if it contains none.
+    ///
+    /// Characters are replaced as a whole, even if only their lowercase form is part of a word.
+    pub fn redact(&self, msg: &str) -> Option<String> {
+        let matcher = self.matcher.as_ref()?;
+        let lowercase = msg.to_lowercase();
+        // The index of the character of `msg` every byte of `lowercase` belongs to.
+        // `str::to_lowercase` only differs from lowering each character on its own in the final sigma,
+        // which is as long as the other one.
+        let mut origin = Vec::with_capacity(lowercase.len());
+        for (index, ch) in msg.chars().enumerate() {
+            let length: usize = ch.to_lowercase().map(char::len_utf8).sum();
+            origin.extend(std::iter::repeat_n(index, length));
+        }
+        debug_assert_eq!(origin.len(), lowercase.len());
+
+        let mut redacted = vec![false; msg.chars().count()];
+        let mut found = false;
+        for found_word in matcher.find_iter(&lowercase) {
+            found = true;
+            for flag in &mut redacted[origin[found_word.start()]..=origin[found_word.end() - 1]] {
+                *flag = true;
+            }
+        }
+        if !found {
+            return None;
+        }
+        Some(
+            msg.chars()
+                .zip(redacted)
+                .map(|(ch, redacted)| if redacted { '*' } else { ch })
+                .collect(),
+        )
+    }
+}
+
+impl Default for WordFilter {
+    fn default() -> WordFilter {
+        WordFilter::new(BTreeSet::new())
+    }
+}
+
+/// Normalizes a word, so it can be compared with the words of a [`WordFilter`].
+pub fn normalize_word(word: &str) -> String {
+    word.trim().to_lowercase()
+}
+
+fn read_words(path: &Path) -> Result<BTreeSet<String>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(BTreeSet::new());
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let mut words = BTreeSet::new();
+    for line in BufReader::new(file).lines() {
+        let word = normalize_word(&line?);
+        if !word.is_empty() {
+            words.insert(word);
+        }
+    }
+    Ok(words)
+}
+
+/// Writes `words` to a line separated file, replacing its content.
+pub fn write_words(path: &Path, words: &BTreeSet<String>) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for word in words {
+        writeln!(writer, "{}", word)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
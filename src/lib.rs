@@ -0,0 +1,23 @@
+//! A chat server for Minecraft modifications using the Mojang authentication scheme and websockets.
+//!
+//! The server can either be run with the `axochat` binary
+//! or embedded into another actix-web application using [`chat::ChatServerBuilder`].
+
+pub mod auth;
+pub mod chat;
+pub mod config;
+pub mod emote;
+pub mod error;
+pub mod filter;
+pub mod message;
+pub mod moderation;
+mod redis;
+pub mod rules;
+pub mod selftest;
+pub mod signing;
+pub mod storage;
+#[cfg(feature = "testutil")]
+pub mod testutil;
+pub mod tls;
+pub mod transform;
+pub mod version;
@@ -1,30 +1,20 @@
-mod auth;
-mod chat;
-mod config;
-mod error;
-mod message;
-mod moderation;
-
-use config::Config;
-use error::*;
+use axochat::{
+    auth,
+    chat::{self, ChatServerBuilder},
+    config::{self, Config},
+    error::*,
+    selftest, version,
+};
 use log::*;
 use structopt::*;
 
 use actix::*;
-use actix_web::{web, App, HttpServer};
+use actix_web::{App, HttpServer};
+use std::io::{self, Write};
 use uuid::Uuid;
 
-#[cfg(feature = "rust-tls")]
-use {
-    rustls::{
-        internal::pemfile::{certs, rsa_private_keys},
-        NoClientAuth, ServerConfig,
-    },
-    std::{fs::File, io::BufReader},
-};
-
-#[cfg(feature = "ssl")]
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+#[cfg(any(feature = "ssl", feature = "rust-tls"))]
+use axochat::tls;
 
 #[derive(StructOpt)]
 enum Opt {
@@ -41,10 +31,20 @@ enum Opt {
         #[structopt(name = "uuid")]
         uuid: Option<Uuid>,
     },
+    /// Prints a machine-readable description of all packets as JSON.
+    #[structopt(name = "dump-schema")]
+    DumpSchema,
+    /// Checks the configuration, the files it refers to and the reachability of Mojang,
+    /// without starting the server. Exits with an error if a check failed.
+    #[structopt(name = "self-test")]
+    SelfTest,
 }
 
 fn main() -> Result<()> {
-    env_logger::init();
+    version::mark_started();
+    env_logger::Builder::from_default_env()
+        .format(format_log)
+        .init();
 
     let config = config::read_config()?;
     debug!("Read configuration file: {:?}", config);
@@ -63,23 +63,72 @@ fn main() -> Result<()> {
             let token = auth.new_token(auth::UserInfo {
                 name,
                 uuid: uuid.unwrap_or_else(|| Uuid::from_u128(0)),
+                bot: false,
             })?;
             println!("{}", token);
             Ok(())
         }
+        Opt::DumpSchema => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&chat::protocol_schema())?
+            );
+            Ok(())
+        }
+        Opt::SelfTest => self_test(config),
     }
 }
 
+fn self_test(config: Config) -> Result<()> {
+    let mut system = System::new("axochat-self-test");
+    let mut results = selftest::run_local_checks(&config);
+    let mojang = system
+        .block_on(selftest::check_mojang(selftest::MOJANG_TIMEOUT))
+        .expect("checks do not fail");
+    results.push(("mojang", mojang));
+
+    let width = results
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    for (name, outcome) in &results {
+        println!("{:width$}  {}", name, outcome, width = width);
+    }
+    if results.iter().any(|(_, outcome)| outcome.is_failed()) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Formats log events like `env_logger` does, with the trace of the packet being handled.
+fn format_log(buf: &mut env_logger::fmt::Formatter, record: &Record) -> io::Result<()> {
+    write!(
+        buf,
+        "[{} {:<5} {}",
+        buf.timestamp(),
+        buf.default_styled_level(record.level()),
+        record.module_path().unwrap_or_default()
+    )?;
+    if let Some(trace) = chat::current_trace() {
+        write!(buf, " trace={}", trace)?;
+    }
+    writeln!(buf, "] {}", record.args())
+}
+
 fn start_server(config: Config) -> Result<()> {
+    info!(
+        "Starting axochat {} (commit {}, built {})",
+        version::VERSION,
+        version::GIT_COMMIT,
+        version::BUILD_TIMESTAMP
+    );
     let system = System::new("axochat");
-    let server_config = config.clone();
-    let server = chat::ChatServer::new(server_config).start();
+    let chat = ChatServerBuilder::new(config.clone()).start()?;
+    #[cfg(unix)]
+    chat.drain_on_signal();
 
-    let server = HttpServer::new(move || {
-        App::new()
-            .data(server.clone())
-            .service(web::resource("/ws").to(chat::chat_route))
-    });
+    let server = HttpServer::new(move || App::new().configure(|cfg| chat.configure(cfg)));
 
     if let (Some(cert), Some(key)) = (config.net.cert_file, config.net.key_file) {
         #[cfg(all(feature = "ssl", feature = "rust-tls"))]
@@ -89,26 +138,14 @@ fn start_server(config: Config) -> Result<()> {
 
         #[cfg(feature = "ssl")]
         {
-            let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-            builder.set_certificate_chain_file(cert)?;
-            let ft = match key.extension() {
-                Some(ext) if ext == "pem" => SslFiletype::PEM,
-                _ => SslFiletype::ASN1,
-            };
-            builder.set_private_key_file(key, ft)?;
-
+            let builder = tls::load_ssl(&cert, &key)?;
             server.bind_ssl(config.net.address, builder)?.start();
         }
 
         #[cfg(feature = "rust-tls")]
         {
-            let mut config = ServerConfig::new(NoClientAuth::new());
-            let mut cert_file = BufReader::new(File::open(cert)?);
-            let cert_chain = certs(&mut cert_file).or_else(|()| Err(Error::RustTLSNoMsg))?;
-            let mut key_file = BufReader::new(File::open(key)?);
-            let mut keys = rsa_private_keys(&mut key_file)?;
-            config.set_single_cert(cert_chain, keys.remove(0))?;
-            server.bind_rustls(config.net.address, config)?.start();
+            let tls_config = tls::load_rustls(&cert, &key)?;
+            server.bind_rustls(config.net.address, tls_config)?.start();
         }
     } else {
         server.bind(config.net.address)?.start();
@@ -1,7 +1,15 @@
 use crate::error::*;
 
-use crate::config::MsgConfig;
-use std::{collections::VecDeque, time::Instant};
+use crate::config::{BlankLines, BlockedWordsAction, LengthUnit, MsgConfig, ValidationConfig};
+use crate::filter::WordFilter;
+use crate::rules::{self, Links, Rejection, RuleContext, ValidationRule};
+use serde::{Serialize, Serializer};
+use std::{
+    collections::VecDeque,
+    fmt, mem,
+    time::{Duration, Instant},
+};
+use unicode_segmentation::UnicodeSegmentation;
 
 pub struct RateLimiter {
     buf: VecDeque<(Instant, String)>,
@@ -16,24 +24,47 @@ impl RateLimiter {
         }
     }
 
-    /// Returns if a new message in this instant would be rate limited.
+    /// The approximate number of bytes kept for the recent messages.
+    pub fn memory_footprint(&self) -> usize {
+        mem::size_of::<RateLimiter>()
+            + self.buf.capacity() * mem::size_of::<(Instant, String)>()
+            + self
+                .buf
+                .iter()
+                .map(|(_, message)| message.capacity())
+                .sum::<usize>()
+    }
+
+    /// The `count_duration` in which messages are counted.
+    pub fn window(&self) -> Duration {
+        *self.cfg.count_duration
+    }
+
+    /// The number of messages counted in the `count_duration` before `now`.
+    pub fn recent_messages(&self, now: Instant) -> usize {
+        let limit = now - *self.cfg.count_duration;
+        self.buf.iter().filter(|(time, _)| *time >= limit).count()
+    }
+
+    /// Returns if a new message at `now` would be rate limited,
+    /// allowing at most `max_messages` in `count_duration`.
     /// If not, then it registers the new message instant.
-    pub fn check_new_message(&mut self, message: String) -> bool {
-        let now = Instant::now();
+    pub fn check_new_message(
+        &mut self,
+        message: String,
+        max_messages: usize,
+        now: Instant,
+    ) -> bool {
         let limit = now - *self.cfg.count_duration;
 
-        #[allow(clippy::op_ref)]
-        let last_index = self
+        let expired = self
             .buf
             .iter()
             .take_while(|(time, _)| *time < limit)
-            .enumerate()
-            .map(|(i, _)| i)
-            .last()
-            .unwrap_or(0);
-        self.buf.drain(..last_index);
+            .count();
+        self.buf.drain(..expired);
 
-        if self.buf.len() < self.cfg.max_messages {
+        if self.buf.len() < max_messages {
             let message_found = self.buf.iter().any(|(_, msg)| &message == msg);
             if message_found {
                 true
@@ -47,29 +78,289 @@ impl RateLimiter {
     }
 }
 
+/// The content of a message which passed [`MessageValidator::validate`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ValidatedContent {
+    content: String,
+    /// Whether blocked words of the content were redacted.
+    redacted: bool,
+}
+
+impl ValidatedContent {
+    /// Wraps content which is not checked by this validator,
+    /// because another instance of the cluster validated it.
+    pub fn trusted(content: String, redacted: bool) -> ValidatedContent {
+        ValidatedContent { content, redacted }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.content
+    }
+
+    pub fn into_string(self) -> String {
+        self.content
+    }
+
+    pub fn is_redacted(&self) -> bool {
+        self.redacted
+    }
+}
+
+/// Only the content is serialized.
+impl Serialize for ValidatedContent {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.content)
+    }
+}
+
+impl AsRef<str> for ValidatedContent {
+    fn as_ref(&self) -> &str {
+        &self.content
+    }
+}
+
+impl From<ValidatedContent> for String {
+    fn from(content: ValidatedContent) -> String {
+        content.content
+    }
+}
+
+impl fmt::Display for ValidatedContent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.content)
+    }
+}
+
 pub struct MessageValidator {
     cfg: MsgConfig,
+    validation: ValidationConfig,
+    word_filter: WordFilter,
+    /// The rules checked by [`MessageValidator::validate`], in order.
+    rules: Vec<Box<dyn ValidationRule>>,
+    links: Links,
 }
 
 impl MessageValidator {
-    pub fn new(cfg: MsgConfig) -> MessageValidator {
-        MessageValidator { cfg }
+    /// Creates a validator without any blocked words.
+    ///
+    /// Fails if the options of a rule are invalid.
+    pub fn new(cfg: MsgConfig, validation: ValidationConfig) -> Result<MessageValidator> {
+        let rules = rules::content_rules(&cfg, &validation)?;
+        let links = rules::link_rule(&validation)?;
+        Ok(MessageValidator {
+            cfg,
+            validation,
+            word_filter: WordFilter::default(),
+            rules,
+            links,
+        })
+    }
+
+    pub fn word_filter(&self) -> &WordFilter {
+        &self.word_filter
+    }
+
+    pub fn set_word_filter(&mut self, word_filter: WordFilter) {
+        self.word_filter = word_filter;
     }
 
-    pub fn validate(&self, msg: &str) -> Result<()> {
+    /// Checks the length, characters, lines and words of `msg`.
+    ///
+    /// The validated content has its line breaks normalized, see [`MessageValidator::normalize`],
+    /// and its blocked words redacted if `validation.blocked_words_action` is `redact`.
+    pub fn validate(&self, msg: &str) -> Result<ValidatedContent> {
+        self.check(msg).map_err(Error::from)
+    }
+
+    /// Like [`MessageValidator::validate`], but tells which rule rejected `msg`.
+    pub fn check(&self, msg: &str) -> std::result::Result<ValidatedContent, Rejection> {
+        let msg = self.normalize(msg);
+        match self.rejections(&msg, false, true).into_iter().next() {
+            Some(rejection) => Err(rejection),
+            None => Ok(self.redact(msg)),
+        }
+    }
+
+    /// Normalizes and redacts `msg` like [`MessageValidator::validate`] without checking it,
+    /// for messages which are let through because of `validation.dry_run`.
+    pub fn unchecked(&self, msg: &str) -> ValidatedContent {
+        self.redact(self.normalize(msg))
+    }
+
+    /// Returns every rule `msg` violates, including the link policy,
+    /// instead of only the first one like [`MessageValidator::validate`].
+    pub fn violations(&self, msg: &str, is_moderator: bool) -> Vec<Rejection> {
+        let msg = self.normalize(msg);
+        let mut violations = self.rejections(&msg, is_moderator, false);
+        if let Err(rejection) = self.check_links(&msg, is_moderator) {
+            violations.push(rejection);
+        }
+        violations
+    }
+
+    /// Replaces `\r\n` and `\r` in `msg` with `\n` if `validation.allow_newlines` is enabled,
+    /// and collapses consecutive blank lines into one if `validation.blank_lines` is `collapse`.
+    pub fn normalize(&self, msg: &str) -> String {
+        if !self.validation.allow_newlines {
+            return msg.to_string();
+        }
+        let msg = msg.replace("\r\n", "\n").replace('\r', "\n");
+        if self.validation.blank_lines != BlankLines::Collapse {
+            return msg;
+        }
+        let mut previous_blank = false;
+        msg.split('\n')
+            .filter(|line| {
+                let blank = line.trim().is_empty();
+                let keep = !(blank && previous_blank);
+                previous_blank = blank;
+                keep
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Returns the violated rules checked by [`MessageValidator::validate`], in the order it checks them,
+    /// or only the first one if `first` is set.
+    ///
+    /// `msg` has to be normalized already.
+    fn rejections(&self, msg: &str, is_moderator: bool, first: bool) -> Vec<Rejection> {
+        let context = RuleContext {
+            word_filter: &self.word_filter,
+            is_moderator,
+        };
+        let mut rejections = Vec::new();
+        for rule in &self.rules {
+            if let Some(error) = rule.check(msg, &context) {
+                rejections.push(Rejection {
+                    rule: rule.name(),
+                    error,
+                });
+                if first || rule.is_final() {
+                    break;
+                }
+            }
+        }
+        rejections
+    }
+
+    /// Redacts the blocked words in `msg` if `validation.blocked_words_action` is `redact`.
+    fn redact(&self, msg: String) -> ValidatedContent {
+        let redacted = match self.validation.blocked_words_action {
+            BlockedWordsAction::Reject => None,
+            BlockedWordsAction::Redact => self.word_filter.redact(&msg),
+        };
+        match redacted {
+            Some(content) => ValidatedContent::trusted(content, true),
+            None => ValidatedContent::trusted(msg, false),
+        }
+    }
+
+    /// Checks a message sent by the operator of the server, which is only limited in length,
+    /// by `message.max_system_length` instead of `message.max_length`.
+    pub fn validate_system(&self, msg: &str) -> Result<ValidatedContent> {
         if msg.is_empty() {
             return Err(ClientError::EmptyMessage.into());
         }
 
-        for (char_index, ch) in msg.chars().enumerate() {
-            if char_index >= self.cfg.max_length {
-                return Err(ClientError::MessageTooLong.into());
-            }
-            if ch != ' ' && !ch.is_ascii_graphic() && !ch.is_alphanumeric() {
-                return Err(ClientError::InvalidCharacter(ch).into());
+        let unit = self.validation.length_unit;
+        let length = message_length(msg, unit);
+        if length > self.cfg.max_system_length {
+            return Err(ClientError::MessageTooLong {
+                length,
+                max_length: self.cfg.max_system_length,
+                unit,
             }
+            .into());
+        }
+
+        Ok(ValidatedContent::trusted(msg.to_string(), false))
+    }
+
+    /// Checks the URLs in `msg` against the link policy.
+    /// Moderators can be exempt from this check.
+    pub fn validate_links(&self, msg: &str, is_moderator: bool) -> Result<()> {
+        self.check_links(msg, is_moderator).map_err(Error::from)
+    }
+
+    /// Like [`MessageValidator::validate_links`], but tells which rule rejected `msg`.
+    pub fn check_links(&self, msg: &str, is_moderator: bool) -> std::result::Result<(), Rejection> {
+        let context = RuleContext {
+            word_filter: &self.word_filter,
+            is_moderator,
+        };
+        match self.links.check(msg, &context) {
+            Some(error) => Err(Rejection {
+                rule: self.links.name(),
+                error,
+            }),
+            None => Ok(()),
         }
+    }
+}
 
-        Ok(())
+/// Returns the length of `msg` in `unit`.
+pub fn message_length(msg: &str, unit: LengthUnit) -> usize {
+    match unit {
+        LengthUnit::Chars => msg.chars().count(),
+        LengthUnit::Graphemes => msg.graphemes(true).count(),
+        LengthUnit::Bytes => msg.len(),
     }
 }
+
+/// Top level domains which are detected even if a URL has neither a scheme nor `www.`.
+const COMMON_TLDS: &[&str] = &[
+    "com", "net", "org", "info", "biz", "io", "gg", "me", "tv", "co", "cc", "to", "ly", "xyz",
+    "eu", "de", "uk", "us", "ru", "fr", "nl",
+];
+
+/// Returns the first URL in `msg` in its normalized form.
+pub fn find_url(msg: &str) -> Option<String> {
+    find_urls(msg).into_iter().next()
+}
+
+/// Returns all words of `msg` which look like URLs in their normalized form.
+///
+/// This only uses heuristics; a word is a URL if it contains a scheme, starts with `www.`
+/// or ends with a common top level domain.
+/// Obfuscations like `example(dot)com` are detected too.
+pub(crate) fn find_urls(msg: &str) -> Vec<String> {
+    normalize_urls(msg)
+        .split_whitespace()
+        .map(|word| word.trim_matches(|ch: char| !ch.is_alphanumeric() && ch != '/'))
+        .filter(|word| {
+            word.contains("://") || word.starts_with("www.") || {
+                let domain = url_domain(word);
+                let mut labels = domain.rsplit('.');
+                let tld = labels.next().unwrap_or("");
+                labels.next().is_some_and(|label| !label.is_empty()) && COMMON_TLDS.contains(&tld)
+            }
+        })
+        .map(String::from)
+        .collect()
+}
+
+/// Lowercases `msg` and replaces common obfuscations of dots with real dots.
+fn normalize_urls(msg: &str) -> String {
+    let mut msg = msg.to_lowercase().replace(['\u{3002}', '\u{ff0e}'], ".");
+    for (open, close) in &[("(", ")"), ("[", "]"), ("{", "}")] {
+        for dot in &["dot", "."] {
+            let obfuscated = format!("{}{}{}", open, dot, close);
+            msg = msg
+                .replace(&format!(" {} ", obfuscated), ".")
+                .replace(&obfuscated, ".");
+        }
+    }
+    msg
+}
+
+/// Returns the host part of a normalized URL.
+pub(crate) fn url_domain(url: &str) -> &str {
+    let url = match url.find("://") {
+        Some(index) => &url[index + 3..],
+        None => url,
+    };
+    let end = url.find(['/', ':', '?', '#']).unwrap_or(url.len());
+    let domain = &url[..end];
+    domain.strip_prefix("www.").unwrap_or(domain)
+}
@@ -1,27 +1,103 @@
 use crate::config::ModConfig;
 use crate::error::*;
-use std::collections::HashSet;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::{
-    fs::{File, OpenOptions},
-    io::{BufRead, BufReader, BufWriter, Write},
-    path::Path,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use uuid::Uuid;
 
+/// A ban or mute, with why and for how long it was given.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Restriction {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// When the restriction ends, in seconds since the unix epoch; `None` if it is permanent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl Restriction {
+    /// A restriction for `reason` which ends `duration` after `now`, or never if it is `None`.
+    pub fn new(reason: Option<String>, duration: Option<Duration>, now: SystemTime) -> Restriction {
+        Restriction {
+            reason,
+            expires_at: duration.map(|duration| unix_secs(now).saturating_add(duration.as_secs())),
+        }
+    }
+
+    /// Returns how long the restriction lasts after `now`, or `None` if it is permanent.
+    pub fn remaining(&self, now: SystemTime) -> Option<Duration> {
+        self.expires_at
+            .map(|at| Duration::from_secs(at.saturating_sub(unix_secs(now))))
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|at| unix_secs(now) >= at)
+    }
+}
+
+/// The banned, muted and whitelisted users, as exported and imported by the admin API.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModerationState {
+    pub banned: Vec<Uuid>,
+    pub whitelisted: Vec<Uuid>,
+    /// The reasons and ends of the bans in `banned` which have any.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub ban_details: BTreeMap<Uuid, Restriction>,
+    /// The muted users, with the reasons and ends of their mutes.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub mutes: BTreeMap<Uuid, Restriction>,
+}
+
+/// A page of the banned users, as returned by [`Moderation::banned_page`].
+#[derive(Debug, Clone, Serialize)]
+pub struct BanPage {
+    /// The number of banned users matching the filter, on all pages.
+    pub total: usize,
+    pub banned: Vec<Uuid>,
+}
+
+/// How an imported [`ModerationState`] is combined with the current one.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// The imported users are added to the current ones.
+    Merge,
+    /// The current users are replaced.
+    #[default]
+    Replace,
+}
+
 pub struct Moderation {
     config: ModConfig,
     moderators: HashSet<Uuid>,
-    banned: HashSet<Uuid>,
+    banned: HashMap<Uuid, Restriction>,
+    /// The muted users, which are not saved.
+    muted: HashMap<Uuid, Restriction>,
+    whitelisted: HashSet<Uuid>,
+    /// Whether the banned users changed since the file could last be written.
+    dirty: bool,
 }
 
 impl Moderation {
     pub fn new(config: ModConfig) -> Result<Moderation> {
         let moderators = read_ids(&config.moderators)?;
-        let banned = read_ids(&config.banned)?;
+        let banned = read_bans(&config.banned)?;
+        let whitelisted = read_ids(&config.whitelisted)?;
         Ok(Moderation {
             config,
             moderators,
             banned,
+            muted: HashMap::new(),
+            whitelisted,
+            dirty: false,
         })
     }
 
@@ -29,58 +105,353 @@ impl Moderation {
         self.moderators.contains(user)
     }
 
-    /// Ban user if user is not a moderator.
-    pub fn ban(&mut self, user: &Uuid) -> Result<()> {
+    /// Ban user if user is not a moderator, replacing an earlier ban.
+    ///
+    /// If the file can not be written, the user is banned anyway
+    /// and [`Moderation::is_dirty`] returns `true` until [`Moderation::flush`] succeeds.
+    pub fn ban(&mut self, user: &Uuid, ban: Restriction) -> Result<()> {
         if self.is_moderator(user) {
             Err(ClientError::NotPermitted.into())
         } else {
-            if self.banned.insert(user.clone()) {
-                let mut file = OpenOptions::new()
-                    .append(true)
-                    .create(true)
-                    .open(&self.config.banned)?;
-
-                writeln!(file, "{}", user.to_hyphenated())?;
+            if self.banned.get(user) != Some(&ban) {
+                let res = if self.dirty {
+                    self.banned.insert(*user, ban);
+                    self.flush()
+                } else {
+                    // Later lines replace earlier ones when the file is read.
+                    let res = self.append_banned(user, &ban);
+                    self.banned.insert(*user, ban);
+                    res
+                };
+                self.record_write(res);
             }
 
             Ok(())
         }
     }
 
+    /// Unbans user; like [`Moderation::ban`], this succeeds even if the file can not be written.
     pub fn unban(&mut self, user: &Uuid) -> Result<()> {
-        if self.banned.remove(user) {
-            let mut writer = BufWriter::new(File::create(&self.config.banned)?);
-
-            for banned in &self.banned {
-                writeln!(writer, "{}", banned)?;
-            }
-
+        if self.banned.remove(user).is_some() {
+            let res = self.flush();
+            self.record_write(res);
             Ok(())
         } else {
             Err(ClientError::NotBanned.into())
         }
     }
 
-    pub fn is_banned(&self, user: &Uuid) -> bool {
-        self.banned.contains(user)
+    /// Returns whether the banned users changed since the file could last be written.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Replaces the file of the banned users with the ones in memory.
+    pub fn flush(&mut self) -> Result<()> {
+        let tmp = write_temporary(&self.config.banned, ban_lines(&self.banned))?;
+        fs::rename(tmp, &self.config.banned)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn append_banned(&self, user: &Uuid, ban: &Restriction) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.config.banned)?;
+        writeln!(file, "{}", ban_line(user, ban))?;
+        Ok(())
+    }
+
+    fn record_write(&mut self, res: Result<()>) {
+        if let Err(err) = res {
+            error!(
+                "Could not write the banned users, keeping the change in memory: {}",
+                err
+            );
+            self.dirty = true;
+        }
+    }
+
+    /// Returns whether `user` is banned at `now`.
+    pub fn is_banned(&self, user: &Uuid, now: SystemTime) -> bool {
+        self.ban_of(user, now).is_some()
+    }
+
+    /// Returns the ban of `user`, unless it ended before `now`.
+    pub fn ban_of(&self, user: &Uuid, now: SystemTime) -> Option<&Restriction> {
+        self.banned.get(user).filter(|ban| !ban.is_expired(now))
+    }
+
+    /// Mutes user if user is not a moderator, replacing an earlier mute.
+    ///
+    /// Mutes always end and are only kept in memory.
+    pub fn mute(&mut self, user: &Uuid, reason: Option<String>, expires_at: u64) -> Result<()> {
+        if self.is_moderator(user) {
+            return Err(ClientError::NotPermitted.into());
+        }
+        let mute = Restriction {
+            reason,
+            expires_at: Some(expires_at),
+        };
+        self.muted.insert(*user, mute);
+        Ok(())
+    }
+
+    pub fn unmute(&mut self, user: &Uuid) -> Result<()> {
+        match self.muted.remove(user) {
+            Some(_) => Ok(()),
+            None => Err(ClientError::NotMuted.into()),
+        }
+    }
+
+    /// Returns the mute of `user`, unless it ended before `now`.
+    pub fn mute_of(&self, user: &Uuid, now: SystemTime) -> Option<&Restriction> {
+        self.muted.get(user).filter(|mute| !mute.is_expired(now))
     }
+
+    /// Removes the bans and mutes which ended before `now`
+    /// and returns the users who are not restricted by them anymore.
+    pub fn prune_expired(&mut self, now: SystemTime) -> Vec<Uuid> {
+        let mut lapsed = Vec::new();
+        self.banned.retain(|user, ban| {
+            let expired = ban.is_expired(now);
+            if expired {
+                lapsed.push(*user);
+            }
+            !expired
+        });
+        if !lapsed.is_empty() {
+            let res = self.flush();
+            self.record_write(res);
+        }
+        self.muted.retain(|user, mute| {
+            let expired = mute.is_expired(now);
+            if expired {
+                lapsed.push(*user);
+            }
+            !expired
+        });
+        lapsed
+    }
+
+    pub fn is_whitelisted(&self, user: &Uuid) -> bool {
+        self.whitelisted.contains(user)
+    }
+
+    /// Returns up to `limit` banned users whose hyphenated uuid starts with `prefix`,
+    /// skipping the first `offset`.
+    ///
+    /// The users are sorted by uuid, so the pages are stable while nobody is (un-)banned.
+    pub fn banned_page(&self, prefix: &str, offset: usize, limit: usize) -> BanPage {
+        let prefix = prefix.to_lowercase();
+        let mut banned: Vec<_> = self
+            .banned
+            .keys()
+            .filter(|user| user.to_hyphenated().to_string().starts_with(&prefix))
+            .copied()
+            .collect();
+        banned.sort();
+        BanPage {
+            total: banned.len(),
+            banned: banned.into_iter().skip(offset).take(limit).collect(),
+        }
+    }
+
+    /// Returns the banned, muted and whitelisted users, sorted,
+    /// leaving out the bans and mutes which ended before `now`.
+    pub fn export(&self, now: SystemTime) -> ModerationState {
+        let mut banned: Vec<_> = self
+            .banned
+            .iter()
+            .filter(|(_, ban)| !ban.is_expired(now))
+            .map(|(user, _)| *user)
+            .collect();
+        let mut whitelisted: Vec<_> = self.whitelisted.iter().copied().collect();
+        banned.sort();
+        whitelisted.sort();
+        let ban_details = self
+            .banned
+            .iter()
+            .filter(|(_, ban)| **ban != Restriction::default() && !ban.is_expired(now))
+            .map(|(user, ban)| (*user, ban.clone()))
+            .collect();
+        let mutes = self
+            .muted
+            .iter()
+            .filter(|(_, mute)| !mute.is_expired(now))
+            .map(|(user, mute)| (*user, mute.clone()))
+            .collect();
+        ModerationState {
+            banned,
+            whitelisted,
+            ban_details,
+            mutes,
+        }
+    }
+
+    /// Replaces the banned, muted and whitelisted users or merges `state` into them.
+    ///
+    /// Nothing is changed if `state` bans or mutes a moderator,
+    /// has details of a user it does not ban, a mute without an end
+    /// or a ban or mute which ended before `now`.
+    /// Both files are written completely before either replaces the old one,
+    /// and the file of the banned users is restored if the other can not be replaced,
+    /// so a failed import leaves them untouched as well.
+    pub fn import(
+        &mut self,
+        mut state: ModerationState,
+        mode: ImportMode,
+        now: SystemTime,
+    ) -> Result<()> {
+        if state
+            .banned
+            .iter()
+            .chain(state.mutes.keys())
+            .any(|user| self.is_moderator(user))
+        {
+            return Err(ClientError::NotPermitted.into());
+        }
+        let imported: HashSet<&Uuid> = state.banned.iter().collect();
+        if state
+            .ban_details
+            .keys()
+            .any(|user| !imported.contains(user))
+            || state.ban_details.values().any(|ban| ban.is_expired(now))
+            || state
+                .mutes
+                .values()
+                .any(|mute| mute.expires_at.is_none() || mute.is_expired(now))
+        {
+            return Err(ClientError::InvalidImport.into());
+        }
+
+        let (mut banned, mut muted, mut whitelisted) = match mode {
+            ImportMode::Merge => (
+                self.banned.clone(),
+                self.muted.clone(),
+                self.whitelisted.clone(),
+            ),
+            ImportMode::Replace => (HashMap::new(), HashMap::new(), HashSet::new()),
+        };
+        for user in state.banned {
+            let ban = state.ban_details.remove(&user).unwrap_or_default();
+            banned.insert(user, ban);
+        }
+        muted.extend(state.mutes);
+        whitelisted.extend(state.whitelisted);
+
+        let banned_tmp = write_temporary(&self.config.banned, ban_lines(&banned))?;
+        let whitelisted_tmp = write_temporary(
+            &self.config.whitelisted,
+            whitelisted.iter().map(|id| id.to_hyphenated().to_string()),
+        )?;
+        fs::rename(banned_tmp, &self.config.banned)?;
+        if let Err(err) = fs::rename(whitelisted_tmp, &self.config.whitelisted) {
+            // The old bans are still in memory, so they replace the imported ones again.
+            let res = self.flush();
+            self.record_write(res);
+            return Err(err.into());
+        }
+
+        self.banned = banned;
+        self.muted = muted;
+        self.whitelisted = whitelisted;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+/// Formats a line of the file of the banned users.
+///
+/// Bans without a reason or end are just the uuid, like the other files;
+/// otherwise the end and the reason follow, separated by tabs.
+fn ban_line(user: &Uuid, ban: &Restriction) -> String {
+    let user = user.to_hyphenated().to_string();
+    if *ban == Restriction::default() {
+        return user;
+    }
+    let expires_at = ban.expires_at.map(|at| at.to_string()).unwrap_or_default();
+    let reason: String = ban
+        .reason
+        .as_deref()
+        .unwrap_or_default()
+        .chars()
+        .map(|ch| if ch.is_control() { ' ' } else { ch })
+        .collect();
+    format!("{}\t{}\t{}", user, expires_at, reason)
+}
+
+fn ban_lines(banned: &HashMap<Uuid, Restriction>) -> impl Iterator<Item = String> + '_ {
+    banned.iter().map(|(user, ban)| ban_line(user, ban))
+}
+
+/// Writes `lines` to a file next to `path`, which can then be renamed to `path`.
+fn write_temporary<I: Iterator<Item = String>>(path: &Path, lines: I) -> Result<PathBuf> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    for line in lines {
+        writeln!(writer, "{}", line)?;
+    }
+    writer.flush()?;
+    Ok(tmp)
 }
 
 fn read_ids(path: &Path) -> Result<HashSet<Uuid>> {
+    let mut ids = HashSet::new();
+    for line in read_lines(path)? {
+        ids.insert(line.parse()?);
+    }
+    Ok(ids)
+}
+
+/// Reads the file of the banned users written with [`ban_line`].
+fn read_bans(path: &Path) -> Result<HashMap<Uuid, Restriction>> {
+    let mut bans = HashMap::new();
+    for line in read_lines(path)? {
+        let mut parts = line.splitn(3, '\t');
+        let user = parts.next().unwrap_or_default().parse()?;
+        let expires_at =
+            match parts.next() {
+                Some(at) if !at.is_empty() => Some(at.parse().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid end of ban")
+                })?),
+                _ => None,
+            };
+        let reason = parts.next().filter(|reason| !reason.is_empty());
+        let ban = Restriction {
+            reason: reason.map(str::to_string),
+            expires_at,
+        };
+        bans.insert(user, ban);
+    }
+    Ok(bans)
+}
+
+/// Reads the non-empty lines of `path`, creating the file if it does not exist.
+fn read_lines(path: &Path) -> Result<Vec<String>> {
     let file = match File::open(path) {
         Ok(file) => file,
-        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
             File::create(path)?;
-            return Ok(HashSet::new());
+            return Ok(Vec::new());
         }
         Err(err) => return Err(err.into()),
     };
-    let reader = BufReader::new(file);
-    let mut lines = HashSet::new();
-    for line in reader.lines() {
+    let mut lines = Vec::new();
+    for line in BufReader::new(file).lines() {
         let line = line?;
         if !line.is_empty() {
-            lines.insert(line.parse()?);
+            lines.push(line);
         }
     }
     Ok(lines)
@@ -0,0 +1,497 @@
+//! A minimal Redis client supporting just the commands clustering needs.
+
+use crate::error::*;
+use log::*;
+
+use actix::io::{FramedWrite, WriteHandler};
+use actix::*;
+use bytes::BytesMut;
+use futures::{sync::oneshot, Future};
+use std::collections::VecDeque;
+use std::{io, net::SocketAddr, str};
+use tokio_codec::{Decoder, Encoder, FramedRead};
+use tokio_io::{io::WriteHalf, AsyncRead};
+use tokio_tcp::TcpStream;
+
+/// A value of the Redis serialization protocol.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Status(String),
+    Error(String),
+    Int(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Value>>),
+}
+
+impl Value {
+    /// Creates a command consisting of `args`.
+    pub fn command<I, A>(args: I) -> Value
+    where
+        I: IntoIterator<Item = A>,
+        A: Into<Vec<u8>>,
+    {
+        Value::Array(Some(
+            args.into_iter()
+                .map(|arg| Value::Bulk(Some(arg.into())))
+                .collect(),
+        ))
+    }
+}
+
+/// The longest bulk string which is accepted, in bytes.
+const MAX_BULK_SIZE: usize = 16 * 1024 * 1024;
+/// The most values an array which is accepted can contain.
+const MAX_ARRAY_LEN: usize = 1024 * 1024;
+/// How deep arrays which are accepted can be nested.
+const MAX_DEPTH: usize = 8;
+/// The longest line, e.g. of a status or an error, which is accepted, in bytes.
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// Encodes and decodes [`Value`]s.
+///
+/// Arrays are decoded incrementally: values which were received completely
+/// are removed from the buffer, so an incomplete array is not parsed again from its start.
+#[derive(Default)]
+pub struct RespCodec {
+    /// The arrays whose values are still being received, the innermost last.
+    arrays: Vec<PartialArray>,
+    /// How many bytes the buffer has to contain before decoding can make progress.
+    needed: usize,
+}
+
+struct PartialArray {
+    len: usize,
+    values: Vec<Value>,
+}
+
+impl RespCodec {
+    pub fn new() -> RespCodec {
+        RespCodec::default()
+    }
+
+    /// Adds `value` to the innermost incomplete array,
+    /// and returns the outermost value once it is complete.
+    fn complete(&mut self, mut value: Value) -> Option<Value> {
+        while let Some(array) = self.arrays.last_mut() {
+            array.values.push(value);
+            if array.values.len() < array.len {
+                return None;
+            }
+            let array = self.arrays.pop().expect("array was just completed");
+            value = Value::Array(Some(array.values));
+        }
+        Some(value)
+    }
+}
+
+impl Encoder for RespCodec {
+    type Item = Value;
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Value, dst: &mut BytesMut) -> io::Result<()> {
+        write_value(&item, dst);
+        Ok(())
+    }
+}
+
+impl Decoder for RespCodec {
+    type Item = Value;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Value>> {
+        loop {
+            if src.len() < self.needed {
+                return Ok(None);
+            }
+            let (item, len) = match parse_item(src)? {
+                Parsed::Item(item, len) => (item, len),
+                Parsed::Incomplete { needed } => {
+                    self.needed = needed;
+                    return Ok(None);
+                }
+            };
+            src.split_to(len);
+            self.needed = 0;
+
+            let value = match item {
+                Item::Value(value) => value,
+                Item::Array(0) => Value::Array(Some(Vec::new())),
+                Item::Array(len) => {
+                    if self.arrays.len() >= MAX_DEPTH {
+                        return Err(invalid_data("arrays nested too deeply"));
+                    }
+                    self.arrays.push(PartialArray {
+                        len,
+                        values: Vec::with_capacity(len.min(1024)),
+                    });
+                    continue;
+                }
+            };
+            if let Some(value) = self.complete(value) {
+                return Ok(Some(value));
+            }
+        }
+    }
+}
+
+fn write_value(value: &Value, dst: &mut BytesMut) {
+    match value {
+        Value::Status(status) => write_line(dst, b'+', status.as_bytes()),
+        Value::Error(message) => write_line(dst, b'-', message.as_bytes()),
+        Value::Int(int) => write_line(dst, b':', int.to_string().as_bytes()),
+        Value::Bulk(None) => write_line(dst, b'$', b"-1"),
+        Value::Bulk(Some(data)) => {
+            write_line(dst, b'$', data.len().to_string().as_bytes());
+            dst.extend_from_slice(data);
+            dst.extend_from_slice(b"\r\n");
+        }
+        Value::Array(None) => write_line(dst, b'*', b"-1"),
+        Value::Array(Some(values)) => {
+            write_line(dst, b'*', values.len().to_string().as_bytes());
+            for value in values {
+                write_value(value, dst);
+            }
+        }
+    }
+}
+
+fn write_line(dst: &mut BytesMut, kind: u8, line: &[u8]) {
+    dst.extend_from_slice(&[kind]);
+    dst.extend_from_slice(line);
+    dst.extend_from_slice(b"\r\n");
+}
+
+/// The start of a value: either a complete value,
+/// or the header of an array whose values follow it.
+enum Item {
+    Value(Value),
+    Array(usize),
+}
+
+enum Parsed {
+    /// The item and its length in bytes.
+    Item(Item, usize),
+    /// `buf` is incomplete, and has to contain at least `needed` bytes to be parsed.
+    Incomplete { needed: usize },
+}
+
+/// Parses the item at the start of `buf`.
+fn parse_item(buf: &[u8]) -> io::Result<Parsed> {
+    let line_end = match buf.windows(2).position(|window| window == b"\r\n") {
+        Some(line_end) => line_end,
+        None if buf.len() > MAX_LINE_LEN + 2 => return Err(invalid_data("line too long")),
+        None => {
+            return Ok(Parsed::Incomplete {
+                needed: buf.len() + 1,
+            })
+        }
+    };
+    if line_end == 0 {
+        return Err(invalid_data("empty line"));
+    }
+    if line_end > MAX_LINE_LEN + 1 {
+        return Err(invalid_data("line too long"));
+    }
+    let line = str::from_utf8(&buf[1..line_end]).map_err(|_| invalid_data("invalid UTF-8"))?;
+    let len = line_end + 2;
+
+    let item = match buf[0] {
+        b'+' => Item::Value(Value::Status(line.to_string())),
+        b'-' => Item::Value(Value::Error(line.to_string())),
+        b':' => Item::Value(Value::Int(parse_int(line)?)),
+        b'$' => match parse_int(line)? {
+            size if size < 0 => Item::Value(Value::Bulk(None)),
+            size if size as u64 > MAX_BULK_SIZE as u64 => {
+                return Err(invalid_data("bulk string too long"))
+            }
+            size => {
+                let size = size as usize;
+                let end = len + size + 2;
+                if buf.len() < end {
+                    return Ok(Parsed::Incomplete { needed: end });
+                }
+                if &buf[len + size..end] != b"\r\n" {
+                    return Err(invalid_data("bulk string not terminated"));
+                }
+                let data = buf[len..len + size].to_vec();
+                return Ok(Parsed::Item(Item::Value(Value::Bulk(Some(data))), end));
+            }
+        },
+        b'*' => match parse_int(line)? {
+            count if count < 0 => Item::Value(Value::Array(None)),
+            count if count as u64 > MAX_ARRAY_LEN as u64 => {
+                return Err(invalid_data("array too long"))
+            }
+            count => Item::Array(count as usize),
+        },
+        _ => return Err(invalid_data("unknown value type")),
+    };
+    Ok(Parsed::Item(item, len))
+}
+
+fn parse_int(line: &str) -> io::Result<i64> {
+    line.parse().map_err(|_| invalid_data("invalid integer"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("redis: {}", message))
+}
+
+/// A connection to Redis for sending commands.
+///
+/// Replies are matched to the commands in the order they were sent.
+pub struct RedisConnection {
+    writer: FramedWrite<WriteHalf<TcpStream>, RespCodec>,
+    /// The receivers of replies to commands which were sent, but not answered yet.
+    /// Commands without a receiver only log errors.
+    pending: VecDeque<Option<oneshot::Sender<Value>>>,
+}
+
+impl RedisConnection {
+    /// Connects to `addr`, authenticating with `password` if there is one.
+    pub fn connect(
+        addr: SocketAddr,
+        password: Option<String>,
+    ) -> impl Future<Item = Addr<RedisConnection>, Error = Error> {
+        TcpStream::connect(&addr)
+            .map_err(Error::from)
+            .map(move |stream| {
+                RedisConnection::create(move |ctx| {
+                    let (reader, writer) = stream.split();
+                    ctx.add_stream(FramedRead::new(reader, RespCodec::new()));
+                    let mut connection = RedisConnection {
+                        writer: FramedWrite::new(writer, RespCodec::new(), ctx),
+                        pending: VecDeque::new(),
+                    };
+                    if let Some(password) = password {
+                        connection.send(Value::command(vec!["AUTH".into(), password]), None);
+                    }
+                    connection
+                })
+            })
+    }
+
+    fn send(&mut self, command: Value, reply: Option<oneshot::Sender<Value>>) {
+        self.pending.push_back(reply);
+        self.writer.write(command);
+    }
+}
+
+impl Actor for RedisConnection {
+    type Context = Context<Self>;
+}
+
+impl WriteHandler<io::Error> for RedisConnection {
+    fn error(&mut self, err: io::Error, _ctx: &mut Self::Context) -> Running {
+        warn!("Could not write to redis: {}", err);
+        Running::Stop
+    }
+}
+
+impl StreamHandler<Value, io::Error> for RedisConnection {
+    fn handle(&mut self, value: Value, _ctx: &mut Context<Self>) {
+        match self.pending.pop_front() {
+            Some(Some(reply)) => {
+                reply.send(value).ok();
+            }
+            Some(None) => {
+                if let Value::Error(message) = value {
+                    warn!("Redis command failed: {}", message);
+                }
+            }
+            None => warn!("Received unexpected reply from redis: {:?}", value),
+        }
+    }
+
+    fn error(&mut self, err: io::Error, _ctx: &mut Context<Self>) -> Running {
+        warn!("Could not read from redis: {}", err);
+        Running::Stop
+    }
+}
+
+/// Sends a command and resolves to its reply.
+///
+/// Error replies are turned into errors.
+pub struct Command(pub Value);
+
+impl Message for Command {
+    type Result = io::Result<Value>;
+}
+
+impl Handler<Command> for RedisConnection {
+    type Result = ResponseFuture<Value, io::Error>;
+
+    fn handle(&mut self, msg: Command, _ctx: &mut Context<Self>) -> Self::Result {
+        let (reply, rx) = oneshot::channel();
+        self.send(msg.0, Some(reply));
+        Box::new(
+            rx.map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "redis connection closed"))
+                .and_then(|value| match value {
+                    Value::Error(message) => Err(io::Error::other(format!("redis: {}", message))),
+                    value => Ok(value),
+                }),
+        )
+    }
+}
+
+/// Sends a command without waiting for its reply.
+pub struct FireCommand(pub Value);
+
+impl Message for FireCommand {
+    type Result = ();
+}
+
+impl Handler<FireCommand> for RedisConnection {
+    type Result = ();
+
+    fn handle(&mut self, msg: FireCommand, _ctx: &mut Context<Self>) {
+        self.send(msg.0, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(value: &Value) -> BytesMut {
+        let mut buf = BytesMut::new();
+        write_value(value, &mut buf);
+        buf
+    }
+
+    /// Decodes all values in `chunks`, which are received one after another.
+    fn decode_chunks(chunks: &[&[u8]]) -> io::Result<Vec<Value>> {
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::new();
+        let mut values = Vec::new();
+        for chunk in chunks {
+            buf.extend_from_slice(chunk);
+            while let Some(value) = codec.decode(&mut buf)? {
+                values.push(value);
+            }
+        }
+        assert!(buf.is_empty(), "left over: {:?}", buf);
+        Ok(values)
+    }
+
+    fn sample() -> Value {
+        Value::Array(Some(vec![
+            Value::Status("OK".to_string()),
+            Value::Error("ERR unknown command".to_string()),
+            Value::Int(-42),
+            Value::Bulk(None),
+            Value::Bulk(Some(b"with\r\nnewline".to_vec())),
+            Value::Array(None),
+            Value::Array(Some(Vec::new())),
+            Value::Array(Some(vec![Value::Bulk(Some(Vec::new())), Value::Int(7)])),
+        ]))
+    }
+
+    fn error_of(result: io::Result<Vec<Value>>) -> String {
+        let err = result.expect_err("decoding should fail");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        err.to_string()
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let value = sample();
+        assert_eq!(decode_chunks(&[&encode(&value)]).unwrap(), vec![value]);
+    }
+
+    #[test]
+    fn values_split_anywhere_are_decoded() {
+        let value = sample();
+        let buf = encode(&value);
+        for at in 0..=buf.len() {
+            let (first, second) = buf.split_at(at);
+            assert_eq!(
+                decode_chunks(&[first, second]).unwrap(),
+                vec![value.clone()],
+                "split at {}",
+                at
+            );
+        }
+        let bytes: Vec<&[u8]> = buf.chunks(1).collect();
+        assert_eq!(decode_chunks(&bytes).unwrap(), vec![value]);
+    }
+
+    #[test]
+    fn pipelined_values_are_decoded_in_order() {
+        let mut buf = encode(&Value::Int(1));
+        buf.extend_from_slice(&encode(&sample()));
+        buf.extend_from_slice(&encode(&Value::Status("PONG".to_string())));
+        assert_eq!(
+            decode_chunks(&[&buf]).unwrap(),
+            vec![Value::Int(1), sample(), Value::Status("PONG".to_string())]
+        );
+    }
+
+    #[test]
+    fn received_values_of_incomplete_arrays_are_not_parsed_again() {
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::from(&b"*3\r\n:1\r\n$5\r\nhel"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        // The header and the first value were consumed; the bulk string waits for its end.
+        assert_eq!(&buf[..], b"$5\r\nhel");
+        assert_eq!(codec.needed, 4 + 5 + 2);
+        buf.extend_from_slice(b"l");
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"o\r\n:3\r\n");
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Value::Array(Some(vec![
+                Value::Int(1),
+                Value::Bulk(Some(b"hello".to_vec())),
+                Value::Int(3),
+            ])))
+        );
+        assert!(codec.arrays.is_empty());
+    }
+
+    #[test]
+    fn large_announced_sizes_are_rejected() {
+        let bulk = format!("${}\r\n", MAX_BULK_SIZE + 1);
+        assert_eq!(
+            error_of(decode_chunks(&[bulk.as_bytes()])),
+            "redis: bulk string too long"
+        );
+        let array = format!("*{}\r\n", MAX_ARRAY_LEN + 1);
+        assert_eq!(
+            error_of(decode_chunks(&[array.as_bytes()])),
+            "redis: array too long"
+        );
+        let line = vec![b'+'; MAX_LINE_LEN + 3];
+        assert_eq!(error_of(decode_chunks(&[&line])), "redis: line too long");
+    }
+
+    #[test]
+    fn deeply_nested_arrays_are_rejected() {
+        let mut nested = Value::Int(1);
+        for _ in 0..MAX_DEPTH {
+            nested = Value::Array(Some(vec![nested]));
+        }
+        assert_eq!(
+            decode_chunks(&[&encode(&nested)]).unwrap(),
+            vec![nested.clone()]
+        );
+        let too_deep = Value::Array(Some(vec![nested]));
+        assert_eq!(
+            error_of(decode_chunks(&[&encode(&too_deep)])),
+            "redis: arrays nested too deeply"
+        );
+    }
+
+    #[test]
+    fn malformed_values_are_rejected() {
+        for (input, message) in &[
+            (&b"\r\n"[..], "redis: empty line"),
+            (b"?\r\n", "redis: unknown value type"),
+            (b":one\r\n", "redis: invalid integer"),
+            (b"$3\r\nabcde\r\n", "redis: bulk string not terminated"),
+            (b"+\xff\r\n", "redis: invalid UTF-8"),
+        ] {
+            assert_eq!(&error_of(decode_chunks(&[input])), message);
+        }
+    }
+}
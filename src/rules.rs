@@ -0,0 +1,434 @@
+//! The rules messages are validated against by the [`MessageValidator`](crate::message::MessageValidator).
+//!
+//! Every rule checks one property of a message and has a stable name,
+//! which is sent with the error of a rejected message, logged for dry runs and used as the label of
+//! `axochat_validation_rejections_total`.
+//! The rules are built from the configuration in the order they are checked in;
+//! rules which are disabled by the configuration are left out.
+
+use crate::config::{BlockedWordsAction, LengthUnit, LinkPolicy, MsgConfig, ValidationConfig};
+use crate::error::*;
+use crate::filter::WordFilter;
+use crate::message::{find_url, find_urls, message_length, url_domain};
+
+use serde::de::Error as _;
+use std::fmt;
+
+/// What the rules know about a message besides its content.
+pub struct RuleContext<'a> {
+    pub word_filter: &'a WordFilter,
+    /// Whether the message was sent by a moderator.
+    pub is_moderator: bool,
+}
+
+/// A property messages need to have to be delivered.
+pub trait ValidationRule {
+    /// The name of the rule, like `max_length`, which does not change between versions.
+    fn name(&self) -> &'static str;
+
+    /// Returns the error a client receives if `msg` violates the rule.
+    ///
+    /// `msg` is already normalized.
+    fn check(&self, msg: &str, context: &RuleContext) -> Option<ClientError>;
+
+    /// Whether the rules after this one are not checked if it is violated.
+    fn is_final(&self) -> bool {
+        false
+    }
+}
+
+/// A message which violates a rule.
+#[derive(Debug, Clone)]
+pub struct Rejection {
+    /// The name of the rule, see [`ValidationRule::name`].
+    pub rule: &'static str,
+    pub error: ClientError,
+}
+
+impl From<Rejection> for Error {
+    fn from(rejection: Rejection) -> Error {
+        rejection.error.into()
+    }
+}
+
+/// Builds the rules checked by [`MessageValidator::validate`](crate::message::MessageValidator::validate),
+/// in the order they are checked.
+///
+/// Fails if the configuration of a rule is invalid.
+pub fn content_rules(
+    cfg: &MsgConfig,
+    validation: &ValidationConfig,
+) -> Result<Vec<Box<dyn ValidationRule>>> {
+    let mut rules: Vec<Box<dyn ValidationRule>> = vec![
+        Box::new(NotEmpty {
+            reject_blank: validation.reject_blank,
+        }),
+        Box::new(MaxBytes {
+            max_bytes: validation.max_bytes,
+        }),
+        Box::new(MaxLength {
+            max_length: cfg.max_length,
+            unit: validation.length_unit,
+        }),
+        Box::new(Charset {
+            allow_newlines: validation.allow_newlines,
+        }),
+    ];
+    if validation.allow_newlines {
+        rules.push(Box::new(MaxLines {
+            max_lines: validation.max_lines,
+        }));
+        if validation.max_line_length != 0 {
+            rules.push(Box::new(MaxLineLength {
+                max_length: validation.max_line_length,
+                unit: validation.length_unit,
+            }));
+        }
+        rules.push(Box::new(BlankLines));
+    }
+    if validation.max_char_run != 0 {
+        rules.push(Box::new(Repetition {
+            max_run: validation.max_char_run,
+        }));
+    }
+    if !(0.0..=1.0).contains(&validation.min_content_ratio) {
+        return Err(invalid_config(format!(
+            "validation.min_content_ratio has to be between 0 and 1, not {}",
+            validation.min_content_ratio
+        )));
+    }
+    if validation.min_content_ratio > 0.0 {
+        rules.push(Box::new(ContentRatio {
+            min_ratio: validation.min_content_ratio,
+        }));
+    }
+    rules.push(Box::new(BlockedWords {
+        action: validation.blocked_words_action,
+        min_redacted_content: validation.min_redacted_content,
+    }));
+    Ok(rules)
+}
+
+/// Builds the rule checked by [`MessageValidator::validate_links`](crate::message::MessageValidator::validate_links).
+///
+/// Fails if a domain of `validation.link_whitelist` is empty.
+pub fn link_rule(validation: &ValidationConfig) -> Result<Links> {
+    if validation
+        .link_whitelist
+        .iter()
+        .any(|domain| domain.trim().is_empty())
+    {
+        return Err(invalid_config(
+            "validation.link_whitelist contains an empty domain",
+        ));
+    }
+    Ok(Links {
+        policy: validation.links,
+        whitelist: validation
+            .link_whitelist
+            .iter()
+            .map(|domain| domain.to_lowercase())
+            .collect(),
+        moderators_bypass: validation.moderators_bypass_links,
+    })
+}
+
+/// Reports an invalid option of a rule like an invalid value in the configuration file.
+fn invalid_config(message: impl fmt::Display) -> Error {
+    toml::de::Error::custom(message).into()
+}
+
+/// Rejects empty messages, and messages of only whitespace if `validation.reject_blank` is set.
+/// No other rule is checked for them.
+struct NotEmpty {
+    reject_blank: bool,
+}
+
+impl ValidationRule for NotEmpty {
+    fn name(&self) -> &'static str {
+        "not_empty"
+    }
+
+    fn check(&self, msg: &str, _context: &RuleContext) -> Option<ClientError> {
+        if msg.is_empty() || (self.reject_blank && msg.trim().is_empty()) {
+            Some(ClientError::EmptyMessage)
+        } else {
+            None
+        }
+    }
+
+    fn is_final(&self) -> bool {
+        true
+    }
+}
+
+/// `validation.max_bytes`, regardless of the unit of the length.
+struct MaxBytes {
+    max_bytes: usize,
+}
+
+impl ValidationRule for MaxBytes {
+    fn name(&self) -> &'static str {
+        "max_bytes"
+    }
+
+    fn check(&self, msg: &str, _context: &RuleContext) -> Option<ClientError> {
+        if msg.len() > self.max_bytes {
+            Some(ClientError::MessageTooLong {
+                length: msg.len(),
+                max_length: self.max_bytes,
+                unit: LengthUnit::Bytes,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// `message.max_length` in `validation.length_unit`.
+struct MaxLength {
+    max_length: usize,
+    unit: LengthUnit,
+}
+
+impl ValidationRule for MaxLength {
+    fn name(&self) -> &'static str {
+        "max_length"
+    }
+
+    fn check(&self, msg: &str, _context: &RuleContext) -> Option<ClientError> {
+        let length = message_length(msg, self.unit);
+        if length > self.max_length {
+            Some(ClientError::MessageTooLong {
+                length,
+                max_length: self.max_length,
+                unit: self.unit,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Only spaces, printable ASCII characters, letters and digits,
+/// and line breaks if `validation.allow_newlines` is set.
+struct Charset {
+    allow_newlines: bool,
+}
+
+impl ValidationRule for Charset {
+    fn name(&self) -> &'static str {
+        "charset"
+    }
+
+    fn check(&self, msg: &str, _context: &RuleContext) -> Option<ClientError> {
+        let is_allowed = |ch: char| {
+            ch == ' '
+                || ch.is_ascii_graphic()
+                || ch.is_alphanumeric()
+                || (self.allow_newlines && ch == '\n')
+        };
+        msg.char_indices()
+            .enumerate()
+            .find(|(_, (_, ch))| !is_allowed(*ch))
+            .map(
+                |(char_index, (byte_offset, character))| ClientError::InvalidCharacter {
+                    character,
+                    char_index,
+                    byte_offset,
+                },
+            )
+    }
+}
+
+/// `validation.max_lines`.
+struct MaxLines {
+    max_lines: usize,
+}
+
+impl ValidationRule for MaxLines {
+    fn name(&self) -> &'static str {
+        "max_lines"
+    }
+
+    fn check(&self, msg: &str, _context: &RuleContext) -> Option<ClientError> {
+        let lines = msg.split('\n').count();
+        if lines > self.max_lines {
+            Some(ClientError::TooManyLines {
+                lines,
+                max_lines: self.max_lines,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// `validation.max_line_length` in `validation.length_unit`.
+struct MaxLineLength {
+    max_length: usize,
+    unit: LengthUnit,
+}
+
+impl ValidationRule for MaxLineLength {
+    fn name(&self) -> &'static str {
+        "max_line_length"
+    }
+
+    fn check(&self, msg: &str, _context: &RuleContext) -> Option<ClientError> {
+        msg.split('\n')
+            .map(|line| message_length(line, self.unit))
+            .enumerate()
+            .find(|(_, length)| *length > self.max_length)
+            .map(|(index, length)| ClientError::LineTooLong {
+                line: index + 1,
+                length,
+                max_length: self.max_length,
+                unit: self.unit,
+            })
+    }
+}
+
+/// No consecutive blank lines, which are only left if `validation.blank_lines` is `reject`.
+struct BlankLines;
+
+impl ValidationRule for BlankLines {
+    fn name(&self) -> &'static str {
+        "blank_lines"
+    }
+
+    fn check(&self, msg: &str, _context: &RuleContext) -> Option<ClientError> {
+        let lines: Vec<&str> = msg.split('\n').collect();
+        let consecutive_blank = lines
+            .windows(2)
+            .any(|pair| pair.iter().all(|line| line.trim().is_empty()));
+        if consecutive_blank {
+            Some(ClientError::ConsecutiveBlankLines)
+        } else {
+            None
+        }
+    }
+}
+
+/// `validation.max_char_run`.
+struct Repetition {
+    max_run: usize,
+}
+
+impl ValidationRule for Repetition {
+    fn name(&self) -> &'static str {
+        "repetition"
+    }
+
+    fn check(&self, msg: &str, _context: &RuleContext) -> Option<ClientError> {
+        let mut run: Option<(char, usize)> = None;
+        let mut longest: Option<(char, usize)> = None;
+        for ch in msg.chars() {
+            let length = match run {
+                Some((previous, length)) if previous == ch => length + 1,
+                _ => 1,
+            };
+            run = Some((ch, length));
+            if length > longest.map_or(self.max_run, |(_, longest)| longest) {
+                longest = Some((ch, length));
+            }
+        }
+        longest.map(|(character, run)| ClientError::ExcessiveRepetition {
+            character,
+            run,
+            max_run: self.max_run,
+        })
+    }
+}
+
+/// `validation.min_content_ratio`.
+struct ContentRatio {
+    min_ratio: f64,
+}
+
+impl ValidationRule for ContentRatio {
+    fn name(&self) -> &'static str {
+        "content_ratio"
+    }
+
+    fn check(&self, msg: &str, _context: &RuleContext) -> Option<ClientError> {
+        let (content, total) = msg
+            .chars()
+            .filter(|ch| !ch.is_whitespace())
+            .fold((0, 0), |(content, total), ch| {
+                (content + ch.is_alphanumeric() as usize, total + 1)
+            });
+        if total > 0 && (content as f64) < self.min_ratio * total as f64 {
+            Some(ClientError::InsufficientContent)
+        } else {
+            None
+        }
+    }
+}
+
+/// The words of `validation.blocked_words`.
+///
+/// If they are redacted, a message is only rejected if too few letters and digits would remain,
+/// since a message of asterisks is worse than none.
+struct BlockedWords {
+    action: BlockedWordsAction,
+    min_redacted_content: usize,
+}
+
+impl ValidationRule for BlockedWords {
+    fn name(&self) -> &'static str {
+        "blocked_words"
+    }
+
+    fn check(&self, msg: &str, context: &RuleContext) -> Option<ClientError> {
+        let blocked = match self.action {
+            BlockedWordsAction::Reject => context.word_filter.find(msg).is_some(),
+            BlockedWordsAction::Redact => match context.word_filter.redact(msg) {
+                Some(redacted) => {
+                    let content = redacted.chars().filter(|ch| ch.is_alphanumeric()).count();
+                    content < self.min_redacted_content
+                }
+                None => false,
+            },
+        };
+        if blocked {
+            Some(ClientError::BlockedContent)
+        } else {
+            None
+        }
+    }
+}
+
+/// `validation.links`, which moderators may be exempt from.
+pub struct Links {
+    policy: LinkPolicy,
+    /// The lowercase domains of `validation.link_whitelist`.
+    whitelist: Vec<String>,
+    moderators_bypass: bool,
+}
+
+impl ValidationRule for Links {
+    fn name(&self) -> &'static str {
+        "links"
+    }
+
+    fn check(&self, msg: &str, context: &RuleContext) -> Option<ClientError> {
+        if context.is_moderator && self.moderators_bypass {
+            return None;
+        }
+
+        let url = match self.policy {
+            LinkPolicy::Allow => None,
+            LinkPolicy::Block => find_url(msg),
+            LinkPolicy::Whitelist => find_urls(msg).into_iter().find(|url| {
+                let domain = url_domain(url);
+                !self.whitelist.iter().any(|allowed| {
+                    domain == allowed
+                        || (domain.ends_with(allowed.as_str())
+                            && domain[..domain.len() - allowed.len()].ends_with('.'))
+                })
+            }),
+        };
+        url.map(|url| ClientError::LinksNotAllowed { url })
+    }
+}
@@ -0,0 +1,168 @@
+//! Checks of a deployment which would otherwise only fail once a user needs the broken part,
+//! run by `axochat self-test`.
+//!
+//! Every check is a function of its own, so it can also be run while the server is running.
+
+use crate::auth::{Authenticator, UserInfo, SESSION_SERVER_URL};
+use crate::chat::ChatServerBuilder;
+use crate::config::Config;
+use crate::error::*;
+use crate::tls;
+
+use actix_web::client::Client;
+use futures::Future;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// The time the Mojang session server has to answer within.
+pub const MOJANG_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The result of a single check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    /// The checked part is not configured.
+    Skipped(&'static str),
+    Failed(String),
+}
+
+impl Outcome {
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Outcome::Failed(_))
+    }
+}
+
+impl From<Result<()>> for Outcome {
+    fn from(res: Result<()>) -> Outcome {
+        match res {
+            Ok(()) => Outcome::Passed,
+            Err(err) => Outcome::Failed(err.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "pass"),
+            Outcome::Skipped(reason) => write!(f, "skip ({})", reason),
+            Outcome::Failed(err) => write!(f, "FAIL: {}", err),
+        }
+    }
+}
+
+/// Runs the checks which do not need the network, in the order they are listed in.
+///
+/// The Mojang session server is checked by [`check_mojang`].
+pub fn run_local_checks(config: &Config) -> Vec<(&'static str, Outcome)> {
+    vec![
+        ("config", check_config(config)),
+        ("listen socket", check_listen(config)),
+        ("tls", check_tls(config)),
+        ("storage", check_storage(config)),
+        ("jwt", check_jwt(config)),
+    ]
+}
+
+/// Loads every file the configuration refers to, like the chat server does when it is started.
+pub fn check_config(config: &Config) -> Outcome {
+    ChatServerBuilder::new(config.clone())
+        .build()
+        .map(drop)
+        .into()
+}
+
+/// Opens and closes the socket at `net.address`.
+///
+/// This fails while the server is running.
+pub fn check_listen(config: &Config) -> Outcome {
+    TcpListener::bind(config.net.address)
+        .map(drop)
+        .map_err(Error::from)
+        .into()
+}
+
+/// Loads `net.cert_file` and `net.key_file`.
+pub fn check_tls(config: &Config) -> Outcome {
+    match (&config.net.cert_file, &config.net.key_file) {
+        (Some(cert), Some(key)) => tls::check(cert, key).into(),
+        (None, None) => Outcome::Skipped("no certificate configured"),
+        _ => Outcome::Failed("only one of `cert_file` and `key_file` is set".to_string()),
+    }
+}
+
+/// Checks that the files of the moderation state and the storage can be written,
+/// by creating and deleting a file next to each of them.
+pub fn check_storage(config: &Config) -> Outcome {
+    check_writable(&[
+        &config.moderation.banned,
+        &config.moderation.whitelisted,
+        &config.storage.first_seen,
+        &config.storage.audit_log,
+    ])
+    .into()
+}
+
+/// Checks that each of `files` can be written and a file can be created next to it.
+pub fn check_writable(files: &[&Path]) -> Result<()> {
+    let dirs: BTreeSet<PathBuf> = files
+        .iter()
+        .map(|file| match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        })
+        .collect();
+    for dir in dirs {
+        let sentinel = dir.join(".axochat-self-test");
+        fs::write(&sentinel, b"")?;
+        fs::remove_file(&sentinel)?;
+    }
+    for file in files.iter().filter(|file| file.exists()) {
+        OpenOptions::new().append(true).open(file)?;
+    }
+    Ok(())
+}
+
+/// Signs a token with the key of `auth` and verifies it again.
+pub fn check_jwt(config: &Config) -> Outcome {
+    let auth = match &config.auth {
+        Some(auth) => auth,
+        None => return Outcome::Skipped("no `auth` section"),
+    };
+    let res = Authenticator::new(auth).and_then(|authenticator| {
+        let user = UserInfo {
+            name: "self-test".to_string(),
+            uuid: Uuid::nil(),
+            bot: false,
+        };
+        let token = authenticator.new_token(user.clone())?;
+        let verified = authenticator.auth(&token)?;
+        if verified.uuid == user.uuid && verified.name == user.name {
+            Ok(())
+        } else {
+            Err(ClientError::LoginFailed.into())
+        }
+    });
+    res.into()
+}
+
+/// Sends a `HEAD` request to the Mojang session server.
+///
+/// Every response passes, since only the reachability is checked.
+pub fn check_mojang(timeout: Duration) -> impl Future<Item = Outcome, Error = ()> {
+    Client::new()
+        .head(SESSION_SERVER_URL)
+        .timeout(timeout)
+        .send()
+        .then(|res| {
+            Ok(match res {
+                Ok(_) => Outcome::Passed,
+                Err(err) => Outcome::Failed(err.to_string()),
+            })
+        })
+}
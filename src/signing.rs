@@ -0,0 +1,163 @@
+//! Ed25519 signatures of broadcast messages,
+//! so bridges and bots can verify that a message was sent by this server.
+//!
+//! A signature covers the sequence number, the timestamp, the uuid of the author and the content,
+//! encoded by [`signed_data`].
+
+use crate::error::*;
+
+use ring::signature::{self, Ed25519KeyPair, KeyPair};
+use serde::Serialize;
+use std::{fs, io, path::Path};
+use uuid::Uuid;
+
+/// The signature of a broadcast message.
+#[derive(Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct MessageSignature {
+    /// When the message was signed, in milliseconds since the unix epoch.
+    pub timestamp: u64,
+    /// The base64 encoded Ed25519 signature.
+    pub signature: String,
+}
+
+/// Signs broadcast messages with the key in `signing.key_file`.
+pub struct MessageSigner {
+    key_pair: Ed25519KeyPair,
+}
+
+impl MessageSigner {
+    /// Loads an unencrypted Ed25519 private key in the PKCS#8 DER format,
+    /// like one generated with `openssl genpkey -algorithm ed25519 -outform DER`.
+    pub fn load(path: &Path) -> Result<MessageSigner> {
+        let der = fs::read(path)?;
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(untrusted::Input::from(&der))
+            .map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid signing key: {}", err),
+                )
+            })?;
+        Ok(MessageSigner { key_pair })
+    }
+
+    /// The public key, base64 encoded.
+    pub fn public_key(&self) -> String {
+        base64::encode(self.key_pair.public_key().as_ref())
+    }
+
+    pub fn sign(&self, seq: u64, timestamp: u64, author: &Uuid, content: &str) -> MessageSignature {
+        let data = signed_data(seq, timestamp, author, content);
+        MessageSignature {
+            timestamp,
+            signature: base64::encode(self.key_pair.sign(&data).as_ref()),
+        }
+    }
+}
+
+/// Encodes the signed part of a message:
+/// the sequence number and the timestamp as big endian 64 bit integers,
+/// followed by the 16 bytes of the uuid and the UTF-8 encoded content.
+pub fn signed_data(seq: u64, timestamp: u64, author: &Uuid, content: &str) -> Vec<u8> {
+    let mut data = Vec::with_capacity(8 + 8 + 16 + content.len());
+    data.extend_from_slice(&seq.to_be_bytes());
+    data.extend_from_slice(&timestamp.to_be_bytes());
+    data.extend_from_slice(author.as_bytes());
+    data.extend_from_slice(content.as_bytes());
+    data
+}
+
+/// Returns whether `signature` is a valid signature of a message
+/// with the base64 encoded `public_key`.
+pub fn verify(
+    public_key: &str,
+    seq: u64,
+    author: &Uuid,
+    content: &str,
+    signature: &MessageSignature,
+) -> bool {
+    let (public_key, signature_bytes) = match (
+        base64::decode(public_key),
+        base64::decode(&signature.signature),
+    ) {
+        (Ok(public_key), Ok(signature)) => (public_key, signature),
+        _ => return false,
+    };
+    let data = signed_data(seq, signature.timestamp, author, content);
+    signature::verify(
+        &signature::ED25519,
+        untrusted::Input::from(&public_key),
+        untrusted::Input::from(&data),
+        untrusted::Input::from(&signature_bytes),
+    )
+    .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+
+    fn signer() -> MessageSigner {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(untrusted::Input::from(pkcs8.as_ref())).unwrap();
+        MessageSigner { key_pair }
+    }
+
+    fn notch() -> Uuid {
+        Uuid::from_u128(0x069a79f4_44e9_4726_a5be_fca90e38aaf5)
+    }
+
+    #[test]
+    fn signed_messages_are_verified() {
+        let signer = signer();
+        let signature = signer.sign(7, 1000, &notch(), "hello");
+        assert!(verify(
+            &signer.public_key(),
+            7,
+            &notch(),
+            "hello",
+            &signature
+        ));
+    }
+
+    #[test]
+    fn tampered_messages_fail_verification() {
+        let signer = signer();
+        let public_key = signer.public_key();
+        let signature = signer.sign(7, 1000, &notch(), "hello");
+
+        assert!(!verify(&public_key, 7, &notch(), "hellO", &signature));
+        assert!(!verify(&public_key, 8, &notch(), "hello", &signature));
+        assert!(!verify(&public_key, 7, &Uuid::nil(), "hello", &signature));
+        let retimed = MessageSignature {
+            timestamp: 1001,
+            ..signature.clone()
+        };
+        assert!(!verify(&public_key, 7, &notch(), "hello", &retimed));
+        assert!(!verify(
+            &self::signer().public_key(),
+            7,
+            &notch(),
+            "hello",
+            &signature
+        ));
+    }
+
+    #[test]
+    fn malformed_signatures_fail_verification() {
+        let signer = signer();
+        let signature = MessageSignature {
+            timestamp: 1000,
+            signature: "not base64!".to_string(),
+        };
+        assert!(!verify(
+            &signer.public_key(),
+            7,
+            &notch(),
+            "hello",
+            &signature
+        ));
+        let signature = signer.sign(7, 1000, &notch(), "hello");
+        assert!(!verify("not base64!", 7, &notch(), "hello", &signature));
+    }
+}
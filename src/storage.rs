@@ -0,0 +1,336 @@
+use crate::config::StorageConfig;
+use crate::error::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+use uuid::Uuid;
+
+/// A moderation action, as recorded in the audit log.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// When the action was taken, in milliseconds since the unix epoch.
+    pub timestamp: u64,
+    /// The moderator who took the action, or `None` for the admin API.
+    pub actor: Option<Uuid>,
+    pub action: AuditAction,
+    pub target: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// How long the ban or mute lasts, in seconds; `None` if it is permanent or has no duration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    Ban,
+    /// A permanent ban was requested; it is recorded as `Ban` once it is confirmed.
+    BanRequested,
+    Unban,
+    Mute,
+    Unmute,
+    Kick,
+    /// A packet was simulated with `/api/v1/simulate` for the target.
+    Simulate,
+}
+
+/// Selects entries of the audit log.
+#[derive(Debug, Clone, Default)]
+pub struct AuditQuery {
+    pub actor: Option<Uuid>,
+    pub target: Option<Uuid>,
+    /// Only entries from this time on, in milliseconds since the unix epoch.
+    pub since: Option<u64>,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl AuditQuery {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.actor.is_none_or(|actor| entry.actor == Some(actor))
+            && self.target.is_none_or(|target| entry.target == target)
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+    }
+}
+
+/// A page of the audit log, newest entries first.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct AuditPage {
+    /// The number of all entries matching the query.
+    pub total: usize,
+    pub entries: Vec<AuditEntry>,
+}
+
+/// Persistent state about users which has to survive restarts.
+/// The length of a bucket of the long-term statistics.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsResolution {
+    Hour,
+    Day,
+}
+
+impl StatsResolution {
+    /// The length of a bucket in milliseconds.
+    pub fn millis(self) -> u64 {
+        match self {
+            StatsResolution::Hour => 60 * 60 * 1000,
+            StatsResolution::Day => 24 * 60 * 60 * 1000,
+        }
+    }
+
+    /// The start of the bucket containing `time`, in milliseconds since the unix epoch.
+    pub fn bucket_start(self, time: u64) -> u64 {
+        time - time % self.millis()
+    }
+}
+
+/// The activity within one bucket of the long-term statistics.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StatsBucket {
+    /// The start of the bucket, in milliseconds since the unix epoch.
+    pub start: u64,
+    /// The number of messages users sent, broadcast and private.
+    pub messages: u64,
+    /// The number of users who sent a message.
+    pub active_users: u64,
+    /// Set if more users were active than could be told apart,
+    /// so there were at least `active_users`.
+    #[serde(default)]
+    pub active_users_at_least: bool,
+    /// The most connections open at the same time.
+    pub peak_connections: u64,
+}
+
+pub trait Storage {
+    /// Returns the time a user logged in for the first time, if they ever did.
+    fn first_seen(&self, user: &Uuid) -> Option<SystemTime>;
+
+    /// Records that a user logged in at `time`, unless they were seen before.
+    /// Returns the time the user was first seen.
+    fn register_seen(&mut self, user: &Uuid, time: SystemTime) -> Result<SystemTime>;
+
+    /// Appends an entry to the audit log.
+    ///
+    /// Storages without an audit log ignore it.
+    fn record_audit(&mut self, _entry: AuditEntry) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the entries of the audit log matching `query`.
+    fn audit_log(&self, _query: &AuditQuery) -> AuditPage {
+        AuditPage::default()
+    }
+
+    /// Removes the entries of the audit log older than `before`, in milliseconds since the unix epoch.
+    /// Returns the number of removed entries.
+    fn prune_audit(&mut self, _before: u64) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Stores `bucket` of the long-term statistics, replacing the one with the same start.
+    ///
+    /// Storages without statistics ignore it.
+    fn record_stats(&mut self, _resolution: StatsResolution, _bucket: StatsBucket) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the stored buckets starting in `from..to`, in milliseconds since the unix epoch, oldest first.
+    fn stats(&self, _resolution: StatsResolution, _from: u64, _to: u64) -> Vec<StatsBucket> {
+        Vec::new()
+    }
+}
+
+/// Stores everything in line separated files.
+pub struct FileStorage {
+    config: StorageConfig,
+    first_seen: HashMap<Uuid, SystemTime>,
+    /// The audit log, oldest entries first.
+    audit: Vec<AuditEntry>,
+    stats: BTreeMap<(StatsResolution, u64), StatsBucket>,
+}
+
+/// A line of the statistics file.
+#[derive(Serialize, Deserialize)]
+struct StatsLine {
+    resolution: StatsResolution,
+    #[serde(flatten)]
+    bucket: StatsBucket,
+}
+
+impl FileStorage {
+    pub fn new(config: StorageConfig) -> Result<FileStorage> {
+        let first_seen = read_first_seen(&config)?;
+        let audit = read_audit(&config)?;
+        let stats = read_stats(&config)?;
+        Ok(FileStorage {
+            config,
+            first_seen,
+            audit,
+            stats,
+        })
+    }
+}
+
+impl Storage for FileStorage {
+    fn first_seen(&self, user: &Uuid) -> Option<SystemTime> {
+        self.first_seen.get(user).cloned()
+    }
+
+    fn register_seen(&mut self, user: &Uuid, time: SystemTime) -> Result<SystemTime> {
+        if let Some(first_seen) = self.first_seen.get(user) {
+            return Ok(*first_seen);
+        }
+
+        let secs = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("system time is somehow before the unix epoch")
+            .as_secs();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.config.first_seen)?;
+        writeln!(file, "{} {}", user.to_hyphenated(), secs)?;
+
+        self.first_seen.insert(*user, time);
+        Ok(time)
+    }
+
+    fn record_audit(&mut self, entry: AuditEntry) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.config.audit_log)?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+
+        self.audit.push(entry);
+        Ok(())
+    }
+
+    fn audit_log(&self, query: &AuditQuery) -> AuditPage {
+        let matching = self.audit.iter().rev().filter(|entry| query.matches(entry));
+        AuditPage {
+            total: matching.clone().count(),
+            entries: matching
+                .skip(query.offset)
+                .take(query.limit)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn prune_audit(&mut self, before: u64) -> Result<usize> {
+        let kept = self.audit.partition_point(|entry| entry.timestamp < before);
+        if kept == 0 {
+            return Ok(0);
+        }
+
+        let mut tmp = self.config.audit_log.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        for entry in &self.audit[kept..] {
+            writeln!(writer, "{}", serde_json::to_string(entry)?)?;
+        }
+        writer.flush()?;
+        fs::rename(&tmp, &self.config.audit_log)?;
+
+        self.audit.drain(..kept);
+        Ok(kept)
+    }
+
+    fn record_stats(&mut self, resolution: StatsResolution, bucket: StatsBucket) -> Result<()> {
+        if self.stats.get(&(resolution, bucket.start)) == Some(&bucket) {
+            return Ok(());
+        }
+        // Buckets are appended every time they are flushed; the last line of a bucket wins.
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.config.stats)?;
+        writeln!(
+            file,
+            "{}",
+            serde_json::to_string(&StatsLine { resolution, bucket })?
+        )?;
+
+        self.stats.insert((resolution, bucket.start), bucket);
+        Ok(())
+    }
+
+    fn stats(&self, resolution: StatsResolution, from: u64, to: u64) -> Vec<StatsBucket> {
+        self.stats
+            .range((resolution, from)..(resolution, to))
+            .map(|(_, bucket)| *bucket)
+            .collect()
+    }
+}
+
+fn read_first_seen(config: &StorageConfig) -> Result<HashMap<Uuid, SystemTime>> {
+    let file = match File::open(&config.first_seen) {
+        Ok(file) => file,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(HashMap::new());
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let reader = BufReader::new(file);
+    let mut first_seen = HashMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        let mut parts = line.split_whitespace();
+        if let (Some(uuid), Some(secs)) = (parts.next(), parts.next()) {
+            let secs: u64 = secs.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid timestamp in `{}`", line),
+                )
+            })?;
+            first_seen.insert(
+                uuid.parse()?,
+                SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            );
+        }
+    }
+    Ok(first_seen)
+}
+
+fn read_audit(config: &StorageConfig) -> Result<Vec<AuditEntry>> {
+    let file = match File::open(&config.audit_log) {
+        Ok(file) => file,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut audit = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            audit.push(serde_json::from_str(&line)?);
+        }
+    }
+    // Entries are appended in order, unless the clock was turned back.
+    audit.sort_by_key(|entry: &AuditEntry| entry.timestamp);
+    Ok(audit)
+}
+
+fn read_stats(config: &StorageConfig) -> Result<BTreeMap<(StatsResolution, u64), StatsBucket>> {
+    let file = match File::open(&config.stats) {
+        Ok(file) => file,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut stats = BTreeMap::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            let StatsLine { resolution, bucket } = serde_json::from_str(&line)?;
+            stats.insert((resolution, bucket.start), bucket);
+        }
+    }
+    Ok(stats)
+}
@@ -0,0 +1,582 @@
+//! Helpers for testing the chat server end to end.
+//!
+//! A [`TestServer`] runs a chat server on an ephemeral port in a background thread;
+//! [`TestClient`]s connect to it over websockets and exchange packets synchronously.
+//! The helpers panic on failure, since they are only meant to be used in tests.
+//!
+//! This module is only available with the `testutil` feature.
+
+use crate::auth::{Authenticator, UserInfo};
+use crate::chat::{ChatServerBuilder, ManualClock, PROTOCOL_VERSION};
+use crate::config::{AuthConfig, Config, GuestConfig};
+
+use actix::{System, SystemRunner};
+use actix_web::{App, HttpServer};
+use awc::error::WsClientError;
+use awc::http::{HeaderMap, Method};
+use awc::ws::{CloseReason, Frame, Message};
+use futures::{future::Either, stream::StreamFuture, Future, Sink, Stream};
+use jsonwebtoken::Algorithm;
+use rand::{rngs::OsRng, RngCore};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tokio_timer::Delay;
+use uuid::Uuid;
+
+/// How long a client waits for a packet by default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The largest HTTP response body which is read.
+const MAX_RESPONSE_BODY: usize = 16 * 1024 * 1024;
+
+thread_local! {
+    /// Drives the clients of the current thread.
+    static RUNNER: RefCell<Option<SystemRunner>> = const { RefCell::new(None) };
+}
+
+/// Runs `fut` to completion on the client system of the current thread.
+fn block_on<F: Future>(fut: F) -> Result<F::Item, F::Error> {
+    RUNNER.with(|runner| {
+        runner
+            .borrow_mut()
+            .get_or_insert_with(|| System::new("axochat-test-client"))
+            .block_on(fut)
+    })
+}
+
+/// The uuid of the account tests use as a moderator, see [`TestServerBuilder::with_moderator`].
+pub fn moderator() -> Uuid {
+    Uuid::from_u128(0x61699b2e_d327_4a01_9f1e_0ea8c3f06bc6)
+}
+
+/// The uuid of Notch, which tests use for most users.
+pub fn notch() -> Uuid {
+    Uuid::from_u128(0x069a79f4_44e9_4726_a5be_fca90e38aaf5)
+}
+
+/// The uuid of jeb_, which tests use when they need a second user.
+pub fn jeb() -> Uuid {
+    Uuid::from_u128(0x853c80ef_3c37_49fd_aa49_938b674adae6)
+}
+
+/// Configures and starts a [`TestServer`].
+///
+/// All files the server uses are placed in a new temporary directory,
+/// and logins use JWTs signed with a random key.
+pub struct TestServerBuilder {
+    config: Config,
+    guests: GuestConfig,
+    moderators: Vec<Uuid>,
+    setup: Option<Box<dyn FnOnce(ChatServerBuilder) -> ChatServerBuilder + Send>>,
+    clock: Option<Arc<ManualClock>>,
+}
+
+impl TestServerBuilder {
+    pub fn new() -> TestServerBuilder {
+        TestServerBuilder {
+            config: Config::default(),
+            guests: GuestConfig::default(),
+            moderators: Vec::new(),
+            setup: None,
+            clock: None,
+        }
+    }
+
+    /// A builder which makes [`moderator`] a moderator.
+    pub fn with_moderator() -> TestServerBuilder {
+        TestServerBuilder::new().moderator(moderator())
+    }
+
+    /// Changes the configuration; the file paths and `auth` are overwritten when starting.
+    pub fn config<F: FnOnce(&mut Config)>(mut self, f: F) -> TestServerBuilder {
+        f(&mut self.config);
+        self
+    }
+
+    /// Changes how connections which did not log in are treated, which is part of `auth`.
+    pub fn guests<F: FnOnce(&mut GuestConfig)>(mut self, f: F) -> TestServerBuilder {
+        f(&mut self.guests);
+        self
+    }
+
+    /// Makes `uuid` a moderator.
+    pub fn moderator(mut self, uuid: Uuid) -> TestServerBuilder {
+        self.moderators.push(uuid);
+        self
+    }
+
+    /// Enables the chat commands.
+    pub fn commands(self) -> TestServerBuilder {
+        self.config(|config| config.commands.enabled = true)
+    }
+
+    /// Customizes the chat server, e.g. to register hooks.
+    ///
+    /// `setup` is called in the server thread, since the chat server can not be moved between threads.
+    pub fn setup<F>(mut self, setup: F) -> TestServerBuilder
+    where
+        F: FnOnce(ChatServerBuilder) -> ChatServerBuilder + Send + 'static,
+    {
+        self.setup = Some(Box::new(setup));
+        self
+    }
+
+    /// Makes the server use a [`ManualClock`], which only moves with [`TestServer::advance_time`].
+    pub fn manual_clock(mut self) -> TestServerBuilder {
+        self.clock = Some(Arc::new(ManualClock::new()));
+        self
+    }
+
+    /// Starts the server and waits until it accepts connections.
+    pub fn start(self) -> TestServer {
+        let dir = std::env::temp_dir().join(format!("axochat-test-{:016x}", OsRng.next_u64()));
+        fs::create_dir_all(&dir).expect("could not create test directory");
+
+        let mut key = vec![0; 32];
+        OsRng.fill_bytes(&mut key);
+        let key_file = dir.join("jwt.key");
+        fs::write(&key_file, &key).expect("could not write JWT key");
+        let auth = AuthConfig {
+            key_file,
+            algorithm: Algorithm::HS256,
+            valid_time: Duration::from_secs(60 * 60).into(),
+            allow_anonymous: false,
+            guests: self.guests,
+        };
+
+        let moderators: Vec<_> = self
+            .moderators
+            .iter()
+            .map(|uuid| uuid.to_hyphenated().to_string())
+            .collect();
+        let mut config = self.config;
+        config.net.address = ([127, 0, 0, 1], 0).into();
+        config.moderation.moderators = dir.join("moderators.txt");
+        config.moderation.banned = dir.join("banned.txt");
+        config.moderation.whitelisted = dir.join("whitelisted.txt");
+        config.storage.first_seen = dir.join("first_seen.txt");
+        config.storage.audit_log = dir.join("audit_log.jsonl");
+        config.storage.stats = dir.join("stats.jsonl");
+        config.validation.blocked_words = dir.join("blocked_words.txt");
+        config.auth = Some(auth.clone());
+        fs::write(&config.moderation.moderators, moderators.join("\n"))
+            .expect("could not write moderators");
+
+        let authenticator = Authenticator::new(&auth).expect("could not create authenticator");
+        let setup = self.setup;
+        let clock = self.clock;
+        let server_clock = clock.clone();
+        let (tx, rx) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let system = System::new("axochat-test-server");
+
+            let mut builder = ChatServerBuilder::new(config.clone());
+            if let Some(clock) = server_clock {
+                builder = builder.clock(clock);
+            }
+            if let Some(setup) = setup {
+                builder = setup(builder);
+            }
+            let chat = builder.start().expect("could not start chat server");
+            let server = HttpServer::new(move || App::new().configure(|cfg| chat.configure(cfg)))
+                .workers(1)
+                .disable_signals()
+                .bind(config.net.address)
+                .expect("could not bind test server");
+            let addr = server.addrs()[0];
+            server.start();
+
+            tx.send((addr, System::current())).ok();
+            system.run().expect("test server failed");
+        });
+        let (addr, system) = rx.recv().expect("test server did not start");
+
+        TestServer {
+            addr,
+            system,
+            thread: Some(thread),
+            authenticator,
+            dir,
+            clock,
+        }
+    }
+}
+
+impl Default for TestServerBuilder {
+    fn default() -> TestServerBuilder {
+        TestServerBuilder::new()
+    }
+}
+
+/// A chat server running in a background thread.
+///
+/// It is stopped when this is dropped.
+pub struct TestServer {
+    addr: SocketAddr,
+    system: System,
+    thread: Option<JoinHandle<()>>,
+    authenticator: Authenticator,
+    dir: PathBuf,
+    clock: Option<Arc<ManualClock>>,
+}
+
+impl TestServer {
+    /// Starts a server with the default configuration.
+    pub fn start() -> TestServer {
+        TestServerBuilder::new().start()
+    }
+
+    /// The address the server listens at.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The directory containing the files of the server.
+    pub fn dir(&self) -> &PathBuf {
+        &self.dir
+    }
+
+    /// Moves the clock of the server `duration` forward,
+    /// e.g. to let rate limits, cooldowns or resume tokens expire.
+    ///
+    /// Panics unless the server was started with [`TestServerBuilder::manual_clock`].
+    pub fn advance_time(&self, duration: Duration) {
+        self.clock
+            .as_ref()
+            .expect("the test server does not use a manual clock")
+            .advance(duration);
+    }
+
+    /// Creates a JWT the server accepts for `name` and `uuid`.
+    pub fn token(&self, name: &str, uuid: Uuid) -> String {
+        self.authenticator
+            .new_token(UserInfo {
+                name: name.to_string(),
+                uuid,
+                bot: false,
+            })
+            .expect("could not create JWT")
+    }
+
+    /// Sends an HTTP request with the `body`, if any, as JSON
+    /// and the bearer `token`, if any, and waits for the response.
+    pub fn request(
+        &self,
+        method: &str,
+        path: &str,
+        token: Option<&str>,
+        body: Option<&[u8]>,
+    ) -> TestResponse {
+        let method = Method::from_bytes(method.as_bytes()).expect("invalid method");
+        let url = format!("http://{}{}", self.addr, path);
+        let mut request = awc::Client::new().request(method, url.as_str());
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = match body {
+            Some(body) => request
+                .content_type("application/json")
+                .send_body(body.to_vec()),
+            None => request.send(),
+        };
+        block_on(
+            response
+                .map_err(|err| err.to_string())
+                .and_then(|mut response| {
+                    response
+                        .body()
+                        .limit(MAX_RESPONSE_BODY)
+                        .map_err(|err| err.to_string())
+                        .map(move |body| TestResponse {
+                            status: response.status().as_u16(),
+                            headers: response.headers().clone(),
+                            body: body.to_vec(),
+                        })
+                }),
+        )
+        .unwrap_or_else(|err| panic!("request to {} failed: {}", url, err))
+    }
+
+    /// Connects a new client.
+    pub fn client(&self) -> TestClient<'_> {
+        self.handshake(&[])
+            .unwrap_or_else(|status| panic!("the handshake was refused with {}", status))
+            .0
+    }
+
+    /// Connects a new client which sends the `headers` with the handshake, e.g. to offer subprotocols.
+    /// A header which is given more than once is sent more than once.
+    ///
+    /// Returns the client with the headers of the response, or the status if the server refused the handshake.
+    pub fn handshake(&self, headers: &[(&str, &str)]) -> Result<(TestClient<'_>, HeaderMap), u16> {
+        let url = format!("ws://{}/ws", self.addr);
+        let request = headers.iter().fold(
+            awc::Client::new().ws(url.as_str()),
+            |request, (name, value)| request.header(*name, *value),
+        );
+        let (response, framed) = match block_on(request.connect()) {
+            Ok(connected) => connected,
+            Err(WsClientError::InvalidResponseStatus(status)) => return Err(status.as_u16()),
+            Err(err) => panic!("could not connect to {}: {}", url, err),
+        };
+        let (sink, stream) = framed.split();
+        let client = TestClient {
+            server: self,
+            sink: Some(Box::new(sink.sink_map_err(|err| format!("{}", err)))),
+            next: Some(FrameStream::into_future(Box::new(
+                stream.map_err(|err| format!("{}", err)),
+            ))),
+            timeout: DEFAULT_TIMEOUT,
+        };
+        Ok((client, response.headers().clone()))
+    }
+
+    /// Connects a new client and logs it in as `name` with a JWT.
+    pub fn login(&self, name: &str, uuid: Uuid) -> TestClient<'_> {
+        let mut client = self.client();
+        client.login_as(name, uuid);
+        client
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.system.stop();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+/// A response to a request sent with [`TestServer::request`].
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: u16,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    /// The value of the header `name`, if it is set.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(name)
+            .map(|value| value.to_str().expect("header is not visible ASCII"))
+    }
+
+    /// Decodes the body as JSON.
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body)
+            .unwrap_or_else(|err| panic!("could not decode {:?}: {}", self, err))
+    }
+
+    /// The body as text.
+    pub fn text(&self) -> String {
+        String::from_utf8(self.body.clone()).expect("body is not UTF-8")
+    }
+}
+
+/// A packet received by a [`TestClient`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct Packet {
+    /// The name of the packet, e.g. `Message`.
+    #[serde(rename = "m")]
+    pub name: String,
+    /// The body of the packet; `null` if it has none.
+    #[serde(rename = "c", default)]
+    pub content: Value,
+}
+
+/// What a [`TestClient`] received next.
+enum Event {
+    Packet(Packet),
+    /// The connection was closed, with the reason if the server sent one.
+    Closed(Option<CloseReason>),
+}
+
+type PacketSink = Box<dyn Sink<SinkItem = Message, SinkError = String>>;
+type FrameStream = Box<dyn Stream<Item = Frame, Error = String>>;
+
+/// A websocket client connected to a [`TestServer`].
+pub struct TestClient<'a> {
+    server: &'a TestServer,
+    sink: Option<PacketSink>,
+    /// The next frame, which is kept while waiting for it times out.
+    next: Option<StreamFuture<FrameStream>>,
+    timeout: Duration,
+}
+
+impl<'a> TestClient<'a> {
+    /// Changes how long the client waits for packets.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sends a packet with the name `name` and the body `content`.
+    /// The body is omitted if it is `null`.
+    pub fn send(&mut self, name: &str, content: Value) {
+        let packet = if content.is_null() {
+            json!({ "m": name })
+        } else {
+            json!({ "m": name, "c": content })
+        };
+        let sink = self.sink.take().expect("connection is closed");
+        let sink = block_on(sink.send(Message::Text(packet.to_string())))
+            .unwrap_or_else(|err| panic!("could not send `{}`: {}", name, err));
+        self.sink = Some(sink);
+    }
+
+    /// Sends `Hello` with the current version of the protocol and the optional `features`.
+    pub fn hello(&mut self, features: &[&str]) {
+        self.send(
+            "Hello",
+            json!({
+                "features": features,
+                "protocol": PROTOCOL_VERSION,
+            }),
+        );
+    }
+
+    /// Logs in as `name` with a JWT and waits for the successful login.
+    pub fn login_as(&mut self, name: &str, uuid: Uuid) {
+        let token = self.server.token(name, uuid);
+        self.send(
+            "LoginJWT",
+            json!({
+                "token": token,
+                "allow_messages": true,
+            }),
+        );
+        let content = self.expect("Success");
+        assert_eq!(
+            content["reason"], "Login",
+            "unexpected success: {}",
+            content
+        );
+    }
+
+    /// Sends a message to every client.
+    pub fn send_message(&mut self, content: &str) {
+        self.send("Message", json!({ "content": content }));
+    }
+
+    /// Sends a private message to `receiver`.
+    pub fn send_private_message(&mut self, receiver: &str, content: &str) {
+        self.send(
+            "PrivateMessage",
+            json!({
+                "receiver": receiver,
+                "content": content,
+            }),
+        );
+    }
+
+    /// Waits for the next packet.
+    pub fn next_packet(&mut self) -> Packet {
+        self.try_next_packet(self.timeout)
+            .unwrap_or_else(|| panic!("no packet received within {:?}", self.timeout))
+    }
+
+    /// Waits at most `timeout` for the next packet.
+    pub fn try_next_packet(&mut self, timeout: Duration) -> Option<Packet> {
+        match self.next_event(timeout)? {
+            Event::Packet(packet) => Some(packet),
+            Event::Closed(reason) => panic!("connection was closed: {:?}", reason),
+        }
+    }
+
+    /// Waits until the server closes the connection and returns the close code and its description.
+    pub fn expect_close(&mut self) -> (u16, Option<String>) {
+        match self.next_event(self.timeout) {
+            Some(Event::Closed(Some(reason))) => (reason.code.into(), reason.description),
+            Some(Event::Closed(None)) => panic!("connection was closed without a code"),
+            Some(Event::Packet(packet)) => {
+                panic!(
+                    "expected the connection to close, but received {:?}",
+                    packet
+                )
+            }
+            None => panic!("connection was not closed within {:?}", self.timeout),
+        }
+    }
+
+    /// Waits at most `timeout` for the next packet or the end of the connection.
+    fn next_event(&mut self, timeout: Duration) -> Option<Event> {
+        loop {
+            let next = self.next.take().expect("connection is closed");
+            let delay = Delay::new(Instant::now() + timeout);
+            let (frame, stream) = match block_on(next.select2(delay)) {
+                Ok(Either::A((next, _delay))) => next,
+                Ok(Either::B((_, next))) => {
+                    self.next = Some(next);
+                    return None;
+                }
+                Err(Either::A(((err, _stream), _delay))) => {
+                    panic!("could not receive packet: {}", err)
+                }
+                Err(Either::B((err, _next))) => panic!("timer failed: {}", err),
+            };
+            self.next = Some(stream.into_future());
+
+            match frame {
+                Some(Frame::Text(Some(text))) => {
+                    return Some(Event::Packet(
+                        serde_json::from_slice(&text)
+                            .unwrap_or_else(|err| panic!("could not decode packet: {}", err)),
+                    ));
+                }
+                Some(Frame::Text(None)) => panic!("received an empty text frame"),
+                Some(Frame::Close(reason)) => {
+                    self.next = None;
+                    return Some(Event::Closed(reason));
+                }
+                None => {
+                    self.next = None;
+                    return Some(Event::Closed(None));
+                }
+                Some(Frame::Binary(_)) | Some(Frame::Ping(_)) | Some(Frame::Pong(_)) => {}
+            }
+        }
+    }
+
+    /// Waits for the next packet and asserts that it is named `name`.
+    /// Returns its body.
+    pub fn expect(&mut self, name: &str) -> Value {
+        let packet = self.next_packet();
+        assert_eq!(
+            packet.name, name,
+            "expected `{}`, but received {:?}",
+            name, packet
+        );
+        packet.content
+    }
+
+    /// Waits for the next packet and decodes its body as `T`.
+    pub fn expect_packet<T: DeserializeOwned>(&mut self) -> T {
+        let packet = self.next_packet();
+        serde_json::from_value(packet.content.clone())
+            .unwrap_or_else(|err| panic!("could not decode {:?}: {}", packet, err))
+    }
+
+    /// Asserts that no packet arrives within `timeout`.
+    pub fn expect_none(&mut self, timeout: Duration) {
+        if let Some(packet) = self.try_next_packet(timeout) {
+            panic!("expected no packet, but received {:?}", packet);
+        }
+    }
+
+    /// Waits for an `Error` packet and asserts that its error is `expected`,
+    /// e.g. `json!("RateLimited")`.
+    pub fn expect_error(&mut self, expected: Value) {
+        let content = self.expect("Error");
+        assert_eq!(content["message"], expected, "unexpected error");
+    }
+}
@@ -0,0 +1,64 @@
+//! Loading the certificate and key the server listens with, using the enabled TLS feature.
+
+use crate::error::*;
+use std::path::Path;
+
+#[cfg(feature = "ssl")]
+use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod};
+
+#[cfg(feature = "rust-tls")]
+use {
+    rustls::{
+        internal::pemfile::{certs, rsa_private_keys},
+        NoClientAuth, ServerConfig,
+    },
+    std::{fs::File, io::BufReader},
+};
+
+/// Loads the certificate chain `cert` and the private key `key`.
+/// If the extension of `key` is `pem`, the `PEM` format is used, otherwise `ASN1`.
+#[cfg(feature = "ssl")]
+pub fn load_ssl(cert: &Path, key: &Path) -> Result<SslAcceptorBuilder> {
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    builder.set_certificate_chain_file(cert)?;
+    let ft = match key.extension() {
+        Some(ext) if ext == "pem" => SslFiletype::PEM,
+        _ => SslFiletype::ASN1,
+    };
+    builder.set_private_key_file(key, ft)?;
+    builder.check_private_key()?;
+    Ok(builder)
+}
+
+/// Loads the PEM encoded certificate chain `cert` and RSA private key `key`.
+#[cfg(feature = "rust-tls")]
+pub fn load_rustls(cert: &Path, key: &Path) -> Result<ServerConfig> {
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    let mut cert_file = BufReader::new(File::open(cert)?);
+    let cert_chain = certs(&mut cert_file).map_err(|()| Error::RustTLSNoMsg)?;
+    let mut key_file = BufReader::new(File::open(key)?);
+    let mut keys = rsa_private_keys(&mut key_file).map_err(|()| Error::RustTLSNoMsg)?;
+    if keys.is_empty() {
+        return Err(Error::RustTLSNoMsg);
+    }
+    config.set_single_cert(cert_chain, keys.remove(0))?;
+    Ok(config)
+}
+
+/// Loads the certificate and key with the enabled TLS feature, without using them.
+#[cfg(feature = "ssl")]
+pub fn check(cert: &Path, key: &Path) -> Result<()> {
+    load_ssl(cert, key).map(drop)
+}
+
+/// Loads the certificate and key with the enabled TLS feature, without using them.
+#[cfg(all(feature = "rust-tls", not(feature = "ssl")))]
+pub fn check(cert: &Path, key: &Path) -> Result<()> {
+    load_rustls(cert, key).map(drop)
+}
+
+/// Fails, because the server was built without TLS support.
+#[cfg(not(any(feature = "ssl", feature = "rust-tls")))]
+pub fn check(_cert: &Path, _key: &Path) -> Result<()> {
+    Err(ClientError::NotSupported.into())
+}
@@ -0,0 +1,146 @@
+//! Changes applied to the content of messages before they are validated and delivered.
+//!
+//! The transformations run in the order of `validation.transforms`,
+//! and each one sees the result of the previous ones, so the order matters:
+//! with `strip_invisible` before `collapse_whitespace`, `a \u{200b} b` becomes `a b`,
+//! while the other way around, the invisible character keeps the spaces apart
+//! and the message becomes `a  b`.
+//! Likewise, `expand_emotes` before `collapse_whitespace` also collapses the whitespace of emotes,
+//! and `strip_invisible` before `expand_emotes` expands shortcodes with invisible characters in them.
+
+use crate::config::Transform;
+use crate::emote::Emotes;
+use crate::error::*;
+
+/// Characters without a visible glyph which only serve to evade filters.
+/// Zero-width joiners are kept, since emoji sequences depend on them.
+const INVISIBLE: &[char] = &[
+    '\u{00ad}', // soft hyphen
+    '\u{180e}', // mongolian vowel separator
+    '\u{200b}', // zero-width space
+    '\u{200c}', // zero-width non-joiner
+    '\u{2060}', // word joiner
+    '\u{feff}', // zero-width no-break space
+];
+
+impl Transform {
+    /// Applies the transformation to `msg`.
+    ///
+    /// Only `expand_emotes` can fail, with [`ClientError::MessageTooLong`]
+    /// if the expanded message is longer than `max_bytes`.
+    pub fn apply(self, msg: &str, emotes: &Emotes, max_bytes: usize) -> Result<String> {
+        match self {
+            Transform::StripInvisible => Ok(strip_invisible(msg)),
+            Transform::CollapseWhitespace => Ok(collapse_whitespace(msg)),
+            Transform::ExpandEmotes if emotes.table().is_empty() => Ok(msg.to_string()),
+            Transform::ExpandEmotes => emotes.expand(msg, max_bytes),
+        }
+    }
+}
+
+/// Applies `transforms` to `msg` in order.
+pub fn transform(
+    transforms: &[Transform],
+    msg: &str,
+    emotes: &Emotes,
+    max_bytes: usize,
+) -> Result<String> {
+    let mut msg = msg.to_string();
+    for transform in transforms {
+        msg = transform.apply(&msg, emotes, max_bytes)?;
+    }
+    Ok(msg)
+}
+
+/// Removes the [`INVISIBLE`] characters.
+pub fn strip_invisible(msg: &str) -> String {
+    msg.chars().filter(|c| !INVISIBLE.contains(c)).collect()
+}
+
+/// Replaces every run of whitespace other than line breaks with a single space.
+pub fn collapse_whitespace(msg: &str) -> String {
+    let mut collapsed = String::with_capacity(msg.len());
+    let mut in_run = false;
+    for c in msg.chars() {
+        if c.is_whitespace() && c != '\n' && c != '\r' {
+            if !in_run {
+                collapsed.push(' ');
+            }
+            in_run = true;
+        } else {
+            collapsed.push(c);
+            in_run = false;
+        }
+    }
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use Transform::*;
+
+    fn emotes() -> Emotes {
+        let mut table = BTreeMap::new();
+        table.insert("heart".to_string(), "\u{2764}".to_string());
+        table.insert(
+            "lenny".to_string(),
+            "( \u{361}\u{b0}  \u{35c}\u{296} \u{361}\u{b0})".to_string(),
+        );
+        Emotes::new(table)
+    }
+
+    fn apply(transforms: &[Transform], msg: &str) -> String {
+        transform(transforms, msg, &emotes(), 1000).unwrap()
+    }
+
+    #[test]
+    fn invisible_characters_keep_whitespace_apart_unless_stripped_first() {
+        let msg = "a \u{200b} b";
+        assert_eq!(apply(&[StripInvisible, CollapseWhitespace], msg), "a b");
+        assert_eq!(apply(&[CollapseWhitespace, StripInvisible], msg), "a  b");
+    }
+
+    #[test]
+    fn whitespace_of_emotes_is_only_collapsed_after_expanding_them() {
+        let msg = "hi  :lenny:";
+        assert_eq!(
+            apply(&[ExpandEmotes, CollapseWhitespace], msg),
+            "hi ( \u{361}\u{b0} \u{35c}\u{296} \u{361}\u{b0})"
+        );
+        assert_eq!(
+            apply(&[CollapseWhitespace, ExpandEmotes], msg),
+            "hi ( \u{361}\u{b0}  \u{35c}\u{296} \u{361}\u{b0})"
+        );
+    }
+
+    #[test]
+    fn shortcodes_are_only_expanded_after_stripping_invisible_characters() {
+        let msg = "i :he\u{200b}art: you";
+        assert_eq!(
+            apply(&[StripInvisible, ExpandEmotes], msg),
+            "i \u{2764} you"
+        );
+        assert_eq!(apply(&[ExpandEmotes, StripInvisible], msg), "i :heart: you");
+    }
+
+    #[test]
+    fn expanded_messages_are_length_checked() {
+        let result = transform(&[ExpandEmotes], ":heart::heart:", &emotes(), 5);
+        assert!(matches!(
+            result,
+            Err(Error::AxoChat {
+                source: ClientError::MessageTooLong { .. }
+            })
+        ));
+    }
+
+    #[test]
+    fn unknown_transforms_are_rejected() {
+        let transforms: Vec<Transform> =
+            serde_json::from_str(r#"["collapse_whitespace", "strip_invisible"]"#).unwrap();
+        assert_eq!(transforms, [CollapseWhitespace, StripInvisible]);
+        assert!(serde_json::from_str::<Vec<Transform>>(r#"["shout"]"#).is_err());
+    }
+}
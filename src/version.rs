@@ -0,0 +1,27 @@
+//! Information about the running build, for telling servers apart when triaging issues.
+
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// The version of the crate.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The abbreviated git commit the server was built from, or `unknown`.
+pub const GIT_COMMIT: &str = env!("AXOCHAT_GIT_COMMIT");
+
+/// When the server was built, in RFC 3339 format, or `unknown`.
+pub const BUILD_TIMESTAMP: &str = env!("AXOCHAT_BUILD_TIMESTAMP");
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// Records the start of the server.
+///
+/// Calling this again does not reset the start time.
+pub fn mark_started() {
+    STARTED.get_or_init(Instant::now);
+}
+
+/// How long the server has been running since [`mark_started`] was first called.
+pub fn uptime() -> Duration {
+    STARTED.get_or_init(Instant::now).elapsed()
+}
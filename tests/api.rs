@@ -0,0 +1,142 @@
+//! End-to-end tests of the limits of the admin API.
+#![cfg(feature = "testutil")]
+
+use axochat::testutil::{notch, TestServer, TestServerBuilder};
+use serde_json::json;
+use std::time::Duration;
+
+const TOKEN: &str = "admin-token";
+
+/// Starts a server whose API allows `per_token` requests per token and `per_ip` per IP address.
+fn server(per_token: usize, per_ip: usize) -> TestServer {
+    TestServerBuilder::new()
+        .config(|config| {
+            config.api.token = Some(TOKEN.to_string());
+            config.api.max_requests_per_token = per_token;
+            config.api.max_requests_per_ip = per_ip;
+            config.server.metrics = true;
+        })
+        .start()
+}
+
+fn block_word(server: &TestServer, word: &str) -> u16 {
+    let body = json!({ "word": word }).to_string();
+    server
+        .request(
+            "POST",
+            "/api/v1/blocked-words",
+            Some(TOKEN),
+            Some(body.as_bytes()),
+        )
+        .status
+}
+
+#[test]
+fn requests_above_the_limit_are_told_when_to_retry() {
+    let server = server(10, 2);
+    for _ in 0..2 {
+        let response = server.request("GET", "/api/v1/blocked-words", Some(TOKEN), None);
+        assert_eq!(response.status, 200);
+    }
+
+    let response = server.request("GET", "/api/v1/blocked-words", Some(TOKEN), None);
+    assert_eq!(response.status, 429);
+    let retry_after: u64 = response.header("Retry-After").unwrap().parse().unwrap();
+    assert!(retry_after > 0 && retry_after <= 60, "{}", retry_after);
+    // The limit is shared by all routes.
+    assert_eq!(block_word(&server, "spam"), 429);
+}
+
+#[test]
+fn rejected_requests_never_reach_the_chat_server() {
+    let server = server(1, 10);
+    assert_eq!(block_word(&server, "alpha"), 204);
+    assert_eq!(block_word(&server, "beta"), 429);
+
+    // The chat server handles its messages in order, so a word blocked by the
+    // rejected request would have been blocked before this message is validated.
+    let mut client = server.client();
+    client.login_as("Notch", notch());
+    client.send_message("beta");
+    assert_eq!(client.expect("Message")["content"], "beta");
+    client.send_message("alpha");
+    client.expect_error(json!("BlockedContent"));
+
+    let words = std::fs::read_to_string(server.dir().join("blocked_words.txt")).unwrap();
+    assert_eq!(words.lines().collect::<Vec<_>>(), ["alpha"]);
+}
+
+#[test]
+fn unauthorized_requests_count_towards_the_limit_of_their_address() {
+    let server = server(10, 2);
+    for _ in 0..2 {
+        let response = server.request("GET", "/api/v1/blocked-words", Some("wrong"), None);
+        assert_eq!(response.status, 401);
+    }
+
+    // The limits are checked before the token, so guessing it is limited as well.
+    let response = server.request("GET", "/api/v1/blocked-words", Some(TOKEN), None);
+    assert_eq!(response.status, 429);
+}
+
+#[test]
+fn tokens_are_limited_independently_of_addresses() {
+    let server = server(1, 10);
+    assert_eq!(block_word(&server, "alpha"), 204);
+    assert_eq!(block_word(&server, "beta"), 429);
+
+    let response = server.request("GET", "/api/v1/blocked-words", Some("other"), None);
+    assert_eq!(response.status, 401);
+    let response = server.request("GET", "/api/v1/blocked-words", None, None);
+    assert_eq!(response.status, 401);
+}
+
+#[test]
+fn large_bodies_are_rejected_after_the_limits() {
+    let server = server(2, 10);
+    let word = "a".repeat(2000);
+    assert_eq!(block_word(&server, &word), 413);
+    assert_eq!(block_word(&server, "alpha"), 204);
+
+    // The rejected body still counted, and the limit is checked before the body is read.
+    assert_eq!(block_word(&server, &word), 429);
+}
+
+#[test]
+fn requests_are_counted_by_route_and_status() {
+    let server = server(1, 10);
+    assert_eq!(block_word(&server, "alpha"), 204);
+    assert_eq!(block_word(&server, "beta"), 429);
+    let response = server.request("GET", "/api/v1/users/not-a-uuid", Some(TOKEN), None);
+    assert_eq!(response.status, 429);
+
+    let metrics = server.request("GET", "/metrics", None, None).text();
+    for line in &[
+        r#"axochat_api_requests_total{method="POST",route="/api/v1/blocked-words",status="204"} 1"#,
+        r#"axochat_api_requests_total{method="POST",route="/api/v1/blocked-words",status="429"} 1"#,
+        r#"axochat_api_requests_total{method="GET",route="/api/v1/users/{uuid}",status="429"} 1"#,
+    ] {
+        assert!(
+            metrics.lines().any(|l| l == *line),
+            "{} not in {}",
+            line,
+            metrics
+        );
+    }
+}
+
+#[test]
+fn the_limits_end_after_their_duration() {
+    let server = TestServerBuilder::new()
+        .config(|config| {
+            config.api.token = Some(TOKEN.to_string());
+            config.api.max_requests_per_ip = 1;
+            config.api.rate_limit_duration = Duration::from_millis(200).into();
+        })
+        .start();
+    assert_eq!(block_word(&server, "alpha"), 204);
+    assert_eq!(block_word(&server, "beta"), 429);
+
+    std::thread::sleep(Duration::from_millis(250));
+    assert_eq!(block_word(&server, "beta"), 204);
+}
@@ -0,0 +1,58 @@
+//! End-to-end test of the backlog of the chat server.
+#![cfg(feature = "testutil")]
+
+use axochat::testutil::{TestServer, TestServerBuilder};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Reads `axochat_chat_server_backlog` from the metrics.
+fn backlog(server: &TestServer) -> u64 {
+    let metrics = server.request("GET", "/metrics", None, None).text();
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix("axochat_chat_server_backlog "))
+        .expect("backlog is not in the metrics")
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn the_backlog_returns_to_zero_when_idle() {
+    let server = TestServerBuilder::new()
+        .config(|config| {
+            config.server.metrics = true;
+            config.message.max_messages = 1000;
+        })
+        .start();
+
+    let mut clients: Vec<_> = (0..5u128)
+        .map(|i| {
+            let mut client = server.client();
+            client.login_as(&format!("user{}", i), Uuid::from_u128(i + 1));
+            client
+        })
+        .collect();
+    for round in 0..50 {
+        for (i, client) in clients.iter_mut().enumerate() {
+            client.send_message(&format!("message {} of user {}", round, i));
+        }
+    }
+    // Connections closed with messages in flight are cleaned up as well.
+    clients.truncate(2);
+    clients[0].send_private_message("user1", "psst");
+    drop(clients);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let pending = backlog(&server);
+        if pending == 0 {
+            break;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "backlog stayed at {} messages",
+            pending
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,71 @@
+//! End-to-end tests of bot accounts.
+#![cfg(feature = "testutil")]
+
+use axochat::config::BotConfig;
+use axochat::testutil::{jeb, moderator, notch, TestServer, TestServerBuilder};
+use serde_json::json;
+use std::time::Duration;
+
+/// Starts a server where the moderator is also listed as a bot by mistake.
+fn server() -> TestServer {
+    TestServerBuilder::with_moderator()
+        .commands()
+        .config(|config| {
+            config.bots = BotConfig {
+                uuids: vec![moderator()],
+                ..BotConfig::default()
+            };
+        })
+        .start()
+}
+
+#[test]
+fn bots_can_not_moderate_even_if_they_are_moderators() {
+    let server = server();
+    let mut bot = server.login("Bot", moderator());
+    let mut player = server.login("Notch", notch());
+
+    bot.send("BanUser", json!({ "user": notch() }));
+    bot.expect_error(json!("NotPermitted"));
+
+    bot.send_message("/ban Notch 1h");
+    let result = bot.expect("CommandResult");
+    assert_eq!(result["success"], false);
+    assert_eq!(result["translation_key"], "error.not_permitted");
+    bot.send_message("/mute Notch 10m");
+    assert_eq!(bot.expect("CommandResult")["success"], false);
+    bot.send_message("/kick Notch");
+    assert_eq!(bot.expect("CommandResult")["success"], false);
+
+    player.expect_none(Duration::from_millis(200));
+    player.send_message("still here");
+    assert_eq!(player.expect("Message")["content"], "still here");
+}
+
+#[test]
+fn bots_are_not_reported_as_moderators() {
+    let server = server();
+    let mut bot = server.login("Bot", moderator());
+
+    bot.send("RequestDiagnostics", json!(null));
+    assert_eq!(bot.expect("Diagnostics")["moderator"], false);
+}
+
+#[test]
+fn bots_are_marked_in_author_info() {
+    let server = server();
+    // Version 1 of the protocol has no `bot` flag.
+    let mut bot = server.client();
+    bot.hello(&[]);
+    bot.login_as("Bot", moderator());
+    let mut player = server.client();
+    player.hello(&[]);
+    player.login_as("jeb_", jeb());
+
+    bot.send_message("beep");
+    assert_eq!(player.expect("Message")["author_info"]["bot"], true);
+    assert_eq!(bot.expect("Message")["author_info"]["bot"], true);
+    // The flag is omitted for players.
+    player.send_message("boop");
+    assert!(bot.expect("Message")["author_info"].get("bot").is_none());
+}
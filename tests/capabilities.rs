@@ -0,0 +1,71 @@
+//! End-to-end tests of optional packets, which only reach the clients supporting them.
+#![cfg(feature = "testutil")]
+
+use axochat::testutil::{moderator, notch, TestClient, TestServer, TestServerBuilder};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Connects a client which sends `Hello` with `features` before it logs in.
+fn login<'a>(server: &'a TestServer, name: &str, uuid: Uuid, features: &[&str]) -> TestClient<'a> {
+    let mut client = server.client();
+    client.hello(features);
+    client.login_as(name, uuid);
+    client
+}
+
+#[test]
+fn optional_packets_only_reach_the_clients_supporting_them() {
+    let server = TestServerBuilder::with_moderator()
+        .commands()
+        .config(|config| {
+            config.presence.enabled = true;
+            config.presence.batch_window = Duration::from_millis(200).into();
+            config.moderation.announce_actions = true;
+        })
+        .start();
+    let mut full = login(
+        &server,
+        "Full",
+        Uuid::from_u128(1),
+        &["presence", "system_messages"],
+    );
+    let mut plain = login(&server, "Plain", Uuid::from_u128(2), &[]);
+    // Clients which do not send `Hello` support no features.
+    let mut old = server.login("Old", Uuid::from_u128(3));
+    let mut moderator = login(&server, "Moderator", moderator(), &[]);
+    let _notch = server.login("Notch", notch());
+
+    let joined = full.expect("PresenceDiff")["joined"].clone();
+    assert!(
+        joined
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|user| user["name"] == "Notch"),
+        "{}",
+        joined
+    );
+
+    // Messages reach everyone.
+    moderator.send_message("hello");
+    for client in [&mut full, &mut plain, &mut old, &mut moderator].iter_mut() {
+        assert_eq!(client.expect("Message")["content"], "hello");
+    }
+
+    moderator.send_message("/ban Notch 1h");
+    assert_eq!(moderator.expect("CommandResult")["success"], true);
+    let announcement = full.expect("SystemMessage");
+    assert!(
+        announcement["content"].as_str().unwrap().contains("Notch"),
+        "{}",
+        announcement
+    );
+    assert_eq!(
+        full.expect("PresenceDiff")["left"],
+        serde_json::json!(["Notch"])
+    );
+
+    for client in [&mut plain, &mut old, &mut moderator].iter_mut() {
+        client.expect_none(Duration::from_millis(400));
+    }
+}
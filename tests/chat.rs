@@ -0,0 +1,211 @@
+//! End-to-end tests of messages, private messages and bans.
+#![cfg(feature = "testutil")]
+
+use axochat::testutil::{jeb, moderator, notch, TestClient, TestServerBuilder};
+use serde_json::json;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Sends `BanUser` for `user` and returns the nonce which confirms it.
+fn request_ban(moderator: &mut TestClient, user: Uuid) -> serde_json::Value {
+    moderator.send("BanUser", json!({ "user": user }));
+    let confirmation = moderator.expect("ConfirmAction");
+    assert_eq!(
+        confirmation["summary"],
+        format!("ban `{}` permanently", user.to_hyphenated())
+    );
+    confirmation["nonce"].clone()
+}
+
+#[test]
+fn messages_are_sent_to_everyone() {
+    let server = TestServerBuilder::with_moderator().start();
+    let mut notch = server.login("Notch", notch());
+    let mut jeb = server.login("jeb_", jeb());
+
+    notch.send_message("hello");
+    for client in [&mut notch, &mut jeb].iter_mut() {
+        let message = client.expect("Message");
+        assert_eq!(message["content"], "hello");
+        assert_eq!(message["author_info"]["name"], "Notch");
+        assert_eq!(
+            message["author_info"]["uuid"],
+            self::notch().to_hyphenated().to_string()
+        );
+    }
+}
+
+#[test]
+fn messages_require_a_login() {
+    let server = TestServerBuilder::with_moderator().start();
+    let mut guest = server.client();
+    let mut notch = server.login("Notch", notch());
+
+    guest.send_message("hello");
+    guest.expect_error(json!("NotLoggedIn"));
+    notch.expect_none(Duration::from_millis(200));
+}
+
+#[test]
+fn invalid_messages_are_rejected() {
+    let server = TestServerBuilder::with_moderator().start();
+    let mut notch = server.login("Notch", notch());
+    let mut jeb = server.login("jeb_", jeb());
+
+    notch.send_message("");
+    notch.expect_error(json!("EmptyMessage"));
+    notch.send_message(&"a".repeat(1000));
+    notch.expect_error(json!("MessageTooLong"));
+    jeb.expect_none(Duration::from_millis(200));
+}
+
+#[test]
+fn repeated_messages_are_rate_limited() {
+    let server = TestServerBuilder::with_moderator().start();
+    let mut notch = server.login("Notch", notch());
+
+    notch.send_message("hello");
+    notch.expect("Message");
+    notch.send_message("hello");
+    notch.expect_error(json!("RateLimited"));
+}
+
+#[test]
+fn private_messages_reach_only_their_receiver() {
+    let server = TestServerBuilder::with_moderator().start();
+    let mut notch = server.login("Notch", notch());
+    let mut jeb = server.login("jeb_", jeb());
+    let mut moderator = server.login("Moderator", moderator());
+
+    notch.send_private_message("jeb_", "psst");
+    let message = jeb.expect("PrivateMessage");
+    assert_eq!(message["content"], "psst");
+    assert_eq!(message["author_info"]["name"], "Notch");
+    moderator.expect_none(Duration::from_millis(200));
+}
+
+#[test]
+fn private_messages_to_offline_users_fail() {
+    let server = TestServerBuilder::with_moderator().start();
+    let mut notch = server.login("Notch", notch());
+
+    notch.send_private_message("jeb_", "psst");
+    notch.expect_error(json!("UserNotFound"));
+}
+
+#[test]
+fn private_messages_are_validated() {
+    let server = TestServerBuilder::with_moderator().start();
+    let mut notch = server.login("Notch", notch());
+    let mut jeb = server.login("jeb_", jeb());
+
+    notch.send_private_message("jeb_", "");
+    notch.expect_error(json!("EmptyMessage"));
+    jeb.expect_none(Duration::from_millis(200));
+}
+
+#[test]
+fn banned_users_are_disconnected_and_can_not_write() {
+    let server = TestServerBuilder::with_moderator().start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+
+    let nonce = request_ban(&mut moderator, self::notch());
+    notch.expect_none(Duration::from_millis(200));
+    moderator.send("ConfirmAction", json!({ "nonce": nonce }));
+    assert_eq!(moderator.expect("Success")["reason"], "Ban");
+    assert_eq!(notch.expect("Disconnected")["reason_code"], "banned");
+    assert_eq!(notch.expect_close().0, 1008);
+
+    // By default banned users can still log in, but not write.
+    let mut notch = server.login("Notch", self::notch());
+    assert_eq!(notch.expect("ModerationStatus")["banned"], true);
+    notch.send_message("hello");
+    notch.expect_error(json!("Banned"));
+    notch.send_private_message("Moderator", "please");
+    notch.expect_error(json!("Banned"));
+    moderator.expect_none(Duration::from_millis(200));
+
+    moderator.send("UnbanUser", json!({ "user": self::notch() }));
+    assert_eq!(moderator.expect("Success")["reason"], "Unban");
+    assert_eq!(notch.expect("ModerationStatus")["banned"], false);
+    notch.send_message("thanks");
+    assert_eq!(notch.expect("Message")["content"], "thanks");
+}
+
+#[test]
+fn only_moderators_can_ban() {
+    let server = TestServerBuilder::with_moderator().start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+
+    notch.send("BanUser", json!({ "user": jeb() }));
+    notch.expect_error(json!("NotPermitted"));
+    notch.send("BanUser", json!({ "user": self::moderator() }));
+    notch.expect_error(json!("NotPermitted"));
+    moderator.send("BanUser", json!({ "user": self::moderator() }));
+    moderator.expect_error(json!("NotPermitted"));
+    moderator.send("UnbanUser", json!({ "user": jeb() }));
+    moderator.expect_error(json!("NotBanned"));
+}
+
+#[test]
+fn confirmations_can_only_be_used_once() {
+    let server = TestServerBuilder::with_moderator().start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+
+    let nonce = request_ban(&mut moderator, self::notch());
+    moderator.send("ConfirmAction", json!({ "nonce": nonce }));
+    assert_eq!(moderator.expect("Success")["reason"], "Ban");
+    notch.expect("Disconnected");
+
+    moderator.send("UnbanUser", json!({ "user": self::notch() }));
+    assert_eq!(moderator.expect("Success")["reason"], "Unban");
+    moderator.send("ConfirmAction", json!({ "nonce": nonce }));
+    moderator.expect_error(json!("ConfirmationFailed"));
+
+    moderator.send("RequestAuditLog", json!({}));
+    let actions: Vec<_> = moderator.expect("AuditLog")["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["action"].clone())
+        .collect();
+    assert_eq!(
+        actions,
+        [json!("Unban"), json!("Ban"), json!("BanRequested")]
+    );
+}
+
+#[test]
+fn confirmations_belong_to_their_session() {
+    let server = TestServerBuilder::with_moderator().start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut other = server.login("Moderator", self::moderator());
+    let mut notch = server.login("Notch", notch());
+
+    let nonce = request_ban(&mut moderator, self::notch());
+    other.send("ConfirmAction", json!({ "nonce": nonce }));
+    other.expect_error(json!("ConfirmationFailed"));
+    notch.expect_none(Duration::from_millis(200));
+}
+
+#[test]
+fn confirmations_expire() {
+    let server = TestServerBuilder::with_moderator().manual_clock().start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+
+    let nonce = request_ban(&mut moderator, self::notch());
+    server.advance_time(Duration::from_secs(30));
+    moderator.send("ConfirmAction", json!({ "nonce": nonce }));
+    moderator.expect_error(json!("ConfirmationFailed"));
+    notch.expect_none(Duration::from_millis(200));
+
+    let nonce = request_ban(&mut moderator, self::notch());
+    server.advance_time(Duration::from_secs(29));
+    moderator.send("ConfirmAction", json!({ "nonce": nonce }));
+    assert_eq!(moderator.expect("Success")["reason"], "Ban");
+    notch.expect("Disconnected");
+}
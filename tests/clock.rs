@@ -0,0 +1,51 @@
+//! End-to-end tests of rules which depend on the time, fast-forwarded with a manual clock.
+#![cfg(feature = "testutil")]
+
+use axochat::testutil::{moderator, notch, TestServerBuilder};
+use serde_json::json;
+use std::time::Duration;
+
+#[test]
+fn messages_are_broadcast_once_a_mute_ends() {
+    let server = TestServerBuilder::with_moderator()
+        .commands()
+        .manual_clock()
+        .start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+
+    moderator.send_message("/mute Notch 60s flooding");
+    assert_eq!(moderator.expect("CommandResult")["success"], true);
+    notch.expect("ModerationStatus");
+
+    server.advance_time(Duration::from_secs(59));
+    notch.send_message("still muted");
+    notch.expect_error(json!("Muted"));
+    moderator.expect_none(Duration::from_millis(200));
+
+    server.advance_time(Duration::from_secs(1));
+    notch.send_message("hello");
+    notch.expect("Message");
+    assert_eq!(moderator.expect("Message")["content"], "hello");
+}
+
+#[test]
+fn rate_limits_reset_after_their_window() {
+    let server = TestServerBuilder::new()
+        .config(|config| {
+            config.message.max_messages = 1;
+            config.message.count_duration = Duration::from_secs(10).into();
+        })
+        .manual_clock()
+        .start();
+    let mut notch = server.login("Notch", notch());
+
+    notch.send_message("first");
+    notch.expect("Message");
+    notch.send_message("second");
+    notch.expect_error(json!("RateLimited"));
+
+    server.advance_time(Duration::from_secs(11));
+    notch.send_message("third");
+    assert_eq!(notch.expect("Message")["content"], "third");
+}
@@ -0,0 +1,335 @@
+//! End-to-end tests of the moderation commands.
+#![cfg(feature = "testutil")]
+
+use axochat::config::BannedLogin;
+use axochat::testutil::{jeb, moderator, notch, TestClient, TestServerBuilder};
+use serde_json::json;
+use std::time::Duration;
+
+/// Runs `command` and returns the `CommandResult`, asserting whether it succeeded.
+fn run(client: &mut TestClient, command: &str, success: bool) -> serde_json::Value {
+    client.send_message(command);
+    let result = client.expect("CommandResult");
+    assert_eq!(result["success"], success, "unexpected result: {}", result);
+    result
+}
+
+fn audit_log(client: &mut TestClient) -> serde_json::Value {
+    client.send("RequestAuditLog", json!({}));
+    client.expect("AuditLog")["entries"].clone()
+}
+
+#[test]
+fn ban_passes_duration_and_reason_through() {
+    let server = TestServerBuilder::with_moderator().commands().start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+
+    let result = run(&mut moderator, "/ban Notch 1h spam and more", true);
+    assert_eq!(result["message"], "banned `Notch` for 1h");
+    assert_eq!(result["params"]["duration"], "1h");
+
+    let disconnected = notch.expect("Disconnected");
+    assert_eq!(disconnected["reason_code"], "banned");
+    assert_eq!(disconnected["reason"], "spam and more");
+    assert_eq!(notch.expect_close().0, 1008);
+
+    let entries = audit_log(&mut moderator);
+    assert_eq!(entries[0]["action"], "Ban");
+    assert_eq!(entries[0]["reason"], "spam and more");
+    assert_eq!(entries[0]["duration_secs"], 3600);
+
+    let banned = std::fs::read_to_string(server.dir().join("banned.txt")).unwrap();
+    assert!(banned.ends_with("\tspam and more\n"), "{:?}", banned);
+}
+
+#[test]
+fn ban_without_duration_is_permanent() {
+    let server = TestServerBuilder::with_moderator().commands().start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+
+    moderator.send_message("/ban Notch griefing");
+    let confirmation = moderator.expect("ConfirmAction");
+    assert_eq!(confirmation["summary"], "ban `Notch` permanently");
+    let result = moderator.expect("CommandResult");
+    assert_eq!(result["success"], true);
+    assert_eq!(result["translation_key"], "command.confirm");
+    notch.expect_none(Duration::from_millis(200));
+
+    moderator.send("ConfirmAction", json!({ "nonce": confirmation["nonce"] }));
+    assert_eq!(moderator.expect("Success")["reason"], "Ban");
+    assert_eq!(notch.expect("Disconnected")["reason"], "griefing");
+
+    let entries = audit_log(&mut moderator);
+    assert_eq!(entries[0]["action"], "Ban");
+    assert_eq!(entries[0]["reason"], "griefing");
+    assert!(entries[0].get("duration_secs").is_none());
+    assert_eq!(entries[1]["action"], "BanRequested");
+    assert_eq!(entries[1]["reason"], "griefing");
+}
+
+#[test]
+fn bans_with_a_duration_need_no_confirmation() {
+    let server = TestServerBuilder::with_moderator().commands().start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+
+    let result = run(&mut moderator, "/ban Notch 1h", true);
+    assert_eq!(result["message"], "banned `Notch` for 1h");
+    notch.expect("Disconnected");
+
+    let entries = audit_log(&mut moderator);
+    assert_eq!(entries.as_array().unwrap().len(), 1);
+    assert_eq!(entries[0]["action"], "Ban");
+}
+
+#[test]
+fn mute_blocks_messages_until_unmuted() {
+    let server = TestServerBuilder::with_moderator().commands().start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+
+    let result = run(&mut moderator, "/mute Notch 10m caps", true);
+    assert_eq!(result["message"], "muted `Notch` for 10m");
+    let status = notch.expect("ModerationStatus");
+    assert_eq!(status["muted"], true);
+    assert_eq!(status["reason"], "caps");
+
+    notch.send_message("hello");
+    notch.expect_error(json!("Muted"));
+    notch.send_private_message("Moderator", "psst");
+    notch.expect_error(json!("Muted"));
+    moderator.expect_none(Duration::from_millis(200));
+
+    run(&mut moderator, "/unmute Notch", true);
+    assert_eq!(notch.expect("ModerationStatus")["muted"], false);
+    let result = run(&mut moderator, &format!("/unmute {}", self::notch()), false);
+    assert_eq!(result["translation_key"], "error.not_muted");
+    notch.send_message("hello again");
+    notch.expect("Message");
+    assert_eq!(moderator.expect("Message")["content"], "hello again");
+
+    let actions: Vec<_> = audit_log(&mut moderator)
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["action"].clone())
+        .collect();
+    assert_eq!(actions, [json!("Unmute"), json!("Mute")]);
+}
+
+#[test]
+fn banned_users_learn_about_their_ban_when_they_log_in() {
+    let server = TestServerBuilder::with_moderator().commands().start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+    run(&mut moderator, "/ban Notch 1h spam", true);
+    notch.expect("Disconnected");
+    notch.expect_close();
+
+    let mut notch = server.login("Notch", self::notch());
+    let status = notch.expect("ModerationStatus");
+    assert_eq!(status["banned"], true);
+    assert_eq!(status["muted"], false);
+    assert_eq!(status["reason"], "spam");
+    assert!(status["expires_at"].is_u64(), "{}", status);
+}
+
+#[test]
+fn banned_users_can_be_refused_to_log_in() {
+    let server = TestServerBuilder::with_moderator()
+        .commands()
+        .config(|config| {
+            config.moderation.banned_login = BannedLogin::Reject;
+        })
+        .start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+    run(&mut moderator, "/ban Notch 1h spam", true);
+    notch.expect("Disconnected");
+    notch.expect_close();
+
+    let mut notch = server.client();
+    notch.send(
+        "LoginJWT",
+        json!({
+            "token": server.token("Notch", self::notch()),
+            "allow_messages": true,
+        }),
+    );
+    let disconnected = notch.expect("Disconnected");
+    assert_eq!(disconnected["reason_code"], "banned");
+    assert_eq!(disconnected["translation_key"], "disconnect.banned");
+    assert_eq!(disconnected["reason"], "spam");
+    let retry_after = disconnected["retry_after_secs"].as_u64().unwrap();
+    assert!(retry_after > 3500 && retry_after <= 3600, "{}", retry_after);
+    assert_eq!(notch.expect_close().0, 1008);
+
+    // Users who are only muted still log in.
+    let mut jeb = server.login("jeb_", jeb());
+    run(&mut moderator, "/mute jeb_ 1h caps", true);
+    jeb.expect("ModerationStatus");
+    let mut jeb = server.login("jeb_", self::jeb());
+    let status = jeb.expect("ModerationStatus");
+    assert_eq!(status["banned"], false);
+    assert_eq!(status["muted"], true);
+    assert_eq!(status["reason"], "caps");
+}
+
+#[test]
+fn a_lapsed_mute_is_reported() {
+    let server = TestServerBuilder::with_moderator()
+        .commands()
+        .config(|config| {
+            config.maintenance.interval = Duration::from_secs(1).into();
+        })
+        .manual_clock()
+        .start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+    run(&mut moderator, "/mute Notch 1m caps", true);
+    assert_eq!(notch.expect("ModerationStatus")["muted"], true);
+
+    server.advance_time(Duration::from_secs(61));
+    let status = notch.expect("ModerationStatus");
+    assert_eq!(status["muted"], false);
+    assert!(status.get("expires_at").is_none(), "{}", status);
+    notch.send_message("hello");
+    assert_eq!(moderator.expect("Message")["content"], "hello");
+}
+
+#[test]
+fn audit_log_pages_are_limited() {
+    let server = TestServerBuilder::with_moderator()
+        .commands()
+        .config(|config| {
+            config.message.max_messages = 100;
+            config.moderation.max_ban_actions = 0;
+            config.moderation.audit_log_max_entries = 25;
+        })
+        .start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+    for minutes in 1..=30 {
+        run(&mut moderator, &format!("/mute Notch {}m", minutes), true);
+        notch.expect("ModerationStatus");
+    }
+
+    let page = |moderator: &mut TestClient, request| {
+        moderator.send("RequestAuditLog", request);
+        let page = moderator.expect("AuditLog");
+        assert_eq!(page["total"], 30);
+        page["entries"].as_array().unwrap().len()
+    };
+    assert_eq!(page(&mut moderator, json!({})), 20);
+    assert_eq!(page(&mut moderator, json!({ "limit": 3 })), 3);
+    assert_eq!(page(&mut moderator, json!({ "limit": 1000 })), 25);
+}
+
+#[test]
+fn mute_requires_a_duration() {
+    let server = TestServerBuilder::with_moderator().commands().start();
+    let mut moderator = server.login("Moderator", moderator());
+    let _notch = server.login("Notch", notch());
+
+    let result = run(&mut moderator, "/mute Notch forever", false);
+    assert_eq!(result["translation_key"], "command.invalid_duration");
+    let result = run(&mut moderator, "/mute Notch", false);
+    assert_eq!(result["translation_key"], "command.usage");
+}
+
+#[test]
+fn kick_closes_the_connection_with_the_reason() {
+    let server = TestServerBuilder::with_moderator().commands().start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+
+    let result = run(&mut moderator, "/kick Notch \"calm down\"", true);
+    assert_eq!(result["message"], "kicked `Notch`");
+    let disconnected = notch.expect("Disconnected");
+    assert_eq!(disconnected["reason_code"], "kicked");
+    assert_eq!(disconnected["translation_key"], "disconnect.kicked");
+    assert_eq!(disconnected["reason"], "calm down");
+    assert_eq!(notch.expect_close(), (4010, Some("kicked".to_string())));
+
+    // Kicked users are not banned.
+    server.login("Notch", self::notch());
+    assert_eq!(audit_log(&mut moderator)[0]["action"], "Kick");
+}
+
+#[test]
+fn kick_of_an_offline_user_fails() {
+    let server = TestServerBuilder::with_moderator().commands().start();
+    let mut moderator = server.login("Moderator", moderator());
+
+    let result = run(&mut moderator, &format!("/kick {}", notch()), false);
+    assert_eq!(result["translation_key"], "error.user_not_found");
+}
+
+#[test]
+fn moderation_commands_require_a_moderator() {
+    let server = TestServerBuilder::with_moderator().commands().start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+
+    for command in &["/ban Moderator", "/mute Moderator 1m", "/kick Moderator"] {
+        let result = run(&mut notch, command, false);
+        assert_eq!(
+            result["translation_key"], "error.not_permitted",
+            "{}",
+            command
+        );
+    }
+    for command in &["/ban Moderator 1h", "/mute Moderator 1m", "/kick Moderator"] {
+        let result = run(&mut moderator, command, false);
+        assert_eq!(
+            result["translation_key"], "error.not_permitted",
+            "{}",
+            command
+        );
+    }
+}
+
+#[test]
+fn commands_are_rate_limited() {
+    let server = TestServerBuilder::new()
+        .commands()
+        .config(|config| {
+            config.message.max_messages = 2;
+        })
+        .start();
+    let mut notch = server.login("Notch", notch());
+
+    run(&mut notch, "/help", true);
+    run(&mut notch, "/motd", false);
+    notch.send_message("/help");
+    notch.expect_error(json!("RateLimited"));
+}
+
+#[test]
+fn repeated_commands_are_rate_limited() {
+    let server = TestServerBuilder::with_moderator().commands().start();
+    let mut notch = server.login("Notch", notch());
+
+    run(&mut notch, "/help", true);
+    notch.send_message("/help");
+    notch.expect_error(json!("RateLimited"));
+}
+
+#[test]
+fn private_messages_through_msg_are_limited_once() {
+    let server = TestServerBuilder::new()
+        .commands()
+        .config(|config| {
+            config.message.max_messages = 1;
+        })
+        .start();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+
+    notch.send_message("/msg Moderator hi");
+    assert_eq!(moderator.expect("PrivateMessage")["content"], "hi");
+    notch.send_message("/msg Moderator hi again");
+    notch.expect_error(json!("RateLimited"));
+}
@@ -0,0 +1,177 @@
+//! Tests of how packets sent by clients are decoded and why they are rejected.
+
+use axochat::chat::{decode_packet, protocol_schema, PacketLimits};
+use axochat::error::MalformedCategory;
+use axochat::error::MalformedCategory::*;
+use serde_json::{json, Value};
+
+const LIMITS: PacketLimits = PacketLimits {
+    max_size: 1024,
+    max_depth: 8,
+    max_malformed: 0,
+};
+
+fn decode(text: &str) -> Result<(), MalformedCategory> {
+    decode_packet(text, &LIMITS).map(|_| ())
+}
+
+#[test]
+fn classifies_the_corpus() {
+    let corpus: &[(&str, Result<(), MalformedCategory>)] = &[
+        (r#"{"m":"RequestUserCount"}"#, Ok(())),
+        (r#"{"m":"Message","c":{"content":"hello"}}"#, Ok(())),
+        (
+            r#"{"m":"Message","c":{"content":"hello","reply_to":3}}"#,
+            Ok(()),
+        ),
+        (r#"{"m":"ListBlockedWords","c":{}}"#, Ok(())),
+        // Features unknown to the server are ignored.
+        (r#"{"m":"Hello","c":{"features":["teleport"]}}"#, Ok(())),
+        (
+            r#"{"m":"LoginMojang","c":{"name":"Notch","uuid":"069a79f4-44e9-4726-a5be-fca90e38aaf5","allow_messages":true}}"#,
+            Ok(()),
+        ),
+        // Syntax
+        ("", Err(Syntax)),
+        ("{", Err(Syntax)),
+        (r#"{"m":"Message","c":{"content":"hello"}"#, Err(Syntax)),
+        (r#"{"m":"Message",}"#, Err(Syntax)),
+        ("not json", Err(Syntax)),
+        (
+            r#"{"m":"Message","c":{"content":"hello"}} trailing"#,
+            Err(Syntax),
+        ),
+        // Unknown packets
+        (r#"{"m":"Teleport"}"#, Err(UnknownPacket)),
+        (
+            r#"{"m":"message","c":{"content":"hello"}}"#,
+            Err(UnknownPacket),
+        ),
+        (r#"{"m":"","c":{}}"#, Err(UnknownPacket)),
+        // Missing fields
+        (r#"{}"#, Err(MissingField)),
+        (r#"{"c":{"content":"hello"}}"#, Err(MissingField)),
+        (r#"{"m":"Message"}"#, Err(MissingField)),
+        (r#"{"m":"Message","c":{}}"#, Err(MissingField)),
+        (r#"{"m":"LoginJWT","c":{"token":"abc"}}"#, Err(MissingField)),
+        (
+            r#"{"m":"LoginMojang","c":{"name":"Notch"}}"#,
+            Err(MissingField),
+        ),
+        // Type mismatches
+        ("[]", Err(TypeMismatch)),
+        ("42", Err(TypeMismatch)),
+        (r#"{"m":7}"#, Err(TypeMismatch)),
+        (r#"{"m":"Message","c":"hello"}"#, Err(TypeMismatch)),
+        (r#"{"m":"Message","c":{"content":7}}"#, Err(TypeMismatch)),
+        (
+            r#"{"m":"BanUser","c":{"user":"not a uuid"}}"#,
+            Err(TypeMismatch),
+        ),
+        (r#"{"m":"ResyncFrom","c":{"seq":-1}}"#, Err(TypeMismatch)),
+        (
+            r#"{"m":"SetStatus","c":{"status":"away"}}"#,
+            Err(TypeMismatch),
+        ),
+        (
+            r#"{"m":"Hello","c":{"features":"teleport"}}"#,
+            Err(TypeMismatch),
+        ),
+    ];
+    for (text, expected) in corpus {
+        assert_eq!(decode(text), *expected, "{}", text);
+    }
+}
+
+#[test]
+fn checks_the_limits_before_parsing() {
+    let large = format!(
+        r#"{{"m":"Message","c":{{"content":"{}"}}}}"#,
+        "a".repeat(1024)
+    );
+    assert_eq!(decode(&large), Err(TooLarge));
+    // Broken JSON which is too large or deep is rejected for its size or depth.
+    assert_eq!(decode(&format!("{{{}", "a".repeat(1024))), Err(TooLarge));
+    assert_eq!(decode(&"[".repeat(9)), Err(TooDeep));
+    assert_eq!(
+        decode(&format!("{}{}", "[".repeat(8), "]".repeat(8))),
+        Err(TypeMismatch)
+    );
+    // Brackets in strings do not count.
+    let brackets = json!({ "m": "Message", "c": { "content": "[[[[[[[[[[" } });
+    assert_eq!(decode(&brackets.to_string()), Ok(()));
+}
+
+/// The serverbound packets of the schema, with whether they have content.
+fn serverbound() -> Vec<(String, bool)> {
+    let schema = serde_json::to_value(protocol_schema()).unwrap();
+    schema["serverbound"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|packet| {
+            let name = packet["name"].as_str().unwrap().to_string();
+            let content = packet.get("fields").is_some() || packet.get("content").is_some();
+            (name, content)
+        })
+        .collect()
+}
+
+/// Whether a packet of the schema has any field which can not be omitted.
+fn has_required_fields(name: &str) -> bool {
+    let schema = serde_json::to_value(protocol_schema()).unwrap();
+    let packet = schema["serverbound"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|packet| packet["name"] == name)
+        .unwrap();
+    let fields = match packet.get("content") {
+        Some(Value::String(ty)) => schema["types"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|t| t["name"] == *ty)
+            .unwrap()["fields"]
+            .clone(),
+        _ => packet["fields"].clone(),
+    };
+    fields
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|field| field["optional"] == false)
+}
+
+#[test]
+fn the_schema_agrees_with_the_decoder() {
+    for (name, content) in serverbound() {
+        if !content {
+            assert_eq!(
+                decode(&json!({ "m": name }).to_string()),
+                Ok(()),
+                "{}",
+                name
+            );
+            continue;
+        }
+        assert_eq!(
+            decode(&json!({ "m": name }).to_string()),
+            Err(MissingField),
+            "{}",
+            name
+        );
+        // Fields the schema marks as optional must also be optional to the decoder.
+        let expected = if has_required_fields(&name) {
+            Err(MissingField)
+        } else {
+            Ok(())
+        };
+        assert_eq!(
+            decode(&json!({ "m": name, "c": {} }).to_string()),
+            expected,
+            "{}",
+            name
+        );
+    }
+}
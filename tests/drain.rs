@@ -0,0 +1,114 @@
+//! End-to-end tests of draining an instance before a deploy.
+#![cfg(feature = "testutil")]
+
+use axochat::testutil::{TestClient, TestServer, TestServerBuilder};
+use std::thread;
+use std::time::Duration;
+use uuid::Uuid;
+
+const TOKEN: &str = "admin-token";
+
+/// Starts a server closing half of its connections every second while it drains.
+fn server() -> TestServer {
+    TestServerBuilder::new()
+        .config(|config| {
+            config.api.token = Some(TOKEN.to_string());
+            config.server.metrics = true;
+            config.drain.step_percent = 50;
+            config.drain.step_interval = Duration::from_secs(1).into();
+            config.drain.retry_after = Duration::from_secs(7).into();
+        })
+        .start()
+}
+
+/// Sends `POST path` to the admin API and returns whether the instance drains afterwards.
+fn post(server: &TestServer, path: &str) -> bool {
+    let response = server.request("POST", path, Some(TOKEN), None);
+    assert_eq!(response.status, 200, "{}", response.text());
+    response.json()["draining"].as_bool().unwrap()
+}
+
+fn login_users(server: &TestServer, count: u128) -> Vec<TestClient<'_>> {
+    (0..count)
+        .map(|i| server.login(&format!("user{}", i), Uuid::from_u128(i + 1)))
+        .collect()
+}
+
+/// Removes the clients which were told to reconnect and returns how many there were.
+fn take_drained(clients: &mut Vec<TestClient>) -> usize {
+    let before = clients.len();
+    clients.retain_mut(
+        |client| match client.try_next_packet(Duration::from_millis(50)) {
+            Some(packet) => {
+                assert_eq!(packet.name, "Disconnected", "{:?}", packet);
+                assert_eq!(packet.content["reason_code"], "migrate");
+                assert_eq!(packet.content["retry_after_secs"], 7);
+                assert_eq!(client.expect_close().0, 4009);
+                false
+            }
+            None => true,
+        },
+    );
+    before - clients.len()
+}
+
+#[test]
+fn draining_instances_are_not_ready_and_refuse_handshakes() {
+    let server = server();
+    assert_eq!(server.request("GET", "/ready", None, None).status, 200);
+
+    assert!(post(&server, "/api/v1/drain"));
+    let ready = server.request("GET", "/ready", None, None);
+    assert_eq!(ready.status, 503);
+    assert_eq!(ready.header("Retry-After"), Some("7"));
+
+    assert_eq!(server.handshake(&[]).err(), Some(503));
+    let refused = server.request("GET", "/ws", None, None);
+    assert_eq!(refused.status, 503);
+    assert_eq!(refused.header("Retry-After"), Some("7"));
+
+    let metrics = server.request("GET", "/metrics", None, None).text();
+    assert!(metrics.contains("\naxochat_draining 1\n"), "{}", metrics);
+}
+
+#[test]
+fn connections_are_closed_in_steps() {
+    let server = server();
+    let mut clients = login_users(&server, 4);
+
+    assert!(post(&server, "/api/v1/drain"));
+    thread::sleep(Duration::from_millis(1500));
+    assert_eq!(take_drained(&mut clients), 2);
+
+    thread::sleep(Duration::from_secs(1));
+    assert_eq!(take_drained(&mut clients), 2);
+    assert!(clients.is_empty());
+}
+
+#[test]
+fn undraining_stops_closing_connections() {
+    let server = server();
+    let mut clients = login_users(&server, 4);
+
+    assert!(post(&server, "/api/v1/drain"));
+    thread::sleep(Duration::from_millis(1500));
+    assert_eq!(take_drained(&mut clients), 2);
+
+    assert!(!post(&server, "/api/v1/undrain"));
+    assert_eq!(server.request("GET", "/ready", None, None).status, 200);
+    thread::sleep(Duration::from_millis(1500));
+    assert_eq!(take_drained(&mut clients), 0);
+    assert!(server.handshake(&[]).is_ok());
+}
+
+#[test]
+fn draining_again_cancels_it() {
+    let server = server();
+    let mut clients = login_users(&server, 2);
+
+    assert!(post(&server, "/api/v1/drain"));
+    assert!(!post(&server, "/api/v1/drain"));
+    assert_eq!(server.request("GET", "/ready", None, None).status, 200);
+    thread::sleep(Duration::from_millis(1500));
+    assert_eq!(take_drained(&mut clients), 0);
+}
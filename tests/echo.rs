@@ -0,0 +1,63 @@
+//! End-to-end tests of the echo of own messages.
+#![cfg(feature = "testutil")]
+
+use axochat::testutil::{jeb, notch, TestClient, TestServer};
+use serde_json::json;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Connects a client which sends `Hello` before it logs in.
+fn login<'a>(server: &'a TestServer, name: &str, uuid: Uuid) -> TestClient<'a> {
+    let mut client = server.client();
+    client.hello(&[]);
+    client.login_as(name, uuid);
+    client
+}
+
+#[test]
+fn own_messages_are_echoed_by_default() {
+    let server = TestServer::start();
+    let mut notch = login(&server, "Notch", notch());
+
+    notch.send_message("hello");
+    assert_eq!(notch.expect("Message")["content"], "hello");
+}
+
+#[test]
+fn only_the_sending_connection_receives_an_ack_instead() {
+    let server = TestServer::start();
+    let mut sender = login(&server, "Notch", notch());
+    let mut other = login(&server, "Notch", notch());
+    let mut jeb = login(&server, "jeb_", jeb());
+
+    sender.send("SetEchoOwnMessages", json!({ "enabled": false }));
+    sender.send_message("hello");
+    let ack = sender.expect("MessageAck");
+    assert!(ack["seq"].is_u64(), "{}", ack);
+    assert!(ack["timestamp"].is_u64(), "{}", ack);
+    sender.expect_none(Duration::from_millis(200));
+
+    // Other connections of the author still receive the message.
+    let message = other.expect("Message");
+    assert_eq!(message["content"], "hello");
+    assert_eq!(message["seq"], ack["seq"]);
+    assert_eq!(jeb.expect("Message")["seq"], ack["seq"]);
+}
+
+#[test]
+fn the_echo_can_be_disabled_in_hello() {
+    let server = TestServer::start();
+    let mut notch = server.client();
+    notch.send(
+        "Hello",
+        json!({ "features": [], "echo_own_messages": false }),
+    );
+    notch.login_as("Notch", self::notch());
+
+    notch.send_message("hello");
+    notch.expect("MessageAck");
+
+    notch.send("SetEchoOwnMessages", json!({ "enabled": true }));
+    notch.send_message("hello again");
+    assert_eq!(notch.expect("Message")["content"], "hello again");
+}
@@ -0,0 +1,126 @@
+//! End-to-end tests of how errors are sent to clients.
+#![cfg(feature = "testutil")]
+
+use axochat::testutil::{notch, TestClient, TestServer};
+use serde_json::json;
+use std::time::Duration;
+
+#[test]
+fn errors_carry_their_translation() {
+    let server = TestServer::start();
+    let mut client = server.client();
+    client.hello(&[]);
+
+    client.send_message("hello");
+    let error = client.expect("Error");
+    assert_eq!(error["message"], "NotLoggedIn");
+    assert_eq!(error["translation_key"], "error.not_logged_in");
+    assert_eq!(error["params"], json!({}));
+
+    client.login_as("Notch", notch());
+    client.send_message(&"a".repeat(1000));
+    let error = client.expect("Error");
+    assert_eq!(error["translation_key"], "error.message_too_long");
+    assert_eq!(error["params"]["length"], "1000");
+    assert_eq!(error["params"]["unit"], "chars");
+}
+
+#[test]
+fn legacy_clients_only_receive_the_name_of_errors() {
+    let server = TestServer::start();
+    let mut client = server.client();
+
+    client.send_message("hello");
+    assert_eq!(client.expect("Error"), json!({ "message": "NotLoggedIn" }));
+}
+
+/// Longer than a summary of suppressed errors takes to be sent.
+const SUMMARY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Sends `count` messages, which are rejected, since `client` did not log in.
+fn send_rejected(client: &mut TestClient, count: usize) {
+    for i in 0..count {
+        client.send_message(&format!("hello {}", i));
+    }
+}
+
+#[test]
+fn identical_errors_are_summarized() {
+    let server = TestServer::start();
+    let mut client = server.client();
+    client.hello(&[]);
+
+    send_rejected(&mut client, 10);
+    for _ in 0..3 {
+        let error = client.expect("Error");
+        assert_eq!(error["message"], "NotLoggedIn");
+        assert!(error.get("repeated").is_none());
+    }
+    let summary = client
+        .try_next_packet(SUMMARY_TIMEOUT)
+        .expect("no summary received");
+    assert_eq!(summary.name, "Error");
+    assert_eq!(summary.content["message"], "NotLoggedIn");
+    assert_eq!(summary.content["translation_key"], "error.not_logged_in");
+    assert_eq!(summary.content["repeated"], 7);
+    client.expect_none(SUMMARY_TIMEOUT);
+
+    // The next window starts with unsuppressed errors again.
+    send_rejected(&mut client, 1);
+    assert!(client.expect("Error").get("repeated").is_none());
+}
+
+#[test]
+fn distinct_errors_are_not_coalesced() {
+    let server = TestServer::start();
+    let mut client = server.client();
+    client.hello(&[]);
+    client.login_as("Notch", notch());
+
+    for i in 0..5 {
+        client.send_message(&"a".repeat(1000 + i));
+        client.send_private_message("nobody", &format!("hello {}", i));
+    }
+    for _ in 0..5 {
+        let error = client.expect("Error");
+        assert_eq!(error["translation_key"], "error.message_too_long");
+        assert!(error.get("repeated").is_none());
+        let error = client.expect("Error");
+        assert_eq!(error["translation_key"], "error.user_not_found");
+        assert!(error.get("repeated").is_none());
+    }
+    client.expect_none(SUMMARY_TIMEOUT);
+}
+
+#[test]
+fn other_errors_and_successes_end_the_suppression() {
+    let server = TestServer::start();
+    let mut client = server.client();
+    client.hello(&[]);
+
+    send_rejected(&mut client, 5);
+    client.send(
+        "LoginJWT",
+        json!({ "token": "not a token", "allow_messages": true }),
+    );
+    for _ in 0..3 {
+        assert_eq!(client.expect("Error")["message"], "NotLoggedIn");
+    }
+    // The summary is sent before the other error, not when the window closes.
+    let summary = client.expect("Error");
+    assert_eq!(summary["message"], "NotLoggedIn");
+    assert_eq!(summary["repeated"], 2);
+    assert!(client.expect("Error").get("repeated").is_none());
+
+    send_rejected(&mut client, 5);
+    client.send(
+        "LoginJWT",
+        json!({ "token": server.token("Notch", notch()), "allow_messages": true }),
+    );
+    for _ in 0..3 {
+        assert_eq!(client.expect("Error")["message"], "NotLoggedIn");
+    }
+    assert_eq!(client.expect("Error")["repeated"], 2);
+    assert_eq!(client.expect("Success")["reason"], "Login");
+    client.expect_none(SUMMARY_TIMEOUT);
+}
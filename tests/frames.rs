@@ -0,0 +1,200 @@
+//! End-to-end tests of how the server handles websocket frames other clients would not send.
+#![cfg(feature = "testutil")]
+
+use axochat::chat::PROTOCOL_VERSION;
+use axochat::testutil::TestServer;
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/// A websocket client which writes frames exactly as it is told to.
+struct RawClient {
+    stream: TcpStream,
+}
+
+impl RawClient {
+    fn connect(server: &TestServer) -> RawClient {
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        write!(
+            stream,
+            "GET /ws HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n",
+            server.addr()
+        )
+        .unwrap();
+
+        // The response is read byte by byte, so no frame is read with it.
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101"), "{}", response);
+        RawClient { stream }
+    }
+
+    /// Writes a masked frame.
+    fn send_frame(&mut self, fin: bool, opcode: u8, payload: &[u8]) {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len if len <= 0xffff => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+        // The server may close the connection before it read everything.
+        self.stream.write_all(&frame).ok();
+    }
+
+    fn send(&mut self, packet: Value) {
+        self.send_frame(true, TEXT, packet.to_string().as_bytes());
+    }
+
+    fn hello(&mut self) {
+        self.send(json!({
+            "m": "Hello",
+            "c": { "features": [], "protocol": PROTOCOL_VERSION },
+        }));
+    }
+
+    /// Reads the next frame and returns its opcode and payload.
+    fn read_frame(&mut self) -> (u8, Vec<u8>) {
+        let mut header = [0; 2];
+        self.stream.read_exact(&mut header).unwrap();
+        assert_eq!(header[0] & 0x80, 0x80, "the server sent a fragment");
+        let len = match header[1] & 0x7f {
+            126 => {
+                let mut len = [0; 2];
+                self.stream.read_exact(&mut len).unwrap();
+                u16::from_be_bytes(len) as usize
+            }
+            127 => {
+                let mut len = [0; 8];
+                self.stream.read_exact(&mut len).unwrap();
+                u64::from_be_bytes(len) as usize
+            }
+            len => len as usize,
+        };
+        let mut payload = vec![0; len];
+        self.stream.read_exact(&mut payload).unwrap();
+        (header[0] & 0x0f, payload)
+    }
+
+    /// Reads the next packet, skipping other frames.
+    fn expect(&mut self, name: &str) -> Value {
+        loop {
+            match self.read_frame() {
+                (TEXT, payload) => {
+                    let packet: Value = serde_json::from_slice(&payload).unwrap();
+                    assert_eq!(packet["m"], name, "unexpected packet {}", packet);
+                    return packet["c"].clone();
+                }
+                (CLOSE, payload) => panic!("connection was closed: {:?}", payload),
+                _ => {}
+            }
+        }
+    }
+
+    /// Reads frames until the close frame and returns its code.
+    fn expect_close(&mut self) -> u16 {
+        loop {
+            if let (CLOSE, payload) = self.read_frame() {
+                return u16::from_be_bytes([payload[0], payload[1]]);
+            }
+        }
+    }
+}
+
+#[test]
+fn fragmented_messages_close_the_connection() {
+    let server = TestServer::start();
+    let mut client = RawClient::connect(&server);
+    client.send_frame(false, TEXT, br#"{"m":"RequestUser"#);
+    client.send_frame(true, CONTINUATION, br#"Count"}"#);
+    assert_eq!(client.expect_close(), 1002);
+}
+
+#[test]
+fn continuations_without_a_start_close_the_connection() {
+    let server = TestServer::start();
+    let mut client = RawClient::connect(&server);
+    client.send_frame(true, CONTINUATION, br#"{"m":"RequestUserCount"}"#);
+    assert_eq!(client.expect_close(), 1002);
+}
+
+#[test]
+fn oversized_frames_close_the_connection() {
+    let server = TestServer::start();
+    let mut client = RawClient::connect(&server);
+    let content = "a".repeat(128 * 1024);
+    client.send(json!({ "m": "Message", "c": { "content": content } }));
+    assert_eq!(client.expect_close(), 1009);
+}
+
+#[test]
+fn invalid_utf8_closes_the_connection() {
+    let server = TestServer::start();
+    let mut client = RawClient::connect(&server);
+    client.send_frame(true, TEXT, b"{\"m\":\"\xff\xfe\"}");
+    assert_eq!(client.expect_close(), 1007);
+}
+
+#[test]
+fn binary_frames_are_malformed_packets() {
+    let server = TestServer::start();
+    let mut client = RawClient::connect(&server);
+    client.hello();
+    client.send_frame(true, BINARY, br#"{"m":"RequestUserCount"}"#);
+    let error = client.expect("Error");
+    assert_eq!(error["message"]["MalformedPacket"]["category"], "binary");
+    assert_eq!(error["translation_key"], "error.malformed_packet");
+
+    // The connection stays usable.
+    client.send(json!({ "m": "RequestMojangInfo" }));
+    client.expect("MojangInfo");
+}
+
+#[test]
+fn pings_are_answered_and_pongs_ignored() {
+    let server = TestServer::start();
+    let mut client = RawClient::connect(&server);
+    client.send_frame(true, PING, b"are you there?");
+    assert_eq!(client.read_frame(), (PONG, b"are you there?".to_vec()));
+
+    client.send_frame(true, PONG, b"unsolicited");
+    client.send(json!({ "m": "RequestMojangInfo" }));
+    client.expect("MojangInfo");
+}
+
+#[test]
+fn close_frames_are_answered() {
+    let server = TestServer::start();
+    let mut client = RawClient::connect(&server);
+    client.send_frame(true, CLOSE, &1000u16.to_be_bytes());
+    assert_eq!(client.expect_close(), 1000);
+}
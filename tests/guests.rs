@@ -0,0 +1,92 @@
+//! End-to-end tests of connections watching the chat without logging in.
+#![cfg(feature = "testutil")]
+
+use axochat::chat::close::GUEST_LIMIT;
+use axochat::testutil::{jeb, notch, TestServerBuilder};
+use serde_json::json;
+use std::time::Duration;
+
+#[test]
+fn guests_receive_broadcasts() {
+    let server = TestServerBuilder::new()
+        .guests(|guests| guests.login_timeout = Duration::from_millis(100).into())
+        .start();
+    let mut guest = server.client();
+    let mut notch = server.login("Notch", notch());
+
+    // Guests stay connected past the login timeout.
+    guest.expect_none(Duration::from_millis(300));
+    notch.send_message("hello");
+    let message = guest.expect("Message");
+    assert_eq!(message["content"], "hello");
+    assert_eq!(message["author_info"]["name"], "Notch");
+}
+
+#[test]
+fn guests_can_not_send_anything() {
+    let server = TestServerBuilder::new().start();
+    let mut guest = server.client();
+    let mut notch = server.login("Notch", notch());
+
+    guest.send_message("hello");
+    guest.expect_error(json!("NotLoggedIn"));
+    guest.send_private_message("Notch", "psst");
+    guest.expect_error(json!("NotLoggedIn"));
+    notch.expect_none(Duration::from_millis(200));
+}
+
+#[test]
+fn guests_become_users_by_logging_in() {
+    let server = TestServerBuilder::new().start();
+    let mut guest = server.client();
+    let mut notch = server.login("Notch", notch());
+
+    notch.send_message("hello");
+    guest.expect("Message");
+    notch.expect("Message");
+
+    guest.login_as("jeb_", jeb());
+    guest.send_message("hi");
+    let message = notch.expect("Message");
+    assert_eq!(message["content"], "hi");
+    assert_eq!(message["author_info"]["name"], "jeb_");
+    assert_eq!(guest.expect("Message")["content"], "hi");
+}
+
+#[test]
+fn guests_without_broadcasts_receive_nothing() {
+    let server = TestServerBuilder::new()
+        .guests(|guests| guests.guest_events = Vec::new())
+        .start();
+    let mut guest = server.client();
+    let mut notch = server.login("Notch", notch());
+
+    notch.send_message("hello");
+    notch.expect("Message");
+    guest.expect_none(Duration::from_millis(200));
+}
+
+#[test]
+fn guests_are_limited_per_address_if_configured() {
+    let server = TestServerBuilder::new()
+        .guests(|guests| guests.max_guests_per_ip = 2)
+        .start();
+    let mut first = server.client();
+    let _second = server.client();
+
+    assert_eq!(server.client().expect_close().0, GUEST_LIMIT);
+
+    // Users do not count towards the limit.
+    first.login_as("Notch", notch());
+    let mut third = server.client();
+    third.expect_none(Duration::from_millis(200));
+}
+
+#[test]
+fn connections_are_not_limited_per_address_by_default() {
+    let server = TestServerBuilder::new().start();
+    let mut guests: Vec<_> = (0..8).map(|_| server.client()).collect();
+    for guest in &mut guests {
+        guest.expect_none(Duration::from_millis(50));
+    }
+}
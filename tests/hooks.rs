@@ -0,0 +1,151 @@
+//! End-to-end tests of custom rules registered as a `ChatHook`.
+#![cfg(feature = "testutil")]
+
+use axochat::auth::UserInfo;
+use axochat::chat::{ChatHook, HookDecision};
+use axochat::error::ClientError;
+use axochat::testutil::{jeb, notch, TestServer, TestServerBuilder};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Rejects messages mentioning `word`, in public and in private.
+struct Reject(&'static str);
+
+impl ChatHook for Reject {
+    fn on_message(&mut self, _author: &UserInfo, content: &str) -> HookDecision {
+        if content.contains(self.0) {
+            HookDecision::Reject(ClientError::BlockedContent)
+        } else {
+            HookDecision::Allow
+        }
+    }
+
+    fn on_private_message(
+        &mut self,
+        author: &UserInfo,
+        _receiver: &str,
+        content: &str,
+    ) -> HookDecision {
+        self.on_message(author, content)
+    }
+}
+
+/// Replaces `from` with `to` in messages.
+struct Replace(&'static str, &'static str);
+
+impl ChatHook for Replace {
+    fn on_message(&mut self, _author: &UserInfo, content: &str) -> HookDecision {
+        HookDecision::Rewrite(content.replace(self.0, self.1))
+    }
+}
+
+/// Panics for every message.
+struct Panicking;
+
+impl ChatHook for Panicking {
+    fn on_message(&mut self, _author: &UserInfo, _content: &str) -> HookDecision {
+        panic!("the hook is broken");
+    }
+}
+
+/// Records the logins and disconnects it is told about.
+struct Lifecycle(Arc<Mutex<Vec<String>>>);
+
+impl ChatHook for Lifecycle {
+    fn on_login(&mut self, user: &UserInfo) {
+        self.0.lock().unwrap().push(format!("login {}", user.name));
+    }
+
+    fn on_disconnect(&mut self, user: Option<&UserInfo>) {
+        let name = user.map_or("guest", |user| user.name.as_str());
+        self.0.lock().unwrap().push(format!("disconnect {}", name));
+    }
+}
+
+fn server<H: ChatHook + Send + 'static>(hooks: Vec<H>) -> TestServer {
+    TestServerBuilder::new()
+        .setup(move |builder| {
+            hooks
+                .into_iter()
+                .fold(builder, |builder, hook| builder.hook(hook))
+        })
+        .start()
+}
+
+#[test]
+fn rejected_messages_are_not_sent() {
+    let server = server(vec![Reject("forbidden")]);
+    let mut notch = server.login("Notch", notch());
+    let mut jeb = server.login("jeb_", jeb());
+
+    notch.send_message("something forbidden");
+    notch.expect_error(json!("BlockedContent"));
+    notch.send_private_message("jeb_", "forbidden secrets");
+    notch.expect_error(json!("BlockedContent"));
+    jeb.expect_none(Duration::from_millis(200));
+
+    notch.send_message("something else");
+    assert_eq!(jeb.expect("Message")["content"], "something else");
+}
+
+#[test]
+fn rewrites_are_passed_on_to_the_next_hook() {
+    let server = server(vec![Replace("cat", "dog"), Replace("dog", "wolf")]);
+    let mut notch = server.login("Notch", notch());
+    let mut jeb = server.login("jeb_", jeb());
+
+    notch.send_message("a cat");
+    assert_eq!(jeb.expect("Message")["content"], "a wolf");
+}
+
+#[test]
+fn rewritten_messages_are_validated_again() {
+    let server = server(vec![Replace("spam", "")]);
+    let mut notch = server.login("Notch", notch());
+    let mut jeb = server.login("jeb_", jeb());
+
+    notch.send_message("spam");
+    notch.expect_error(json!("EmptyMessage"));
+    jeb.expect_none(Duration::from_millis(200));
+}
+
+#[test]
+fn panicking_hooks_allow_the_message() {
+    let server = TestServerBuilder::new()
+        .setup(|builder| builder.hook(Panicking).hook(Replace("cat", "dog")))
+        .start();
+    let mut notch = server.login("Notch", notch());
+    let mut jeb = server.login("jeb_", jeb());
+
+    notch.send_message("a cat");
+    assert_eq!(jeb.expect("Message")["content"], "a dog");
+    // The server keeps running the hooks.
+    notch.send_message("another cat");
+    assert_eq!(jeb.expect("Message")["content"], "another dog");
+}
+
+#[test]
+fn hooks_are_told_about_logins_and_disconnects() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let server = server(vec![Lifecycle(events.clone())]);
+    let notch = server.login("Notch", notch());
+    let guest = server.client();
+    let mut jeb = server.login("jeb_", jeb());
+
+    drop(notch);
+    drop(guest);
+    // Waits until the server handled both disconnects.
+    jeb.expect_none(Duration::from_millis(300));
+    let mut events = events.lock().unwrap().clone();
+    events[2..].sort();
+    assert_eq!(
+        events,
+        [
+            "login Notch",
+            "login jeb_",
+            "disconnect Notch",
+            "disconnect guest"
+        ]
+    );
+}
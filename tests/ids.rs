@@ -0,0 +1,118 @@
+//! Property tests of the rules by which names are compared.
+
+use axochat::chat::CanonicalId;
+use proptest::prelude::*;
+
+/// How a character of a name is spelled.
+#[derive(Debug, Clone, Copy)]
+enum Spelling {
+    Lower,
+    Upper,
+    /// The fullwidth form, like `ｎ`, which NFKC normalizes to the ASCII character.
+    Fullwidth,
+}
+
+fn spelling() -> impl Strategy<Value = Spelling> {
+    prop_oneof![
+        Just(Spelling::Lower),
+        Just(Spelling::Upper),
+        Just(Spelling::Fullwidth)
+    ]
+}
+
+/// A lowercase name like Minecraft allows them.
+fn name() -> impl Strategy<Value = String> {
+    "[a-z0-9_]{1,16}"
+}
+
+/// Spells `name` with one spelling per character, repeating them if there are fewer.
+fn spell(name: &str, spellings: &[Spelling]) -> String {
+    name.chars()
+        .zip(spellings.iter().cycle())
+        .map(|(c, spelling)| match spelling {
+            Spelling::Lower => c,
+            Spelling::Upper => c.to_ascii_uppercase(),
+            Spelling::Fullwidth => {
+                std::char::from_u32(c as u32 - 0x21 + 0xff01).expect("not a printable character")
+            }
+        })
+        .collect()
+}
+
+/// Letters with an accent, precomposed and as a letter followed by the combining accent.
+const ACCENTED: &[(&str, &str)] = &[
+    ("é", "e\u{301}"),
+    ("É", "E\u{301}"),
+    ("ü", "u\u{308}"),
+    ("ñ", "n\u{303}"),
+    ("å", "a\u{30a}"),
+];
+
+proptest! {
+    #[test]
+    fn spellings_of_a_name_are_the_same_id(
+        name in name(),
+        a in prop::collection::vec(spelling(), 1..16),
+        b in prop::collection::vec(spelling(), 1..16),
+    ) {
+        let expected = CanonicalId::from_display_name(&name);
+        prop_assert_eq!(CanonicalId::from_display_name(&spell(&name, &a)), expected.clone());
+        prop_assert_eq!(CanonicalId::parse_client_input(&spell(&name, &b)), expected);
+    }
+
+    #[test]
+    fn composed_and_decomposed_accents_are_the_same_id(
+        parts in prop::collection::vec((name(), 0..ACCENTED.len()), 1..4),
+    ) {
+        let composed: String = parts
+            .iter()
+            .map(|(name, accent)| format!("{}{}", name, ACCENTED[*accent].0))
+            .collect();
+        let decomposed: String = parts
+            .iter()
+            .map(|(name, accent)| format!("{}{}", name, ACCENTED[*accent].1))
+            .collect();
+        prop_assert_eq!(
+            CanonicalId::from_display_name(&composed),
+            CanonicalId::from_display_name(&decomposed)
+        );
+    }
+
+    #[test]
+    fn different_names_never_collide(
+        a in name(),
+        b in name(),
+        spellings in prop::collection::vec(spelling(), 1..16),
+    ) {
+        prop_assume!(a != b);
+        prop_assert_ne!(
+            CanonicalId::from_display_name(&spell(&a, &spellings)),
+            CanonicalId::from_display_name(&spell(&b, &spellings))
+        );
+    }
+
+    #[test]
+    fn client_input_is_trimmed(name in name(), padding in "[ \t]{0,3}") {
+        let input = format!("{}{}{}", padding, name, padding);
+        prop_assert_eq!(
+            CanonicalId::parse_client_input(&input),
+            CanonicalId::from_display_name(&name)
+        );
+    }
+
+    #[test]
+    fn deserialized_ids_are_normalized(
+        name in name(),
+        spellings in prop::collection::vec(spelling(), 1..16),
+    ) {
+        let json = serde_json::to_string(&spell(&name, &spellings)).unwrap();
+        let id: CanonicalId = serde_json::from_str(&json).unwrap();
+        prop_assert_eq!(id, CanonicalId::from_display_name(&name));
+    }
+
+    #[test]
+    fn canonicalizing_is_idempotent(name in "\\PC{0,16}") {
+        let id = CanonicalId::from_display_name(&name);
+        prop_assert_eq!(CanonicalId::from_display_name(id.as_str()), id);
+    }
+}
@@ -0,0 +1,160 @@
+//! End-to-end tests of the join cooldown and the users who are exempt from it.
+#![cfg(feature = "testutil")]
+
+use axochat::config::{BotConfig, Config};
+use axochat::testutil::{jeb, moderator, notch, TestClient, TestServer, TestServerBuilder};
+use serde_json::json;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Starts a server with a join cooldown of a minute and a grace period of five minutes.
+fn server() -> TestServer {
+    server_with(|_| ())
+}
+
+/// Starts a server like [`server`], with the configuration changed by `f`.
+fn server_with<F: FnOnce(&mut Config)>(f: F) -> TestServer {
+    TestServerBuilder::with_moderator()
+        .config(|config| {
+            config.moderation.join_cooldown_secs = 60;
+            config.moderation.join_cooldown_grace_secs = 5 * 60;
+            f(config);
+        })
+        .manual_clock()
+        .start()
+}
+
+/// Connects a client speaking the current protocol, which is told how long the cooldown lasts.
+fn login<'a>(server: &'a TestServer, name: &str, uuid: Uuid) -> TestClient<'a> {
+    let mut client = server.client();
+    client.hello(&[]);
+    client.login_as(name, uuid);
+    client
+}
+
+/// Expects `client` to be rejected for another `remaining_secs`.
+fn expect_cooldown(client: &mut TestClient, remaining_secs: u64) {
+    client.expect_error(json!({ "JoinCooldown": { "remaining_secs": remaining_secs } }));
+}
+
+/// Closes the connection of `client` and waits until the server remembered when the user was last seen.
+fn leave(client: TestClient, witness: &mut TestClient) {
+    drop(client);
+    witness.expect_none(Duration::from_millis(300));
+}
+
+#[test]
+fn new_users_wait_before_sending_public_messages() {
+    let server = server();
+    let mut notch = login(&server, "Notch", notch());
+
+    notch.send_message("first!");
+    expect_cooldown(&mut notch, 60);
+    server.advance_time(Duration::from_secs(45));
+    notch.send_message("second!");
+    expect_cooldown(&mut notch, 15);
+
+    server.advance_time(Duration::from_secs(15));
+    notch.send_message("third!");
+    assert_eq!(notch.expect("Message")["content"], "third!");
+}
+
+#[test]
+fn private_messages_are_only_blocked_if_configured() {
+    let server = server();
+    let mut jeb = login(&server, "jeb_", jeb());
+    server.advance_time(Duration::from_secs(60));
+    let mut notch = login(&server, "Notch", notch());
+    notch.send_private_message("jeb_", "psst");
+    assert_eq!(jeb.expect("PrivateMessage")["content"], "psst");
+
+    let server = server_with(|config| config.moderation.join_cooldown_block_private = true);
+    let _jeb = login(&server, "jeb_", self::jeb());
+    server.advance_time(Duration::from_secs(60));
+    let mut notch = login(&server, "Notch", self::notch());
+    notch.send_private_message("jeb_", "psst");
+    expect_cooldown(&mut notch, 60);
+}
+
+#[test]
+fn moderators_and_bots_are_exempt() {
+    let bot = jeb();
+    let server = server_with(move |config| {
+        config.bots = BotConfig {
+            uuids: vec![bot],
+            ..BotConfig::default()
+        }
+    });
+    let mut moderator = login(&server, "Moderator", moderator());
+    moderator.send_message("hello");
+    assert_eq!(moderator.expect("Message")["content"], "hello");
+
+    let mut bot = login(&server, "Bot", bot);
+    bot.send_message("beep");
+    assert_eq!(bot.expect("Message")["content"], "beep");
+}
+
+#[test]
+fn users_returning_within_the_grace_period_are_exempt() {
+    let server = server();
+    let mut jeb = login(&server, "jeb_", jeb());
+    let notch = login(&server, "Notch", notch());
+    leave(notch, &mut jeb);
+
+    server.advance_time(Duration::from_secs(5 * 60));
+    let mut notch = login(&server, "Notch", self::notch());
+    notch.send_message("back again");
+    assert_eq!(notch.expect("Message")["content"], "back again");
+    jeb.expect("Message");
+    leave(notch, &mut jeb);
+
+    // The grace period starts when the last session closed, not at the first login.
+    server.advance_time(Duration::from_secs(5 * 60 + 1));
+    let mut notch = login(&server, "Notch", self::notch());
+    notch.send_message("back again, later");
+    expect_cooldown(&mut notch, 60);
+}
+
+#[test]
+fn additional_sessions_are_exempt() {
+    let server = server();
+    let mut first = login(&server, "Notch", notch());
+    first.send_message("first!");
+    expect_cooldown(&mut first, 60);
+
+    // Only the session which was not already online waits.
+    let mut second = login(&server, "Notch", notch());
+    second.send_message("from the second session");
+    assert_eq!(
+        second.expect("Message")["content"],
+        "from the second session"
+    );
+    first.expect("Message");
+    first.send_message("first again");
+    expect_cooldown(&mut first, 60);
+}
+
+#[test]
+fn resumed_sessions_are_exempt() {
+    let server = server_with(|config| {
+        config.resume.enabled = true;
+        config.moderation.join_cooldown_grace_secs = 0;
+    });
+    let mut jeb = login(&server, "jeb_", jeb());
+    let mut notch = server.client();
+    notch.hello(&["resume"]);
+    notch.login_as("Notch", self::notch());
+    let token = notch.expect("ResumeToken")["token"].clone();
+    leave(notch, &mut jeb);
+
+    // Outside of the grace period, only resuming is exempt.
+    server.advance_time(Duration::from_secs(10));
+    let mut notch = server.client();
+    notch.hello(&["resume"]);
+    notch.send("Resume", json!({ "token": token }));
+    assert_eq!(notch.expect("Success")["reason"], "Resume");
+    notch.expect("ResumeToken");
+    notch.expect("ReplayComplete");
+    notch.send_message("resumed");
+    assert_eq!(notch.expect("Message")["content"], "resumed");
+}
@@ -0,0 +1,216 @@
+//! End-to-end tests of the maintenance scheduler, fast-forwarded with a manual clock.
+#![cfg(feature = "testutil")]
+
+use axochat::chat::{ChatServerBuilder, JobRun, MaintenanceJob};
+use axochat::testutil::{TestServer, TestServerBuilder};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A job which succeeds immediately.
+struct Quick(&'static str);
+
+impl MaintenanceJob for Quick {
+    fn name(&self) -> &'static str {
+        self.0
+    }
+
+    fn run(&mut self) -> JobRun {
+        JobRun::Done(Ok(()))
+    }
+}
+
+/// A job which panics every time it runs.
+struct Panicking;
+
+impl MaintenanceJob for Panicking {
+    fn name(&self) -> &'static str {
+        "panicking"
+    }
+
+    fn run(&mut self) -> JobRun {
+        panic!("the job is broken");
+    }
+}
+
+/// A job whose work on the thread pool takes longer than its budget,
+/// counting how often its work started and finished.
+struct Slow {
+    started: Arc<AtomicUsize>,
+    finished: Arc<AtomicUsize>,
+}
+
+impl MaintenanceJob for Slow {
+    fn name(&self) -> &'static str {
+        "slow"
+    }
+
+    fn run(&mut self) -> JobRun {
+        self.started.fetch_add(1, Ordering::SeqCst);
+        let finished = self.finished.clone();
+        JobRun::Blocking(Box::new(move || {
+            thread::sleep(Duration::from_secs(4));
+            finished.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }))
+    }
+}
+
+/// Starts a server running the maintenance jobs every minute, with the jobs registered by `setup`.
+fn server<F>(job_budget: Duration, setup: F) -> TestServer
+where
+    F: FnOnce(ChatServerBuilder) -> ChatServerBuilder + Send + 'static,
+{
+    TestServerBuilder::new()
+        .config(move |config| {
+            config.server.metrics = true;
+            config.maintenance.interval = Duration::from_secs(60).into();
+            config.maintenance.job_budget = job_budget.into();
+        })
+        .setup(setup)
+        .manual_clock()
+        .start()
+}
+
+/// Reads the metric with the labels of `series`, which is `0` before it was recorded.
+fn metric(server: &TestServer, series: &str) -> u64 {
+    let metrics = server.request("GET", "/metrics", None, None).text();
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .map_or(0, |value| value.parse().unwrap())
+}
+
+/// The number of finished runs of the job `name`.
+fn runs(server: &TestServer, name: &str) -> u64 {
+    metric(
+        server,
+        &format!(
+            "axochat_maintenance_job_duration_seconds_count{{job=\"{}\"}}",
+            name
+        ),
+    )
+}
+
+/// The number of runs of the job `name` which ended with `outcome`.
+fn failures(server: &TestServer, name: &str, outcome: &str) -> u64 {
+    metric(
+        server,
+        &format!(
+            "axochat_maintenance_job_failures_total{{job=\"{}\",outcome=\"{}\"}}",
+            name, outcome
+        ),
+    )
+}
+
+/// Waits until `done` holds, checking it a few times a second.
+fn wait_until(what: &str, mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done() {
+        assert!(
+            Instant::now() < deadline,
+            "timed out waiting until {}",
+            what
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Waits longer than the scheduler takes to notice a due run.
+fn wait_for_check() {
+    thread::sleep(Duration::from_millis(1500));
+}
+
+#[test]
+fn jobs_run_once_per_interval() {
+    let server = server(Duration::from_secs(5), |builder| {
+        builder
+            .maintenance_job(Quick("first"))
+            .maintenance_job(Quick("second"))
+    });
+    // The first run starts with the server.
+    wait_until("the first run", || {
+        runs(&server, "first") == 1 && runs(&server, "second") == 1
+    });
+
+    server.advance_time(Duration::from_secs(59));
+    wait_for_check();
+    assert_eq!(runs(&server, "first"), 1);
+    assert_eq!(runs(&server, "second"), 1);
+
+    server.advance_time(Duration::from_secs(1));
+    wait_until("the second run", || {
+        runs(&server, "first") == 2 && runs(&server, "second") == 2
+    });
+
+    server.advance_time(Duration::from_secs(60));
+    wait_until("the third run", || {
+        runs(&server, "first") == 3 && runs(&server, "second") == 3
+    });
+    wait_for_check();
+    assert_eq!(runs(&server, "first"), 3);
+}
+
+#[test]
+fn slow_jobs_do_not_hold_up_the_others() {
+    let started = Arc::new(AtomicUsize::new(0));
+    let finished = Arc::new(AtomicUsize::new(0));
+    let slow = Slow {
+        started: started.clone(),
+        finished: finished.clone(),
+    };
+    let server = server(Duration::from_secs(2), move |builder| {
+        builder
+            .maintenance_job(slow)
+            .maintenance_job(Quick("quick"))
+    });
+    wait_until("the first run", || runs(&server, "quick") == 1);
+    assert_eq!(runs(&server, "slow"), 0);
+
+    // The slow job is skipped while its work is still running.
+    server.advance_time(Duration::from_secs(60));
+    wait_until("the second run", || runs(&server, "quick") == 2);
+    assert_eq!(runs(&server, "slow"), 0);
+
+    // Timing out does not start a second copy of the work.
+    wait_until("the slow job timed out", || {
+        failures(&server, "slow", "timed_out") == 1
+    });
+    server.advance_time(Duration::from_secs(60));
+    wait_until("the third run", || runs(&server, "quick") == 3);
+    assert_eq!(started.load(Ordering::SeqCst), 1);
+
+    // The work finishing late is not recorded as another run.
+    wait_until("the slow work finished", || {
+        finished.load(Ordering::SeqCst) == 1
+    });
+    server.advance_time(Duration::from_secs(60));
+    wait_until("the fourth run", || runs(&server, "quick") == 4);
+    assert_eq!(started.load(Ordering::SeqCst), 2);
+    assert_eq!(runs(&server, "slow"), 1);
+}
+
+#[test]
+fn panicking_jobs_do_not_stop_the_others() {
+    let server = server(Duration::from_secs(5), |builder| {
+        builder
+            .maintenance_job(Quick("before"))
+            .maintenance_job(Panicking)
+            .maintenance_job(Quick("after"))
+    });
+    wait_until("the first run", || {
+        runs(&server, "before") == 1
+            && runs(&server, "after") == 1
+            && failures(&server, "panicking", "panicked") == 1
+    });
+
+    // Jobs which panicked run again the next time.
+    server.advance_time(Duration::from_secs(60));
+    wait_until("the second run", || {
+        runs(&server, "before") == 2
+            && runs(&server, "after") == 2
+            && failures(&server, "panicking", "panicked") == 2
+    });
+    assert_eq!(runs(&server, "panicking"), 2);
+}
@@ -0,0 +1,366 @@
+//! Tests of the bans and mutes kept by the moderation state and the file of the banned users.
+#![cfg(feature = "testutil")]
+
+use axochat::config::ModConfig;
+use axochat::moderation::{ImportMode, Moderation, ModerationState, Restriction};
+use axochat::testutil::{jeb, moderator, notch};
+use rand::{rngs::OsRng, RngCore};
+use serde_json::json;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The files of a moderation state in a new temporary directory, which is removed when dropped.
+struct Files {
+    dir: PathBuf,
+    config: ModConfig,
+}
+
+impl Files {
+    fn new() -> Files {
+        let dir =
+            std::env::temp_dir().join(format!("axochat-moderation-{:016x}", OsRng.next_u64()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = ModConfig {
+            moderators: dir.join("moderators.txt"),
+            banned: dir.join("banned.txt"),
+            whitelisted: dir.join("whitelisted.txt"),
+            ..ModConfig::default()
+        };
+        fs::write(&config.moderators, moderator().to_hyphenated().to_string()).unwrap();
+        Files { dir, config }
+    }
+
+    fn open(&self) -> Moderation {
+        Moderation::new(self.config.clone()).unwrap()
+    }
+
+    fn banned(&self) -> String {
+        fs::read_to_string(&self.config.banned).unwrap()
+    }
+
+    fn whitelisted(&self) -> String {
+        fs::read_to_string(&self.config.whitelisted).unwrap()
+    }
+}
+
+impl Drop for Files {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn ban(reason: Option<&str>, expires_at: Option<u64>) -> Restriction {
+    Restriction {
+        reason: reason.map(str::to_string),
+        expires_at,
+    }
+}
+
+#[test]
+fn plain_bans_are_written_as_uuids() {
+    let files = Files::new();
+    let mut moderation = files.open();
+    moderation.ban(&notch(), Restriction::default()).unwrap();
+
+    assert_eq!(files.banned(), format!("{}\n", notch().to_hyphenated()));
+    assert!(files.open().is_banned(&notch(), at(0)));
+}
+
+#[test]
+fn reasons_and_ends_survive_a_restart() {
+    let files = Files::new();
+    let mut moderation = files.open();
+    moderation
+        .ban(&notch(), ban(Some("spam\tand\nmore"), Some(1000)))
+        .unwrap();
+    moderation.ban(&jeb(), ban(Some("griefing"), None)).unwrap();
+
+    let moderation = files.open();
+    assert_eq!(
+        moderation.ban_of(&notch(), at(0)),
+        Some(&ban(Some("spam and more"), Some(1000)))
+    );
+    assert_eq!(
+        moderation.ban_of(&jeb(), at(0)),
+        Some(&ban(Some("griefing"), None))
+    );
+}
+
+#[test]
+fn a_new_ban_replaces_the_old_one() {
+    let files = Files::new();
+    let mut moderation = files.open();
+    moderation
+        .ban(&notch(), ban(Some("spam"), Some(1000)))
+        .unwrap();
+    moderation.ban(&notch(), ban(None, None)).unwrap();
+
+    // Bans are appended, so the later line has to win when the file is read.
+    assert_eq!(files.banned().lines().count(), 2);
+    assert_eq!(
+        files.open().ban_of(&notch(), at(2000)),
+        Some(&ban(None, None))
+    );
+}
+
+#[test]
+fn temporary_bans_end() {
+    let files = Files::new();
+    let mut moderation = files.open();
+    moderation.ban(&notch(), ban(None, Some(1000))).unwrap();
+
+    assert!(moderation.is_banned(&notch(), at(999)));
+    assert!(!moderation.is_banned(&notch(), at(1000)));
+    assert_eq!(
+        Restriction::new(None, Some(Duration::from_secs(60)), at(1000)).expires_at,
+        Some(1060)
+    );
+    assert_eq!(
+        ban(None, Some(1060)).remaining(at(1000)),
+        Some(Duration::from_secs(60))
+    );
+    assert_eq!(ban(None, None).remaining(at(1000)), None);
+}
+
+#[test]
+fn ended_restrictions_are_pruned() {
+    let files = Files::new();
+    let mut moderation = files.open();
+    moderation.ban(&notch(), ban(None, Some(1000))).unwrap();
+    moderation.ban(&jeb(), ban(None, None)).unwrap();
+    moderation.mute(&jeb(), None, 500).unwrap();
+
+    assert!(moderation.prune_expired(at(400)).is_empty());
+    let mut lapsed = moderation.prune_expired(at(1000));
+    let mut expected = vec![notch(), jeb()];
+    lapsed.sort();
+    expected.sort();
+    assert_eq!(lapsed, expected);
+    assert!(moderation.mute_of(&jeb(), at(0)).is_none());
+    assert_eq!(files.banned(), format!("{}\n", jeb().to_hyphenated()));
+}
+
+#[test]
+fn mutes_end_and_can_be_lifted() {
+    let files = Files::new();
+    let mut moderation = files.open();
+    moderation
+        .mute(&notch(), Some("caps".to_string()), 1000)
+        .unwrap();
+
+    assert_eq!(
+        moderation.mute_of(&notch(), at(999)),
+        Some(&ban(Some("caps"), Some(1000)))
+    );
+    assert!(moderation.mute_of(&notch(), at(1000)).is_none());
+    moderation.unmute(&notch()).unwrap();
+    let err = moderation.unmute(&notch()).unwrap_err();
+    assert_eq!(err.to_string(), "axochat: not muted");
+    // Mutes are not saved.
+    assert_eq!(files.banned(), "");
+}
+
+#[test]
+fn moderators_can_not_be_banned_or_muted() {
+    let files = Files::new();
+    let mut moderation = files.open();
+
+    assert!(moderation
+        .ban(&moderator(), Restriction::default())
+        .is_err());
+    assert!(moderation.mute(&moderator(), None, 1000).is_err());
+    assert!(!moderation.is_banned(&moderator(), at(0)));
+    assert!(moderation.mute_of(&moderator(), at(0)).is_none());
+}
+
+#[test]
+fn details_are_exported_and_imported() {
+    let files = Files::new();
+    let mut moderation = files.open();
+    moderation
+        .ban(&notch(), ban(Some("spam"), Some(1000)))
+        .unwrap();
+    moderation.ban(&jeb(), Restriction::default()).unwrap();
+    moderation
+        .mute(&jeb(), Some("caps".to_string()), 2000)
+        .unwrap();
+
+    let state = moderation.export(at(0));
+    let exported = serde_json::to_value(&state).unwrap();
+    let mut banned = vec![
+        notch().to_hyphenated().to_string(),
+        jeb().to_hyphenated().to_string(),
+    ];
+    banned.sort();
+    assert_eq!(
+        exported,
+        json!({
+            "banned": banned,
+            "whitelisted": [],
+            "ban_details": {
+                notch().to_hyphenated().to_string(): { "reason": "spam", "expires_at": 1000 },
+            },
+            "mutes": {
+                jeb().to_hyphenated().to_string(): { "reason": "caps", "expires_at": 2000 },
+            },
+        })
+    );
+
+    let other = Files::new();
+    let mut imported = other.open();
+    let state: ModerationState = serde_json::from_value(exported).unwrap();
+    imported.import(state, ImportMode::Replace, at(0)).unwrap();
+    assert_eq!(
+        imported.ban_of(&notch(), at(0)),
+        Some(&ban(Some("spam"), Some(1000)))
+    );
+    assert_eq!(
+        imported.mute_of(&jeb(), at(0)),
+        Some(&ban(Some("caps"), Some(2000)))
+    );
+    assert_eq!(
+        other.open().ban_of(&jeb(), at(0)),
+        Some(&Restriction::default())
+    );
+}
+
+#[test]
+fn ended_restrictions_are_not_exported() {
+    let files = Files::new();
+    let mut moderation = files.open();
+    moderation.ban(&notch(), ban(None, Some(1000))).unwrap();
+    moderation.mute(&jeb(), None, 1000).unwrap();
+
+    let state = moderation.export(at(1000));
+    assert!(state.banned.is_empty());
+    assert!(state.ban_details.is_empty());
+    assert!(state.mutes.is_empty());
+}
+
+#[test]
+fn replacing_lifts_the_current_mutes_and_merging_keeps_them() {
+    let files = Files::new();
+    let mut moderation = files.open();
+    moderation.mute(&notch(), None, 1000).unwrap();
+    let mut state = ModerationState::default();
+    state.mutes.insert(jeb(), ban(None, Some(1000)));
+
+    moderation
+        .import(state.clone(), ImportMode::Merge, at(0))
+        .unwrap();
+    assert!(moderation.mute_of(&notch(), at(0)).is_some());
+    assert!(moderation.mute_of(&jeb(), at(0)).is_some());
+
+    state.mutes.clear();
+    moderation
+        .import(state, ImportMode::Replace, at(0))
+        .unwrap();
+    assert!(moderation.mute_of(&notch(), at(0)).is_none());
+    assert!(moderation.mute_of(&jeb(), at(0)).is_none());
+}
+
+#[test]
+fn invalid_imports_change_nothing() {
+    let files = Files::new();
+    let mut moderation = files.open();
+    moderation
+        .ban(&notch(), ban(Some("spam"), Some(1000)))
+        .unwrap();
+    moderation.mute(&jeb(), None, 1000).unwrap();
+    moderation
+        .import(
+            ModerationState {
+                whitelisted: vec![jeb()],
+                ..ModerationState::default()
+            },
+            ImportMode::Merge,
+            at(0),
+        )
+        .unwrap();
+    let (banned, whitelisted) = (files.banned(), files.whitelisted());
+
+    let bans_moderator = ModerationState {
+        banned: vec![moderator()],
+        ..ModerationState::default()
+    };
+    let mutes_moderator = ModerationState {
+        mutes: vec![(moderator(), ban(None, Some(1000)))]
+            .into_iter()
+            .collect(),
+        ..ModerationState::default()
+    };
+    let details_without_ban = ModerationState {
+        banned: vec![jeb()],
+        ban_details: vec![(notch(), ban(Some("other"), None))]
+            .into_iter()
+            .collect(),
+        ..ModerationState::default()
+    };
+    let ended_ban = ModerationState {
+        banned: vec![jeb()],
+        ban_details: vec![(jeb(), ban(None, Some(500)))].into_iter().collect(),
+        ..ModerationState::default()
+    };
+    let endless_mute = ModerationState {
+        mutes: vec![(notch(), ban(None, None))].into_iter().collect(),
+        ..ModerationState::default()
+    };
+    let ended_mute = ModerationState {
+        mutes: vec![(notch(), ban(None, Some(500)))].into_iter().collect(),
+        ..ModerationState::default()
+    };
+    let invalid = vec![
+        (bans_moderator, "axochat: not permitted"),
+        (mutes_moderator, "axochat: not permitted"),
+        (details_without_ban, "axochat: invalid moderation state"),
+        (ended_ban, "axochat: invalid moderation state"),
+        (endless_mute, "axochat: invalid moderation state"),
+        (ended_mute, "axochat: invalid moderation state"),
+    ];
+    for (state, expected) in invalid {
+        for mode in [ImportMode::Merge, ImportMode::Replace] {
+            let err = moderation.import(state.clone(), mode, at(500)).unwrap_err();
+            assert_eq!(err.to_string(), expected, "{:?}", state);
+
+            assert_eq!(files.banned(), banned);
+            assert_eq!(files.whitelisted(), whitelisted);
+            assert_eq!(
+                moderation.ban_of(&notch(), at(500)),
+                Some(&ban(Some("spam"), Some(1000)))
+            );
+            assert!(moderation.ban_of(&jeb(), at(500)).is_none());
+            assert!(moderation.mute_of(&jeb(), at(500)).is_some());
+            assert!(moderation.mute_of(&notch(), at(500)).is_none());
+            assert!(moderation.is_whitelisted(&jeb()));
+        }
+    }
+}
+
+#[test]
+fn bans_are_restored_if_the_whitelist_can_not_be_replaced() {
+    let files = Files::new();
+    let mut moderation = files.open();
+    moderation.ban(&notch(), ban(Some("spam"), None)).unwrap();
+    let banned = files.banned();
+    // A directory can not be replaced by the new file of the whitelisted users.
+    fs::remove_file(&files.config.whitelisted).unwrap();
+    fs::create_dir_all(files.config.whitelisted.join("blocker")).unwrap();
+
+    let state = ModerationState {
+        banned: vec![jeb()],
+        whitelisted: vec![notch()],
+        ..ModerationState::default()
+    };
+    assert!(moderation
+        .import(state, ImportMode::Replace, at(0))
+        .is_err());
+    assert_eq!(files.banned(), banned);
+    assert!(!moderation.is_dirty());
+    assert!(moderation.is_banned(&notch(), at(0)));
+    assert!(!moderation.is_banned(&jeb(), at(0)));
+}
@@ -0,0 +1,117 @@
+//! End-to-end tests of the metadata of private messages kept for moderators.
+#![cfg(feature = "testutil")]
+
+use axochat::testutil::{jeb, moderator, notch, TestClient, TestServer, TestServerBuilder};
+use serde_json::json;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Starts a server keeping the metadata of private messages for a minute,
+/// which never prunes it on its own.
+fn server() -> TestServer {
+    TestServerBuilder::with_moderator()
+        .config(|config| {
+            config.moderation.pm_metadata_retention_minutes = 1;
+            config.maintenance.interval = Duration::from_secs(24 * 60 * 60).into();
+        })
+        .manual_clock()
+        .start()
+}
+
+/// Requests the metadata of the private messages of `uuid` and returns the whole packet.
+fn pm_metadata(moderator: &mut TestClient, uuid: Uuid) -> serde_json::Value {
+    moderator.send("RequestPmMetadata", json!({ "uuid": uuid }));
+    let packet = moderator.next_packet();
+    assert_eq!(packet.name, "PmMetadata", "unexpected packet: {:?}", packet);
+    packet.content
+}
+
+#[test]
+fn entries_hold_no_content() {
+    let server = server();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+    let mut jeb = server.login("jeb_", jeb());
+
+    notch.send_private_message("jeb_", "a secret");
+    assert_eq!(jeb.expect("PrivateMessage")["content"], "a secret");
+
+    let packet = pm_metadata(&mut moderator, self::jeb());
+    assert!(!packet.to_string().contains("secret"), "{}", packet);
+    let entries = packet["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 1);
+    let mut fields: Vec<_> = entries[0].as_object().unwrap().keys().collect();
+    fields.sort();
+    assert_eq!(fields, ["length", "receiver", "sender", "timestamp"]);
+    assert_eq!(
+        entries[0]["sender"],
+        self::notch().to_hyphenated().to_string()
+    );
+    assert_eq!(
+        entries[0]["receiver"],
+        self::jeb().to_hyphenated().to_string()
+    );
+    assert_eq!(entries[0]["length"], "a secret".len());
+
+    // Both directions are shown.
+    let packet = pm_metadata(&mut moderator, self::notch());
+    assert_eq!(packet["entries"], json!(entries));
+}
+
+#[test]
+fn expired_entries_are_left_out_before_they_are_pruned() {
+    let server = server();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+    let mut jeb = server.login("jeb_", jeb());
+
+    notch.send_private_message("jeb_", "psst");
+    jeb.expect("PrivateMessage");
+
+    server.advance_time(Duration::from_secs(59));
+    let packet = pm_metadata(&mut moderator, self::notch());
+    assert_eq!(packet["entries"].as_array().unwrap().len(), 1);
+
+    server.advance_time(Duration::from_secs(1));
+    let packet = pm_metadata(&mut moderator, self::notch());
+    assert_eq!(packet["entries"], json!([]));
+}
+
+#[test]
+fn only_moderators_can_look_up_private_messages() {
+    let server = server();
+    let mut notch = server.login("Notch", notch());
+
+    notch.send("RequestPmMetadata", json!({ "uuid": jeb() }));
+    notch.expect_error(json!("NotPermitted"));
+}
+
+#[test]
+fn private_messages_are_not_recorded_by_default() {
+    let server = TestServerBuilder::with_moderator().start();
+    let mut moderator = server.login("Moderator", moderator());
+
+    moderator.send("RequestPmMetadata", json!({ "uuid": jeb() }));
+    moderator.expect_error(json!("NotSupported"));
+}
+
+#[test]
+fn diagnostics_disclose_whether_private_messages_are_recorded() {
+    let server = server();
+    let mut notch = server.login("Notch", notch());
+    notch.send("RequestDiagnostics", json!(null));
+    assert_eq!(
+        notch.expect("Diagnostics")["pm_metadata_retention_minutes"],
+        1
+    );
+
+    let server = TestServer::start();
+    let mut notch = server.login("Notch", self::notch());
+    notch.send("RequestDiagnostics", json!(null));
+    let diagnostics = notch.expect("Diagnostics");
+    assert!(
+        diagnostics.get("pm_metadata_retention_minutes").is_none(),
+        "{}",
+        diagnostics
+    );
+}
@@ -0,0 +1,98 @@
+//! End-to-end tests of replaying missed broadcast messages with `ResyncFrom`.
+#![cfg(feature = "testutil")]
+
+use axochat::testutil::{jeb, notch, TestClient, TestServer, TestServerBuilder};
+use serde_json::json;
+use uuid::Uuid;
+
+/// Starts a server keeping the last `history_size` messages and replaying two at once.
+fn server(history_size: usize) -> TestServer {
+    TestServerBuilder::new()
+        .config(move |config| {
+            config.message.history_size = history_size;
+            config.message.replay_chunk_size = 2;
+            config.message.max_messages = 100;
+        })
+        .start()
+}
+
+/// Connects a client speaking the current protocol, which stamps messages with their `seq`.
+fn login<'a>(server: &'a TestServer, name: &str, uuid: Uuid) -> TestClient<'a> {
+    let mut client = server.client();
+    client.hello(&[]);
+    client.login_as(name, uuid);
+    client
+}
+
+/// Sends `count` messages as `client` and returns their sequence numbers.
+fn send_messages(client: &mut TestClient, count: usize) -> Vec<u64> {
+    (0..count)
+        .map(|i| {
+            client.send_message(&format!("message {}", i));
+            let message = client.expect("Message");
+            assert_eq!(message["content"], format!("message {}", i));
+            message["seq"].as_u64().unwrap()
+        })
+        .collect()
+}
+
+/// Requests the messages newer than `seq` and returns the replayed sequence numbers.
+fn resync(client: &mut TestClient, seq: u64) -> Vec<u64> {
+    client.send("ResyncFrom", json!({ "seq": seq }));
+    let mut replayed = Vec::new();
+    loop {
+        let packet = client.next_packet();
+        match packet.name.as_str() {
+            "Message" => replayed.push(packet.content["seq"].as_u64().unwrap()),
+            "ReplayComplete" => {
+                assert_eq!(packet.content["count"], replayed.len());
+                return replayed;
+            }
+            _ => panic!("unexpected packet during replay: {:?}", packet),
+        }
+    }
+}
+
+#[test]
+fn missed_messages_are_replayed_in_order() {
+    let server = server(10);
+    let mut notch = login(&server, "Notch", notch());
+    let seqs = send_messages(&mut notch, 5);
+    assert!(seqs.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", seqs);
+
+    let mut jeb = login(&server, "jeb_", jeb());
+    jeb.send("ResyncFrom", json!({ "seq": seqs[0] - 1 }));
+    for (i, seq) in seqs.iter().enumerate() {
+        let message = jeb.expect("Message");
+        assert_eq!(message["seq"], *seq);
+        assert_eq!(message["content"], format!("message {}", i));
+        assert_eq!(message["author_info"]["name"], "Notch");
+    }
+    assert_eq!(jeb.expect("ReplayComplete")["count"], 5);
+}
+
+#[test]
+fn only_messages_newer_than_the_sequence_number_are_replayed() {
+    let server = server(10);
+    let mut notch = login(&server, "Notch", notch());
+    let seqs = send_messages(&mut notch, 5);
+
+    let mut jeb = login(&server, "jeb_", jeb());
+    assert_eq!(resync(&mut jeb, seqs[2]), &seqs[3..]);
+    // A client which is up to date receives nothing.
+    assert_eq!(resync(&mut jeb, seqs[4]), Vec::<u64>::new());
+}
+
+#[test]
+fn resyncing_from_messages_which_rolled_off_is_too_old() {
+    let server = server(3);
+    let mut notch = login(&server, "Notch", notch());
+    let seqs = send_messages(&mut notch, 5);
+
+    let mut jeb = login(&server, "jeb_", jeb());
+    jeb.send("ResyncFrom", json!({ "seq": seqs[0] }));
+    assert_eq!(jeb.expect("ResyncTooOld")["oldest_available"], seqs[2]);
+
+    // Everything since the oldest message which is still kept can be replayed.
+    assert_eq!(resync(&mut jeb, seqs[1]), &seqs[2..]);
+}
@@ -0,0 +1,213 @@
+//! End-to-end tests of reviewing messages with an external reviewer.
+#![cfg(feature = "testutil")]
+
+use axochat::config::ReviewVerdict;
+use axochat::testutil::{jeb, moderator, notch, TestServer, TestServerBuilder};
+use serde_json::json;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// A reviewer answering every review with `status` and `body` after `delay`,
+/// recording the reviews it received.
+struct Reviewer {
+    addr: SocketAddr,
+    reviews: Arc<Mutex<Vec<serde_json::Value>>>,
+}
+
+impl Reviewer {
+    fn start(status: u16, body: &'static str, delay: Duration) -> Reviewer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let reviews = Arc::new(Mutex::new(Vec::new()));
+        let recorded = reviews.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let recorded = recorded.clone();
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut length = 0;
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        let line = line.trim_end();
+                        if line.is_empty() {
+                            break;
+                        }
+                        let mut header = line.splitn(2, ':');
+                        let name = header.next().unwrap();
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = header.next().unwrap().trim().parse().unwrap();
+                        }
+                    }
+                    let mut request = vec![0; length];
+                    reader.read_exact(&mut request).unwrap();
+                    recorded
+                        .lock()
+                        .unwrap()
+                        .push(serde_json::from_slice(&request).unwrap());
+
+                    thread::sleep(delay);
+                    let response = format!(
+                        "HTTP/1.1 {} Reviewed\r\ncontent-type: application/json\r\n\
+                         content-length: {}\r\nconnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    // The server stops waiting for timed out reviews.
+                    stream.write_all(response.as_bytes()).ok();
+                });
+            }
+        });
+        Reviewer { addr, reviews }
+    }
+
+    /// A reviewer answering with `verdict` right away.
+    fn answering(verdict: &str) -> Reviewer {
+        let body = match verdict {
+            "allow" => r#"{"verdict":"allow"}"#,
+            "deny" => r#"{"verdict":"deny"}"#,
+            "flag" => r#"{"verdict":"flag"}"#,
+            _ => panic!("unknown verdict `{}`", verdict),
+        };
+        Reviewer::start(200, body, Duration::from_millis(0))
+    }
+
+    /// Starts a server reviewing messages with this reviewer,
+    /// waiting at most `timeout` for the verdict and applying `fallback` otherwise.
+    fn server(&self, timeout: Duration, fallback: ReviewVerdict) -> TestServer {
+        let review_url = format!("http://{}/review", self.addr);
+        TestServerBuilder::with_moderator()
+            .commands()
+            .config(move |config| {
+                config.moderation.review_url = Some(review_url);
+                config.moderation.review_timeout = timeout.into();
+                config.moderation.review_fallback = fallback;
+            })
+            .start()
+    }
+
+    fn reviews(&self) -> Vec<serde_json::Value> {
+        self.reviews.lock().unwrap().clone()
+    }
+}
+
+/// Long enough for any review which is not held up on purpose.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+#[test]
+fn allowed_messages_are_broadcast() {
+    let reviewer = Reviewer::answering("allow");
+    let server = reviewer.server(TIMEOUT, ReviewVerdict::Deny);
+    let mut notch = server.login("Notch", notch());
+    let mut jeb = server.login("jeb_", jeb());
+
+    notch.send_message("hello");
+    assert_eq!(jeb.expect("Message")["content"], "hello");
+    assert_eq!(notch.expect("Message")["content"], "hello");
+
+    let reviews = reviewer.reviews();
+    assert_eq!(reviews.len(), 1);
+    assert_eq!(reviews[0]["content"], "hello");
+    assert_eq!(
+        reviews[0]["author"]["uuid"],
+        self::notch().to_hyphenated().to_string()
+    );
+}
+
+#[test]
+fn denied_messages_are_rejected() {
+    let reviewer = Reviewer::answering("deny");
+    let server = reviewer.server(TIMEOUT, ReviewVerdict::Allow);
+    let mut notch = server.login("Notch", notch());
+    let mut jeb = server.login("jeb_", jeb());
+
+    notch.send_message("hello");
+    notch.expect_error(json!("BlockedContent"));
+    jeb.expect_none(Duration::from_millis(200));
+}
+
+#[test]
+fn flagged_messages_are_broadcast_and_shown_to_moderators() {
+    let reviewer = Reviewer::answering("flag");
+    let server = reviewer.server(TIMEOUT, ReviewVerdict::Allow);
+    let mut moderator = server.client();
+    moderator.hello(&["flagged_messages"]);
+    moderator.login_as("Moderator", self::moderator());
+    let mut notch = server.login("Notch", notch());
+    let mut jeb = server.login("jeb_", jeb());
+
+    notch.send_message("hello");
+    let flagged = moderator.expect("MessageFlagged");
+    assert_eq!(flagged["content"], "hello");
+    assert_eq!(flagged["author_info"]["name"], "Notch");
+    assert_eq!(moderator.expect("Message")["content"], "hello");
+    assert_eq!(jeb.expect("Message")["content"], "hello");
+    jeb.expect_none(Duration::from_millis(200));
+}
+
+#[test]
+fn timed_out_reviews_apply_the_fallback() {
+    let reviewer = Reviewer::start(200, r#"{"verdict":"allow"}"#, Duration::from_secs(1));
+    let server = reviewer.server(Duration::from_millis(200), ReviewVerdict::Deny);
+    let mut notch = server.login("Notch", notch());
+    let mut jeb = server.login("jeb_", jeb());
+
+    notch.send_message("hello");
+    notch.expect_error(json!("BlockedContent"));
+    // The late verdict is ignored.
+    jeb.expect_none(Duration::from_millis(1500));
+}
+
+#[test]
+fn failed_reviews_apply_the_fallback() {
+    let reviewer = Reviewer::start(500, "{}", Duration::from_millis(0));
+    let server = reviewer.server(TIMEOUT, ReviewVerdict::Allow);
+    let mut notch = server.login("Notch", notch());
+    let mut jeb = server.login("jeb_", jeb());
+
+    notch.send_message("hello");
+    assert_eq!(jeb.expect("Message")["content"], "hello");
+    assert_eq!(reviewer.reviews().len(), 1);
+}
+
+#[test]
+fn messages_of_users_muted_during_the_review_are_dropped() {
+    let reviewer = Reviewer::start(200, r#"{"verdict":"allow"}"#, Duration::from_millis(500));
+    let server = reviewer.server(TIMEOUT, ReviewVerdict::Allow);
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+
+    notch.send_message("hello");
+    moderator.send_message("/mute Notch 10m");
+    assert_eq!(moderator.expect("CommandResult")["success"], true);
+    assert_eq!(notch.expect("ModerationStatus")["muted"], true);
+
+    notch.expect_error(json!("Muted"));
+    moderator.expect_none(Duration::from_millis(200));
+}
+
+#[test]
+fn messages_of_users_banned_during_the_review_are_dropped() {
+    let reviewer = Reviewer::start(200, r#"{"verdict":"flag"}"#, Duration::from_millis(500));
+    let server = reviewer.server(TIMEOUT, ReviewVerdict::Allow);
+    let mut moderator = server.client();
+    moderator.hello(&["flagged_messages"]);
+    moderator.login_as("Moderator", self::moderator());
+    let mut notch = server.login("Notch", notch());
+
+    notch.send_message("hello");
+    moderator.send_message("/ban Notch 1h");
+    assert_eq!(moderator.expect("CommandResult")["success"], true);
+    assert_eq!(notch.expect("Disconnected")["reason_code"], "banned");
+
+    // Neither flagged nor broadcast.
+    moderator.expect_none(Duration::from_secs(1));
+}
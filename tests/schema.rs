@@ -0,0 +1,234 @@
+//! Tests of the protocol schema against the snapshot committed in `tests/snapshots/schema.json`.
+//!
+//! Changes which break existing clients fail unless the version of the protocol was bumped.
+//! After reviewing a change, the snapshot is updated by running the tests with `UPDATE_SNAPSHOTS=1`.
+
+use axochat::chat::protocol_schema;
+use serde_json::{json, Map, Value};
+use std::path::PathBuf;
+
+fn snapshot_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots/schema.json")
+}
+
+/// The schema without the version of the server, which changes with every release.
+fn current_schema() -> Value {
+    let mut schema = serde_json::to_value(protocol_schema()).unwrap();
+    schema.as_object_mut().unwrap().remove("version");
+    schema
+}
+
+/// The entries of an array of named objects by their name.
+fn by_name(entries: &Value) -> Map<String, Value> {
+    entries
+        .as_array()
+        .map(|entries| {
+            entries
+                .iter()
+                .map(|entry| (entry["name"].as_str().unwrap().to_string(), entry.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn strings(value: &Value) -> Vec<&str> {
+    value
+        .as_array()
+        .map(|values| values.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default()
+}
+
+/// Compares the fields of an object in `old` and `new`.
+///
+/// Fields may only be added; new fields have to be optional if `added_must_be_optional`,
+/// because old clients do not send them.
+fn compare_fields(
+    context: &str,
+    old: &Value,
+    new: &Value,
+    added_must_be_optional: bool,
+    breaking: &mut Vec<String>,
+) {
+    let old = by_name(old);
+    let new = by_name(new);
+    for (name, old) in &old {
+        match new.get(name) {
+            None => breaking.push(format!("{}: removed the field `{}`", context, name)),
+            Some(new) if new["type"] != old["type"] => breaking.push(format!(
+                "{}: changed the type of `{}` from {} to {}",
+                context, name, old["type"], new["type"]
+            )),
+            Some(new) if new["optional"] != old["optional"] => breaking.push(format!(
+                "{}: changed whether `{}` is optional",
+                context, name
+            )),
+            Some(_) => {}
+        }
+    }
+    for (name, new) in &new {
+        if added_must_be_optional && !old.contains_key(name) && new["optional"] == false {
+            breaking.push(format!("{}: added the required field `{}`", context, name));
+        }
+    }
+}
+
+/// Lists the changes from `old` to `new` which break clients written against `old`.
+fn breaking_changes(old: &Value, new: &Value) -> Vec<String> {
+    let mut breaking = Vec::new();
+    for direction in &["serverbound", "clientbound"] {
+        let new_packets = by_name(&new[direction]);
+        for (name, old) in by_name(&old[direction]) {
+            let context = format!("{} packet `{}`", direction, name);
+            let new = match new_packets.get(&name) {
+                Some(new) => new,
+                None => {
+                    breaking.push(format!("{}: removed", context));
+                    continue;
+                }
+            };
+            if new.get("content") != old.get("content") {
+                breaking.push(format!("{}: changed its content", context));
+            }
+            if old.get("fields").is_some() != new.get("fields").is_some() {
+                breaking.push(format!("{}: changed whether it has content", context));
+            } else if let (Some(old), Some(new)) = (old.get("fields"), new.get("fields")) {
+                let serverbound = *direction == "serverbound";
+                compare_fields(&context, old, new, serverbound, &mut breaking);
+            }
+        }
+    }
+
+    // Types are used in both directions.
+    let new_types = by_name(&new["types"]);
+    for (name, old) in by_name(&old["types"]) {
+        let context = format!("type `{}`", name);
+        match new_types.get(&name) {
+            Some(new) => compare_fields(
+                &context,
+                &old["fields"],
+                &new["fields"],
+                true,
+                &mut breaking,
+            ),
+            None => breaking.push(format!("{}: removed", context)),
+        }
+    }
+
+    let new_enums = by_name(&new["enums"]);
+    for (name, old) in by_name(&old["enums"]) {
+        let new_values = new_enums
+            .get(&name)
+            .map(|new| strings(&new["values"]))
+            .unwrap_or_default();
+        for value in strings(&old["values"]) {
+            if !new_values.contains(&value) {
+                breaking.push(format!("enum `{}`: removed `{}`", name, value));
+            }
+        }
+    }
+
+    let new_errors = strings(&new["errors"]);
+    for error in strings(&old["errors"]) {
+        if !new_errors.contains(&error) {
+            breaking.push(format!("removed the error `{}`", error));
+        }
+    }
+    breaking
+}
+
+#[test]
+fn the_schema_matches_the_snapshot() {
+    let current = current_schema();
+    let path = snapshot_path();
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let snapshot: Value = match std::fs::read_to_string(&path) {
+        Ok(snapshot) => serde_json::from_str(&snapshot).unwrap(),
+        Err(_) if update => Value::Null,
+        Err(err) => panic!("could not read {}: {}", path.display(), err),
+    };
+
+    if !snapshot.is_null() && snapshot["protocol"] == current["protocol"] {
+        let breaking = breaking_changes(&snapshot, &current);
+        assert!(
+            breaking.is_empty(),
+            "the schema changed incompatibly without bumping the protocol version:\n{}",
+            breaking.join("\n")
+        );
+    }
+    if snapshot == current {
+        return;
+    }
+    if update {
+        let mut encoded = serde_json::to_string_pretty(&current).unwrap();
+        encoded.push('\n');
+        std::fs::write(&path, encoded).unwrap();
+    } else {
+        panic!(
+            "the schema differs from {}; review the change and run the tests with UPDATE_SNAPSHOTS=1",
+            path.display()
+        );
+    }
+}
+
+/// Applies `change` to the packet `name` of `direction` in a copy of the current schema.
+fn change_packet(direction: &str, name: &str, change: impl FnOnce(&mut Value)) -> Value {
+    let mut schema = current_schema();
+    let packet = schema[direction]
+        .as_array_mut()
+        .unwrap()
+        .iter_mut()
+        .find(|packet| packet["name"] == name)
+        .unwrap();
+    change(packet);
+    schema
+}
+
+#[test]
+fn detects_breaking_changes() {
+    let old = current_schema();
+    assert_eq!(breaking_changes(&old, &old), Vec::<String>::new());
+
+    let removed_field = change_packet("clientbound", "MessageAck", |packet| {
+        packet["fields"].as_array_mut().unwrap().remove(0);
+    });
+    assert_eq!(
+        breaking_changes(&old, &removed_field),
+        ["clientbound packet `MessageAck`: removed the field `seq`"]
+    );
+
+    let changed_type = change_packet("serverbound", "ResyncFrom", |packet| {
+        packet["fields"][0]["type"] = json!("string");
+    });
+    assert_eq!(breaking_changes(&old, &changed_type).len(), 1);
+
+    let required = change_packet("serverbound", "Message", |packet| {
+        packet["fields"][1]["optional"] = json!(false);
+    });
+    assert_eq!(
+        breaking_changes(&old, &required),
+        ["serverbound packet `Message`: changed whether `origin` is optional"]
+    );
+
+    let added = |optional| {
+        change_packet("serverbound", "Resume", |packet| {
+            let field = json!({ "name": "nonce", "type": "string", "optional": optional });
+            packet["fields"].as_array_mut().unwrap().push(field);
+        })
+    };
+    assert_eq!(
+        breaking_changes(&old, &added(false)),
+        ["serverbound packet `Resume`: added the required field `nonce`"]
+    );
+    assert!(breaking_changes(&old, &added(true)).is_empty());
+
+    let mut removed_error = old.clone();
+    removed_error["errors"].as_array_mut().unwrap().pop();
+    assert_eq!(breaking_changes(&old, &removed_error).len(), 1);
+
+    // Additions to clientbound packets do not break clients, which ignore unknown fields.
+    let new_field = change_packet("clientbound", "MessageAck", |packet| {
+        let field = json!({ "name": "latency", "type": "integer", "optional": false });
+        packet["fields"].as_array_mut().unwrap().push(field);
+    });
+    assert!(breaking_changes(&old, &new_field).is_empty());
+}
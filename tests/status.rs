@@ -0,0 +1,99 @@
+//! End-to-end tests of the status page at `/status`.
+#![cfg(feature = "testutil")]
+
+use axochat::testutil::{moderator, notch, TestServer, TestServerBuilder};
+
+const TOKEN: &str = "admin-token";
+
+/// Starts a server serving the status page to everyone.
+fn server() -> TestServer {
+    TestServerBuilder::with_moderator()
+        .commands()
+        .config(|config| {
+            config.server.status_page = true;
+            config.server.status_page_requires_token = false;
+        })
+        .start()
+}
+
+/// Reads the status page, asserting that it was served as HTML.
+fn status(server: &TestServer) -> String {
+    let response = server.request("GET", "/status", None, None);
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.header("Content-Type"),
+        Some("text/html; charset=utf-8")
+    );
+    response.text()
+}
+
+fn row(name: &str, value: &str) -> String {
+    format!("<tr><th>{}</th><td>{}</td></tr>", name, value)
+}
+
+#[test]
+fn the_page_of_a_fresh_server_shows_empty_counters() {
+    let server = server();
+    let page = status(&server);
+
+    assert!(page.contains("<meta http-equiv=\"refresh\""), "{}", page);
+    assert!(page.contains(&row("Connections", "0")), "{}", page);
+    assert!(page.contains(&row("Logged in", "0")), "{}", page);
+    assert!(page.contains(&row("Guests", "0")), "{}", page);
+    assert!(
+        page.contains(&row("Messages in the last hour", "0")),
+        "{}",
+        page
+    );
+    assert!(
+        page.contains("No moderation actions were recorded."),
+        "{}",
+        page
+    );
+    assert!(!page.contains("<script"), "{}", page);
+}
+
+#[test]
+fn the_page_shows_the_current_counters_and_moderation() {
+    let server = server();
+    let _guest = server.client();
+    let mut moderator = server.login("Moderator", moderator());
+    let mut notch = server.login("Notch", notch());
+
+    notch.send_message("hello");
+    moderator.expect("Message");
+    notch.expect("Message");
+    moderator.send_message("/mute Notch 10m <b>caps</b>");
+    moderator.expect("CommandResult");
+
+    let page = status(&server);
+    assert!(page.contains(&row("Connections", "3")), "{}", page);
+    assert!(page.contains(&row("Logged in", "2")), "{}", page);
+    assert!(page.contains(&row("Guests", "1")), "{}", page);
+    assert!(
+        page.contains(&row("Messages in the last hour", "1")),
+        "{}",
+        page
+    );
+    assert!(page.contains("<td>Mute</td>"), "{}", page);
+    assert!(page.contains("&lt;b&gt;caps&lt;/b&gt;"), "{}", page);
+}
+
+#[test]
+fn the_page_requires_the_token_by_default() {
+    let server = TestServerBuilder::new()
+        .config(|config| {
+            config.api.token = Some(TOKEN.to_string());
+            config.server.status_page = true;
+        })
+        .start();
+
+    let response = server.request("GET", "/status", None, None);
+    assert_eq!(response.status, 401);
+    let response = server.request("GET", "/status", Some(TOKEN), None);
+    assert_eq!(response.status, 200);
+    let response = server.request("GET", &format!("/status?token={}", TOKEN), None, None);
+    assert_eq!(response.status, 200);
+    let response = server.request("GET", "/status?token=wrong", None, None);
+    assert_eq!(response.status, 401);
+}
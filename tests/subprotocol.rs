@@ -0,0 +1,97 @@
+//! End-to-end tests of negotiating the version of the protocol with the `Sec-WebSocket-Protocol` header.
+#![cfg(feature = "testutil")]
+
+use axochat::testutil::{notch, TestClient, TestServer};
+use serde_json::{json, Value};
+
+const SUBPROTOCOL: &str = "Sec-WebSocket-Protocol";
+
+/// Connects with the `offers` as `Sec-WebSocket-Protocol` headers and returns the selected subprotocol.
+fn connect<'a>(server: &'a TestServer, offers: &[&str]) -> (TestClient<'a>, Option<String>) {
+    let headers: Vec<_> = offers.iter().map(|offer| (SUBPROTOCOL, *offer)).collect();
+    let (client, headers) = server
+        .handshake(&headers)
+        .unwrap_or_else(|status| panic!("the handshake was refused with {}", status));
+    let selected = headers
+        .get(SUBPROTOCOL)
+        .map(|value| value.to_str().unwrap().to_string());
+    (client, selected)
+}
+
+/// Logs `client` in, sends `content` and returns the message it receives back.
+///
+/// Every message of a test needs other content, since repeated messages are rate limited.
+fn echo(client: &mut TestClient, content: &str) -> Value {
+    client.login_as("Notch", notch());
+    client.send_message(content);
+    client.expect("Message")
+}
+
+/// Whether `message` is a `Message` packet of version 2, which version 1 sends without `seq`.
+fn is_v2(message: &Value) -> bool {
+    message.get("seq").is_some()
+}
+
+#[test]
+fn the_newest_offered_version_is_selected() {
+    let server = TestServer::start();
+
+    let (mut client, selected) = connect(&server, &["axochat.v2, axochat.v1"]);
+    assert_eq!(selected.as_deref(), Some("axochat.v2"));
+    assert!(is_v2(&echo(&mut client, "message 1")));
+
+    let (mut client, selected) = connect(&server, &["axochat.v1, chat.example"]);
+    assert_eq!(selected.as_deref(), Some("axochat.v1"));
+    assert!(!is_v2(&echo(&mut client, "message 2")));
+}
+
+#[test]
+fn offers_in_several_headers_are_combined() {
+    let server = TestServer::start();
+
+    let (_client, selected) = connect(&server, &["chat.example", "axochat.v1"]);
+    assert_eq!(selected.as_deref(), Some("axochat.v1"));
+    let (mut client, selected) = connect(&server, &["axochat.v1", "chat.example, axochat.v2"]);
+    assert_eq!(selected.as_deref(), Some("axochat.v2"));
+    assert!(is_v2(&echo(&mut client, "message 3")));
+}
+
+#[test]
+fn handshakes_offering_only_unknown_subprotocols_are_refused() {
+    let server = TestServer::start();
+
+    for offers in [
+        &["chat.example"][..],
+        &["axochat.v3, axochat.v2+msgpack"],
+        &["a", "b"],
+    ] {
+        let headers: Vec<_> = offers.iter().map(|offer| (SUBPROTOCOL, *offer)).collect();
+        assert_eq!(server.handshake(&headers).err(), Some(400), "{:?}", offers);
+    }
+}
+
+#[test]
+fn handshakes_without_offers_speak_version_1_until_hello() {
+    let server = TestServer::start();
+
+    let (mut client, selected) = connect(&server, &[]);
+    assert_eq!(selected, None);
+    assert!(!is_v2(&echo(&mut client, "message 4")));
+
+    let (mut client, _) = connect(&server, &[]);
+    client.hello(&[]);
+    assert!(is_v2(&echo(&mut client, "message 5")));
+}
+
+#[test]
+fn the_negotiated_version_wins_over_hello() {
+    let server = TestServer::start();
+
+    let (mut client, _) = connect(&server, &["axochat.v2"]);
+    client.send("Hello", json!({ "protocol": 1, "features": [] }));
+    assert!(is_v2(&echo(&mut client, "message 6")));
+
+    let (mut client, _) = connect(&server, &["axochat.v1"]);
+    client.hello(&[]);
+    assert!(!is_v2(&echo(&mut client, "message 7")));
+}
@@ -0,0 +1,226 @@
+//! Tests of the translation keys and parameters of errors.
+
+use axochat::chat::protocol_schema;
+use axochat::config::LengthUnit;
+use axochat::error::{ClientError, MalformedCategory, TokenFailure};
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+/// One error of every variant.
+fn every_error() -> Vec<ClientError> {
+    use ClientError::*;
+
+    vec![
+        NotSupported,
+        LoginFailed,
+        NotLoggedIn,
+        AlreadyLoggedIn,
+        TooManySessions { max: 3 },
+        HandshakeRequired,
+        ClientOutdated {
+            upgrade_url: Some("https://example.com".to_string()),
+        },
+        MojangRequestMissing,
+        NotPermitted,
+        NotBanned,
+        NotMuted,
+        NotBlocked,
+        UserNotFound,
+        EmptyWord,
+        Banned,
+        Muted { remaining_secs: 60 },
+        RateLimited,
+        Probation { remaining_secs: 30 },
+        JoinCooldown { remaining_secs: 10 },
+        PrivateMessageNotAccepted,
+        DeliveryFailed,
+        DoNotDisturb,
+        TooManyWatches { max: 50 },
+        EmptyMessage,
+        MessageTooLong {
+            length: 120,
+            max_length: 100,
+            unit: LengthUnit::Graphemes,
+        },
+        InvalidCharacter {
+            character: '\n',
+            char_index: 3,
+            byte_offset: 4,
+        },
+        TooManyLines {
+            lines: 9,
+            max_lines: 5,
+        },
+        LineTooLong {
+            line: 2,
+            length: 90,
+            max_length: 80,
+            unit: LengthUnit::Chars,
+        },
+        ConsecutiveBlankLines,
+        ExcessiveRepetition {
+            character: 'a',
+            run: 12,
+            max_run: 10,
+        },
+        InsufficientContent,
+        LinksNotAllowed {
+            url: "example.com".to_string(),
+        },
+        BlockedContent,
+        ResumeFailed,
+        InvalidId,
+        InvalidReaction,
+        InvalidPayload,
+        MessageNotFound,
+        TooManyReactions { max: 10 },
+        NotReacted,
+        PersistenceDegraded,
+        ConfirmationFailed,
+        InvalidImport,
+        MalformedPacket {
+            category: MalformedCategory::Syntax,
+        },
+        InvalidToken {
+            reason: TokenFailure::Expired,
+        },
+        Internal,
+    ]
+}
+
+/// Fails to compile if [`every_error`] misses a variant added to [`ClientError`].
+#[allow(dead_code)]
+fn is_listed(error: &ClientError) {
+    use ClientError::*;
+
+    match error {
+        NotSupported
+        | LoginFailed
+        | NotLoggedIn
+        | AlreadyLoggedIn
+        | TooManySessions { .. }
+        | HandshakeRequired
+        | ClientOutdated { .. }
+        | MojangRequestMissing
+        | NotPermitted
+        | NotBanned
+        | NotMuted
+        | NotBlocked
+        | UserNotFound
+        | EmptyWord
+        | Banned
+        | Muted { .. }
+        | RateLimited
+        | Probation { .. }
+        | JoinCooldown { .. }
+        | PrivateMessageNotAccepted
+        | DeliveryFailed
+        | DoNotDisturb
+        | TooManyWatches { .. }
+        | EmptyMessage
+        | MessageTooLong { .. }
+        | InvalidCharacter { .. }
+        | TooManyLines { .. }
+        | LineTooLong { .. }
+        | ConsecutiveBlankLines
+        | ExcessiveRepetition { .. }
+        | InsufficientContent
+        | LinksNotAllowed { .. }
+        | BlockedContent
+        | ResumeFailed
+        | InvalidId
+        | InvalidReaction
+        | InvalidPayload
+        | MessageNotFound
+        | TooManyReactions { .. }
+        | NotReacted
+        | PersistenceDegraded
+        | ConfirmationFailed
+        | InvalidImport
+        | MalformedPacket { .. }
+        | InvalidToken { .. }
+        | Internal => {}
+    }
+}
+
+/// The name of the variant of `error`, as it is serialized.
+fn variant(error: &ClientError) -> String {
+    match serde_json::to_value(error).unwrap() {
+        Value::String(name) => name,
+        Value::Object(fields) => fields.keys().next().unwrap().clone(),
+        value => panic!("unexpected serialization {}", value),
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+#[test]
+fn every_error_is_named_like_its_key() {
+    for error in every_error() {
+        let expected = format!("error.{}", snake_case(&variant(&error)));
+        assert_eq!(error.translation_key(), expected, "{:?}", error);
+    }
+}
+
+#[test]
+fn keys_are_unique() {
+    let errors = every_error();
+    let keys: BTreeSet<_> = errors.iter().map(ClientError::translation_key).collect();
+    assert_eq!(keys.len(), errors.len());
+}
+
+#[test]
+fn the_schema_lists_every_key() {
+    let schema = serde_json::to_value(protocol_schema()).unwrap();
+    let listed: BTreeSet<_> = schema["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|key| key.as_str().unwrap().to_string())
+        .collect();
+    let keys: BTreeSet<_> = every_error()
+        .iter()
+        .map(|error| error.translation_key().to_string())
+        .collect();
+    assert_eq!(listed, keys);
+}
+
+#[test]
+fn params_contain_the_details_of_errors() {
+    for error in every_error() {
+        let params = error.translation_params();
+        let fields = match serde_json::to_value(&error).unwrap() {
+            Value::Object(fields) => fields.into_iter().next().unwrap().1,
+            _ => {
+                assert!(params.is_empty(), "{:?} has params {:?}", error, params);
+                continue;
+            }
+        };
+        assert!(!params.is_empty(), "{:?} has no params", error);
+        for (name, value) in &params {
+            let field = &fields[*name];
+            let expected = match field {
+                Value::String(value) => value.clone(),
+                Value::Null => panic!("{:?} has no field `{}`", error, name),
+                field => field.to_string(),
+            };
+            assert_eq!(value, &expected, "param `{}` of {:?}", name, error);
+        }
+    }
+}
+
+#[test]
+fn every_error_has_an_english_message() {
+    for error in every_error() {
+        assert!(!error.to_string().is_empty(), "{:?}", error);
+    }
+}
@@ -0,0 +1,448 @@
+//! Characterization tests pinning what the message validator accepts and rejects.
+
+use axochat::config::{
+    BlankLines, BlockedWordsAction, LengthUnit, LinkPolicy, MsgConfig, ValidationConfig,
+};
+use axochat::error::Error;
+use axochat::filter::WordFilter;
+use axochat::message::MessageValidator;
+use axochat::rules::Rejection;
+use serde_json::{json, Value};
+
+fn validator() -> MessageValidator {
+    MessageValidator::new(MsgConfig::default(), ValidationConfig::default()).unwrap()
+}
+
+/// A validator accepting line breaks.
+fn multi_line(blank_lines: BlankLines) -> MessageValidator {
+    MessageValidator::new(
+        MsgConfig::default(),
+        ValidationConfig {
+            allow_newlines: true,
+            max_lines: 3,
+            max_line_length: 10,
+            blank_lines,
+            ..ValidationConfig::default()
+        },
+    )
+    .unwrap()
+}
+
+/// A validator with the default message limits and `validation` changed by `f`.
+fn configured<F: FnOnce(&mut ValidationConfig)>(f: F) -> MessageValidator {
+    let mut validation = ValidationConfig::default();
+    f(&mut validation);
+    MessageValidator::new(MsgConfig::default(), validation).unwrap()
+}
+
+/// A validator blocking the word `bad` with `action`.
+fn filtered(action: BlockedWordsAction) -> MessageValidator {
+    let mut validator = configured(|validation| validation.blocked_words_action = action);
+    validator.set_word_filter(WordFilter::new(
+        vec!["bad".to_string()].into_iter().collect(),
+    ));
+    validator
+}
+
+/// Validates `msg` and returns the serialized error it is rejected with.
+fn rejection(msg: &str) -> Value {
+    rejection_by(&validator(), msg)
+}
+
+fn rejection_by(validator: &MessageValidator, msg: &str) -> Value {
+    match validator.validate(msg) {
+        Ok(content) => panic!("{:?} was accepted as {:?}", msg, content),
+        Err(Error::AxoChat { source }) => serde_json::to_value(source).unwrap(),
+        Err(err) => panic!("{:?} was rejected with {}", msg, err),
+    }
+}
+
+/// Checks `msg` against the link policy and returns the serialized error it is rejected with.
+fn link_rejection(validator: &MessageValidator, msg: &str, is_moderator: bool) -> Value {
+    match validator.validate_links(msg, is_moderator) {
+        Ok(()) => panic!("the links of {:?} were accepted", msg),
+        Err(Error::AxoChat { source }) => serde_json::to_value(source).unwrap(),
+        Err(err) => panic!("the links of {:?} were rejected with {}", msg, err),
+    }
+}
+
+fn serialized(rejections: Vec<Rejection>) -> Vec<Value> {
+    rejections
+        .into_iter()
+        .map(|rejection| serde_json::to_value(rejection.error).unwrap())
+        .collect()
+}
+
+/// The names of the rules `msg` violates.
+fn rule_names(validator: &MessageValidator, msg: &str) -> Vec<&'static str> {
+    validator
+        .violations(msg, false)
+        .into_iter()
+        .map(|rejection| rejection.rule)
+        .collect()
+}
+
+fn invalid_character(character: char, index: usize) -> Value {
+    json!({
+        "InvalidCharacter": {
+            "character": character.to_string(),
+            "char_index": index,
+            "byte_offset": index,
+        }
+    })
+}
+
+#[test]
+fn accepts_a_single_line() {
+    let content = validator().validate("hello world").unwrap();
+    assert_eq!(content.as_str(), "hello world");
+}
+
+#[test]
+fn rejects_line_feeds_as_invalid_characters() {
+    assert_eq!(rejection("a\nb"), invalid_character('\n', 1));
+    assert_eq!(rejection("\nb"), invalid_character('\n', 0));
+    assert_eq!(rejection("a\n"), invalid_character('\n', 1));
+    assert_eq!(rejection("\n"), invalid_character('\n', 0));
+    assert_eq!(rejection("a\n\n\nb"), invalid_character('\n', 1));
+}
+
+#[test]
+fn rejects_carriage_returns_as_invalid_characters() {
+    assert_eq!(rejection("a\rb"), invalid_character('\r', 1));
+    assert_eq!(rejection("a\r\nb"), invalid_character('\r', 1));
+}
+
+#[test]
+fn reports_the_first_invalid_character() {
+    assert_eq!(rejection("ab\tc\nd"), invalid_character('\t', 2));
+}
+
+#[test]
+fn checks_the_length_before_line_breaks() {
+    let msg = format!("{}\n", "a".repeat(100));
+    assert_eq!(
+        rejection(&msg),
+        json!({
+            "MessageTooLong": {
+                "length": 101,
+                "max_length": 100,
+                "unit": "chars",
+            }
+        })
+    );
+}
+
+#[test]
+fn normalizes_line_breaks_if_allowed() {
+    let validator = multi_line(BlankLines::Collapse);
+    let content = validator.validate("a\r\nb\rc").unwrap();
+    assert_eq!(content.as_str(), "a\nb\nc");
+}
+
+#[test]
+fn limits_the_number_of_lines() {
+    let validator = multi_line(BlankLines::Collapse);
+    assert_eq!(
+        rejection_by(&validator, "a\nb\nc\nd"),
+        json!({ "TooManyLines": { "lines": 4, "max_lines": 3 } })
+    );
+}
+
+#[test]
+fn limits_the_length_of_each_line() {
+    let validator = multi_line(BlankLines::Collapse);
+    assert_eq!(
+        rejection_by(&validator, "short\nmuch too long"),
+        json!({
+            "LineTooLong": {
+                "line": 2,
+                "length": 13,
+                "max_length": 10,
+                "unit": "chars",
+            }
+        })
+    );
+}
+
+#[test]
+fn collapses_consecutive_blank_lines() {
+    let validator = multi_line(BlankLines::Collapse);
+    let content = validator.validate("a\n\n \n\nb").unwrap();
+    assert_eq!(content.as_str(), "a\n\nb");
+}
+
+#[test]
+fn rejects_consecutive_blank_lines() {
+    let validator = multi_line(BlankLines::Reject);
+    assert_eq!(
+        rejection_by(&validator, "\n\nb"),
+        json!("ConsecutiveBlankLines")
+    );
+}
+
+#[test]
+fn rejects_empty_messages() {
+    assert_eq!(rejection(""), json!("EmptyMessage"));
+}
+
+#[test]
+fn accepts_blank_messages_unless_configured() {
+    assert_eq!(validator().validate("   ").unwrap().as_str(), "   ");
+
+    let validator = configured(|validation| validation.reject_blank = true);
+    assert_eq!(rejection_by(&validator, "   "), json!("EmptyMessage"));
+}
+
+#[test]
+fn checks_the_byte_limit_before_the_length() {
+    let validator = configured(|validation| validation.max_bytes = 8);
+    assert_eq!(
+        rejection_by(&validator, "ééééé"),
+        json!({
+            "MessageTooLong": {
+                "length": 10,
+                "max_length": 8,
+                "unit": "bytes",
+            }
+        })
+    );
+    assert_eq!(
+        serialized(validator.violations(&"a".repeat(101), false)),
+        vec![
+            json!({ "MessageTooLong": { "length": 101, "max_length": 8, "unit": "bytes" } }),
+            json!({ "MessageTooLong": { "length": 101, "max_length": 100, "unit": "chars" } }),
+        ]
+    );
+}
+
+#[test]
+fn counts_the_length_in_the_configured_unit() {
+    // A consonant followed by a vowel sign, which are two chars but one grapheme.
+    let msg = "\u{915}\u{93F}".repeat(60);
+
+    assert_eq!(
+        rejection(&msg),
+        json!({ "MessageTooLong": { "length": 120, "max_length": 100, "unit": "chars" } })
+    );
+    let graphemes = configured(|validation| validation.length_unit = LengthUnit::Graphemes);
+    assert_eq!(graphemes.validate(&msg).unwrap().as_str(), msg);
+}
+
+#[test]
+fn accepts_alphanumeric_characters_of_all_scripts() {
+    let msg = "héllo мир 世界 ~!@#";
+    assert_eq!(validator().validate(msg).unwrap().as_str(), msg);
+}
+
+#[test]
+fn rejects_combining_marks() {
+    assert_eq!(rejection("e\u{301}"), invalid_character('\u{301}', 1));
+}
+
+#[test]
+fn reports_invalid_characters_by_char_and_byte() {
+    assert_eq!(
+        rejection("é\u{1F600}"),
+        json!({
+            "InvalidCharacter": {
+                "character": "\u{1F600}",
+                "char_index": 1,
+                "byte_offset": 2,
+            }
+        })
+    );
+}
+
+#[test]
+fn reports_the_longest_run_of_a_character() {
+    assert_eq!(
+        validator().validate("aaaaaaaa").unwrap().as_str(),
+        "aaaaaaaa"
+    );
+
+    let validator = configured(|validation| validation.max_char_run = 3);
+    assert_eq!(validator.validate("aaa bbb").unwrap().as_str(), "aaa bbb");
+    assert_eq!(
+        rejection_by(&validator, "aaaa bbbbbb cccc"),
+        json!({ "ExcessiveRepetition": { "character": "b", "run": 6, "max_run": 3 } })
+    );
+}
+
+#[test]
+fn requires_the_configured_share_of_letters_and_digits() {
+    let validator = configured(|validation| validation.min_content_ratio = 0.5);
+    assert_eq!(validator.validate("a! b?").unwrap().as_str(), "a! b?");
+    assert_eq!(
+        rejection_by(&validator, "a!!"),
+        json!("InsufficientContent")
+    );
+}
+
+#[test]
+fn rejects_blocked_words_case_insensitively() {
+    let validator = filtered(BlockedWordsAction::Reject);
+    assert_eq!(rejection_by(&validator, "so BaD"), json!("BlockedContent"));
+    assert_eq!(rejection_by(&validator, "badge"), json!("BlockedContent"));
+}
+
+#[test]
+fn redacts_blocked_words_if_configured() {
+    let validator = filtered(BlockedWordsAction::Redact);
+    assert_eq!(
+        validator.validate("so very BaD").unwrap().as_str(),
+        "so very ***"
+    );
+    assert_eq!(rejection_by(&validator, "a bad"), json!("BlockedContent"));
+}
+
+#[test]
+fn checks_the_rules_in_order() {
+    let validator = configured(|validation| {
+        validation.max_char_run = 2;
+        validation.min_content_ratio = 0.99;
+    });
+    let msg = format!("{}\t!!!", "a".repeat(100));
+    assert_eq!(
+        serialized(validator.violations(&msg, false)),
+        vec![
+            json!({ "MessageTooLong": { "length": 104, "max_length": 100, "unit": "chars" } }),
+            invalid_character('\t', 100),
+            json!({ "ExcessiveRepetition": { "character": "a", "run": 100, "max_run": 2 } }),
+            json!("InsufficientContent"),
+        ]
+    );
+    assert_eq!(
+        rejection_by(&validator, &msg),
+        json!({ "MessageTooLong": { "length": 104, "max_length": 100, "unit": "chars" } })
+    );
+}
+
+#[test]
+fn allows_links_by_default() {
+    validator()
+        .validate_links("see https://example.com", false)
+        .unwrap();
+}
+
+#[test]
+fn blocks_links_if_configured() {
+    let validator = configured(|validation| {
+        validation.links = LinkPolicy::Block;
+    });
+    assert_eq!(
+        link_rejection(&validator, "see Example.com/page", false),
+        json!({ "LinksNotAllowed": { "url": "example.com/page" } })
+    );
+    assert_eq!(
+        link_rejection(&validator, "see example(dot)com", false),
+        json!({ "LinksNotAllowed": { "url": "example.com" } })
+    );
+    validator.validate_links("see example.com", true).unwrap();
+    validator.validate_links("no links here.", false).unwrap();
+}
+
+#[test]
+fn allows_whitelisted_domains_and_their_subdomains() {
+    let validator = configured(|validation| {
+        validation.links = LinkPolicy::Whitelist;
+        validation.link_whitelist = vec!["Example.com".to_string()];
+        validation.moderators_bypass_links = false;
+    });
+    validator
+        .validate_links("https://example.com and www.docs.example.com", false)
+        .unwrap();
+    assert_eq!(
+        link_rejection(&validator, "notexample.com", true),
+        json!({ "LinksNotAllowed": { "url": "notexample.com" } })
+    );
+}
+
+#[test]
+fn lists_link_violations_after_the_content_violations() {
+    let validator = configured(|validation| validation.links = LinkPolicy::Block);
+    assert_eq!(
+        serialized(validator.violations("\texample.com", false)),
+        vec![
+            invalid_character('\t', 0),
+            json!({ "LinksNotAllowed": { "url": "example.com" } }),
+        ]
+    );
+}
+
+#[test]
+fn only_limits_the_length_of_system_messages() {
+    let msg = format!("{}\t", "a".repeat(150));
+    assert_eq!(validator().validate_system(&msg).unwrap().as_str(), msg);
+    match validator().validate_system("") {
+        Err(Error::AxoChat { source }) => {
+            assert_eq!(serde_json::to_value(source).unwrap(), json!("EmptyMessage"))
+        }
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn names_the_rule_a_message_violates() {
+    let rejection = validator().check("a\tb").unwrap_err();
+    assert_eq!(rejection.rule, "charset");
+    assert_eq!(
+        serde_json::to_value(rejection.error).unwrap(),
+        invalid_character('\t', 1)
+    );
+}
+
+#[test]
+fn names_every_violated_rule_in_order() {
+    let validator = configured(|validation| {
+        validation.max_bytes = 50;
+        validation.max_char_run = 2;
+        validation.min_content_ratio = 0.99;
+        validation.links = LinkPolicy::Block;
+    });
+    assert_eq!(rule_names(&validator, ""), vec!["not_empty"]);
+    let msg = format!("{}\t!!! example.com", "a".repeat(100));
+    assert_eq!(
+        rule_names(&validator, &msg),
+        vec![
+            "max_bytes",
+            "max_length",
+            "charset",
+            "repetition",
+            "content_ratio",
+            "links",
+        ]
+    );
+
+    let mut validator = multi_line(BlankLines::Reject);
+    validator.set_word_filter(WordFilter::new(
+        vec!["bad".to_string()].into_iter().collect(),
+    ));
+    assert_eq!(
+        rule_names(&validator, "much too long\n\n\nbad"),
+        vec![
+            "max_lines",
+            "max_line_length",
+            "blank_lines",
+            "blocked_words"
+        ]
+    );
+}
+
+#[test]
+fn rejects_invalid_rule_configurations() {
+    for ratio in &[-0.1, 1.5] {
+        let validation = ValidationConfig {
+            min_content_ratio: *ratio,
+            ..ValidationConfig::default()
+        };
+        assert!(MessageValidator::new(MsgConfig::default(), validation).is_err());
+    }
+
+    let validation = ValidationConfig {
+        links: LinkPolicy::Whitelist,
+        link_whitelist: vec![" ".to_string()],
+        ..ValidationConfig::default()
+    };
+    assert!(MessageValidator::new(MsgConfig::default(), validation).is_err());
+}